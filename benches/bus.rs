@@ -0,0 +1,76 @@
+// Bus throughput by region: reads and writes of each size through the
+// Memory accessors the CPU uses
+#[macro_use]
+extern crate criterion;
+extern crate gba;
+
+mod common;
+
+use criterion::{black_box, Criterion, Throughput};
+
+use gba::gba_mem::Address;
+
+const ACCESSES: usize = 1024;
+
+const REGIONS: [(&str, Address); 6] = [
+    ("bios", 0x00000000),
+    ("ewram", 0x02000000),
+    ("iwram", 0x03000000),
+    ("io", 0x04000000),
+    ("vram", 0x06000000),
+    ("rom", 0x08000000),
+];
+
+fn reads(c: &mut Criterion) {
+    let gba = common::booted_gba();
+    let mem = gba.mem();
+    let mut group = c.benchmark_group("read");
+    group.throughput(Throughput::Elements(ACCESSES as u64));
+    for &(name, base) in REGIONS.iter() {
+        group.bench_function(format!("{}/8", name), |b| b.iter(|| {
+            for i in 0..ACCESSES {
+                black_box(mem.read::<u8>(base + i));
+            }
+        }));
+        group.bench_function(format!("{}/16", name), |b| b.iter(|| {
+            for i in 0..ACCESSES {
+                black_box(mem.read::<u16>(base + i * 2));
+            }
+        }));
+        group.bench_function(format!("{}/32", name), |b| b.iter(|| {
+            for i in 0..ACCESSES {
+                black_box(mem.read::<u32>(base + i * 4));
+            }
+        }));
+    }
+    group.finish();
+}
+
+// Only the RAMs; writes elsewhere have side effects or are dropped
+fn writes(c: &mut Criterion) {
+    let mut gba = common::booted_gba();
+    let mem = gba.mem_mut();
+    let mut group = c.benchmark_group("write");
+    group.throughput(Throughput::Elements(ACCESSES as u64));
+    for &(name, base) in REGIONS[1..3].iter() {
+        group.bench_function(format!("{}/8", name), |b| b.iter(|| {
+            for i in 0..ACCESSES {
+                mem.write8::<u8>(base + i, black_box(i as u8));
+            }
+        }));
+        group.bench_function(format!("{}/16", name), |b| b.iter(|| {
+            for i in 0..ACCESSES {
+                mem.write16::<u16>(base + i * 2, black_box(i as u16));
+            }
+        }));
+        group.bench_function(format!("{}/32", name), |b| b.iter(|| {
+            for i in 0..ACCESSES {
+                mem.write32::<u32>(base + i * 4, black_box(i as u32));
+            }
+        }));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, reads, writes);
+criterion_main!(benches);
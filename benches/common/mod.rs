@@ -0,0 +1,35 @@
+use gba::Gba;
+
+// There is no homebrew ROM we can bundle that the interpreter runs yet, so
+// the benches run a generated one: a valid header followed by Thumb
+// "add r0, pc, #4" repeated, enough for a frame or two from ROM.
+const ROM_LEN: usize = 0x80000;
+const THUMB_ADR: [u8; 2] = [0x01, 0xA0];
+
+pub fn synthetic_rom() -> Vec<u8> {
+    let mut rom = vec![0; ROM_LEN];
+    rom[0xB2] = 0x96; // Fixed value
+    let checksum = rom[0xA0..0xBD].iter()
+        .fold(0u8, |sum, &b| sum.wrapping_sub(b))
+        .wrapping_sub(0x19);
+    rom[0xBD] = checksum;
+    for instr in rom[0xC0..].chunks_mut(2) {
+        instr.copy_from_slice(&THUMB_ADR);
+    }
+    rom
+}
+
+// A machine past the BIOS, running Thumb code from the start of the ROM
+pub fn booted_gba() -> Gba {
+    let mut gba = Gba::builder().rom(&synthetic_rom()).build()
+        .expect("failed to load the benchmark ROM");
+    restart(&mut gba);
+    gba
+}
+
+pub fn restart(gba: &mut Gba) {
+    let cpu = gba.cpu_mut();
+    cpu.skip_bios();
+    cpu.set_thumb();
+    cpu.set_pc(0x080000C0);
+}
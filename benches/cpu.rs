@@ -0,0 +1,80 @@
+// Instruction dispatch rate for the plain and cached interpreters and the
+// JIT, from ROM and from IWRAM, and whole frames per second
+#[macro_use]
+extern crate criterion;
+extern crate gba;
+
+mod common;
+
+use criterion::{Criterion, Throughput};
+use gba::Gba;
+
+// The engines run the same instructions in the same time, so running for
+// a number of cycles runs the same code on each. The JIT runs whole blocks
+// per step, so counting steps wouldn't.
+const CYCLES: u64 = 8192;
+
+// The benchmark ROM's code copied to IWRAM, where fetches cost the same
+// every time
+const IWRAM_CODE: usize = 0x03000000;
+const IWRAM_CODE_LEN: usize = 0x5000; // More than CYCLES runs through
+const THUMB_ADR: u16 = 0xA001;
+
+fn copy_to_iwram(gba: &mut Gba) {
+    for addr in (IWRAM_CODE..IWRAM_CODE + IWRAM_CODE_LEN).step_by(2) {
+        gba.mem_mut().write16::<u16>(addr, THUMB_ADR);
+    }
+}
+
+#[cfg(not(feature = "jit"))]
+const ENGINES: [&str; 2] = ["interpreter", "cached"];
+#[cfg(feature = "jit")]
+const ENGINES: [&str; 3] = ["interpreter", "cached", "jit"];
+
+fn set_engine(gba: &mut Gba, name: &str) {
+    match name {
+        #[cfg(feature = "jit")]
+        "jit" => gba.set_jit(true).expect("the JIT should run on the host"),
+        _ => gba.set_cached_interpreter(name == "cached"),
+    }
+}
+
+fn dispatch(c: &mut Criterion) {
+    for &(group_name, in_iwram) in [("dispatch", false), ("dispatch_iwram", true)].iter() {
+        let mut group = c.benchmark_group(group_name);
+        group.throughput(Throughput::Elements(CYCLES));
+        for &name in ENGINES.iter() {
+            let mut gba = common::booted_gba();
+            set_engine(&mut gba, name);
+            if in_iwram {
+                copy_to_iwram(&mut gba);
+            }
+            group.bench_function(name, |b| b.iter(|| {
+                common::restart(&mut gba);
+                if in_iwram {
+                    gba.cpu_mut().set_pc(IWRAM_CODE as u32);
+                }
+                gba.run_cycles(CYCLES);
+            }));
+        }
+        group.finish();
+    }
+}
+
+// Frames per second is one over the time per iteration
+fn frames(c: &mut Criterion) {
+    let mut group = c.benchmark_group("frame");
+    group.sample_size(20);
+    for &name in ENGINES.iter() {
+        let mut gba = common::booted_gba();
+        set_engine(&mut gba, name);
+        group.bench_function(name, |b| b.iter(|| {
+            common::restart(&mut gba);
+            gba.run_frame();
+        }));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, dispatch, frames);
+criterion_main!(benches);
@@ -0,0 +1,66 @@
+// Direct Sound FIFO channels A and B from:
+// http://problemkaputt.de/gbatek.htm#gbasoundchannelaandbdmasound
+
+/// Samples each FIFO holds
+pub const FIFO_LEN: usize = 32;
+
+// DMA is asked for more data once half of the FIFO has been played
+const FIFO_REFILL_LEVEL: usize = 16;
+
+/// Queue of signed 8-bit samples fed by DMA1/DMA2 and drained on timer
+/// overflow
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SoundFifo {
+    buf: [i8; FIFO_LEN],
+    read: usize,
+    len: usize,
+    sample: i8, // Sample currently being output
+}
+
+impl_save_state!(SoundFifo { buf, read, len, sample });
+
+impl SoundFifo {
+    /// Samples pushed into a full FIFO are lost
+    pub fn push(&mut self, sample: i8) {
+        if self.len < FIFO_LEN {
+            self.buf[(self.read + self.len) % FIFO_LEN] = sample;
+            self.len += 1;
+        }
+    }
+
+    /// Latch the next sample for output. An empty FIFO keeps playing the
+    /// last sample.
+    pub fn pop(&mut self) {
+        if self.len > 0 {
+            self.sample = self.buf[self.read];
+            self.read = (self.read + 1) % FIFO_LEN;
+            self.len -= 1;
+        }
+    }
+
+    /// Empty the FIFO
+    pub fn reset(&mut self) {
+        self.read = 0;
+        self.len = 0;
+    }
+
+    /// Samples queued
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether no samples are queued
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Whether DMA should top it up
+    pub fn needs_refill(&self) -> bool {
+        self.len <= FIFO_REFILL_LEVEL
+    }
+
+    /// Sample playing now
+    pub fn sample(&self) -> i8 {
+        self.sample
+    }
+}
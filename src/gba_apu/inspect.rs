@@ -0,0 +1,274 @@
+use core::fmt;
+
+use gba_apu::{Apu, DMA_A_FULL_VOL, DMA_A_LEFT, DMA_A_RIGHT, DMA_A_TIMER, DMA_B_FULL_VOL,
+              DMA_B_SHIFT, PSG_LEFT_SHIFT, PSG_RIGHT_SHIFT};
+use gba_apu::direct_sound::SoundFifo;
+use gba_apu::psg::{Envelope, PSG_MAX_VOLUME, WAVE_RAM_LEN};
+
+/// The six sound channels, in the order SOUNDCNT gives their bits
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Channel {
+    /// Tone and sweep
+    Square1,
+    /// Tone
+    Square2,
+    /// Wave output
+    Wave,
+    /// Noise
+    Noise,
+    /// Direct Sound A
+    FifoA,
+    /// Direct Sound B
+    FifoB,
+}
+
+/// Every channel, in SOUNDCNT bit order
+pub const CHANNELS: [Channel; 6] = [
+    Channel::Square1,
+    Channel::Square2,
+    Channel::Wave,
+    Channel::Noise,
+    Channel::FifoA,
+    Channel::FifoB,
+];
+
+const ALL_CHANNELS: u8 = 0x3F;
+
+impl Channel {
+    /// The channel called name, as `name` gives it
+    pub fn from_name(name: &str) -> Option<Channel> {
+        CHANNELS.iter().cloned().find(|ch| ch.name() == name)
+    }
+
+    /// The channel's name on the command line and in the debugger
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Channel::Square1 => "square1",
+            Channel::Square2 => "square2",
+            Channel::Wave => "wave",
+            Channel::Noise => "noise",
+            Channel::FifoA => "fifo_a",
+            Channel::FifoB => "fifo_b",
+        }
+    }
+
+    fn bit(&self) -> u8 {
+        1 << *self as u8
+    }
+}
+
+impl fmt::Display for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write![f, "{}", self.name()]
+    }
+}
+
+/// What one channel is doing, for debuggers and music rippers
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChannelInfo {
+    /// Which channel this is
+    pub channel: Channel,
+    /// Whether it's making sound
+    pub playing: bool,
+    /// By the host, not the game
+    pub muted: bool,
+    /// Whether it goes to the left speaker
+    pub left: bool,
+    /// Whether it goes to the right speaker
+    pub right: bool,
+    /// In Hz. Direct Sound's rate is its timer's.
+    pub frequency: Option<f64>,
+    /// Percent of the channel's full volume
+    pub volume: u8,
+    /// Square and noise channels only
+    pub envelope: Option<Envelope>,
+}
+
+impl fmt::Display for ChannelInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write![f, "{:<8} {:<7} {}{} vol:{:>3}%",
+               self.channel,
+               if self.playing { "playing" } else { "off" },
+               if self.left { 'L' } else { '-' },
+               if self.right { 'R' } else { '-' },
+               self.volume]?;
+        if let Some(freq) = self.frequency {
+            write![f, " {:>9.1}Hz", freq]?;
+        }
+        if let Some(env) = self.envelope {
+            write![f, " env:{}{}/{}", env.initial(),
+                   if env.is_increasing() { '+' } else { '-' }, env.step_time()]?;
+        }
+        if self.muted {
+            write![f, " (muted)"]?;
+        }
+        Ok(())
+    }
+}
+
+/// A snapshot of the sound hardware
+#[derive(Clone, Debug, PartialEq)]
+pub struct ApuInfo {
+    /// Master enable, SOUNDCNT_X bit 7
+    pub enabled: bool,
+    /// Every channel, in SOUNDCNT bit order
+    pub channels: [ChannelInfo; 6],
+    /// Both banks of wave RAM
+    pub wave_ram: [[u8; WAVE_RAM_LEN]; 2],
+    /// Playing; the CPU sees the other
+    pub wave_bank: usize,
+    /// Samples queued in FIFO A and B
+    pub fifo_len: [usize; 2],
+    /// Timer each FIFO plays on
+    pub fifo_timer: [usize; 2],
+}
+
+impl fmt::Display for ApuInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln![f, "APU {}", if self.enabled { "on" } else { "off" }]?;
+        for ch in self.channels.iter() {
+            writeln![f, "{}", ch]?;
+        }
+        for (bank, ram) in self.wave_ram.iter().enumerate() {
+            write![f, "wave bank {}{}:", bank, if bank == self.wave_bank { "*" } else { " " }]?;
+            for byte in ram.iter() {
+                write![f, " {:02x}", byte]?;
+            }
+            writeln![f]?;
+        }
+        write![f, "fifo_a: {}/32 on timer {}, fifo_b: {}/32 on timer {}",
+               self.fifo_len[0], self.fifo_timer[0], self.fifo_len[1], self.fifo_timer[1]]
+    }
+}
+
+fn envelope_percent(env: &Envelope) -> u8 {
+    (env.volume() as u32 * 100 / PSG_MAX_VOLUME as u32) as u8
+}
+
+// Channel muting belongs to the host, like the sample rate, so it isn't
+// saved in states and survives loading one
+impl Apu {
+    /// A snapshot of every channel
+    pub fn info(&self) -> ApuInfo {
+        let psg = |ch: Channel, playing: bool, frequency: f64, volume: u8,
+                   envelope: Option<Envelope>| {
+            let bit = ch as u16;
+            ChannelInfo {
+                channel: ch,
+                playing,
+                muted: self.is_muted(ch),
+                left: (self.soundcnt_l >> (PSG_LEFT_SHIFT + bit)) & 1 != 0,
+                right: (self.soundcnt_l >> (PSG_RIGHT_SHIFT + bit)) & 1 != 0,
+                frequency: Some(frequency),
+                volume,
+                envelope,
+            }
+        };
+        let fifo = |ch: Channel, fifo: &SoundFifo, shift: u16, full_vol: u16| {
+            ChannelInfo {
+                channel: ch,
+                playing: self.is_enabled() && !fifo.is_empty(),
+                muted: self.is_muted(ch),
+                left: self.soundcnt_h & (DMA_A_LEFT << shift) != 0,
+                right: self.soundcnt_h & (DMA_A_RIGHT << shift) != 0,
+                frequency: None,
+                volume: if self.soundcnt_h & full_vol != 0 { 100 } else { 50 },
+                envelope: None,
+            }
+        };
+
+        ApuInfo {
+            enabled: self.is_enabled(),
+            channels: [
+                psg(Channel::Square1, self.sq1.is_enabled(), self.sq1.frequency(),
+                    envelope_percent(self.sq1.envelope()), Some(*self.sq1.envelope())),
+                psg(Channel::Square2, self.sq2.is_enabled(), self.sq2.frequency(),
+                    envelope_percent(self.sq2.envelope()), Some(*self.sq2.envelope())),
+                psg(Channel::Wave, self.wave.is_enabled(), self.wave.frequency(),
+                    self.wave.volume_percent(), None),
+                psg(Channel::Noise, self.noise.is_enabled(), self.noise.frequency(),
+                    envelope_percent(self.noise.envelope()), Some(*self.noise.envelope())),
+                fifo(Channel::FifoA, &self.fifo_a, 0, DMA_A_FULL_VOL),
+                fifo(Channel::FifoB, &self.fifo_b, DMA_B_SHIFT, DMA_B_FULL_VOL),
+            ],
+            wave_ram: [*self.wave.wave_bank(0), *self.wave.wave_bank(1)],
+            wave_bank: self.wave.bank(),
+            fifo_len: [self.fifo_a.len(), self.fifo_b.len()],
+            fifo_timer: [(self.soundcnt_h & DMA_A_TIMER != 0) as usize,
+                         (self.soundcnt_h & (DMA_A_TIMER << DMA_B_SHIFT) != 0) as usize],
+        }
+    }
+
+    /// Whether the host has muted ch
+    pub fn is_muted(&self, ch: Channel) -> bool {
+        self.muted & ch.bit() != 0
+    }
+
+    /// Mute or unmute ch; the game can't tell
+    pub fn set_muted(&mut self, ch: Channel, muted: bool) {
+        if muted {
+            self.muted |= ch.bit();
+        }
+        else {
+            self.muted &= !ch.bit();
+        }
+    }
+
+    /// Returns whether the channel is now muted
+    pub fn toggle_muted(&mut self, ch: Channel) -> bool {
+        self.muted ^= ch.bit();
+        self.is_muted(ch)
+    }
+
+    /// Mute every channel but one, or with None unmute them all
+    pub fn solo(&mut self, ch: Option<Channel>) {
+        self.muted = match ch {
+            Some(ch) => !ch.bit() & ALL_CHANNELS,
+            None => 0,
+        };
+    }
+
+    /// The only channel not muted, if there is just one
+    pub fn soloed(&self) -> Option<Channel> {
+        CHANNELS.iter().cloned().find(|&ch| self.muted == !ch.bit() & ALL_CHANNELS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gba_apu::{SOUND1CNT_H, SOUND1CNT_X, SOUNDCNT_L, SOUNDCNT_X};
+
+    #[test]
+    fn info_and_mutes() {
+        let mut apu = Apu::default();
+        apu.write16(SOUNDCNT_X, 0x0080, 0xFFFF);
+        apu.write16(SOUNDCNT_L, 0x1177, 0xFFFF); // Square 1 on both sides
+        apu.write16(SOUND1CNT_H, 0xF080, 0xFFFF); // Full volume, 50% duty
+        apu.write16(SOUND1CNT_X, 0x8000 | 1750, 0xFFFF);
+
+        let info = apu.info();
+        let sq1 = info.channels[0];
+        assert!(sq1.playing && sq1.left && sq1.right && !sq1.muted);
+        assert_eq!(sq1.volume, 100);
+        assert!((sq1.frequency.unwrap() - 131072.0 / 298.0).abs() < 1e-9);
+        assert!(!info.channels[1].playing);
+        assert_eq!(info.channels[4].frequency, None);
+
+        apu.step(2048);
+        let loud = apu.drain_samples();
+        assert!(loud.iter().any(|&s| s != 0));
+
+        apu.solo(Some(Channel::Wave));
+        assert_eq!(apu.soloed(), Some(Channel::Wave));
+        assert!(apu.info().channels[0].muted);
+        apu.step(2048);
+        assert!(apu.drain_samples().iter().all(|&s| s == 0));
+
+        assert!(!apu.toggle_muted(Channel::Square1));
+        assert_eq!(apu.soloed(), None);
+        apu.solo(None);
+        assert!(CHANNELS.iter().all(|&ch| !apu.is_muted(ch)));
+        assert_eq!(Channel::from_name("fifo_b"), Some(Channel::FifoB));
+    }
+}
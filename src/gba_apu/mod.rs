@@ -0,0 +1,571 @@
+/// The FIFO channels games stream samples into
+pub mod direct_sound;
+/// Snapshots of the sound channels for debuggers
+pub mod inspect;
+/// The four Game Boy sound channels
+pub mod psg;
+
+use core::fmt;
+
+use gba_apu::direct_sound::SoundFifo;
+use gba_apu::inspect::Channel;
+use gba_apu::psg::{NoiseChannel, SquareChannel, WaveChannel, PSG_MAX_VOLUME};
+use gba_error::GbaResult;
+use gba_mem::Address;
+use gba_state::{SaveState, StateReader, StateWriter};
+use prelude::*;
+
+/// Sound register addresses from:
+/// http://problemkaputt.de/gbatek.htm#gbasoundcontroller
+pub const SOUND1CNT_L: Address = 0x04000060;
+/// Channel 1 duty, length and envelope
+pub const SOUND1CNT_H: Address = 0x04000062;
+/// Channel 1 frequency and control
+pub const SOUND1CNT_X: Address = 0x04000064;
+/// Channel 2 duty, length and envelope
+pub const SOUND2CNT_L: Address = 0x04000068;
+/// Channel 2 frequency and control
+pub const SOUND2CNT_H: Address = 0x0400006C;
+/// Channel 3 stop and wave RAM select
+pub const SOUND3CNT_L: Address = 0x04000070;
+/// Channel 3 length and volume
+pub const SOUND3CNT_H: Address = 0x04000072;
+/// Channel 3 frequency and control
+pub const SOUND3CNT_X: Address = 0x04000074;
+/// Channel 4 length and envelope
+pub const SOUND4CNT_L: Address = 0x04000078;
+/// Channel 4 frequency and control
+pub const SOUND4CNT_H: Address = 0x0400007C;
+/// PSG volume and enables
+pub const SOUNDCNT_L:  Address = 0x04000080;
+/// Direct Sound control and the PSG master volume
+pub const SOUNDCNT_H:  Address = 0x04000082;
+/// Master enable and the channels' on flags
+pub const SOUNDCNT_X:  Address = 0x04000084;
+/// Bias level and PWM resolution
+pub const SOUNDBIAS:   Address = 0x04000088;
+/// Start of wave RAM
+pub const WAVE_RAM_LO: Address = 0x04000090;
+/// End of wave RAM
+pub const WAVE_RAM_HI: Address = 0x0400009F;
+/// Direct Sound channel A's FIFO
+pub const FIFO_A:      Address = 0x040000A0;
+/// Direct Sound channel B's FIFO
+pub const FIFO_B:      Address = 0x040000A4;
+
+/// First and last addresses handled by the APU
+pub const APU_LO: Address = SOUND1CNT_L;
+/// Last address handled by the APU
+pub const APU_HI: Address = 0x040000A7;
+
+/// CPU cycles per second
+pub const CPU_FREQ: u32 = 16777216;
+/// Sample rate when the host doesn't ask for one
+pub const DEFAULT_SAMPLE_RATE: u32 = 32768;
+
+// The frame sequencer runs at 512Hz and clocks length (256Hz),
+// sweep (128Hz) and envelope (64Hz) units
+const FRAME_SEQ_CYCLES: u32 = CPU_FREQ / 512;
+
+const SOUNDCNT_X_ENABLE: u16 = 0x0080; // Master sound enable
+const RESTART:           u16 = 0x8000; // Initial bit of the channel control registers
+const APU_REGS:          usize = (APU_HI - APU_LO).div_ceil(2);
+
+// SOUNDBIAS: the level the mix is centred on, and the resolution of the
+// PWM that plays it. Each step of resolution trades a bit of depth for
+// twice the rate, from 9 bits at 32768Hz to 6 bits at 262144Hz.
+const SOUNDBIAS_RESET:     u16 = 0x0200;
+const SOUNDBIAS_WRITABLE:  u16 = 0xC3FE;
+const SOUNDBIAS_LEVEL:     u16 = 0x03FE;
+const SOUNDBIAS_RES_SHIFT: u16 = 14;
+const PWM_MAX:             i32 = 0x3FF; // The mix plus the bias is clamped to 10 bits
+const PWM_BASE_RATE:       u32 = 32768;
+// SOUNDCNT_L PSG master volume (0-7) and per channel enables for each
+// side. The enables have a bit per channel starting with sound 1.
+const PSG_VOL_MASK:        u16 = 0x7;
+const PSG_VOL_RIGHT_SHIFT: u16 = 0;
+const PSG_VOL_LEFT_SHIFT:  u16 = 4;
+const PSG_RIGHT_SHIFT:     u16 = 8;
+const PSG_LEFT_SHIFT:      u16 = 12;
+
+// SOUNDCNT_H PSG volume ratio: 0=25%, 1=50%, 2=100%, 3=prohibited
+const PSG_RATIO_MASK: u16 = 0x0003;
+
+// SOUNDCNT_H Direct Sound control bits; B uses the same layout as A
+// shifted by DMA_B_SHIFT
+const DMA_A_FULL_VOL:  u16 = 0x0004; // 100% rather than 50%
+const DMA_B_FULL_VOL:  u16 = 0x0008;
+const DMA_A_RIGHT:     u16 = 0x0100;
+const DMA_A_LEFT:      u16 = 0x0200;
+const DMA_A_TIMER:     u16 = 0x0400; // Timer 1 rather than timer 0
+const DMA_A_RESET:     u16 = 0x0800; // Write only
+const DMA_B_SHIFT:     u16 = 4;
+
+// Mixing happens in the hardware's 10-bit domain. Four PSG channels at
+// full master volume and ratio reach +/-480 and each Direct Sound channel
+// +/-512. The PWM clamps that around the bias, and what is left is scaled
+// to a signed 16-bit sample.
+const DMA_MIX_SCALE: i32 = 2; // Per volume step (50% or 100%)
+const OUTPUT_SCALE:  i32 = 32;
+
+/// The sound controller: the four PSG channels, the two Direct Sound FIFOs and the mixer
+#[derive(Clone, Debug)]
+pub struct Apu {
+    sq1: SquareChannel,
+    sq2: SquareChannel,
+    wave: WaveChannel,
+    noise: NoiseChannel,
+    fifo_a: SoundFifo,
+    fifo_b: SoundFifo,
+    soundcnt_l: u16,
+    soundcnt_h: u16,
+    soundcnt_x: u16,
+    soundbias: u16,
+    frame_seq_cycles: u32,
+    frame_seq_step: u8,
+    pwm_cycles: u32, // Into the current PWM period
+    pwm_sample: (i16, i16), // Played for the rest of the period
+    sample_rate: u32,
+    sample_phase: u64, // Elapsed cycles scaled by the sample rate
+    samples: Vec<i16>, // Interleaved left/right samples
+    raw: [u16; APU_REGS], // Last value written to each register
+    muted: u8, // A bit per channel left out of the mix, in SOUNDCNT order
+}
+
+impl Default for Apu {
+    fn default() -> Apu {
+        Apu::new(DEFAULT_SAMPLE_RATE)
+    }
+}
+
+// The sample rate belongs to the host, so it and the phase measured in it
+// are left alone. Samples mixed before a load are dropped.
+impl SaveState for Apu {
+    fn save_state(&self, w: &mut StateWriter) {
+        self.sq1.save_state(w);
+        self.sq2.save_state(w);
+        self.wave.save_state(w);
+        self.noise.save_state(w);
+        self.fifo_a.save_state(w);
+        self.fifo_b.save_state(w);
+        self.soundcnt_l.save_state(w);
+        self.soundcnt_h.save_state(w);
+        self.soundcnt_x.save_state(w);
+        self.soundbias.save_state(w);
+        self.frame_seq_cycles.save_state(w);
+        self.frame_seq_step.save_state(w);
+        self.pwm_cycles.save_state(w);
+        self.raw.save_state(w);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> GbaResult<()> {
+        self.sq1.load_state(r)?;
+        self.sq2.load_state(r)?;
+        self.wave.load_state(r)?;
+        self.noise.load_state(r)?;
+        self.fifo_a.load_state(r)?;
+        self.fifo_b.load_state(r)?;
+        self.soundcnt_l.load_state(r)?;
+        self.soundcnt_h.load_state(r)?;
+        self.soundcnt_x.load_state(r)?;
+        self.soundbias.load_state(r)?;
+        self.frame_seq_cycles.load_state(r)?;
+        self.frame_seq_step.load_state(r)?;
+        self.pwm_cycles.load_state(r)?;
+        self.raw.load_state(r)?;
+        self.samples.clear();
+        Ok(())
+    }
+}
+
+impl_serde_via_state!(Apu);
+
+impl Apu {
+    /// Sound hardware mixing samples at sample_rate, as after power on
+    pub fn new(sample_rate: u32) -> Apu {
+        assert!(sample_rate > 0);
+
+        Apu {
+            sq1: SquareChannel::default(),
+            sq2: SquareChannel::default(),
+            wave: WaveChannel::default(),
+            noise: NoiseChannel::default(),
+            fifo_a: SoundFifo::default(),
+            fifo_b: SoundFifo::default(),
+            soundcnt_l: 0,
+            soundcnt_h: 0,
+            soundcnt_x: 0,
+            soundbias: SOUNDBIAS_RESET,
+            frame_seq_cycles: 0,
+            frame_seq_step: 0,
+            pwm_cycles: 0,
+            pwm_sample: (0, 0),
+            sample_rate,
+            sample_phase: 0,
+            samples: Vec::new(),
+            raw: [0; APU_REGS],
+            muted: 0,
+        }
+    }
+
+    /// Rate samples are mixed at for the host
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Change the rate samples are mixed at
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        assert!(sample_rate > 0);
+        self.sample_rate = sample_rate;
+        self.sample_phase = 0;
+    }
+
+    /// Take every stereo sample mixed since the last call, interleaved as
+    /// left, right, left, right...
+    pub fn drain_samples(&mut self) -> Vec<i16> {
+        core::mem::take(&mut self.samples)
+    }
+
+    /// Whether the master enable in SOUNDCNT_X is set
+    pub fn is_enabled(&self) -> bool {
+        self.soundcnt_x & SOUNDCNT_X_ENABLE != 0
+    }
+
+    /// The level silence is output at, 0-0x3FE
+    pub fn bias_level(&self) -> u16 {
+        self.soundbias & SOUNDBIAS_LEVEL
+    }
+
+    /// Bits per PWM sample, 9 down to 6
+    pub fn pwm_bits(&self) -> u32 {
+        9 - (self.soundbias >> SOUNDBIAS_RES_SHIFT) as u32
+    }
+
+    /// PWM samples per second, 32768Hz up to 262144Hz
+    pub fn pwm_rate(&self) -> u32 {
+        PWM_BASE_RATE << (self.soundbias >> SOUNDBIAS_RES_SHIFT)
+    }
+
+    fn pwm_period(&self) -> u32 {
+        CPU_FREQ / self.pwm_rate()
+    }
+
+    /// Channel 1
+    pub fn square1(&self) -> &SquareChannel { &self.sq1 }
+    /// Channel 2
+    pub fn square2(&self) -> &SquareChannel { &self.sq2 }
+    /// Channel 3
+    pub fn wave(&self) -> &WaveChannel { &self.wave }
+    /// Channel 4
+    pub fn noise(&self) -> &NoiseChannel { &self.noise }
+    /// Direct Sound channel A's FIFO
+    pub fn fifo_a(&self) -> &SoundFifo { &self.fifo_a }
+    /// Direct Sound channel B's FIFO
+    pub fn fifo_b(&self) -> &SoundFifo { &self.fifo_b }
+
+    /// Timer 0 or 1 overflowed `count` times; each Direct Sound channel
+    /// clocked by it plays its next samples. Returns the FIFOs that should
+    /// be refilled by DMA.
+    pub fn timer_overflow(&mut self, timer: usize, count: u32) -> Vec<Address> {
+        let mut refill = Vec::new();
+        if !self.is_enabled() || count == 0 {
+            return refill;
+        }
+
+        let fifos = [(FIFO_A, 0), (FIFO_B, DMA_B_SHIFT)];
+        for &(addr, shift) in fifos.iter() {
+            if ((self.soundcnt_h >> shift) & DMA_A_TIMER != 0) as usize != timer {
+                continue;
+            }
+
+            let fifo = if addr == FIFO_A { &mut self.fifo_a } else { &mut self.fifo_b };
+            for _ in 0..count {
+                fifo.pop();
+            }
+            if fifo.needs_refill() {
+                refill.push(addr);
+            }
+        }
+
+        refill
+    }
+
+    /// Run the sound hardware for cycles, mixing samples as it goes
+    pub fn step(&mut self, cycles: u32) {
+        let mut remaining = cycles;
+
+        while remaining > 0 {
+            let to_seq = FRAME_SEQ_CYCLES - self.frame_seq_cycles;
+            let to_sample = self.cycles_to_sample();
+            // The period changes with SOUNDBIAS, so a shorter one may
+            // already have passed
+            let to_pwm = self.pwm_period().saturating_sub(self.pwm_cycles).max(1);
+            let chunk = *[remaining, to_seq, to_sample, to_pwm].iter().min().unwrap();
+
+            if self.is_enabled() {
+                self.sq1.step(chunk);
+                self.sq2.step(chunk);
+                self.wave.step(chunk);
+                self.noise.step(chunk);
+            }
+            remaining -= chunk;
+
+            self.frame_seq_cycles += chunk;
+            if self.frame_seq_cycles == FRAME_SEQ_CYCLES {
+                self.frame_seq_cycles = 0;
+                self.clock_frame_seq();
+            }
+
+            // The PWM holds each sample for a whole period, so a host
+            // rate above the PWM rate hears the same steps hardware plays
+            self.pwm_cycles += chunk;
+            if self.pwm_cycles >= self.pwm_period() {
+                self.pwm_cycles = 0;
+                self.pwm_sample = self.mix();
+            }
+
+            self.sample_phase += chunk as u64 * self.sample_rate as u64;
+            if self.sample_phase >= CPU_FREQ as u64 {
+                self.sample_phase -= CPU_FREQ as u64;
+                let (left, right) = self.pwm_sample;
+                self.samples.push(left);
+                self.samples.push(right);
+            }
+        }
+    }
+
+    /// Cycles until the frame sequencer next clocks the channels, the only
+    /// time their state seen through the registers changes by itself
+    pub fn cycles_to_event(&self) -> u32 {
+        FRAME_SEQ_CYCLES - self.frame_seq_cycles
+    }
+
+    fn cycles_to_sample(&self) -> u32 {
+        let rate = self.sample_rate as u64;
+        let needed = CPU_FREQ as u64 - self.sample_phase;
+        needed.div_ceil(rate) as u32
+    }
+
+    fn clock_frame_seq(&mut self) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        let step = self.frame_seq_step;
+        self.frame_seq_step = (step + 1) % 8;
+
+        if step.is_multiple_of(2) {
+            self.sq1.clock_length();
+            self.sq2.clock_length();
+            self.wave.clock_length();
+            self.noise.clock_length();
+        }
+        if step == 2 || step == 6 {
+            self.sq1.clock_sweep();
+        }
+        if step == 7 {
+            self.sq1.clock_envelope();
+            self.sq2.clock_envelope();
+            self.noise.clock_envelope();
+        }
+    }
+
+    // Mix the PSG and Direct Sound channels into a stereo sample
+    fn mix(&self) -> (i16, i16) {
+        if !self.is_enabled() {
+            return (0, 0);
+        }
+
+        let mut psg = [self.sq1.output(),
+                       self.sq2.output(),
+                       self.wave.output(),
+                       self.noise.output()];
+        for (ch, out) in psg.iter_mut().enumerate() {
+            if self.muted & (1 << ch) != 0 {
+                *out = 0;
+            }
+        }
+        let mut left = self.mix_psg(&psg, PSG_LEFT_SHIFT, PSG_VOL_LEFT_SHIFT);
+        let mut right = self.mix_psg(&psg, PSG_RIGHT_SHIFT, PSG_VOL_RIGHT_SHIFT);
+
+        let dma_a = self.fifo_a.sample() as i32 * DMA_MIX_SCALE *
+            if self.soundcnt_h & DMA_A_FULL_VOL != 0 { 2 } else { 1 } *
+            !self.is_muted(Channel::FifoA) as i32;
+        let dma_b = self.fifo_b.sample() as i32 * DMA_MIX_SCALE *
+            if self.soundcnt_h & DMA_B_FULL_VOL != 0 { 2 } else { 1 } *
+            !self.is_muted(Channel::FifoB) as i32;
+
+        if self.soundcnt_h & DMA_A_LEFT != 0 { left += dma_a; }
+        if self.soundcnt_h & DMA_A_RIGHT != 0 { right += dma_a; }
+        if self.soundcnt_h & (DMA_A_LEFT << DMA_B_SHIFT) != 0 { left += dma_b; }
+        if self.soundcnt_h & (DMA_A_RIGHT << DMA_B_SHIFT) != 0 { right += dma_b; }
+
+        (self.pwm(left), self.pwm(right))
+    }
+
+    // One side of the mix as the PWM plays it: offset by the bias, clamped
+    // to 10 bits and cut to the PWM's depth. The bias is taken back out,
+    // as the capacitor on the real output does, so a bias too near either
+    // end only shows up as clipping.
+    fn pwm(&self, mix: i32) -> i16 {
+        let bias = self.bias_level() as i32;
+        let step = 1 << (10 - self.pwm_bits());
+        let level = (mix + bias).clamp(0, PWM_MAX) & !(step - 1);
+        ((level - bias) * OUTPUT_SCALE) as i16
+    }
+
+    // One side of the PSG mix: the channels enabled on that side, scaled
+    // by its master volume and then the PSG volume ratio
+    fn mix_psg(&self, outputs: &[i16; 4], enable_shift: u16, vol_shift: u16) -> i32 {
+        let sum: i32 = outputs.iter()
+            .enumerate()
+            .filter(|&(ch, _)| (self.soundcnt_l >> (enable_shift + ch as u16)) & 1 != 0)
+            .map(|(_, &out)| out as i32)
+            .sum();
+        debug_assert!(sum.abs() <= 4 * PSG_MAX_VOLUME as i32);
+
+        let volume = ((self.soundcnt_l >> vol_shift) & PSG_VOL_MASK) as i32 + 1;
+        let ratio_shift = match self.soundcnt_h & PSG_RATIO_MASK {
+            0 => 2,
+            1 => 1,
+            _ => 0, // 3 is prohibited; treat it as 100%
+        };
+        (sum * volume) >> ratio_shift
+    }
+
+    // Channel on flags for SOUNDCNT_X bits 0-3
+    fn channel_flags(&self) -> u16 {
+        self.sq1.is_enabled() as u16 |
+        (self.sq2.is_enabled() as u16) << 1 |
+        (self.wave.is_enabled() as u16) << 2 |
+        (self.noise.is_enabled() as u16) << 3
+    }
+
+    /// Read a sound register
+    pub fn read16(&self, addr: Address) -> u16 {
+        match addr & !1 {
+            SOUND1CNT_L => self.sq1.read_sweep(),
+            SOUND1CNT_H => self.sq1.read_duty(),
+            SOUND1CNT_X => self.sq1.read_freq(),
+            SOUND2CNT_L => self.sq2.read_duty(),
+            SOUND2CNT_H => self.sq2.read_freq(),
+            SOUND3CNT_L => self.wave.read_cnt_l(),
+            SOUND3CNT_H => self.wave.read_cnt_h(),
+            SOUND3CNT_X => self.wave.read_cnt_x(),
+            SOUND4CNT_L => self.noise.read_cnt_l(),
+            SOUND4CNT_H => self.noise.read_cnt_h(),
+            SOUNDCNT_L  => self.soundcnt_l & 0xFF77,
+            SOUNDCNT_H  => self.soundcnt_h & 0x770F,
+            SOUNDCNT_X  => (self.soundcnt_x & SOUNDCNT_X_ENABLE) | self.channel_flags(),
+            SOUNDBIAS   => self.soundbias,
+            a @ WAVE_RAM_LO..=WAVE_RAM_HI => {
+                let idx = a - WAVE_RAM_LO;
+                self.wave.read_wave_ram(idx) as u16 |
+                (self.wave.read_wave_ram(idx + 1) as u16) << 8
+            },
+            _ => 0,
+        }
+    }
+
+    /// Only the bits selected by mask are written. Byte writes are merged
+    /// with the last value written rather than the value read back, since
+    /// many sound register bits are write only.
+    pub fn write16(&mut self, addr: Address, val: u16, mask: u16) {
+        // Each byte written to a FIFO is queued as its own sample
+        if addr & !3 == FIFO_A || addr & !3 == FIFO_B {
+            let fifo = if addr & !3 == FIFO_A { &mut self.fifo_a } else { &mut self.fifo_b };
+            if mask & 0x00FF != 0 { fifo.push(val as i8); }
+            if mask & 0xFF00 != 0 { fifo.push((val >> 8) as i8); }
+            return;
+        }
+
+        let idx = ((addr & !1) - APU_LO) / 2;
+        let val = (self.raw[idx] & !mask) | (val & mask);
+        self.raw[idx] = match addr & !1 {
+            SOUND1CNT_X | SOUND2CNT_H | SOUND3CNT_X | SOUND4CNT_H => val & !RESTART,
+            SOUNDCNT_H => val & !(DMA_A_RESET | DMA_A_RESET << DMA_B_SHIFT),
+            _ => val,
+        };
+
+        // While the master enable is clear only SOUNDCNT_X, SOUNDBIAS and
+        // wave RAM can be written
+        let locked = !self.is_enabled();
+
+        match addr & !1 {
+            SOUNDCNT_X => {
+                self.soundcnt_x = val & SOUNDCNT_X_ENABLE;
+                if !self.is_enabled() {
+                    self.power_off();
+                }
+            },
+            SOUNDBIAS => self.soundbias = val & SOUNDBIAS_WRITABLE,
+            a @ WAVE_RAM_LO..=WAVE_RAM_HI => {
+                let idx = a - WAVE_RAM_LO;
+                self.wave.write_wave_ram(idx, val as u8);
+                self.wave.write_wave_ram(idx + 1, (val >> 8) as u8);
+            },
+            _ if locked => {},
+            SOUND1CNT_L => self.sq1.write_sweep(val),
+            SOUND1CNT_H => self.sq1.write_duty(val),
+            SOUND1CNT_X => self.sq1.write_freq(val),
+            SOUND2CNT_L => self.sq2.write_duty(val),
+            SOUND2CNT_H => self.sq2.write_freq(val),
+            SOUND3CNT_L => self.wave.write_cnt_l(val),
+            SOUND3CNT_H => self.wave.write_cnt_h(val),
+            SOUND3CNT_X => self.wave.write_cnt_x(val),
+            SOUND4CNT_L => self.noise.write_cnt_l(val),
+            SOUND4CNT_H => self.noise.write_cnt_h(val),
+            SOUNDCNT_L  => self.soundcnt_l = val,
+            SOUNDCNT_H  => {
+                self.soundcnt_h = val & !(DMA_A_RESET | DMA_A_RESET << DMA_B_SHIFT);
+                if val & DMA_A_RESET != 0 { self.fifo_a.reset(); }
+                if val & (DMA_A_RESET << DMA_B_SHIFT) != 0 { self.fifo_b.reset(); }
+            },
+            _ => {},
+        }
+    }
+
+    // Clearing the master enable resets all PSG registers
+    fn power_off(&mut self) {
+        self.sq1 = SquareChannel::default();
+        self.sq2 = SquareChannel::default();
+        self.wave.power_off();
+        self.noise = NoiseChannel::default();
+        self.fifo_a = SoundFifo::default();
+        self.fifo_b = SoundFifo::default();
+        self.soundcnt_l = 0;
+        self.soundcnt_h = 0;
+        self.frame_seq_step = 0;
+        for reg in (SOUND1CNT_L..SOUNDCNT_X).step_by(2) {
+            self.raw[(reg - APU_LO) / 2] = 0;
+        }
+    }
+}
+
+impl fmt::Display for Apu {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write![f, "APU: enabled:{} channels:{:04b} rate:{}Hz",
+               self.is_enabled(), self.channel_flags(), self.sample_rate]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn soundbias_clips_and_quantizes_the_mix() {
+        let mut apu = Apu::default();
+        assert_eq!((apu.bias_level(), apu.pwm_bits(), apu.pwm_rate()), (0x200, 9, 32768));
+        assert_eq!(apu.pwm(100), 100 * OUTPUT_SCALE as i16);
+        assert_eq!(apu.pwm(101), 100 * OUTPUT_SCALE as i16);
+        assert_eq!(apu.pwm(-600), -0x200 * OUTPUT_SCALE as i16);
+
+        // 6 bits at 262144Hz around a low bias
+        apu.write16(SOUNDBIAS, 0xC000 | 0x100, 0xFFFF);
+        assert_eq!((apu.bias_level(), apu.pwm_bits(), apu.pwm_rate()), (0x100, 6, 262144));
+        assert_eq!(apu.pwm(-300), -0x100 * OUTPUT_SCALE as i16);
+        assert_eq!(apu.pwm(31), 16 * OUTPUT_SCALE as i16);
+        assert_eq!(apu.pwm(1000), (PWM_MAX + 1 - 16 - 0x100) as i16 * OUTPUT_SCALE as i16);
+    }
+}
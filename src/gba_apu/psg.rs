@@ -0,0 +1,806 @@
+// Legacy Game Boy sound channels 1-4 from:
+// http://problemkaputt.de/gbatek.htm#gbasoundchannel1tonesweep
+// http://problemkaputt.de/gbatek.htm#gbasoundchannel2tone
+// http://problemkaputt.de/gbatek.htm#gbasoundchannel3waveoutput
+// http://problemkaputt.de/gbatek.htm#gbasoundchannel4noise
+
+use gba_apu::CPU_FREQ;
+
+// Shared register bits
+const LENGTH_FLAG: u16 = 0x4000; // Stop output when length expires
+const INITIAL:     u16 = 0x8000; // Restart sound (write only)
+const FREQ_MASK:   u16 = 0x07FF;
+
+// Envelope bits of SOUND1CNT_H, SOUND2CNT_L and SOUND4CNT_L
+const ENV_STEP_SHIFT: u16 = 8;
+const ENV_STEP_MASK:  u16 = 0x0700;
+const ENV_INCREASE:   u16 = 0x0800;
+const ENV_VOL_SHIFT:  u16 = 12;
+
+// Duty cycle waveforms, 12.5%, 25%, 50%, 75%
+const DUTY_PATTERNS: [u8; 4] = [0b00000001, 0b10000001, 0b10000111, 0b01111110];
+
+/// Amplitude of a single channel at full volume
+pub const PSG_MAX_VOLUME: i16 = 15;
+
+/// Volume envelope shared by the square and noise channels
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Envelope {
+    initial: u8,
+    increase: bool,
+    step_time: u8,
+    volume: u8,
+    counter: u8,
+}
+
+impl_save_state!(Envelope { initial, increase, step_time, volume, counter });
+
+impl Envelope {
+    fn write(&mut self, cnt: u16) {
+        self.initial = (cnt >> ENV_VOL_SHIFT) as u8;
+        self.increase = cnt & ENV_INCREASE != 0;
+        self.step_time = ((cnt & ENV_STEP_MASK) >> ENV_STEP_SHIFT) as u8;
+    }
+
+    fn trigger(&mut self) {
+        self.volume = self.initial;
+        self.counter = self.step_time;
+    }
+
+    // Clocked at 64Hz by the frame sequencer
+    fn clock(&mut self) {
+        if self.step_time == 0 {
+            return;
+        }
+
+        self.counter = self.counter.saturating_sub(1);
+        if self.counter == 0 {
+            self.counter = self.step_time;
+            if self.increase && self.volume < PSG_MAX_VOLUME as u8 {
+                self.volume += 1;
+            }
+            else if !self.increase && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+
+    // The channel produces nothing when it can never become audible
+    fn dac_enabled(&self) -> bool {
+        self.initial != 0 || self.increase
+    }
+
+    /// Current volume, 0-15
+    pub fn volume(&self) -> u8 {
+        self.volume
+    }
+
+    /// Volume at the start of each note
+    pub fn initial(&self) -> u8 {
+        self.initial
+    }
+
+    /// Whether the volume steps up rather than down
+    pub fn is_increasing(&self) -> bool {
+        self.increase
+    }
+
+    /// 64Hz ticks between volume steps; 0 holds the volume
+    pub fn step_time(&self) -> u8 {
+        self.step_time
+    }
+}
+
+/// Length counter shared by all four channels
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LengthCounter {
+    counter: u16,
+    enabled: bool,
+}
+
+impl_save_state!(LengthCounter { counter, enabled });
+
+impl LengthCounter {
+    fn load(&mut self, max: u16, len: u16) {
+        self.counter = max - len;
+    }
+
+    fn trigger(&mut self, max: u16) {
+        if self.counter == 0 {
+            self.counter = max;
+        }
+    }
+
+    // Clocked at 256Hz by the frame sequencer. Returns false once the
+    // channel should be silenced.
+    fn clock(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            return self.counter != 0;
+        }
+        true
+    }
+}
+
+/// Tone channels 1 and 2. Only channel 1 has the frequency sweep unit.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SquareChannel {
+    cnt_sweep: u16,
+    cnt_duty: u16,
+    cnt_freq: u16,
+    enabled: bool,
+    freq: u16,
+    timer: u32,
+    duty_pos: u8,
+    env: Envelope,
+    length: LengthCounter,
+    sweep_counter: u8,
+}
+
+impl_save_state!(SquareChannel { cnt_sweep, cnt_duty, cnt_freq, enabled, freq, timer, duty_pos, env, length, sweep_counter });
+
+const SQUARE_LEN_MAX:     u16 = 64;
+const SQUARE_LEN_MASK:    u16 = 0x003F;
+const SQUARE_DUTY_SHIFT:  u16 = 6;
+const SWEEP_SHIFT_MASK:   u16 = 0x0007;
+const SWEEP_DECREASE:     u16 = 0x0008;
+const SWEEP_TIME_SHIFT:   u16 = 4;
+const SWEEP_TIME_MASK:    u16 = 0x0070;
+
+impl SquareChannel {
+    /// Sweep register (SOUND1CNT_L)
+    pub fn read_sweep(&self) -> u16 { self.cnt_sweep & 0x007F }
+    /// Write the sweep register
+    pub fn write_sweep(&mut self, val: u16) { self.cnt_sweep = val & 0x007F; }
+
+    /// Duty/Length/Envelope (SOUND1CNT_H, SOUND2CNT_L)
+    pub fn read_duty(&self) -> u16 { self.cnt_duty & 0xFFC0 }
+    /// Write the duty, length and envelope register
+    pub fn write_duty(&mut self, val: u16) {
+        self.cnt_duty = val;
+        self.length.load(SQUARE_LEN_MAX, val & SQUARE_LEN_MASK);
+        self.env.write(val);
+        if !self.env.dac_enabled() {
+            self.enabled = false;
+        }
+    }
+
+    /// Frequency/Control (SOUND1CNT_X, SOUND2CNT_H)
+    pub fn read_freq(&self) -> u16 { self.cnt_freq & LENGTH_FLAG }
+    /// Write the frequency and control register, starting a note if INITIAL is set
+    pub fn write_freq(&mut self, val: u16) {
+        self.cnt_freq = val & !INITIAL;
+        self.freq = val & FREQ_MASK;
+        self.length.enabled = val & LENGTH_FLAG != 0;
+        if val & INITIAL != 0 {
+            self.trigger();
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.env.dac_enabled();
+        self.length.trigger(SQUARE_LEN_MAX);
+        self.env.trigger();
+        self.timer = self.period();
+        self.sweep_counter = self.sweep_time();
+        if self.sweep_shift() != 0 && self.sweep_freq() > FREQ_MASK {
+            self.enabled = false;
+        }
+    }
+
+    // 131072/(2048-n) Hz with 8 duty steps per period, 16 cycles a step
+    fn period(&self) -> u32 {
+        (2048 - self.freq as u32) * 16
+    }
+
+    fn sweep_time(&self) -> u8 { ((self.cnt_sweep & SWEEP_TIME_MASK) >> SWEEP_TIME_SHIFT) as u8 }
+    fn sweep_shift(&self) -> u16 { self.cnt_sweep & SWEEP_SHIFT_MASK }
+
+    fn sweep_freq(&self) -> u16 {
+        let delta = self.freq >> self.sweep_shift();
+        if self.cnt_sweep & SWEEP_DECREASE != 0 {
+            self.freq.saturating_sub(delta)
+        }
+        else {
+            self.freq + delta
+        }
+    }
+
+    /// Run the channel for cycles
+    pub fn step(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.duty_pos = (self.duty_pos + 1) % 8;
+        }
+        self.timer -= cycles;
+    }
+
+    /// Tick the length counter, silencing the channel when it runs out
+    pub fn clock_length(&mut self) {
+        if !self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    /// Tick the volume envelope
+    pub fn clock_envelope(&mut self) {
+        self.env.clock();
+    }
+
+    /// Clocked at 128Hz by the frame sequencer
+    pub fn clock_sweep(&mut self) {
+        if self.sweep_time() == 0 {
+            return;
+        }
+
+        self.sweep_counter = self.sweep_counter.saturating_sub(1);
+        if self.sweep_counter == 0 {
+            self.sweep_counter = self.sweep_time();
+            let new_freq = self.sweep_freq();
+            if new_freq > FREQ_MASK {
+                self.enabled = false;
+            }
+            else if self.sweep_shift() != 0 {
+                self.freq = new_freq;
+                self.cnt_freq = (self.cnt_freq & !FREQ_MASK) | new_freq;
+            }
+        }
+    }
+
+    /// Whether the channel is playing
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Frequency setting, the period being 2048 minus it
+    pub fn freq(&self) -> u16 { self.freq }
+    /// The volume envelope
+    pub fn envelope(&self) -> &Envelope { &self.env }
+
+    /// 0-3 for 12.5%, 25%, 50% and 75%
+    pub fn duty(&self) -> u8 {
+        (self.cnt_duty >> SQUARE_DUTY_SHIFT) as u8 & 0b11
+    }
+
+    /// Pitch of the tone in Hz
+    pub fn frequency(&self) -> f64 {
+        CPU_FREQ as f64 / (self.period() * 8) as f64
+    }
+
+    /// Signed output between -15 and 15
+    pub fn output(&self) -> i16 {
+        if !self.enabled {
+            return 0;
+        }
+
+        let duty = DUTY_PATTERNS[(self.cnt_duty >> SQUARE_DUTY_SHIFT) as usize & 0b11];
+        let vol = self.env.volume() as i16;
+        if duty & (1 << self.duty_pos) != 0 { vol } else { -vol }
+    }
+}
+
+/// Wave output channel 3
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WaveChannel {
+    cnt_l: u16,
+    cnt_h: u16,
+    cnt_x: u16,
+    enabled: bool,
+    freq: u16,
+    timer: u32,
+    position: u8, // Sample within the playing bank
+    length: LengthCounter,
+    wave_ram: [[u8; WAVE_RAM_LEN]; WAVE_BANKS],
+}
+
+impl_save_state!(WaveChannel { cnt_l, cnt_h, cnt_x, enabled, freq, timer, position, length, wave_ram });
+
+/// Wave RAM is two banks of 32 4-bit samples. The bank selected in
+/// SOUND3CNT_L plays while the CPU reads and writes the other one through
+/// the WAVE_RAM registers, so a song can refill one bank as the other plays.
+pub const WAVE_RAM_LEN: usize = 16; // Bytes per bank
+const WAVE_BANKS:       usize = 2;
+const WAVE_SAMPLES:     u8 = WAVE_RAM_LEN as u8 * 2;
+const WAVE_LEN_MAX:     u16 = 256;
+const WAVE_LEN_MASK:    u16 = 0x00FF;
+const WAVE_TWO_BANKS:   u16 = 0x0020; // Play both banks as one 64 sample wave
+const WAVE_BANK_SELECT: u16 = 0x0040;
+const WAVE_DAC_ENABLE:  u16 = 0x0080;
+const WAVE_VOL_SHIFT:   u16 = 13;
+const WAVE_FORCE_75:    u16 = 0x8000;
+
+impl WaveChannel {
+    /// Stop/Wave RAM select (SOUND3CNT_L)
+    pub fn read_cnt_l(&self) -> u16 { self.cnt_l & 0x00E0 }
+    /// Write the stop and wave RAM select register
+    pub fn write_cnt_l(&mut self, val: u16) {
+        self.cnt_l = val & 0x00E0;
+        if !self.dac_enabled() {
+            self.enabled = false;
+        }
+    }
+
+    /// Length/Volume (SOUND3CNT_H)
+    pub fn read_cnt_h(&self) -> u16 { self.cnt_h & 0xE000 }
+    /// Write the length and volume register
+    pub fn write_cnt_h(&mut self, val: u16) {
+        self.cnt_h = val;
+        self.length.load(WAVE_LEN_MAX, val & WAVE_LEN_MASK);
+    }
+
+    /// Frequency/Control (SOUND3CNT_X)
+    pub fn read_cnt_x(&self) -> u16 { self.cnt_x & LENGTH_FLAG }
+    /// Write the frequency and control register, starting a note if INITIAL is set
+    pub fn write_cnt_x(&mut self, val: u16) {
+        self.cnt_x = val & !INITIAL;
+        self.freq = val & FREQ_MASK;
+        self.length.enabled = val & LENGTH_FLAG != 0;
+        if val & INITIAL != 0 {
+            self.trigger();
+        }
+    }
+
+    /// Bank being played
+    pub fn bank(&self) -> usize {
+        ((self.cnt_l & WAVE_BANK_SELECT) >> 6) as usize
+    }
+
+    /// Whether the wave is 64 samples from both banks instead of one bank's 32
+    pub fn is_two_banks(&self) -> bool {
+        self.cnt_l & WAVE_TWO_BANKS != 0
+    }
+
+    /// The CPU always sees the bank that isn't selected for playback, even
+    /// while the channel is playing
+    pub fn read_wave_ram(&self, idx: usize) -> u8 {
+        self.wave_ram[self.bank() ^ 1][idx % WAVE_RAM_LEN]
+    }
+
+    /// Write wave RAM, which goes to the bank not being played
+    pub fn write_wave_ram(&mut self, idx: usize, val: u8) {
+        let bank = self.bank() ^ 1;
+        self.wave_ram[bank][idx % WAVE_RAM_LEN] = val;
+    }
+
+    /// Both banks, for debuggers
+    pub fn wave_bank(&self, bank: usize) -> &[u8; WAVE_RAM_LEN] {
+        &self.wave_ram[bank]
+    }
+
+    /// Reset the channel, keeping the contents of wave RAM
+    pub fn power_off(&mut self) {
+        *self = WaveChannel {
+            wave_ram: self.wave_ram,
+            ..WaveChannel::default()
+        };
+    }
+
+    fn dac_enabled(&self) -> bool {
+        self.cnt_l & WAVE_DAC_ENABLE != 0
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enabled();
+        self.length.trigger(WAVE_LEN_MAX);
+        self.timer = self.period();
+        self.position = 0;
+    }
+
+    // 2097152/(2048-n) samples per second, 8 cycles a tick
+    fn period(&self) -> u32 {
+        (2048 - self.freq as u32) * 8
+    }
+
+    /// Run the channel for cycles
+    pub fn step(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.position += 1;
+            if self.position == WAVE_SAMPLES {
+                self.position = 0;
+                // A 64 sample wave carries on into the other bank, which
+                // then becomes the selected bank
+                if self.is_two_banks() {
+                    self.cnt_l ^= WAVE_BANK_SELECT;
+                }
+            }
+        }
+        self.timer -= cycles;
+    }
+
+    /// Tick the length counter, silencing the channel when it runs out
+    pub fn clock_length(&mut self) {
+        if !self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    /// Whether the channel is playing
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Frequency setting, the period being 2048 minus it
+    pub fn freq(&self) -> u16 { self.freq }
+
+    /// Pitch in Hz of the wave in wave RAM, played once round
+    pub fn frequency(&self) -> f64 {
+        let banks = if self.is_two_banks() { 2 } else { 1 };
+        CPU_FREQ as f64 / (self.period() * WAVE_SAMPLES as u32 * banks) as f64
+    }
+
+    /// 0, 25, 50, 75 or 100
+    pub fn volume_percent(&self) -> u8 {
+        if self.cnt_h & WAVE_FORCE_75 != 0 {
+            return 75;
+        }
+        match (self.cnt_h >> WAVE_VOL_SHIFT) & 0b11 {
+            0 => 0,
+            1 => 100,
+            2 => 50,
+            _ => 25,
+        }
+    }
+
+    // Current 4-bit sample; the high nibble of each byte plays first
+    fn sample(&self) -> u8 {
+        let byte = self.wave_ram[self.bank()][self.position as usize / 2];
+        if self.position.is_multiple_of(2) { byte >> 4 } else { byte & 0xF }
+    }
+
+    /// Signed output between -15 and 15
+    pub fn output(&self) -> i16 {
+        if !self.enabled {
+            return 0;
+        }
+
+        let sample = self.sample() as i16 * 2 - PSG_MAX_VOLUME;
+        if self.cnt_h & WAVE_FORCE_75 != 0 {
+            return sample * 3 / 4;
+        }
+        match (self.cnt_h >> WAVE_VOL_SHIFT) & 0b11 {
+            0 => 0,
+            1 => sample,
+            2 => sample / 2,
+            _ => sample / 4,
+        }
+    }
+}
+
+/// Noise channel 4
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoiseChannel {
+    cnt_l: u16,
+    cnt_h: u16,
+    enabled: bool,
+    timer: u32,
+    lfsr: u16,
+    env: Envelope,
+    length: LengthCounter,
+}
+
+impl_save_state!(NoiseChannel { cnt_l, cnt_h, enabled, timer, lfsr, env, length });
+
+const NOISE_LEN_MAX:     u16 = 64;
+const NOISE_LEN_MASK:    u16 = 0x003F;
+const NOISE_RATIO_MASK:  u16 = 0x0007;
+const NOISE_WIDTH_7:     u16 = 0x0008; // Counter step width, 1=7 bits
+const NOISE_SHIFT_SHIFT: u16 = 4;
+const NOISE_SHIFT_MASK:  u16 = 0x00F0;
+
+// The shift register is always 15 bits wide. Feedback is the XOR of the
+// two low bits and goes into bit 14, and also into bit 6 in 7-bit mode, so
+// the width can change mid-note without losing the register's state.
+// http://gbdev.io/pandocs/Audio_details.html#noise-channel-ch4
+const LFSR_INIT:     u16 = 0x7FFF;
+const LFSR_15_SHIFT: u16 = 14;
+const LFSR_7_SHIFT:  u16 = 6;
+
+impl NoiseChannel {
+    /// Length/Envelope (SOUND4CNT_L)
+    pub fn read_cnt_l(&self) -> u16 { self.cnt_l & 0xFF00 }
+    /// Write the length and envelope register
+    pub fn write_cnt_l(&mut self, val: u16) {
+        self.cnt_l = val;
+        self.length.load(NOISE_LEN_MAX, val & NOISE_LEN_MASK);
+        self.env.write(val);
+        if !self.env.dac_enabled() {
+            self.enabled = false;
+        }
+    }
+
+    /// Frequency/Control (SOUND4CNT_H)
+    pub fn read_cnt_h(&self) -> u16 { self.cnt_h & 0x40FF }
+    /// Write the frequency and control register, starting a note if INITIAL is set
+    pub fn write_cnt_h(&mut self, val: u16) {
+        self.cnt_h = val & !INITIAL;
+        self.length.enabled = val & LENGTH_FLAG != 0;
+        if val & INITIAL != 0 {
+            self.trigger();
+        }
+    }
+
+    fn is_7bit(&self) -> bool {
+        self.cnt_h & NOISE_WIDTH_7 != 0
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.env.dac_enabled();
+        self.length.trigger(NOISE_LEN_MAX);
+        self.env.trigger();
+        self.timer = self.period();
+        self.lfsr = LFSR_INIT;
+    }
+
+    // 524288/r/2^(s+1) Hz, with r=0 treated as r=0.5
+    fn period(&self) -> u32 {
+        let r = (self.cnt_h & NOISE_RATIO_MASK) as u32;
+        let s = ((self.cnt_h & NOISE_SHIFT_MASK) >> NOISE_SHIFT_SHIFT) as u32;
+        let base = if r == 0 { 16 } else { 32 * r };
+        base << (s + 1)
+    }
+
+    fn clock_lfsr(&mut self) {
+        let feedback = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+        self.lfsr = (self.lfsr >> 1) | (feedback << LFSR_15_SHIFT);
+        if self.is_7bit() {
+            self.lfsr = (self.lfsr & !(1 << LFSR_7_SHIFT)) | (feedback << LFSR_7_SHIFT);
+        }
+    }
+
+    /// Run the channel for cycles
+    pub fn step(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.clock_lfsr();
+        }
+        self.timer -= cycles;
+    }
+
+    /// Tick the length counter, silencing the channel when it runs out
+    pub fn clock_length(&mut self) {
+        if !self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    /// Tick the volume envelope
+    pub fn clock_envelope(&mut self) {
+        self.env.clock();
+    }
+
+    /// Whether the channel is playing
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// The noise shift register
+    pub fn lfsr(&self) -> u16 { self.lfsr }
+    /// The volume envelope
+    pub fn envelope(&self) -> &Envelope { &self.env }
+
+    /// 7 or 15
+    pub fn width(&self) -> u8 {
+        if self.is_7bit() { 7 } else { 15 }
+    }
+
+    /// Rate in Hz the shift register is clocked at
+    pub fn frequency(&self) -> f64 {
+        CPU_FREQ as f64 / self.period() as f64
+    }
+
+    /// Signed output between -15 and 15. The output is high while bit 0 of
+    /// the register is clear.
+    pub fn output(&self) -> i16 {
+        if !self.enabled {
+            return 0;
+        }
+
+        let vol = self.env.volume() as i16;
+        if self.lfsr & 1 == 0 { vol } else { -vol }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prelude::*;
+
+    const LFSR_15_PERIOD: usize = 32767;
+    const LFSR_7_PERIOD:  usize = 127;
+
+    // Noise channel triggered at full volume with the given SOUND4CNT_H
+    fn noise(cnt_h: u16) -> NoiseChannel {
+        let mut ch = NoiseChannel::default();
+        ch.write_cnt_l(0xF000);
+        ch.write_cnt_h(cnt_h | INITIAL);
+        ch
+    }
+
+    fn is_high(ch: &NoiseChannel) -> bool {
+        ch.output() > 0
+    }
+
+    // Wave channel playing bank 0 at the fastest rate, one sample per tick
+    fn wave(cnt_l: u16) -> WaveChannel {
+        let mut ch = WaveChannel::default();
+        ch.write_cnt_l(cnt_l | WAVE_DAC_ENABLE);
+        ch.write_cnt_h(1 << WAVE_VOL_SHIFT);
+        ch.write_cnt_x(FREQ_MASK | INITIAL);
+        ch
+    }
+
+    #[test]
+    fn wave_cpu_accesses_other_bank() {
+        let mut ch = wave(0);
+        ch.write_wave_ram(0, 0xAB);
+        assert_eq!(ch.wave_bank(1)[0], 0xAB);
+        assert_eq!(ch.wave_bank(0)[0], 0x00);
+        assert_eq!(ch.read_wave_ram(0), 0xAB);
+
+        // Selecting bank 1 for playback exposes bank 0 to the CPU
+        ch.write_cnt_l(WAVE_DAC_ENABLE | WAVE_BANK_SELECT);
+        assert_eq!(ch.read_wave_ram(0), 0x00);
+        ch.write_wave_ram(0, 0xCD);
+        assert_eq!(ch.wave_bank(0)[0], 0xCD);
+        assert_eq!(ch.wave_bank(1)[0], 0xAB);
+    }
+
+    #[test]
+    fn wave_rewrite_while_playing() {
+        let mut ch = wave(0);
+        let tick = ch.period();
+
+        // Bank 0 holds zeros and keeps playing while the CPU fills bank 1
+        for i in 0..WAVE_RAM_LEN {
+            ch.write_wave_ram(i, 0xFF);
+        }
+        for _ in 0..WAVE_SAMPLES {
+            assert_eq!(ch.output(), -PSG_MAX_VOLUME);
+            ch.step(tick);
+        }
+        assert_eq!(ch.bank(), 0);
+    }
+
+    #[test]
+    fn wave_two_banks_play_in_sequence() {
+        let mut ch = wave(WAVE_TWO_BANKS);
+        let tick = ch.period();
+        for i in 0..WAVE_RAM_LEN {
+            ch.write_wave_ram(i, 0xFF);
+        }
+
+        for _ in 0..WAVE_SAMPLES {
+            assert_eq!(ch.output(), -PSG_MAX_VOLUME);
+            ch.step(tick);
+        }
+        assert_eq!(ch.bank(), 1);
+        for _ in 0..WAVE_SAMPLES {
+            assert_eq!(ch.output(), PSG_MAX_VOLUME);
+            ch.step(tick);
+        }
+        assert_eq!(ch.bank(), 0);
+    }
+
+    #[test]
+    fn trigger_loads_all_ones() {
+        assert_eq!(noise(0).lfsr(), LFSR_INIT);
+        assert_eq!(noise(NOISE_WIDTH_7).lfsr(), LFSR_INIT);
+    }
+
+    #[test]
+    fn lfsr_15_known_sequence() {
+        let mut ch = noise(0);
+
+        // Zeros are shifted in until the two low bits differ
+        for i in 1..14 {
+            ch.clock_lfsr();
+            assert_eq!(ch.lfsr(), LFSR_INIT >> i);
+            assert!(!is_high(&ch));
+        }
+        ch.clock_lfsr();
+        assert_eq!(ch.lfsr(), 0x0001);
+        assert!(!is_high(&ch));
+
+        ch.clock_lfsr();
+        assert_eq!(ch.lfsr(), 0x4000);
+        assert!(is_high(&ch));
+
+        for _ in 0..13 {
+            ch.clock_lfsr();
+        }
+        assert_eq!(ch.lfsr(), 0x0002);
+        ch.clock_lfsr();
+        assert_eq!(ch.lfsr(), 0x4001);
+        assert!(!is_high(&ch));
+        ch.clock_lfsr();
+        assert_eq!(ch.lfsr(), 0x6000);
+        assert!(is_high(&ch));
+    }
+
+    #[test]
+    fn lfsr_7_known_sequence() {
+        let mut ch = noise(NOISE_WIDTH_7);
+        let low = |ch: &NoiseChannel| ch.lfsr() & 0x7F;
+
+        // Feedback lands in bit 6 as well as bit 14
+        for i in 1..7 {
+            ch.clock_lfsr();
+            assert_eq!(low(&ch), 0x7F >> i);
+            assert!(!is_high(&ch));
+        }
+        ch.clock_lfsr();
+        assert_eq!(low(&ch), 0x40);
+        assert!(is_high(&ch));
+        ch.clock_lfsr();
+        assert_eq!(low(&ch), 0x20);
+        assert!(is_high(&ch));
+    }
+
+    #[test]
+    fn lfsr_15_period() {
+        let mut ch = noise(0);
+        for i in 1..LFSR_15_PERIOD {
+            ch.clock_lfsr();
+            assert!(ch.lfsr() != LFSR_INIT, "repeated after {} clocks", i);
+        }
+        ch.clock_lfsr();
+        assert_eq!(ch.lfsr(), LFSR_INIT);
+    }
+
+    #[test]
+    fn lfsr_7_period() {
+        let mut ch = noise(NOISE_WIDTH_7);
+        let low = |ch: &NoiseChannel| ch.lfsr() & 0x7F;
+
+        let start = low(&ch);
+        for i in 1..LFSR_7_PERIOD {
+            ch.clock_lfsr();
+            assert!(low(&ch) != start, "repeated after {} clocks", i);
+        }
+        ch.clock_lfsr();
+        assert_eq!(low(&ch), start);
+    }
+
+    #[test]
+    fn width_switch_keeps_register() {
+        let mut ch = noise(0);
+        for _ in 0..100 {
+            ch.clock_lfsr();
+        }
+        let before = ch.lfsr();
+
+        // Changing the width without INITIAL must not reload the register
+        ch.write_cnt_h(NOISE_WIDTH_7);
+        assert_eq!(ch.lfsr(), before);
+
+        ch.clock_lfsr();
+        let feedback = (before ^ (before >> 1)) & 1;
+        let expected = ((before >> 1) | (feedback << 14)) & !0x40 | (feedback << 6);
+        assert_eq!(ch.lfsr(), expected);
+
+        // Once in 7-bit mode the output repeats every 127 clocks
+        let mut first = Vec::new();
+        for _ in 0..LFSR_7_PERIOD {
+            ch.clock_lfsr();
+            first.push(is_high(&ch));
+        }
+        for &out in first.iter() {
+            ch.clock_lfsr();
+            assert_eq!(is_high(&ch), out);
+        }
+
+        // And switching back resumes the 15-bit sequence
+        ch.write_cnt_h(0);
+        let mid = ch.lfsr();
+        ch.clock_lfsr();
+        let feedback = (mid ^ (mid >> 1)) & 1;
+        assert_eq!(ch.lfsr(), (mid >> 1) | (feedback << 14));
+    }
+}
@@ -0,0 +1,83 @@
+use cpal;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+
+use gba_audio::SampleQueue;
+
+// Plays queued samples on the host's default output device
+#[allow(missing_debug_implementations)]
+pub struct CpalOutput {
+    stream: cpal::Stream,
+    queue: SampleQueue,
+    rate: u32,
+}
+
+impl CpalOutput {
+    pub fn open() -> Result<CpalOutput, String> {
+        let host = cpal::default_host();
+        let device = host.default_output_device()
+            .ok_or_else(|| "No audio output device available".to_string())?;
+        let supported = device.default_output_config()
+            .map_err(|e| e.to_string())?;
+        let channels = supported.channels() as usize;
+        let format = supported.sample_format();
+        let config: cpal::StreamConfig = supported.into();
+        let rate = config.sample_rate.0;
+
+        let queue = SampleQueue::default();
+        let cb_queue = queue.clone();
+        let mut frame = [0i16; 2];
+        let err_fn = |e| warn!(target: "audio", "audio stream error: {}", e);
+
+        // Queued samples are stereo; devices with more channels get silence
+        // on the rest and mono devices get the left channel.
+        let stream = match format {
+            cpal::SampleFormat::I16 => device.build_output_stream(
+                &config,
+                move |data: &mut [i16], _: &cpal::OutputCallbackInfo| {
+                    for out in data.chunks_mut(channels) {
+                        cb_queue.pop_into(&mut frame);
+                        for (i, s) in out.iter_mut().enumerate() {
+                            *s = if i < 2 { frame[i] } else { 0 };
+                        }
+                    }
+                },
+                err_fn, None),
+            cpal::SampleFormat::F32 => device.build_output_stream(
+                &config,
+                move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                    for out in data.chunks_mut(channels) {
+                        cb_queue.pop_into(&mut frame);
+                        for (i, s) in out.iter_mut().enumerate() {
+                            *s = if i < 2 { frame[i] as f32 / 32768.0 } else { 0.0 };
+                        }
+                    }
+                },
+                err_fn, None),
+            f => return Err(format!("Unsupported audio sample format {:?}", f)),
+        }.map_err(|e| e.to_string())?;
+
+        stream.play().map_err(|e| e.to_string())?;
+
+        Ok(CpalOutput {
+            stream: stream,
+            queue: queue,
+            rate: rate,
+        })
+    }
+
+    pub fn queue(&self) -> &SampleQueue {
+        &self.queue
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.rate
+    }
+
+    pub fn pause(&self) -> Result<(), String> {
+        self.stream.pause().map_err(|e| e.to_string())
+    }
+
+    pub fn resume(&self) -> Result<(), String> {
+        self.stream.play().map_err(|e| e.to_string())
+    }
+}
@@ -0,0 +1,124 @@
+#[cfg(feature = "audio")]
+pub mod cpal_output;
+/// Resampling the APU's output to the host's rate
+pub mod resample;
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use gba_apu::Apu;
+use gba_audio::resample::Resampler;
+
+/// Interleaved stereo samples shared between the emulation thread and the
+/// audio device callback
+#[derive(Clone, Debug, Default)]
+pub struct SampleQueue {
+    samples: Arc<Mutex<VecDeque<i16>>>,
+}
+
+impl SampleQueue {
+    /// Queue interleaved stereo samples
+    pub fn push(&self, samples: &[i16]) {
+        self.samples.lock().unwrap().extend(samples.iter().cloned());
+    }
+
+    /// Fill out with queued samples, padding with silence on underrun
+    pub fn pop_into(&self, out: &mut [i16]) {
+        let mut samples = self.samples.lock().unwrap();
+        for s in out.iter_mut() {
+            *s = samples.pop_front().unwrap_or(0);
+        }
+    }
+
+    /// Number of stereo frames waiting to be played
+    pub fn len_frames(&self) -> usize {
+        self.samples.lock().unwrap().len() / 2
+    }
+
+    /// Drop every queued sample
+    pub fn clear(&self) {
+        self.samples.lock().unwrap().clear();
+    }
+}
+
+/// Moves samples from the APU mixer to an audio device, converting from the
+/// APU's sample rate to the device rate on the way.
+#[derive(Debug)]
+pub struct AudioOutput {
+    queue: SampleQueue,
+    resampler: Resampler,
+    device_rate: u32,
+    scratch: Vec<i16>,
+}
+
+impl AudioOutput {
+    /// Output resampling from apu_rate to device_rate into queue
+    pub fn new(queue: SampleQueue, apu_rate: u32, device_rate: u32) -> AudioOutput {
+        AudioOutput {
+            queue,
+            resampler: Resampler::new(apu_rate, device_rate),
+            device_rate,
+            scratch: Vec::new(),
+        }
+    }
+
+    /// Samples waiting for the device
+    pub fn queue(&self) -> &SampleQueue {
+        &self.queue
+    }
+
+    /// The device's sample rate
+    pub fn device_rate(&self) -> u32 {
+        self.device_rate
+    }
+
+    /// Pull everything the APU has mixed since the last call
+    pub fn push_from(&mut self, apu: &mut Apu) {
+        let rate = apu.sample_rate();
+        self.push(&apu.drain_samples(), rate);
+    }
+
+    /// Queue samples already taken from the APU, mixed at rate
+    pub fn push(&mut self, samples: &[i16], rate: u32) {
+        if rate != self.resampler.in_rate() {
+            self.resampler = Resampler::new(rate, self.device_rate);
+        }
+
+        self.scratch.clear();
+        self.resampler.process(samples, &mut self.scratch);
+        self.queue.push(&self.scratch);
+    }
+}
+
+/// Audio driven frame pacing. Instead of waiting on vsync, the emulation
+/// thread blocks while more than the target latency of audio is queued, so
+/// the device's clock sets the emulation speed.
+#[derive(Clone, Debug)]
+pub struct AudioPacer {
+    queue: SampleQueue,
+    target_frames: usize,
+}
+
+impl AudioPacer {
+    /// Keep roughly latency_ms of audio buffered at device_rate
+    pub fn new(queue: SampleQueue, device_rate: u32, latency_ms: u32) -> AudioPacer {
+        AudioPacer {
+            queue,
+            target_frames: (device_rate as u64 * latency_ms as u64 / 1000) as usize,
+        }
+    }
+
+    /// Whether more than the target latency is queued
+    pub fn should_wait(&self) -> bool {
+        self.queue.len_frames() > self.target_frames
+    }
+
+    /// Sleep until the queue is down to the target latency
+    pub fn wait(&self) {
+        while self.should_wait() {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
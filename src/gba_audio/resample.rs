@@ -0,0 +1,52 @@
+/// Linear interpolating resampler for interleaved stereo samples. The APU
+/// mixes at its own rate (32768Hz by default) while most devices run at
+/// 44.1kHz or 48kHz.
+#[derive(Clone, Copy, Debug)]
+pub struct Resampler {
+    in_rate: u32,
+    out_rate: u32,
+    pos: u64, // Position between prev and the next input frame, scaled by out_rate
+    prev: (i16, i16),
+}
+
+impl Resampler {
+    /// Resample from in_rate to out_rate
+    pub fn new(in_rate: u32, out_rate: u32) -> Resampler {
+        assert!(in_rate > 0 && out_rate > 0);
+
+        Resampler {
+            in_rate,
+            out_rate,
+            pos: 0,
+            prev: (0, 0),
+        }
+    }
+
+    /// Input sample rate
+    pub fn in_rate(&self) -> u32 { self.in_rate }
+    /// Output sample rate
+    pub fn out_rate(&self) -> u32 { self.out_rate }
+
+    /// Resample input, appending the result to out. Fractional positions
+    /// carry over between calls so chunks can be fed in any size.
+    pub fn process(&mut self, input: &[i16], out: &mut Vec<i16>) {
+        let out_rate = self.out_rate as u64;
+
+        for frame in input.chunks(2) {
+            let next = (frame[0], *frame.get(1).unwrap_or(&frame[0]));
+
+            while self.pos < out_rate {
+                let t = self.pos as i64;
+                let lerp = |a: i16, b: i16| {
+                    (a as i64 + (b as i64 - a as i64) * t / out_rate as i64) as i16
+                };
+                out.push(lerp(self.prev.0, next.0));
+                out.push(lerp(self.prev.1, next.1));
+                self.pos += self.in_rate as u64;
+            }
+
+            self.pos -= out_rate;
+            self.prev = next;
+        }
+    }
+}
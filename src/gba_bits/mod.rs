@@ -0,0 +1,102 @@
+// Fields of hardware registers kept as plain integers: a run of width bits
+// starting shift bits up, e.g. the BG mode in the bottom three bits of
+// DISPCNT or LYC in the top byte of DISPSTAT
+
+use core::mem::size_of;
+
+/// Integers holding register fields
+pub trait Bits: Copy {
+    /// The width bits starting shift bits up
+    fn field(&self, shift: u32, width: u32) -> Self;
+
+    /// Replace the field with val, leaving the other bits alone. Bits of val
+    /// that don't fit are dropped.
+    fn set_field(&mut self, shift: u32, width: u32, val: Self);
+
+    /// Replace the bits in mask with those of val
+    fn write_masked(&mut self, mask: Self, val: Self);
+}
+
+macro_rules! def_bits {
+    ($($t:ty),*) => {
+        $(
+            impl Bits for $t {
+                fn field(&self, shift: u32, width: u32) -> $t {
+                    (*self >> shift) & field_mask::<$t>(width)
+                }
+
+                fn set_field(&mut self, shift: u32, width: u32, val: $t) {
+                    self.write_masked(field_mask::<$t>(width) << shift, val << shift);
+                }
+
+                fn write_masked(&mut self, mask: $t, val: $t) {
+                    *self = (*self & !mask) | (val & mask);
+                }
+            }
+        )*
+    };
+}
+
+def_bits!(u8, u16, u32);
+
+// The bottom width bits set
+fn field_mask<T>(width: u32) -> T
+    where T: Copy + ::core::ops::Not<Output = T> + ::core::ops::Shr<u32, Output = T> + Default {
+    let bits = (size_of::<T>() * 8) as u32;
+    assert!(width > 0 && width <= bits, "bit field of width {}", width);
+    !T::default() >> (bits - width)
+}
+
+// Getters, and optionally setters, for fields of a struct member that is an
+// integer or a Register:
+//
+//     bitfield!(Ppu {
+//         dispcnt => pub bg_mode, set_bg_mode: u16 [0, 3];
+//         dispstat => pub lyc: u16 [8, 8];
+//     });
+macro_rules! bitfield {
+    ($ty:ty { $($reg:tt => $vis:vis $get:ident $(, $set:ident)?: $int:ty [$shift:expr, $width:expr];)* }) => {
+        impl $ty {
+            $(
+                #[doc = concat!("The ", stringify!($width), " bits at bit ", stringify!($shift),
+                                " of ", stringify!($reg))]
+                $vis fn $get(&self) -> $int {
+                    #[allow(unused_imports)]
+                    use $crate::gba_bits::Bits;
+                    self.$reg.field($shift, $width)
+                }
+
+                $(
+                    #[doc = concat!("Set the ", stringify!($width), " bits at bit ",
+                                    stringify!($shift), " of ", stringify!($reg))]
+                    $vis fn $set(&mut self, val: $int) {
+                        #[allow(unused_imports)]
+                        use $crate::gba_bits::Bits;
+                        self.$reg.set_field($shift, $width, val)
+                    }
+                )?
+            )*
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_read_and_write_in_place() {
+        let mut x: u16 = 0xABCD;
+        assert_eq!(x.field(4, 8), 0xBC);
+        assert_eq!(x.field(0, 16), 0xABCD);
+
+        x.set_field(4, 8, 0x123);
+        assert_eq!(x, 0xA23D);
+        x.write_masked(0xF00F, 0x1234);
+        assert_eq!(x, 0x1234);
+
+        let mut y: u32 = 0;
+        y.set_field(28, 4, 0xF);
+        assert_eq!(y, 0xF0000000);
+    }
+}
@@ -0,0 +1,628 @@
+/// Narrowing down where a game keeps a value
+pub mod ram_search;
+
+use core::fmt;
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+use gba_error::{GbaError, GbaResult};
+use gba_mem::{Address, Memory};
+use prelude::*;
+
+// Cheat device code formats from GBATEK's GBA Cheat Codes sections:
+// http://problemkaputt.de/gbatek.htm
+//
+// GameShark and Action Replay codes are usually published encrypted with
+// TEA under a key fixed per device generation. CodeBreaker codes are
+// usually published raw; encrypted ones follow a 9 line that seeds the key,
+// which is set up as the CodeBreaker ROM does it, following VBA-M's port.
+const GS_V1_SEEDS: [u32; 4] = [0x09F4FBBD, 0x9681884A, 0x352027E9, 0xF3DEE5A7];
+const GS_V3_SEEDS: [u32; 4] = [0x7AA9648F, 0x7FAE6994, 0xC0EFAAD5, 0x42712C57];
+const TEA_DELTA: u32 = 0x9E3779B9;
+const TEA_ROUNDS: u32 = 32;
+
+const ADDR_MASK: u32 = 0x0FFFFFFF;
+
+// An encrypted CodeBreaker line is 48 bits: the address then the value
+const CB_BITS: usize = 48;
+const CB_SHUFFLES: u32 = 0x50;
+
+fn invalid(msg: String) -> GbaError {
+    GbaError::InvalidCheat(msg)
+}
+
+fn tea_decrypt(mut addr: u32, mut val: u32, seeds: &[u32; 4]) -> (u32, u32) {
+    let mut sum = TEA_DELTA.wrapping_mul(TEA_ROUNDS);
+    for _ in 0..TEA_ROUNDS {
+        val = val.wrapping_sub((addr << 4).wrapping_add(seeds[2]) ^ addr.wrapping_add(sum) ^
+                               (addr >> 5).wrapping_add(seeds[3]));
+        addr = addr.wrapping_sub((val << 4).wrapping_add(seeds[0]) ^ val.wrapping_add(sum) ^
+                                 (val >> 5).wrapping_add(seeds[1]));
+        sum = sum.wrapping_sub(TEA_DELTA);
+    }
+    (addr, val)
+}
+
+// Three steps of the C standard's sample rand() generator, packed into a
+// word the way CodeBreaker draws its keys
+fn cb_random(state: &mut u32) -> u32 {
+    let step = |s: u32| s.wrapping_mul(0x41C64E6D).wrapping_add(0x3039);
+    let x = step(*state);
+    let y = step(x);
+    let z = step(y);
+    *state = z;
+    (x >> 16) << 30 | ((y >> 16) & 0x7FFF) << 15 | ((z >> 16) & 0x7FFF)
+}
+
+// Draw a pair of key words, each of the first skip draws seeding the next
+fn cb_key_pair(mut state: u32, skip: u32) -> (u32, u32) {
+    for _ in 0..skip {
+        let mut scratch = state;
+        state = cb_random(&mut scratch);
+    }
+    let first = cb_random(&mut state);
+    (first, cb_random(&mut state))
+}
+
+// A code line as CodeBreaker scrambles it: the address then the value, most
+// significant byte first
+fn cb_bytes(addr: u32, val: u32) -> [u8; 6] {
+    let (a, v) = (addr.to_be_bytes(), (val as u16).to_be_bytes());
+    [a[0], a[1], a[2], a[3], v[0], v[1]]
+}
+
+fn cb_line(bytes: &[u8; 6]) -> (u32, u32) {
+    (u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+     u16::from_be_bytes([bytes[4], bytes[5]]) as u32)
+}
+
+fn swap_bits(bytes: &mut [u8; 6], a: usize, b: usize) {
+    let (bit_a, bit_b) = (bytes[a >> 3] >> (a & 7) & 1, bytes[b >> 3] >> (b & 7) & 1);
+    bytes[a >> 3] = (bytes[a >> 3] & !(1 << (a & 7))) | bit_b << (a & 7);
+    bytes[b >> 3] = (bytes[b >> 3] & !(1 << (b & 7))) | bit_a << (b & 7);
+}
+
+// The key for encrypted CodeBreaker codes, from a 9xxxxxxx yyyy seed line:
+// an order to shuffle a line's bits in, four words to XOR it with and the
+// seed line's address, which is chained through its bytes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct CbKey {
+    shuffle: [u8; CB_BITS],
+    words: [u32; 4],
+    seed: u32,
+}
+
+impl CbKey {
+    fn new(addr: u32, val: u32) -> CbKey {
+        let mut shuffle = [0; CB_BITS];
+        for (i, bit) in shuffle.iter_mut().enumerate() {
+            *bit = i as u8;
+        }
+        let mut state = (val & 0xFF) ^ 0x1111;
+        for _ in 0..CB_SHUFFLES {
+            let a = cb_random(&mut state) as usize % CB_BITS;
+            let b = cb_random(&mut state) as usize % CB_BITS;
+            shuffle.swap(a, b);
+        }
+        let (w2, w3) = cb_key_pair(0x4EFAD1C3, (addr >> 24) & 0xF);
+        let (w0, w1) = cb_key_pair(((val >> 8) & 0xFF) ^ 0xF254, (val >> 8) & 0xFF);
+        CbKey {
+            shuffle,
+            words: [w0, w1, w2, w3],
+            seed: addr,
+        }
+    }
+
+    fn decrypt(&self, addr: u32, val: u32) -> (u32, u32) {
+        let mut bytes = cb_bytes(addr, val);
+        for bit in (0..CB_BITS).rev() {
+            swap_bits(&mut bytes, bit, self.shuffle[bit] as usize);
+        }
+        let (addr, val) = cb_line(&bytes);
+        let mut bytes = cb_bytes(addr ^ self.words[0], val ^ self.words[1]);
+        let (lo, hi) = (self.seed as u8, (self.seed >> 8) as u8);
+        for i in 0..5 {
+            bytes[i] ^= hi ^ bytes[i + 1];
+        }
+        bytes[5] ^= hi;
+        for i in (1..6).rev() {
+            bytes[i] ^= lo ^ bytes[i - 1];
+        }
+        bytes[0] ^= lo;
+        let (addr, val) = cb_line(&bytes);
+        (addr ^ self.words[2], (val ^ self.words[3]) & 0xFFFF)
+    }
+}
+
+/// Devices whose codes can be loaded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheatFormat {
+    /// GameShark and Action Replay v1/v2
+    GameShark,
+    /// Pro Action Replay and GameShark v3
+    ActionReplay,
+    /// CodeBreaker
+    CodeBreaker,
+}
+
+impl CheatFormat {
+    /// Names used in cheat files, with whether codes are encrypted
+    pub fn from_name(name: &str) -> Option<(CheatFormat, bool)> {
+        match name {
+            "gs" => Some((CheatFormat::GameShark, true)),
+            "gs-raw" => Some((CheatFormat::GameShark, false)),
+            "ar" => Some((CheatFormat::ActionReplay, true)),
+            "ar-raw" => Some((CheatFormat::ActionReplay, false)),
+            "cb" => Some((CheatFormat::CodeBreaker, false)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for CheatFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            CheatFormat::GameShark => "GameShark v1/v2",
+            CheatFormat::ActionReplay => "Action Replay v3",
+            CheatFormat::CodeBreaker => "CodeBreaker",
+        };
+        write![f, "{}", name]
+    }
+}
+
+/// What a line of code does each frame, after decryption
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheatOp {
+    /// A master code, telling the device which game it is in and where to
+    /// hook the game's code to run the cheats from. Cheats are applied once
+    /// a frame instead, so it does nothing but is kept to show.
+    MasterCode {
+        /// The code's first word
+        code: u32,
+        /// The code's second word
+        val: u32,
+    },
+    /// Fills count bytes
+    Write8 {
+        /// First byte written
+        addr: Address,
+        /// Byte written
+        val: u8,
+        /// Bytes written
+        count: u32,
+    },
+    /// Fills count halfwords
+    Write16 {
+        /// First halfword written
+        addr: Address,
+        /// Halfword written
+        val: u16,
+        /// Halfwords written
+        count: u32,
+    },
+    /// Writes a word
+    Write32 {
+        /// Where it goes
+        addr: Address,
+        /// Word written
+        val: u32,
+    },
+    /// Sets bits in a halfword
+    Or16 {
+        /// Halfword changed
+        addr: Address,
+        /// Bits set
+        val: u16,
+    },
+    /// Clears bits in a halfword
+    And16 {
+        /// Halfword changed
+        addr: Address,
+        /// Bits kept
+        val: u16,
+    },
+    /// Unless the halfword at addr compares as given, skip the next ops
+    IfEqual16 {
+        /// Halfword compared
+        addr: Address,
+        /// Value it has to equal
+        val: u16,
+        /// Ops skipped otherwise
+        skip: u32,
+    },
+    /// Unless the halfword at addr differs from val, skip the next ops
+    IfNotEqual16 {
+        /// Halfword compared
+        addr: Address,
+        /// Value it has to differ from
+        val: u16,
+        /// Ops skipped otherwise
+        skip: u32,
+    },
+}
+
+impl CheatOp {
+    // Returns how many of the following ops to skip
+    fn apply(&self, mem: &mut Memory) -> u32 {
+        match *self {
+            CheatOp::MasterCode { .. } => {},
+            CheatOp::Write8 { addr, val, count } => {
+                for i in 0..count as Address {
+                    mem.write8::<u8>(addr + i, val);
+                }
+            },
+            CheatOp::Write16 { addr, val, count } => {
+                for i in 0..count as Address {
+                    mem.write16::<u16>(addr + 2 * i, val);
+                }
+            },
+            CheatOp::Write32 { addr, val } => mem.write32::<u32>(addr, val),
+            CheatOp::Or16 { addr, val } => {
+                let old = mem.peek::<u16>(addr);
+                mem.write16::<u16>(addr, old | val);
+            },
+            CheatOp::And16 { addr, val } => {
+                let old = mem.peek::<u16>(addr);
+                mem.write16::<u16>(addr, old & val);
+            },
+            CheatOp::IfEqual16 { addr, val, skip } =>
+                if mem.peek::<u16>(addr) != val { return skip; },
+            CheatOp::IfNotEqual16 { addr, val, skip } =>
+                if mem.peek::<u16>(addr) == val { return skip; },
+        }
+        0
+    }
+}
+
+fn parse_hex(s: &str, digits: usize, line: &str) -> GbaResult<u32> {
+    if s.len() != digits {
+        return Err(invalid(format!("{} isn't a valid code line", line)));
+    }
+    u32::from_str_radix(s, 16).map_err(|_| invalid(format!("{} isn't a valid code line", line)))
+}
+
+// "XXXXXXXX YYYYYYYY" or "XXXXXXXX YYYY", with or without the space
+fn parse_line(line: &str, val_digits: usize) -> GbaResult<(u32, u32)> {
+    let digits: String = line.chars().filter(|c| !c.is_whitespace()).collect();
+    if digits.len() != 8 + val_digits {
+        return Err(invalid(format!("{} isn't a valid code line", line)));
+    }
+    Ok((parse_hex(&digits[..8], 8, line)?, parse_hex(&digits[8..], val_digits, line)?))
+}
+
+fn unsupported(format: CheatFormat, addr: u32, val: u32) -> GbaError {
+    GbaError::UnsupportedCheat(format!("{} code {:08X} {:08X}", format, addr, val))
+}
+
+fn decode_gameshark(addr: u32, val: u32) -> GbaResult<Option<CheatOp>> {
+    let target = (addr & ADDR_MASK) as Address;
+    match addr >> 28 {
+        0x0 => Ok(Some(CheatOp::Write8 { addr: target, val: val as u8, count: 1 })),
+        0x1 => Ok(Some(CheatOp::Write16 { addr: target, val: val as u16, count: 1 })),
+        0x2 => Ok(Some(CheatOp::Write32 { addr: target, val })),
+        // E0zzxxxx aaaaaaaa: the next zz lines only run if [aaaaaaaa] == xxxx
+        0xE => Ok(Some(CheatOp::IfEqual16 {
+            addr: (val & ADDR_MASK) as Address,
+            val: addr as u16,
+            skip: (addr >> 16) & 0xFF,
+        })),
+        // Fhhhhhhh 00000yyy: hook the game's code at hhhhhhh
+        0xF => Ok(Some(CheatOp::MasterCode { code: addr, val })),
+        _ => Err(unsupported(CheatFormat::GameShark, addr, val)),
+    }
+}
+
+fn decode_action_replay(addr: u32, val: u32) -> GbaResult<Option<CheatOp>> {
+    if addr == 0 && val == 0 {
+        return Ok(None);
+    }
+    // The top byte is the code type and the region is packed into bits
+    // 20-23, e.g. 02224000 is a halfword write to 02024000
+    let target = (((addr & 0x00F00000) << 4) | (addr & 0x0003FFFF)) as Address;
+    match addr >> 24 {
+        0x00 => Ok(Some(CheatOp::Write8 { addr: target, val: val as u8, count: (val >> 8) + 1 })),
+        0x02 => Ok(Some(CheatOp::Write16 { addr: target, val: val as u16, count: (val >> 16) + 1 })),
+        0x04 => Ok(Some(CheatOp::Write32 { addr: target, val })),
+        _ => Err(unsupported(CheatFormat::ActionReplay, addr, val)),
+    }
+}
+
+fn decode_codebreaker(addr: u32, val: u32) -> GbaResult<Option<CheatOp>> {
+    let target = (addr & ADDR_MASK) as Address;
+    let val16 = val as u16;
+    match addr >> 28 {
+        // 0000iiii 000y game ID and 1hhhhhhh iiii hook address
+        0x0 | 0x1 => Ok(Some(CheatOp::MasterCode { code: addr, val })),
+        0x2 => Ok(Some(CheatOp::Or16 { addr: target, val: val16 })),
+        0x3 => Ok(Some(CheatOp::Write8 { addr: target, val: val as u8, count: 1 })),
+        0x6 => Ok(Some(CheatOp::And16 { addr: target, val: val16 })),
+        0x7 => Ok(Some(CheatOp::IfEqual16 { addr: target, val: val16, skip: 1 })),
+        0x8 => Ok(Some(CheatOp::Write16 { addr: target, val: val16, count: 1 })),
+        // 9xxxxxxx yyyy seeds the key for the encrypted lines after it
+        0x9 => Ok(Some(CheatOp::MasterCode { code: addr, val })),
+        0xA => Ok(Some(CheatOp::IfNotEqual16 { addr: target, val: val16, skip: 1 })),
+        _ => Err(unsupported(CheatFormat::CodeBreaker, addr, val)),
+    }
+}
+
+/// A named code, made of one or more ops
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cheat {
+    /// Name shown to the player
+    pub name: String,
+    /// Applied each frame when set
+    pub enabled: bool,
+    ops: Vec<CheatOp>,
+}
+
+impl Cheat {
+    /// Decrypt and decode the lines of a code
+    pub fn parse(name: &str, format: CheatFormat, encrypted: bool, lines: &[&str])
+                 -> GbaResult<Cheat> {
+        Cheat::parse_keyed(name, format, encrypted, lines, &mut None)
+    }
+
+    // CodeBreaker lines after a seed line are decrypted with its key, which
+    // carries on to the cheats that follow in a file
+    fn parse_keyed(name: &str, format: CheatFormat, encrypted: bool, lines: &[&str],
+                   cb_key: &mut Option<CbKey>) -> GbaResult<Cheat> {
+        let mut ops = Vec::new();
+        for line in lines {
+            let op = match format {
+                CheatFormat::GameShark | CheatFormat::ActionReplay => {
+                    let (mut addr, mut val) = parse_line(line, 8)?;
+                    let v3 = format == CheatFormat::ActionReplay;
+                    if encrypted {
+                        let seeds = if v3 { &GS_V3_SEEDS } else { &GS_V1_SEEDS };
+                        let (a, v) = tea_decrypt(addr, val, seeds);
+                        addr = a;
+                        val = v;
+                    }
+                    if v3 { decode_action_replay(addr, val)? } else { decode_gameshark(addr, val)? }
+                },
+                CheatFormat::CodeBreaker => {
+                    let (mut addr, mut val) = parse_line(line, 4)?;
+                    if let Some(ref key) = *cb_key {
+                        let (a, v) = key.decrypt(addr, val);
+                        addr = a;
+                        val = v;
+                    }
+                    if addr >> 28 == 0x9 {
+                        *cb_key = Some(CbKey::new(addr, val));
+                    }
+                    decode_codebreaker(addr, val)?
+                },
+            };
+            ops.extend(op);
+        }
+
+        Ok(Cheat {
+            name: name.to_string(),
+            enabled: true,
+            ops,
+        })
+    }
+
+    /// The code's ops, in order
+    pub fn ops(&self) -> &[CheatOp] {
+        &self.ops
+    }
+
+    /// Whether it carries a master code, which frontends can point out as
+    /// not needed
+    pub fn has_master_code(&self) -> bool {
+        self.ops.iter().any(|op| matches!(*op, CheatOp::MasterCode { .. }))
+    }
+
+    /// Run the code's ops on memory
+    pub fn apply(&self, mem: &mut Memory) {
+        let mut skip = 0;
+        for op in self.ops.iter() {
+            if skip > 0 {
+                skip -= 1;
+                continue;
+            }
+            skip = op.apply(mem);
+        }
+    }
+}
+
+/// Read a cheat file:
+///
+/// ```text
+/// # Comments and blank lines are ignored
+/// [Infinite health] gs
+/// 1A2B3C4D 5E6F7A8B
+/// [!Walk through walls] cb
+/// 82025C8C 03E7
+/// ```
+///
+/// Each cheat is a name in brackets and its format, gs, gs-raw, ar, ar-raw
+/// or cb, followed by its code lines. CodeBreaker lines after a 9 seed line
+/// are taken as encrypted, in the following cheats too. A name starting
+/// with ! is loaded disabled.
+pub fn parse_file(text: &str) -> GbaResult<Vec<Cheat>> {
+    let mut cheats = Vec::new();
+    let mut cb_key = None;
+    let mut current: Option<(String, CheatFormat, bool, bool, Vec<&str>)> = None;
+
+    let lines = text.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'));
+    for line in lines.chain(Some("[]")) {
+        if !line.starts_with('[') {
+            match current {
+                Some((_, _, _, _, ref mut code)) => code.push(line),
+                None => return Err(invalid(format!("{} comes before any cheat name", line))),
+            }
+            continue;
+        }
+
+        if let Some((name, format, encrypted, enabled, code)) = current.take() {
+            let mut cheat = Cheat::parse_keyed(&name, format, encrypted, &code, &mut cb_key)?;
+            cheat.enabled = enabled;
+            cheats.push(cheat);
+        }
+        if line == "[]" {
+            break;
+        }
+
+        let end = line.find(']')
+            .ok_or_else(|| invalid(format!("{} is missing a ]", line)))?;
+        let format_name = line[end + 1..].trim();
+        let (format, encrypted) = CheatFormat::from_name(format_name)
+            .ok_or_else(|| invalid(format!("unknown cheat format {:?}", format_name)))?;
+        let name = &line[1..end];
+        let (name, enabled) = match name.strip_prefix('!') { Some(name) => (name, false), None => (name, true) };
+        current = Some((name.to_string(), format, encrypted, enabled, Vec::new()));
+    }
+    Ok(cheats)
+}
+
+/// The cheats in use, applied once a frame
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CheatEngine {
+    cheats: Vec<Cheat>,
+}
+
+impl CheatEngine {
+    /// Add the cheats in a cheat file
+    #[cfg(feature = "std")]
+    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> GbaResult<()> {
+        let text = fs::read_to_string(path)?;
+        self.cheats.extend(parse_file(&text)?);
+        Ok(())
+    }
+
+    /// Add a cheat, returning its index
+    pub fn add(&mut self, cheat: Cheat) -> usize {
+        self.cheats.push(cheat);
+        self.cheats.len() - 1
+    }
+
+    /// Remove a cheat by index, returning it
+    pub fn remove(&mut self, index: usize) -> Option<Cheat> {
+        if index < self.cheats.len() {
+            Some(self.cheats.remove(index))
+        }
+        else {
+            None
+        }
+    }
+
+    /// Returns false if there is no cheat at index
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) -> bool {
+        match self.cheats.get_mut(index) {
+            Some(cheat) => {
+                cheat.enabled = enabled;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Cheats loaded, by index
+    pub fn cheats(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    /// Run every enabled cheat on memory
+    pub fn apply(&self, mem: &mut Memory) {
+        for cheat in self.cheats.iter().filter(|cheat| cheat.enabled) {
+            cheat.apply(mem);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The published TEA test vector: an all zero block under an all zero
+    // key encrypts to 41EA3A0A 94BAA940
+    #[test]
+    fn tea_matches_the_reference() {
+        assert_eq!(tea_decrypt(0x41EA3A0A, 0x94BAA940, &[0; 4]), (0, 0));
+    }
+
+    // Encrypted under each device's key with the reference TEA, which
+    // gives the vector above
+    #[test]
+    fn encrypted_codes_decode() {
+        let gs = Cheat::parse("money", CheatFormat::GameShark, true, &["DEE319E1 96CF1846"])
+            .unwrap();
+        let raw = Cheat::parse("money", CheatFormat::GameShark, false, &["1200AB10 000003E7"])
+            .unwrap();
+        assert_eq!(gs.ops(), raw.ops());
+        assert_eq!(raw.ops(), &[CheatOp::Write16 { addr: 0x0200AB10, val: 0x3E7, count: 1 }]);
+
+        let ar = Cheat::parse("money", CheatFormat::ActionReplay, true, &["E6600B38 ECE3CFD8"])
+            .unwrap();
+        assert_eq!(ar.ops(), &[CheatOp::Write16 { addr: 0x02024000, val: 0x3E7, count: 1 }]);
+    }
+
+    // Kept rather than dropped, and harmless to apply
+    #[test]
+    fn master_codes_are_kept() {
+        let gs = Cheat::parse("m", CheatFormat::GameShark, true, &["54A2859A 53EBC552"]).unwrap();
+        assert_eq!(gs.ops(), &[CheatOp::MasterCode { code: 0xF8000F00, val: 2 }]);
+        assert!(gs.has_master_code());
+
+        let cb = Cheat::parse("m", CheatFormat::CodeBreaker, false,
+                              &["00004A4B 000A", "1008A2C4 0007", "8200AB12 0063"]).unwrap();
+        assert_eq!(cb.ops().len(), 3);
+        assert!(cb.has_master_code());
+        let mut mem = Memory::blank();
+        cb.apply(&mut mem);
+        assert_eq!(mem.peek::<u16>(0x0200AB12), 0x63);
+    }
+
+    // The first rand() results of the C standard's sample generator seeded
+    // with 1 are 16838, 5758 and 10113
+    #[test]
+    fn codebreaker_generator_matches_the_reference() {
+        let mut state = 1;
+        assert_eq!(cb_random(&mut state), (16838 & 3) << 30 | 5758 << 15 | 10113);
+    }
+
+    #[test]
+    fn codebreaker_shuffle_is_a_permutation() {
+        let mut shuffle = CbKey::new(0x9123DDCA, 0x0BD2).shuffle;
+        shuffle.sort();
+        assert!(shuffle.iter().enumerate().all(|(i, &bit)| bit as usize == i));
+    }
+
+    // Encrypted under the seed line with a port of VBA-M's routine
+    #[test]
+    fn encrypted_codebreaker_codes_decode() {
+        let cb = Cheat::parse("enc", CheatFormat::CodeBreaker, false,
+                              &["9123DDCA 0BD2", "05AEC3FD 8FE3", "0C2B47D4 FA12"]).unwrap();
+        assert_eq!(cb.ops(), &[
+            CheatOp::MasterCode { code: 0x9123DDCA, val: 0x0BD2 },
+            CheatOp::MasterCode { code: 0x00004A4B, val: 0x000A },
+            CheatOp::Write16 { addr: 0x0200AB12, val: 0x63, count: 1 },
+        ]);
+
+        // The key carries on to the next cheat in a file
+        let cheats = parse_file("[Master] cb\n9123DDCA 0BD2\n\
+                                 [Lives] cb\nAA5C6A33 EFBB\n").unwrap();
+        assert_eq!(cheats[1].ops(), &[CheatOp::Write8 { addr: 0x0200AB14, val: 9, count: 1 }]);
+    }
+
+    #[test]
+    fn cheat_files_parse() {
+        let cheats = parse_file("# Test\n\
+                                 [Health] cb\n\
+                                 7200AB10 0001\n\
+                                 8200AB12 0063\n\
+                                 [!Lives] gs-raw\n\
+                                 0200AB14 00000009\n").unwrap();
+        assert_eq!(cheats.len(), 2);
+        assert_eq!(cheats[0].name, "Health");
+        assert_eq!(cheats[0].ops().len(), 2);
+        assert!(!cheats[1].enabled);
+        assert!(parse_file("[Bad] xx\n").is_err());
+    }
+}
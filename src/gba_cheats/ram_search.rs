@@ -0,0 +1,211 @@
+use core::fmt;
+
+use gba_mem::{Address, Memory};
+use prelude::*;
+
+// Searches cover the work RAM games keep their state in
+const EWRAM: Address = 0x02000000;
+const IWRAM: Address = 0x03000000;
+
+/// The kind of value being searched for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SearchType {
+    /// Bytes
+    pub size: u8,
+    /// Whether the top bit is a sign
+    pub signed: bool,
+}
+
+impl SearchType {
+    /// u8, u16, u32, s8, s16 or s32
+    pub fn from_name(name: &str) -> Option<SearchType> {
+        let signed = match name.chars().next() {
+            Some('u') => false,
+            Some('s') => true,
+            _ => return None,
+        };
+        let size = match &name[1..] {
+            "8" => 1,
+            "16" => 2,
+            "32" => 4,
+            _ => return None,
+        };
+        Some(SearchType { size, signed })
+    }
+
+    fn mask(&self) -> u32 {
+        (!0u32) >> (32 - 8 * self.size as u32)
+    }
+
+    /// The value bits hold, as this type
+    pub fn decode(&self, bits: u32) -> i64 {
+        let shift = 32 - 8 * self.size as u32;
+        if self.signed {
+            ((bits << shift) as i32 >> shift) as i64
+        }
+        else {
+            (bits & self.mask()) as i64
+        }
+    }
+}
+
+impl fmt::Display for SearchType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write![f, "{}{}", if self.signed { "s" } else { "u" }, self.size * 8]
+    }
+}
+
+/// What a candidate's value has to be, now or compared with the last search
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    /// Is the value
+    Equal(i64),
+    /// Isn't the value
+    NotEqual(i64),
+    /// Is more than the value
+    Greater(i64),
+    /// Is less than the value
+    Less(i64),
+    /// Differs from the last search
+    Changed,
+    /// Is the same as at the last search
+    Unchanged,
+    /// Is more than at the last search
+    Increased,
+    /// Is less than at the last search
+    Decreased,
+    /// Wrapping, so a u8 going from 0 to 255 changed by -1
+    ChangedBy(i64),
+}
+
+impl Comparison {
+    fn matches(&self, ty: SearchType, now_bits: u32, before_bits: u32) -> bool {
+        let (now, before) = (ty.decode(now_bits), ty.decode(before_bits));
+        let mask = ty.mask();
+        match *self {
+            // Compare the bits, so 0xff finds -1 in an s8 search
+            Comparison::Equal(val) => now_bits == val as u32 & mask,
+            Comparison::NotEqual(val) => now_bits != val as u32 & mask,
+            Comparison::Greater(val) => now > val,
+            Comparison::Less(val) => now < val,
+            Comparison::Changed => now != before,
+            Comparison::Unchanged => now == before,
+            Comparison::Increased => now > before,
+            Comparison::Decreased => now < before,
+            Comparison::ChangedBy(delta) =>
+                now_bits.wrapping_sub(before_bits) & mask == delta as u32 & mask,
+        }
+    }
+}
+
+/// A candidate left by a search
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SearchHit {
+    /// Where the value is
+    pub addr: Address,
+    /// Its value now
+    pub value: i64,
+    /// At the last search
+    pub previous: i64,
+}
+
+/// Narrows down where a game keeps a value, like the cheat search in other
+/// emulators: start with every address in EWRAM and IWRAM, then play a
+/// little between searches that keep only the addresses whose value did
+/// what the one being looked for did
+#[derive(Clone, Debug)]
+pub struct RamSearch {
+    ty: SearchType,
+    ewram: Vec<u8>, // As of the last search
+    iwram: Vec<u8>,
+    candidates: Vec<Address>,
+}
+
+// A little-endian value from a snapshot of the work RAM
+fn read_bits(ewram: &[u8], iwram: &[u8], addr: Address, size: u8) -> u32 {
+    let (data, offset) = if addr >= IWRAM { (iwram, addr - IWRAM) } else { (ewram, addr - EWRAM) };
+    data[offset..offset + size as usize].iter().rev().fold(0, |bits, &b| bits << 8 | b as u32)
+}
+
+impl RamSearch {
+    /// Every address aligned to the size of ty is a candidate
+    pub fn new(mem: &Memory, ty: SearchType) -> RamSearch {
+        let size = ty.size as usize;
+        let candidates = (0..mem.ewram().len()).step_by(size).map(|offset| EWRAM + offset)
+            .chain((0..mem.iwram().len()).step_by(size).map(|offset| IWRAM + offset))
+            .collect();
+        RamSearch {
+            ty,
+            ewram: mem.ewram().to_vec(),
+            iwram: mem.iwram().to_vec(),
+            candidates,
+        }
+    }
+
+    /// What is being searched for
+    pub fn search_type(&self) -> SearchType {
+        self.ty
+    }
+
+    /// Keep the candidates that match, and remember the values now for the
+    /// next search. Returns how many are left.
+    pub fn filter(&mut self, mem: &Memory, cmp: Comparison) -> usize {
+        let ty = self.ty;
+        {
+            let (ewram, iwram) = (mem.ewram(), mem.iwram());
+            let (old_ewram, old_iwram) = (&self.ewram, &self.iwram);
+            self.candidates.retain(|&addr| {
+                let now = read_bits(ewram, iwram, addr, ty.size);
+                let before = read_bits(old_ewram, old_iwram, addr, ty.size);
+                cmp.matches(ty, now, before)
+            });
+        }
+        self.ewram.copy_from_slice(mem.ewram());
+        self.iwram.copy_from_slice(mem.iwram());
+        self.candidates.len()
+    }
+
+    /// Candidates left
+    pub fn len(&self) -> usize {
+        self.candidates.len()
+    }
+
+    /// Whether no candidates are left
+    pub fn is_empty(&self) -> bool {
+        self.candidates.is_empty()
+    }
+
+    /// Up to limit candidates, lowest address first
+    pub fn hits(&self, mem: &Memory, limit: usize) -> Vec<SearchHit> {
+        self.candidates.iter().take(limit).map(|&addr| {
+            SearchHit {
+                addr,
+                value: self.ty.decode(read_bits(mem.ewram(), mem.iwram(), addr, self.ty.size)),
+                previous: self.ty.decode(read_bits(&self.ewram, &self.iwram, addr, self.ty.size)),
+            }
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn search_narrows_to_the_changing_value() {
+        let mut mem = Memory::blank();
+        let ty = SearchType::from_name("s16").unwrap();
+        mem.write16::<u16>(0x03000100, 10);
+        let mut search = RamSearch::new(&mem, ty);
+        assert_eq!(search.len(), (0x40000 + 0x8000) / 2);
+
+        assert_eq!(search.filter(&mem, Comparison::Equal(10)), 1);
+        mem.write16::<u16>(0x03000100, 0xffff);
+        mem.write16::<u16>(0x02000000, 3);
+        assert_eq!(search.filter(&mem, Comparison::ChangedBy(-11)), 1);
+        assert_eq!(search.hits(&mem, 10), vec![SearchHit { addr: 0x03000100, value: -1,
+                                                           previous: -1 }]);
+        assert_eq!(search.filter(&mem, Comparison::Less(0)), 1);
+        assert_eq!(search.filter(&mem, Comparison::Changed), 0);
+    }
+}
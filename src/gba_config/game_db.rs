@@ -0,0 +1,138 @@
+// Built in knowledge of particular games: hardware on the cartridge that
+// the ROM can't tell us about, and settings and fixes they need. It is
+// consulted when a game is loaded, under the config's own sections for it.
+//
+// Entries keyed by a three character game code cover every region's
+// release; a fourth character narrows it to one.
+
+use gba_config::{FlashChip, GameId, GameOverrides, RomPatch, SaveType, Sensor};
+
+/// What an entry is matched against
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GameKey {
+    /// A game code, three characters for every region or four for one
+    Code(&'static str),
+    /// One dump, for fixes that only apply to it
+    Crc32(u32),
+}
+
+/// What the database knows about a game
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GameDbEntry {
+    /// Which game it is
+    pub key: GameKey,
+    /// Detected from the ROM if not given
+    pub save_type: Option<SaveType>,
+    /// For games that check for one
+    pub flash_chip: Option<FlashChip>,
+    /// Real-time clock fitted
+    pub rtc: bool,
+    /// Sensors fitted
+    pub sensors: &'static [Sensor],
+    /// Rumble motor fitted
+    pub rumble: bool,
+    /// Address of a known busy-wait loop
+    pub idle_loop: Option<u32>,
+    /// Bytes written over the ROM at an address
+    pub patches: &'static [(u32, &'static [u8])],
+}
+
+const NONE: GameDbEntry = GameDbEntry {
+    key: GameKey::Code(""),
+    save_type: None,
+    flash_chip: None,
+    rtc: false,
+    sensors: &[],
+    rumble: false,
+    idle_loop: None,
+    patches: &[],
+};
+
+const FLASH128: Option<SaveType> = Some(SaveType::Flash128);
+
+/// Games that need settings or fixes the ROM can't tell us about
+pub static GAME_DB: [GameDbEntry; 14] = [
+    // Pokemon Ruby, Sapphire and Emerald
+    GameDbEntry { key: GameKey::Code("AXV"), save_type: FLASH128, rtc: true, ..NONE },
+    GameDbEntry { key: GameKey::Code("AXP"), save_type: FLASH128, rtc: true, ..NONE },
+    GameDbEntry { key: GameKey::Code("BPE"), save_type: FLASH128, rtc: true, ..NONE },
+    // Pokemon FireRed and LeafGreen
+    GameDbEntry { key: GameKey::Code("BPR"), save_type: FLASH128, ..NONE },
+    GameDbEntry { key: GameKey::Code("BPG"), save_type: FLASH128, ..NONE },
+    // Boktai 1-3
+    GameDbEntry { key: GameKey::Code("U3I"), rtc: true, sensors: &[Sensor::Solar], ..NONE },
+    GameDbEntry { key: GameKey::Code("U32"), rtc: true, sensors: &[Sensor::Solar], ..NONE },
+    GameDbEntry { key: GameKey::Code("U33"), rtc: true, sensors: &[Sensor::Solar], ..NONE },
+    // Rockman EXE 4.5 and Sennen Kazoku
+    GameDbEntry { key: GameKey::Code("BR4"), rtc: true, ..NONE },
+    GameDbEntry { key: GameKey::Code("BKA"), rtc: true, ..NONE },
+    // Yoshi Topsy-Turvy and Koro Koro Puzzle
+    GameDbEntry { key: GameKey::Code("KYG"), sensors: &[Sensor::Tilt], ..NONE },
+    GameDbEntry { key: GameKey::Code("KHP"), sensors: &[Sensor::Tilt], ..NONE },
+    // WarioWare: Twisted
+    GameDbEntry {
+        key: GameKey::Code("RZW"),
+        save_type: Some(SaveType::Sram),
+        sensors: &[Sensor::Gyro],
+        rumble: true,
+        ..NONE
+    },
+    // Drill Dozer
+    GameDbEntry {
+        key: GameKey::Code("V49"),
+        save_type: Some(SaveType::Sram),
+        rumble: true,
+        ..NONE
+    },
+];
+
+impl GameDbEntry {
+    fn matches(&self, id: &GameId) -> bool {
+        match self.key {
+            GameKey::Code(key) => id.code.as_ref().is_some_and(|code| code.starts_with(key)),
+            GameKey::Crc32(crc32) => id.crc32 == crc32,
+        }
+    }
+
+    /// The settings the entry gives, to merge over the global ones
+    pub fn overrides(&self) -> GameOverrides {
+        GameOverrides {
+            save_type: self.save_type,
+            flash_chip: self.flash_chip,
+            idle_loop: self.idle_loop,
+            patches: if self.patches.is_empty() {
+                None
+            } else {
+                Some(self.patches.iter().map(|&(addr, bytes)| RomPatch {
+                    addr,
+                    bytes: bytes.to_vec(),
+                }).collect())
+            },
+            ..GameOverrides::default()
+        }
+    }
+}
+
+/// The entry for a game, one for its exact dump before one for its code
+pub fn lookup(id: &GameId) -> Option<&'static GameDbEntry> {
+    GAME_DB.iter()
+        .find(|entry| match entry.key { GameKey::Crc32(_) => entry.matches(id), _ => false })
+        .or_else(|| GAME_DB.iter().find(|entry| entry.matches(id)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(code: &str) -> GameId {
+        GameId { code: Some(code.to_string()), crc32: 0 }
+    }
+
+    #[test]
+    fn looks_up_every_region() {
+        assert_eq!(lookup(&id("BPEE")).map(|e| e.key), Some(GameKey::Code("BPE")));
+        assert_eq!(lookup(&id("BPEJ")).map(|e| e.key), Some(GameKey::Code("BPE")));
+        assert!(lookup(&id("ABCD")).is_none());
+        assert!(lookup(&GameId { code: None, crc32: 0 }).is_none());
+    }
+}
@@ -0,0 +1,747 @@
+/// Built in settings for games that need them
+pub mod game_db;
+
+use std::collections::HashMap;
+use std::fmt;
+#[cfg(feature = "config")]
+use std::fs::File;
+#[cfg(feature = "config")]
+use std::io::Read;
+#[cfg(feature = "config")]
+use std::path::Path;
+use std::path::PathBuf;
+
+use crc32fast;
+#[cfg(feature = "config")]
+use toml;
+
+#[cfg(feature = "config")]
+use gba_error::GbaError;
+use gba_error::GbaResult;
+use gba_mem::archive;
+use gba_mem::flash::FlashId;
+
+// Location of the game code in the cartridge header
+const GAME_CODE_OFFSET: usize = 0xAC;
+const GAME_CODE_LEN: usize = 4;
+
+/// Backup memory fitted to the cartridge
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "config", serde(rename_all = "lowercase"))]
+#[derive(Default)]
+pub enum SaveType {
+    /// Detect from the ROM
+    #[default]
+    Auto,
+    /// No backup memory
+    None,
+    /// 32K of battery-backed SRAM
+    Sram,
+    /// 64K of flash
+    Flash64,
+    /// 128K of flash, in two banks
+    Flash128,
+    /// 512 byte EEPROM
+    Eeprom512,
+    /// 8K EEPROM
+    Eeprom8k,
+}
+
+impl SaveType {
+    /// The save type named in the settings, e.g. "flash128"
+    pub fn from_name(name: &str) -> Option<SaveType> {
+        match name {
+            "auto" => Some(SaveType::Auto),
+            "none" => Some(SaveType::None),
+            "sram" => Some(SaveType::Sram),
+            "flash64" => Some(SaveType::Flash64),
+            "flash128" => Some(SaveType::Flash128),
+            "eeprom512" => Some(SaveType::Eeprom512),
+            "eeprom8k" => Some(SaveType::Eeprom8k),
+            _ => None,
+        }
+    }
+}
+
+
+// ID strings Nintendo's save libraries leave in the ROM, followed by a
+// version number, e.g. "FLASH1M_V103". They are word aligned.
+const SAVE_LIBRARIES: [(&[u8], SaveType); 6] = [
+    (b"EEPROM_V", SaveType::Eeprom512),
+    (b"SRAM_V", SaveType::Sram),
+    (b"SRAM_F_V", SaveType::Sram),
+    (b"FLASH_V", SaveType::Flash64),
+    (b"FLASH512_V", SaveType::Flash64),
+    (b"FLASH1M_V", SaveType::Flash128),
+];
+
+impl SaveType {
+    /// The backup memory a game's save library is for, and the library's ID
+    /// string, or None if no library was found. The ID doesn't give the size
+    /// of an EEPROM, so those always come back as Eeprom512.
+    pub fn detect(rom: &[u8]) -> Option<(SaveType, String)> {
+        (0..rom.len()).step_by(4).filter_map(|pos| {
+            let rest = &rom[pos..];
+            SAVE_LIBRARIES.iter().find(|&&(id, _)| rest.starts_with(id)).map(|&(id, save_type)| {
+                let version = rest[id.len()..].iter().take(3).take_while(|b| b.is_ascii_digit());
+                let name = id.iter().chain(version).map(|&b| b as char).collect();
+                (save_type, name)
+            })
+        }).next()
+    }
+}
+
+/// Flash chips found on cartridges, by maker and size. Their IDs differ,
+/// and Atmel's are written a page at a time instead of erased and written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "config", serde(rename_all = "lowercase"))]
+pub enum FlashChip {
+    /// 64K
+    Sst,
+    /// 64K
+    Macronix64,
+    /// 64K
+    Panasonic,
+    /// 64K
+    Atmel,
+    /// 128K
+    Sanyo,
+    /// 128K
+    Macronix128,
+}
+
+impl FlashChip {
+    /// The chip named in the settings, e.g. "sanyo"
+    pub fn from_name(name: &str) -> Option<FlashChip> {
+        match name {
+            "sst" => Some(FlashChip::Sst),
+            "macronix64" => Some(FlashChip::Macronix64),
+            "panasonic" => Some(FlashChip::Panasonic),
+            "atmel" => Some(FlashChip::Atmel),
+            "sanyo" => Some(FlashChip::Sanyo),
+            "macronix128" => Some(FlashChip::Macronix128),
+            _ => None,
+        }
+    }
+
+    /// The chip fitted for a flash save type when the settings don't say
+    pub fn default_for(save_type: SaveType) -> Option<FlashChip> {
+        match save_type {
+            SaveType::Flash64 => Some(FlashChip::Panasonic),
+            SaveType::Flash128 => Some(FlashChip::Sanyo),
+            _ => None,
+        }
+    }
+
+    /// The IDs the chip answers with in ID mode
+    pub fn id(&self) -> FlashId {
+        let (manufacturer, device, banks) = match *self {
+            FlashChip::Sst => (0xBF, 0xD4, 1),
+            FlashChip::Macronix64 => (0xC2, 0x1C, 1),
+            FlashChip::Panasonic => (0x32, 0x1B, 1),
+            FlashChip::Atmel => (0x1F, 0x3D, 1),
+            FlashChip::Sanyo => (0x62, 0x13, 2),
+            FlashChip::Macronix128 => (0xC2, 0x09, 2),
+        };
+        FlashId {
+            manufacturer,
+            device,
+            banks,
+            page_writes: *self == FlashChip::Atmel,
+        }
+    }
+}
+
+/// Real-time clock fitted to the cartridge
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "config", serde(rename_all = "lowercase"))]
+#[derive(Default)]
+pub enum RtcMode {
+    /// A host clock for games known to have one
+    #[default]
+    Auto,
+    /// No clock
+    None,
+    /// Follows the host's clock
+    Host,
+    /// Starts at rtc_start and runs with emulated time, for repeatable runs
+    Fixed,
+}
+
+
+/// Sensors fitted to the cartridge
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "config", serde(rename_all = "lowercase"))]
+pub enum Sensor {
+    /// Boktai light sensor
+    Solar,
+    /// Accelerometer on the Yoshi and Koro Koro Puzzle games
+    Tilt,
+    /// Rotation sensor on WarioWare: Twisted
+    Gyro,
+}
+
+impl Sensor {
+    /// The sensor named in the settings, e.g. "solar"
+    pub fn from_name(name: &str) -> Option<Sensor> {
+        match name {
+            "solar" => Some(Sensor::Solar),
+            "tilt" => Some(Sensor::Tilt),
+            "gyro" => Some(Sensor::Gyro),
+            _ => None,
+        }
+    }
+}
+
+/// Something plugged into the link port that answers for itself
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "config", serde(rename_all = "lowercase"))]
+#[derive(Default)]
+pub enum Peripheral {
+    /// Leave the port to the frontend, e.g. for a network link
+    #[default]
+    None,
+    /// Game Boy Player, for its rumble
+    GbPlayer,
+    /// An e-Reader with no card scanned
+    EReader,
+}
+
+impl Peripheral {
+    /// The peripheral named in the settings, e.g. "gbplayer"
+    pub fn from_name(name: &str) -> Option<Peripheral> {
+        match name {
+            "gbplayer" => Some(Peripheral::GbPlayer),
+            "ereader" => Some(Peripheral::EReader),
+            _ => None,
+        }
+    }
+}
+
+
+/// Bytes written over the ROM when it is loaded, e.g. to skip a check the
+/// emulator fails. The address is in the ROM's memory map.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(Serialize, Deserialize))]
+pub struct RomPatch {
+    /// Where the bytes go
+    pub addr: u32,
+    /// The bytes written there
+    pub bytes: Vec<u8>,
+}
+
+/// Settings that can differ between games
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "config", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "config", serde(default))]
+pub struct GameSettings {
+    /// Backup memory fitted
+    pub save_type: SaveType,
+    /// Picked by the save type if not given
+    pub flash_chip: Option<FlashChip>,
+    /// Address of a known busy-wait loop
+    pub idle_loop: Option<u32>,
+    /// Adjust colours for a modern screen, as the GBA's LCD is darker
+    pub color_correction: bool,
+    /// Apply the game's cheats
+    pub cheats_enabled: bool,
+    /// Real-time clock fitted
+    pub rtc: RtcMode,
+    /// Unix time a fixed clock starts at, or 2000-01-01
+    pub rtc_start: Option<u64>,
+    /// Detected from the game code if not given
+    pub sensors: Option<Vec<Sensor>>,
+    /// Also detected if not given
+    pub rumble: Option<bool>,
+    /// Device on the link port
+    pub peripheral: Peripheral,
+    /// Written over the ROM when it's loaded
+    pub patches: Vec<RomPatch>,
+}
+
+/// A per-game section. Only the settings given replace the global ones.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "config", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "config", serde(default))]
+pub struct GameOverrides {
+    /// Backup memory fitted
+    pub save_type: Option<SaveType>,
+    /// Flash chip, if the save type is flash
+    pub flash_chip: Option<FlashChip>,
+    /// Address of a known busy-wait loop
+    pub idle_loop: Option<u32>,
+    /// Adjust colours for a modern screen
+    pub color_correction: Option<bool>,
+    /// Apply the game's cheats
+    pub cheats_enabled: Option<bool>,
+    /// Real-time clock fitted
+    pub rtc: Option<RtcMode>,
+    /// Unix time a fixed clock starts at
+    pub rtc_start: Option<u64>,
+    /// Sensors fitted
+    pub sensors: Option<Vec<Sensor>>,
+    /// Rumble motor fitted
+    pub rumble: Option<bool>,
+    /// Device on the link port
+    pub peripheral: Option<Peripheral>,
+    /// Written over the ROM when it's loaded
+    pub patches: Option<Vec<RomPatch>>,
+}
+
+impl GameOverrides {
+    /// Replace the settings given here
+    pub fn apply(&self, settings: &mut GameSettings) {
+        if let Some(save_type) = self.save_type {
+            settings.save_type = save_type;
+        }
+        if self.flash_chip.is_some() {
+            settings.flash_chip = self.flash_chip;
+        }
+        if self.idle_loop.is_some() {
+            settings.idle_loop = self.idle_loop;
+        }
+        if let Some(color_correction) = self.color_correction {
+            settings.color_correction = color_correction;
+        }
+        if let Some(cheats_enabled) = self.cheats_enabled {
+            settings.cheats_enabled = cheats_enabled;
+        }
+        if let Some(rtc) = self.rtc {
+            settings.rtc = rtc;
+        }
+        if self.rtc_start.is_some() {
+            settings.rtc_start = self.rtc_start;
+        }
+        if self.sensors.is_some() {
+            settings.sensors = self.sensors.clone();
+        }
+        if self.rumble.is_some() {
+            settings.rumble = self.rumble;
+        }
+        if let Some(peripheral) = self.peripheral {
+            settings.peripheral = peripheral;
+        }
+        if let Some(ref patches) = self.patches {
+            settings.patches = patches.clone();
+        }
+    }
+}
+
+/// What a ROM can be looked up by in the config
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GameId {
+    /// Four character game code, e.g. "BPEE"
+    pub code: Option<String>,
+    /// CRC-32 of the whole ROM
+    pub crc32: u32,
+}
+
+impl GameId {
+    /// The IDs of a ROM image
+    pub fn from_rom(rom: &[u8]) -> GameId {
+        let code = rom.get(GAME_CODE_OFFSET..GAME_CODE_OFFSET + GAME_CODE_LEN)
+            .filter(|code| code.iter().all(|c| c.is_ascii_alphanumeric()))
+            .map(|code| code.iter().map(|&c| c as char).collect());
+
+        GameId {
+            code,
+            crc32: crc32fast::hash(rom),
+        }
+    }
+
+    /// The IDs of the ROM in a file or archive
+    pub fn from_file(rom_path: &str) -> GbaResult<GameId> {
+        Ok(GameId::from_rom(&archive::read_rom(rom_path)?))
+    }
+
+    /// Whether the game database knows of a real-time clock on the cartridge
+    pub fn has_rtc(&self) -> bool {
+        game_db::lookup(self).is_some_and(|entry| entry.rtc)
+    }
+
+    /// Whether the game database knows of a rumble motor on the cartridge
+    pub fn has_rumble(&self) -> bool {
+        game_db::lookup(self).is_some_and(|entry| entry.rumble)
+    }
+
+    /// Sensors the game is known to have
+    pub fn sensors(&self) -> Vec<Sensor> {
+        game_db::lookup(self).map_or(Vec::new(), |entry| entry.sensors.to_vec())
+    }
+
+    /// Key of the section matching the ROM hash
+    pub fn hash_key(&self) -> String {
+        format!("crc32:{:08x}", self.crc32)
+    }
+}
+
+impl fmt::Display for GameId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.code {
+            Some(ref code) => write![f, "{} ({})", code, self.hash_key()],
+            None => write![f, "{}", self.hash_key()],
+        }
+    }
+}
+
+/// Where save slots are kept. The pattern names a slot's file under root,
+/// with {crc32} replaced by the ROM hash, {code} by the game code (or the
+/// hash if the ROM has none) and {slot} by the slot number.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "config", serde(default))]
+pub struct SlotLayout {
+    /// Directory the slots are kept under
+    pub root: PathBuf,
+    /// File name of a slot under root
+    pub pattern: String,
+}
+
+impl Default for SlotLayout {
+    fn default() -> SlotLayout {
+        SlotLayout {
+            root: PathBuf::from("saves"),
+            pattern: "{crc32}/slot{slot}.state".to_string(),
+        }
+    }
+}
+
+impl SlotLayout {
+    /// File a game's slot is kept in
+    pub fn path(&self, id: &GameId, slot: u32) -> PathBuf {
+        let crc32 = format!("{:08x}", id.crc32);
+        let name = self.pattern
+            .replace("{crc32}", &crc32)
+            .replace("{code}", id.code.as_ref().unwrap_or(&crc32))
+            .replace("{slot}", &slot.to_string());
+        self.root.join(name)
+    }
+}
+
+/// How the LCD image is scaled up to the window
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "config", serde(rename_all = "lowercase"))]
+#[derive(Default)]
+pub enum Filter {
+    /// Sharp pixels
+    #[default]
+    Nearest,
+    /// Smoothed
+    Linear,
+}
+
+impl Filter {
+    /// The filter named in the settings, e.g. "linear"
+    pub fn from_name(name: &str) -> Option<Filter> {
+        match name {
+            "nearest" => Some(Filter::Nearest),
+            "linear" => Some(Filter::Linear),
+            _ => None,
+        }
+    }
+}
+
+
+/// Window and picture settings
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "config", serde(default))]
+pub struct VideoConfig {
+    /// Window size as a multiple of the LCD
+    pub scale: u32,
+    /// How the picture is scaled up
+    pub filter: Filter,
+    /// Mix each frame with the last, like the LCD's ghosting
+    pub frame_blending: bool,
+    /// Scaled up this much in software before the filter
+    pub prescale: u32,
+    /// Darken the last row of each prescaled pixel
+    pub scanlines: bool,
+    /// The GPU frontend's scaling shader: a built in one by name, or a
+    /// .wgsl file supplying fs_main. Nearest if not given.
+    pub shader: Option<String>,
+    /// Only scale by whole multiples, leaving a border
+    pub integer_scaling: bool,
+    /// Start in fullscreen
+    pub fullscreen: bool,
+}
+
+impl Default for VideoConfig {
+    fn default() -> VideoConfig {
+        VideoConfig {
+            scale: 3,
+            filter: Filter::Nearest,
+            frame_blending: false,
+            prescale: 1,
+            scanlines: false,
+            shader: None,
+            integer_scaling: false,
+            fullscreen: false,
+        }
+    }
+}
+
+/// Sound output settings
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "config", serde(default))]
+pub struct AudioConfig {
+    /// Play sound at all
+    pub enabled: bool,
+    /// Asked of the device, which may pick another
+    pub sample_rate: u32,
+    /// Stereo frames per device callback
+    pub buffer_frames: u16,
+}
+
+impl Default for AudioConfig {
+    fn default() -> AudioConfig {
+        AudioConfig {
+            enabled: true,
+            sample_rate: 48000,
+            buffer_frames: 1024,
+        }
+    }
+}
+
+/// How closely to hold games to what the hardware documents. Permissive
+/// mode does what the hardware does with anything odd, which some games
+/// rely on. Strict mode stops a game at it with an abort exception instead,
+/// which is what homebrew developers want: accesses to unmapped memory,
+/// writes to ROM or to IO registers that can't be written, and misaligned
+/// loads and stores, which the bus rounds down and SWP and LDR rotate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "config", serde(rename_all = "lowercase"))]
+#[derive(Default)]
+pub enum Strictness {
+    /// Do what the hardware does
+    #[default]
+    Permissive,
+    /// Take an abort exception instead
+    Strict,
+}
+
+
+/// How the machine is emulated
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "config", serde(default))]
+pub struct EmulationConfig {
+    /// How closely to hold games to the hardware
+    pub strictness: Strictness,
+    /// Apply the built in database's settings for the game
+    pub game_db: bool,
+    /// Boot straight into the game, see Gba::skip_bios
+    pub skip_bios: bool,
+}
+
+impl Default for EmulationConfig {
+    fn default() -> EmulationConfig {
+        EmulationConfig {
+            strictness: Strictness::Permissive,
+            game_db: true,
+            skip_bios: false,
+        }
+    }
+}
+
+/// Rollback netplay settings
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "config", serde(default))]
+pub struct NetplayConfig {
+    /// Frames between pressing a button and the game seeing it
+    pub input_delay: u32,
+    /// Frames to run ahead of the other player before waiting
+    pub max_rollback: u32,
+    /// Frames between state hashes sent to check for a desync
+    pub hash_interval: u32,
+}
+
+impl Default for NetplayConfig {
+    fn default() -> NetplayConfig {
+        NetplayConfig {
+            input_delay: 2,
+            max_rollback: 8,
+            hash_interval: 60,
+        }
+    }
+}
+
+/// Global settings plus per-game sections, e.g.
+///
+/// ```toml
+/// save_type = "auto"
+///
+/// [games.BPEE]
+/// save_type = "flash128"
+/// flash_chip = "macronix128"
+///
+/// [games."crc32:1f1c08fb"]
+/// idle_loop = 0x080002a4
+/// patches = [{ addr = 0x080001c0, bytes = [0x00, 0x00] }]
+///
+/// [slots]
+/// root = "/home/me/.local/share/rusty-gba"
+/// ```
+///
+/// The frontend's own settings go alongside them:
+///
+/// ```toml
+/// bios = "gba_bios.bin"
+/// save_dir = "saves"
+///
+/// [video]
+/// scale = 4
+/// filter = "linear"
+/// frame_blending = true
+/// shader = "sharp-bilinear"
+///
+/// [audio]
+/// sample_rate = 44100
+///
+/// [emulation]
+/// strictness = "strict"
+/// game_db = false
+/// skip_bios = true
+///
+/// [netplay]
+/// input_delay = 1
+///
+/// [keys]
+/// a = "C"
+/// fast_forward = "Space"
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "config", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "config", serde(default))]
+pub struct Config {
+    /// Settings for every game, at the top level of the file
+    #[cfg_attr(feature = "config", serde(flatten))]
+    pub global: GameSettings,
+    /// Per-game sections, keyed by game code or "crc32:" and the ROM hash
+    pub games: HashMap<String, GameOverrides>,
+    /// Where save slots are kept
+    pub slots: SlotLayout,
+    /// The built in BIOS is used if not given
+    pub bios: Option<PathBuf>,
+    /// Battery saves go next to the ROM if not given
+    pub save_dir: Option<PathBuf>,
+    /// Window and picture settings
+    pub video: VideoConfig,
+    /// Sound output settings
+    pub audio: AudioConfig,
+    /// How the machine is emulated
+    pub emulation: EmulationConfig,
+    /// Rollback netplay settings
+    pub netplay: NetplayConfig,
+    /// Keyboard keys by the button or hotkey action they replace the
+    /// default key for, e.g. "a" or "save_state"
+    pub keys: HashMap<String, String>,
+}
+
+impl Config {
+    /// A config from TOML text
+    #[cfg(feature = "config")]
+    pub fn parse(text: &str) -> GbaResult<Config> {
+        toml::from_str(text).map_err(|e| GbaError::InvalidConfig(e.to_string()))
+    }
+
+    /// A config from a TOML file
+    #[cfg(feature = "config")]
+    pub fn load<P: AsRef<Path>>(path: P) -> GbaResult<Config> {
+        let mut text = String::new();
+        File::open(path)?.read_to_string(&mut text)?;
+        Config::parse(&text)
+    }
+
+    /// Global settings with the game database's entry and then the game's
+    /// sections merged over them. A section keyed by ROM hash wins over one
+    /// keyed by game code.
+    pub fn settings_for(&self, id: &GameId) -> GameSettings {
+        let mut settings = self.global.clone();
+        if self.emulation.game_db {
+            if let Some(entry) = game_db::lookup(id) {
+                entry.overrides().apply(&mut settings);
+            }
+        }
+        if let Some(ref code) = id.code {
+            if let Some(overrides) = self.games.get(code) {
+                overrides.apply(&mut settings);
+            }
+        }
+        if let Some(overrides) = self.games.get(&id.hash_key()) {
+            overrides.apply(&mut settings);
+        }
+        settings
+    }
+}
+
+#[cfg(all(test, feature = "config"))]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+save_type = "sram"
+idle_loop = 0x08001000
+color_correction = true
+
+[games.BPEE]
+save_type = "flash128"
+idle_loop = 0x08002000
+
+[games."crc32:1f1c08fb"]
+idle_loop = 0x08003000
+
+[emulation]
+game_db = false
+"#;
+
+    #[test]
+    fn game_sections_override_the_global_settings() {
+        let config = Config::parse(CONFIG).unwrap();
+        let game = |code: Option<&str>, crc32| config.settings_for(&GameId {
+            code: code.map(|c| c.to_string()),
+            crc32,
+        });
+
+        let other = game(Some("AXVE"), 0);
+        assert_eq!((other.save_type, other.idle_loop), (SaveType::Sram, Some(0x08001000)));
+
+        let by_code = game(Some("BPEE"), 0);
+        assert_eq!((by_code.save_type, by_code.idle_loop),
+                   (SaveType::Flash128, Some(0x08002000)));
+
+        // The hash beats the code, and whatever neither section gives is global
+        let by_hash = game(Some("BPEE"), 0x1f1c08fb);
+        assert_eq!((by_hash.save_type, by_hash.idle_loop),
+                   (SaveType::Flash128, Some(0x08003000)));
+        assert!(by_hash.color_correction);
+        assert_eq!(game(None, 0x1f1c08fb).save_type, SaveType::Sram);
+    }
+
+    #[test]
+    fn bad_toml_is_an_invalid_config() {
+        let bad = [
+            "save_type = ",
+            "save_type = \"floppy\"",
+            "idle_loop = \"soon\"",
+            "[video]\nscale = -1",
+            "[games.BPEE]\nrtc = 3",
+        ];
+        for text in bad.iter() {
+            match Config::parse(text) {
+                Err(GbaError::InvalidConfig(_)) => {},
+                other => panic!("{:?} parsed as {:?}", text, other),
+            }
+        }
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+    }
+}
@@ -0,0 +1,141 @@
+// Arithmetic shared by the ARM data processing and THUMB ALU instructions,
+// with the flags they set. ARM uses an inverted borrow: subtracting sets C
+// when nothing was borrowed, i.e. when a >= b unsigned, and SBC subtracts
+// one more when C is clear.
+// See: https://www.scss.tcd.ie/~waldroj/3d1/arm_arm.pdf
+// section A4.1; ADC, ADD, SBC and SUB
+
+use gba_cpu::RType;
+
+const SIGN: RType = 0x80000000;
+
+/// NZCV condition flags as an ALU operation sets them
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Flags {
+    /// Negative
+    pub n: bool,
+    /// Zero
+    pub z: bool,
+    /// Carry, or not borrow
+    pub c: bool,
+    /// Signed overflow
+    pub v: bool,
+}
+
+impl Flags {
+    /// N and Z of a result, for logical operations. C comes from the shifter
+    /// and V is left as it was, so both are passed in.
+    pub fn logical(result: RType, c: bool, v: bool) -> Flags {
+        Flags {
+            n: result & SIGN != 0,
+            z: result == 0,
+            c,
+            v,
+        }
+    }
+
+    /// NZCV in the top four bits, as they sit in the CPSR
+    pub fn bits(&self) -> RType {
+        (self.n as RType) << 3 | (self.z as RType) << 2 | (self.c as RType) << 1 | self.v as RType
+    }
+}
+
+/// a + b + carry_in. Overflow is when both operands have the same sign and
+/// the result doesn't.
+pub fn adc(a: RType, b: RType, carry_in: bool) -> (RType, Flags) {
+    let wide = a as u64 + b as u64 + carry_in as u64;
+    let result = wide as RType;
+    let flags = Flags {
+        c: wide > RType::MAX as u64,
+        v: !(a ^ b) & (a ^ result) & SIGN != 0,
+        ..Flags::logical(result, false, false)
+    };
+    (result, flags)
+}
+
+/// a - b - !carry_in, done as a + !b + carry_in as the hardware does
+pub fn sbc(a: RType, b: RType, carry_in: bool) -> (RType, Flags) {
+    adc(a, !b, carry_in)
+}
+
+/// a + b, setting all four flags
+pub fn add_with_flags(a: RType, b: RType) -> (RType, Flags) {
+    adc(a, b, false)
+}
+
+/// Also CMP, and RSB with the operands swapped
+pub fn sub_with_flags(a: RType, b: RType) -> (RType, Flags) {
+    sbc(a, b, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use prelude::*;
+    use rand::{Rng, SeedableRng, XorShiftRng};
+
+    // Flags worked out the long way, from the signed and unsigned results
+    fn reference(a: RType, b: RType, carry_in: bool, sub: bool) -> (RType, Flags) {
+        let c = carry_in as i64;
+        let (unsigned, signed) = if sub {
+            (a as i64 - b as i64 - (1 - c), a as i32 as i64 - b as i32 as i64 - (1 - c))
+        }
+        else {
+            (a as i64 + b as i64 + c, a as i32 as i64 + b as i32 as i64 + c)
+        };
+        let result = unsigned as RType;
+        let flags = Flags {
+            n: (result as i32) < 0,
+            z: result == 0,
+            c: if sub { unsigned >= 0 } else { unsigned > RType::MAX as i64 },
+            v: signed != result as i32 as i64,
+        };
+        (result, flags)
+    }
+
+    #[test]
+    fn edge_cases() {
+        assert_eq!(add_with_flags(0xFFFFFFFF, 1),
+                   (0, Flags { n: false, z: true, c: true, v: false }));
+        assert_eq!(add_with_flags(0x7FFFFFFF, 1),
+                   (0x80000000, Flags { n: true, z: false, c: false, v: true }));
+        // No borrow sets C
+        assert_eq!(sub_with_flags(5, 5),
+                   (0, Flags { n: false, z: true, c: true, v: false }));
+        assert_eq!(sub_with_flags(0, 1),
+                   (0xFFFFFFFF, Flags { n: true, z: false, c: false, v: false }));
+        assert_eq!(sub_with_flags(0x80000000, 1),
+                   (0x7FFFFFFF, Flags { n: false, z: false, c: true, v: true }));
+        // The carry in can carry out on its own
+        assert!(adc(0xFFFFFFFF, 0, true).1.c);
+        // SBC with C clear borrows one more; 0 - 0 - 1 borrows
+        assert_eq!(sbc(0, 0, false),
+                   (0xFFFFFFFF, Flags { n: true, z: false, c: false, v: false }));
+    }
+
+    #[test]
+    fn matches_the_long_way() {
+        let interesting = [0, 1, 0x7FFFFFFF, 0x80000000, 0x80000001, 0xFFFFFFFE, 0xFFFFFFFF];
+        let mut rng: XorShiftRng = SeedableRng::from_seed([0x1234, 0x5678, 0x9abc, 0xdef0]);
+        let mut values: Vec<RType> = interesting.to_vec();
+        values.extend((0..32).map(|_| rng.gen::<RType>()));
+
+        for &a in values.iter() {
+            for &b in values.iter() {
+                for &c in [false, true].iter() {
+                    assert_eq!(adc(a, b, c), reference(a, b, c, false),
+                               "adc {:#x} {:#x} {}", a, b, c);
+                    assert_eq!(sbc(a, b, c), reference(a, b, c, true),
+                               "sbc {:#x} {:#x} {}", a, b, c);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn flags_pack_like_the_cpsr() {
+        assert_eq!(Flags { n: true, z: false, c: true, v: false }.bits(), 0b1010);
+        assert_eq!(Flags::logical(0, true, false).bits(), 0b0110);
+    }
+}
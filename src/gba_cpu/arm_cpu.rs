@@ -1,14 +1,16 @@
 //use std::mem; // Needed if useing transmute
 use self::ARM7Mode::*;
 
-use std::fmt;
-use gba_cpu::RType;
+use core::fmt;
+use gba_cpu::{arm_instr, thumb_instr, Core, IType, Memory, RType};
+use gba_cpu::alu::Flags;
 use gba_cpu::register::Register;
 
 // Important PSR bits from:
 // http://www.atmel.com/Images/DDI0029G_7TDMI_R3_trm.pdf
 // section 2.7, page 2-13
 // Condition code flag bits (28-31)
+#[allow(dead_code)]
 const COND_MASK: RType = 0xF0000000;
 const N_MASK: RType = 0x80000000; // Negative or less than (31)
 const Z_MASK: RType = 0x40000000; // Zero (30)
@@ -20,6 +22,11 @@ const F_MASK: RType = 0x40; // FRQ Disable (6)
 const T_MASK: RType = 0x20; // Thumb State (5)
 const M_MASK: RType = 0x1F; // Mode State (4-0)
 
+/// PSR fields MSR can write, for the mask given to write_cpsr
+pub const PSR_FLAGS:   RType = 0xFF000000;
+/// The control byte: the I, F and T bits and the mode
+pub const PSR_CONTROL: RType = 0x000000FF;
+
 // PSR mode bits from:
 // http://www.atmel.com/Images/DDI0029G_7TDMI_R3_trm.pdf
 // section 2.7.2, page 2-15
@@ -34,66 +41,142 @@ const SYS_MODE:  RType = 0b11111; // System mode
 
 // Register indices (Not verified after reg 15)
 // TODO: Find real register names if important
+/// Index of R0 in the register file
 pub const R0:       i8 = 0;
+/// Index of R1
 pub const R1:       i8 = 1;
+/// Index of R2
 pub const R2:       i8 = 2;
+/// Index of R3
 pub const R3:       i8 = 3;
+/// Index of R4
 pub const R4:       i8 = 4;
+/// Index of R5
 pub const R5:       i8 = 5;
+/// Index of R6
 pub const R6:       i8 = 6;
+/// Index of R7
 pub const R7:       i8 = 7;
+/// Index of R8
 pub const R8:       i8 = 8;
+/// Index of R9
 pub const R9:       i8 = 9;
+/// Index of R10
 pub const R10:      i8 = 10;
+/// Index of R11
 pub const R11:      i8 = 11;
+/// Index of R12
 pub const R12:      i8 = 12;
+/// Index of R13
 pub const R13:      i8 = 13;
+/// Index of R14
 pub const R14:      i8 = 14;
+/// Index of R15
 pub const R15:      i8 = 15;
+/// R8, banked for FIQ mode
 pub const R8_FIQ:   i8 = 16;
+/// R9, banked for FIQ mode
 pub const R9_FIQ:   i8 = 17;
+/// R10, banked for FIQ mode
 pub const R10_FIQ:  i8 = 18;
+/// R11, banked for FIQ mode
 pub const R11_FIQ:  i8 = 19;
+/// R12, banked for FIQ mode
 pub const R12_FIQ:  i8 = 20;
+/// SP, banked for FIQ mode
 pub const R13_FIQ:  i8 = 21;
+/// LR, banked for FIQ mode
 pub const R14_FIQ:  i8 = 22;
+/// SP, banked for Supervisor mode
 pub const R13_SV:   i8 = 23;
+/// LR, banked for Supervisor mode
 pub const R14_SV:   i8 = 24;
+/// SP, banked for Abort mode
 pub const R13_ABT:  i8 = 25;
+/// LR, banked for Abort mode
 pub const R14_ABT:  i8 = 26;
+/// SP, banked for IRQ mode
 pub const R13_IRQ:  i8 = 27;
+/// LR, banked for IRQ mode
 pub const R14_IRQ:  i8 = 28;
+/// SP, banked for Undefined mode
 pub const R13_UND:  i8 = 29;
+/// LR, banked for Undefined mode
 pub const R14_UND:  i8 = 30;
+/// Registers in the file, banked ones included
 pub const NUM_REGS: usize = 31;
 
-// Saved status register indices
+/// Saved status register indices
 pub const SPSR_FIQ: i8 = 0;
+/// SPSR of Supervisor mode
 pub const SPSR_SV:  i8 = 1;
+/// SPSR of Abort mode
 pub const SPSR_ABT: i8 = 2;
+/// SPSR of IRQ mode
 pub const SPSR_IRQ: i8 = 3;
+/// SPSR of Undefined mode
 pub const SPSR_UND: i8 = 4;
+/// Saved status registers, one per exception mode and a spare
 pub const NUM_STATUS_REGS: usize = 6;
 
-// Register alias
+/// Stack pointers the BIOS sets up before it jumps to the cartridge, see:
+/// http://problemkaputt.de/gbatek.htm#biosramusage
+pub const BIOS_SP_SV:  RType = 0x03007FE0;
+/// IRQ mode stack
+pub const BIOS_SP_IRQ: RType = 0x03007FA0;
+/// User and System mode stack
+pub const BIOS_SP_USR: RType = 0x03007F00;
+
+/// Where the BIOS starts the cartridge
+pub const PAK_ENTRY: RType = 0x08000000;
+/// Where the BIOS starts a program it received over the link cable
+pub const MULTIBOOT_ENTRY: RType = 0x020000C0;
+
+/// Register alias
 pub const SP:   i8 = R13;
+/// Link register
 pub const LINK: i8 = R14;
+/// Program counter
 pub const PC:   i8 = R15;
 
 // Modes of execution for ARM7TDMI
 // TODO: Consider creating a typed state machine if performance is an issue: SEE
 // BOTTOM OF THIS FILE
+/// Modes of execution for ARM7TDMI
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum ARM7Mode {
+    /// Normal program execution
     User       = USER_MODE as isize,
+    /// Fast interrupts, with R8-R14 banked
     FIQ        = FIQ_MODE  as isize,
+    /// Interrupts
     IRQ        = IRQ_MODE  as isize,
+    /// Protected mode for the operating system, entered by SWI and reset
     Supervisor = SV_MODE   as isize,
+    /// After a data or prefetch abort
     Abort      = ABRT_MODE as isize,
+    /// After an undefined instruction
     Undefined  = UDEF_MODE as isize,
+    /// Privileged mode sharing User mode's registers
     System     = SYS_MODE  as isize,
 }
 
+impl ARM7Mode {
+    /// The mode encoded in the bottom five bits of a PSR, if they are one
+    pub fn from_bits(bits: RType) -> Option<ARM7Mode> {
+        match bits & M_MASK {
+            USER_MODE => Some(User),
+            FIQ_MODE  => Some(FIQ),
+            IRQ_MODE  => Some(IRQ),
+            SV_MODE   => Some(Supervisor),
+            ABRT_MODE => Some(Abort),
+            UDEF_MODE => Some(Undefined),
+            SYS_MODE  => Some(System),
+            _ => None,
+        }
+    }
+}
+
 impl fmt::Display for ARM7Mode {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let mode_word = match *self {
@@ -109,76 +192,194 @@ impl fmt::Display for ARM7Mode {
     }
 }
 
-// Registers from:
-// http://www.atmel.com/Images/DDI0029G_7TDMI_R3_trm.pdf
-// section 2.6, page 2-8
+/// Exceptions from:
+/// http://www.atmel.com/Images/DDI0029G_7TDMI_R3_trm.pdf
+/// section 2.8, page 2-16
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Exception {
+    /// Power on
+    Reset,
+    /// An instruction the CPU couldn't execute
+    Undefined,
+    /// SWI
+    SoftwareInterrupt,
+    /// An instruction fetch aborted
+    PrefetchAbort,
+    /// A load or store aborted
+    DataAbort,
+    /// Interrupt request
+    Irq,
+    /// Fast interrupt request
+    Fiq,
+}
+
+impl Exception {
+    /// Address the exception jumps to
+    pub fn vector(&self) -> RType {
+        match *self {
+            Exception::Reset             => 0x00,
+            Exception::Undefined         => 0x04,
+            Exception::SoftwareInterrupt => 0x08,
+            Exception::PrefetchAbort     => 0x0C,
+            Exception::DataAbort         => 0x10,
+            Exception::Irq               => 0x18,
+            Exception::Fiq               => 0x1C,
+        }
+    }
+
+    /// Mode the exception is taken in
+    pub fn mode(&self) -> ARM7Mode {
+        match *self {
+            Exception::Reset | Exception::SoftwareInterrupt => Supervisor,
+            Exception::Undefined => Undefined,
+            Exception::PrefetchAbort | Exception::DataAbort => Abort,
+            Exception::Irq => IRQ,
+            Exception::Fiq => FIQ,
+        }
+    }
+}
+
+/// Copy of the full register file, banked registers included
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CoreState {
+    /// R0-R15 and the banked registers, indexed as R0..R14_UND
+    pub regs: [RType; NUM_REGS],
+    /// Current program status register
+    pub cpsr: RType,
+    /// Saved program status registers, indexed as SPSR_FIQ..SPSR_UND
+    pub spsr: [RType; NUM_STATUS_REGS],
+}
+
+/// What the CPU does with an instruction it can't execute: an undefined
+/// encoding, or one the interpreter doesn't implement yet
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Default)]
+pub enum UndefinedPolicy {
+    /// Take the undefined instruction exception, as the hardware does
+    #[default]
+    Exception,
+    /// Log it and go on to the next instruction
+    Skip,
+    /// Stay on it and report it, for the debugger to stop at
+    Break,
+}
+
+impl UndefinedPolicy {
+    /// The policy named on the command line or in the config file
+    pub fn from_name(name: &str) -> Option<UndefinedPolicy> {
+        match name {
+            "exception" => Some(UndefinedPolicy::Exception),
+            "skip" => Some(UndefinedPolicy::Skip),
+            "break" => Some(UndefinedPolicy::Break),
+            _ => None,
+        }
+    }
+}
+
+
+/// An instruction the Break policy stopped at
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UndefinedInstr {
+    /// Address of the instruction
+    pub addr: RType,
+    /// A Thumb instruction in the low half
+    pub instr: IType,
+    /// Whether it is a THUMB instruction
+    pub thumb: bool,
+}
+
+impl fmt::Display for UndefinedInstr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.thumb {
+            write![f, "undefined Thumb instruction {:04x} at {:#010x}", self.instr, self.addr]
+        }
+        else {
+            write![f, "undefined ARM instruction {:08x} at {:#010x}", self.instr, self.addr]
+        }
+    }
+}
+
+/// Registers from:
+/// http://www.atmel.com/Images/DDI0029G_7TDMI_R3_trm.pdf
+/// section 2.6, page 2-8
 #[allow(missing_copy_implementations)]
 pub struct ARM7 {
     regs: [Register; NUM_REGS],
     cpsr: Register,
     spsr: [Register; NUM_STATUS_REGS],
+    undefined_policy: UndefinedPolicy, // A setting, so not saved
+    undefined: Option<UndefinedInstr>, // Stopped at, see take_undefined
 }
 
+impl_save_state!(ARM7 { regs, cpsr, spsr });
+impl_serde_via_state!(ARM7);
+
 impl Default for ARM7 {
     fn default() -> ARM7 {
         let mut cpu = ARM7 {
             regs: [Register::default(); NUM_REGS],
             cpsr: Register::default(),
             spsr: [Register::default(); NUM_STATUS_REGS],
+            undefined_policy: UndefinedPolicy::default(),
+            undefined: None,
         };
 
-        cpu.set_mode(FIQ);
-        cpu.set_irq_disable();
+        cpu.reset();
         cpu
     }
 }
 
 // Implementation of ARM7TDMI
 impl ARM7 {
-    fn reg_map_index(&self, reg_num: i8) -> Option<i8> {
-        assert!(reg_num >= R0);
-        assert!(reg_num <= R15);
-
-        if !self.is_thumb() {
-            if reg_num <= R7 || reg_num == PC {
-                Some(reg_num)
-            }
-            else {
-                match self.mode() {
-                    User | System => Some(reg_num),
-                    FIQ => Some(reg_num + R8_FIQ - R8),
-                    _ if reg_num <= R12 => Some(reg_num),
-                    _ => Some(match self.mode() {
-                        IRQ => reg_num + R13_IRQ,
-                        Supervisor => reg_num + R13_SV,
-                        Abort => reg_num + R13_ABT,
-                        Undefined => reg_num + R13_UND,
-                        _ => unreachable!(),
-                    } - R13),
-                }
-            }
+    // Index of the register mode sees as reg_num
+    fn banked_index_in(mode: ARM7Mode, reg_num: i8) -> i8 {
+        if reg_num <= R7 || reg_num == PC {
+            reg_num
         }
         else {
-            if reg_num <= R7 {
-                Some(reg_num)
-            }
-            else {
-                None
+            match mode {
+                User | System => reg_num,
+                FIQ => reg_num + R8_FIQ - R8,
+                _ if reg_num <= R12 => reg_num,
+                IRQ => reg_num + R13_IRQ - R13,
+                Supervisor => reg_num + R13_SV - R13,
+                Abort => reg_num + R13_ABT - R13,
+                Undefined => reg_num + R13_UND - R13,
             }
         }
     }
 
-    fn unmapped_reg_op<F>(&mut self, reg_num: i8, op: F)
-        where F: Fn(&mut Register) {
-        op(&mut self.regs[reg_num as usize])
+    // Index of the register the current mode sees as reg_num
+    fn banked_index(&self, reg_num: i8) -> i8 {
+        ARM7::banked_index_in(self.mode(), reg_num)
     }
 
+    // Index of the SPSR mode saves the CPSR in, if it has one
+    fn spsr_index(mode: ARM7Mode) -> Option<i8> {
+        match mode {
+            User | System => None,
+            FIQ        => Some(SPSR_FIQ),
+            IRQ        => Some(SPSR_IRQ),
+            Supervisor => Some(SPSR_SV),
+            Abort      => Some(SPSR_ABT),
+            Undefined  => Some(SPSR_UND),
+        }
+    }
+
+    // Banking depends only on the mode. Thumb instructions can only encode
+    // R8-R15 in the hi register operations, but see the same registers as
+    // ARM code does when they do.
+    fn reg_map_index(&self, reg_num: i8) -> i8 {
+        assert!(reg_num >= R0);
+        assert!(reg_num <= R15);
+        self.banked_index(reg_num)
+    }
+
+    /// Run op on a register as the current mode sees it
     pub fn reg_op<F>(&mut self, reg_num: i8, op: F)
         where F: Fn(&mut Register) {
-        match self.reg_map_index(reg_num) {
-            Some(reg) => self.unmapped_reg_op(reg, op),
-            None => unreachable!(),
-        }
+        let reg = self.reg_map_index(reg_num);
+        op(self.reg_raw_mut(reg))
     }
 
     fn reg_raw(&self, reg_num: i8) -> &Register {
@@ -189,25 +390,46 @@ impl ARM7 {
         &mut self.regs[reg_num as usize]
     }
 
-    pub fn reg(&self, reg_num: i8) -> Option<&Register> {
-        match self.reg_map_index(reg_num) {
-            Some(x) => Some(self.reg_raw(x)),
-            None => None,
-        }
+    /// A register as the current mode sees it
+    pub fn reg(&self, reg_num: i8) -> &Register {
+        self.reg_raw(self.reg_map_index(reg_num))
     }
 
-    pub fn reg_mut(&mut self, reg_num: i8) -> Option<&mut Register> {
-        match self.reg_map_index(reg_num) {
-            Some(x) => Some(self.reg_raw_mut(x)),
-            None => None,
-        }
+    /// A register as the current mode sees it, for writing
+    pub fn reg_mut(&mut self, reg_num: i8) -> &mut Register {
+        let reg = self.reg_map_index(reg_num);
+        self.reg_raw_mut(reg)
+    }
+
+    /// Stack pointer of the current mode
+    pub fn sp(&self) -> RType {
+        self.reg(SP).read()
+    }
+
+    /// Link register of the current mode
+    pub fn lr(&self) -> RType {
+        self.reg(LINK).read()
+    }
+
+    /// Register reg_num as mode sees it, so banked registers can be read
+    /// without switching the CPU into mode
+    pub fn reg_in_mode(&self, reg_num: i8, mode: ARM7Mode) -> RType {
+        assert!((R0..=R15).contains(&reg_num));
+        self.reg_raw(ARM7::banked_index_in(mode, reg_num)).read()
     }
 
-    // PC register
+    /// Write a register of mode, whatever mode the CPU is in
+    pub fn set_reg_in_mode(&mut self, reg_num: i8, mode: ARM7Mode, val: RType) {
+        assert!((R0..=R15).contains(&reg_num));
+        self.reg_raw_mut(ARM7::banked_index_in(mode, reg_num)).write(val);
+    }
+
+    /// PC register
     pub fn pc(&self) -> RType {
         self.reg_raw(PC).read()
     }
 
+    /// Step the PC on to the next instruction in the current state
     pub fn inc_pc(&mut self) {
         let pc_val = self.reg_raw(PC).read();
         if self.is_thumb() {
@@ -218,81 +440,292 @@ impl ARM7 {
         }
     }
 
+    /// Set the PC as is, without branching or aligning it
     pub fn set_pc(&mut self, pc_val: RType) {
         self.reg_raw_mut(PC).write(pc_val);
     }
 
+    /// Write a register as an instruction's result. Instructions go through
+    /// here rather than reg_mut so that writing R15 branches.
+    pub fn write_reg(&mut self, reg_num: i8, val: RType) {
+        if reg_num == PC {
+            self.write_pc(val, false);
+        }
+        else {
+            self.reg_mut(reg_num).write(val);
+        }
+    }
+
+    /// Branch to addr, as writing R15 does. Whatever was fetched after the
+    /// writing instruction is thrown away; with no pipeline modelled that
+    /// just means the next fetch comes from addr, aligned for the state
+    /// being run in. With restore_cpsr, as for a data processing instruction
+    /// with the S bit or LDM with ^ and R15 in the list, the CPSR is first
+    /// restored from the current mode's SPSR, which returns from an
+    /// exception and can switch back to THUMB state. User and System mode
+    /// have no SPSR, so the CPSR is left alone there.
+    pub fn write_pc(&mut self, addr: RType, restore_cpsr: bool) {
+        if restore_cpsr {
+            if let Some(spsr) = self.spsr().map(|spsr| spsr.read()) {
+                self.write_cpsr(spsr, !0);
+            }
+        }
+        let mask = if self.is_thumb() { 0xFFFFFFFE } else { 0xFFFFFFFC };
+        self.set_pc(addr & mask);
+    }
+
+    /// Copy of the register file
+    pub fn state(&self) -> CoreState {
+        let mut state = CoreState {
+            regs: [0; NUM_REGS],
+            cpsr: self.cpsr.read(),
+            spsr: [0; NUM_STATUS_REGS],
+        };
+        for (dst, src) in state.regs.iter_mut().zip(self.regs.iter()) {
+            *dst = src.read();
+        }
+        for (dst, src) in state.spsr.iter_mut().zip(self.spsr.iter()) {
+            *dst = src.read();
+        }
+        state
+    }
+
     // CPSR Register access
     // TODO: Do we need mutators for this?
+    /// CPSR register
     pub fn cpsr(&self) -> &Register {
         &self.cpsr
     }
 
+    /// SPSR of the current mode, which User and System mode don't have
     pub fn spsr(&self) -> Option<&Register> {
-        match self.mode() {
-            User       => None,
-            FIQ        => Some(&self.spsr[SPSR_FIQ as usize]),
-            IRQ        => Some(&self.spsr[SPSR_IRQ as usize]),
-            Supervisor => Some(&self.spsr[SPSR_SV  as usize]),
-            Abort      => Some(&self.spsr[SPSR_ABT as usize]),
-            Undefined  => Some(&self.spsr[SPSR_UND as usize]),
-            System     => None,
+        self.spsr_for(self.mode())
+    }
+
+    /// SPSR of the current mode, for writing
+    pub fn spsr_mut(&mut self) -> Option<&mut Register> {
+        let mode = self.mode();
+        self.spsr_for_mut(mode)
+    }
+
+    /// The SPSR belonging to mode, whatever mode the CPU is in
+    pub fn spsr_for(&self, mode: ARM7Mode) -> Option<&Register> {
+        ARM7::spsr_index(mode).map(move |i| &self.spsr[i as usize])
+    }
+
+    /// The SPSR belonging to mode, for writing
+    pub fn spsr_for_mut(&mut self, mode: ARM7Mode) -> Option<&mut Register> {
+        match ARM7::spsr_index(mode) {
+            Some(i) => Some(&mut self.spsr[i as usize]),
+            None => None,
+        }
+    }
+
+    /// Enter an exception: bank the CPSR into the new mode's SPSR, save the
+    /// return address in its link register and jump to the vector
+    pub fn raise_exception(&mut self, exc: Exception, return_addr: RType) {
+        let old_cpsr = self.cpsr;
+
+        self.set_mode(exc.mode());
+        self.reset_thumb();
+        self.set_irq_disable();
+        if exc == Exception::Reset || exc == Exception::Fiq {
+            self.set_fiq_disable();
+        }
+
+        if let Some(spsr) = self.spsr_mut() {
+            *spsr = old_cpsr;
+        }
+        self.write_reg(LINK, return_addr);
+        self.set_pc(exc.vector());
+    }
+
+    /// Take the reset exception, as at power on: Supervisor mode in ARM
+    /// state with both interrupts disabled, executing from address 0. The
+    /// other registers keep their values; LR_svc and SPSR_svc are left as
+    /// they were since their contents are unpredictable.
+    pub fn reset(&mut self) {
+        self.set_mode(Supervisor);
+        self.reset_thumb();
+        self.set_irq_disable();
+        self.set_fiq_disable();
+        self.set_pc(Exception::Reset.vector());
+    }
+
+    /// The state the BIOS leaves the CPU in when it starts the cartridge, for
+    /// booting without running it: registers cleared, each mode's stack set
+    /// up and System mode with interrupts enabled at the cartridge entry.
+    pub fn skip_bios(&mut self) {
+        for reg in self.regs.iter_mut() {
+            reg.write(0);
+        }
+        for spsr in self.spsr.iter_mut() {
+            spsr.write(0);
+        }
+        self.set_reg_in_mode(SP, Supervisor, BIOS_SP_SV);
+        self.set_reg_in_mode(SP, IRQ, BIOS_SP_IRQ);
+        self.set_reg_in_mode(SP, System, BIOS_SP_USR);
+
+        self.cpsr.write(0);
+        self.set_mode(System);
+        self.set_pc(PAK_ENTRY);
+    }
+
+    /// Take an interrupt between instructions. LR_irq points 4 past the
+    /// next one in either state, so SUBS PC, LR, #4 returns to it.
+    pub fn interrupt(&mut self) {
+        let next = self.pc();
+        self.raise_exception(Exception::Irq, next.wrapping_add(4));
+    }
+
+    /// Instruction fetch from instr_addr aborted. LR points past the
+    /// aborted instruction so SUBS PC, LR, #4 retries it.
+    pub fn prefetch_abort(&mut self, instr_addr: RType) {
+        self.raise_exception(Exception::PrefetchAbort, instr_addr.wrapping_add(4));
+    }
+
+    /// Load or store by the instruction at instr_addr aborted. LR is set so
+    /// SUBS PC, LR, #8 retries it.
+    pub fn data_abort(&mut self, instr_addr: RType) {
+        self.raise_exception(Exception::DataAbort, instr_addr.wrapping_add(8));
+    }
+
+    /// What the CPU does with an instruction it can't execute
+    pub fn undefined_policy(&self) -> UndefinedPolicy {
+        self.undefined_policy
+    }
+
+    /// Set what the CPU does with an instruction it can't execute
+    pub fn set_undefined_policy(&mut self, policy: UndefinedPolicy) {
+        self.undefined_policy = policy;
+    }
+
+    /// The instruction at addr couldn't be executed. The PC has already
+    /// moved past it.
+    pub fn undefined_instruction(&mut self, addr: RType, instr: IType) {
+        let undef = UndefinedInstr { addr, instr, thumb: self.is_thumb() };
+        match self.undefined_policy {
+            UndefinedPolicy::Exception => {
+                debug!(target: "cpu", "{}", undef);
+                // LR points past it, so MOVS PC, LR returns to the next one
+                let next = addr.wrapping_add(if undef.thumb { 2 } else { 4 });
+                self.raise_exception(Exception::Undefined, next);
+            },
+            UndefinedPolicy::Skip => warn!(target: "cpu", "skipped {}", undef),
+            UndefinedPolicy::Break => {
+                self.set_pc(addr);
+                self.undefined = Some(undef);
+            },
         }
     }
 
-    // Negative or less than
+    /// The instruction the Break policy last stopped at, if it hasn't been
+    /// taken yet
+    pub fn take_undefined(&mut self) -> Option<UndefinedInstr> {
+        self.undefined.take()
+    }
+
+    /// Negative or less than
     pub fn is_neg_lt(&self) -> bool { self.cpsr.read_masked(N_MASK) != 0 }
+    /// Set N
     pub fn set_neg_lt(&mut self)    { self.cpsr.set(N_MASK, N_MASK); }
+    /// Clear N
     pub fn reset_neg_lt(&mut self)  { self.cpsr.reset(N_MASK, N_MASK); }
 
-    // Zero
+    /// Zero
     pub fn is_zero(&self) -> bool { self.cpsr.read_masked(Z_MASK) != 0 }
+    /// Set Z
     pub fn set_zero(&mut self)    { self.cpsr.set(Z_MASK, Z_MASK); }
+    /// Clear Z
     pub fn reset_zero(&mut self)  { self.cpsr.reset(Z_MASK, Z_MASK); }
 
-    // Carry, borrow, or extend
+    /// Carry, borrow, or extend
     pub fn is_carry(&self) -> bool { self.cpsr.read_masked(C_MASK) != 0 }
+    /// Set C
     pub fn set_carry(&mut self)    { self.cpsr.set(C_MASK, C_MASK); }
+    /// Clear C
     pub fn reset_carry(&mut self)  { self.cpsr.reset(C_MASK, C_MASK); }
 
-    // Overflow
+    /// Overflow
     pub fn is_overflow(&self) -> bool { self.cpsr.read_masked(V_MASK) != 0 }
+    /// Set V
     pub fn set_overflow(&mut self)    { self.cpsr.set(V_MASK, V_MASK); }
+    /// Clear V
     pub fn reset_overflow(&mut self)  { self.cpsr.reset(V_MASK, V_MASK); }
 
-    // Reset condition bits
-    pub fn reset_cond(&mut self) { self.cpsr.reset(COND_MASK, COND_MASK); }
+    /// Reset condition bits
+    pub fn reset_cond(&mut self) { self.set_flag_bits(0); }
+
+    /// All four condition flags at once, as an ALU result sets them
+    pub fn set_flags(&mut self, flags: Flags) { self.set_flag_bits(flags.bits()); }
 
-    // IRQ disable
+    /// IRQ disable
     pub fn is_irq_disable(&self) -> bool { self.cpsr.read_masked(I_MASK) != 0 }
+    /// Set I
     pub fn set_irq_disable(&mut self)    { self.cpsr.set(I_MASK, I_MASK); }
+    /// Clear I
     pub fn reset_irq_disable(&mut self)  { self.cpsr.reset(I_MASK, I_MASK); }
 
-    // FRQ disable
+    /// FRQ disable
     pub fn is_fiq_disable(&self) -> bool { self.cpsr.read_masked(F_MASK) != 0 }
+    /// Set F
     pub fn set_fiq_disable(&mut self)    { self.cpsr.set(F_MASK, F_MASK); }
+    /// Clear F
     pub fn reset_fiq_disable(&mut self)  { self.cpsr.reset(F_MASK, F_MASK); }
 
-    // Thumb mode
+    /// Thumb mode
     pub fn is_thumb(&self) -> bool { self.cpsr.read_masked(T_MASK) != 0 }
+    /// Set T
     pub fn set_thumb(&mut self)    { self.cpsr.set(T_MASK, T_MASK); }
+    /// Clear T
     pub fn reset_thumb(&mut self)  { self.cpsr.reset(T_MASK, T_MASK); }
 
+    /// Mode from the CPSR's mode bits
     pub fn mode(&self) -> ARM7Mode {
-        match self.cpsr.read_masked(M_MASK) {
-            USER_MODE => User,
-            FIQ_MODE  => FIQ,
-            IRQ_MODE  => IRQ,
-            SV_MODE   => Supervisor,
-            ABRT_MODE => Abort,
-            UDEF_MODE => Undefined,
-            SYS_MODE  => System,
-            _ => unreachable!(),
-        }
+        ARM7Mode::from_bits(self.mode_bits()).expect("invalid mode in the CPSR")
     }
 
+    /// Registers are banked by looking up the current mode on every access,
+    /// so switching mode needs nothing more than the new mode bits
     pub fn set_mode(&mut self, new_mode: ARM7Mode) {
-        self.cpsr.set(M_MASK, new_mode as u32)
+        self.set_mode_bits(new_mode as RType);
+    }
+
+    /// Write the bits of the CPSR in mask, as MSR and returning from an
+    /// exception do. User mode can only change the flags. Mode bits that
+    /// aren't a mode would leave the CPU in an unrecoverable state, so they
+    /// are ignored and the mode is kept.
+    pub fn write_cpsr(&mut self, val: RType, mask: RType) {
+        let mut mask = mask;
+        if self.mode() == User {
+            mask &= PSR_FLAGS;
+        }
+        if mask & M_MASK != 0 && ARM7Mode::from_bits(val).is_none() {
+            warn!(target: "cpu", "ignored a CPSR write with invalid mode bits {:#07b}",
+                  val & M_MASK);
+            mask &= !M_MASK;
+        }
+        self.cpsr.write_masked(mask, val);
+    }
+}
+
+bitfield!(ARM7 {
+    cpsr => mode_bits, set_mode_bits: RType [0, 5];
+    cpsr => pub flag_bits, set_flag_bits: RType [28, 4]; // NZCV from the top down
+});
+
+impl Core for ARM7 {
+    fn step(&mut self, mem: &mut Memory) {
+        if self.is_thumb() {
+            thumb_instr::step(self, mem);
+        }
+        else {
+            arm_instr::step(self, mem);
+        }
+    }
+
+    fn state(&self) -> CoreState {
+        ARM7::state(self)
     }
 }
 
@@ -304,29 +737,29 @@ impl fmt::Debug for ARM7 {
 
 impl fmt::Display for ARM7 {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write![f, "ARM7TDMI:\n"]?;
+        writeln![f, "ARM7TDMI:"]?;
         for i in 0..R15 {
-            let reg_idx = self.reg_map_index(i).unwrap_or(-1);
-            let alt_reg = if reg_idx > PC || reg_idx < R0 { "*" } else { "" };
-            let reg_val = *self.reg(i).unwrap_or(&Register::default());
-            write![f, "\tR{:02}[{:2}]:\t{}({:p}){}\n",
+            let reg_idx = self.reg_map_index(i);
+            let alt_reg = if reg_idx > PC { "*" } else { "" };
+            let reg_val = *self.reg(i);
+            writeln![f, "\tR{:02}[{:2}]:\t{}({:p}){}",
                    i, reg_idx, reg_val, reg_val, alt_reg]?;
         }
-        write![f, "\tR{:02}[{:2}]:\t{}({:#010x})\t(PC)\n",
-               PC + 1, self.reg_map_index(PC).unwrap_or(-1),
+        writeln![f, "\tR{:02}[{:2}]:\t{}({:#010x})\t(PC)",
+               PC + 1, self.reg_map_index(PC),
                self.pc(), self.pc()]?;
 
-        write![f, "\tCPSR:\t{:#032b}\n", self.cpsr()]?;
+        writeln![f, "\tCPSR:\t{:#032b}", self.cpsr()]?;
 
         //write![f, "ARM7TDMI State:\n"]?;
-        write![f, "\tMode:        {}\n", self.mode()]?;
-        write![f, "\tThumb Mode:  {}\n", self.is_thumb()]?;
-        write![f, "\tIRQ Disable: {}\n", self.is_irq_disable()]?;
-        write![f, "\tFIQ Disable: {}\n", self.is_fiq_disable()]?;
-        write![f, "\tNeg/Less:    {}\n", self.is_neg_lt()]?;
-        write![f, "\tZero:        {}\n", self.is_zero()]?;
-        write![f, "\tCarry:       {}\n", self.is_carry()]?;
-        write![f, "\tOverflow:    {}\n", self.is_overflow()]
+        writeln![f, "\tMode:        {}", self.mode()]?;
+        writeln![f, "\tThumb Mode:  {}", self.is_thumb()]?;
+        writeln![f, "\tIRQ Disable: {}", self.is_irq_disable()]?;
+        writeln![f, "\tFIQ Disable: {}", self.is_fiq_disable()]?;
+        writeln![f, "\tNeg/Less:    {}", self.is_neg_lt()]?;
+        writeln![f, "\tZero:        {}", self.is_zero()]?;
+        writeln![f, "\tCarry:       {}", self.is_carry()]?;
+        writeln![f, "\tOverflow:    {}", self.is_overflow()]
     }
 }
 
@@ -416,3 +849,69 @@ impl fmt::Display for ARM7 {
 // Register availability map based on mode in THUMB state from:
 // http://www.atmel.com/Images/DDI0029G_7TDMI_R3_trm.pdf
 // section 2.6.2, page 2-10
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writing_the_pc_aligns_it() {
+        let mut cpu = ARM7::default();
+        cpu.write_reg(PC, 0x03000007);
+        assert_eq!(cpu.pc(), 0x03000004);
+
+        cpu.set_thumb();
+        cpu.write_reg(PC, 0x03000007);
+        assert_eq!(cpu.pc(), 0x03000006);
+    }
+
+    // MOVS PC, LR out of an IRQ taken from THUMB code goes back to it
+    #[test]
+    fn writing_the_pc_can_restore_the_cpsr() {
+        let mut cpu = ARM7::default();
+        cpu.skip_bios();
+        cpu.set_thumb();
+        let cpsr = cpu.cpsr().read();
+        cpu.raise_exception(Exception::Irq, 0x08000103);
+        assert_eq!(cpu.mode(), IRQ);
+        assert!(!cpu.is_thumb());
+
+        let lr = cpu.lr();
+        cpu.write_pc(lr, true);
+        assert_eq!(cpu.cpsr().read(), cpsr);
+        assert_eq!(cpu.pc(), 0x08000102);
+
+        // Nothing to restore from in System mode
+        cpu.write_pc(0x08000000, true);
+        assert_eq!(cpu.cpsr().read(), cpsr);
+    }
+
+    // Supervisor (10011) to User (10000) has to clear bits, not just set them
+    #[test]
+    fn mode_changes_clear_the_old_mode() {
+        let mut cpu = ARM7::default();
+        assert_eq!(cpu.mode(), Supervisor);
+        cpu.set_mode(User);
+        assert_eq!(cpu.mode(), User);
+
+        let mut cpu = ARM7::default();
+        cpu.write_cpsr(USER_MODE, PSR_CONTROL);
+        assert_eq!(cpu.mode(), User);
+        assert!(!cpu.is_irq_disable());
+    }
+
+    #[test]
+    fn cpsr_writes_are_checked() {
+        let mut cpu = ARM7::default();
+        cpu.write_reg(SP, 0x1234);
+        cpu.write_cpsr(0b00110, PSR_CONTROL);
+        assert_eq!(cpu.mode(), Supervisor);
+        assert_eq!(cpu.sp(), 0x1234);
+
+        // User mode can set the flags but can't leave
+        cpu.write_cpsr(USER_MODE, PSR_CONTROL);
+        cpu.write_cpsr(N_MASK | SYS_MODE, PSR_FLAGS | PSR_CONTROL);
+        assert_eq!(cpu.mode(), User);
+        assert!(cpu.is_neg_lt());
+    }
+}
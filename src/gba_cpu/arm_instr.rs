@@ -1,108 +1,81 @@
-use std::fmt;
+use core::fmt;
 
-use gba_cpu::{Instruction, IType, SIType, ARM7};
+use gba_cpu::{Instruction, IType, RType, SIType, ARM7};
+use gba_cpu::arm_cpu::LINK;
 use gba_mem::{Address, Memory};
 
-const COND_MASK: IType = 0xF0000000;
-
-// Condition codes:
-// https://www.scss.tcd.ie/~waldroj/3d1/arm_arm.pdf
-// section A3.2.1
-const COND_EQ: i8 = 0b0000; // Equal; Z set
-const COND_NE: i8 = 0b0001; // Not equal; Z clear
-const COND_CS: i8 = 0b0010; // Carry set; C set     (AKA: HS)
-const COND_CC: i8 = 0b0011; // Carry clear; C clear (AKA: LO)
-const COND_MI: i8 = 0b0100; // Minus/negative; N set
-const COND_PL: i8 = 0b0101; // Plus/positive or zero; N clear
-const COND_VS: i8 = 0b0110; // Overflow; V set
-const COND_VC: i8 = 0b0111; // No overflow; V clear
-const COND_HI: i8 = 0b1000; // Unsigned higher; C set and Z clear
-const COND_LS: i8 = 0b1001; // Unsigned lower; C clear and Z set
-const COND_GE: i8 = 0b1010; // Signed greater than or equal; N == V
-const COND_LT: i8 = 0b1011; // Signed less than; N != V
-const COND_GT: i8 = 0b1100; // Signed greater than; (Z == 0 && N == V)
-const COND_LE: i8 = 0b1101; // Signed less than or equal; (Z == 1 || N != V)
-const COND_AL: i8 = 0b1110; // Always
-
-const COND_SHIFT: IType = 27;
-const COND_EQ_MASKED: IType = 0b0000 << COND_SHIFT;
-const COND_NE_MASKED: IType = 0b0001 << COND_SHIFT;
-const COND_CS_MASKED: IType = 0b0010 << COND_SHIFT;
-const COND_CC_MASKED: IType = 0b0011 << COND_SHIFT;
-const COND_MI_MASKED: IType = 0b0100 << COND_SHIFT;
-const COND_PL_MASKED: IType = 0b0101 << COND_SHIFT;
-const COND_VS_MASKED: IType = 0b0110 << COND_SHIFT;
-const COND_VC_MASKED: IType = 0b0111 << COND_SHIFT;
-const COND_HI_MASKED: IType = 0b1000 << COND_SHIFT;
-const COND_LS_MASKED: IType = 0b1001 << COND_SHIFT;
-const COND_GE_MASKED: IType = 0b1010 << COND_SHIFT;
-const COND_LT_MASKED: IType = 0b1011 << COND_SHIFT;
-const COND_GT_MASKED: IType = 0b1100 << COND_SHIFT;
-const COND_LE_MASKED: IType = 0b1101 << COND_SHIFT;
-const COND_AL_MASKED: IType = 0b1110 << COND_SHIFT;
+const COND_SHIFT: IType = 28;
 
+/// Condition codes, in the order of their encodings:
+/// https://www.scss.tcd.ie/~waldroj/3d1/arm_arm.pdf
+/// section A3.2.1
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum Cond {
-    EQ = COND_EQ_MASKED as isize,
-    NE = COND_NE_MASKED as isize,
-    CS = COND_CS_MASKED as isize,
-    CC = COND_CC_MASKED as isize,
-    MI = COND_MI_MASKED as isize,
-    PL = COND_PL_MASKED as isize,
-    VS = COND_VS_MASKED as isize,
-    VC = COND_VC_MASKED as isize,
-    HI = COND_HI_MASKED as isize,
-    LS = COND_LS_MASKED as isize,
-    GE = COND_GE_MASKED as isize,
-    LT = COND_LT_MASKED as isize,
-    GT = COND_GT_MASKED as isize,
-    LE = COND_LE_MASKED as isize,
-    AL = COND_AL_MASKED as isize,
+    /// Equal; Z set
+    EQ,
+    /// Not equal; Z clear
+    NE,
+    /// Carry set; C set     (AKA: HS)
+    CS,
+    /// Carry clear; C clear (AKA: LO)
+    CC,
+    /// Minus/negative; N set
+    MI,
+    /// Plus/positive or zero; N clear
+    PL,
+    /// Overflow; V set
+    VS,
+    /// No overflow; V clear
+    VC,
+    /// Unsigned higher; C set and Z clear
+    HI,
+    /// Unsigned lower or same; C clear or Z set
+    LS,
+    /// Signed greater than or equal; N == V
+    GE,
+    /// Signed less than; N != V
+    LT,
+    /// Signed greater than; (Z == 0 && N == V)
+    GT,
+    /// Signed less than or equal; (Z == 1 || N != V)
+    LE,
+    /// Always
+    AL,
+    /// Unpredictable on ARMv4; later architectures put other
+    NV,
+        // instructions here, so it's treated as undefined
 }
 
+const CONDS: [Cond; 16] = [
+    Cond::EQ, Cond::NE, Cond::CS, Cond::CC, Cond::MI, Cond::PL, Cond::VS, Cond::VC,
+    Cond::HI, Cond::LS, Cond::GE, Cond::LT, Cond::GT, Cond::LE, Cond::AL, Cond::NV,
+];
+
 impl Cond {
-    fn decode(instr: IType) -> Cond {
-        match instr & COND_MASK {
-            COND_EQ_MASKED => Cond::EQ,
-            COND_NE_MASKED => Cond::NE,
-            COND_CS_MASKED => Cond::CS,
-            COND_CC_MASKED => Cond::CC,
-            COND_MI_MASKED => Cond::MI,
-            COND_PL_MASKED => Cond::PL,
-            COND_VS_MASKED => Cond::VS,
-            COND_VC_MASKED => Cond::VC,
-            COND_HI_MASKED => Cond::HI,
-            COND_LS_MASKED => Cond::LS,
-            COND_GE_MASKED => Cond::GE,
-            COND_LT_MASKED => Cond::LT,
-            COND_GT_MASKED => Cond::GT,
-            COND_LE_MASKED => Cond::LE,
-            COND_AL_MASKED => Cond::AL,
-            _ => unreachable!(),
-        }
+    /// The condition in the top four bits of an instruction
+    pub fn decode(instr: IType) -> Cond {
+        CONDS[(instr >> COND_SHIFT) as usize]
     }
 
-    fn is_satisfied(&self, cpu: &ARM7) -> bool {
-        // Check ensure correct shift amount at compile time
-        assert!(0xF << COND_SHIFT == COND_MASK);
-
-        match *self as IType & COND_MASK {
-            COND_EQ_MASKED =>  cpu.is_zero(),
-            COND_NE_MASKED => !cpu.is_zero(),
-            COND_CS_MASKED =>  cpu.is_carry(),
-            COND_CC_MASKED => !cpu.is_carry(),
-            COND_MI_MASKED =>  cpu.is_neg_lt(),
-            COND_PL_MASKED => !cpu.is_neg_lt(),
-            COND_VS_MASKED =>  cpu.is_overflow(),
-            COND_VC_MASKED => !cpu.is_overflow(),
-            COND_HI_MASKED =>  cpu.is_carry() && !cpu.is_zero(),
-            COND_LS_MASKED => !cpu.is_carry() &&  cpu.is_zero(),
-            COND_GE_MASKED =>  cpu.is_neg_lt() == cpu.is_overflow(),
-            COND_LT_MASKED =>  cpu.is_neg_lt() != cpu.is_overflow(),
-            COND_GT_MASKED =>  cpu.is_zero() && cpu.is_neg_lt() == cpu.is_overflow(),
-            COND_LE_MASKED => !cpu.is_zero() || cpu.is_neg_lt() != cpu.is_overflow(),
-            COND_AL_MASKED =>  true,
-            _ => unreachable!(),
+    /// Whether the CPU's flags pass the condition
+    pub fn is_satisfied(&self, cpu: &ARM7) -> bool {
+        match *self {
+            Cond::EQ =>  cpu.is_zero(),
+            Cond::NE => !cpu.is_zero(),
+            Cond::CS =>  cpu.is_carry(),
+            Cond::CC => !cpu.is_carry(),
+            Cond::MI =>  cpu.is_neg_lt(),
+            Cond::PL => !cpu.is_neg_lt(),
+            Cond::VS =>  cpu.is_overflow(),
+            Cond::VC => !cpu.is_overflow(),
+            Cond::HI =>  cpu.is_carry() && !cpu.is_zero(),
+            Cond::LS => !cpu.is_carry() ||  cpu.is_zero(),
+            Cond::GE =>  cpu.is_neg_lt() == cpu.is_overflow(),
+            Cond::LT =>  cpu.is_neg_lt() != cpu.is_overflow(),
+            Cond::GT => !cpu.is_zero() && cpu.is_neg_lt() == cpu.is_overflow(),
+            Cond::LE =>  cpu.is_zero() || cpu.is_neg_lt() != cpu.is_overflow(),
+            Cond::AL =>  true,
+            Cond::NV =>  false,
         }
     }
 }
@@ -125,27 +98,13 @@ impl fmt::Display for Cond {
             Cond::GT => "gt",
             Cond::LE => "le",
             Cond::AL => "",
+            Cond::NV => "nv",
         };
 
         write!(f, "{}", c)
     }
 }
 
-const DATA_OPCODE_MASK: IType = 0x01E00000;
-
-// pub enum ARM7Instruction {
-//     Branch(Branch),
-//     Unknown,
-// }
-
-// impl ARM7Instruction {
-//     fn fetch(pc: Address, mem: &Memory) -> IType {
-//         mem.read::<IType>(pc)
-//     }
-
-//     fn decode(instr: IType) -> ARM7Instruction
-// }
-
 // The ARM7TDMI uses the ARMv4T architecture
 // Instuction encodings from:
 // https://www.scss.tcd.ie/~waldroj/3d1/arm_arm.pdf
@@ -169,6 +128,8 @@ const BRANCH_LINK:  IType = 0x01000000;
 const BRANCH_SIGN:  IType = 0x00800000;
 const BRANCH_EXTEND:IType = 0xFF000000;
 
+/// B and BL
+#[derive(Clone, Copy, Debug)]
 pub struct Branch {
     cond: Cond,
     link: bool,
@@ -192,8 +153,32 @@ impl Instruction for Branch {
         }
     }
 
-    fn execute(&self, cpu: &mut Self::CPU, mem: &mut Memory) {
+    // The PC has already moved past it. BL leaves that address in LR.
+    fn execute(&self, cpu: &mut Self::CPU, _mem: &mut Memory) {
+        if self.cond.is_satisfied(cpu) {
+            let next = cpu.pc();
+            if self.link {
+                cpu.write_reg(LINK, next);
+            }
+            cpu.write_pc(self.target(next.wrapping_sub(4)), false);
+        }
+    }
+}
+
+impl Branch {
+    /// Condition the branch is taken on
+    pub fn cond(&self) -> Cond {
+        self.cond
+    }
+
+    /// Whether the return address is saved in LR
+    pub fn is_link(&self) -> bool {
+        self.link
+    }
 
+    /// Where the branch at addr goes: the PC reads 8 ahead of it
+    pub fn target(&self, addr: RType) -> RType {
+        addr.wrapping_add(8).wrapping_add(self.off as RType)
     }
 }
 
@@ -210,15 +195,288 @@ impl fmt::Display for Branch {
     }
 }
 
-// TODO: Determine if this is necessary
-fn decode(instr: IType) -> Branch {
+// Coprocessor instructions
+// Instruction descriptions from:
+// https://www.scss.tcd.ie/~waldroj/3d1/arm_arm.pdf
+// section A4.1; CDP, LDC, MCR, MRC and STC
+// The GBA has no coprocessors, so nothing answers these and every one that
+// passes its condition takes the undefined instruction exception.
+const COPROC_LOAD_STORE_MASK:  IType = 0x0E000000;
+const COPROC_LOAD_STORE_IDENT: IType = 0x0C000000;
+const COPROC_OTHER_MASK:       IType = 0x0F000000;
+const COPROC_OTHER_IDENT:      IType = 0x0E000000;
+const COPROC_LOAD:             IType = 0x00100000;
+const COPROC_REG_TRANSFER:     IType = 0x00000010;
+const COPROC_NUM_MASK:         IType = 0x00000F00;
+const COPROC_NUM_SHIFT:        IType = 8;
+
+/// What a coprocessor instruction asks of the coprocessor
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CoprocessorOp {
+    /// Data processing
+    Cdp,
+    /// Load to the coprocessor
+    Ldc,
+    /// Store from the coprocessor
+    Stc,
+    /// Move to an ARM register
+    Mrc,
+    /// Move from an ARM register
+    Mcr,
+}
+
+/// An instruction for a coprocessor, which the GBA doesn't have
+#[derive(Clone, Copy, Debug)]
+pub struct Coprocessor {
+    cond: Cond,
+    op: CoprocessorOp,
+    cp: u8,
+    instr: IType, // Reported to the undefined instruction handling
+}
+
+impl Coprocessor {
+    /// Condition the instruction runs on
+    pub fn cond(&self) -> Cond {
+        self.cond
+    }
+
+    /// The instruction word, as reported when it traps
+    pub fn word(&self) -> IType {
+        self.instr
+    }
+
+    fn is_coprocessor(instr: IType) -> bool {
+        instr & COPROC_LOAD_STORE_MASK == COPROC_LOAD_STORE_IDENT ||
+        instr & COPROC_OTHER_MASK == COPROC_OTHER_IDENT
+    }
+}
+
+impl Instruction for Coprocessor {
+    type CPU = ARM7;
+    type Instr = IType;
+
+    fn decode(instr: IType) -> Coprocessor {
+        let load = instr & COPROC_LOAD != 0;
+        let op = if instr & COPROC_LOAD_STORE_MASK == COPROC_LOAD_STORE_IDENT {
+            if load { CoprocessorOp::Ldc } else { CoprocessorOp::Stc }
+        }
+        else if instr & COPROC_REG_TRANSFER != 0 {
+            if load { CoprocessorOp::Mrc } else { CoprocessorOp::Mcr }
+        }
+        else {
+            CoprocessorOp::Cdp
+        };
+
+        Coprocessor {
+            cond: Cond::decode(instr),
+            op,
+            cp: ((instr & COPROC_NUM_MASK) >> COPROC_NUM_SHIFT) as u8,
+            instr,
+        }
+    }
+
+    fn execute(&self, cpu: &mut Self::CPU, _mem: &mut Memory) {
+        if self.cond.is_satisfied(cpu) {
+            // The PC has already moved past it
+            let addr = cpu.pc().wrapping_sub(4);
+            cpu.undefined_instruction(addr, self.instr);
+        }
+    }
+}
+
+impl fmt::Display for Coprocessor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match self.op {
+            CoprocessorOp::Cdp => "cdp",
+            CoprocessorOp::Ldc => "ldc",
+            CoprocessorOp::Stc => "stc",
+            CoprocessorOp::Mrc => "mrc",
+            CoprocessorOp::Mcr => "mcr",
+        };
+
+        write!(f, "{}{}\tp{}", op, self.cond, self.cp)
+    }
+}
+
+/// A decoded ARM instruction
+#[derive(Clone, Copy, Debug)]
+pub enum ARMInstruction {
+    /// B and BL
+    Branch(Branch),
+    /// CDP, LDC, STC, MRC and MCR
+    Coprocessor(Coprocessor),
+}
+
+impl ARMInstruction {
+    /// Execute the instruction on cpu
+    pub fn execute(&self, cpu: &mut ARM7, mem: &mut Memory) {
+        match *self {
+            ARMInstruction::Branch(ref instr) => instr.execute(cpu, mem),
+            ARMInstruction::Coprocessor(ref instr) => instr.execute(cpu, mem),
+        }
+    }
+}
+
+/// None for encodings that are undefined or not implemented yet, which
+/// includes everything with the NV condition
+pub fn decode(instr: IType) -> Option<ARMInstruction> {
+    if Cond::decode(instr) == Cond::NV {
+        return None
+    }
     if instr & BRANCH_MASK == BRANCH_IDENT {
-        return Branch::decode(instr)
+        return Some(ARMInstruction::Branch(Branch::decode(instr)))
     }
-    unimplemented!()
+    if Coprocessor::is_coprocessor(instr) {
+        return Some(ARMInstruction::Coprocessor(Coprocessor::decode(instr)))
+    }
+    None
+}
+
+/// Fetch, decode and execute the instruction at the PC
+pub fn step(cpu: &mut ARM7, mem: &mut Memory) {
+    let pc = cpu.pc();
+    let instr = mem.fetch::<IType>(pc as Address);
+    cpu.inc_pc();
+    match decode(instr) {
+        Some(op) => op.execute(cpu, mem),
+        None => cpu.undefined_instruction(pc, instr),
+    }
+}
+
+/// True when decode() knows how to handle the instruction
+pub fn decodes(instr: IType) -> bool {
+    Cond::decode(instr) != Cond::NV &&
+    (instr & BRANCH_MASK == BRANCH_IDENT || Coprocessor::is_coprocessor(instr))
 }
 
 // ARM and THUMB instruction definitions can be found at:
 // https://www.scss.tcd.ie/~waldroj/3d1/arm_arm.pdf
 
 // Data processing instructions
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use gba_cpu::arm_cpu::{UndefinedInstr, UndefinedPolicy};
+
+    // Every condition against every combination of NZCV, checked against
+    // the table in the ARM ARM section A3.2.1 written out independently
+    #[test]
+    fn conditions_match_the_arm_arm() {
+        for flags in 0..16 {
+            let (n, z, c, v) = (flags & 8 != 0, flags & 4 != 0, flags & 2 != 0, flags & 1 != 0);
+            let mut cpu = ARM7::default();
+            if n { cpu.set_neg_lt(); }
+            if z { cpu.set_zero(); }
+            if c { cpu.set_carry(); }
+            if v { cpu.set_overflow(); }
+
+            let expected = [
+                z, !z, c, !c, n, !n, v, !v,
+                c && !z, !c || z,
+                n == v, n != v,
+                !z && n == v, z || n != v,
+                true, false,
+            ];
+            for (code, &want) in expected.iter().enumerate() {
+                let cond = Cond::decode((code as IType) << COND_SHIFT);
+                assert_eq!(cond.is_satisfied(&cpu), want,
+                           "{:?} with N={} Z={} C={} V={}", cond, n, z, c, v);
+            }
+        }
+    }
+
+    // beq with Z clear falls through
+    cpu_test!(branch_not_taken: arm [0x0A000010] { flags "nzcv"; } => {
+        pc = 0x03000004;
+        flags "nzcv";
+    });
+
+    // The permanently undefined encoding takes the exception, with LR on
+    // the instruction after it
+    cpu_test!(undefined_takes_the_exception: arm [0xE7F000F0] {} => {
+        pc = 0x00000004;
+        lr = 0x03000004;
+    });
+
+    #[test]
+    fn undefined_policies() {
+        let mut mem = Memory::blank();
+        mem.write32::<u32>(0x03000000, 0xE7F000F0);
+        let mut cpu = ARM7::default();
+        cpu.skip_bios();
+        cpu.set_pc(0x03000000);
+
+        cpu.set_undefined_policy(UndefinedPolicy::Skip);
+        step(&mut cpu, &mut mem);
+        assert_eq!(cpu.pc(), 0x03000004);
+        assert_eq!(cpu.take_undefined(), None);
+
+        cpu.set_pc(0x03000000);
+        cpu.set_undefined_policy(UndefinedPolicy::Break);
+        step(&mut cpu, &mut mem);
+        assert_eq!(cpu.pc(), 0x03000000);
+        assert_eq!(cpu.take_undefined(),
+                   Some(UndefinedInstr { addr: 0x03000000, instr: 0xE7F000F0, thumb: false }));
+    }
+
+    // mrc p15, 0, r0, c0, c0, 0 reads the ID register on CPUs with a
+    // system control coprocessor; here it traps
+    cpu_test!(coprocessor_takes_the_exception: arm [0xEE100F10] {} => {
+        pc = 0x00000004;
+        lr = 0x03000004;
+    });
+
+    // mrceq with Z clear doesn't trap
+    cpu_test!(coprocessor_condition_fails: arm [0x0E100F10] { flags "nzcv"; } => {
+        pc = 0x03000004;
+    });
+
+    #[test]
+    fn coprocessor_space_decodes() {
+        let ops = [
+            (0xEE000000, CoprocessorOp::Cdp),
+            (0xED900000, CoprocessorOp::Ldc),
+            (0xEC800000, CoprocessorOp::Stc),
+            (0xEE100010, CoprocessorOp::Mrc),
+            (0xEE000010, CoprocessorOp::Mcr),
+        ];
+        for &(instr, op) in ops.iter() {
+            assert!(decodes(instr));
+            assert_eq!(Coprocessor::decode(instr | 0xE00).op, op);
+            assert_eq!(Coprocessor::decode(instr | 0xE00).cp, 14);
+        }
+        // SWI sits next to them
+        assert!(!decodes(0xEF000000));
+    }
+
+    #[test]
+    fn conditions_decode_from_the_top_nibble() {
+        assert_eq!(Cond::decode(0x0A000000), Cond::EQ);
+        assert_eq!(Cond::decode(0x1A000000), Cond::NE);
+        assert_eq!(Cond::decode(0x9A000000), Cond::LS);
+        assert_eq!(Cond::decode(0xCA000000), Cond::GT);
+        assert_eq!(Cond::decode(0xEA000000), Cond::AL);
+        assert_eq!(Cond::decode(0xFA000000), Cond::NV);
+    }
+
+    // cdp2 and blx from later architectures have the NV condition and are
+    // undefined here
+    cpu_test!(nv_coprocessor_is_undefined: arm [0xFE000000] {} => {
+        pc = 0x00000004;
+        lr = 0x03000004;
+    });
+    cpu_test!(nv_branch_is_undefined: arm [0xFA000000] {} => {
+        pc = 0x00000004;
+        lr = 0x03000004;
+    });
+
+    #[test]
+    fn nv_words_dont_decode() {
+        for &instr in [0xFE000000, 0xFC000000, 0xFA000000].iter() {
+            assert!(decode(instr).is_none());
+            assert!(!decodes(instr));
+        }
+    }
+}
@@ -0,0 +1,299 @@
+use alloc::collections::BTreeMap;
+
+use gba_cpu::{Core, CoreState, Instruction, IType, TIType, ARM7};
+use gba_cpu::arm_instr::{self, ARMInstruction, Branch, Coprocessor};
+use gba_cpu::thumb_instr::{self, LoadAddress};
+use gba_mem::{Address, Memory};
+use gba_mem::code_pages::CODE_PAGE_SIZE;
+use prelude::*;
+
+// A block ends at a branch, after this many instructions or at the end of
+// a code page, so checking one page tells whether it is stale
+const MAX_BLOCK_OPS: usize = 64;
+// Dropped wholesale past this, rather than tracking which are still used
+const MAX_BLOCKS: usize = 1 << 16;
+
+/// An instruction decoded once, ready to execute
+#[derive(Clone, Copy, Debug)]
+pub enum Op {
+    /// B, BL, BX and the Thumb branches
+    Branch(Branch),
+    /// Coprocessor instructions, which trap
+    Coprocessor(Coprocessor),
+    /// Thumb ADD Rd, PC/SP, #imm
+    LoadAddress(LoadAddress),
+}
+
+impl Op {
+    fn execute(&self, cpu: &mut ARM7, mem: &mut Memory) {
+        match *self {
+            Op::Branch(ref instr) => instr.execute(cpu, mem),
+            Op::Coprocessor(ref instr) => instr.execute(cpu, mem),
+            Op::LoadAddress(ref instr) => instr.execute(cpu, mem),
+        }
+    }
+}
+
+/// A run of instructions decoded from one page, shared with the JIT
+#[derive(Debug)]
+pub struct Block {
+    start: Address,
+    generation: u32, // Of the page when decoded
+    ops: Vec<Op>,
+}
+
+impl Block {
+    /// Decode from start up to the end of the block. Decoding stops short at
+    /// an instruction the interpreter doesn't know.
+    pub fn decode(start: Address, thumb: bool, mem: &Memory) -> Block {
+        let size = if thumb { 2 } else { 4 };
+        let page_end = (start & !(CODE_PAGE_SIZE - 1)) + CODE_PAGE_SIZE;
+        let mut ops = Vec::new();
+        let mut addr = start;
+
+        while ops.len() < MAX_BLOCK_OPS && addr < page_end {
+            let op = if thumb {
+                let instr = mem.peek::<TIType>(addr);
+                if !thumb_instr::decodes(instr) {
+                    break;
+                }
+                Op::LoadAddress(LoadAddress::decode(instr))
+            }
+            else {
+                match arm_instr::decode(mem.peek::<IType>(addr)) {
+                    Some(ARMInstruction::Branch(instr)) => Op::Branch(instr),
+                    Some(ARMInstruction::Coprocessor(instr)) => Op::Coprocessor(instr),
+                    None => break,
+                }
+            };
+            // Coprocessor instructions trap, so they end a block like a branch
+            let ends = matches!(op, Op::Branch(_) | Op::Coprocessor(_));
+            ops.push(op);
+            if ends {
+                break;
+            }
+            addr += size;
+        }
+
+        Block {
+            start,
+            generation: mem.code_generation(start),
+            ops,
+        }
+    }
+
+    /// Address of the first instruction
+    pub fn start(&self) -> Address {
+        self.start
+    }
+
+    /// The code generation of the block's page when it was decoded
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
+    /// The block's instructions, in order
+    pub fn ops(&self) -> &[Op] {
+        &self.ops
+    }
+}
+
+// Where the next instruction is expected to come from
+#[derive(Clone, Copy)]
+struct Cursor {
+    block: usize,
+    index: usize,
+    pc: Address,
+    thumb: bool,
+}
+
+/// Cached interpreter. Runs of instructions are decoded once and replayed
+/// from then on. Opcodes are still fetched over the bus, so timing,
+/// watchpoints and bus logs see the same accesses as the plain interpreter;
+/// only the decoding is skipped. Blocks decoded from EWRAM or IWRAM are
+/// thrown away once their page is written.
+#[derive(Default)]
+pub struct BlockCache {
+    blocks: Vec<Block>,
+    starts: BTreeMap<(Address, bool), usize>, // Index into blocks
+    next: Option<Cursor>,
+    decoded: u64, // Blocks decoded, for judging how well the cache works
+}
+
+impl BlockCache {
+    /// Blocks decoded and kept
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Whether no blocks are kept
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// Blocks decoded since the cache was made, counting ones decoded again
+    pub fn blocks_decoded(&self) -> u64 {
+        self.decoded
+    }
+
+    /// Forget every block, e.g. after loading a save state
+    pub fn clear(&mut self) {
+        self.blocks.clear();
+        self.starts.clear();
+        self.next = None;
+    }
+
+    // The block starting at pc, decoding it if it is new or stale
+    fn block_at(&mut self, pc: Address, thumb: bool, mem: &Memory) -> usize {
+        match self.starts.get(&(pc, thumb)) {
+            Some(&i) if self.blocks[i].generation == mem.code_generation(pc) => return i,
+            Some(&i) => {
+                self.blocks[i] = Block::decode(pc, thumb, mem);
+                self.decoded += 1;
+                return i;
+            },
+            None => {},
+        }
+
+        if self.blocks.len() >= MAX_BLOCKS {
+            self.clear();
+        }
+        self.blocks.push(Block::decode(pc, thumb, mem));
+        self.decoded += 1;
+        self.starts.insert((pc, thumb), self.blocks.len() - 1);
+        self.blocks.len() - 1
+    }
+
+    /// Execute the instruction at the PC, as the interpreter's step would
+    pub fn step(&mut self, cpu: &mut ARM7, mem: &mut Memory) {
+        let pc = cpu.pc() as Address;
+        let thumb = cpu.is_thumb();
+
+        // Carry on through the block the last instruction came from if it
+        // follows on and is still fresh, otherwise start a block here
+        let (i, index) = match self.next.take() {
+            Some(cursor) if cursor.pc == pc && cursor.thumb == thumb &&
+                            self.blocks[cursor.block].generation ==
+                            mem.code_generation(self.blocks[cursor.block].start) =>
+                (cursor.block, cursor.index),
+            _ => (self.block_at(pc, thumb, mem), 0),
+        };
+
+        let block = &self.blocks[i];
+        let op = match block.ops.get(index) {
+            Some(op) => op,
+            // Let the interpreter deal with what couldn't be decoded
+            None => return cpu.step(mem),
+        };
+        if thumb {
+            mem.fetch::<TIType>(pc);
+        }
+        else {
+            mem.fetch::<IType>(pc);
+        }
+        cpu.inc_pc();
+        op.execute(cpu, mem);
+
+        if index + 1 < block.ops.len() {
+            self.next = Some(Cursor {
+                block: i,
+                index: index + 1,
+                pc: pc + if thumb { 2 } else { 4 },
+                thumb,
+            });
+        }
+    }
+}
+
+// Only the number of blocks is worth showing
+impl ::core::fmt::Debug for BlockCache {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+        write![f, "BlockCache {{ blocks: {}, decoded: {} }}", self.blocks.len(), self.decoded]
+    }
+}
+
+/// The cached interpreter as a core of its own, e.g. to run in lockstep
+/// against the plain interpreter
+#[derive(Debug, Default)]
+pub struct CachedCore {
+    cpu: ARM7,
+    cache: BlockCache,
+}
+
+impl CachedCore {
+    /// Run cpu through a new, empty cache
+    pub fn new(cpu: ARM7) -> CachedCore {
+        CachedCore {
+            cpu,
+            cache: BlockCache::default(),
+        }
+    }
+
+    /// The CPU
+    pub fn cpu(&self) -> &ARM7 {
+        &self.cpu
+    }
+
+    /// The block cache
+    pub fn cache(&self) -> &BlockCache {
+        &self.cache
+    }
+}
+
+impl Core for CachedCore {
+    fn step(&mut self, mem: &mut Memory) {
+        self.cache.step(&mut self.cpu, mem);
+    }
+
+    fn state(&self) -> CoreState {
+        self.cpu.state()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // add r0, pc, #4 twice in IWRAM, then rewritten to add r0, pc, #8
+    #[test]
+    fn writes_to_ram_invalidate_blocks() {
+        let mut mem = Memory::blank();
+        let mut cpu = ARM7::default();
+        let mut cache = BlockCache::default();
+        mem.write16::<u16>(0x03000000, 0xA001);
+        mem.write16::<u16>(0x03000002, 0xA001);
+        cpu.set_thumb();
+
+        for _ in 0..2 {
+            cpu.set_pc(0x03000000);
+            cache.step(&mut cpu, &mut mem);
+            cache.step(&mut cpu, &mut mem);
+        }
+        assert_eq!(cache.blocks_decoded(), 1);
+        assert_eq!(cpu.reg(0).read(), 0x03000008);
+
+        mem.write16::<u16>(0x03000002, 0xA002);
+        cpu.set_pc(0x03000000);
+        cache.step(&mut cpu, &mut mem);
+        cache.step(&mut cpu, &mut mem);
+        assert_eq!(cache.blocks_decoded(), 2);
+        assert_eq!(cpu.reg(0).read(), 0x0300000C);
+    }
+
+    // Words with the NV condition aren't decoded into a block, so they
+    // reach the interpreter and take the undefined instruction exception
+    #[test]
+    fn nv_words_are_undefined() {
+        for &instr in [0xFE000000, 0xFA000000].iter() {
+            let mut mem = Memory::blank();
+            let mut cpu = ARM7::default();
+            let mut cache = BlockCache::default();
+            mem.write32::<u32>(0x03000000, instr);
+            cpu.skip_bios();
+            cpu.set_pc(0x03000000);
+            cache.step(&mut cpu, &mut mem);
+            assert_eq!(cpu.pc(), 0x00000004);
+            assert_eq!(cpu.lr(), 0x03000004);
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use core::fmt;
+
+use gba_cpu::{Core, CoreState, Memory};
+use gba_cpu::arm_cpu::{NUM_REGS, NUM_STATUS_REGS};
+use gba_mem::bus_log::BusMismatch;
+use prelude::*;
+
+/// Developer mode running two core implementations in lockstep. The primary
+/// core drives the real bus while its accesses are recorded; the shadow core
+/// then executes the same instruction against the recording. Registers are
+/// compared after every instruction and stepping halts at the first
+/// divergence.
+#[derive(Debug)]
+pub struct Lockstep<A: Core, B: Core> {
+    primary: A,
+    shadow: B,
+    steps: u64,
+    divergence: Option<Divergence>,
+}
+
+/// Where the two cores stopped agreeing
+#[derive(Clone, Copy, Debug)]
+pub struct Divergence {
+    /// Steps run before the cores differed
+    pub step: u64,
+    /// The primary core after that step
+    pub primary: CoreState,
+    /// The shadow core after that step
+    pub shadow: CoreState,
+    /// Bus accesses that differed, if any did
+    pub bus: Option<BusMismatch>,
+}
+
+impl<A: Core, B: Core> Lockstep<A, B> {
+    /// Both cores should start from the same state
+    pub fn new(primary: A, shadow: B) -> Lockstep<A, B> {
+        Lockstep {
+            primary,
+            shadow,
+            steps: 0,
+            divergence: None,
+        }
+    }
+
+    /// The core whose results count
+    pub fn primary(&self) -> &A {
+        &self.primary
+    }
+
+    /// The core checked against it
+    pub fn shadow(&self) -> &B {
+        &self.shadow
+    }
+
+    /// Instructions executed by both cores so far
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Where the cores first differed, if they have
+    pub fn divergence(&self) -> Option<&Divergence> {
+        self.divergence.as_ref()
+    }
+
+    /// Execute one instruction on each core. Once the cores diverge every
+    /// further step returns the same divergence. It's returned by value as
+    /// it's rare and only ever reported.
+    #[allow(clippy::result_large_err)]
+    pub fn step(&mut self, mem: &mut Memory) -> Result<(), Divergence> {
+        if let Some(div) = self.divergence {
+            return Err(div);
+        }
+
+        mem.record_bus();
+        self.primary.step(mem);
+        mem.replay_bus();
+        self.shadow.step(mem);
+        let bus = mem.stop_bus_log().err();
+        self.steps += 1;
+
+        let primary = self.primary.state();
+        let shadow = self.shadow.state();
+        if bus.is_some() || primary != shadow {
+            let div = Divergence {
+                step: self.steps,
+                primary,
+                shadow,
+                bus,
+            };
+            self.divergence = Some(div);
+            return Err(div);
+        }
+
+        Ok(())
+    }
+
+    /// Step until the cores diverge or max_steps instructions have run
+    #[allow(clippy::result_large_err)]
+    pub fn run(&mut self, mem: &mut Memory, max_steps: u64) -> Result<(), Divergence> {
+        for _ in 0..max_steps {
+            self.step(mem)?;
+        }
+        Ok(())
+    }
+}
+
+// Name of a raw register file index
+fn reg_name(idx: usize) -> String {
+    match idx {
+        0..=15 => format!("R{}", idx),
+        16..=22 => format!("R{}_fiq", idx - 8),
+        23 | 24 => format!("R{}_svc", idx - 10),
+        25 | 26 => format!("R{}_abt", idx - 12),
+        27 | 28 => format!("R{}_irq", idx - 14),
+        _ => format!("R{}_und", idx - 16),
+    }
+}
+
+const SPSR_NAMES: [&str; NUM_STATUS_REGS] = [
+    "SPSR_fiq", "SPSR_svc", "SPSR_abt", "SPSR_irq", "SPSR_und", "SPSR_?",
+];
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln![f, "Cores diverged after {} instructions", self.step]?;
+        if let Some(bus) = self.bus {
+            writeln![f, "\tbus: {}", bus]?;
+        }
+
+        for i in 0..NUM_REGS {
+            let (p, s) = (self.primary.regs[i], self.shadow.regs[i]);
+            if p != s {
+                writeln![f, "\t{}: {:#010x} != {:#010x}", reg_name(i), p, s]?;
+            }
+        }
+        if self.primary.cpsr != self.shadow.cpsr {
+            writeln![f, "\tCPSR: {:#010x} != {:#010x}",
+                   self.primary.cpsr, self.shadow.cpsr]?;
+        }
+        for (i, name) in SPSR_NAMES.iter().enumerate() {
+            let (p, s) = (self.primary.spsr[i], self.shadow.spsr[i]);
+            if p != s {
+                writeln![f, "\t{}: {:#010x} != {:#010x}", name, p, s]?;
+            }
+        }
+        Ok(())
+    }
+}
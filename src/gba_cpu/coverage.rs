@@ -0,0 +1,156 @@
+use core::fmt;
+
+use gba_cpu::{IType, TIType};
+use gba_cpu::{arm_instr, thumb_instr};
+
+/// An instruction group of the ARMv4T encoding matrix, matched when
+/// instr & mask == ident. Groups are listed so the first match wins.
+#[derive(Clone, Copy, Debug)]
+pub struct EncodingGroup<T: 'static> {
+    /// As the ARM7TDMI manual calls it
+    pub name: &'static str,
+    /// Bits that identify the group
+    pub mask: T,
+    /// Value of those bits for the group
+    pub ident: T,
+}
+
+// Condition used for the representative encoding of each ARM group
+const COND_ALWAYS: IType = 0xE0000000;
+
+/// ARM instruction set encoding from:
+/// https://www.scss.tcd.ie/~waldroj/3d1/arm_arm.pdf
+/// section A3.1, figure A3-1
+pub static ARM_GROUPS: [EncodingGroup<IType>; 16] = [
+    EncodingGroup { name: "Multiplies / extra load/store",      mask: 0x0E000090, ident: 0x00000090 },
+    EncodingGroup { name: "Miscellaneous (MRS/MSR/BX)",         mask: 0x0F900010, ident: 0x01000000 },
+    EncodingGroup { name: "Miscellaneous (register shift)",     mask: 0x0F900090, ident: 0x01000010 },
+    EncodingGroup { name: "Data processing (immediate shift)",  mask: 0x0E000010, ident: 0x00000000 },
+    EncodingGroup { name: "Data processing (register shift)",   mask: 0x0E000090, ident: 0x00000010 },
+    EncodingGroup { name: "Undefined",                          mask: 0x0FB00000, ident: 0x03000000 },
+    EncodingGroup { name: "Move immediate to status register",  mask: 0x0FB00000, ident: 0x03200000 },
+    EncodingGroup { name: "Data processing (immediate)",        mask: 0x0E000000, ident: 0x02000000 },
+    EncodingGroup { name: "Load/store (immediate offset)",      mask: 0x0E000000, ident: 0x04000000 },
+    EncodingGroup { name: "Architecturally undefined",          mask: 0x0E000010, ident: 0x06000010 },
+    EncodingGroup { name: "Load/store (register offset)",       mask: 0x0E000010, ident: 0x06000000 },
+    EncodingGroup { name: "Load/store multiple",                mask: 0x0E000000, ident: 0x08000000 },
+    EncodingGroup { name: "Branch / branch with link",          mask: 0x0E000000, ident: 0x0A000000 },
+    EncodingGroup { name: "Coprocessor load/store",             mask: 0x0E000000, ident: 0x0C000000 },
+    EncodingGroup { name: "Coprocessor data/register transfer", mask: 0x0F000000, ident: 0x0E000000 },
+    EncodingGroup { name: "Software interrupt",                 mask: 0x0F000000, ident: 0x0F000000 },
+];
+
+/// THUMB instruction formats from:
+/// http://www.atmel.com/Images/DDI0029G_7TDMI_R3_trm.pdf
+/// section 1.4, figure 1-6
+pub static THUMB_GROUPS: [EncodingGroup<TIType>; 19] = [
+    EncodingGroup { name: "Add/subtract (format 2)",                 mask: 0xF800, ident: 0x1800 },
+    EncodingGroup { name: "Move shifted register (format 1)",        mask: 0xE000, ident: 0x0000 },
+    EncodingGroup { name: "Move/compare/add/subtract imm (format 3)",mask: 0xE000, ident: 0x2000 },
+    EncodingGroup { name: "ALU operations (format 4)",               mask: 0xFC00, ident: 0x4000 },
+    EncodingGroup { name: "Hi register operations/BX (format 5)",    mask: 0xFC00, ident: 0x4400 },
+    EncodingGroup { name: "PC-relative load (format 6)",             mask: 0xF800, ident: 0x4800 },
+    EncodingGroup { name: "Load/store register offset (format 7)",   mask: 0xF200, ident: 0x5000 },
+    EncodingGroup { name: "Load/store sign-extended (format 8)",     mask: 0xF200, ident: 0x5200 },
+    EncodingGroup { name: "Load/store immediate offset (format 9)",  mask: 0xE000, ident: 0x6000 },
+    EncodingGroup { name: "Load/store halfword (format 10)",         mask: 0xF000, ident: 0x8000 },
+    EncodingGroup { name: "SP-relative load/store (format 11)",      mask: 0xF000, ident: 0x9000 },
+    EncodingGroup { name: "Load address (format 12)",                mask: 0xF000, ident: 0xA000 },
+    EncodingGroup { name: "Add offset to SP (format 13)",            mask: 0xFF00, ident: 0xB000 },
+    EncodingGroup { name: "Push/pop registers (format 14)",          mask: 0xF600, ident: 0xB400 },
+    EncodingGroup { name: "Multiple load/store (format 15)",         mask: 0xF000, ident: 0xC000 },
+    EncodingGroup { name: "Software interrupt (format 17)",          mask: 0xFF00, ident: 0xDF00 },
+    EncodingGroup { name: "Conditional branch (format 16)",          mask: 0xF000, ident: 0xD000 },
+    EncodingGroup { name: "Unconditional branch (format 18)",        mask: 0xF800, ident: 0xE000 },
+    EncodingGroup { name: "Long branch with link (format 19)",       mask: 0xF000, ident: 0xF000 },
+];
+
+/// Index into ARM_GROUPS of instr's group
+pub fn arm_group(instr: IType) -> Option<usize> {
+    ARM_GROUPS.iter().position(|g| instr & g.mask == g.ident)
+}
+
+/// Index into THUMB_GROUPS of instr's group
+pub fn thumb_group(instr: TIType) -> Option<usize> {
+    THUMB_GROUPS.iter().position(|g| instr & g.mask == g.ident)
+}
+
+/// Per group execution counts, filled in by the interpreter when enabled
+#[derive(Clone, Copy, Debug, Default)]
+pub struct InstrStats {
+    arm: [u64; 16],
+    thumb: [u64; 19],
+}
+
+impl InstrStats {
+    /// Count an ARM instruction
+    pub fn record_arm(&mut self, instr: IType) {
+        if let Some(g) = arm_group(instr) {
+            self.arm[g] += 1;
+        }
+    }
+
+    /// Count a Thumb instruction
+    pub fn record_thumb(&mut self, instr: TIType) {
+        if let Some(g) = thumb_group(instr) {
+            self.thumb[g] += 1;
+        }
+    }
+
+    /// ARM instructions counted in a group
+    pub fn arm_hits(&self, group: usize) -> u64 { self.arm[group] }
+    /// Thumb instructions counted in a group
+    pub fn thumb_hits(&self, group: usize) -> u64 { self.thumb[group] }
+}
+
+/// Report of which encoding groups the decoders handle
+#[derive(Clone, Copy, Debug)]
+pub struct CoverageReport<'a> {
+    stats: Option<&'a InstrStats>,
+}
+
+impl<'a> CoverageReport<'a> {
+    /// A report showing the counts in stats, if any
+    pub fn new(stats: Option<&'a InstrStats>) -> CoverageReport<'a> {
+        CoverageReport { stats }
+    }
+
+    /// Whether the interpreter decodes ARM group group
+    pub fn arm_handled(group: usize) -> bool {
+        arm_instr::decodes(COND_ALWAYS | ARM_GROUPS[group].ident)
+    }
+
+    /// Whether the interpreter decodes Thumb group group
+    pub fn thumb_handled(group: usize) -> bool {
+        thumb_instr::decodes(THUMB_GROUPS[group].ident)
+    }
+
+    fn write_group(&self, f: &mut fmt::Formatter, name: &str, handled: bool,
+                   hits: Option<u64>) -> fmt::Result {
+        let mark = if handled { "x" } else { " " };
+        match hits {
+            Some(n) => writeln![f, "  [{}] {:<42} {:>10} hits", mark, name, n],
+            None    => writeln![f, "  [{}] {}", mark, name],
+        }
+    }
+}
+
+impl<'a> fmt::Display for CoverageReport<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let arm_handled = (0..ARM_GROUPS.len()).filter(|&g| Self::arm_handled(g)).count();
+        writeln![f, "ARM ({}/{} groups handled):", arm_handled, ARM_GROUPS.len()]?;
+        for (i, g) in ARM_GROUPS.iter().enumerate() {
+            self.write_group(f, g.name, Self::arm_handled(i),
+                             self.stats.map(|s| s.arm_hits(i)))?;
+        }
+
+        let thumb_handled = (0..THUMB_GROUPS.len()).filter(|&g| Self::thumb_handled(g)).count();
+        writeln![f, "THUMB ({}/{} groups handled):", thumb_handled, THUMB_GROUPS.len()]?;
+        for (i, g) in THUMB_GROUPS.iter().enumerate() {
+            self.write_group(f, g.name, Self::thumb_handled(i),
+                             self.stats.map(|s| s.thumb_hits(i)))?;
+        }
+
+        Ok(())
+    }
+}
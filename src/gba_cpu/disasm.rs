@@ -0,0 +1,706 @@
+use core::fmt;
+
+use gba_cpu::{IType, TIType};
+use prelude::*;
+
+// Disassembler for the ARMv4T ARM and Thumb instruction sets. Mnemonics
+// and operand order follow the ARM ARM:
+// https://www.scss.tcd.ie/~waldroj/3d1/arm_arm.pdf
+// Thumb formats are numbered as in the ARM7TDMI data sheet, section 5.
+
+const REG_NAMES: [&str; 16] = [
+    "r0", "r1", "r2", "r3", "r4", "r5", "r6", "r7",
+    "r8", "r9", "r10", "r11", "r12", "sp", "lr", "pc",
+];
+
+// Indexed by the condition field; NV is unpredictable on ARMv4
+const COND_NAMES: [&str; 16] = [
+    "eq", "ne", "cs", "cc", "mi", "pl", "vs", "vc",
+    "hi", "ls", "ge", "lt", "gt", "le", "", "nv",
+];
+
+const DATA_OPS: [&str; 16] = [
+    "and", "eor", "sub", "rsb", "add", "adc", "sbc", "rsc",
+    "tst", "teq", "cmp", "cmn", "orr", "mov", "bic", "mvn",
+];
+
+const SHIFT_NAMES: [&str; 4] = ["lsl", "lsr", "asr", "ror"];
+
+const THUMB_ALU_OPS: [&str; 16] = [
+    "and", "eor", "lsl", "lsr", "asr", "adc", "sbc", "ror",
+    "tst", "neg", "cmp", "cmn", "orr", "mul", "bic", "mvn",
+];
+
+fn reg(instr: u32, shift: u32) -> &'static str {
+    REG_NAMES[((instr >> shift) & 0xF) as usize]
+}
+
+fn bit(instr: u32, n: u32) -> bool {
+    (instr >> n) & 1 != 0
+}
+
+fn sign_extend(val: u32, bits: u32) -> i32 {
+    ((val << (32 - bits)) as i32) >> (32 - bits)
+}
+
+fn signed_hex(val: i32) -> String {
+    if val < 0 {
+        format!("#-{:#x}", -(val as i64))
+    }
+    else {
+        format!("#{:#x}", val)
+    }
+}
+
+// "{r0-r3, r5, lr}". Runs of three or more registers are shortened.
+fn reg_list(list: u32) -> String {
+    let mut names = Vec::new();
+    let mut reg = 0;
+    while reg < 16 {
+        if list & (1 << reg) == 0 {
+            reg += 1;
+            continue;
+        }
+        let first = reg;
+        while reg < 16 && list & (1 << reg) != 0 {
+            reg += 1;
+        }
+        if reg - first >= 3 {
+            names.push(format!("{}-{}", REG_NAMES[first], REG_NAMES[reg - 1]));
+        }
+        else {
+            names.extend((first..reg).map(|r| REG_NAMES[r].to_string()));
+        }
+    }
+    format!("{{{}}}", names.join(", "))
+}
+
+// Rm with an immediate or register shift, as used by data processing
+// operand 2 and register offsets
+fn shifted_reg(instr: u32) -> String {
+    let rm = reg(instr, 0);
+    let shift = ((instr >> 5) & 3) as usize;
+
+    if bit(instr, 4) {
+        return format!("{}, {} {}", rm, SHIFT_NAMES[shift], reg(instr, 8));
+    }
+    match ((instr >> 7) & 0x1F, shift) {
+        (0, 0) => rm.to_string(),
+        (0, 3) => format!("{}, rrx", rm),
+        (0, _) => format!("{}, {} #32", rm, SHIFT_NAMES[shift]),
+        (amount, _) => format!("{}, {} #{}", rm, SHIFT_NAMES[shift], amount),
+    }
+}
+
+fn arm_operand2(instr: u32) -> String {
+    if bit(instr, 25) {
+        let rotate = ((instr >> 8) & 0xF) * 2;
+        format!("#{:#x}", (instr & 0xFF).rotate_right(rotate))
+    }
+    else {
+        shifted_reg(instr)
+    }
+}
+
+// "[rn, #off]!", "[rn], -rm, lsl #2" and friends
+fn arm_address(instr: u32, offset: String, zero_offset: bool) -> String {
+    let rn = reg(instr, 16);
+    let pre = bit(instr, 24);
+    let writeback = if bit(instr, 21) { "!" } else { "" };
+
+    match (pre, zero_offset) {
+        (true, true) => format!("[{}]{}", rn, writeback),
+        (true, false) => format!("[{}, {}]{}", rn, offset, writeback),
+        (false, _) => format!("[{}], {}", rn, offset),
+    }
+}
+
+fn arm_data_processing(instr: u32, cond: &str) -> String {
+    let opcode = ((instr >> 21) & 0xF) as usize;
+    let op = DATA_OPS[opcode];
+    let s = if bit(instr, 20) { "s" } else { "" };
+    let op2 = arm_operand2(instr);
+
+    match opcode {
+        // tst, teq, cmp, cmn always set the flags
+        0x8..=0xB => format!("{}{}\t{}, {}", op, cond, reg(instr, 16), op2),
+        0xD | 0xF => format!("{}{}{}\t{}, {}", op, cond, s, reg(instr, 12), op2),
+        _ => format!("{}{}{}\t{}, {}, {}", op, cond, s, reg(instr, 12), reg(instr, 16), op2),
+    }
+}
+
+fn psr_name(instr: u32) -> &'static str {
+    if bit(instr, 22) { "spsr" } else { "cpsr" }
+}
+
+fn arm_msr(instr: u32, cond: &str) -> String {
+    let mut fields = String::new();
+    for &(n, c) in [(19, 'f'), (18, 's'), (17, 'x'), (16, 'c')].iter() {
+        if bit(instr, n) {
+            fields.push(c);
+        }
+    }
+    let src = if bit(instr, 25) { arm_operand2(instr) } else { reg(instr, 0).to_string() };
+    format!("msr{}\t{}_{}, {}", cond, psr_name(instr), fields, src)
+}
+
+fn arm_multiply(instr: u32, cond: &str) -> String {
+    let s = if bit(instr, 20) { "s" } else { "" };
+    let (rd, rm, rs) = (reg(instr, 16), reg(instr, 0), reg(instr, 8));
+
+    if bit(instr, 21) {
+        format!("mla{}{}\t{}, {}, {}, {}", cond, s, rd, rm, rs, reg(instr, 12))
+    }
+    else {
+        format!("mul{}{}\t{}, {}, {}", cond, s, rd, rm, rs)
+    }
+}
+
+fn arm_multiply_long(instr: u32, cond: &str) -> String {
+    let sign = if bit(instr, 22) { "s" } else { "u" };
+    let op = if bit(instr, 21) { "mlal" } else { "mull" };
+    let s = if bit(instr, 20) { "s" } else { "" };
+    format!("{}{}{}{}\t{}, {}, {}, {}", sign, op, cond, s,
+            reg(instr, 12), reg(instr, 16), reg(instr, 0), reg(instr, 8))
+}
+
+fn arm_halfword_transfer(instr: u32, cond: &str) -> String {
+    let load = bit(instr, 20);
+    let op = match ((instr >> 5) & 3, load) {
+        (1, false) => "strh",
+        (1, true) => "ldrh",
+        (2, _) => "ldrsb",
+        _ => "ldrsh",
+    };
+    let sign = if bit(instr, 23) { "" } else { "-" };
+    let (offset, zero) = if bit(instr, 22) {
+        let imm = (instr >> 4) & 0xF0 | instr & 0xF;
+        (format!("#{}{:#x}", sign, imm), imm == 0)
+    }
+    else {
+        (format!("{}{}", sign, reg(instr, 0)), false)
+    };
+    format!("{}{}\t{}, {}", op, cond, reg(instr, 12), arm_address(instr, offset, zero))
+}
+
+fn arm_single_transfer(instr: u32, cond: &str) -> String {
+    let op = if bit(instr, 20) { "ldr" } else { "str" };
+    let b = if bit(instr, 22) { "b" } else { "" };
+    // Post-indexed with writeback forces a user mode access
+    let t = if !bit(instr, 24) && bit(instr, 21) { "t" } else { "" };
+    let sign = if bit(instr, 23) { "" } else { "-" };
+    let (offset, zero) = if bit(instr, 25) {
+        (format!("{}{}", sign, shifted_reg(instr)), false)
+    }
+    else {
+        let imm = instr & 0xFFF;
+        (format!("#{}{:#x}", sign, imm), imm == 0)
+    };
+    format!("{}{}{}{}\t{}, {}", op, cond, b, t, reg(instr, 12), arm_address(instr, offset, zero))
+}
+
+fn arm_block_transfer(instr: u32, cond: &str) -> String {
+    let op = if bit(instr, 20) { "ldm" } else { "stm" };
+    let mode = match (bit(instr, 24), bit(instr, 23)) {
+        (false, true) => "ia",
+        (true, true) => "ib",
+        (false, false) => "da",
+        (true, false) => "db",
+    };
+    let writeback = if bit(instr, 21) { "!" } else { "" };
+    let user = if bit(instr, 22) { "^" } else { "" };
+    format!("{}{}{}\t{}{}, {}{}", op, cond, mode, reg(instr, 16), writeback,
+            reg_list(instr & 0xFFFF), user)
+}
+
+fn arm_coprocessor(instr: u32, cond: &str) -> String {
+    let cp = (instr >> 8) & 0xF;
+    let (cn, cd, cm) = ((instr >> 16) & 0xF, (instr >> 12) & 0xF, instr & 0xF);
+
+    if instr & 0x0E000000 == 0x0C000000 {
+        let op = if bit(instr, 20) { "ldc" } else { "stc" };
+        let long = if bit(instr, 22) { "l" } else { "" };
+        let sign = if bit(instr, 23) { "" } else { "-" };
+        let imm = (instr & 0xFF) * 4;
+        let offset = format!("#{}{:#x}", sign, imm);
+        format!("{}{}{}\tp{}, c{}, {}", op, cond, long, cp, cd,
+                arm_address(instr, offset, imm == 0))
+    }
+    else if bit(instr, 4) {
+        let op = if bit(instr, 20) { "mrc" } else { "mcr" };
+        format!("{}{}\tp{}, {}, {}, c{}, c{}, {}", op, cond, cp, (instr >> 21) & 7,
+                reg(instr, 12), cn, cm, (instr >> 5) & 7)
+    }
+    else {
+        format!("cdp{}\tp{}, {}, c{}, c{}, c{}, {}", cond, cp, (instr >> 20) & 0xF,
+                cd, cn, cm, (instr >> 5) & 7)
+    }
+}
+
+/// Disassemble a 32-bit ARM instruction fetched from addr. Branch targets
+/// are resolved against addr, so pass the real address where possible.
+pub fn disasm_arm(instr: IType, addr: u32) -> String {
+    let cond = COND_NAMES[(instr >> 28) as usize];
+
+    if instr >> 28 == 0xF {
+        return format!("undefined\t{:#010x}", instr);
+    }
+
+    if instr & 0x0FFFFFF0 == 0x012FFF10 {
+        format!("bx{}\t{}", cond, reg(instr, 0))
+    }
+    else if instr & 0x0FC000F0 == 0x00000090 {
+        arm_multiply(instr, cond)
+    }
+    else if instr & 0x0F8000F0 == 0x00800090 {
+        arm_multiply_long(instr, cond)
+    }
+    else if instr & 0x0FB00FF0 == 0x01000090 {
+        let b = if bit(instr, 22) { "b" } else { "" };
+        format!("swp{}{}\t{}, {}, [{}]", cond, b, reg(instr, 12), reg(instr, 0), reg(instr, 16))
+    }
+    else if instr & 0x0E000090 == 0x00000090 && instr & 0x60 != 0 {
+        arm_halfword_transfer(instr, cond)
+    }
+    else if instr & 0x0FBF0FFF == 0x010F0000 {
+        format!("mrs{}\t{}, {}", cond, reg(instr, 12), psr_name(instr))
+    }
+    else if instr & 0x0DB0F000 == 0x0120F000 {
+        arm_msr(instr, cond)
+    }
+    else if instr & 0x0C000000 == 0x00000000 {
+        // Compares without S are the PSR transfers handled above
+        if instr & 0x01900000 == 0x01000000 {
+            format!("undefined\t{:#010x}", instr)
+        }
+        else {
+            arm_data_processing(instr, cond)
+        }
+    }
+    else if instr & 0x0E000010 == 0x06000010 {
+        format!("undefined\t{:#010x}", instr)
+    }
+    else if instr & 0x0C000000 == 0x04000000 {
+        arm_single_transfer(instr, cond)
+    }
+    else if instr & 0x0E000000 == 0x08000000 {
+        arm_block_transfer(instr, cond)
+    }
+    else if instr & 0x0E000000 == 0x0A000000 {
+        let link = if bit(instr, 24) { "l" } else { "" };
+        let target = addr.wrapping_add(8)
+            .wrapping_add((sign_extend(instr & 0xFFFFFF, 24) << 2) as u32);
+        format!("b{}{}\t{:#010x}", link, cond, target)
+    }
+    else if instr & 0x0F000000 == 0x0F000000 {
+        format!("swi{}\t#{:#x}", cond, instr & 0xFFFFFF)
+    }
+    else {
+        arm_coprocessor(instr, cond)
+    }
+}
+
+fn low_reg(instr: u32, shift: u32) -> &'static str {
+    REG_NAMES[((instr >> shift) & 7) as usize]
+}
+
+/// Disassemble a 16-bit Thumb instruction fetched from addr. The halves of
+/// a long branch with link come out separately; use disasm_thumb_bl for the
+/// pair.
+pub fn disasm_thumb(instr: TIType, addr: u32) -> String {
+    let instr = instr as u32;
+    let (rd, rs) = (low_reg(instr, 0), low_reg(instr, 3));
+
+    match instr >> 11 {
+        // Format 1: move shifted register, format 2: add/subtract
+        0b00000..=0b00010 => {
+            let amount = (instr >> 6) & 0x1F;
+            let op = (instr >> 11) as usize;
+            let amount = if amount == 0 && op != 0 { 32 } else { amount };
+            format!("{}\t{}, {}, #{}", SHIFT_NAMES[op], rd, rs, amount)
+        },
+        0b00011 => {
+            let op = if bit(instr, 9) { "sub" } else { "add" };
+            if bit(instr, 10) {
+                format!("{}\t{}, {}, #{}", op, rd, rs, (instr >> 6) & 7)
+            }
+            else {
+                format!("{}\t{}, {}, {}", op, rd, rs, low_reg(instr, 6))
+            }
+        },
+        // Format 3: move/compare/add/subtract immediate
+        0b00100..=0b00111 => {
+            let op = ["mov", "cmp", "add", "sub"][((instr >> 11) & 3) as usize];
+            format!("{}\t{}, #{:#x}", op, low_reg(instr, 8), instr & 0xFF)
+        },
+        0b01000 => {
+            if !bit(instr, 10) {
+                // Format 4: ALU operations
+                format!("{}\t{}, {}", THUMB_ALU_OPS[((instr >> 6) & 0xF) as usize], rd, rs)
+            }
+            else {
+                // Format 5: hi register operations and branch exchange
+                let hd = REG_NAMES[((instr >> 4) & 8 | instr & 7) as usize];
+                let hs = reg(instr, 3);
+                match (instr >> 8) & 3 {
+                    0 => format!("add\t{}, {}", hd, hs),
+                    1 => format!("cmp\t{}, {}", hd, hs),
+                    2 => format!("mov\t{}, {}", hd, hs),
+                    _ => format!("bx\t{}", hs),
+                }
+            }
+        },
+        // Format 6: PC-relative load
+        0b01001 => format!("ldr\t{}, [pc, #{:#x}]", low_reg(instr, 8), (instr & 0xFF) * 4),
+        0b01010 | 0b01011 => {
+            let ro = low_reg(instr, 6);
+            let op = if !bit(instr, 9) {
+                // Format 7: load/store with register offset
+                ["str", "strb", "ldr", "ldrb"][((instr >> 10) & 3) as usize]
+            }
+            else {
+                // Format 8: load/store sign-extended byte/halfword
+                ["strh", "ldrsb", "ldrh", "ldrsh"][((instr >> 10) & 3) as usize]
+            };
+            format!("{}\t{}, [{}, {}]", op, rd, rs, ro)
+        },
+        // Format 9: load/store with immediate offset
+        0b01100..=0b01111 => {
+            let byte = bit(instr, 12);
+            let op = match (bit(instr, 11), byte) {
+                (false, false) => "str",
+                (false, true) => "strb",
+                (true, false) => "ldr",
+                (true, true) => "ldrb",
+            };
+            let offset = ((instr >> 6) & 0x1F) * if byte { 1 } else { 4 };
+            format!("{}\t{}, [{}, #{:#x}]", op, rd, rs, offset)
+        },
+        // Format 10: load/store halfword
+        0b10000 | 0b10001 => {
+            let op = if bit(instr, 11) { "ldrh" } else { "strh" };
+            format!("{}\t{}, [{}, #{:#x}]", op, rd, rs, ((instr >> 6) & 0x1F) * 2)
+        },
+        // Format 11: SP-relative load/store
+        0b10010 | 0b10011 => {
+            let op = if bit(instr, 11) { "ldr" } else { "str" };
+            format!("{}\t{}, [sp, #{:#x}]", op, low_reg(instr, 8), (instr & 0xFF) * 4)
+        },
+        // Format 12: load address. PC-relative ones also show the address
+        // they load, which has bit 1 of the PC cleared.
+        0b10100 | 0b10101 => {
+            let dst = low_reg(instr, 8);
+            let off = (instr & 0xFF) * 4;
+            if bit(instr, 11) {
+                format!("add\t{}, sp, #{:#x}", dst, off)
+            }
+            else {
+                let target = (addr.wrapping_add(4) & !2).wrapping_add(off);
+                format!("add\t{}, pc, #{:#x}\t; {:#010x}", dst, off, target)
+            }
+        },
+        0b10110 | 0b10111 => {
+            if instr & 0xFF00 == 0xB000 {
+                // Format 13: add offset to stack pointer
+                let offset = (instr & 0x7F) as i32 * 4;
+                format!("add\tsp, {}", signed_hex(if bit(instr, 7) { -offset } else { offset }))
+            }
+            else if instr & 0x0600 == 0x0400 {
+                // Format 14: push/pop registers
+                let mut list = instr & 0xFF;
+                if bit(instr, 8) {
+                    list |= if bit(instr, 11) { 1 << 15 } else { 1 << 14 };
+                }
+                let op = if bit(instr, 11) { "pop" } else { "push" };
+                format!("{}\t{}", op, reg_list(list))
+            }
+            else {
+                format!("undefined\t{:#06x}", instr)
+            }
+        },
+        // Format 15: multiple load/store
+        0b11000 | 0b11001 => {
+            let op = if bit(instr, 11) { "ldmia" } else { "stmia" };
+            format!("{}\t{}!, {}", op, low_reg(instr, 8), reg_list(instr & 0xFF))
+        },
+        // Format 16: conditional branch, format 17: software interrupt
+        0b11010 | 0b11011 => {
+            match (instr >> 8) & 0xF {
+                0xE => format!("undefined\t{:#06x}", instr),
+                0xF => format!("swi\t#{:#x}", instr & 0xFF),
+                cond => {
+                    let target = addr.wrapping_add(4)
+                        .wrapping_add((sign_extend(instr & 0xFF, 8) << 1) as u32);
+                    format!("b{}\t{:#010x}", COND_NAMES[cond as usize], target)
+                },
+            }
+        },
+        // Format 18: unconditional branch
+        0b11100 => {
+            let target = addr.wrapping_add(4)
+                .wrapping_add((sign_extend(instr & 0x7FF, 11) << 1) as u32);
+            format!("b\t{:#010x}", target)
+        },
+        // Format 19: long branch with link, one half at a time
+        0b11110 => format!("bl.hi\t{}", signed_hex(sign_extend(instr & 0x7FF, 11) << 12)),
+        0b11111 => format!("bl.lo\t#{:#x}", (instr & 0x7FF) << 1),
+        _ => format!("undefined\t{:#06x}", instr),
+    }
+}
+
+/// True for the first half of a Thumb long branch with link
+pub fn is_thumb_bl_prefix(instr: TIType) -> bool {
+    instr >> 11 == 0b11110
+}
+
+/// Disassemble a complete Thumb long branch with link starting at addr
+pub fn disasm_thumb_bl(hi: TIType, lo: TIType, addr: u32) -> String {
+    let offset = (sign_extend(hi as u32 & 0x7FF, 11) << 12) + ((lo as i32 & 0x7FF) << 1);
+    format!("bl\t{:#010x}", addr.wrapping_add(4).wrapping_add(offset as u32))
+}
+
+/// One disassembled instruction
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DisasmLine {
+    /// Address of the instruction
+    pub addr: u32,
+    /// Both halves for a Thumb long branch, first in the low bits
+    pub raw: u32,
+    /// Bytes
+    pub size: u32,
+    /// Disassembly
+    pub text: String,
+}
+
+impl fmt::Display for DisasmLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.size {
+            2 => write![f, "{:08x}:     {:04x}\t{}", self.addr, self.raw, self.text],
+            _ => write![f, "{:08x}: {:08x}\t{}", self.addr, self.raw, self.text],
+        }
+    }
+}
+
+/// Disassemble little-endian code loaded at base, e.g. a slice of a ROM.
+/// Trailing bytes too short for an instruction are left out.
+pub fn disassemble(code: &[u8], base: u32, thumb: bool) -> Vec<DisasmLine> {
+    let mut lines = Vec::new();
+    let mut pos = 0;
+
+    if thumb {
+        let half = |at: usize| code[at] as TIType | (code[at + 1] as TIType) << 8;
+        while pos + 2 <= code.len() {
+            let addr = base.wrapping_add(pos as u32);
+            let instr = half(pos);
+            if is_thumb_bl_prefix(instr) && pos + 4 <= code.len() && half(pos + 2) >> 11 == 0b11111 {
+                let lo = half(pos + 2);
+                lines.push(DisasmLine {
+                    addr,
+                    raw: instr as u32 | (lo as u32) << 16,
+                    size: 4,
+                    text: disasm_thumb_bl(instr, lo, addr),
+                });
+                pos += 4;
+            }
+            else {
+                lines.push(DisasmLine {
+                    addr,
+                    raw: instr as u32,
+                    size: 2,
+                    text: disasm_thumb(instr, addr),
+                });
+                pos += 2;
+            }
+        }
+    }
+    else {
+        while pos + 4 <= code.len() {
+            let addr = base.wrapping_add(pos as u32);
+            let instr = code[pos] as IType | (code[pos + 1] as IType) << 8 |
+                (code[pos + 2] as IType) << 16 | (code[pos + 3] as IType) << 24;
+            lines.push(DisasmLine {
+                addr,
+                raw: instr,
+                size: 4,
+                text: disasm_arm(instr, addr),
+            });
+            pos += 4;
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: u32 = 0x08000000;
+
+    // Encodings assembled by hand from the ARM ARM
+    const ARM: [(IType, &str); 43] = [
+        // Data processing, immediate operand 2
+        (0xE3A00001, "mov\tr0, #0x1"),
+        (0xE2811004, "add\tr1, r1, #0x4"),
+        (0xE3A004FF, "mov\tr0, #0xff000000"),
+        (0xE3500000, "cmp\tr0, #0x0"),
+        // Data processing, register operand 2 shifted by an immediate or
+        // a register
+        (0xE0810002, "add\tr0, r1, r2"),
+        (0xE1A00101, "mov\tr0, r1, lsl #2"),
+        (0xE1A00021, "mov\tr0, r1, lsr #32"),
+        (0xE1A00061, "mov\tr0, r1, rrx"),
+        (0xE0910312, "adds\tr0, r1, r2, lsl r3"),
+        (0x11A0F00E, "movne\tpc, lr"),
+        // Branch exchange, PSR transfers, multiplies and swaps
+        (0xE12FFF1E, "bx\tlr"),
+        (0xE10F0000, "mrs\tr0, cpsr"),
+        (0xE129F000, "msr\tcpsr_fc, r0"),
+        (0xE0000291, "mul\tr0, r1, r2"),
+        (0xE0203291, "mla\tr0, r1, r2, r3"),
+        (0xE0810392, "umull\tr0, r1, r2, r3"),
+        (0xE1020091, "swp\tr0, r1, [r2]"),
+        // Single data transfer
+        (0xE5910004, "ldr\tr0, [r1, #0x4]"),
+        (0xE5810000, "str\tr0, [r1]"),
+        (0xE5310004, "ldr\tr0, [r1, #-0x4]!"),
+        (0xE4D10001, "ldrb\tr0, [r1], #0x1"),
+        (0xE7910102, "ldr\tr0, [r1, r2, lsl #2]"),
+        (0xE4B10004, "ldrt\tr0, [r1], #0x4"),
+        // Halfword and signed transfers
+        (0xE1D100B2, "ldrh\tr0, [r1, #0x2]"),
+        (0xE1C100B0, "strh\tr0, [r1]"),
+        (0xE19100D2, "ldrsb\tr0, [r1, r2]"),
+        (0xE05100F2, "ldrsh\tr0, [r1], #-0x2"),
+        // Block data transfer
+        (0xE92D4010, "stmdb\tsp!, {r4, lr}"),
+        (0xE8BD8010, "ldmia\tsp!, {r4, pc}"),
+        (0xE891000F, "ldmia\tr1, {r0-r3}"),
+        (0xE8D10003, "ldmia\tr1, {r0, r1}^"),
+        // Branches, resolved against BASE
+        (0xEA000000, "b\t0x08000008"),
+        (0xEBFFFFFE, "bl\t0x08000000"),
+        (0x0AFFFFFD, "beq\t0x07fffffc"),
+        // Software interrupts
+        (0xEF000005, "swi\t#0x5"),
+        (0xEF123456, "swi\t#0x123456"),
+        // Coprocessor
+        (0xEE000000, "cdp\tp0, 0, c0, c0, c0, 0"),
+        (0xEE010F10, "mcr\tp15, 0, r0, c1, c0, 0"),
+        (0xEE110F10, "mrc\tp15, 0, r0, c1, c0, 0"),
+        (0xED910104, "ldc\tp1, c0, [r1, #0x10]"),
+        (0xEC810100, "stc\tp1, c0, [r1], #0x0"),
+        // The NV condition and the undefined instruction space
+        (0xF0000000, "undefined\t0xf0000000"),
+        (0xE7F000F0, "undefined\t0xe7f000f0"),
+    ];
+
+    const THUMB: [(TIType, &str); 44] = [
+        // Format 1: move shifted register
+        (0x0088, "lsl\tr0, r1, #2"),
+        (0x0848, "lsr\tr0, r1, #1"),
+        (0x1008, "asr\tr0, r1, #32"),
+        // Format 2: add/subtract
+        (0x1888, "add\tr0, r1, r2"),
+        (0x1E48, "sub\tr0, r1, #1"),
+        // Format 3: move/compare/add/subtract immediate
+        (0x2001, "mov\tr0, #0x1"),
+        (0x2AFF, "cmp\tr2, #0xff"),
+        (0x3010, "add\tr0, #0x10"),
+        (0x3901, "sub\tr1, #0x1"),
+        // Format 4: ALU operations
+        (0x4048, "eor\tr0, r1"),
+        (0x4348, "mul\tr0, r1"),
+        (0x4248, "neg\tr0, r1"),
+        // Format 5: hi register operations and branch exchange
+        (0x4485, "add\tsp, r0"),
+        (0x46C0, "mov\tr8, r8"),
+        (0x45A0, "cmp\tr8, r4"),
+        (0x4770, "bx\tlr"),
+        // Format 6: PC-relative load
+        (0x4801, "ldr\tr0, [pc, #0x4]"),
+        // Format 7: load/store with register offset
+        (0x5088, "str\tr0, [r1, r2]"),
+        (0x5C88, "ldrb\tr0, [r1, r2]"),
+        // Format 8: load/store sign-extended byte/halfword
+        (0x5288, "strh\tr0, [r1, r2]"),
+        (0x5688, "ldrsb\tr0, [r1, r2]"),
+        (0x5E88, "ldrsh\tr0, [r1, r2]"),
+        // Format 9: load/store with immediate offset
+        (0x6848, "ldr\tr0, [r1, #0x4]"),
+        (0x7048, "strb\tr0, [r1, #0x1]"),
+        // Format 10: load/store halfword
+        (0x8848, "ldrh\tr0, [r1, #0x2]"),
+        (0x8048, "strh\tr0, [r1, #0x2]"),
+        // Format 11: SP-relative load/store
+        (0x9801, "ldr\tr0, [sp, #0x4]"),
+        (0x9001, "str\tr0, [sp, #0x4]"),
+        // Format 12: load address, PC-relative resolved against BASE
+        (0xA001, "add\tr0, pc, #0x4\t; 0x08000008"),
+        (0xA901, "add\tr1, sp, #0x4"),
+        // Format 13: add offset to stack pointer
+        (0xB004, "add\tsp, #0x10"),
+        (0xB084, "add\tsp, #-0x10"),
+        // Format 14: push/pop registers
+        (0xB510, "push\t{r4, lr}"),
+        (0xBD10, "pop\t{r4, pc}"),
+        (0xB40F, "push\t{r0-r3}"),
+        // Format 15: multiple load/store
+        (0xC803, "ldmia\tr0!, {r0, r1}"),
+        (0xC10C, "stmia\tr1!, {r2, r3}"),
+        // Format 16: conditional branch, resolved against BASE
+        (0xD0FE, "beq\t0x08000000"),
+        (0xD101, "bne\t0x08000006"),
+        // Format 17: software interrupt, next to the undefined condition
+        (0xDF05, "swi\t#0x5"),
+        (0xDE00, "undefined\t0xde00"),
+        // Format 18: unconditional branch
+        (0xE7FE, "b\t0x08000000"),
+        // Format 19: long branch with link, one half at a time
+        (0xF7FF, "bl.hi\t#-0x1000"),
+        (0xF802, "bl.lo\t#0x4"),
+    ];
+
+    #[test]
+    fn arm_encodings() {
+        for &(instr, text) in ARM.iter() {
+            assert_eq!(disasm_arm(instr, BASE), text, "{:#010x}", instr);
+        }
+    }
+
+    #[test]
+    fn thumb_encodings() {
+        for &(instr, text) in THUMB.iter() {
+            assert_eq!(disasm_thumb(instr, BASE), text, "{:#06x}", instr);
+        }
+    }
+
+    // Targets are relative to the instruction's own address, which for a
+    // Thumb load address has bit 1 of the PC cleared
+    #[test]
+    fn pc_relative_targets() {
+        assert_eq!(disasm_arm(0xEA000000, 0x03000010), "b\t0x03000018");
+        assert_eq!(disasm_arm(0xEBFFFFFE, 0x00000000), "bl\t0x00000000");
+        assert_eq!(disasm_thumb(0xA001, 0x08000002), "add\tr0, pc, #0x4\t; 0x08000008");
+        assert_eq!(disasm_thumb(0xA001, 0x08000004), "add\tr0, pc, #0x4\t; 0x0800000c");
+        assert_eq!(disasm_thumb(0xE7FE, 0x03000100), "b\t0x03000100");
+        assert_eq!(disasm_thumb_bl(0xF000, 0xF802, BASE), "bl\t0x08000008");
+        assert_eq!(disasm_thumb_bl(0xF7FF, 0xFFFE, BASE), "bl\t0x08000000");
+    }
+
+    // A long branch's halves are paired up; a lone half is left as is
+    #[test]
+    fn disassemble_pairs_long_branches() {
+        let code = [0x00, 0xF0, 0x02, 0xF8, 0x01, 0xA0, 0x00, 0xF0];
+        let lines = disassemble(&code, BASE, true);
+        let texts: Vec<_> = lines.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(texts, ["bl\t0x08000008", "add\tr0, pc, #0x4\t; 0x0800000c", "bl.hi\t#0x0"]);
+        assert_eq!(lines[0].raw, 0xF802F000);
+        assert_eq!(lines[0].size, 4);
+        assert_eq!(lines[1].addr, 0x08000004);
+        assert_eq!(lines[1].to_string(), "08000004:     a001\tadd\tr0, pc, #0x4\t; 0x0800000c");
+
+        let arm = disassemble(&[0x01, 0x00, 0xA0, 0xE3, 0xFF], BASE, false);
+        assert_eq!(arm.len(), 1);
+        assert_eq!(arm[0].to_string(), "08000000: e3a00001\tmov\tr0, #0x1");
+    }
+}
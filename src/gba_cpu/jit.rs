@@ -0,0 +1,801 @@
+// Compiled code is called through raw pointers and calls back into the
+// emulator through them
+#![allow(unsafe_code)]
+
+use alloc::collections::BTreeMap;
+use core::fmt;
+use core::mem::{self, ManuallyDrop};
+
+use cranelift_codegen::Context;
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{types, AbiParam, FuncRef, InstBuilder, Signature, Value};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Switch};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, FuncId, Linkage, Module};
+
+use gba_cpu::{Core, CoreState, IType, RType, TIType, ARM7};
+use gba_cpu::arm_cpu::{LINK, SP};
+use gba_cpu::arm_instr::{Branch, Cond, Coprocessor};
+use gba_cpu::block_cache::{Block, Op};
+use gba_cpu::thumb_instr::LoadAddress;
+use gba_error::{GbaError, GbaResult};
+use gba_mem::{Address, Memory};
+use prelude::*;
+
+// Dropped wholesale past this, rather than tracking which are still used
+const MAX_BLOCKS: usize = 1 << 16;
+// The code of a stale block isn't freed when it is recompiled, only when
+// the whole module is thrown away after this many functions
+const MAX_FUNCTIONS: usize = 1 << 16;
+
+// Condition flags in the CPSR
+const N_SHIFT: i64 = 31;
+const Z_SHIFT: i64 = 30;
+const C_SHIFT: i64 = 29;
+const V_SHIFT: i64 = 28;
+
+// What compiled code is handed, to call back into the emulator with
+#[repr(C)]
+struct JitContext {
+    cpu: *mut ARM7,
+    mem: *mut Memory,
+}
+
+// Runs the instructions from an index into the block until a limit or the
+// block's exit, returning how many ran. Their opcodes are fetched by the
+// caller.
+type BlockFn = extern "C" fn(*mut JitContext, u32, u32) -> u32;
+
+// Safe as long as ctx came from JitCache::step, which holds both borrows
+// for as long as the compiled code runs
+unsafe fn parts<'a>(ctx: *mut JitContext) -> (&'a mut ARM7, &'a mut Memory) {
+    let ctx = &*ctx;
+    (&mut *ctx.cpu, &mut *ctx.mem)
+}
+
+extern "C" fn gba_jit_set_pc(ctx: *mut JitContext, pc: u32) {
+    let (cpu, _) = unsafe { parts(ctx) };
+    cpu.set_pc(pc);
+}
+
+extern "C" fn gba_jit_cpsr(ctx: *mut JitContext) -> u32 {
+    let (cpu, _) = unsafe { parts(ctx) };
+    cpu.cpsr().read()
+}
+
+extern "C" fn gba_jit_read_reg(ctx: *mut JitContext, reg: u32) -> u32 {
+    let (cpu, _) = unsafe { parts(ctx) };
+    cpu.reg(reg as i8).read()
+}
+
+extern "C" fn gba_jit_write_reg(ctx: *mut JitContext, reg: u32, val: u32) {
+    let (cpu, _) = unsafe { parts(ctx) };
+    cpu.write_reg(reg as i8, val);
+}
+
+extern "C" fn gba_jit_undefined(ctx: *mut JitContext, addr: u32, instr: u32) {
+    let (cpu, _) = unsafe { parts(ctx) };
+    cpu.undefined_instruction(addr, instr);
+}
+
+fn jit_error<E: fmt::Display>(e: E) -> GbaError {
+    GbaError::Jit(e.to_string())
+}
+
+// The calls back into the emulator, as declared in a module
+#[derive(Clone, Copy)]
+struct Helpers {
+    set_pc: FuncId,
+    cpsr: FuncId,
+    read_reg: FuncId,
+    write_reg: FuncId,
+    undefined: FuncId,
+}
+
+impl Helpers {
+    fn declare(module: &mut JITModule) -> GbaResult<Helpers> {
+        let mut declare = |name: &str, params: usize, ret: bool| {
+            let ptr = module.target_config().pointer_type();
+            let mut sig = module.make_signature();
+            sig.params.push(AbiParam::new(ptr));
+            for _ in 0..params {
+                sig.params.push(AbiParam::new(types::I32));
+            }
+            if ret {
+                sig.returns.push(AbiParam::new(types::I32));
+            }
+            module.declare_function(name, Linkage::Import, &sig).map_err(jit_error)
+        };
+
+        Ok(Helpers {
+            set_pc: declare("gba_jit_set_pc", 1, false)?,
+            cpsr: declare("gba_jit_cpsr", 0, true)?,
+            read_reg: declare("gba_jit_read_reg", 1, true)?,
+            write_reg: declare("gba_jit_write_reg", 2, false)?,
+            undefined: declare("gba_jit_undefined", 2, false)?,
+        })
+    }
+}
+
+// The helpers as imported into the function being compiled
+struct HelperRefs {
+    set_pc: FuncRef,
+    cpsr: FuncRef,
+    read_reg: FuncRef,
+    write_reg: FuncRef,
+    undefined: FuncRef,
+}
+
+fn new_module() -> GbaResult<JITModule> {
+    let mut flags = settings::builder();
+    // The helpers can be anywhere in the address space
+    flags.set("use_colocated_libcalls", "false").map_err(jit_error)?;
+    flags.set("is_pic", "true").map_err(jit_error)?;
+    // Blocks are mostly calls back into the emulator, which optimizing
+    // doesn't speed up, so compile them as quickly as possible
+    flags.set("opt_level", "none").map_err(jit_error)?;
+    flags.set("regalloc_algorithm", "single_pass").map_err(jit_error)?;
+    flags.set("enable_verifier", if cfg!(debug_assertions) { "true" } else { "false" })
+        .map_err(jit_error)?;
+    let isa = cranelift_native::builder().map_err(jit_error)?
+        .finish(settings::Flags::new(flags)).map_err(jit_error)?;
+
+    let mut builder = JITBuilder::with_isa(isa, default_libcall_names());
+    builder.symbol("gba_jit_set_pc", gba_jit_set_pc as *const u8);
+    builder.symbol("gba_jit_cpsr", gba_jit_cpsr as *const u8);
+    builder.symbol("gba_jit_read_reg", gba_jit_read_reg as *const u8);
+    builder.symbol("gba_jit_write_reg", gba_jit_write_reg as *const u8);
+    builder.symbol("gba_jit_undefined", gba_jit_undefined as *const u8);
+    Ok(JITModule::new(builder))
+}
+
+// One bit of the CPSR, as 0 or 1
+fn flag(b: &mut FunctionBuilder, cpsr: Value, shift: i64) -> Value {
+    let bit = b.ins().ushr_imm(cpsr, shift);
+    b.ins().band_imm(bit, 1)
+}
+
+// 1 when cond passes, else 0. AL and NV are settled at compile time, so
+// they never get here.
+fn passes(b: &mut FunctionBuilder, cpsr: Value, cond: Cond) -> Value {
+    let n = flag(b, cpsr, N_SHIFT);
+    let z = flag(b, cpsr, Z_SHIFT);
+    let c = flag(b, cpsr, C_SHIFT);
+    let v = flag(b, cpsr, V_SHIFT);
+    match cond {
+        Cond::EQ => z,
+        Cond::NE => b.ins().bxor_imm(z, 1),
+        Cond::CS => c,
+        Cond::CC => b.ins().bxor_imm(c, 1),
+        Cond::MI => n,
+        Cond::PL => b.ins().bxor_imm(n, 1),
+        Cond::VS => v,
+        Cond::VC => b.ins().bxor_imm(v, 1),
+        Cond::HI => {
+            let nz = b.ins().bxor_imm(z, 1);
+            b.ins().band(c, nz)
+        },
+        Cond::LS => {
+            let nc = b.ins().bxor_imm(c, 1);
+            b.ins().bor(nc, z)
+        },
+        Cond::GE => {
+            let lt = b.ins().bxor(n, v);
+            b.ins().bxor_imm(lt, 1)
+        },
+        Cond::LT => b.ins().bxor(n, v),
+        Cond::GT => {
+            let lt = b.ins().bxor(n, v);
+            let le = b.ins().bor(z, lt);
+            b.ins().bxor_imm(le, 1)
+        },
+        Cond::LE => {
+            let lt = b.ins().bxor(n, v);
+            b.ins().bor(z, lt)
+        },
+        Cond::AL | Cond::NV => unreachable!("{} is known at compile time", cond),
+    }
+}
+
+fn const32(b: &mut FunctionBuilder, val: u32) -> Value {
+    b.ins().iconst(types::I32, i64::from(val))
+}
+
+fn set_pc(b: &mut FunctionBuilder, helpers: &HelperRefs, ctx: Value, pc: u32) {
+    let pc = const32(b, pc);
+    b.ins().call(helpers.set_pc, &[ctx, pc]);
+}
+
+// Run taken when cond passes. AL and NV are settled at compile time.
+fn when<F>(b: &mut FunctionBuilder, helpers: &HelperRefs, ctx: Value, cond: Cond, taken: F)
+    where F: FnOnce(&mut FunctionBuilder) {
+    match cond {
+        Cond::AL => taken(b),
+        Cond::NV => {},
+        cond => {
+            let call = b.ins().call(helpers.cpsr, &[ctx]);
+            let cpsr = b.inst_results(call)[0];
+            let pass = passes(b, cpsr, cond);
+            let then = b.create_block();
+            let done = b.create_block();
+            b.ins().brif(pass, then, &[], done, &[]);
+            b.switch_to_block(then);
+            taken(b);
+            b.ins().jump(done, &[]);
+            b.switch_to_block(done);
+        },
+    }
+}
+
+// Leaves the PC past it unless taken, like the interpreter
+fn compile_branch(b: &mut FunctionBuilder, helpers: &HelperRefs, ctx: Value,
+                  instr: &Branch, addr: u32) {
+    set_pc(b, helpers, ctx, addr.wrapping_add(4));
+    when(b, helpers, ctx, instr.cond(), |b| {
+        if instr.is_link() {
+            let lr = const32(b, LINK as u32);
+            let next = const32(b, addr.wrapping_add(4));
+            b.ins().call(helpers.write_reg, &[ctx, lr, next]);
+        }
+        set_pc(b, helpers, ctx, instr.target(addr));
+    });
+}
+
+// Traps when its condition passes, as nothing answers it
+fn compile_coprocessor(b: &mut FunctionBuilder, helpers: &HelperRefs, ctx: Value,
+                       instr: &Coprocessor, addr: u32) {
+    set_pc(b, helpers, ctx, addr.wrapping_add(4));
+    when(b, helpers, ctx, instr.cond(), |b| {
+        let addr = const32(b, addr);
+        let word = const32(b, instr.word());
+        b.ins().call(helpers.undefined, &[ctx, addr, word]);
+    });
+}
+
+// A PC relative address is the same every time, so only SP is read
+fn compile_load_address(b: &mut FunctionBuilder, helpers: &HelperRefs, ctx: Value,
+                        instr: &LoadAddress, addr: u32) {
+    let val = if instr.is_sp_relative() {
+        let sp = const32(b, SP as u32);
+        let call = b.ins().call(helpers.read_reg, &[ctx, sp]);
+        let base = b.inst_results(call)[0];
+        b.ins().iadd_imm(base, i64::from(instr.offset()))
+    }
+    else {
+        // The PC reads as the address + 4, with bit 1 forced to zero
+        const32(b, (addr.wrapping_add(4) & !2).wrapping_add(instr.offset()))
+    };
+    let rd = const32(b, instr.rd() as u32);
+    b.ins().call(helpers.write_reg, &[ctx, rd, val]);
+}
+
+// A block as compiled
+struct JitBlock {
+    start: Address,
+    generation: u32, // Of the page when decoded
+    len: usize, // Instructions in it
+    fetch_cycles: Option<u32>, // Each fetch's, if the same every time
+    code: Option<BlockFn>, // None if nothing in it could be compiled
+}
+
+// Where the next instruction is expected to come from
+#[derive(Clone, Copy)]
+struct Cursor {
+    block: usize,
+    index: usize,
+    pc: Address,
+    thumb: bool,
+}
+
+/// ARM and THUMB to native code compiler, built on Cranelift. Blocks are
+/// decoded as for the cached interpreter and each is compiled into one
+/// function that runs from a given index into it to the block's exit: the
+/// end of the block or a branch, which sets the PC. step runs just one
+/// instruction, like every other core, fetching its opcode over the bus so
+/// timing, watchpoints and bus logs see the same accesses as the
+/// interpreter. run goes to the exit in one call when nothing watches the
+/// bus. Its fetches aren't made then, only charged: off the game pak they
+/// cost the same every time, and on it they go through the prefetch unit.
+/// Blocks from EWRAM or IWRAM are recompiled once their page is written.
+pub struct JitCache {
+    module: ManuallyDrop<JITModule>, // Its code is freed by hand
+    helpers: Helpers,
+    ctx: Context,
+    func_ctx: FunctionBuilderContext,
+    blocks: Vec<JitBlock>,
+    starts: BTreeMap<(Address, bool), usize>, // Index into blocks
+    next: Option<Cursor>,
+    functions: usize, // Compiled into the current module
+    compiled: u64, // Blocks compiled, for judging how well the cache works
+}
+
+impl JitCache {
+    /// Fails if Cranelift can't generate code for the host
+    pub fn new() -> GbaResult<JitCache> {
+        let mut module = new_module()?;
+        let helpers = Helpers::declare(&mut module)?;
+        Ok(JitCache {
+            ctx: module.make_context(),
+            module: ManuallyDrop::new(module),
+            helpers,
+            func_ctx: FunctionBuilderContext::new(),
+            blocks: Vec::new(),
+            starts: BTreeMap::new(),
+            next: None,
+            functions: 0,
+            compiled: 0,
+        })
+    }
+
+    /// Blocks compiled and kept
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Whether no blocks are kept
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// Blocks compiled since the cache was made, counting ones compiled again
+    pub fn blocks_compiled(&self) -> u64 {
+        self.compiled
+    }
+
+    /// Forget every block, e.g. after loading a save state
+    pub fn clear(&mut self) {
+        self.blocks.clear();
+        self.starts.clear();
+        self.next = None;
+    }
+
+    // Free the code of every block along with the module it was compiled
+    // into. If a new module can't be made the old one is kept.
+    fn reset(&mut self) {
+        self.clear();
+        let fresh = new_module().and_then(|mut module| {
+            let helpers = Helpers::declare(&mut module)?;
+            Ok((module, helpers))
+        });
+        match fresh {
+            Ok((module, helpers)) => {
+                let old = mem::replace(&mut self.module, ManuallyDrop::new(module));
+                // Nothing points into it now the blocks are gone
+                unsafe { ManuallyDrop::into_inner(old).free_memory() };
+                self.helpers = helpers;
+                self.functions = 0;
+            },
+            Err(e) => warn!(target: "cpu", "couldn't make a new JIT module: {}", e),
+        }
+    }
+
+    fn compile(&mut self, block: &Block, thumb: bool) -> GbaResult<BlockFn> {
+        let ptr = self.module.target_config().pointer_type();
+        let mut sig = Signature::new(self.module.isa().default_call_conv());
+        sig.params.push(AbiParam::new(ptr));
+        sig.params.push(AbiParam::new(types::I32));
+        sig.params.push(AbiParam::new(types::I32));
+        sig.returns.push(AbiParam::new(types::I32));
+        let id = self.module.declare_anonymous_function(&sig).map_err(jit_error)?;
+
+        self.module.clear_context(&mut self.ctx);
+        self.ctx.func.signature = sig;
+        {
+            let helpers = HelperRefs {
+                set_pc: self.module.declare_func_in_func(self.helpers.set_pc, &mut self.ctx.func),
+                cpsr: self.module.declare_func_in_func(self.helpers.cpsr, &mut self.ctx.func),
+                read_reg: self.module.declare_func_in_func(self.helpers.read_reg,
+                                                           &mut self.ctx.func),
+                write_reg: self.module.declare_func_in_func(self.helpers.write_reg,
+                                                            &mut self.ctx.func),
+                undefined: self.module.declare_func_in_func(self.helpers.undefined,
+                                                            &mut self.ctx.func),
+            };
+            let mut b = FunctionBuilder::new(&mut self.ctx.func, &mut self.func_ctx);
+            let entry = b.create_block();
+            b.append_block_params_for_function_params(entry);
+            b.switch_to_block(entry);
+            let ctx = b.block_params(entry)[0];
+            let index = b.block_params(entry)[1];
+            let limit = b.block_params(entry)[2];
+
+            // The index is always in the block
+            let exit = b.create_block();
+            let mut switch = Switch::new();
+            let starts: Vec<_> = block.ops().iter().map(|_| b.create_block()).collect();
+            for (i, &start) in starts.iter().enumerate() {
+                switch.set_entry(i as u128, start);
+            }
+            switch.emit(&mut b, index, exit);
+
+            let size = if thumb { 2 } else { 4 };
+            for (i, op) in block.ops().iter().enumerate() {
+                let addr = (block.start() + i * size) as u32;
+                b.switch_to_block(starts[i]);
+                // Run when this one is done
+                let last = b.ins().iconst(types::I32, i as i64 + 1);
+                let ran = b.ins().isub(last, index);
+
+                match *op {
+                    Op::Branch(ref instr) => compile_branch(&mut b, &helpers, ctx, instr, addr),
+                    Op::Coprocessor(ref instr) =>
+                        compile_coprocessor(&mut b, &helpers, ctx, instr, addr),
+                    Op::LoadAddress(ref instr) => {
+                        compile_load_address(&mut b, &helpers, ctx, instr, addr);
+                        // Carry on into the next one unless at the limit
+                        if let Some(&following) = starts.get(i + 1) {
+                            let stop = b.create_block();
+                            let at_limit = b.ins().icmp(IntCC::Equal, ran, limit);
+                            b.ins().brif(at_limit, stop, &[], following, &[]);
+                            b.switch_to_block(stop);
+                        }
+                        set_pc(&mut b, &helpers, ctx, addr.wrapping_add(size as u32));
+                    },
+                }
+                b.ins().return_(&[ran]);
+            }
+
+            b.switch_to_block(exit);
+            let none = const32(&mut b, 0);
+            b.ins().return_(&[none]);
+            b.seal_all_blocks();
+            b.finalize();
+        }
+
+        self.module.define_function(id, &mut self.ctx).map_err(jit_error)?;
+        self.module.clear_context(&mut self.ctx);
+        self.module.finalize_definitions().map_err(jit_error)?;
+        self.functions += 1;
+        let code = self.module.get_finalized_function(id);
+        // Compiled with the signature BlockFn has
+        Ok(unsafe { mem::transmute::<*const u8, BlockFn>(code) })
+    }
+
+    // Decode and compile the block starting at pc. A block the compiler
+    // fails on is left to the interpreter.
+    fn build(&mut self, pc: Address, thumb: bool, mem: &Memory) -> JitBlock {
+        let block = Block::decode(pc, thumb, mem);
+        let code = if block.ops().is_empty() {
+            None
+        }
+        else {
+            match self.compile(&block, thumb) {
+                Ok(code) => Some(code),
+                Err(e) => {
+                    warn!(target: "cpu", "interpreting the block at {:#010x}: {}", pc, e);
+                    None
+                },
+            }
+        };
+        self.compiled += 1;
+        JitBlock {
+            start: pc,
+            generation: block.generation(),
+            len: block.ops().len(),
+            fetch_cycles: Memory::fixed_fetch_cycles(pc, if thumb { 2 } else { 4 }),
+            code,
+        }
+    }
+
+    // The block starting at pc, compiling it if it is new or stale
+    fn block_at(&mut self, pc: Address, thumb: bool, mem: &Memory) -> usize {
+        if self.functions >= MAX_FUNCTIONS {
+            self.reset();
+        }
+
+        match self.starts.get(&(pc, thumb)) {
+            Some(&i) if self.blocks[i].generation == mem.code_generation(pc) => return i,
+            Some(&i) => {
+                self.blocks[i] = self.build(pc, thumb, mem);
+                return i;
+            },
+            None => {},
+        }
+
+        if self.blocks.len() >= MAX_BLOCKS {
+            self.reset();
+        }
+        let block = self.build(pc, thumb, mem);
+        self.blocks.push(block);
+        self.starts.insert((pc, thumb), self.blocks.len() - 1);
+        self.blocks.len() - 1
+    }
+
+    // Run from the PC for up to limit instructions. In bulk their fetches
+    // are charged rather than made. Returns the address of the last one.
+    fn execute(&mut self, cpu: &mut ARM7, mem: &mut Memory, limit: u32, bulk: bool) -> Address {
+        let pc = cpu.pc() as Address;
+        let thumb = cpu.is_thumb();
+
+        // Carry on through the block the last instruction came from if it
+        // follows on and is still fresh, otherwise start a block here
+        let (i, index) = match self.next.take() {
+            Some(cursor) if cursor.pc == pc && cursor.thumb == thumb &&
+                            self.blocks[cursor.block].generation ==
+                            mem.code_generation(self.blocks[cursor.block].start) =>
+                (cursor.block, cursor.index),
+            _ => (self.block_at(pc, thumb, mem), 0),
+        };
+
+        let block = &self.blocks[i];
+        let code = match block.code {
+            Some(code) if index < block.len => code,
+            // Let the interpreter deal with what couldn't be compiled
+            _ => {
+                cpu.step(mem);
+                return pc;
+            },
+        };
+        let size = if thumb { 2 } else { 4 };
+        if !bulk {
+            if thumb {
+                mem.fetch::<TIType>(pc);
+            }
+            else {
+                mem.fetch::<IType>(pc);
+            }
+        }
+        let mut ctx = JitContext {
+            cpu: &mut *cpu,
+            mem: &mut *mem,
+        };
+        let ran = code(&mut ctx, index as u32, limit) as usize;
+
+        // Nothing else touches the bus in a block, so in bulk its fetches
+        // are charged afterwards without being made
+        let next = pc + ran * size;
+        if bulk {
+            match block.fetch_cycles {
+                Some(cycles) => mem.charge_fetches(cycles * ran as u32, next),
+                None => for addr in (pc..next).step_by(size) {
+                    mem.charge(addr, size, true);
+                },
+            }
+        }
+        if index + ran < block.len {
+            self.next = Some(Cursor {
+                block: i,
+                index: index + ran,
+                pc: next,
+                thumb,
+            });
+        }
+        next - size
+    }
+
+    /// Execute the instruction at the PC, as the interpreter's step would
+    pub fn step(&mut self, cpu: &mut ARM7, mem: &mut Memory) {
+        self.execute(cpu, mem, 1, false);
+    }
+
+    /// Execute from the PC to the exit of its block in one call, returning
+    /// the address of the last instruction run. With anything watching the
+    /// bus this is just a step.
+    pub fn run(&mut self, cpu: &mut ARM7, mem: &mut Memory) -> RType {
+        let last = if mem.is_plain_bus() {
+            self.execute(cpu, mem, u32::MAX, true)
+        }
+        else {
+            self.execute(cpu, mem, 1, false)
+        };
+        last as RType
+    }
+}
+
+impl Drop for JitCache {
+    fn drop(&mut self) {
+        self.blocks.clear();
+        // Nothing can call into the code once the blocks are gone
+        unsafe { ManuallyDrop::take(&mut self.module).free_memory() };
+    }
+}
+
+// Only the number of blocks is worth showing
+impl fmt::Debug for JitCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write![f, "JitCache {{ blocks: {}, compiled: {} }}", self.blocks.len(), self.compiled]
+    }
+}
+
+/// The JIT as a core of its own, e.g. to run in lockstep against the
+/// interpreter
+#[derive(Debug)]
+pub struct JitCore {
+    cpu: ARM7,
+    cache: JitCache,
+}
+
+impl JitCore {
+    /// Run cpu through a new, empty JIT cache
+    pub fn new(cpu: ARM7) -> GbaResult<JitCore> {
+        Ok(JitCore {
+            cpu,
+            cache: JitCache::new()?,
+        })
+    }
+
+    /// The CPU
+    pub fn cpu(&self) -> &ARM7 {
+        &self.cpu
+    }
+
+    /// The JIT cache
+    pub fn cache(&self) -> &JitCache {
+        &self.cache
+    }
+}
+
+impl Core for JitCore {
+    fn step(&mut self, mem: &mut Memory) {
+        self.cache.step(&mut self.cpu, mem);
+    }
+
+    fn state(&self) -> CoreState {
+        self.cpu.state()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gba_cpu::compare::Lockstep;
+
+    use super::*;
+
+    fn cpu_at(pc: RType, thumb: bool, flags: RType) -> ARM7 {
+        let mut cpu = ARM7::default();
+        cpu.skip_bios();
+        cpu.set_flag_bits(flags);
+        if thumb {
+            cpu.set_thumb();
+        }
+        cpu.set_pc(pc);
+        cpu
+    }
+
+    // Every THUMB load address form, from word and halfword addresses, to
+    // the end of the block and on into the interpreter
+    #[test]
+    fn thumb_matches_the_interpreter() {
+        let mut mem = Memory::blank();
+        let code = [0xA001, 0xA7FF, 0xA801, 0xAFFF, 0xAD00, 0xA3FF];
+        for (i, &instr) in code.iter().enumerate() {
+            mem.write16::<u16>(0x03000000 + i * 2, instr);
+        }
+
+        let jit = JitCore::new(cpu_at(0x03000000, true, 0)).unwrap();
+        let mut lockstep = Lockstep::new(jit, cpu_at(0x03000000, true, 0));
+        if let Err(div) = lockstep.run(&mut mem, code.len() as u64 + 1) {
+            panic!("{}", div);
+        }
+        assert_eq!(lockstep.primary().cache().blocks_compiled(), 2);
+    }
+
+    // A coprocessor instruction under every condition, against every
+    // combination of flags
+    #[test]
+    fn conditions_match_the_interpreter() {
+        for cond in 0..16 {
+            for flags in 0..16 {
+                let mut mem = Memory::blank();
+                mem.write32::<u32>(0x03000000, cond << 28 | 0x0E000000);
+
+                let jit = JitCore::new(cpu_at(0x03000000, false, flags)).unwrap();
+                let mut lockstep = Lockstep::new(jit, cpu_at(0x03000000, false, flags));
+                if let Err(div) = lockstep.step(&mut mem) {
+                    panic!("cond {:x}, flags {:04b}: {}", cond, flags, div);
+                }
+            }
+        }
+    }
+
+    // A branch and a trapping coprocessor instruction both end blocks
+    #[test]
+    fn arm_matches_the_interpreter() {
+        let mut mem = Memory::blank();
+        mem.write32::<u32>(0x03000000, 0xEA000000);
+        mem.write32::<u32>(0x03000004, 0xEE000000);
+
+        let jit = JitCore::new(cpu_at(0x03000000, false, 0)).unwrap();
+        let mut lockstep = Lockstep::new(jit, cpu_at(0x03000000, false, 0));
+        if let Err(div) = lockstep.run(&mut mem, 2) {
+            panic!("{}", div);
+        }
+        assert_eq!(lockstep.primary().cpu().pc(), 0x00000004);
+        assert_eq!(lockstep.primary().cache().blocks_compiled(), 2);
+    }
+
+    // bl to a beq, which is taken with Z set, then b back to the start
+    #[test]
+    fn branches_match_the_interpreter() {
+        for &flags in [0b0000, 0b0100].iter() {
+            let mut mem = Memory::blank();
+            mem.write32::<u32>(0x03000000, 0xEB000000);
+            mem.write32::<u32>(0x03000008, 0x0A000000);
+            mem.write32::<u32>(0x0300000C, 0xEAFFFFFB);
+            mem.write32::<u32>(0x03000010, 0xEAFFFFFA);
+
+            let jit = JitCore::new(cpu_at(0x03000000, false, flags)).unwrap();
+            let mut lockstep = Lockstep::new(jit, cpu_at(0x03000000, false, flags));
+            if let Err(div) = lockstep.run(&mut mem, 6) {
+                panic!("flags {:04b}: {}", flags, div);
+            }
+            assert_eq!(lockstep.primary().cpu().pc(), 0x03000000);
+            assert_eq!(lockstep.primary().cpu().reg(LINK).read(), 0x03000004);
+        }
+    }
+
+    // Eight add r0, pc, #4 and the end of the block, run in one call
+    fn runs_like_stepping<F: Fn() -> Memory>(new_mem: F, pc: RType) {
+        let mut mem = new_mem();
+        let mut cpu = cpu_at(pc, true, 0);
+        let mut cycles = 0;
+        for _ in 0..8 {
+            cpu.step(&mut mem);
+            cycles += mem.take_cycles();
+        }
+
+        let mut mem = new_mem();
+        let mut jit_cpu = cpu_at(pc, true, 0);
+        let mut cache = JitCache::new().unwrap();
+        assert_eq!(cache.run(&mut jit_cpu, &mut mem), pc + 14);
+        assert_eq!(jit_cpu.state(), cpu.state());
+        assert_eq!(mem.take_cycles(), cycles);
+    }
+
+    #[test]
+    fn runs_whole_blocks() {
+        let code: Vec<u8> = [0x01, 0xA0].iter().cycle().take(16).cloned().collect();
+        // Counted in the compiled code
+        runs_like_stepping(|| {
+            let mut mem = Memory::blank();
+            for (i, &b) in code.iter().enumerate() {
+                mem.write8::<u8>(0x02000000 + i, b);
+            }
+            mem
+        }, 0x02000000);
+        // Through the prefetch unit
+        runs_like_stepping(|| Memory::with_rom_bytes(&code).unwrap(), 0x08000000);
+    }
+
+    // add r0, pc, #4 twice in IWRAM, then rewritten to add r0, pc, #8
+    #[test]
+    fn writes_to_ram_recompile_blocks() {
+        let mut mem = Memory::blank();
+        let mut cpu = cpu_at(0x03000000, true, 0);
+        let mut cache = JitCache::new().unwrap();
+        mem.write16::<u16>(0x03000000, 0xA001);
+        mem.write16::<u16>(0x03000002, 0xA001);
+
+        for _ in 0..2 {
+            cpu.set_pc(0x03000000);
+            cache.step(&mut cpu, &mut mem);
+            cache.step(&mut cpu, &mut mem);
+        }
+        assert_eq!(cache.blocks_compiled(), 1);
+        assert_eq!(cpu.reg(0).read(), 0x03000008);
+
+        mem.write16::<u16>(0x03000002, 0xA002);
+        cpu.set_pc(0x03000000);
+        cache.step(&mut cpu, &mut mem);
+        cache.step(&mut cpu, &mut mem);
+        assert_eq!(cache.blocks_compiled(), 2);
+        assert_eq!(cpu.reg(0).read(), 0x0300000C);
+    }
+
+    #[test]
+    fn reset_frees_the_module() {
+        let mut mem = Memory::blank();
+        let mut cpu = cpu_at(0x03000000, true, 0);
+        let mut cache = JitCache::new().unwrap();
+        mem.write16::<u16>(0x03000000, 0xA001);
+        cache.step(&mut cpu, &mut mem);
+        cache.reset();
+        assert!(cache.is_empty());
+
+        cpu.set_pc(0x03000000);
+        cache.step(&mut cpu, &mut mem);
+        assert_eq!(cpu.reg(0).read(), 0x03000008);
+        assert_eq!(cache.blocks_compiled(), 2);
+    }
+}
@@ -1,20 +1,59 @@
+#[cfg(test)]
+#[macro_use]
+mod test_dsl;
+
+/// Arithmetic and shifts shared by ARM and THUMB
+pub mod alu;
+/// The register file, modes and exceptions
 pub mod arm_cpu;
+/// Decoded ARM instructions
 pub mod arm_instr;
+/// Decoded instructions cached by block
+pub mod block_cache;
+/// Running two cores in lockstep to find where they differ
+pub mod compare;
+/// Which ARMv4T encodings the decoder handles
+pub mod coverage;
+/// The disassembler
+pub mod disasm;
+/// The Cranelift JIT
+#[cfg(feature = "jit")]
+pub mod jit;
+/// A single CPU register
 pub mod register;
+/// Decoded THUMB instructions
+pub mod thumb_instr;
 
 pub use gba_mem::Memory;
-pub use gba_cpu::arm_cpu::ARM7;
+pub use gba_cpu::arm_cpu::{ARM7, CoreState, UndefinedPolicy};
 
+/// A register's value
 pub type RType = u32;
+/// An ARM instruction
 pub type IType = u32;
+/// A register's value, signed
 pub type SIType = i32;
+/// A THUMB instruction
 pub type TIType = u16;
 
-// Common interface for executing and loading instructions
+/// Common interface for executing and loading instructions
 pub trait Instruction {
+    /// Core it executes on
     type CPU;
+    /// Encoded form of the instruction
     type Instr;
 
+    /// Decode an encoded instruction
     fn decode(instr: Self::Instr) -> Self;
+    /// Execute on cpu, accessing memory through mem
     fn execute(&self, cpu: &mut Self::CPU, mem: &mut Memory);
 }
+
+/// Common interface for CPU core implementations, so the interpreter and
+/// faster cores can be swapped or run against each other
+pub trait Core {
+    /// Execute a single instruction
+    fn step(&mut self, mem: &mut Memory);
+    /// Copy of the register file
+    fn state(&self) -> CoreState;
+}
@@ -1,10 +1,16 @@
-use std::fmt;
+use core::fmt;
+use gba_bits::Bits;
 use gba_cpu::RType;
 
+/// A 32-bit CPU register
 #[derive(Copy, Clone, Debug, Default)]
 pub struct Register(RType);
 
+impl_save_state!(Register { 0 });
+impl_serde_via_state!(Register);
+
 impl Register {
+    /// The whole value
     pub fn read(&self) -> RType {
         self.0
     }
@@ -25,6 +31,7 @@ impl Register {
     //     }
     // }
 
+    /// Replace the whole value
     pub fn write(&mut self, val: RType) {
         self.0 = val
     }
@@ -41,21 +48,41 @@ impl Register {
     //     }
     // }
 
+    /// The bits in mask
     pub fn read_masked(&self, mask: RType) -> RType {
         self.0 & mask
     }
 
+    /// Turn on the bits of val in mask
     pub fn set(&mut self, mask: RType, val: RType) {
         self.0 |= val & mask
     }
 
+    /// Turn off the bits of val in mask
     pub fn reset(&mut self, mask: RType, val: RType) {
         self.0 &= !(val & mask)
     }
 
+    /// Flip the bits of val in mask
     pub fn toggle(&mut self, mask: RType, val: RType) {
         self.0 ^= val & mask
     }
+
+    /// Replace the bits in mask with those of val, unlike set and reset
+    /// which can only turn bits on or off
+    pub fn write_masked(&mut self, mask: RType, val: RType) {
+        self.0.write_masked(mask, val)
+    }
+
+    /// The width bits starting shift bits up
+    pub fn field(&self, shift: u32, width: u32) -> RType {
+        self.0.field(shift, width)
+    }
+
+    /// Replace the width bits starting shift bits up
+    pub fn set_field(&mut self, shift: u32, width: u32, val: RType) {
+        self.0.set_field(shift, width, val)
+    }
 }
 
 impl fmt::Display for Register {
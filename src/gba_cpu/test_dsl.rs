@@ -0,0 +1,174 @@
+// Terse per-instruction tests. Each test gives the registers, flags and
+// memory to start from, the encoded instructions to run and what should be
+// different afterwards:
+//
+//     cpu_test!(add_pc_relative: thumb [0xA001] {
+//         pc = 0x03000002;
+//     } => {
+//         r0 = 0x03000008;
+//     });
+//
+// Code is placed in IWRAM at pc, which defaults to CODE_BASE, and each
+// instruction runs as one step. Registers are named r0-r15, sp, lr or pc
+// and resolve through the current mode's banks; flags are written as NZCV
+// with upper case for set, e.g. "nZCv". mem[addr] reads and writes words.
+
+use gba_cpu::arm_cpu::{ARM7, R13, R14, R15};
+use gba_cpu::{Core, IType, RType, TIType};
+use gba_mem::{Address, Memory};
+use prelude::*;
+
+pub const CODE_BASE: RType = 0x03000000;
+
+const FLAG_NAMES: [char; 4] = ['n', 'z', 'c', 'v'];
+
+fn reg_index(name: &str) -> i8 {
+    match name {
+        "sp" => R13,
+        "lr" => R14,
+        "pc" => R15,
+        _ => name[1..].parse().expect("unknown register in cpu test"),
+    }
+}
+
+fn flags_of(cpu: &ARM7) -> String {
+    let set = [cpu.is_neg_lt(), cpu.is_zero(), cpu.is_carry(), cpu.is_overflow()];
+    FLAG_NAMES.iter().zip(set.iter())
+        .map(|(&c, &set)| if set { c.to_ascii_uppercase() } else { c })
+        .collect()
+}
+
+pub enum Code {
+    Arm(Vec<IType>),
+    Thumb(Vec<TIType>),
+}
+
+pub struct CpuTest {
+    pub cpu: ARM7,
+    pub mem: Memory,
+    code: Code,
+}
+
+impl CpuTest {
+    pub fn arm(code: &[IType]) -> CpuTest {
+        CpuTest::new(Code::Arm(code.to_vec()))
+    }
+
+    pub fn thumb(code: &[TIType]) -> CpuTest {
+        let mut test = CpuTest::new(Code::Thumb(code.to_vec()));
+        test.cpu.set_thumb();
+        test
+    }
+
+    fn new(code: Code) -> CpuTest {
+        let mut cpu = ARM7::default();
+        cpu.skip_bios();
+        cpu.set_pc(CODE_BASE);
+        CpuTest {
+            cpu,
+            mem: Memory::blank(),
+            code,
+        }
+    }
+
+    pub fn set_reg(&mut self, name: &str, val: RType) {
+        self.cpu.reg_mut(reg_index(name)).write(val);
+    }
+
+    pub fn set_flags(&mut self, flags: &str) {
+        self.cpu.reset_cond();
+        for c in flags.chars().filter(|c| c.is_ascii_uppercase()) {
+            match c {
+                'N' => self.cpu.set_neg_lt(),
+                'Z' => self.cpu.set_zero(),
+                'C' => self.cpu.set_carry(),
+                'V' => self.cpu.set_overflow(),
+                _ => panic!("unknown flag {} in cpu test", c),
+            }
+        }
+    }
+
+    #[allow(dead_code)] // Until load and store instructions are decoded
+    pub fn set_mem32(&mut self, addr: Address, val: u32) {
+        self.mem.write32::<u32>(addr, val);
+    }
+
+    // Copy the code to the PC and execute each instruction once
+    pub fn run(mut self) -> CpuTest {
+        let pc = self.cpu.pc() as Address;
+        let steps = match self.code {
+            Code::Arm(ref code) => {
+                for (i, &instr) in code.iter().enumerate() {
+                    self.mem.write32::<IType>(pc + 4 * i, instr);
+                }
+                code.len()
+            },
+            Code::Thumb(ref code) => {
+                for (i, &instr) in code.iter().enumerate() {
+                    self.mem.write16::<TIType>(pc + 2 * i, instr);
+                }
+                code.len()
+            },
+        };
+        for _ in 0..steps {
+            self.cpu.step(&mut self.mem);
+        }
+        self
+    }
+
+    pub fn check_reg(&self, name: &str, val: RType) {
+        let actual = self.cpu.reg(reg_index(name)).read();
+        assert!(actual == val, "{} is {:#010x}, expected {:#010x}", name, actual, val);
+    }
+
+    pub fn check_flags(&self, flags: &str) {
+        assert_eq!(flags_of(&self.cpu), flags, "flags");
+    }
+
+    #[allow(dead_code)]
+    pub fn check_mem32(&self, addr: Address, val: u32) {
+        let actual = self.mem.peek::<u32>(addr);
+        assert!(actual == val, "[{:#010x}] is {:#010x}, expected {:#010x}", addr, actual, val);
+    }
+}
+
+macro_rules! cpu_test {
+    ($name:ident: $state:ident [$($instr:expr),*] { $($given:tt)* } => { $($expect:tt)* }) => {
+        #[test]
+        fn $name() {
+            #[allow(unused_mut)]
+            let mut test = ::gba_cpu::test_dsl::CpuTest::$state(&[$($instr),*]);
+            cpu_test!(@given test $($given)*);
+            let test = test.run();
+            cpu_test!(@expect test $($expect)*);
+        }
+    };
+
+    (@given $t:ident) => {};
+    (@given $t:ident flags $flags:expr; $($rest:tt)*) => {
+        $t.set_flags($flags);
+        cpu_test!(@given $t $($rest)*);
+    };
+    (@given $t:ident mem[$addr:expr] = $val:expr; $($rest:tt)*) => {
+        $t.set_mem32($addr, $val);
+        cpu_test!(@given $t $($rest)*);
+    };
+    (@given $t:ident $reg:ident = $val:expr; $($rest:tt)*) => {
+        $t.set_reg(stringify!($reg), $val);
+        cpu_test!(@given $t $($rest)*);
+    };
+
+    (@expect $t:ident) => {};
+    (@expect $t:ident flags $flags:expr; $($rest:tt)*) => {
+        $t.check_flags($flags);
+        cpu_test!(@expect $t $($rest)*);
+    };
+    (@expect $t:ident mem[$addr:expr] = $val:expr; $($rest:tt)*) => {
+        $t.check_mem32($addr, $val);
+        cpu_test!(@expect $t $($rest)*);
+    };
+    (@expect $t:ident $reg:ident = $val:expr; $($rest:tt)*) => {
+        $t.check_reg(stringify!($reg), $val);
+        cpu_test!(@expect $t $($rest)*);
+    };
+}
@@ -0,0 +1,124 @@
+use core::fmt;
+
+use gba_cpu::{IType, Instruction, RType, TIType, ARM7};
+use gba_mem::{Address, Memory};
+
+// THUMB instruction formats from:
+// http://www.atmel.com/Images/DDI0029G_7TDMI_R3_trm.pdf
+// section 5
+
+// Implementation of load address (format 12)
+// Instruction description from:
+// http://www.atmel.com/Images/DDI0029G_7TDMI_R3_trm.pdf
+// section 5.12; page 5-30 to 5-31
+const LOAD_ADDR_MASK:  TIType = 0xF000;
+const LOAD_ADDR_IDENT: TIType = 0xA000;
+const LOAD_ADDR_SP:    TIType = 0x0800;
+const LOAD_ADDR_RD:    TIType = 0x0700;
+const LOAD_ADDR_IMM:   TIType = 0x00FF;
+
+/// Thumb format 12, ADD Rd, PC/SP, #imm
+#[derive(Clone, Copy, Debug)]
+pub struct LoadAddress {
+    rd: i8,
+    sp: bool, // Relative to SP rather than PC
+    off: RType, // Word offset already scaled to bytes
+}
+
+impl LoadAddress {
+    /// Destination register
+    pub fn rd(&self) -> i8 {
+        self.rd
+    }
+
+    /// Whether the base is SP rather than PC
+    pub fn is_sp_relative(&self) -> bool {
+        self.sp
+    }
+
+    /// Offset in bytes
+    pub fn offset(&self) -> RType {
+        self.off
+    }
+
+    /// Bit 1 of the PC is forced to zero so the result is word aligned
+    /// even when the instruction sits at a halfword address. SP is used as
+    /// is: it is expected to be word aligned already and no alignment is
+    /// applied to it.
+    pub fn address(&self, cpu: &ARM7) -> RType {
+        let base = if self.sp {
+            cpu.sp()
+        }
+        else {
+            // The PC has already moved past this instruction, and reads as
+            // its address + 4
+            cpu.pc().wrapping_add(2) & !2
+        };
+        base.wrapping_add(self.off)
+    }
+}
+
+impl Instruction for LoadAddress {
+    type CPU = ARM7;
+    type Instr = TIType;
+
+    fn decode(instr: TIType) -> LoadAddress {
+        LoadAddress {
+            rd: ((instr & LOAD_ADDR_RD) >> 8) as i8,
+            sp: instr & LOAD_ADDR_SP != 0,
+            off: ((instr & LOAD_ADDR_IMM) as RType) << 2,
+        }
+    }
+
+    // Flags are not affected
+    fn execute(&self, cpu: &mut Self::CPU, _mem: &mut Memory) {
+        let addr = self.address(cpu);
+        cpu.write_reg(self.rd, addr);
+    }
+}
+
+impl fmt::Display for LoadAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let base = if self.sp { "sp" } else { "pc" };
+        write!(f, "add\tr{}, {}, #{:#x}", self.rd, base, self.off)
+    }
+}
+
+/// Fetch, decode and execute the THUMB instruction at the PC
+pub fn step(cpu: &mut ARM7, mem: &mut Memory) {
+    let pc = cpu.pc();
+    let instr = mem.fetch::<TIType>(pc as Address);
+    cpu.inc_pc();
+    if instr & LOAD_ADDR_MASK == LOAD_ADDR_IDENT {
+        LoadAddress::decode(instr).execute(cpu, mem);
+    }
+    else {
+        cpu.undefined_instruction(pc, instr as IType);
+    }
+}
+
+/// True when step() knows how to handle the instruction
+pub fn decodes(instr: TIType) -> bool {
+    instr & LOAD_ADDR_MASK == LOAD_ADDR_IDENT
+}
+
+#[cfg(test)]
+mod tests {
+    // add r0, pc, #4 from a word and a halfword aligned address
+    cpu_test!(load_address_pc: thumb [0xA001] {} => {
+        r0 = 0x03000008;
+        pc = 0x03000002;
+    });
+    cpu_test!(load_address_pc_halfword: thumb [0xA001] { pc = 0x03000002; } => {
+        r0 = 0x03000008;
+    });
+
+    // add r7, sp, #0x3FC leaves the flags alone
+    cpu_test!(load_address_sp: thumb [0xAFFF] {
+        sp = 0x03007F00;
+        flags "NzCv";
+    } => {
+        r7 = 0x030082FC;
+        flags "NzCv";
+    });
+}
@@ -0,0 +1,163 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use gba_cpu::RType;
+use gba_debug::trace::{TraceEntry, TraceFormat, Tracer};
+use gba_error::GbaResult;
+use gba_mem::Address;
+use gba_system::Gba;
+
+// Memory code can run from, see:
+// http://problemkaputt.de/gbatek.htm#gbamemorymap
+const EXEC_REGIONS: [(Address, Address); 5] = [
+    (0x00000000, 0x00003FFF), // BIOS
+    (0x02000000, 0x0203FFFF), // On-board work RAM
+    (0x03000000, 0x03007FFF), // On-chip work RAM
+    (0x06000000, 0x06017FFF), // VRAM, used by a few demos
+    (0x08000000, 0x0DFFFFFF), // Game pak ROM
+];
+
+fn is_executable(pc: RType) -> bool {
+    let pc = pc as Address;
+    EXEC_REGIONS.iter().any(|&(lo, hi)| pc >= lo && pc <= hi)
+}
+
+/// Ways a game goes off the rails that are easy to spot from the PC
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Crash {
+    /// Jumped somewhere code can't run from, usually through a bad pointer
+    BadPc {
+        /// Address of the jump
+        from: RType,
+        /// Where it went
+        to: RType,
+    },
+    /// Jumped to the reset vector, usually by calling a null function pointer
+    NullJump {
+        /// Address of the jump
+        from: RType,
+    },
+}
+
+impl Crash {
+    /// Check the instruction at from, which has just executed
+    pub fn detect(from: RType, gba: &Gba) -> Option<Crash> {
+        let to = gba.cpu().pc();
+        if !is_executable(to) {
+            Some(Crash::BadPc { from, to })
+        }
+        else if to == 0 && from >= 0x4000 {
+            Some(Crash::NullJump { from })
+        }
+        else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for Crash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Crash::BadPc { from, to } =>
+                write![f, "jump from {:#010x} to non-code address {:#010x}", from, to],
+            Crash::NullJump { from } =>
+                write![f, "jump from {:#010x} to the reset vector", from],
+        }
+    }
+}
+
+/// Snapshots the machine when a watchpoint or crash fires, so a bug that
+/// takes hours to show up can be picked apart offline. Each capture is a
+/// save state plus a text file with the reason and the instructions that
+/// led up to it:
+///
+/// ```text
+/// <dir>/capture-<frame>-<cycles>.state
+/// <dir>/capture-<frame>-<cycles>.txt
+/// ```
+#[derive(Debug)]
+pub struct AutoCapture {
+    dir: PathBuf,
+    window: usize, // Instructions of trace kept
+    history: Rc<RefCell<VecDeque<TraceEntry>>>,
+    limit: usize, // Captures taken before giving up
+    taken: Vec<PathBuf>,
+}
+
+impl AutoCapture {
+    /// Capture into dir, keeping the last window traced instructions
+    pub fn new<P: AsRef<Path>>(dir: P, window: usize) -> AutoCapture {
+        AutoCapture {
+            dir: dir.as_ref().to_path_buf(),
+            window,
+            history: Rc::new(RefCell::new(VecDeque::with_capacity(window))),
+            limit: 1,
+            taken: Vec::new(),
+        }
+    }
+
+    /// Stop capturing after limit captures; by default only the first is kept
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+    }
+
+    /// Files written so far, one save state per capture
+    pub fn captures(&self) -> &[PathBuf] {
+        &self.taken
+    }
+
+    /// Replace the machine's tracer with one that keeps the trace window
+    pub fn install(&self, gba: &mut Gba) {
+        let history = self.history.clone();
+        let window = self.window;
+        gba.set_tracer(Tracer::new(move |entry| {
+            let mut history = history.borrow_mut();
+            if history.len() == window {
+                history.pop_front();
+            }
+            history.push_back(entry.clone());
+        }));
+    }
+
+    /// Write a capture of the machine as it is now. Returns the path of the
+    /// save state, or None once the limit has been reached.
+    pub fn capture(&mut self, gba: &Gba, reason: &str) -> GbaResult<Option<PathBuf>> {
+        if self.taken.len() >= self.limit {
+            return Ok(None);
+        }
+
+        fs::create_dir_all(&self.dir)?;
+        let base = self.dir.join(format!("capture-{}-{}", gba.frame(), gba.cycles()));
+        let state = base.with_extension("state");
+        fs::write(&state, gba.save_state())?;
+
+        let mut report = format!("{}\n\n{}\n\n", reason, gba);
+        for entry in self.history.borrow().iter() {
+            report.push_str(&entry.format(TraceFormat::Changes));
+            report.push('\n');
+        }
+        fs::write(base.with_extension("txt"), report)?;
+
+        self.taken.push(state.clone());
+        Ok(Some(state))
+    }
+
+    /// Execute one instruction unattended, capturing if it hit a watchpoint
+    /// or crashed
+    pub fn step(&mut self, gba: &mut Gba) -> GbaResult<Option<PathBuf>> {
+        let pc = gba.cpu().pc();
+        gba.step_instruction();
+
+        if let Some(hit) = gba.mem().take_watch_hit() {
+            return self.capture(gba, &hit.to_string());
+        }
+        if let Some(crash) = Crash::detect(pc, gba) {
+            return self.capture(gba, &crash.to_string());
+        }
+        Ok(None)
+    }
+}
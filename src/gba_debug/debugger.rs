@@ -0,0 +1,324 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::path::PathBuf;
+
+use gba_cheats::ram_search::RamSearch;
+use gba_cpu::arm_cpu::UndefinedInstr;
+use gba_cpu::disasm::{disasm_arm, disasm_thumb, disasm_thumb_bl, is_thumb_bl_prefix};
+use gba_cpu::{IType, RType, TIType};
+use gba_debug::capture::{AutoCapture, Crash};
+use gba_debug::irq_watch::{IrqHandlerWatch, IrqWatchEvent};
+use gba_mem::Address;
+use gba_mem::watch::WatchHit;
+use gba_system::Gba;
+
+/// Why the debugger handed control back
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    /// The requested instructions ran
+    Step,
+    /// Reached a breakpoint at this address
+    Breakpoint(RType),
+    /// An access hit a watchpoint
+    Watchpoint(WatchHit),
+    /// The IRQ handler was entered
+    IrqHandler(IrqWatchEvent),
+    /// The game crashed
+    Crash(Crash),
+    /// Under the break policy
+    Undefined(UndefinedInstr),
+    /// Gave up after the instruction limit
+    Limit,
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StopReason::Step => write![f, "stepped"],
+            StopReason::Breakpoint(pc) => write![f, "breakpoint at {:#010x}", pc],
+            StopReason::Watchpoint(hit) => write![f, "{}", hit],
+            StopReason::IrqHandler(_) => write![f, "entered the IRQ handler"],
+            StopReason::Crash(crash) => write![f, "{}", crash],
+            StopReason::Undefined(instr) => write![f, "{}", instr],
+            StopReason::Limit => write![f, "instruction limit reached"],
+        }
+    }
+}
+
+/// Execution control on top of Gba::step. Watchpoints live in Memory since
+/// only the bus sees the accesses; breakpoints are checked here between
+/// instructions.
+#[derive(Debug, Default)]
+pub struct Debugger {
+    breakpoints: BTreeSet<RType>,
+    irq_watch: IrqHandlerWatch,
+    capture: Option<AutoCapture>,
+    last_capture: Option<PathBuf>,
+    ram_search: Option<RamSearch>,
+}
+
+impl Debugger {
+    /// A debugger with nothing set
+    pub fn new() -> Debugger {
+        Debugger::default()
+    }
+
+    /// Returns false if there already was one at addr
+    pub fn add_breakpoint(&mut self, addr: RType) -> bool {
+        self.breakpoints.insert(addr)
+    }
+
+    /// Returns false if there wasn't one at addr
+    pub fn remove_breakpoint(&mut self, addr: RType) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    /// Breakpoint addresses, lowest first
+    pub fn breakpoints(&self) -> Vec<RType> {
+        self.breakpoints.iter().cloned().collect()
+    }
+
+    /// What the IRQ handler watch has seen
+    pub fn irq_watch(&self) -> &IrqHandlerWatch {
+        &self.irq_watch
+    }
+
+    /// The IRQ handler watch, for changing
+    pub fn irq_watch_mut(&mut self) -> &mut IrqHandlerWatch {
+        &mut self.irq_watch
+    }
+
+    /// Snapshot the machine whenever a watchpoint or crash stops it
+    pub fn set_capture(&mut self, gba: &mut Gba, capture: Option<AutoCapture>) {
+        match capture {
+            Some(ref capture) => capture.install(gba),
+            None => { gba.take_tracer(); },
+        }
+        self.capture = capture;
+    }
+
+    /// How watchpoint and crash stops are captured, if they are
+    pub fn capture(&self) -> Option<&AutoCapture> {
+        self.capture.as_ref()
+    }
+
+    /// Save state written by the last capture, if it hasn't been taken
+    pub fn take_last_capture(&mut self) -> Option<PathBuf> {
+        self.last_capture.take()
+    }
+
+    /// The RAM search in progress, kept between commands
+    pub fn ram_search(&self) -> Option<&RamSearch> {
+        self.ram_search.as_ref()
+    }
+
+    /// The RAM search in progress, for filtering
+    pub fn ram_search_mut(&mut self) -> Option<&mut RamSearch> {
+        self.ram_search.as_mut()
+    }
+
+    /// Start a RAM search, or drop the current one
+    pub fn set_ram_search(&mut self, search: Option<RamSearch>) {
+        self.ram_search = search;
+    }
+
+    /// The instruction at the PC, as shown when execution stops
+    pub fn current_instr(gba: &Gba) -> String {
+        let pc = gba.cpu().pc();
+        let mem = gba.mem();
+        if gba.cpu().is_thumb() {
+            let instr = mem.peek::<TIType>(pc as Address);
+            let text = if is_thumb_bl_prefix(instr) {
+                disasm_thumb_bl(instr, mem.peek::<TIType>(pc as Address + 2), pc)
+            }
+            else {
+                disasm_thumb(instr, pc)
+            };
+            format!("{:08x}:     {:04x}\t{}", pc, instr, text)
+        }
+        else {
+            let instr = mem.peek::<IType>(pc as Address);
+            format!("{:08x}: {:08x}\t{}", pc, instr, disasm_arm(instr, pc))
+        }
+    }
+
+    // Execute one instruction, returning why execution should stop there
+    // if it should
+    fn step_one(&mut self, gba: &mut Gba) -> Option<StopReason> {
+        let pc = gba.cpu().pc();
+        // A halted or idling CPU sits at the same PC without running
+        // anything there
+        let asleep = gba.is_asleep();
+        gba.mem().take_watch_hit();
+        gba.step_instruction();
+        if let Some(instr) = gba.cpu_mut().take_undefined() {
+            return Some(StopReason::Undefined(instr));
+        }
+
+        self.irq_watch.check_write(pc, gba.mem());
+        if let Some(hit) = gba.mem().take_watch_hit() {
+            self.auto_capture(gba, &hit.to_string());
+            return Some(StopReason::Watchpoint(hit));
+        }
+        if let Some(crash) = Crash::detect(pc, gba) {
+            self.auto_capture(gba, &crash.to_string());
+            return Some(StopReason::Crash(crash));
+        }
+
+        let next = gba.cpu().pc();
+        if let Some(event) = self.irq_watch.check_entry(next) {
+            return Some(StopReason::IrqHandler(event));
+        }
+        if self.breakpoints.contains(&next) && !asleep {
+            return Some(StopReason::Breakpoint(next));
+        }
+        None
+    }
+
+    fn auto_capture(&mut self, gba: &Gba, reason: &str) {
+        if let Some(ref mut capture) = self.capture {
+            match capture.capture(gba, reason) {
+                Ok(Some(path)) => self.last_capture = Some(path),
+                Ok(None) => {},
+                Err(e) => warn!(target: "debug", "failed to capture the machine: {}", e),
+            }
+        }
+    }
+
+    // Run until the PC reaches target, something stops execution, or limit
+    // instructions have run. The instruction at the PC always runs, so
+    // continuing from a breakpoint doesn't stop on it again.
+    fn run_to(&mut self, gba: &mut Gba, target: Option<RType>, limit: u64) -> StopReason {
+        for _ in 0..limit {
+            if let Some(reason) = self.step_one(gba) {
+                return reason;
+            }
+            if target == Some(gba.cpu().pc()) {
+                return StopReason::Step;
+            }
+        }
+        StopReason::Limit
+    }
+
+    /// Run until a breakpoint, watchpoint or the IRQ handler stops it
+    pub fn cont(&mut self, gba: &mut Gba, limit: u64) -> StopReason {
+        self.run_to(gba, None, limit)
+    }
+
+    /// Execute n instructions
+    pub fn step(&mut self, gba: &mut Gba, n: u64) -> StopReason {
+        for _ in 0..n {
+            if let Some(reason) = self.step_one(gba) {
+                return reason;
+            }
+        }
+        StopReason::Step
+    }
+
+    /// Step, running a call through to its return rather than into it
+    pub fn step_over(&mut self, gba: &mut Gba, limit: u64) -> StopReason {
+        let pc = gba.cpu().pc();
+        let ret = if gba.cpu().is_thumb() {
+            let instr = gba.mem().peek::<TIType>(pc as Address);
+            if is_thumb_bl_prefix(instr) { Some(pc.wrapping_add(4)) } else { None }
+        }
+        else {
+            // BL with any condition
+            let instr = gba.mem().peek::<IType>(pc as Address);
+            if instr & 0x0F000000 == 0x0B000000 { Some(pc.wrapping_add(4)) } else { None }
+        };
+
+        match ret {
+            Some(ret) => self.run_to(gba, Some(ret), limit),
+            None => self.step(gba, 1),
+        }
+    }
+
+    /// Run until the current function returns to its caller. This assumes
+    /// LR still holds the return address, which is true until the function
+    /// makes a call of its own and saves it on the stack.
+    pub fn step_out(&mut self, gba: &mut Gba, limit: u64) -> StopReason {
+        let lr = gba.cpu().lr();
+        self.run_to(gba, Some(lr & !1), limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gba_mem::bus_log::BusAccess;
+    use gba_mem::watch::{Watchpoint, WatchKind};
+
+    // Calls a function that returns by branching back to LR. The
+    // interpreter only runs branches so far, so the program's only bus
+    // accesses are its own fetches.
+    const PROGRAM: [u32; 6] = [
+        0xEAFFFFFF, // 08000000: b 0x08000004
+        0xEB000001, // 08000004: bl 0x08000010
+        0xEAFFFFFE, // 08000008: b 0x08000008
+        0xEAFFFFFE, // 0800000c: b 0x0800000c
+        0xEAFFFFFF, // 08000010: b 0x08000014
+        0xEAFFFFFB, // 08000014: b 0x08000008
+    ];
+
+    fn machine() -> Gba {
+        let mut rom = vec![0; 0x200];
+        for (i, instr) in PROGRAM.iter().enumerate() {
+            rom[i * 4..i * 4 + 4].copy_from_slice(&instr.to_le_bytes());
+        }
+        let mut gba = Gba::builder().rom(&rom).build().unwrap();
+        gba.skip_bios();
+        gba
+    }
+
+    #[test]
+    fn steps_over_into_and_out_of_calls() {
+        let mut gba = machine();
+        let mut dbg = Debugger::new();
+        assert_eq!(dbg.step(&mut gba, 1), StopReason::Step);
+        assert_eq!(gba.cpu().pc(), 0x08000004);
+        assert!(Debugger::current_instr(&gba).contains("bl"));
+
+        let mut over = machine();
+        dbg.step(&mut over, 1);
+        assert_eq!(dbg.step_over(&mut over, 100), StopReason::Step);
+        assert_eq!(over.cpu().pc(), 0x08000008);
+
+        assert_eq!(dbg.step(&mut gba, 1), StopReason::Step);
+        assert_eq!((gba.cpu().pc(), gba.cpu().lr()), (0x08000010, 0x08000008));
+        assert_eq!(dbg.step_out(&mut gba, 100), StopReason::Step);
+        assert_eq!(gba.cpu().pc(), 0x08000008);
+
+        // Spinning never reaches the return address
+        gba.cpu_mut().set_pc(0x0800000C);
+        assert_eq!(dbg.step_out(&mut gba, 100), StopReason::Limit);
+    }
+
+    #[test]
+    fn stops_at_breakpoints_and_watchpoints() {
+        let mut gba = machine();
+        let mut dbg = Debugger::new();
+        assert!(dbg.add_breakpoint(0x08000014));
+        assert!(!dbg.add_breakpoint(0x08000014));
+        assert!(dbg.add_breakpoint(0x08000004));
+        assert_eq!(dbg.breakpoints(), [0x08000004, 0x08000014]);
+
+        assert_eq!(dbg.cont(&mut gba, 100), StopReason::Breakpoint(0x08000004));
+        // Continuing runs the instruction at the breakpoint first
+        assert!(dbg.remove_breakpoint(0x08000004));
+        assert!(!dbg.remove_breakpoint(0x08000004));
+        assert_eq!(dbg.cont(&mut gba, 100), StopReason::Breakpoint(0x08000014));
+
+        let spin = Watchpoint::new(0x08000008, 4, WatchKind::Read);
+        let index = gba.mem_mut().add_watchpoint(spin);
+        let access = BusAccess { addr: 0x08000008, size: 4, val: 0xEAFFFFFE, write: false };
+        match dbg.cont(&mut gba, 100) {
+            StopReason::Watchpoint(hit) => assert_eq!((hit.index, hit.access), (index, access)),
+            other => panic!("expected the fetch to stop it, got {:?}", other),
+        }
+        assert_eq!(gba.cpu().pc(), 0x08000008);
+
+        gba.mem_mut().remove_watchpoint(index);
+        assert_eq!(dbg.cont(&mut gba, 100), StopReason::Limit);
+    }
+}
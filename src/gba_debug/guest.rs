@@ -0,0 +1,158 @@
+use gba_mem::{Address, Memory};
+
+/// Typed reads of game data for cheat tools and scripts. Everything goes
+/// through Memory::peek, so reading never disturbs the machine.
+#[derive(Clone, Copy, Debug)]
+pub struct GuestMem<'a> {
+    mem: &'a Memory,
+}
+
+impl<'a> GuestMem<'a> {
+    /// Read mem as the game sees it, without side effects
+    pub fn new(mem: &'a Memory) -> GuestMem<'a> {
+        GuestMem {
+            mem,
+        }
+    }
+
+    /// The byte at addr
+    pub fn read_u8(&self, addr: Address) -> u8 {
+        self.mem.peek::<u8>(addr)
+    }
+
+    /// The halfword at addr
+    pub fn read_u16(&self, addr: Address) -> u16 {
+        self.mem.peek::<u16>(addr)
+    }
+
+    /// The word at addr
+    pub fn read_u32(&self, addr: Address) -> u32 {
+        self.mem.peek::<u32>(addr)
+    }
+
+    /// A T laid out at addr
+    pub fn read<T: GuestRead>(&self, addr: Address) -> T {
+        T::read_from(self, addr)
+    }
+
+    /// len bytes from addr
+    pub fn read_bytes(&self, addr: Address, len: usize) -> Vec<u8> {
+        (0..len).map(|i| self.read_u8(addr + i)).collect()
+    }
+
+    /// count halfwords from addr
+    pub fn read_u16s(&self, addr: Address, count: usize) -> Vec<u16> {
+        (0..count).map(|i| self.read_u16(addr + i * 2)).collect()
+    }
+
+    /// count words from addr
+    pub fn read_u32s(&self, addr: Address, count: usize) -> Vec<u32> {
+        (0..count).map(|i| self.read_u32(addr + i * 4)).collect()
+    }
+
+    /// Consecutive structs, e.g. a party or inventory table
+    pub fn read_array<T: GuestRead>(&self, addr: Address, count: usize) -> Vec<T> {
+        (0..count).map(|i| self.read(addr + i * T::SIZE)).collect()
+    }
+
+    /// A NUL-terminated string of at most max bytes. Bytes are taken as
+    /// Latin-1; games with their own character sets should use read_bytes.
+    pub fn read_cstr(&self, addr: Address, max: usize) -> String {
+        (0..max).map(|i| self.read_u8(addr + i))
+            .take_while(|&b| b != 0)
+            .map(|b| b as char)
+            .collect()
+    }
+
+    /// A fixed-length string field, with any NUL padding dropped
+    pub fn read_str(&self, addr: Address, len: usize) -> String {
+        let mut text: String = self.read_bytes(addr, len).into_iter().map(|b| b as char).collect();
+        let end = text.trim_end_matches('\0').len();
+        text.truncate(end);
+        text
+    }
+}
+
+/// A value that can be read out of guest memory at a fixed size
+pub trait GuestRead: Sized {
+    /// Bytes
+    const SIZE: usize;
+
+    /// Read one from addr
+    fn read_from(mem: &GuestMem, addr: Address) -> Self;
+}
+
+macro_rules! def_guest_read_int {
+    ($ty:ty, $size:expr, $read:ident) => {
+        #[allow(trivial_numeric_casts)]
+        impl GuestRead for $ty {
+            const SIZE: usize = $size;
+
+            fn read_from(mem: &GuestMem, addr: Address) -> $ty {
+                mem.$read(addr) as $ty
+            }
+        }
+    };
+}
+
+def_guest_read_int!(u8,  1, read_u8);
+def_guest_read_int!(i8,  1, read_u8);
+def_guest_read_int!(u16, 2, read_u16);
+def_guest_read_int!(i16, 2, read_u16);
+def_guest_read_int!(u32, 4, read_u32);
+def_guest_read_int!(i32, 4, read_u32);
+
+impl<T: GuestRead, const N: usize> GuestRead for [T; N] {
+    const SIZE: usize = T::SIZE * N;
+
+    fn read_from(mem: &GuestMem, addr: Address) -> [T; N] {
+        ::std::array::from_fn(|i| T::read_from(mem, addr + i * T::SIZE))
+    }
+}
+
+/// Declare a struct laid out in guest memory, giving each field's offset
+/// from the start. The struct's size is where its last field ends.
+///
+/// ```ignore
+/// guest_struct! {
+///     pub struct Item {
+///         0x00 => id: u16,
+///         0x02 => count: u16,
+///         0x04 => name: [u8; 12],
+///     }
+/// }
+///
+/// let bag: Vec<Item> = gba.guest().read_array(BAG_ADDR, 20);
+/// ```
+#[macro_export]
+macro_rules! guest_struct {
+    ($(#[$attr:meta])* pub struct $name:ident {
+        $($off:expr => $field:ident: $ty:ty),* $(,)*
+    }) => {
+        $(#[$attr])*
+        #[derive(Clone, Debug, PartialEq, Eq)]
+        pub struct $name {
+            $(pub $field: $ty),*
+        }
+
+        impl $crate::gba_debug::guest::GuestRead for $name {
+            const SIZE: usize = {
+                let mut end = 0;
+                $(
+                    let field_end = $off + <$ty as $crate::gba_debug::guest::GuestRead>::SIZE;
+                    if field_end > end {
+                        end = field_end;
+                    }
+                )*
+                end
+            };
+
+            fn read_from(mem: &$crate::gba_debug::guest::GuestMem,
+                         addr: $crate::gba_mem::Address) -> $name {
+                $name {
+                    $($field: mem.read(addr + $off)),*
+                }
+            }
+        }
+    };
+}
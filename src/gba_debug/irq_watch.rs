@@ -0,0 +1,116 @@
+use gba_cpu::RType;
+use gba_mem::{Address, Memory};
+
+/// The BIOS IRQ vector jumps through the user handler pointer stored at the
+/// top of IWRAM, see:
+/// http://problemkaputt.de/gbatek.htm#biosramusage
+pub const IRQ_HANDLER_PTR: Address = 0x03007FFC;
+
+/// What the IRQ handler watch saw
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IrqWatchEvent {
+    /// The instruction at pc changed the handler pointer
+    HandlerChanged {
+        /// Address of the write
+        pc: RType,
+        /// Handler before
+        old: u32,
+        /// Handler after
+        new: u32,
+    },
+    /// Execution reached the installed handler
+    HandlerEntry {
+        /// Address of the handler
+        handler: u32,
+    },
+}
+
+/// Diagnostic that follows the game's IRQ handler pointer. Interrupt handler
+/// bugs are hard to localize, so this reports every time a game installs or
+/// replaces its handler and can stop execution when the handler is entered.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct IrqHandlerWatch {
+    log: bool,
+    break_on_entry: bool,
+    handler: u32,
+}
+
+impl IrqHandlerWatch {
+    /// A watch logging handler changes if log is set
+    pub fn new(log: bool) -> IrqHandlerWatch {
+        IrqHandlerWatch {
+            log,
+            .. IrqHandlerWatch::default()
+        }
+    }
+
+    /// Log handler changes, or stop
+    pub fn set_logging(&mut self, log: bool) {
+        self.log = log;
+    }
+
+    /// Stop when the handler is entered, or stop stopping
+    pub fn set_break_on_entry(&mut self, brk: bool) {
+        self.break_on_entry = brk;
+    }
+
+    /// Whether entering the handler stops the debugger
+    pub fn is_break_on_entry(&self) -> bool {
+        self.break_on_entry
+    }
+
+    /// Last handler address seen in IWRAM
+    pub fn handler(&self) -> u32 {
+        self.handler
+    }
+
+    /// Call after executing the instruction at pc
+    pub fn check_write(&mut self, pc: RType, mem: &Memory) -> Option<IrqWatchEvent> {
+        let new = mem.peek::<u32>(IRQ_HANDLER_PTR);
+        if new == self.handler {
+            return None;
+        }
+
+        let old = self.handler;
+        self.handler = new;
+        if self.log {
+            info!(target: "irq", "handler changed by instruction at {:#010x}: {:#010x} -> {:#010x}",
+                  pc, old, new);
+        }
+        Some(IrqWatchEvent::HandlerChanged { pc, old, new })
+    }
+
+    /// Call before executing the instruction at pc
+    pub fn check_entry(&self, pc: RType) -> Option<IrqWatchEvent> {
+        if self.break_on_entry && self.handler != 0 && pc == self.handler & !1 {
+            Some(IrqWatchEvent::HandlerEntry { handler: self.handler })
+        }
+        else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handler_changes_are_reported() {
+        let mut mem = Memory::blank();
+        let mut watch = IrqHandlerWatch::new(true);
+        watch.set_break_on_entry(true);
+        assert_eq!(watch.check_write(0x08000000, &mem), None);
+
+        mem.write32::<u32>(IRQ_HANDLER_PTR, 0x03000101);
+        assert_eq!(watch.check_write(0x08000004, &mem),
+                   Some(IrqWatchEvent::HandlerChanged { pc: 0x08000004, old: 0, new: 0x03000101 }));
+        assert_eq!(watch.check_write(0x08000008, &mem), None);
+        assert_eq!(watch.handler(), 0x03000101);
+
+        // A Thumb handler is entered at its address without the low bit
+        assert_eq!(watch.check_entry(0x03000100),
+                   Some(IrqWatchEvent::HandlerEntry { handler: 0x03000101 }));
+        assert_eq!(watch.check_entry(0x03000104), None);
+    }
+}
@@ -0,0 +1,20 @@
+/// Crash capture for spotting games gone off the rails
+pub mod capture;
+/// Breakpoints, watchpoints and stepping
+pub mod debugger;
+/// Typed reads of game memory
+pub mod guest;
+/// Watching the IRQ handler address in IWRAM
+pub mod irq_watch;
+/// The interactive debugger prompt
+pub mod repl;
+/// Instruction traces
+pub mod trace;
+/// Decoded views of video memory
+pub mod vram;
+
+pub use gba_debug::capture::{AutoCapture, Crash};
+pub use gba_debug::debugger::{Debugger, StopReason};
+pub use gba_debug::guest::{GuestMem, GuestRead};
+pub use gba_debug::irq_watch::{IrqHandlerWatch, IrqWatchEvent};
+pub use gba_debug::trace::{TraceEntry, TraceFilter, TraceFormat, Tracer};
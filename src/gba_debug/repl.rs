@@ -0,0 +1,503 @@
+use std::io::{self, BufRead, Write};
+
+use gba_apu::inspect::Channel;
+use gba_cheats::ram_search::{Comparison, RamSearch, SearchType};
+use gba_cpu::RType;
+use gba_cpu::disasm::disassemble;
+use gba_debug::capture::AutoCapture;
+use gba_debug::debugger::{Debugger, StopReason};
+use gba_debug::trace::TraceRegs;
+use gba_mem::Address;
+use gba_mem::io_map;
+use gba_mem::watch::{Watchpoint, WatchKind};
+use gba_ppu::Layer;
+use gba_system::Gba;
+
+// Instructions continue/next/finish run before giving up, about two
+// minutes of emulated time
+const RUN_LIMIT: u64 = 500_000_000;
+
+const DEFAULT_EXAMINE: usize = 64; // Bytes
+const DEFAULT_DISASM: usize = 8; // Instructions
+const BYTES_PER_LINE: usize = 16;
+const CAPTURE_WINDOW: usize = 1000; // Instructions of trace per capture
+const DEFAULT_SEARCH_LIST: usize = 20; // Candidates
+
+const HELP: &str = "\
+continue, c              run until a breakpoint or watchpoint
+step, s [N]              execute N instructions (default 1)
+next, n                  step over calls
+finish                   run until the current function returns
+break, b ADDR            stop when the PC reaches ADDR
+delete, d ADDR           remove the breakpoint at ADDR
+watch [r|w|rw] ADDR [N]  stop on accesses to N bytes at ADDR (default w, 1)
+unwatch N                remove watchpoint N
+list, l                  list breakpoints and watchpoints
+regs, r                  show the registers
+set REG VAL              write r0-r15, sp, lr or pc
+x ADDR [N]               dump N bytes at ADDR
+io [NAME]                show the IO registers, or the one named, decoded
+poke ADDR VAL [8|16|32]  write VAL at ADDR (default 32 bits)
+dis [ADDR] [N]           disassemble N instructions at ADDR (default PC)
+irq on|off               stop when the IRQ handler is entered
+apu                      show what each sound channel is doing
+mute CH on|off           silence square1, square2, wave, noise, fifo_a or fifo_b
+solo CH|off              hear only channel CH, or every channel again
+layer bg0-3|obj on|off   show or hide a background or the sprites
+capture DIR [N]|off      save up to N snapshots (default 1) to DIR when a
+                         watchpoint or crash stops execution
+cheats                   list the loaded cheats
+cheat N on|off           enable or disable cheat N
+search u8|u16|u32|s8|s16|s32
+                         start a search of EWRAM and IWRAM for a value
+search =|!=|>|< VAL      keep candidates equal to, not equal to, greater
+                         or less than VAL
+search changed|unchanged|inc|dec
+                         keep candidates that did so since the last search
+search by N              keep candidates that changed by N
+search list [N]          show N candidates (default 20)
+help, h                  show this help
+quit, q                  exit
+Numbers are decimal, or hex with a 0x prefix.";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Command {
+    Continue,
+    Step(u64),
+    Next,
+    Finish,
+    Break(RType),
+    Delete(RType),
+    Watch(Watchpoint),
+    Unwatch(usize),
+    List,
+    Regs,
+    Set(i8, RType),
+    Examine(Address, usize),
+    Io(Option<String>),
+    Poke(Address, u32, u8), // Size in bits
+    Disasm(Option<Address>, usize),
+    Irq(bool),
+    Apu,
+    Mute(Channel, bool),
+    Solo(Option<Channel>),
+    Layer(Layer, bool), // Shown
+    Capture(Option<(String, usize)>),
+    Cheats,
+    Cheat(usize, bool),
+    Search(SearchCommand),
+    Help,
+    Quit,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum SearchCommand {
+    Start(SearchType),
+    Filter(Comparison),
+    List(usize),
+}
+
+/// Decimal, or hex with a 0x prefix
+pub fn parse_num(s: &str) -> Result<u32, String> {
+    let parsed = if s.starts_with("0x") || s.starts_with("0X") {
+        u32::from_str_radix(&s[2..], 16)
+    }
+    else {
+        s.parse()
+    };
+    parsed.map_err(|_| format!("Invalid number {}", s))
+}
+
+// A number with an optional minus sign
+fn parse_signed(s: &str) -> Result<i64, String> {
+    if let Some(s) = s.strip_prefix('-') {
+        Ok(-(parse_num(s)? as i64))
+    }
+    else {
+        Ok(parse_num(s)? as i64)
+    }
+}
+
+fn parse_reg(s: &str) -> Result<i8, String> {
+    match s {
+        "sp" => Ok(13),
+        "lr" => Ok(14),
+        "pc" => Ok(15),
+        _ if s.starts_with('r') => match s[1..].parse() {
+            Ok(n) if n < 16 => Ok(n),
+            _ => Err(format!("Unknown register {}", s)),
+        },
+        _ => Err(format!("Unknown register {}", s)),
+    }
+}
+
+fn parse_channel(s: &str) -> Result<Channel, String> {
+    Channel::from_name(s).ok_or_else(|| format!("Unknown sound channel {}", s))
+}
+
+fn parse_command(line: &str) -> Result<Command, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let arg = |i: usize| words.get(i).cloned()
+        .ok_or_else(|| format!("{} expects more arguments", words[0]));
+    let num_or = |i: usize, default: u32| words.get(i).map_or(Ok(default), |s| parse_num(s));
+
+    match words[0] {
+        "continue" | "c" => Ok(Command::Continue),
+        "step" | "s" => Ok(Command::Step(num_or(1, 1)? as u64)),
+        "next" | "n" => Ok(Command::Next),
+        "finish" => Ok(Command::Finish),
+        "break" | "b" => Ok(Command::Break(parse_num(arg(1)?)?)),
+        "delete" | "d" => Ok(Command::Delete(parse_num(arg(1)?)?)),
+        "watch" | "w" => {
+            let (kind, at) = match words.get(1).cloned() {
+                Some("r") => (WatchKind::Read, 2),
+                Some("w") => (WatchKind::Write, 2),
+                Some("rw") => (WatchKind::Access, 2),
+                _ => (WatchKind::Write, 1),
+            };
+            let addr = parse_num(arg(at)?)? as Address;
+            let len = num_or(at + 1, 1)? as usize;
+            if len == 0 {
+                return Err("Watchpoint length must be positive".to_string());
+            }
+            Ok(Command::Watch(Watchpoint::new(addr, len, kind)))
+        },
+        "unwatch" => Ok(Command::Unwatch(parse_num(arg(1)?)? as usize)),
+        "list" | "l" => Ok(Command::List),
+        "regs" | "r" => Ok(Command::Regs),
+        "set" => Ok(Command::Set(parse_reg(arg(1)?)?, parse_num(arg(2)?)?)),
+        "x" => Ok(Command::Examine(parse_num(arg(1)?)? as Address,
+                                   num_or(2, DEFAULT_EXAMINE as u32)? as usize)),
+        "io" => Ok(Command::Io(words.get(1).map(|s| s.to_uppercase()))),
+        "poke" => {
+            let size = num_or(3, 32)?;
+            if size != 8 && size != 16 && size != 32 {
+                return Err("poke size must be 8, 16 or 32".to_string());
+            }
+            Ok(Command::Poke(parse_num(arg(1)?)? as Address, parse_num(arg(2)?)?, size as u8))
+        },
+        "dis" => Ok(Command::Disasm(words.get(1).map(|s| parse_num(s)).transpose()?
+                                        .map(|a| a as Address),
+                                    num_or(2, DEFAULT_DISASM as u32)? as usize)),
+        "irq" => match arg(1)? {
+            "on" => Ok(Command::Irq(true)),
+            "off" => Ok(Command::Irq(false)),
+            other => Err(format!("irq expects on or off, not {}", other)),
+        },
+        "apu" => Ok(Command::Apu),
+        "mute" => {
+            let ch = parse_channel(arg(1)?)?;
+            match arg(2)? {
+                "on" => Ok(Command::Mute(ch, true)),
+                "off" => Ok(Command::Mute(ch, false)),
+                other => Err(format!("mute expects on or off, not {}", other)),
+            }
+        },
+        "solo" => match arg(1)? {
+            "off" => Ok(Command::Solo(None)),
+            name => Ok(Command::Solo(Some(parse_channel(name)?))),
+        },
+        "layer" => {
+            let name = arg(1)?;
+            let layer = Layer::from_name(name).ok_or_else(|| format!("Unknown layer {}", name))?;
+            match arg(2)? {
+                "on" => Ok(Command::Layer(layer, true)),
+                "off" => Ok(Command::Layer(layer, false)),
+                other => Err(format!("layer expects on or off, not {}", other)),
+            }
+        },
+        "capture" => match arg(1)? {
+            "off" => Ok(Command::Capture(None)),
+            dir => Ok(Command::Capture(Some((dir.to_string(), num_or(2, 1)? as usize)))),
+        },
+        "cheats" => Ok(Command::Cheats),
+        "cheat" => {
+            let index = parse_num(arg(1)?)? as usize;
+            match arg(2)? {
+                "on" => Ok(Command::Cheat(index, true)),
+                "off" => Ok(Command::Cheat(index, false)),
+                other => Err(format!("cheat expects on or off, not {}", other)),
+            }
+        },
+        "search" => {
+            let search = match arg(1)? {
+                "=" => SearchCommand::Filter(Comparison::Equal(parse_signed(arg(2)?)?)),
+                "!=" => SearchCommand::Filter(Comparison::NotEqual(parse_signed(arg(2)?)?)),
+                ">" => SearchCommand::Filter(Comparison::Greater(parse_signed(arg(2)?)?)),
+                "<" => SearchCommand::Filter(Comparison::Less(parse_signed(arg(2)?)?)),
+                "changed" => SearchCommand::Filter(Comparison::Changed),
+                "unchanged" => SearchCommand::Filter(Comparison::Unchanged),
+                "inc" => SearchCommand::Filter(Comparison::Increased),
+                "dec" => SearchCommand::Filter(Comparison::Decreased),
+                "by" => SearchCommand::Filter(Comparison::ChangedBy(parse_signed(arg(2)?)?)),
+                "list" => SearchCommand::List(num_or(2, DEFAULT_SEARCH_LIST as u32)? as usize),
+                other => SearchCommand::Start(SearchType::from_name(other)
+                    .ok_or_else(|| format!("Unknown search {}, try help", other))?),
+            };
+            Ok(Command::Search(search))
+        },
+        "help" | "h" => Ok(Command::Help),
+        "quit" | "q" => Ok(Command::Quit),
+        other => Err(format!("Unknown command {}, try help", other)),
+    }
+}
+
+fn print_regs<W: Write>(gba: &Gba, out: &mut W) -> io::Result<()> {
+    const NAMES: [&str; 16] = [
+        "r0", "r1", "r2", "r3", "r4", "r5", "r6", "r7",
+        "r8", "r9", "r10", "r11", "r12", "sp", "lr", "pc",
+    ];
+
+    let regs = TraceRegs::capture(gba.cpu());
+    for (i, reg) in regs.regs.iter().enumerate() {
+        let sep = if i % 4 == 3 { "\n" } else { "  " };
+        write!(out, "{:>3}: {:08x}{}", NAMES[i], reg, sep)?;
+    }
+    writeln!(out, "cpsr: {:08x} [{}] {:?}{}", regs.cpsr, regs.flags(), gba.cpu().mode(),
+             if gba.cpu().is_thumb() { " thumb" } else { "" })
+}
+
+fn examine<W: Write>(gba: &Gba, addr: Address, len: usize, out: &mut W) -> io::Result<()> {
+    for line in (0..len).step_by(BYTES_PER_LINE) {
+        let at = addr + line;
+        write!(out, "{:08x}:", at)?;
+        for i in 0..BYTES_PER_LINE.min(len - line) {
+            write!(out, " {:02x}", gba.mem().peek::<u8>(at + i))?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+fn disasm<W: Write>(gba: &Gba, addr: Address, count: usize, out: &mut W) -> io::Result<()> {
+    let thumb = gba.cpu().is_thumb();
+    // Read one extra Thumb halfword so a long branch at the end is whole
+    let bytes = count * if thumb { 2 } else { 4 } + if thumb { 2 } else { 0 };
+    let code: Vec<u8> = (0..bytes).map(|i| gba.mem().peek::<u8>(addr + i)).collect();
+    for line in disassemble(&code, addr as u32, thumb).iter().take(count) {
+        writeln!(out, "{}", line)?;
+    }
+    Ok(())
+}
+
+const NO_SEARCH: &str = "No search started, try search u8";
+
+fn search_command<W: Write>(gba: &Gba, dbg: &mut Debugger, cmd: SearchCommand, out: &mut W)
+                            -> io::Result<()> {
+    match cmd {
+        SearchCommand::Start(ty) => {
+            let search = RamSearch::new(gba.mem(), ty);
+            writeln!(out, "{} candidates", search.len())?;
+            dbg.set_ram_search(Some(search));
+        },
+        SearchCommand::Filter(cmp) => match dbg.ram_search_mut() {
+            Some(search) => writeln!(out, "{} candidates", search.filter(gba.mem(), cmp))?,
+            None => writeln!(out, "{}", NO_SEARCH)?,
+        },
+        SearchCommand::List(limit) => match dbg.ram_search() {
+            Some(search) => {
+                for hit in search.hits(gba.mem(), limit) {
+                    writeln!(out, "{:#010x}: {} (was {})", hit.addr, hit.value, hit.previous)?;
+                }
+                if search.len() > limit {
+                    writeln!(out, "... {} more", search.len() - limit)?;
+                }
+            },
+            None => writeln!(out, "{}", NO_SEARCH)?,
+        },
+    }
+    Ok(())
+}
+
+// Run a command, returning false when the session should end
+fn execute<W: Write>(gba: &mut Gba, dbg: &mut Debugger, cmd: Command, out: &mut W)
+                     -> io::Result<bool> {
+    let stop = match cmd {
+        Command::Continue => Some(dbg.cont(gba, RUN_LIMIT)),
+        Command::Step(n) => Some(dbg.step(gba, n)),
+        Command::Next => Some(dbg.step_over(gba, RUN_LIMIT)),
+        Command::Finish => Some(dbg.step_out(gba, RUN_LIMIT)),
+        Command::Break(addr) => {
+            if !dbg.add_breakpoint(addr) {
+                writeln!(out, "Breakpoint at {:#010x} already set", addr)?;
+            }
+            None
+        },
+        Command::Delete(addr) => {
+            if !dbg.remove_breakpoint(addr) {
+                writeln!(out, "No breakpoint at {:#010x}", addr)?;
+            }
+            None
+        },
+        Command::Watch(watch) => {
+            let index = gba.mem_mut().add_watchpoint(watch);
+            writeln!(out, "Watchpoint {}: {}", index, watch)?;
+            None
+        },
+        Command::Unwatch(index) => {
+            if gba.mem_mut().remove_watchpoint(index).is_none() {
+                writeln!(out, "No watchpoint {}", index)?;
+            }
+            None
+        },
+        Command::List => {
+            for addr in dbg.breakpoints() {
+                writeln!(out, "break {:#010x}", addr)?;
+            }
+            for (i, watch) in gba.mem().watchpoints().iter().enumerate() {
+                writeln!(out, "watch {}: {}", i, watch)?;
+            }
+            None
+        },
+        Command::Regs => {
+            print_regs(gba, out)?;
+            None
+        },
+        Command::Set(reg, val) => {
+            gba.cpu_mut().write_reg(reg, val);
+            None
+        },
+        Command::Examine(addr, len) => {
+            examine(gba, addr, len, out)?;
+            None
+        },
+        Command::Io(name) => {
+            let regs = io_map::view(gba.mem().io());
+            let shown: Vec<_> = regs.iter()
+                .filter(|r| name.as_ref().is_none_or(|n| r.reg.name == n.as_str()))
+                .collect();
+            if shown.is_empty() {
+                writeln!(out, "No IO register {}", name.unwrap_or_default())?;
+            }
+            for reg in shown {
+                writeln!(out, "{}", reg)?;
+            }
+            None
+        },
+        Command::Poke(addr, val, size) => {
+            match size {
+                8 => gba.mem_mut().write8::<u8>(addr, val as u8),
+                16 => gba.mem_mut().write16::<u16>(addr, val as u16),
+                _ => gba.mem_mut().write32::<u32>(addr, val),
+            }
+            // The debugger's own writes don't count
+            gba.mem().take_watch_hit();
+            None
+        },
+        Command::Disasm(addr, count) => {
+            let addr = addr.unwrap_or(gba.cpu().pc() as Address);
+            disasm(gba, addr, count, out)?;
+            None
+        },
+        Command::Irq(on) => {
+            dbg.irq_watch_mut().set_break_on_entry(on);
+            None
+        },
+        Command::Apu => {
+            writeln!(out, "{}", gba.apu_info())?;
+            None
+        },
+        Command::Mute(ch, muted) => {
+            gba.set_channel_muted(ch, muted);
+            None
+        },
+        Command::Solo(ch) => {
+            gba.solo_channel(ch);
+            None
+        },
+        Command::Layer(layer, shown) => {
+            gba.set_layer_hidden(layer, !shown);
+            None
+        },
+        Command::Capture(setting) => {
+            let capture = setting.map(|(dir, limit)| {
+                let mut capture = AutoCapture::new(dir, CAPTURE_WINDOW);
+                capture.set_limit(limit);
+                capture
+            });
+            dbg.set_capture(gba, capture);
+            None
+        },
+        Command::Cheats => {
+            for (i, cheat) in gba.cheats().cheats().iter().enumerate() {
+                let master = if cheat.has_master_code() { " (master code ignored)" } else { "" };
+                writeln!(out, "{}: [{}] {}{}", i, if cheat.enabled { "on" } else { "off" },
+                         cheat.name, master)?;
+            }
+            None
+        },
+        Command::Cheat(index, on) => {
+            if !gba.cheats_mut().set_enabled(index, on) {
+                writeln!(out, "No cheat {}", index)?;
+            }
+            None
+        },
+        Command::Search(search) => {
+            search_command(gba, dbg, search, out)?;
+            None
+        },
+        Command::Help => {
+            writeln!(out, "{}", HELP)?;
+            None
+        },
+        Command::Quit => return Ok(false),
+    };
+
+    if let Some(reason) = stop {
+        if reason != StopReason::Step {
+            writeln!(out, "Stopped: {}", reason)?;
+        }
+        if let Some(path) = dbg.take_last_capture() {
+            writeln!(out, "Captured {}", path.display())?;
+        }
+        writeln!(out, "{}", Debugger::current_instr(gba))?;
+    }
+    Ok(true)
+}
+
+/// Read commands from input until quit or end of input. An empty line
+/// repeats the last command, so stepping is a matter of pressing enter.
+pub fn run<R, W>(gba: &mut Gba, dbg: &mut Debugger, input: R, out: &mut W) -> io::Result<()>
+    where R: BufRead, W: Write {
+    let mut last = None;
+    let mut lines = input.lines();
+
+    writeln!(out, "{}", Debugger::current_instr(gba))?;
+    loop {
+        write!(out, "(gba) ")?;
+        out.flush()?;
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => return Ok(()),
+        };
+
+        let cmd = if line.trim().is_empty() {
+            match last.clone() {
+                Some(cmd) => cmd,
+                None => continue,
+            }
+        }
+        else {
+            match parse_command(&line) {
+                Ok(cmd) => cmd,
+                Err(e) => {
+                    writeln!(out, "{}", e)?;
+                    continue;
+                },
+            }
+        };
+
+        last = Some(cmd.clone());
+        if !execute(gba, dbg, cmd, out)? {
+            return Ok(());
+        }
+    }
+}
+
+/// Debug on the terminal
+pub fn run_stdio(gba: &mut Gba) -> io::Result<()> {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let mut dbg = Debugger::new();
+    run(gba, &mut dbg, stdin.lock(), &mut stdout.lock())
+}
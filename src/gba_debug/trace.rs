@@ -0,0 +1,262 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use gba_cpu::disasm::{disasm_arm, disasm_thumb};
+use gba_cpu::{ARM7, IType, RType, TIType};
+use gba_error::GbaResult;
+use gba_mem::{Address, Memory};
+
+const VISIBLE_REGS: usize = 16;
+
+const REG_NAMES: [&str; VISIBLE_REGS] = [
+    "r0", "r1", "r2", "r3", "r4", "r5", "r6", "r7",
+    "r8", "r9", "r10", "r11", "r12", "sp", "lr", "pc",
+];
+
+/// How trace lines are written out
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// PC, instruction, disassembly, then the registers and flags it changed
+    Changes,
+    /// Every register and the CPSR before the instruction, then the
+    /// instruction. This follows the register-first layout of mGBA's trace
+    /// output so the two can be diffed line by line.
+    Registers,
+}
+
+/// The registers visible in the current mode and the CPSR
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TraceRegs {
+    /// The registers the current mode can see
+    pub regs: [RType; VISIBLE_REGS],
+    /// The status register
+    pub cpsr: RType,
+}
+
+impl TraceRegs {
+    /// The registers cpu shows now
+    pub fn capture(cpu: &ARM7) -> TraceRegs {
+        let mut regs = [0; VISIBLE_REGS];
+        for (i, reg) in regs.iter_mut().enumerate() {
+            *reg = cpu.reg(i as i8).read();
+        }
+        TraceRegs {
+            regs,
+            cpsr: cpu.cpsr().read(),
+        }
+    }
+
+    /// NZCV, upper case when set
+    pub fn flags(&self) -> String {
+        "NZCV".chars().enumerate()
+            .map(|(i, c)| if self.cpsr & (1 << (31 - i)) != 0 { c } else { c.to_ascii_lowercase() })
+            .collect()
+    }
+}
+
+/// One executed instruction
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceEntry {
+    /// When the instruction started
+    pub cycles: u64,
+    /// Address of the instruction
+    pub pc: RType,
+    /// Whether it was a Thumb instruction
+    pub thumb: bool,
+    /// Its encoding
+    pub instr: IType,
+    /// Its disassembly
+    pub text: String,
+    /// Registers before it ran
+    pub before: TraceRegs,
+    /// Registers after it ran
+    pub after: TraceRegs,
+}
+
+impl TraceEntry {
+    /// Registers the instruction wrote, as (index, new value)
+    pub fn changed(&self) -> Vec<(usize, RType)> {
+        (0..VISIBLE_REGS)
+            .filter(|&i| self.before.regs[i] != self.after.regs[i])
+            .map(|i| (i, self.after.regs[i]))
+            .collect()
+    }
+
+    /// The line for this instruction in format
+    pub fn format(&self, format: TraceFormat) -> String {
+        let text = self.text.replace('\t', " ");
+        let instr = if self.thumb {
+            format!("    {:04x}", self.instr)
+        }
+        else {
+            format!("{:08x}", self.instr)
+        };
+
+        match format {
+            TraceFormat::Changes => {
+                let mut line = format!("{:08x}: {}  {:<32}", self.pc, instr, text);
+                // The PC always moves; only show it when it jumped
+                let next = self.pc.wrapping_add(if self.thumb { 2 } else { 4 });
+                for (i, val) in self.changed() {
+                    if i != 15 || val != next {
+                        line.push_str(&format!(" {}={:08x}", REG_NAMES[i], val));
+                    }
+                }
+                if self.before.cpsr != self.after.cpsr {
+                    line.push_str(&format!(" cpsr={:08x}", self.after.cpsr));
+                }
+                line.push_str(&format!(" [{}]", self.after.flags()));
+                line
+            },
+            TraceFormat::Registers => {
+                let mut line = String::new();
+                for reg in self.before.regs.iter() {
+                    line.push_str(&format!("{:08X} ", reg));
+                }
+                line.push_str(&format!("cpsr: {:08X} | {}: {}", self.before.cpsr, instr, text));
+                line
+            },
+        }
+    }
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write![f, "{}", self.format(TraceFormat::Changes)]
+    }
+}
+
+/// Address ranges to trace. With no ranges everything is traced.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TraceFilter {
+    ranges: Vec<(Address, Address)>, // Inclusive
+}
+
+impl TraceFilter {
+    /// Also trace lo to hi, inclusive
+    pub fn add_range(&mut self, lo: Address, hi: Address) {
+        assert!(lo <= hi);
+        self.ranges.push((lo, hi));
+    }
+
+    /// Trace everywhere again
+    pub fn clear(&mut self) {
+        self.ranges.clear();
+    }
+
+    /// Whether an instruction at pc is traced
+    pub fn matches(&self, pc: Address) -> bool {
+        self.ranges.is_empty() || self.ranges.iter().any(|&(lo, hi)| pc >= lo && pc <= hi)
+    }
+}
+
+/// State captured before an instruction that the tracer wants
+#[derive(Clone, Copy, Debug)]
+pub struct PendingTrace {
+    cycles: u64,
+    pc: RType,
+    thumb: bool,
+    instr: IType,
+    before: TraceRegs,
+}
+
+/// Logs executed instructions to a callback, e.g. a file writer
+pub struct Tracer {
+    sink: Box<dyn FnMut(&TraceEntry)>,
+    filter: TraceFilter,
+    enabled: bool,
+}
+
+impl Tracer {
+    /// A tracer that hands each line to sink, off until enabled
+    pub fn new<F>(sink: F) -> Tracer
+        where F: FnMut(&TraceEntry) + 'static {
+        Tracer {
+            sink: Box::new(sink),
+            filter: TraceFilter::default(),
+            enabled: true,
+        }
+    }
+
+    /// Write each entry to a file as one line
+    pub fn to_file<P: AsRef<Path>>(path: P, format: TraceFormat) -> GbaResult<Tracer> {
+        let mut out = BufWriter::new(File::create(path)?);
+        Ok(Tracer::new(move |entry| {
+            if let Err(e) = writeln!(out, "{}", entry.format(format)) {
+                warn!(target: "debug", "failed to write trace: {}", e);
+            }
+        }))
+    }
+
+    /// Which addresses are traced
+    pub fn filter(&self) -> &TraceFilter {
+        &self.filter
+    }
+
+    /// Which addresses are traced, for changing
+    pub fn filter_mut(&mut self) -> &mut TraceFilter {
+        &mut self.filter
+    }
+
+    /// Start or stop tracing
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Whether tracing is on
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Call before executing the instruction at the PC. Returns what
+    /// finish() needs if the instruction should be traced.
+    pub fn start(&self, cpu: &ARM7, mem: &Memory, cycles: u64) -> Option<PendingTrace> {
+        let pc = cpu.pc();
+        if !self.enabled || !self.filter.matches(pc as Address) {
+            return None;
+        }
+
+        let thumb = cpu.is_thumb();
+        let instr = if thumb {
+            mem.peek::<TIType>(pc as Address) as IType
+        }
+        else {
+            mem.peek::<IType>(pc as Address)
+        };
+        Some(PendingTrace {
+            cycles,
+            pc,
+            thumb,
+            instr,
+            before: TraceRegs::capture(cpu),
+        })
+    }
+
+    /// Call after the instruction has executed
+    pub fn finish(&mut self, pending: PendingTrace, cpu: &ARM7) {
+        let text = if pending.thumb {
+            disasm_thumb(pending.instr as TIType, pending.pc)
+        }
+        else {
+            disasm_arm(pending.instr, pending.pc)
+        };
+        let entry = TraceEntry {
+            cycles: pending.cycles,
+            pc: pending.pc,
+            thumb: pending.thumb,
+            instr: pending.instr,
+            text,
+            before: pending.before,
+            after: TraceRegs::capture(cpu),
+        };
+        (self.sink)(&entry);
+    }
+}
+
+impl fmt::Debug for Tracer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write![f, "Tracer {{ filter: {:?}, enabled: {} }}", self.filter, self.enabled]
+    }
+}
@@ -0,0 +1,347 @@
+// Decoded views of video memory for VRAM, tile, map and sprite viewer
+// panels. Layouts from:
+// http://problemkaputt.de/gbatek.htm#lcdvrambgscreendataformatbgmap
+// http://problemkaputt.de/gbatek.htm#lcdobjoamattributes
+//
+// Bitmaps come out in the PPU's BGR555, so frontends convert them the way
+// they convert frames, with gba_video::convert.
+
+use gba_mem::Memory;
+use gba_ppu::NUM_BACKGROUNDS;
+use gba_video::bgr555_to_rgb;
+
+/// Pixels square
+pub const TILE_SIZE: usize = 8;
+/// OAM entries
+pub const NUM_SPRITES: usize = 128;
+/// Sprite colors follow the 256 background ones
+pub const OBJ_PALETTE: usize = 256;
+
+const OBJ_TILES: usize = 0x10000; // VRAM offset of sprite tiles
+const TILE_BYTES_4BPP: usize = 32;
+const CHAR_BLOCK: usize = 0x4000;
+const SCREEN_BLOCK: usize = 0x800;
+const SCREEN_BLOCK_TILES: usize = 32; // Text maps are made of 32x32 blocks
+const OAM_ENTRY_BYTES: usize = 8;
+const OBJ_1D_MAPPING: u16 = 0x0040; // DISPCNT bit 6
+
+// Sprite sizes in pixels by shape then size
+const SPRITE_SIZES: [[(usize, usize); 4]; 3] = [
+    [(8, 8), (16, 16), (32, 32), (64, 64)],  // Square
+    [(16, 8), (32, 8), (32, 16), (64, 32)],  // Wide
+    [(8, 16), (8, 32), (16, 32), (32, 64)],  // Tall
+];
+
+/// How many colors a tile's pixels index
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorMode {
+    /// 16 palettes of 16 colors
+    Bpp4,
+    /// One palette of 256 colors
+    Bpp8,
+}
+
+impl ColorMode {
+    fn tile_bytes(&self) -> usize {
+        match *self {
+            ColorMode::Bpp4 => TILE_BYTES_4BPP,
+            ColorMode::Bpp8 => 2 * TILE_BYTES_4BPP,
+        }
+    }
+}
+
+/// A picture of part of video memory in BGR555
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Bitmap {
+    /// Pixels across
+    pub width: usize,
+    /// Pixels down
+    pub height: usize,
+    /// Pixels, left to right then top to bottom
+    pub pixels: Vec<u16>,
+}
+
+impl Bitmap {
+    fn new(width: usize, height: usize) -> Bitmap {
+        Bitmap {
+            width,
+            height,
+            pixels: vec![0; width * height],
+        }
+    }
+
+    // Draw a tile's palette indices with its top left corner at x, y
+    fn draw_tile(&mut self, x: usize, y: usize, indices: &[u8; 64], colors: &[u16],
+                 hflip: bool, vflip: bool) {
+        for ty in 0..TILE_SIZE {
+            for tx in 0..TILE_SIZE {
+                let sx = if hflip { TILE_SIZE - 1 - tx } else { tx };
+                let sy = if vflip { TILE_SIZE - 1 - ty } else { ty };
+                let index = indices[sy * TILE_SIZE + sx] as usize;
+                self.pixels[(y + ty) * self.width + x + tx] = colors[index % colors.len()];
+            }
+        }
+    }
+}
+
+/// Palette RAM as BGR555: the background palette, then the sprite palette
+pub fn palette_colors(mem: &Memory) -> Vec<u16> {
+    mem.palette().chunks(2).map(|c| c[0] as u16 | (c[1] as u16) << 8).collect()
+}
+
+/// Palette RAM as RGB, in the same order
+pub fn palette_rgb(mem: &Memory) -> Vec<[u8; 3]> {
+    palette_colors(mem).into_iter().map(bgr555_to_rgb).collect()
+}
+
+// The colors a tile's indices pick from. first is the palette RAM entry
+// of index 0, e.g. OBJ_PALETTE + 16 * n for 4bpp sprite palette n.
+fn colors(mem: &Memory, first: usize, mode: ColorMode) -> Vec<u16> {
+    let count = match mode { ColorMode::Bpp4 => 16, ColorMode::Bpp8 => 256 };
+    let palette = palette_colors(mem);
+    (0..count).map(|i| palette[(first + i) % palette.len()]).collect()
+}
+
+/// A tile's pixels as palette indices, left to right then top to bottom.
+/// Index 0 is transparent.
+pub fn tile_indices(mem: &Memory, offset: usize, mode: ColorMode) -> [u8; 64] {
+    let vram = mem.vram();
+    let mut indices = [0; 64];
+    for (i, index) in indices.iter_mut().enumerate() {
+        *index = match mode {
+            ColorMode::Bpp4 => {
+                let byte = vram[(offset + i / 2) % vram.len()];
+                if i % 2 == 0 { byte & 0xF } else { byte >> 4 }
+            },
+            ColorMode::Bpp8 => vram[(offset + i) % vram.len()],
+        };
+    }
+    indices
+}
+
+/// count tiles from a VRAM offset, columns tiles to a row, in the colors
+/// from palette RAM entry first on
+pub fn tile_sheet(mem: &Memory, offset: usize, count: usize, columns: usize,
+                  mode: ColorMode, first: usize) -> Bitmap {
+    let rows = count.div_ceil(columns);
+    let mut bitmap = Bitmap::new(columns * TILE_SIZE, rows * TILE_SIZE);
+    let colors = colors(mem, first, mode);
+    for tile in 0..count {
+        let indices = tile_indices(mem, offset + tile * mode.tile_bytes(), mode);
+        bitmap.draw_tile((tile % columns) * TILE_SIZE, (tile / columns) * TILE_SIZE,
+                         &indices, &colors, false, false);
+    }
+    bitmap
+}
+
+/// A background's settings from DISPCNT and its BGxCNT
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Background {
+    /// Which background, 0-3
+    pub index: usize,
+    /// Enabled in DISPCNT
+    pub enabled: bool,
+    /// Drawn over backgrounds with a higher number, 0-3
+    pub priority: u8,
+    /// VRAM offset of its tiles
+    pub char_base: usize,
+    /// VRAM offset of its map
+    pub screen_base: usize,
+    /// Colors per tile
+    pub mode: ColorMode,
+    /// Rotated and scaled under the current video mode
+    pub affine: bool,
+    /// In tiles
+    pub width: usize,
+    /// In tiles
+    pub height: usize,
+}
+
+/// The settings of background bg
+pub fn background(mem: &Memory, bg: usize) -> Background {
+    assert!(bg < NUM_BACKGROUNDS);
+    let ppu = &mem.io().ppu;
+    let (dispcnt, bgcnt) = (ppu.dispcnt(), ppu.bgcnt(bg));
+    let affine = match ppu.bg_mode() {
+        1 => bg == 2,
+        2 => bg >= 2,
+        _ => false,
+    };
+    let size = (bgcnt >> 14) as usize;
+    let (width, height) = if affine {
+        (16 << size, 16 << size)
+    }
+    else {
+        (32 << (size & 1), 32 << (size >> 1))
+    };
+    Background {
+        index: bg,
+        enabled: dispcnt & (0x100 << bg) != 0,
+        priority: (bgcnt & 3) as u8,
+        char_base: ((bgcnt >> 2) & 3) as usize * CHAR_BLOCK,
+        screen_base: ((bgcnt >> 8) & 0x1F) as usize * SCREEN_BLOCK,
+        mode: if affine || bgcnt & 0x80 != 0 { ColorMode::Bpp8 } else { ColorMode::Bpp4 },
+        affine,
+        width,
+        height,
+    }
+}
+
+/// One tile of a background map. Affine maps only have the tile number.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MapEntry {
+    /// Tile number
+    pub tile: u16,
+    /// Flipped horizontally
+    pub hflip: bool,
+    /// Flipped vertically
+    pub vflip: bool,
+    /// For 4bpp tiles
+    pub palette: u8,
+}
+
+/// A background's map, left to right then top to bottom
+pub fn tilemap(mem: &Memory, bg: &Background) -> Vec<MapEntry> {
+    let vram = mem.vram();
+    let mut entries = Vec::with_capacity(bg.width * bg.height);
+    for y in 0..bg.height {
+        for x in 0..bg.width {
+            let entry = if bg.affine {
+                MapEntry {
+                    tile: vram[(bg.screen_base + y * bg.width + x) % vram.len()] as u16,
+                    hflip: false,
+                    vflip: false,
+                    palette: 0,
+                }
+            }
+            else {
+                // Wider and taller maps are more 32x32 blocks, left to
+                // right then top to bottom
+                let block = (y / SCREEN_BLOCK_TILES) * (bg.width / SCREEN_BLOCK_TILES) +
+                            x / SCREEN_BLOCK_TILES;
+                let at = bg.screen_base + block * SCREEN_BLOCK +
+                         ((y % SCREEN_BLOCK_TILES) * SCREEN_BLOCK_TILES +
+                          x % SCREEN_BLOCK_TILES) * 2;
+                let raw = vram[at % vram.len()] as u16 | (vram[(at + 1) % vram.len()] as u16) << 8;
+                MapEntry {
+                    tile: raw & 0x3FF,
+                    hflip: raw & 0x400 != 0,
+                    vflip: raw & 0x800 != 0,
+                    palette: (raw >> 12) as u8,
+                }
+            };
+            entries.push(entry);
+        }
+    }
+    entries
+}
+
+/// A whole background map drawn from its tiles, without scrolling or
+/// transparency
+pub fn render_background(mem: &Memory, bg: &Background) -> Bitmap {
+    let mut bitmap = Bitmap::new(bg.width * TILE_SIZE, bg.height * TILE_SIZE);
+    let palettes: Vec<Vec<u16>> = match bg.mode {
+        ColorMode::Bpp4 => (0..16).map(|p| colors(mem, 16 * p, ColorMode::Bpp4)).collect(),
+        ColorMode::Bpp8 => vec![colors(mem, 0, ColorMode::Bpp8)],
+    };
+    for (i, entry) in tilemap(mem, bg).iter().enumerate() {
+        let offset = bg.char_base + entry.tile as usize * bg.mode.tile_bytes();
+        let indices = tile_indices(mem, offset, bg.mode);
+        let colors = &palettes[entry.palette as usize % palettes.len()];
+        bitmap.draw_tile((i % bg.width) * TILE_SIZE, (i / bg.width) * TILE_SIZE,
+                         &indices, colors, entry.hflip, entry.vflip);
+    }
+    bitmap
+}
+
+/// An OAM entry
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Sprite {
+    /// Which OAM entry
+    pub index: usize,
+    /// -256..255
+    pub x: i16,
+    /// Wraps around past the bottom of the screen
+    pub y: u8,
+    /// Pixels
+    pub width: usize,
+    /// Pixels
+    pub height: usize,
+    /// First tile
+    pub tile: u16,
+    /// Drawn over backgrounds with a higher number, 0-3
+    pub priority: u8,
+    /// For 4bpp sprites
+    pub palette: u8,
+    /// Colors per tile
+    pub mode: ColorMode,
+    /// Flipped horizontally, when not affine
+    pub hflip: bool,
+    /// Flipped vertically, when not affine
+    pub vflip: bool,
+    /// Rotation/scaling parameter group
+    pub affine: Option<u8>,
+    /// Drawn in twice its size, for affine sprites
+    pub double_size: bool,
+    /// Disabled, which only non-affine sprites can be
+    pub hidden: bool,
+    /// 0 normal, 1 semi-transparent, 2 OBJ window
+    pub gfx_mode: u8,
+    /// Drawn with the mosaic effect
+    pub mosaic: bool,
+}
+
+/// Every OAM entry, in order
+pub fn sprites(mem: &Memory) -> Vec<Sprite> {
+    mem.oam().chunks(OAM_ENTRY_BYTES).take(NUM_SPRITES).enumerate().map(|(i, e)| {
+        let attr = |n: usize| e[2 * n] as u16 | (e[2 * n + 1] as u16) << 8;
+        let (attr0, attr1, attr2) = (attr(0), attr(1), attr(2));
+        let affine = attr0 & 0x100 != 0;
+        let shape = ((attr0 >> 14) as usize).min(2); // Shape 3 is prohibited
+        let (width, height) = SPRITE_SIZES[shape][(attr1 >> 14) as usize];
+        Sprite {
+            index: i,
+            x: ((attr1 << 7) as i16) >> 7,
+            y: attr0 as u8,
+            width,
+            height,
+            tile: attr2 & 0x3FF,
+            priority: ((attr2 >> 10) & 3) as u8,
+            palette: (attr2 >> 12) as u8,
+            mode: if attr0 & 0x2000 != 0 { ColorMode::Bpp8 } else { ColorMode::Bpp4 },
+            hflip: !affine && attr1 & 0x1000 != 0,
+            vflip: !affine && attr1 & 0x2000 != 0,
+            affine: if affine { Some(((attr1 >> 9) & 0x1F) as u8) } else { None },
+            double_size: affine && attr0 & 0x200 != 0,
+            hidden: !affine && attr0 & 0x200 != 0,
+            gfx_mode: ((attr0 >> 10) & 3) as u8,
+            mosaic: attr0 & 0x1000 != 0,
+        }
+    }).collect()
+}
+
+/// A sprite's tiles as laid out under the current DISPCNT mapping, without
+/// flipping or rotation
+pub fn render_sprite(mem: &Memory, sprite: &Sprite) -> Bitmap {
+    let mut bitmap = Bitmap::new(sprite.width, sprite.height);
+    let first = match sprite.mode {
+        ColorMode::Bpp4 => OBJ_PALETTE + 16 * sprite.palette as usize,
+        ColorMode::Bpp8 => OBJ_PALETTE,
+    };
+    let colors = colors(mem, first, sprite.mode);
+    // Tile numbers count 4bpp tiles whatever the sprite's mode
+    let step = sprite.mode.tile_bytes() / TILE_BYTES_4BPP;
+    let one_d = mem.io().ppu.dispcnt() & OBJ_1D_MAPPING != 0;
+    let (columns, rows) = (sprite.width / TILE_SIZE, sprite.height / TILE_SIZE);
+    for ty in 0..rows {
+        for tx in 0..columns {
+            // In 2D mapping sprite tiles sit in a 32 tile wide sheet
+            let row_tiles = if one_d { columns * step } else { 32 };
+            let tile = sprite.tile as usize + ty * row_tiles + tx * step;
+            let indices = tile_indices(mem, OBJ_TILES + (tile % 1024) * TILE_BYTES_4BPP,
+                                       sprite.mode);
+            bitmap.draw_tile(tx * TILE_SIZE, ty * TILE_SIZE, &indices, &colors, false, false);
+        }
+    }
+    bitmap
+}
@@ -0,0 +1,410 @@
+use core::fmt;
+
+use gba_irq::{Interrupt, IrqController};
+use gba_mem::Address;
+
+/// DMA registers from:
+/// http://problemkaputt.de/gbatek.htm#gbadmatransfers
+pub const DMA0SAD: Address = 0x040000B0;
+/// DMA3's control register, the last of the block
+pub const DMA3CNT_H: Address = 0x040000DE;
+const DMA_REG_STRIDE: Address = 12;
+
+/// Channels, from DMA0 (highest priority) to DMA3
+pub const NUM_DMA_CHANNELS: usize = 4;
+
+// Register offsets within a channel
+const SAD_L: Address = 0;
+const SAD_H: Address = 2;
+const DAD_L: Address = 4;
+const DAD_H: Address = 6;
+const CNT_L: Address = 8;
+const CNT_H: Address = 10;
+
+// DMAxCNT_H bits
+const DEST_CTRL_SHIFT: u16 = 5;
+const SRC_CTRL_SHIFT:  u16 = 7;
+const ADDR_CTRL_MASK:  u16 = 0b11;
+const DMA_REPEAT:      u16 = 0x0200;
+const DMA_WORD:        u16 = 0x0400; // 32-bit transfer
+const DMA_TIMING_SHIFT:u16 = 12;
+const DMA_IRQ:         u16 = 0x4000;
+const DMA_ENABLE:      u16 = 0x8000;
+const DMA_CNT_MASK:    u16 = 0xFFE0;
+
+// Sound FIFO transfers always move four words
+const FIFO_TRANSFER_WORDS: u32 = 4;
+
+const DMA_IRQS: [Interrupt; NUM_DMA_CHANNELS] = [
+    Interrupt::Dma0, Interrupt::Dma1, Interrupt::Dma2, Interrupt::Dma3,
+];
+
+/// When a channel's transfer starts, from DMAxCNT_H bits 12-13
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DmaTiming {
+    /// As soon as it's enabled
+    Immediate,
+    /// At the start of vertical blank
+    VBlank,
+    /// At the start of each horizontal blank
+    HBlank,
+    /// Sound FIFO for DMA1/2, video capture for DMA3
+    Special,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum AddrCtrl {
+    Increment,
+    Decrement,
+    Fixed,
+    IncrementReload, // Destination only; reloaded on repeat
+}
+
+impl AddrCtrl {
+    fn decode(bits: u16) -> AddrCtrl {
+        match bits & ADDR_CTRL_MASK {
+            0 => AddrCtrl::Increment,
+            1 => AddrCtrl::Decrement,
+            2 => AddrCtrl::Fixed,
+            _ => AddrCtrl::IncrementReload,
+        }
+    }
+
+    fn step(&self, width: u32) -> u32 {
+        match *self {
+            AddrCtrl::Increment | AddrCtrl::IncrementReload => width,
+            AddrCtrl::Decrement => width.wrapping_neg(),
+            AddrCtrl::Fixed => 0,
+        }
+    }
+}
+
+/// A transfer ready to be carried out on the bus
+#[derive(Clone, Copy, Debug)]
+pub struct DmaTransfer {
+    /// Which channel it's for
+    pub channel: usize,
+    /// Source address
+    pub src: u32,
+    /// Destination address
+    pub dst: u32,
+    /// Units to move
+    pub count: u32,
+    /// Whether the units are words rather than halfwords
+    pub word: bool,
+    /// Added to src after each unit
+    pub src_step: u32,
+    /// Added to dst after each unit
+    pub dst_step: u32,
+}
+
+/// One DMA channel's registers and internal state
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DmaChannel {
+    sad: u32,
+    dad: u32,
+    count: u16,
+    cnt: u16,
+    src: u32, // Internal address and count registers
+    dst: u32,
+    pending: bool,
+}
+
+impl_save_state!(DmaChannel { sad, dad, count, cnt, src, dst, pending });
+
+impl DmaChannel {
+    /// Whether DMAxCNT_H enables it
+    pub fn is_enabled(&self) -> bool { self.cnt & DMA_ENABLE != 0 }
+    /// Whether a transfer is waiting for the bus
+    pub fn is_pending(&self) -> bool { self.pending }
+    /// DMAxCNT_H as written
+    pub fn cnt(&self) -> u16 { self.cnt }
+    /// Destination address as written
+    pub fn dad(&self) -> u32 { self.dad }
+
+    /// When the transfer starts
+    pub fn timing(&self) -> DmaTiming {
+        match (self.cnt >> DMA_TIMING_SHIFT) & 0b11 {
+            0 => DmaTiming::Immediate,
+            1 => DmaTiming::VBlank,
+            2 => DmaTiming::HBlank,
+            _ => DmaTiming::Special,
+        }
+    }
+}
+
+/// The four DMA channels
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Dma {
+    channels: [DmaChannel; NUM_DMA_CHANNELS],
+}
+
+impl_save_state!(Dma { channels });
+impl_serde_via_state!(Dma);
+
+impl Dma {
+    /// Channel idx
+    pub fn channel(&self, idx: usize) -> &DmaChannel {
+        &self.channels[idx]
+    }
+
+    // DMA0 can only reach internal memory; DMA3 can also write to the
+    // game pak
+    fn src_mask(idx: usize) -> u32 { if idx == 0 { 0x07FFFFFF } else { 0x0FFFFFFF } }
+    fn dst_mask(idx: usize) -> u32 { if idx == 3 { 0x0FFFFFFF } else { 0x07FFFFFF } }
+    fn max_count(idx: usize) -> u32 { if idx == 3 { 0x10000 } else { 0x4000 } }
+
+    /// Start every enabled channel waiting on a blanking period
+    pub fn trigger(&mut self, timing: DmaTiming) {
+        for ch in self.channels.iter_mut() {
+            if ch.is_enabled() && ch.timing() == timing {
+                ch.pending = true;
+            }
+        }
+    }
+
+    /// A sound FIFO at fifo_addr is running low
+    pub fn request_fifo(&mut self, fifo_addr: Address) {
+        for ch in self.channels[1..3].iter_mut() {
+            if ch.is_enabled() && ch.timing() == DmaTiming::Special &&
+               ch.dad as Address == fifo_addr {
+                ch.pending = true;
+            }
+        }
+    }
+
+    /// Highest priority channel with a transfer waiting
+    pub fn next_pending(&self) -> Option<usize> {
+        self.channels.iter().position(|ch| ch.pending)
+    }
+
+    /// Take channel idx's waiting transfer off the queue and reload its registers
+    pub fn start(&mut self, idx: usize) -> DmaTransfer {
+        let ch = &mut self.channels[idx];
+        ch.pending = false;
+
+        let fifo = (idx == 1 || idx == 2) && ch.timing() == DmaTiming::Special;
+        let word = fifo || ch.cnt & DMA_WORD != 0;
+        let width = if word { 4 } else { 2 };
+        let count = if fifo {
+            FIFO_TRANSFER_WORDS
+        }
+        else {
+            match ch.count as u32 & (Dma::max_count(idx) - 1) {
+                0 => Dma::max_count(idx),
+                n => n,
+            }
+        };
+        let dst_ctrl = if fifo {
+            AddrCtrl::Fixed
+        }
+        else {
+            AddrCtrl::decode(ch.cnt >> DEST_CTRL_SHIFT)
+        };
+        let src_ctrl = AddrCtrl::decode(ch.cnt >> SRC_CTRL_SHIFT);
+
+        trace!(target: "dma", "DMA{} {:#010x} -> {:#010x}, {} x {} bytes",
+               idx, ch.src, ch.dst, count, width);
+        DmaTransfer {
+            channel: idx,
+            src: ch.src,
+            dst: ch.dst,
+            count,
+            word,
+            src_step: src_ctrl.step(width),
+            dst_step: dst_ctrl.step(width),
+        }
+    }
+
+    /// Record the final addresses of a transfer and raise its interrupt
+    pub fn finish(&mut self, xfer: &DmaTransfer, src: u32, dst: u32,
+                  irq: &mut IrqController) {
+        let idx = xfer.channel;
+        let ch = &mut self.channels[idx];
+        ch.src = src & Dma::src_mask(idx);
+        ch.dst = dst & Dma::dst_mask(idx);
+
+        if ch.cnt & DMA_REPEAT != 0 && ch.timing() != DmaTiming::Immediate {
+            if AddrCtrl::decode(ch.cnt >> DEST_CTRL_SHIFT) == AddrCtrl::IncrementReload {
+                ch.dst = ch.dad & Dma::dst_mask(idx);
+            }
+        }
+        else {
+            ch.cnt &= !DMA_ENABLE;
+        }
+
+        if ch.cnt & DMA_IRQ != 0 {
+            irq.request(DMA_IRQS[idx]);
+        }
+    }
+
+    /// Only the control register can be read back
+    pub fn read16(&self, addr: Address) -> u16 {
+        let off = addr - DMA0SAD;
+        match off % DMA_REG_STRIDE {
+            CNT_H => self.channels[off / DMA_REG_STRIDE].cnt,
+            _ => 0,
+        }
+    }
+
+    /// Write the register half at addr, keeping the bits outside mask
+    pub fn write16(&mut self, addr: Address, val: u16, mask: u16) {
+        let off = addr - DMA0SAD;
+        let idx = off / DMA_REG_STRIDE;
+        let ch = &mut self.channels[idx];
+        let merge = |old: u16| (old & !mask) | (val & mask);
+
+        match off % DMA_REG_STRIDE {
+            SAD_L => ch.sad = (ch.sad & 0xFFFF0000) | merge(ch.sad as u16) as u32,
+            SAD_H => ch.sad = (ch.sad & 0x0000FFFF) | (merge((ch.sad >> 16) as u16) as u32) << 16,
+            DAD_L => ch.dad = (ch.dad & 0xFFFF0000) | merge(ch.dad as u16) as u32,
+            DAD_H => ch.dad = (ch.dad & 0x0000FFFF) | (merge((ch.dad >> 16) as u16) as u32) << 16,
+            CNT_L => ch.count = merge(ch.count),
+            CNT_H => {
+                let was_enabled = ch.is_enabled();
+                ch.cnt = merge(ch.cnt) & DMA_CNT_MASK;
+                if !was_enabled && ch.is_enabled() {
+                    ch.src = ch.sad & Dma::src_mask(idx);
+                    ch.dst = ch.dad & Dma::dst_mask(idx);
+                    ch.pending = ch.timing() == DmaTiming::Immediate;
+                }
+                else if !ch.is_enabled() {
+                    ch.pending = false;
+                }
+            },
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl fmt::Display for Dma {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, ch) in self.channels.iter().enumerate() {
+            writeln![f, "DMA{}: {:#010x} -> {:#010x} count:{:#06x} cnt:{:#06x}",
+                   i, ch.sad, ch.dad, ch.count, ch.cnt]?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gba_apu::{Apu, FIFO_A, FIFO_B, SOUNDCNT_H, SOUNDCNT_X};
+    use prelude::*;
+
+    fn reg(idx: usize, off: Address) -> Address { DMA0SAD + idx * DMA_REG_STRIDE + off }
+
+    fn setup(dma: &mut Dma, idx: usize, sad: u32, dad: u32, count: u16, cnt: u16) {
+        dma.write16(reg(idx, SAD_L), sad as u16, 0xFFFF);
+        dma.write16(reg(idx, SAD_H), (sad >> 16) as u16, 0xFFFF);
+        dma.write16(reg(idx, DAD_L), dad as u16, 0xFFFF);
+        dma.write16(reg(idx, DAD_H), (dad >> 16) as u16, 0xFFFF);
+        dma.write16(reg(idx, CNT_L), count, 0xFFFF);
+        dma.write16(reg(idx, CNT_H), cnt, 0xFFFF);
+    }
+
+    fn timing(t: u16) -> u16 { DMA_ENABLE | t << DMA_TIMING_SHIFT }
+
+    #[test]
+    fn channels_start_on_their_timing() {
+        let mut dma = Dma::default();
+        setup(&mut dma, 0, 0x02000000, 0x03000000, 4, timing(0));
+        setup(&mut dma, 1, 0x02000000, 0x03000000, 4, timing(1));
+        setup(&mut dma, 2, 0x02000000, 0x03000000, 4, timing(2));
+        setup(&mut dma, 3, 0x02000000, 0x03000000, 4, timing(3));
+        let pending = |dma: &Dma| (0..NUM_DMA_CHANNELS)
+            .map(|i| dma.channel(i).is_pending())
+            .collect::<Vec<_>>();
+
+        // Only the immediate channel starts on enabling
+        assert_eq!(pending(&dma), [true, false, false, false]);
+        dma.start(0);
+        assert_eq!(dma.next_pending(), None);
+
+        dma.trigger(DmaTiming::HBlank);
+        assert_eq!(pending(&dma), [false, false, true, false]);
+        dma.trigger(DmaTiming::VBlank);
+        assert_eq!(pending(&dma), [false, true, true, false]);
+        assert_eq!(dma.next_pending(), Some(1));
+
+        // Disabling a channel drops its waiting transfer
+        dma.write16(reg(2, CNT_H), 0, 0xFFFF);
+        assert_eq!(pending(&dma), [false, true, false, false]);
+    }
+
+    #[test]
+    fn repeat_reloads_the_destination() {
+        let mut dma = Dma::default();
+        let mut irq = IrqController::default();
+        let reload = 3 << DEST_CTRL_SHIFT;
+        setup(&mut dma, 0, 0x02000000, 0x06000000, 8, timing(2) | DMA_REPEAT | reload);
+        setup(&mut dma, 1, 0x02000000, 0x06000000, 8, timing(2) | DMA_REPEAT);
+        setup(&mut dma, 2, 0x02000000, 0x06000000, 8, timing(2));
+        setup(&mut dma, 3, 0x02000000, 0x06000000, 8, timing(0) | DMA_REPEAT | DMA_IRQ);
+        dma.trigger(DmaTiming::HBlank);
+
+        let mut run = |dma: &mut Dma, idx| {
+            let xfer = dma.start(idx);
+            let end = xfer.count * 2;
+            dma.finish(&xfer, xfer.src + end, xfer.dst + end, &mut irq);
+            dma.start(idx).dst
+        };
+        assert_eq!(run(&mut dma, 0), 0x06000000);
+        assert_eq!(run(&mut dma, 1), 0x06000010);
+        assert!(dma.channel(0).is_enabled() && dma.channel(1).is_enabled());
+
+        // Without repeat, or started immediately, a channel runs once
+        run(&mut dma, 2);
+        run(&mut dma, 3);
+        assert!(!dma.channel(2).is_enabled() && !dma.channel(3).is_enabled());
+        assert_eq!(dma.read16(reg(3, CNT_H)) & DMA_ENABLE, 0);
+        assert_eq!(irq.if_(), Interrupt::Dma3 as u16);
+    }
+
+    #[test]
+    fn zero_count_is_the_maximum() {
+        let mut dma = Dma::default();
+        for idx in 0..NUM_DMA_CHANNELS {
+            setup(&mut dma, idx, 0x02000000, 0x03000000, 0, timing(0) | DMA_WORD);
+        }
+        let counts = (0..NUM_DMA_CHANNELS).map(|i| dma.start(i).count).collect::<Vec<_>>();
+        assert_eq!(counts, [0x4000, 0x4000, 0x4000, 0x10000]);
+
+        // Only 14 bits of the count reach DMA0-2
+        setup(&mut dma, 1, 0x02000000, 0x03000000, 0xC000, timing(1) | DMA_WORD);
+        dma.trigger(DmaTiming::VBlank);
+        assert_eq!(dma.start(1).count, 0x4000);
+    }
+
+    #[test]
+    fn fifos_refill_on_timer_overflow() {
+        let mut apu = Apu::default();
+        let mut dma = Dma::default();
+        apu.write16(SOUNDCNT_X, 0x0080, 0xFFFF);
+        // FIFO A on timer 0, FIFO B on timer 1
+        apu.write16(SOUNDCNT_H, 0x4000, 0xFFFF);
+        for _ in 0..8 {
+            apu.write16(FIFO_A, 0x0101, 0xFFFF);
+            apu.write16(FIFO_A + 2, 0x0101, 0xFFFF);
+        }
+        setup(&mut dma, 1, 0x02000000, FIFO_A as u32, 0, timing(3) | DMA_REPEAT);
+        setup(&mut dma, 2, 0x02000000, FIFO_B as u32, 0, timing(3) | DMA_REPEAT);
+
+        // Timer 1 only drains the empty FIFO B; FIFO A keeps its 32
+        // samples and wants a refill once 16 or fewer remain
+        assert_eq!(apu.timer_overflow(1, 1), [FIFO_B]);
+        assert!(apu.timer_overflow(0, 15).is_empty());
+        let refill = apu.timer_overflow(0, 1);
+        assert_eq!(refill, [FIFO_A]);
+
+        for fifo in refill {
+            dma.request_fifo(fifo);
+        }
+        assert_eq!(dma.next_pending(), Some(1));
+        let xfer = dma.start(1);
+        assert_eq!((xfer.count, xfer.word, xfer.dst, xfer.dst_step),
+                   (4, true, FIFO_A as u32, 0));
+        assert!(!dma.channel(2).is_pending());
+    }
+}
@@ -0,0 +1,91 @@
+use core::fmt;
+#[cfg(feature = "std")]
+use std::error::Error;
+#[cfg(feature = "std")]
+use std::io;
+
+use gba_mem::Address;
+use prelude::*;
+
+/// Everything that can go wrong while setting up or running the emulator
+#[derive(Debug)]
+pub enum GbaError {
+    /// Reading or writing a file failed
+    #[cfg(feature = "std")]
+    Io(io::Error),
+    /// An image too big for the memory it goes in
+    RomTooLarge {
+        /// Which memory
+        region: &'static str,
+        /// Bytes in the image
+        size: usize,
+        /// Bytes the memory holds
+        max: usize,
+    },
+    /// A BIOS image of the wrong size
+    BadBios(String),
+    /// A ROM whose header doesn't check out
+    BadRomHeader(String),
+    /// An address with nothing behind it
+    UnmappedAddress(Address),
+    /// A battery save that doesn't fit the cartridge
+    InvalidSaveFile(String),
+    /// A save state that is corrupt, or for another version or game
+    InvalidSaveState(String),
+    /// A config file or setting that can't be used
+    InvalidConfig(String),
+    /// A cheat code that can't be parsed
+    InvalidCheat(String),
+    /// Valid, but of a kind that can't be applied
+    UnsupportedCheat(String),
+    /// A compressed ROM that couldn't be unpacked
+    Archive(String),
+    /// A Lua script that failed to load or run
+    Script(String),
+    /// Native code that couldn't be generated
+    Jit(String),
+}
+
+/// Result of anything that can fail with a GbaError
+pub type GbaResult<T> = Result<T, GbaError>;
+
+impl fmt::Display for GbaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            #[cfg(feature = "std")]
+            GbaError::Io(ref e) => write![f, "{}", e],
+            GbaError::RomTooLarge { region, size, max } =>
+                write![f, "{} Bytes is too big for the {} memory region ({} Bytes)",
+                       size, region, max],
+            GbaError::BadBios(ref msg) => write![f, "Bad BIOS: {}", msg],
+            GbaError::BadRomHeader(ref msg) => write![f, "Bad ROM header: {}", msg],
+            GbaError::UnmappedAddress(addr) =>
+                write![f, "Access to unmapped address {:#010x}", addr],
+            GbaError::InvalidSaveFile(ref msg) => write![f, "Invalid save file: {}", msg],
+            GbaError::InvalidSaveState(ref msg) => write![f, "Invalid save state: {}", msg],
+            GbaError::InvalidConfig(ref msg) => write![f, "Invalid config: {}", msg],
+            GbaError::InvalidCheat(ref msg) => write![f, "Invalid cheat: {}", msg],
+            GbaError::UnsupportedCheat(ref msg) => write![f, "Unsupported cheat: {}", msg],
+            GbaError::Archive(ref msg) => write![f, "Bad archive: {}", msg],
+            GbaError::Script(ref msg) => write![f, "Script error: {}", msg],
+            GbaError::Jit(ref msg) => write![f, "JIT error: {}", msg],
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for GbaError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            GbaError::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for GbaError {
+    fn from(e: io::Error) -> GbaError {
+        GbaError::Io(e)
+    }
+}
@@ -0,0 +1,68 @@
+#[cfg(feature = "record")]
+use gba_video::record::Recorder;
+use gba_system::Gba;
+
+/// Gameplay being recorded for --record-video. Without the record feature
+/// asking for it is an error, and it does nothing.
+#[derive(Debug, Default)]
+#[allow(missing_copy_implementations)] // Only empty without the record feature
+pub struct Capture {
+    #[cfg(feature = "record")]
+    recorder: Option<Recorder>,
+}
+
+impl Capture {
+    /// Start recording to path, if there is one
+    #[cfg(feature = "record")]
+    pub fn start(path: Option<&str>, gba: &Gba) -> Result<Capture, String> {
+        let recorder = match path {
+            Some(path) => Some(Recorder::create(path, gba.mem().io().apu.sample_rate())
+                .map_err(|e| format!("Failed to record to {}: {}", path, e))?),
+            None => None,
+        };
+        Ok(Capture { recorder })
+    }
+
+    /// Only without a path, since there is nothing to record with
+    #[cfg(not(feature = "record"))]
+    pub fn start(path: Option<&str>, _gba: &Gba) -> Result<Capture, String> {
+        match path {
+            Some(_) => Err("--record-video needs the record feature".to_string()),
+            None => Ok(Capture::default()),
+        }
+    }
+
+    /// Add the frame just run and the samples the APU mixed during it.
+    /// Recording stops, with a warning, if it fails.
+    #[cfg(feature = "record")]
+    pub fn record(&mut self, gba: &Gba, samples: &[i16]) {
+        let failed = match self.recorder {
+            Some(ref mut recorder) => recorder.record(&gba.screenshot(), samples).err(),
+            None => None,
+        };
+        if let Some(e) = failed {
+            warn!(target: "frontend", "stopping the recording: {}", e);
+            self.recorder = None;
+        }
+    }
+
+    /// Does nothing
+    #[cfg(not(feature = "record"))]
+    pub fn record(&mut self, _gba: &Gba, _samples: &[i16]) {}
+
+    /// Stop recording and finish the file
+    #[cfg(feature = "record")]
+    pub fn finish(&mut self) {
+        if let Some(recorder) = self.recorder.take() {
+            let frames = recorder.frames();
+            match recorder.finish() {
+                Ok(()) => info!(target: "frontend", "Recorded {} frames", frames),
+                Err(e) => warn!(target: "frontend", "failed to finish the recording: {}", e),
+            }
+        }
+    }
+
+    /// Does nothing
+    #[cfg(not(feature = "record"))]
+    pub fn finish(&mut self) {}
+}
@@ -0,0 +1,632 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use pollster;
+use wgpu;
+use winit::dpi::PhysicalSize;
+use winit::event::{ElementState, Event, KeyEvent, WindowEvent};
+use winit::event_loop::{ControlFlow, EventLoop};
+use winit::keyboard::{KeyCode, PhysicalKey};
+use winit::window::{Fullscreen, Window, WindowBuilder};
+
+use gba_apu::inspect::Channel;
+use gba_config::{Config, Filter, VideoConfig};
+use gba_frontend::capture::Capture;
+use gba_frontend::{end_session, post_process, start_session, toggle_layer, toggle_mute,
+                   toggle_post_process, toggle_solo, Options};
+use gba_frontend::pacing::{self, FramePacer, Speed};
+use gba_keypad::Button;
+use gba_keypad::hotkey::HotkeyAction;
+use gba_ppu::{Layer, SCREEN_HEIGHT, SCREEN_WIDTH};
+use gba_state::rewind::RewindBuffer;
+use gba_system::Gba;
+use gba_video::{Frame, FrameMetadata, PixelFormat, Presenter, VideoSink};
+use gba_video::filter::PostProcess;
+
+// Same rewind as the SDL frontend
+const REWIND_INTERVAL: u64 = 10;
+const REWIND_SNAPSHOTS: usize = 360;
+const REWIND_STEP: u64 = 60;
+
+// Put in front of every scaling shader
+const COMMON_SHADER: &str = include_str!("shaders/common.wgsl");
+
+// Scaling shaders that come with the emulator, by the name --shader takes
+const BUILTIN_SHADERS: [(&str, &str); 4] = [
+    ("nearest", include_str!("shaders/nearest.wgsl")),
+    ("linear", include_str!("shaders/linear.wgsl")),
+    ("sharp-bilinear", include_str!("shaders/sharp_bilinear.wgsl")),
+    ("scale2x", include_str!("shaders/scale2x.wgsl")),
+];
+
+// What a keyboard key does
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Binding {
+    Button(Button),
+    Hotkey(HotkeyAction),
+    Fullscreen,
+}
+
+// The SDL frontend's default layout. Keys named in [keys] are SDL's, so
+// they aren't used here.
+const DEFAULT_KEYS: [(KeyCode, Binding); 33] = [
+    (KeyCode::KeyZ,        Binding::Button(Button::A)),
+    (KeyCode::KeyX,        Binding::Button(Button::B)),
+    (KeyCode::Backspace,   Binding::Button(Button::Select)),
+    (KeyCode::Enter,       Binding::Button(Button::Start)),
+    (KeyCode::ArrowRight,  Binding::Button(Button::Right)),
+    (KeyCode::ArrowLeft,   Binding::Button(Button::Left)),
+    (KeyCode::ArrowUp,     Binding::Button(Button::Up)),
+    (KeyCode::ArrowDown,   Binding::Button(Button::Down)),
+    (KeyCode::KeyS,        Binding::Button(Button::R)),
+    (KeyCode::KeyA,        Binding::Button(Button::L)),
+    (KeyCode::Tab,         Binding::Hotkey(HotkeyAction::FastForward)),
+    (KeyCode::Backquote,   Binding::Hotkey(HotkeyAction::SlowMotion)),
+    (KeyCode::F6,          Binding::Hotkey(HotkeyAction::Unthrottle)),
+    (KeyCode::F5,          Binding::Hotkey(HotkeyAction::SaveState)),
+    (KeyCode::F7,          Binding::Hotkey(HotkeyAction::Rewind)),
+    (KeyCode::F8,          Binding::Hotkey(HotkeyAction::LoadState)),
+    (KeyCode::F12,         Binding::Hotkey(HotkeyAction::Screenshot)),
+    (KeyCode::F2,          Binding::Hotkey(HotkeyAction::ColorCorrection)),
+    (KeyCode::F3,          Binding::Hotkey(HotkeyAction::FrameBlending)),
+    (KeyCode::F4,          Binding::Hotkey(HotkeyAction::Scanlines)),
+    (KeyCode::F11,         Binding::Fullscreen),
+    (KeyCode::Digit1,      Binding::Hotkey(HotkeyAction::MuteChannel(Channel::Square1))),
+    (KeyCode::Digit2,      Binding::Hotkey(HotkeyAction::MuteChannel(Channel::Square2))),
+    (KeyCode::Digit3,      Binding::Hotkey(HotkeyAction::MuteChannel(Channel::Wave))),
+    (KeyCode::Digit4,      Binding::Hotkey(HotkeyAction::MuteChannel(Channel::Noise))),
+    (KeyCode::Digit5,      Binding::Hotkey(HotkeyAction::MuteChannel(Channel::FifoA))),
+    (KeyCode::Digit6,      Binding::Hotkey(HotkeyAction::MuteChannel(Channel::FifoB))),
+    (KeyCode::Digit7,      Binding::Hotkey(HotkeyAction::ToggleLayer(Layer::Bg0))),
+    (KeyCode::Digit8,      Binding::Hotkey(HotkeyAction::ToggleLayer(Layer::Bg1))),
+    (KeyCode::Digit9,      Binding::Hotkey(HotkeyAction::ToggleLayer(Layer::Bg2))),
+    (KeyCode::Digit0,      Binding::Hotkey(HotkeyAction::ToggleLayer(Layer::Bg3))),
+    (KeyCode::Minus,       Binding::Hotkey(HotkeyAction::ToggleLayer(Layer::Obj))),
+    (KeyCode::Escape,      Binding::Hotkey(HotkeyAction::Quit)),
+];
+
+fn lookup_key(key: KeyCode) -> Option<Binding> {
+    DEFAULT_KEYS.iter().find(|&&(k, _)| k == key).map(|&(_, binding)| binding)
+}
+
+// The scaling shader's source, common part included. A name that isn't
+// built in is read as a file.
+fn shader_source(video: &VideoConfig) -> Result<String, String> {
+    let name = match video.shader {
+        Some(ref name) => name.as_str(),
+        None => match video.filter {
+            Filter::Nearest => "nearest",
+            Filter::Linear => "linear",
+        },
+    };
+    let body = match BUILTIN_SHADERS.iter().find(|&&(n, _)| n == name) {
+        Some(&(_, body)) => body.to_string(),
+        None => fs::read_to_string(name)
+            .map_err(|e| format!("Failed to read shader {}: {}", name, e))?,
+    };
+    Ok(format!("{}\n{}", COMMON_SHADER, body))
+}
+
+// Where in the window the frame goes, as x, y, width and height: as large
+// as fits at the frame's own aspect ratio, centered between black bars.
+// Integer scaling rounds down to a whole multiple unless the window is too
+// small for even one.
+fn viewport(window: (u32, u32), frame: (usize, usize), integer: bool) -> [f32; 4] {
+    let (window_w, window_h) = (window.0 as f32, window.1 as f32);
+    let (frame_w, frame_h) = (frame.0 as f32, frame.1 as f32);
+    let mut scale = (window_w / frame_w).min(window_h / frame_h);
+    if integer && scale >= 1.0 {
+        scale = scale.floor();
+    }
+    let (w, h) = (frame_w * scale, frame_h * scale);
+    [((window_w - w) / 2.0).floor(), ((window_h - h) / 2.0).floor(), w, h]
+}
+
+// The uploaded frame and what the shader reads it through
+struct FrameTexture {
+    texture: wgpu::Texture,
+    bind_group: wgpu::BindGroup,
+    size: (usize, usize),
+}
+
+/// Window drawn by the GPU, the frame scaled by a shader. There's no sound
+/// yet; use the SDL frontend for that.
+#[allow(missing_debug_implementations)]
+pub struct GpuFrontend {
+    window: Arc<Window>,
+    surface: wgpu::Surface<'static>,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    surface_config: wgpu::SurfaceConfiguration,
+    pipeline: wgpu::RenderPipeline,
+    layout: wgpu::BindGroupLayout,
+    params: wgpu::Buffer,
+    nearest: wgpu::Sampler,
+    linear: wgpu::Sampler,
+    frame: Option<FrameTexture>, // Made once the first frame's size is known
+    integer_scaling: bool,
+    post: PostProcess, // Before the game's color correction setting
+    hotkeys: VecDeque<HotkeyAction>,
+    state_path: PathBuf,
+    rewind: RewindBuffer,
+    pacer: FramePacer,
+    capture: Capture,
+}
+
+impl GpuFrontend {
+    /// Open a window on event_loop set up as config says, saving states to
+    /// state_path
+    pub fn open(config: &Config, event_loop: &EventLoop<()>, state_path: PathBuf)
+                -> Result<GpuFrontend, String> {
+        let source = shader_source(&config.video)?;
+        let scale = config.video.scale;
+        let fullscreen = if config.video.fullscreen {
+            Some(Fullscreen::Borderless(None))
+        }
+        else {
+            None
+        };
+        let window = WindowBuilder::new()
+            .with_title("rusty-gba")
+            .with_inner_size(PhysicalSize::new(SCREEN_WIDTH as u32 * scale,
+                                               SCREEN_HEIGHT as u32 * scale))
+            .with_fullscreen(fullscreen)
+            .build(event_loop)
+            .map_err(|e| e.to_string())?;
+        let window = Arc::new(window);
+
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+        let surface = instance.create_surface(window.clone()).map_err(|e| e.to_string())?;
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::default(),
+            compatible_surface: Some(&surface),
+            force_fallback_adapter: false,
+        })).ok_or_else(|| "No GPU adapter can draw to the window".to_string())?;
+        let (device, queue) = pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor {
+            label: None,
+            required_features: wgpu::Features::empty(),
+            required_limits: wgpu::Limits::downlevel_webgl2_defaults()
+                .using_resolution(adapter.limits()),
+            memory_hints: wgpu::MemoryHints::default(),
+        }, None)).map_err(|e| e.to_string())?;
+
+        let size = window.inner_size();
+        let mut surface_config = surface
+            .get_default_config(&adapter, size.width.max(1), size.height.max(1))
+            .ok_or_else(|| "The GPU adapter can't draw to the window".to_string())?;
+        // The frame is uploaded as sRGB, so the output must be too for the
+        // colors to come out unchanged
+        let formats = surface.get_capabilities(&adapter).formats;
+        if let Some(&format) = formats.iter().find(|f| f.is_srgb()) {
+            surface_config.format = format;
+        }
+        // The pacer keeps time, not the display
+        surface_config.present_mode = wgpu::PresentMode::AutoNoVsync;
+        surface.configure(&device, &surface_config);
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("frame"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        // A shader from a file may not compile, which wgpu would otherwise
+        // treat as a bug in the emulator
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("scaling shader"),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("present"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &module,
+                entry_point: "vs_main",
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &module,
+                entry_point: "fs_main",
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: surface_config.format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+        if let Some(e) = pollster::block_on(device.pop_error_scope()) {
+            return Err(format!("Bad scaling shader: {}", e));
+        }
+
+        let params = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("params"),
+            size: 16,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let nearest = device.create_sampler(&wgpu::SamplerDescriptor {
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            ..wgpu::SamplerDescriptor::default()
+        });
+        let linear = device.create_sampler(&wgpu::SamplerDescriptor {
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..wgpu::SamplerDescriptor::default()
+        });
+
+        Ok(GpuFrontend {
+            window,
+            surface,
+            device,
+            queue,
+            surface_config,
+            pipeline,
+            layout,
+            params,
+            nearest,
+            linear,
+            frame: None,
+            integer_scaling: config.video.integer_scaling,
+            post: post_process(&config.video),
+            hotkeys: VecDeque::new(),
+            state_path,
+            rewind: RewindBuffer::new(REWIND_INTERVAL, REWIND_SNAPSHOTS),
+            pacer: FramePacer::default(),
+            capture: Capture::default(),
+        })
+    }
+
+    /// Speed and frame skip control the run loop; the hotkeys change them
+    pub fn pacer_mut(&mut self) -> &mut FramePacer {
+        &mut self.pacer
+    }
+
+    fn resize(&mut self, width: u32, height: u32) {
+        // Minimized windows have no area to draw into
+        if width > 0 && height > 0 {
+            self.surface_config.width = width;
+            self.surface_config.height = height;
+            self.surface.configure(&self.device, &self.surface_config);
+        }
+    }
+
+    fn toggle_fullscreen(&mut self) {
+        let fullscreen = match self.window.fullscreen() {
+            Some(_) => None,
+            None => Some(Fullscreen::Borderless(None)),
+        };
+        self.window.set_fullscreen(fullscreen);
+    }
+
+    fn key(&mut self, event: &KeyEvent, gba: &mut Gba) {
+        let binding = match event.physical_key {
+            PhysicalKey::Code(code) => lookup_key(code),
+            PhysicalKey::Unidentified(_) => None,
+        };
+        let pressed = event.state == ElementState::Pressed;
+        match binding {
+            Some(Binding::Button(button)) => gba.set_button(button, pressed),
+            Some(Binding::Hotkey(action)) if pressed && !event.repeat =>
+                self.hotkeys.push_back(action),
+            Some(Binding::Fullscreen) if pressed && !event.repeat => self.toggle_fullscreen(),
+            _ => {},
+        }
+    }
+
+    // Carry out an emulator action. Returns false if it was Quit.
+    fn handle_hotkey(&mut self, action: HotkeyAction, gba: &mut Gba) -> bool {
+        match action {
+            HotkeyAction::SaveState => {
+                match fs::write(&self.state_path, gba.save_state()) {
+                    Ok(()) => info!(target: "frontend", "Saved state to {}", self.state_path.display()),
+                    Err(e) => warn!(target: "frontend", "failed to save state: {}", e),
+                }
+            },
+            HotkeyAction::LoadState => {
+                let loaded = fs::read(&self.state_path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|data| gba.load_state(&data).map_err(|e| e.to_string()));
+                match loaded {
+                    Ok(()) => info!(target: "frontend", "Loaded state from {}", self.state_path.display()),
+                    Err(e) => warn!(target: "frontend", "failed to load state: {}", e),
+                }
+            },
+            HotkeyAction::Rewind => {
+                if let Err(e) = self.rewind.rewind(gba, REWIND_STEP) {
+                    warn!(target: "frontend", "failed to rewind: {}", e);
+                }
+            },
+            HotkeyAction::Screenshot => {
+                let path = self.state_path.with_extension(format!("{}.png", gba.frame()));
+                match gba.screenshot().save_png(&path) {
+                    Ok(()) => info!(target: "frontend", "Saved screenshot to {}", path.display()),
+                    Err(e) => warn!(target: "frontend", "failed to save screenshot: {}", e),
+                }
+            },
+            HotkeyAction::FastForward => self.toggle_speed(pacing::FAST_FORWARD),
+            HotkeyAction::SlowMotion => self.toggle_speed(pacing::SLOW_MOTION),
+            HotkeyAction::Unthrottle => self.toggle_speed(Speed::Unlimited),
+            HotkeyAction::MuteChannel(ch) => toggle_mute(gba, ch),
+            HotkeyAction::SoloChannel(ch) => toggle_solo(gba, ch),
+            HotkeyAction::ToggleLayer(layer) => toggle_layer(gba, layer),
+            HotkeyAction::Quit => return false,
+            _ => {},
+        }
+        true
+    }
+
+    fn toggle_speed(&mut self, speed: Speed) {
+        self.pacer.toggle_speed(speed);
+        info!(target: "frontend", "Speed: {}", self.pacer.speed());
+    }
+
+    // Run and show one frame. Returns false once the user has asked to quit.
+    fn step(&mut self, presenter: &mut Presenter, gba: &mut Gba) -> bool {
+        while let Some(action) = gba.take_hotkey() {
+            self.hotkeys.push_back(action);
+        }
+        while let Some(action) = self.hotkeys.pop_front() {
+            if let Some(post) = toggle_post_process(action, presenter.post_process()) {
+                presenter.set_post_process(post);
+            }
+            else if !self.handle_hotkey(action, gba) {
+                return false;
+            }
+        }
+
+        gba.run_frame();
+        self.rewind.capture(gba);
+        let samples = gba.mem_mut().io_mut().apu.drain_samples();
+        self.capture.record(gba, &samples);
+        if self.pacer.should_show() {
+            presenter.present_ppu(&gba.mem().io().ppu, self);
+        }
+        self.pacer.wait();
+        true
+    }
+
+    /// Play until the window is closed
+    pub fn run(&mut self, event_loop: EventLoop<()>, gba: &mut Gba) -> Result<(), String> {
+        let mut presenter = Presenter::default();
+        presenter.set_post_process(PostProcess {
+            color_correction: gba.settings().color_correction,
+            ..self.post
+        });
+
+        let result = event_loop.run(|event, target| {
+            target.set_control_flow(ControlFlow::Poll);
+            match event {
+                Event::WindowEvent { event, .. } => match event {
+                    WindowEvent::CloseRequested => target.exit(),
+                    WindowEvent::Resized(size) => self.resize(size.width, size.height),
+                    WindowEvent::KeyboardInput { ref event, .. } => self.key(event, gba),
+                    _ => {},
+                },
+                Event::AboutToWait if !self.step(&mut presenter, gba) => target.exit(),
+                _ => {},
+            }
+        });
+        self.capture.finish();
+        result.map_err(|e| e.to_string())
+    }
+
+    // Remake the texture the frame goes in if the frame size has changed
+    fn fit_frame_texture(&mut self, width: usize, height: usize) {
+        if self.frame.as_ref().is_none_or(|f| f.size != (width, height)) {
+            let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("frame"),
+                size: wgpu::Extent3d {
+                    width: width as u32,
+                    height: height as u32,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            });
+            let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+            let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("frame"),
+                layout: &self.layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&self.nearest),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::Sampler(&self.linear),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: self.params.as_entire_binding(),
+                    },
+                ],
+            });
+            self.frame = Some(FrameTexture {
+                texture,
+                bind_group,
+                size: (width, height),
+            });
+        }
+    }
+}
+
+impl VideoSink for GpuFrontend {
+    fn present(&mut self, frame: &Frame, _meta: &FrameMetadata) {
+        let output = match self.surface.get_current_texture() {
+            Ok(output) => output,
+            Err(wgpu::SurfaceError::Lost) | Err(wgpu::SurfaceError::Outdated) => {
+                // Try again next frame
+                self.surface.configure(&self.device, &self.surface_config);
+                return;
+            },
+            Err(e) => {
+                warn!(target: "frontend", "failed to draw frame: {}", e);
+                return;
+            },
+        };
+
+        let window = (self.surface_config.width, self.surface_config.height);
+        let view = viewport(window, (frame.width, frame.height), self.integer_scaling);
+        let mut params = Vec::with_capacity(16);
+        for &val in &[frame.width as f32, frame.height as f32, view[2], view[3]] {
+            params.extend_from_slice(&val.to_le_bytes());
+        }
+        self.queue.write_buffer(&self.params, 0, &params);
+
+        let pitch = frame.pitch() as u32;
+        let (width, height) = (frame.width as u32, frame.height as u32);
+        self.fit_frame_texture(frame.width, frame.height);
+        let texture = self.frame.as_ref().unwrap();
+        self.queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &texture.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            frame.data,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(pitch),
+                rows_per_image: Some(height),
+            },
+            wgpu::Extent3d { width, height, depth_or_array_layers: 1 });
+
+        let target = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: None,
+        });
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: None,
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &target,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            pass.set_viewport(view[0], view[1], view[2], view[3], 0.0, 1.0);
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &texture.bind_group, &[]);
+            pass.draw(0..3, 0..1);
+        }
+        self.queue.submit(Some(encoder.finish()));
+        output.present();
+    }
+
+    fn preferred_format(&self) -> PixelFormat {
+        PixelFormat::Rgba8888
+    }
+}
+
+/// Open a window drawn by the GPU and play the game
+pub fn run(opts: &Options, config: &Config, gba: &mut Gba) -> Result<(), String> {
+    if opts.script.is_some() {
+        return Err("--script needs the SDL frontend".to_string());
+    }
+    start_session(opts, config, gba)?;
+
+    let event_loop = EventLoop::new().map_err(|e| e.to_string())?;
+    let mut frontend = GpuFrontend::open(config, &event_loop, opts.state_path(config))?;
+    frontend.pacer_mut().set_speed(opts.speed);
+    frontend.pacer_mut().set_frame_skip(opts.frame_skip);
+    frontend.capture = Capture::start(opts.record_video.as_deref(), gba)?;
+    frontend.run(event_loop, gba)?;
+
+    end_session(opts, config, gba)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wgpu::naga;
+    use wgpu::naga::valid::{Capabilities, ValidationFlags, Validator};
+
+    #[test]
+    fn viewport_keeps_aspect_ratio() {
+        let frame = (SCREEN_WIDTH, SCREEN_HEIGHT);
+        assert_eq!(viewport((480, 320), frame, false), [0.0, 0.0, 480.0, 320.0]);
+        // Bars above and below
+        assert_eq!(viewport((720, 600), frame, false), [0.0, 60.0, 720.0, 480.0]);
+        // A whole multiple, centered
+        assert_eq!(viewport((800, 600), frame, true), [40.0, 60.0, 720.0, 480.0]);
+        // Too small for a whole multiple
+        assert_eq!(viewport((120, 120), frame, true), [0.0, 20.0, 120.0, 80.0]);
+    }
+
+    #[test]
+    fn builtin_shaders_validate() {
+        for &(name, _) in BUILTIN_SHADERS.iter() {
+            let video = VideoConfig { shader: Some(name.to_string()), ..VideoConfig::default() };
+            let source = shader_source(&video).unwrap();
+            let module = naga::front::wgsl::parse_str(&source)
+                .unwrap_or_else(|e| panic!("{}: {}", name, e.emit_to_string(&source)));
+            Validator::new(ValidationFlags::all(), Capabilities::default())
+                .validate(&module)
+                .unwrap_or_else(|e| panic!("{}: {}", name, e.emit_to_string(&source)));
+        }
+    }
+}
@@ -0,0 +1,114 @@
+// What `gba info <rom>` prints: the header, save type and entry point, for
+// working out why a ROM won't load or boot without running it, and the
+// code `gba disasm` prints
+
+use std::fmt;
+
+use gba_config::{GameId, SaveType};
+use gba_cpu::disasm::{disassemble, DisasmLine};
+use gba_mem::rom_header::{RomHeader, FIXED_VALUE};
+
+const ROM_BASE: u32 = 0x08000000;
+const ROM_MIRROR_MASK: u32 = 0x01FFFFFF; // The ROM is seen three times over
+const ROM_END: u32 = 0x0E000000;
+
+// Instructions shown from where the header's branch goes
+const START_LINES: usize = 8;
+
+/// What --info prints about a ROM
+#[derive(Debug)]
+pub struct RomInfo {
+    header: RomHeader,
+    id: GameId,
+    size: usize,
+    expected_checksum: u8,
+    save: Option<(SaveType, String)>,
+    entry: Vec<DisasmLine>, // The branch at the start of the header
+    start: Vec<DisasmLine>, // Where it goes, if that's in the ROM
+}
+
+// Where the ARM branch instr at addr goes, if it is one
+fn branch_target(instr: u32, addr: u32) -> Option<u32> {
+    if instr & 0x0E000000 != 0x0A000000 {
+        return None;
+    }
+    let offset = ((instr << 8) as i32 >> 6) as u32;
+    Some(addr.wrapping_add(8).wrapping_add(offset))
+}
+
+impl RomInfo {
+    /// Look over rom
+    pub fn new(rom: &[u8]) -> RomInfo {
+        let header = RomHeader::parse(rom);
+        let entry = disassemble(&rom[..rom.len().min(4)], ROM_BASE, false);
+        let start = branch_target(header.entry, ROM_BASE)
+            .map(|target| target.wrapping_sub(ROM_BASE) as usize)
+            .filter(|&offset| offset < rom.len())
+            .map_or(Vec::new(), |offset| {
+                let end = rom.len().min(offset + 4 * START_LINES);
+                disassemble(&rom[offset..end], ROM_BASE + offset as u32, false)
+            });
+
+        RomInfo {
+            header,
+            id: GameId::from_rom(rom),
+            size: rom.len(),
+            expected_checksum: RomHeader::expected_checksum(rom),
+            save: SaveType::detect(rom),
+            entry,
+            start,
+        }
+    }
+}
+
+fn ok_or(ok: bool, problem: String) -> String {
+    if ok { "ok".to_string() } else { problem }
+}
+
+impl fmt::Display for RomInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let h = &self.header;
+        writeln![f, "Title:        {}", h.title]?;
+        writeln![f, "Game code:    {}", h.game_code]?;
+        writeln![f, "Maker code:   {}", h.maker_code]?;
+        writeln![f, "Version:      {}", h.version]?;
+        writeln![f, "Unit code:    {:#04x}", h.unit_code]?;
+        writeln![f, "Device type:  {:#04x}", h.device_type]?;
+        writeln![f, "ROM size:     {} bytes ({} KiB)", self.size, self.size / 1024]?;
+        writeln![f, "ROM hash:     {}", self.id.hash_key()]?;
+        writeln![f, "Fixed value:  {:#04x} ({})", h.fixed,
+                 ok_or(h.fixed == FIXED_VALUE, format!("expected {:#04x}", FIXED_VALUE))]?;
+        writeln![f, "Checksum:     {:#04x} ({})", h.checksum,
+                 ok_or(h.checksum == self.expected_checksum,
+                       format!("expected {:#04x}", self.expected_checksum))]?;
+        match self.save {
+            Some((save_type, ref library)) =>
+                writeln![f, "Save type:    {:?} ({})", save_type, library]?,
+            None => writeln![f, "Save type:    none found"]?,
+        }
+
+        writeln![f, "Entry point:"]?;
+        for line in self.entry.iter() {
+            writeln![f, "  {}", line]?;
+        }
+        if let Some(first) = self.start.first() {
+            writeln![f, "Start of the game at {:#010x}:", first.addr]?;
+            for line in self.start.iter() {
+                writeln![f, "  {}", line]?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Disassemble len bytes of rom from the address start, which can be in any
+/// of the ROM's mirrors. Stops early at the end of the ROM.
+pub fn disassemble_rom(rom: &[u8], start: u32, len: u32, thumb: bool)
+                       -> Result<Vec<DisasmLine>, String> {
+    let offset = (start & ROM_MIRROR_MASK) as usize;
+    if !(ROM_BASE..ROM_END).contains(&start) || offset >= rom.len() {
+        return Err(format!("{:#010x} isn't in the {} byte ROM", start, rom.len()));
+    }
+    let end = rom.len().min(offset + len as usize);
+    Ok(disassemble(&rom[offset..end], start, thumb))
+}
@@ -0,0 +1,443 @@
+// The libretro API, so frontends such as RetroArch can load the emulator as
+// a core. The entry points and structs follow libretro.h:
+// https://github.com/libretro/RetroArch/blob/master/libretro-common/include/libretro.h
+//
+// Build the core with
+//
+//     cargo rustc --release --lib --crate-type cdylib --features libretro
+//
+// and load target/release/libgba.so (gba.dll, libgba.dylib) in the frontend.
+//
+// libretro has one core per process and calls it from one thread, so the
+// machine lives in a thread local rather than behind a lock; Gba isn't Send.
+//
+// The entry points taking pointers are unsafe; their contract is libretro.h.
+#![allow(unsafe_code, clippy::missing_safety_doc)]
+
+use std::cell::RefCell;
+use std::ffi::CStr;
+use std::mem;
+use std::os::raw::{c_char, c_uint, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+use gba_cheats::{Cheat, CheatEngine, CheatFormat};
+use gba_config::Config;
+use gba_error::GbaResult;
+use gba_keypad::Button;
+use gba_ppu::{REFRESH_RATE, SCREEN_HEIGHT, SCREEN_WIDTH};
+use gba_system::Gba;
+use gba_video::{convert, PixelFormat};
+
+const RETRO_API_VERSION: c_uint = 1;
+
+const RETRO_ENVIRONMENT_SHUTDOWN: c_uint = 7;
+const RETRO_ENVIRONMENT_SET_PIXEL_FORMAT: c_uint = 10;
+const RETRO_PIXEL_FORMAT_RGB565: c_uint = 2;
+
+const RETRO_DEVICE_JOYPAD: c_uint = 1;
+const RETRO_REGION_NTSC: c_uint = 0;
+const RETRO_MEMORY_SAVE_RAM: c_uint = 0;
+
+// RetroPad buttons, by RETRO_DEVICE_ID_JOYPAD_*
+const JOYPAD: [(c_uint, Button); 10] = [
+    (0, Button::B),
+    (2, Button::Select),
+    (3, Button::Start),
+    (4, Button::Up),
+    (5, Button::Down),
+    (6, Button::Left),
+    (7, Button::Right),
+    (8, Button::A),
+    (10, Button::L),
+    (11, Button::R),
+];
+
+/// struct retro_system_info
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct RetroSystemInfo {
+    library_name: *const c_char,
+    library_version: *const c_char,
+    valid_extensions: *const c_char,
+    need_fullpath: bool,
+    block_extract: bool,
+}
+
+/// struct retro_game_geometry
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct RetroGameGeometry {
+    base_width: c_uint,
+    base_height: c_uint,
+    max_width: c_uint,
+    max_height: c_uint,
+    aspect_ratio: f32,
+}
+
+/// struct retro_system_timing
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct RetroSystemTiming {
+    fps: f64,
+    sample_rate: f64,
+}
+
+/// struct retro_system_av_info
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct RetroSystemAvInfo {
+    geometry: RetroGameGeometry,
+    timing: RetroSystemTiming,
+}
+
+/// struct retro_game_info
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct RetroGameInfo {
+    path: *const c_char,
+    data: *const c_void,
+    size: usize,
+    meta: *const c_char,
+}
+
+/// retro_environment_t
+pub type RetroEnvironment = extern "C" fn(cmd: c_uint, data: *mut c_void) -> bool;
+/// retro_video_refresh_t
+pub type RetroVideoRefresh = extern "C" fn(data: *const c_void, width: c_uint, height: c_uint,
+                                           pitch: usize);
+/// retro_audio_sample_t
+pub type RetroAudioSample = extern "C" fn(left: i16, right: i16);
+/// retro_audio_sample_batch_t
+pub type RetroAudioSampleBatch = extern "C" fn(data: *const i16, frames: usize) -> usize;
+/// retro_input_poll_t
+pub type RetroInputPoll = extern "C" fn();
+/// retro_input_state_t
+pub type RetroInputState = extern "C" fn(port: c_uint, device: c_uint, index: c_uint,
+                                         id: c_uint) -> i16;
+
+// What the frontend handed over to call back into
+#[derive(Default)]
+struct Callbacks {
+    environment: Option<RetroEnvironment>,
+    video_refresh: Option<RetroVideoRefresh>,
+    audio_sample_batch: Option<RetroAudioSampleBatch>,
+    input_poll: Option<RetroInputPoll>,
+    input_state: Option<RetroInputState>,
+}
+
+struct Core {
+    gba: Gba,
+    rom: Vec<u8>, // Kept to reset from
+    frame: Vec<u8>, // RGB565, reused between frames
+}
+
+thread_local! {
+    static CALLBACKS: RefCell<Callbacks> = RefCell::new(Callbacks::default());
+    static CORE: RefCell<Option<Core>> = const { RefCell::new(None) };
+}
+
+fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+    CALLBACKS.with(|cb| cb.borrow().environment.is_some_and(|env| env(cmd, data)))
+}
+
+fn with_core<F, T>(f: F) -> Option<T>
+    where F: FnOnce(&mut Core) -> T {
+    CORE.with(|core| core.borrow_mut().as_mut().map(f))
+}
+
+// libretro cheats are bare code lines joined with +. Codes with 12 digits
+// a line are CodeBreaker, anything else is taken as encrypted GameShark.
+fn parse_cheat(code: &str) -> GbaResult<Cheat> {
+    let digits: String = code.chars().filter(|c| c.is_ascii_hexdigit()).collect();
+    let (format, encrypted, len) = if !digits.len().is_multiple_of(16) && digits.len().is_multiple_of(12) {
+        (CheatFormat::CodeBreaker, false, 12)
+    }
+    else {
+        (CheatFormat::GameShark, true, 16)
+    };
+    let lines: Vec<&str> = (0..digits.len() / len)
+        .map(|i| &digits[i * len..(i + 1) * len])
+        .collect();
+    Cheat::parse(code, format, encrypted, &lines)
+}
+
+/// The libretro API version implemented
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    RETRO_API_VERSION
+}
+
+/// Takes the environment callback
+#[no_mangle]
+pub extern "C" fn retro_set_environment(cb: RetroEnvironment) {
+    CALLBACKS.with(|cbs| cbs.borrow_mut().environment = Some(cb));
+}
+
+/// Takes the callback frames are sent to
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(cb: RetroVideoRefresh) {
+    CALLBACKS.with(|cbs| cbs.borrow_mut().video_refresh = Some(cb));
+}
+
+/// Audio goes out a frame at a time through the batch callback
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_cb: RetroAudioSample) {}
+
+/// Takes the callback audio is sent to
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(cb: RetroAudioSampleBatch) {
+    CALLBACKS.with(|cbs| cbs.borrow_mut().audio_sample_batch = Some(cb));
+}
+
+/// Takes the callback that polls input
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(cb: RetroInputPoll) {
+    CALLBACKS.with(|cbs| cbs.borrow_mut().input_poll = Some(cb));
+}
+
+/// Takes the callback input is read from
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(cb: RetroInputState) {
+    CALLBACKS.with(|cbs| cbs.borrow_mut().input_state = Some(cb));
+}
+
+/// Nothing is set up before a game is loaded
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+/// Drops the loaded game and the frontend's callbacks
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    CORE.with(|core| *core.borrow_mut() = None);
+}
+
+/// Names the core and the file extensions it loads
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut RetroSystemInfo) {
+    let info = &mut *info;
+    *info = RetroSystemInfo {
+        library_name: b"rusty-gba\0".as_ptr() as *const c_char,
+        library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char,
+        valid_extensions: b"gba|bin\0".as_ptr() as *const c_char,
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+/// Gives the picture size, frame rate and sample rate
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut RetroSystemAvInfo) {
+    let sample_rate = with_core(|core| core.gba.mem().io().apu.sample_rate()).unwrap_or(0);
+    let info = &mut *info;
+    *info = RetroSystemAvInfo {
+        geometry: RetroGameGeometry {
+            base_width: SCREEN_WIDTH as c_uint,
+            base_height: SCREEN_HEIGHT as c_uint,
+            max_width: SCREEN_WIDTH as c_uint,
+            max_height: SCREEN_HEIGHT as c_uint,
+            aspect_ratio: SCREEN_WIDTH as f32 / SCREEN_HEIGHT as f32,
+        },
+        timing: RetroSystemTiming {
+            fps: REFRESH_RATE,
+            sample_rate: sample_rate as f64,
+        },
+    };
+}
+
+/// There is only the one pad
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+/// Power cycle, keeping the save RAM, settings and cheats
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    with_core(|core| {
+        let mut gba = match Gba::load_bytes(&core.rom, &Config::default()) {
+            Ok(gba) => gba,
+            Err(e) => return warn!(target: "frontend", "failed to reset: {}", e),
+        };
+        gba.set_settings(core.gba.settings().clone());
+        gba.mem_mut().pak_ram_mut().copy_from_slice(core.gba.mem().pak_ram());
+        mem::swap(gba.cheats_mut(), core.gba.cheats_mut());
+        core.gba = gba;
+    });
+}
+
+/// Runs one frame, sending its picture and sound to the frontend
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    let (poll, state, video, audio) = CALLBACKS.with(|cb| {
+        let cb = cb.borrow();
+        (cb.input_poll, cb.input_state, cb.video_refresh, cb.audio_sample_batch)
+    });
+
+    if let Some(poll) = poll {
+        poll();
+    }
+    let ran = with_core(|core| {
+        if let Some(state) = state {
+            for &(id, button) in JOYPAD.iter() {
+                core.gba.set_button(button, state(0, RETRO_DEVICE_JOYPAD, 0, id) != 0);
+            }
+        }
+
+        // Unwinding into the frontend would abort it, so a crash only
+        // takes the game down
+        if panic::catch_unwind(AssertUnwindSafe(|| core.gba.run_frame())).is_err() {
+            return false;
+        }
+
+        convert(core.gba.mem().io().ppu.framebuffer().pixels(),
+                PixelFormat::Rgb565, &mut core.frame);
+        if let Some(video) = video {
+            video(core.frame.as_ptr() as *const c_void, SCREEN_WIDTH as c_uint,
+                  SCREEN_HEIGHT as c_uint, SCREEN_WIDTH * 2);
+        }
+
+        let samples = core.gba.mem_mut().io_mut().apu.drain_samples();
+        if let Some(audio) = audio {
+            let mut frames = &samples[..];
+            while !frames.is_empty() {
+                let sent = audio(frames.as_ptr(), frames.len() / 2);
+                if sent == 0 {
+                    break;
+                }
+                frames = &frames[(sent * 2).min(frames.len())..];
+            }
+        }
+        true
+    });
+
+    if ran == Some(false) {
+        CORE.with(|core| *core.borrow_mut() = None);
+        environment(RETRO_ENVIRONMENT_SHUTDOWN, ptr::null_mut());
+    }
+}
+
+/// Bytes a save state takes
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    with_core(|core| core.gba.save_state().len()).unwrap_or(0)
+}
+
+/// Writes a save state to data
+#[no_mangle]
+pub extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    with_core(|core| {
+        let state = core.gba.save_state();
+        if state.len() > size {
+            return false;
+        }
+        let out = unsafe { slice::from_raw_parts_mut(data as *mut u8, state.len()) };
+        out.copy_from_slice(&state);
+        true
+    }).unwrap_or(false)
+}
+
+/// Loads a save state from data
+#[no_mangle]
+pub extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    let data = unsafe { slice::from_raw_parts(data as *const u8, size) };
+    with_core(|core| core.gba.load_state(data).is_ok()).unwrap_or(false)
+}
+
+/// Removes every cheat
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {
+    with_core(|core| *core.gba.cheats_mut() = CheatEngine::default());
+}
+
+/// Adds or replaces cheat index, codes separated by + or newlines
+#[no_mangle]
+pub unsafe extern "C" fn retro_cheat_set(index: c_uint, enabled: bool, code: *const c_char) {
+    let code = CStr::from_ptr(code).to_string_lossy();
+    with_core(|core| {
+        let mut cheat = match parse_cheat(&code) {
+            Ok(cheat) => cheat,
+            Err(e) => return warn!(target: "frontend", "ignoring cheat {}: {}", index, e),
+        };
+        if cheat.has_master_code() {
+            warn!(target: "frontend", "the master code in cheat {} isn't needed and is ignored", index);
+        }
+        cheat.enabled = enabled;
+        core.gba.cheats_mut().add(cheat);
+
+        let mut settings = core.gba.settings().clone();
+        settings.cheats_enabled = true;
+        core.gba.set_settings(settings);
+    });
+}
+
+/// Loads a ROM, from its path or the data the frontend read
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const RetroGameInfo) -> bool {
+    if game.is_null() {
+        return false;
+    }
+    let game = &*game;
+    if game.data.is_null() {
+        return false;
+    }
+    let rom = unsafe { slice::from_raw_parts(game.data as *const u8, game.size) }.to_vec();
+
+    let mut format = RETRO_PIXEL_FORMAT_RGB565;
+    let format: *mut c_uint = &mut format;
+    if !environment(RETRO_ENVIRONMENT_SET_PIXEL_FORMAT, format as *mut c_void) {
+        warn!(target: "frontend", "the frontend can't show RGB565 frames");
+        return false;
+    }
+
+    let gba = match Gba::load_bytes(&rom, &Config::default()) {
+        Ok(gba) => gba,
+        Err(e) => {
+            error!(target: "frontend", "failed to load the game: {}", e);
+            return false;
+        },
+    };
+    CORE.with(|core| *core.borrow_mut() = Some(Core {
+        gba,
+        rom,
+        frame: Vec::new(),
+    }));
+    true
+}
+
+/// There are no special game types
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(_game_type: c_uint, _info: *const RetroGameInfo,
+                                          _num_info: usize) -> bool {
+    false
+}
+
+/// Drops the loaded game
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    CORE.with(|core| *core.borrow_mut() = None);
+}
+
+/// The GBA runs the same everywhere
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    RETRO_REGION_NTSC
+}
+
+/// The frontend saves and restores the cartridge SRAM through this directly
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(id: c_uint) -> *mut c_void {
+    if id != RETRO_MEMORY_SAVE_RAM {
+        return ptr::null_mut();
+    }
+    with_core(|core| core.gba.mem_mut().pak_ram_mut().as_mut_ptr() as *mut c_void)
+        .unwrap_or(ptr::null_mut())
+}
+
+/// Bytes of the memory retro_get_memory_data gives
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(id: c_uint) -> usize {
+    if id != RETRO_MEMORY_SAVE_RAM {
+        return 0;
+    }
+    with_core(|core| core.gba.mem().pak_ram().len()).unwrap_or(0)
+}
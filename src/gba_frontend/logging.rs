@@ -0,0 +1,99 @@
+// Prints the emulator's log messages to stderr. Each subsystem logs under
+// its own target: cpu, bus, ppu, dma, irq, audio, boot, debug and frontend,
+// the last for what the frontends report to the player, shown from info
+// unless set. Levels are set per target with --log, e.g.
+//
+//     --log debug              everything at debug and above
+//     --log warn,bus=trace     warnings, and every bus access that misses
+//     --log frontend=warn      no status messages, like "Saved state to ..."
+
+use std::io::{self, Write};
+
+use log::{self, Level, LevelFilter, Log, Metadata, Record};
+
+/// Log level for each target, from --log
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogLevels {
+    default: LevelFilter,
+    targets: Vec<(String, LevelFilter)>,
+}
+
+impl Default for LogLevels {
+    fn default() -> LogLevels {
+        LogLevels {
+            default: LevelFilter::Warn,
+            targets: vec![("frontend".to_string(), LevelFilter::Info)],
+        }
+    }
+}
+
+fn parse_level(name: &str) -> Result<LevelFilter, String> {
+    name.parse().map_err(|_| format!("Unknown log level {}", name))
+}
+
+impl LogLevels {
+    /// A comma separated list of LEVEL, for every target, or TARGET=LEVEL.
+    /// Levels are off, error, warn, info, debug and trace.
+    pub fn parse(spec: &str) -> Result<LogLevels, String> {
+        let mut levels = LogLevels::default();
+        for part in spec.split(',').filter(|part| !part.is_empty()) {
+            match part.find('=') {
+                Some(i) => {
+                    let level = parse_level(&part[i + 1..])?;
+                    levels.targets.push((part[..i].to_string(), level));
+                },
+                None => levels.default = parse_level(part)?,
+            }
+        }
+        Ok(levels)
+    }
+
+    // The last setting for target wins. Messages logged without a target
+    // have their module's path, which matches no subsystem.
+    fn level(&self, target: &str) -> LevelFilter {
+        self.targets.iter().rev()
+            .find(|&(name, _)| name == target)
+            .map_or(self.default, |&(_, level)| level)
+    }
+
+    fn max(&self) -> LevelFilter {
+        self.targets.iter().map(|&(_, level)| level).fold(self.default, |a, b| a.max(b))
+    }
+}
+
+struct StderrLogger {
+    levels: LogLevels,
+}
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.levels.level(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let level = match record.level() {
+            Level::Error => "ERROR",
+            Level::Warn => "WARNING",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        };
+        let _ = writeln!(io::stderr(), "{} [{}] {}", level, record.target(), record.args());
+    }
+
+    fn flush(&self) {
+        let _ = io::stderr().flush();
+    }
+}
+
+/// Install the logger. Only the first call in a process has any effect.
+pub fn init(levels: LogLevels) {
+    let max = levels.max();
+    let logger = Box::new(StderrLogger { levels });
+    if log::set_logger(Box::leak(logger)).is_ok() {
+        log::set_max_level(max);
+    }
+}
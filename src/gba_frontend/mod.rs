@@ -0,0 +1,602 @@
+/// Video recording from the frontends
+pub mod capture;
+/// What gba info prints about a ROM
+pub mod info;
+/// The libretro core
+#[cfg(feature = "libretro")]
+pub mod libretro;
+/// Log output and per-target levels
+pub mod logging;
+/// Frame pacing, fast forward and slow motion
+pub mod pacing;
+/// The GPU-backed shader frontend
+#[cfg(feature = "gpu")]
+pub mod gpu;
+/// Python bindings
+#[cfg(feature = "python")]
+pub mod python;
+/// The SDL frontend
+#[cfg(feature = "sdl")]
+pub mod sdl;
+/// The WebAssembly frontend
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use gba_apu::inspect::Channel;
+use gba_config::{Config, Filter, Peripheral, SaveType, Sensor, Strictness, VideoConfig};
+use gba_cpu::{UndefinedPolicy, ARM7};
+use gba_debug::repl::parse_num;
+use gba_mem::Memory;
+use gba_ppu::Layer;
+use gba_state::audit;
+use gba_state::movie::Movie;
+use gba_system::Gba;
+use gba_keypad::hotkey::HotkeyAction;
+use gba_video::filter::{PostProcess, ScaleFilter};
+
+use self::logging::LogLevels;
+use self::pacing::Speed;
+
+/// Config file used when --config isn't given, if it exists
+pub const DEFAULT_CONFIG: &str = "gba.toml";
+
+/// Command line options for the frontend
+#[derive(Clone, Debug, PartialEq)]
+pub struct Options {
+    /// ROM or multiboot image to run
+    pub rom: String,
+    /// Run without a window or sound
+    pub headless: bool,
+    /// Present through the GPU window instead of SDL
+    pub gpu: bool,
+    /// Config file to read instead of the default one
+    pub config: Option<String>,
+    /// Start in the debugger REPL
+    pub debug: bool,
+    /// Run ROMs with a bad header
+    pub force: bool,
+    /// Movie file to record input to
+    pub record: Option<String>,
+    /// Movie file to play input from
+    pub play: Option<String>,
+    /// .mkv file, or directory for PNG frames and a WAV
+    pub record_video: Option<String>,
+    /// File to write a hash of the state after every frame to
+    pub audit: Option<String>,
+    /// Hashes of an earlier run to check this one against
+    pub audit_against: Option<String>,
+    /// Cheat file to load
+    pub cheats: Option<String>,
+    /// Lua script to run alongside the game
+    pub script: Option<String>,
+    /// Fitted to the cartridge instead of the detected ones
+    pub sensors: Vec<Sensor>,
+    /// Port to host a network link cable on
+    pub link_host: Option<u16>,
+    /// Address of a hosted link cable to join
+    pub link_connect: Option<String>,
+    /// Plugged into the link port instead
+    pub peripheral: Option<Peripheral>,
+    /// Beats the game database and the config
+    pub save_type: Option<SaveType>,
+    /// Use the cached interpreter
+    pub cached: bool,
+    /// Compile to native code, with the jit feature
+    pub jit: bool,
+    /// What to do with an instruction the CPU can't execute
+    pub undefined: UndefinedPolicy,
+    /// Speed to start at, compared with a real GBA
+    pub speed: Speed,
+    /// Frames not shown after each one that is
+    pub frame_skip: u32,
+    /// These replace the config file's settings
+    pub bios: Option<String>,
+    /// Directory battery saves go in
+    pub save_dir: Option<String>,
+    /// Window size as a multiple of the LCD
+    pub scale: Option<u32>,
+    /// How the picture is scaled up
+    pub filter: Option<Filter>,
+    /// Scaling shader for the GPU frontend
+    pub shader: Option<String>,
+    /// Start in fullscreen
+    pub fullscreen: bool,
+    /// Start with the sound off
+    pub mute: bool,
+    /// Abort on accesses the hardware lets by
+    pub strict: bool,
+    /// Don't apply the built in game database's settings
+    pub no_game_db: bool,
+    /// Start the game without the BIOS intro
+    pub skip_bios: bool,
+    /// Log levels, by target
+    pub log: LogLevels,
+}
+
+impl Options {
+    /// Parse the command line:
+    ///
+    /// ```text
+    /// gba <PAK ROM> [--headless | --gpu] [--debug] [--force] [--cached | --jit] [--strict]
+    ///     [--no-game-db]
+    ///     [--scale N] [--config FILE] [--speed unlimited|MULTIPLIER] [--frame-skip N]
+    ///     [--bios FILE] [--skip-bios] [--save-dir DIR]
+    ///     [--filter nearest|linear] [--shader NAME|FILE] [--fullscreen]
+    ///     [--mute] [--log LEVELS]
+    ///     [--undefined exception|skip|break]
+    ///     [--record MOVIE | --play MOVIE] [--cheats FILE] [--script FILE]
+    ///     [--record-video FILE.mkv|DIR]
+    ///     [--audit HASHES] [--audit-against HASHES]
+    ///     [--sensor solar|tilt|gyro]... [--save-type TYPE]
+    ///     [--link-host PORT | --link-connect HOST:PORT | --peripheral gbplayer|ereader]
+    /// ```
+    pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+        let mut rom = None;
+        let mut headless = false;
+        let mut gpu = false;
+        let mut scale = None;
+        let mut config = None;
+        let mut debug = false;
+        let mut force = false;
+        let mut cached = false;
+        let mut jit = false;
+        let mut undefined = UndefinedPolicy::default();
+        let mut record = None;
+        let mut play = None;
+        let mut record_video = None;
+        let mut audit = None;
+        let mut audit_against = None;
+        let mut cheats = None;
+        let mut script = None;
+        let mut sensors = Vec::new();
+        let mut link_host = None;
+        let mut link_connect = None;
+        let mut peripheral = None;
+        let mut save_type = None;
+        let mut speed = Speed::NORMAL;
+        let mut frame_skip = 0;
+        let mut bios = None;
+        let mut save_dir = None;
+        let mut filter = None;
+        let mut shader = None;
+        let mut fullscreen = false;
+        let mut mute = false;
+        let mut strict = false;
+        let mut no_game_db = false;
+        let mut skip_bios = false;
+        let mut log = LogLevels::default();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--headless" => headless = true,
+                "--gpu" => gpu = true,
+                "--fullscreen" => fullscreen = true,
+                "--debug" => debug = true,
+                "--force" => force = true,
+                "--cached" => cached = true,
+                "--jit" => jit = true,
+                "--mute" => mute = true,
+                "--strict" => strict = true,
+                "--no-game-db" => no_game_db = true,
+                "--skip-bios" => skip_bios = true,
+                "--scale" => {
+                    scale = Some(args.next()
+                        .and_then(|s| s.parse().ok())
+                        .filter(|&s| s > 0)
+                        .ok_or_else(|| "--scale expects a positive integer".to_string())?);
+                },
+                "--filter" => {
+                    filter = Some(args.next()
+                        .and_then(|s| Filter::from_name(&s))
+                        .ok_or_else(|| "--filter expects nearest or linear".to_string())?);
+                },
+                "--shader" => {
+                    shader = Some(args.next()
+                        .ok_or_else(|| "--shader expects a name or a .wgsl file".to_string())?);
+                },
+                "--log" => {
+                    log = LogLevels::parse(&args.next()
+                        .ok_or_else(|| "--log expects levels, e.g. warn,bus=debug".to_string())?)?;
+                },
+                "--bios" => {
+                    bios = Some(args.next()
+                        .ok_or_else(|| "--bios expects a file".to_string())?);
+                },
+                "--save-dir" => {
+                    save_dir = Some(args.next()
+                        .ok_or_else(|| "--save-dir expects a directory".to_string())?);
+                },
+                "--undefined" => {
+                    undefined = args.next()
+                        .and_then(|s| UndefinedPolicy::from_name(&s))
+                        .ok_or_else(|| "--undefined expects exception, skip or break".to_string())?;
+                },
+                "--speed" => {
+                    speed = args.next()
+                        .and_then(|s| Speed::from_name(&s))
+                        .ok_or_else(|| "--speed expects unlimited or a multiplier, e.g. 2x"
+                                    .to_string())?;
+                },
+                "--frame-skip" => {
+                    frame_skip = args.next()
+                        .and_then(|s| s.parse().ok())
+                        .ok_or_else(|| "--frame-skip expects a number of frames".to_string())?;
+                },
+                "--config" => {
+                    config = Some(args.next()
+                        .ok_or_else(|| "--config expects a file".to_string())?);
+                },
+                "--record" => {
+                    record = Some(args.next()
+                        .ok_or_else(|| "--record expects a file".to_string())?);
+                },
+                "--play" => {
+                    play = Some(args.next()
+                        .ok_or_else(|| "--play expects a file".to_string())?);
+                },
+                "--record-video" => {
+                    record_video = Some(args.next()
+                        .ok_or_else(|| "--record-video expects a .mkv file or a directory"
+                                    .to_string())?);
+                },
+                "--audit" => {
+                    audit = Some(args.next()
+                        .ok_or_else(|| "--audit expects a file".to_string())?);
+                },
+                "--audit-against" => {
+                    audit_against = Some(args.next()
+                        .ok_or_else(|| "--audit-against expects a file".to_string())?);
+                },
+                "--cheats" => {
+                    cheats = Some(args.next()
+                        .ok_or_else(|| "--cheats expects a file".to_string())?);
+                },
+                "--script" => {
+                    script = Some(args.next()
+                        .ok_or_else(|| "--script expects a file".to_string())?);
+                },
+                "--sensor" => {
+                    sensors.push(args.next()
+                        .and_then(|s| Sensor::from_name(&s))
+                        .ok_or_else(|| "--sensor expects solar, tilt or gyro".to_string())?);
+                },
+                "--save-type" => {
+                    save_type = Some(args.next()
+                        .and_then(|s| SaveType::from_name(&s))
+                        .ok_or_else(|| "--save-type expects auto, none, sram, flash64, flash128, \
+                                        eeprom512 or eeprom8k".to_string())?);
+                },
+                "--link-host" => {
+                    link_host = Some(args.next()
+                        .and_then(|s| s.parse().ok())
+                        .ok_or_else(|| "--link-host expects a port".to_string())?);
+                },
+                "--link-connect" => {
+                    link_connect = Some(args.next()
+                        .ok_or_else(|| "--link-connect expects an address".to_string())?);
+                },
+                "--peripheral" => {
+                    peripheral = Some(args.next()
+                        .and_then(|s| Peripheral::from_name(&s))
+                        .ok_or_else(|| "--peripheral expects gbplayer or ereader".to_string())?);
+                },
+                _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+                _ if rom.is_none() => rom = Some(arg),
+                _ => return Err(format!("Unexpected argument {}", arg)),
+            }
+        }
+
+        if record.is_some() && play.is_some() {
+            return Err("--record and --play can't be used together".to_string());
+        }
+        if headless && gpu {
+            return Err("--headless and --gpu can't be used together".to_string());
+        }
+        let links = [link_host.is_some(), link_connect.is_some(), peripheral.is_some()];
+        if links.iter().filter(|&&l| l).count() > 1 {
+            return Err("Only one of --link-host, --link-connect and --peripheral can be used"
+                       .to_string());
+        }
+
+        Ok(Options {
+            rom: rom.ok_or_else(|| "PAK ROM argument not specified".to_string())?,
+            headless,
+            gpu,
+            config,
+            debug,
+            force,
+            record,
+            play,
+            record_video,
+            audit,
+            audit_against,
+            cheats,
+            script,
+            sensors,
+            link_host,
+            link_connect,
+            peripheral,
+            save_type,
+            cached,
+            jit,
+            undefined,
+            speed,
+            frame_skip,
+            bios,
+            save_dir,
+            scale,
+            filter,
+            shader,
+            fullscreen,
+            mute,
+            strict,
+            no_game_db,
+            skip_bios,
+            log,
+        })
+    }
+
+    /// Override the config file with the options given
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(ref bios) = self.bios {
+            config.bios = Some(PathBuf::from(bios));
+        }
+        if let Some(ref save_dir) = self.save_dir {
+            config.save_dir = Some(PathBuf::from(save_dir));
+        }
+        if let Some(scale) = self.scale {
+            config.video.scale = scale;
+        }
+        if let Some(filter) = self.filter {
+            config.video.filter = filter;
+        }
+        if let Some(ref shader) = self.shader {
+            config.video.shader = Some(shader.clone());
+        }
+        if self.fullscreen {
+            config.video.fullscreen = true;
+        }
+        if self.mute {
+            config.audio.enabled = false;
+        }
+        if self.strict {
+            config.emulation.strictness = Strictness::Strict;
+        }
+        if self.no_game_db {
+            config.emulation.game_db = false;
+        }
+        if self.skip_bios {
+            config.emulation.skip_bios = true;
+        }
+    }
+
+    /// Where the save state hotkeys keep their snapshot
+    pub fn state_path(&self, config: &Config) -> PathBuf {
+        self.save_path(config, "state")
+    }
+
+    /// Where the cartridge's battery-backed data is kept between runs
+    pub fn battery_path(&self, config: &Config) -> PathBuf {
+        self.save_path(config, "sav")
+    }
+
+    // Named after the ROM, in the save directory or else next to the ROM
+    fn save_path(&self, config: &Config, ext: &str) -> PathBuf {
+        let rom = Path::new(&self.rom);
+        match (config.save_dir.as_ref(), rom.file_name()) {
+            (Some(dir), Some(name)) => dir.join(name).with_extension(ext),
+            _ => rom.with_extension(ext),
+        }
+    }
+}
+
+/// What every windowed frontend does before playing: load the battery save
+/// and start any movie or audit asked for
+pub fn start_session(opts: &Options, config: &Config, gba: &mut Gba) -> Result<(), String> {
+    if let Some(ref dir) = config.save_dir {
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+    let battery_path = opts.battery_path(config);
+    if let Ok(data) = fs::read(&battery_path) {
+        if let Err(e) = gba.import_battery(&data) {
+            warn!(target: "frontend", "ignoring {}: {}", battery_path.display(), e);
+        }
+    }
+
+    if let Some(ref path) = opts.play {
+        let movie = Movie::load(path).map_err(|e| format!("Failed to load {}: {}", path, e))?;
+        gba.play_movie(movie).map_err(|e| format!("Can't play {}: {}", path, e))?;
+    }
+    else if opts.record.is_some() {
+        gba.record_movie();
+    }
+
+    if opts.audit.is_some() || opts.audit_against.is_some() {
+        let reference = match opts.audit_against {
+            Some(ref path) => Some(audit::load(path)
+                .map_err(|e| format!("Failed to load {}: {}", path, e))?),
+            None => None,
+        };
+        gba.start_audit(reference);
+    }
+    Ok(())
+}
+
+/// And after: write out the movie, the audit and the battery save
+pub fn end_session(opts: &Options, config: &Config, gba: &mut Gba) -> Result<(), String> {
+    if let (Some(ref path), Some(movie)) = (opts.record.as_ref(), gba.stop_movie()) {
+        movie.save(path).map_err(|e| format!("Failed to write {}: {}", path, e))?;
+    }
+
+    if let Some(audit) = gba.stop_audit() {
+        if let Some(ref path) = opts.audit {
+            audit.save(path).map_err(|e| format!("Failed to write {}: {}", path, e))?;
+        }
+        match audit.divergence() {
+            Some(divergence) => warn!(target: "debug", "{}", divergence),
+            None if opts.audit_against.is_some() =>
+                info!(target: "frontend", "state matched for all {} frames", audit.hashes().len()),
+            None => {},
+        }
+    }
+
+    if gba.settings().save_type == SaveType::None {
+        return Ok(());
+    }
+    let battery_path = opts.battery_path(config);
+    fs::write(&battery_path, gba.export_battery())
+        .map_err(|e| format!("Failed to write {}: {}", battery_path.display(), e))
+}
+
+/// The post-processing the video config asks for. Color correction is a
+/// per-game setting, so it is left to the caller.
+pub fn post_process(video: &VideoConfig) -> PostProcess {
+    PostProcess {
+        frame_blending: video.frame_blending,
+        scale: video.prescale.max(1) as usize,
+        scale_filter: if video.scanlines { ScaleFilter::Scanlines } else { ScaleFilter::Nearest },
+        ..PostProcess::default()
+    }
+}
+
+/// The post-processing after a hotkey that changes it
+pub fn toggle_post_process(action: HotkeyAction, post: PostProcess) -> Option<PostProcess> {
+    match action {
+        HotkeyAction::ColorCorrection => {
+            info!(target: "frontend", "Color correction: {}", if post.color_correction { "off" } else { "on" });
+            Some(PostProcess { color_correction: !post.color_correction, ..post })
+        },
+        HotkeyAction::FrameBlending => {
+            info!(target: "frontend", "Frame blending: {}", if post.frame_blending { "off" } else { "on" });
+            Some(PostProcess { frame_blending: !post.frame_blending, ..post })
+        },
+        HotkeyAction::Scanlines => match post.scale_filter {
+            ScaleFilter::Scanlines => {
+                info!(target: "frontend", "Scanlines: off");
+                Some(PostProcess { scale_filter: ScaleFilter::Nearest, ..post })
+            },
+            ScaleFilter::Nearest => {
+                info!(target: "frontend", "Scanlines: on");
+                Some(PostProcess {
+                    scale_filter: ScaleFilter::Scanlines,
+                    scale: post.scale.max(2),
+                    ..post
+                })
+            },
+        },
+        _ => None,
+    }
+}
+
+/// For the mute hotkeys
+pub fn toggle_mute(gba: &mut Gba, ch: Channel) {
+    let muted = gba.mem_mut().io_mut().apu.toggle_muted(ch);
+    info!(target: "frontend", "{}: {}", ch, if muted { "muted" } else { "on" });
+}
+
+/// For the solo hotkeys, which go back to every channel when pressed again
+pub fn toggle_solo(gba: &mut Gba, ch: Channel) {
+    let apu = &mut gba.mem_mut().io_mut().apu;
+    if apu.soloed() == Some(ch) {
+        apu.solo(None);
+        info!(target: "frontend", "All channels on");
+    }
+    else {
+        apu.solo(Some(ch));
+        info!(target: "frontend", "Solo: {}", ch);
+    }
+}
+
+/// For the layer hotkeys
+pub fn toggle_layer(gba: &mut Gba, layer: Layer) {
+    let hidden = gba.mem_mut().io_mut().ppu.toggle_layer(layer);
+    info!(target: "frontend", "{}: {}", layer, if hidden { "hidden" } else { "shown" });
+}
+
+/// Options for `gba disasm`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DisasmOptions {
+    /// ROM to disassemble
+    pub rom: String,
+    /// Address in the ROM's memory map
+    pub start: u32,
+    /// Bytes
+    pub len: u32,
+    /// Disassemble as THUMB instead of ARM
+    pub thumb: bool,
+}
+
+impl DisasmOptions {
+    /// Usage: gba disasm <ROM> [--start ADDR] [--len BYTES] [--thumb]
+    pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<DisasmOptions, String> {
+        let mut rom = None;
+        let mut start = 0x08000000;
+        let mut len = 0x200;
+        let mut thumb = false;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--thumb" => thumb = true,
+                "--start" => {
+                    start = parse_num(&args.next()
+                        .ok_or_else(|| "--start expects an address".to_string())?)?;
+                },
+                "--len" => {
+                    len = parse_num(&args.next()
+                        .ok_or_else(|| "--len expects a number of bytes".to_string())?)?;
+                },
+                _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+                _ if rom.is_none() => rom = Some(arg),
+                _ => return Err(format!("Unexpected argument {}", arg)),
+            }
+        }
+
+        Ok(DisasmOptions {
+            rom: rom.ok_or_else(|| "ROM argument not specified".to_string())?,
+            start,
+            len,
+            thumb,
+        })
+    }
+}
+
+/// Original developer output: poke memory and dump the CPU state
+pub fn run_headless(mem: &mut Memory) {
+    mem.write32::<u32>(0x02000000, 0xdeadbeef);
+
+    println!("{:#x}", mem.read::<u8>(0x02000000));
+
+    let cpu = ARM7::default();
+    println!("{}", cpu);
+}
+
+#[cfg(all(test, feature = "config"))]
+mod tests {
+    use super::*;
+
+    fn options(args: &str) -> Options {
+        Options::parse(args.split_whitespace().map(|s| s.to_string())).unwrap()
+    }
+
+    #[test]
+    fn options_override_the_config_file() {
+        let text = "bios = \"file.bin\"\n\
+                    [video]\nscale = 4\nfilter = \"linear\"\n\
+                    [emulation]\nskip_bios = false";
+        let mut config = Config::parse(text).unwrap();
+        options("game.gba --scale 2 --skip-bios --no-game-db").apply(&mut config);
+        assert_eq!((config.video.scale, config.video.filter), (2, Filter::Linear));
+        assert!(config.emulation.skip_bios && !config.emulation.game_db);
+        assert_eq!(config.bios, Some(PathBuf::from("file.bin")));
+
+        // Options not given leave the file's settings alone
+        let mut config = Config::parse(text).unwrap();
+        options("game.gba --bios cli.bin").apply(&mut config);
+        assert_eq!(config, Config { bios: Some(PathBuf::from("cli.bin")),
+                                    ..Config::parse(text).unwrap() });
+    }
+}
@@ -0,0 +1,161 @@
+use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use gba_ppu::REFRESH_RATE;
+
+/// Speeds the fast forward and slow motion hotkeys switch to
+pub const FAST_FORWARD: Speed = Speed::Multiplier(2.0);
+/// Half speed
+pub const SLOW_MOTION: Speed = Speed::Multiplier(0.5);
+
+/// How fast emulation runs compared with a real GBA
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Speed {
+    /// As fast as the host can go
+    Unlimited,
+    /// This many times a real GBA's speed
+    Multiplier(f64),
+}
+
+impl Speed {
+    /// A real GBA's speed
+    pub const NORMAL: Speed = Speed::Multiplier(1.0);
+
+    /// "unlimited", or a positive multiplier such as 2, 2x or 0.5x
+    pub fn from_name(name: &str) -> Option<Speed> {
+        if name == "unlimited" {
+            return Some(Speed::Unlimited);
+        }
+        let multiplier = name.trim_end_matches('x').parse::<f64>().ok()?;
+        if multiplier > 0.0 && multiplier.is_finite() {
+            Some(Speed::Multiplier(multiplier))
+        }
+        else {
+            None
+        }
+    }
+
+    /// Time a frame should take at this speed
+    pub fn frame_time(&self) -> Option<Duration> {
+        match *self {
+            Speed::Unlimited => None,
+            Speed::Multiplier(m) => Some(Duration::from_secs_f64(1.0 / (REFRESH_RATE * m))),
+        }
+    }
+}
+
+impl Default for Speed {
+    fn default() -> Speed {
+        Speed::NORMAL
+    }
+}
+
+impl fmt::Display for Speed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Speed::Unlimited => write![f, "unlimited"],
+            Speed::Multiplier(m) => write![f, "{}x", m],
+        }
+    }
+}
+
+/// Keeps emulation running at a multiple of the GBA's refresh rate by
+/// sleeping off any time left over after each frame, and decides which
+/// frames are worth showing. It goes by the host clock alone, so audio has
+/// to keep up with it rather than setting the pace.
+#[derive(Clone, Copy, Debug)]
+pub struct FramePacer {
+    speed: Speed,
+    frame_skip: u32, // Frames run without being shown after each one shown
+    skipped: u32,
+    next: Instant,
+    last_shown: Instant,
+}
+
+impl Default for FramePacer {
+    fn default() -> FramePacer {
+        FramePacer::new(Speed::NORMAL, 0)
+    }
+}
+
+impl FramePacer {
+    /// A pacer running at speed, showing one frame in every frame_skip + 1
+    pub fn new(speed: Speed, frame_skip: u32) -> FramePacer {
+        let now = Instant::now();
+        FramePacer {
+            speed,
+            frame_skip,
+            skipped: 0,
+            next: now,
+            last_shown: now,
+        }
+    }
+
+    /// How fast emulation runs
+    pub fn speed(&self) -> Speed {
+        self.speed
+    }
+
+    /// Takes effect from the next frame, without making up for lost time
+    pub fn set_speed(&mut self, speed: Speed) {
+        self.speed = speed;
+        self.next = Instant::now();
+    }
+
+    /// Switch to speed, or back to normal if already there, as the speed
+    /// hotkeys do
+    pub fn toggle_speed(&mut self, speed: Speed) {
+        let speed = if self.speed == speed { Speed::NORMAL } else { speed };
+        self.set_speed(speed);
+    }
+
+    /// Frames run without being shown after each one shown
+    pub fn frame_skip(&self) -> u32 {
+        self.frame_skip
+    }
+
+    /// Show one frame in every frame_skip + 1
+    pub fn set_frame_skip(&mut self, frame_skip: u32) {
+        self.frame_skip = frame_skip;
+        self.skipped = 0;
+    }
+
+    /// Whether the frame just run should be shown. Besides the frames
+    /// skipped on purpose, running unlimited shows no more than the
+    /// display's refresh rate, as the rest would never be seen.
+    pub fn should_show(&mut self) -> bool {
+        if self.skipped < self.frame_skip {
+            self.skipped += 1;
+            return false;
+        }
+        let now = Instant::now();
+        if self.speed == Speed::Unlimited &&
+           now < self.last_shown + Speed::NORMAL.frame_time().unwrap() {
+            return false;
+        }
+        self.skipped = 0;
+        self.last_shown = now;
+        true
+    }
+
+    /// Sleep until the next frame is due
+    pub fn wait(&mut self) {
+        let now = Instant::now();
+        let frame_time = match self.speed.frame_time() {
+            Some(frame_time) => frame_time,
+            None => {
+                self.next = now;
+                return;
+            },
+        };
+        self.next += frame_time;
+        if self.next > now {
+            thread::sleep(self.next - now);
+        }
+        else {
+            // Running behind; don't try to catch up with a burst of frames
+            self.next = now;
+        }
+    }
+}
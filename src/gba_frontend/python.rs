@@ -0,0 +1,182 @@
+// Python bindings, for scripting the emulator from reinforcement learning
+// agents and test harnesses. Build the module with
+//
+//     cargo rustc --release --lib --crate-type cdylib \
+//         --features python,pyo3/extension-module
+//
+// and copy target/release/libgba.so to gba.so (gba.pyd on Windows) on the
+// Python path. Then:
+//
+//     import gba
+//     emu = gba.Gba("game.gba")
+//     emu.set_button("start", True)
+//     emu.run_frames(60)
+//     screen = emu.framebuffer()  # numpy uint8 array, 160 x 240 x 3
+//     hp = emu.peek(0x02024284, 2)
+
+use numpy::ndarray::Array3;
+use numpy::{IntoPyArray, PyArray3};
+use pyo3::exceptions::{PyIOError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use gba_config::Config;
+use gba_error::GbaError;
+use gba_keypad::BUTTONS;
+use gba_mem::Address;
+use gba_ppu::{Layer, SCREEN_HEIGHT, SCREEN_WIDTH};
+use gba_system::Gba;
+use gba_video::{convert, PixelFormat};
+
+fn py_err(e: GbaError) -> PyErr {
+    match e {
+        GbaError::Io(e) => PyIOError::new_err(e.to_string()),
+        e => PyValueError::new_err(e.to_string()),
+    }
+}
+
+/// A machine with a game loaded. It holds Rc state, so it stays on the
+/// Python thread that made it.
+#[pyclass(name = "Gba", module = "gba", unsendable)]
+#[derive(Debug)]
+pub struct PyGba {
+    gba: Gba,
+    rgba: Vec<u8>, // Reused between framebuffer calls
+}
+
+#[pymethods]
+impl PyGba {
+    // Load a game from a file, with the settings from a config file if
+    // one is given
+    #[new]
+    #[pyo3(signature = (rom, config=None))]
+    fn new(rom: &str, config: Option<&str>) -> PyResult<PyGba> {
+        let config = match config {
+            Some(path) => Config::load(path).map_err(py_err)?,
+            None => Config::default(),
+        };
+        Ok(PyGba {
+            gba: Gba::load(rom, &config).map_err(py_err)?,
+            rgba: Vec::new(),
+        })
+    }
+
+    // Load a game from its bytes
+    #[staticmethod]
+    fn from_bytes(rom: &[u8]) -> PyResult<PyGba> {
+        Ok(PyGba {
+            gba: Gba::load_bytes(rom, &Config::default()).map_err(py_err)?,
+            rgba: Vec::new(),
+        })
+    }
+
+    // Execute one instruction
+    fn step(&mut self) {
+        self.gba.step_instruction();
+    }
+
+    fn run_frame(&mut self) {
+        self.gba.run_frame();
+    }
+
+    fn run_frames(&mut self, count: u32) {
+        for _ in 0..count {
+            self.gba.run_frame();
+        }
+    }
+
+    #[getter]
+    fn frame(&self) -> u64 {
+        self.gba.frame()
+    }
+
+    #[getter]
+    fn cycles(&self) -> u64 {
+        self.gba.cycles()
+    }
+
+    // Read 1, 2 or 4 bytes without disturbing the machine
+    #[pyo3(signature = (addr, size=1))]
+    fn peek(&self, addr: Address, size: usize) -> PyResult<u32> {
+        let mem = self.gba.mem();
+        match size {
+            1 => Ok(mem.peek::<u8>(addr) as u32),
+            2 => Ok(mem.peek::<u16>(addr) as u32),
+            4 => Ok(mem.peek::<u32>(addr)),
+            _ => Err(PyValueError::new_err(format!("can't peek {} bytes", size))),
+        }
+    }
+
+    // Write 1, 2 or 4 bytes as the CPU would
+    #[pyo3(signature = (addr, val, size=1))]
+    fn poke(&mut self, addr: Address, val: u32, size: usize) -> PyResult<()> {
+        let mem = self.gba.mem_mut();
+        match size {
+            1 => mem.write8::<u8>(addr, val as u8),
+            2 => mem.write16::<u16>(addr, val as u16),
+            4 => mem.write32::<u32>(addr, val),
+            _ => return Err(PyValueError::new_err(format!("can't poke {} bytes", size))),
+        }
+        Ok(())
+    }
+
+    fn read_bytes<'py>(&self, py: Python<'py>, addr: Address, len: usize) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.gba.guest().read_bytes(addr, len))
+    }
+
+    // Press or release a button by name: a, b, select, start, right,
+    // left, up, down, r or l
+    fn set_button(&mut self, button: &str, pressed: bool) -> PyResult<()> {
+        let found = BUTTONS.iter().find(|b| b.to_string().eq_ignore_ascii_case(button));
+        match found {
+            Some(&button) => {
+                self.gba.set_button(button, pressed);
+                Ok(())
+            }
+            None => Err(PyValueError::new_err(format!("unknown button {:?}", button))),
+        }
+    }
+
+    // Hold exactly the buttons in mask, in KEYINPUT bit order (A = 1,
+    // B = 2, ..., L = 0x200), e.g. an agent's action for the next frame
+    fn set_buttons(&mut self, mask: u16) {
+        for &button in BUTTONS.iter() {
+            self.gba.set_button(button, mask & button as u16 != 0);
+        }
+    }
+
+    // Show or hide a layer by name: bg0, bg1, bg2, bg3 or obj
+    fn show_layer(&mut self, layer: &str, shown: bool) -> PyResult<()> {
+        match Layer::from_name(layer) {
+            Some(found) => {
+                self.gba.set_layer_hidden(found, !shown);
+                Ok(())
+            }
+            None => Err(PyValueError::new_err(format!("unknown layer {:?}", layer))),
+        }
+    }
+
+    // The last frame finished, as a height x width x 3 RGB array
+    fn framebuffer<'py>(&mut self, py: Python<'py>) -> Bound<'py, PyArray3<u8>> {
+        convert(self.gba.mem().io().ppu.framebuffer().pixels(),
+                PixelFormat::Rgba8888, &mut self.rgba);
+        let rgb: Vec<u8> = self.rgba.chunks(4).flat_map(|px| px[..3].iter().cloned()).collect();
+        Array3::from_shape_vec((SCREEN_HEIGHT, SCREEN_WIDTH, 3), rgb)
+            .expect("a frame is always SCREEN_HEIGHT x SCREEN_WIDTH")
+            .into_pyarray(py)
+    }
+
+    fn save_state<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.gba.save_state())
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> PyResult<()> {
+        self.gba.load_state(data).map_err(py_err)
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "gba")]
+fn gba_module(m: &Bound<PyModule>) -> PyResult<()> {
+    m.add_class::<PyGba>()
+}
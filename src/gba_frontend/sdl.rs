@@ -0,0 +1,470 @@
+use std::cell::Cell;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use sdl2;
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::AudioSubsystem;
+use sdl2::controller::{Button as PadButton, GameController};
+use sdl2::event::Event;
+use sdl2::hint;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::render::{Texture, WindowCanvas};
+use sdl2::EventPump;
+
+use gba_apu::inspect::Channel;
+use gba_audio::{AudioOutput, SampleQueue};
+use gba_config::{AudioConfig, Config, Filter};
+use gba_frontend::capture::Capture;
+use gba_frontend::{end_session, post_process, start_session, toggle_layer, toggle_mute,
+                   toggle_post_process, toggle_solo, Options};
+use gba_frontend::pacing::{self, FramePacer, Speed};
+use gba_keypad::{Button, BUTTONS};
+use gba_keypad::hotkey::HotkeyAction;
+use gba_ppu::{Layer, SCREEN_HEIGHT, SCREEN_WIDTH};
+#[cfg(feature = "lua")]
+use gba_script::ScriptHost;
+use gba_state::rewind::RewindBuffer;
+use gba_system::Gba;
+use gba_video::{Frame, FrameMetadata, PixelFormat, Presenter, VideoSink};
+use gba_video::filter::PostProcess;
+
+// Rewind keeps a snapshot every sixth of a second for the last minute and
+// steps back a second per press
+const REWIND_INTERVAL: u64 = 10;
+const REWIND_SNAPSHOTS: usize = 360;
+const REWIND_STEP: u64 = 60;
+
+// The cartridge motor is either on or off, so it drives both of the pad's
+// motors at full strength. SDL needs a duration, so it gets one long enough
+// for the game to switch it off first.
+const RUMBLE_STRENGTH: u16 = 0xFFFF;
+const RUMBLE_MS: u32 = 10_000;
+
+// What a keyboard key does
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Binding {
+    Button(Button),
+    Hotkey(HotkeyAction), // Alongside any chords set up on the pad
+}
+
+// Default keyboard layout
+const DEFAULT_KEYS: [(Keycode, Binding); 32] = [
+    (Keycode::Z,         Binding::Button(Button::A)),
+    (Keycode::X,         Binding::Button(Button::B)),
+    (Keycode::Backspace, Binding::Button(Button::Select)),
+    (Keycode::Return,    Binding::Button(Button::Start)),
+    (Keycode::Right,     Binding::Button(Button::Right)),
+    (Keycode::Left,      Binding::Button(Button::Left)),
+    (Keycode::Up,        Binding::Button(Button::Up)),
+    (Keycode::Down,      Binding::Button(Button::Down)),
+    (Keycode::S,         Binding::Button(Button::R)),
+    (Keycode::A,         Binding::Button(Button::L)),
+    (Keycode::Tab,       Binding::Hotkey(HotkeyAction::FastForward)),
+    (Keycode::Backquote, Binding::Hotkey(HotkeyAction::SlowMotion)),
+    (Keycode::F6,        Binding::Hotkey(HotkeyAction::Unthrottle)),
+    (Keycode::F5,        Binding::Hotkey(HotkeyAction::SaveState)),
+    (Keycode::F7,        Binding::Hotkey(HotkeyAction::Rewind)),
+    (Keycode::F8,        Binding::Hotkey(HotkeyAction::LoadState)),
+    (Keycode::F12,       Binding::Hotkey(HotkeyAction::Screenshot)),
+    (Keycode::F2,        Binding::Hotkey(HotkeyAction::ColorCorrection)),
+    (Keycode::F3,        Binding::Hotkey(HotkeyAction::FrameBlending)),
+    (Keycode::F4,        Binding::Hotkey(HotkeyAction::Scanlines)),
+    (Keycode::Num1,      Binding::Hotkey(HotkeyAction::MuteChannel(Channel::Square1))),
+    (Keycode::Num2,      Binding::Hotkey(HotkeyAction::MuteChannel(Channel::Square2))),
+    (Keycode::Num3,      Binding::Hotkey(HotkeyAction::MuteChannel(Channel::Wave))),
+    (Keycode::Num4,      Binding::Hotkey(HotkeyAction::MuteChannel(Channel::Noise))),
+    (Keycode::Num5,      Binding::Hotkey(HotkeyAction::MuteChannel(Channel::FifoA))),
+    (Keycode::Num6,      Binding::Hotkey(HotkeyAction::MuteChannel(Channel::FifoB))),
+    (Keycode::Num7,      Binding::Hotkey(HotkeyAction::ToggleLayer(Layer::Bg0))),
+    (Keycode::Num8,      Binding::Hotkey(HotkeyAction::ToggleLayer(Layer::Bg1))),
+    (Keycode::Num9,      Binding::Hotkey(HotkeyAction::ToggleLayer(Layer::Bg2))),
+    (Keycode::Num0,      Binding::Hotkey(HotkeyAction::ToggleLayer(Layer::Bg3))),
+    (Keycode::Minus,     Binding::Hotkey(HotkeyAction::ToggleLayer(Layer::Obj))),
+    (Keycode::Escape,    Binding::Hotkey(HotkeyAction::Quit)),
+];
+
+// The default layout with the keys from the config swapped in. Each
+// replaces the default key for its button or action, and takes the key
+// from whatever had it before.
+fn key_bindings(keys: &HashMap<String, String>) -> Result<Vec<(Keycode, Binding)>, String> {
+    let mut bindings = DEFAULT_KEYS.to_vec();
+    for (name, key_name) in keys {
+        let binding = BUTTONS.iter()
+            .find(|b| b.to_string().eq_ignore_ascii_case(name))
+            .map(|&button| Binding::Button(button))
+            .or_else(|| HotkeyAction::from_name(name).map(Binding::Hotkey))
+            .ok_or_else(|| format!("Unknown button or hotkey {} in [keys]", name))?;
+        let key = Keycode::from_name(key_name)
+            .ok_or_else(|| format!("Unknown key {:?} for {} in [keys]", key_name, name))?;
+        bindings.retain(|&(k, b)| k != key && b != binding);
+        bindings.push((key, binding));
+    }
+    Ok(bindings)
+}
+
+fn lookup_key(keys: &[(Keycode, Binding)], key: Keycode) -> Option<Binding> {
+    keys.iter().find(|&&(k, _)| k == key).map(|&(_, binding)| binding)
+}
+
+// Open the device, started, feeding it from queue
+fn open_audio(audio: &AudioSubsystem, config: &AudioConfig, queue: &SampleQueue)
+              -> Result<AudioDevice<QueueCallback>, String> {
+    let desired = AudioSpecDesired {
+        freq: Some(config.sample_rate as i32),
+        channels: Some(2),
+        samples: Some(config.buffer_frames),
+    };
+    let cb_queue = queue.clone();
+    let device = audio.open_playback(None, &desired, |_| QueueCallback { queue: cb_queue })?;
+    device.resume();
+    Ok(device)
+}
+
+fn map_pad_button(button: PadButton) -> Option<Button> {
+    match button {
+        PadButton::A             => Some(Button::A),
+        PadButton::B             => Some(Button::B),
+        PadButton::Back          => Some(Button::Select),
+        PadButton::Start         => Some(Button::Start),
+        PadButton::DPadRight     => Some(Button::Right),
+        PadButton::DPadLeft      => Some(Button::Left),
+        PadButton::DPadUp        => Some(Button::Up),
+        PadButton::DPadDown      => Some(Button::Down),
+        PadButton::RightShoulder => Some(Button::R),
+        PadButton::LeftShoulder  => Some(Button::L),
+        _ => None,
+    }
+}
+
+// Feeds the SDL audio device from the shared sample queue
+struct QueueCallback {
+    queue: SampleQueue,
+}
+
+impl AudioCallback for QueueCallback {
+    type Channel = i16;
+
+    fn callback(&mut self, out: &mut [i16]) {
+        self.queue.pop_into(out);
+    }
+}
+
+/// Window, audio device and input for playing a game
+#[allow(missing_debug_implementations)]
+pub struct SdlFrontend {
+    canvas: WindowCanvas,
+    texture: Texture,
+    texture_size: (usize, usize), // Remade when post-processing changes the frame size
+    post: PostProcess, // Before the game's color correction setting
+    events: EventPump,
+    audio: Option<AudioDevice<QueueCallback>>,
+    queue: SampleQueue,
+    controller: Option<GameController>,
+    keys: Vec<(Keycode, Binding)>,
+    hotkeys: VecDeque<HotkeyAction>,
+    state_path: PathBuf,
+    rewind: RewindBuffer,
+    pacer: FramePacer,
+    capture: Capture,
+    #[cfg(feature = "lua")]
+    script: Option<ScriptHost>,
+}
+
+impl SdlFrontend {
+    /// Open a window set up as config says, saving states to state_path
+    pub fn open(config: &Config, state_path: PathBuf) -> Result<SdlFrontend, String> {
+        let keys = key_bindings(&config.keys)?;
+        let sdl = sdl2::init()?;
+        let video = sdl.video()?;
+
+        let scale = config.video.scale;
+        let window = video.window("rusty-gba",
+                                  SCREEN_WIDTH as u32 * scale,
+                                  SCREEN_HEIGHT as u32 * scale)
+            .position_centered()
+            .build()
+            .map_err(|e| e.to_string())?;
+        let canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+        // Read when the texture is made
+        let quality = match config.video.filter {
+            Filter::Nearest => "nearest",
+            Filter::Linear => "linear",
+        };
+        hint::set("SDL_RENDER_SCALE_QUALITY", quality);
+        let texture = canvas.texture_creator()
+            .create_texture_streaming(PixelFormatEnum::RGBA32,
+                                      SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32)
+            .map_err(|e| e.to_string())?;
+
+        // Run silently rather than fail when there is no audio device
+        let queue = SampleQueue::default();
+        let audio = if config.audio.enabled {
+            match sdl.audio().and_then(|audio| open_audio(&audio, &config.audio, &queue)) {
+                Ok(device) => Some(device),
+                Err(e) => {
+                    warn!(target: "frontend", "audio disabled: {}", e);
+                    None
+                },
+            }
+        }
+        else {
+            None
+        };
+
+        // Use the first game controller if one is plugged in
+        let controller = sdl.game_controller().ok().and_then(|gc| {
+            let count = gc.num_joysticks().unwrap_or(0);
+            (0..count).filter(|&i| gc.is_game_controller(i))
+                .filter_map(|i| gc.open(i).ok())
+                .next()
+        });
+
+        Ok(SdlFrontend {
+            canvas,
+            texture,
+            texture_size: (SCREEN_WIDTH, SCREEN_HEIGHT),
+            post: post_process(&config.video),
+            events: sdl.event_pump()?,
+            audio,
+            queue,
+            controller,
+            keys,
+            hotkeys: VecDeque::new(),
+            state_path,
+            rewind: RewindBuffer::new(REWIND_INTERVAL, REWIND_SNAPSHOTS),
+            pacer: FramePacer::default(),
+            capture: Capture::default(),
+            #[cfg(feature = "lua")]
+            script: None,
+        })
+    }
+
+    // Apply pending window and input events to the keypad. Returns false
+    // once the user has asked to quit.
+    fn poll_input(&mut self, gba: &mut Gba) -> bool {
+        for event in self.events.poll_iter() {
+            let (button, pressed) = match event {
+                Event::Quit { .. } => return false,
+                Event::KeyDown { keycode: Some(key), repeat: false, .. } => {
+                    match lookup_key(&self.keys, key) {
+                        Some(Binding::Button(button)) => (Some(button), true),
+                        Some(Binding::Hotkey(action)) => {
+                            self.hotkeys.push_back(action);
+                            (None, false)
+                        },
+                        None => (None, false),
+                    }
+                },
+                Event::KeyUp { keycode: Some(key), .. } => match lookup_key(&self.keys, key) {
+                    Some(Binding::Button(button)) => (Some(button), false),
+                    _ => (None, false),
+                },
+                Event::ControllerButtonDown { button, .. } =>
+                    (map_pad_button(button), true),
+                Event::ControllerButtonUp { button, .. } =>
+                    (map_pad_button(button), false),
+                _ => (None, false),
+            };
+            if let Some(button) = button {
+                gba.set_button(button, pressed);
+            }
+        }
+        true
+    }
+
+    // Carry out an emulator action. Returns false if it was Quit.
+    fn handle_hotkey(&mut self, action: HotkeyAction, gba: &mut Gba) -> bool {
+        match action {
+            HotkeyAction::SaveState => {
+                match fs::write(&self.state_path, gba.save_state()) {
+                    Ok(()) => info!(target: "frontend", "Saved state to {}", self.state_path.display()),
+                    Err(e) => warn!(target: "frontend", "failed to save state: {}", e),
+                }
+            },
+            HotkeyAction::LoadState => {
+                let loaded = fs::read(&self.state_path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|data| gba.load_state(&data).map_err(|e| e.to_string()));
+                match loaded {
+                    Ok(()) => info!(target: "frontend", "Loaded state from {}", self.state_path.display()),
+                    Err(e) => warn!(target: "frontend", "failed to load state: {}", e),
+                }
+            },
+            HotkeyAction::Rewind => {
+                if let Err(e) = self.rewind.rewind(gba, REWIND_STEP) {
+                    warn!(target: "frontend", "failed to rewind: {}", e);
+                }
+            },
+            HotkeyAction::Screenshot => {
+                // Next to the ROM, numbered by frame: game.1234.png
+                let path = self.state_path.with_extension(format!("{}.png", gba.frame()));
+                match gba.screenshot().save_png(&path) {
+                    Ok(()) => info!(target: "frontend", "Saved screenshot to {}", path.display()),
+                    Err(e) => warn!(target: "frontend", "failed to save screenshot: {}", e),
+                }
+            },
+            HotkeyAction::FastForward => self.toggle_speed(pacing::FAST_FORWARD),
+            HotkeyAction::SlowMotion => self.toggle_speed(pacing::SLOW_MOTION),
+            HotkeyAction::Unthrottle => self.toggle_speed(Speed::Unlimited),
+            HotkeyAction::MuteChannel(ch) => toggle_mute(gba, ch),
+            HotkeyAction::SoloChannel(ch) => toggle_solo(gba, ch),
+            HotkeyAction::ToggleLayer(layer) => toggle_layer(gba, layer),
+            HotkeyAction::Quit => return false,
+            _ => {},
+        }
+        true
+    }
+
+    fn toggle_speed(&mut self, speed: Speed) {
+        self.pacer.toggle_speed(speed);
+        info!(target: "frontend", "Speed: {}", self.pacer.speed());
+    }
+
+    /// Speed and frame skip control the run loop; the hotkeys change them
+    pub fn pacer_mut(&mut self) -> &mut FramePacer {
+        &mut self.pacer
+    }
+
+    #[cfg(feature = "lua")]
+    fn run_frame(&mut self, gba: &mut Gba) {
+        match self.script.as_mut().map(|script| script.run_frame(gba)) {
+            Some(Err(e)) => {
+                // Keep playing without it
+                warn!(target: "frontend", "stopping script: {}", e);
+                self.script = None;
+            },
+            Some(Ok(())) => {},
+            None => gba.run_frame(),
+        }
+    }
+
+    #[cfg(not(feature = "lua"))]
+    fn run_frame(&mut self, gba: &mut Gba) {
+        gba.run_frame();
+    }
+
+    /// Play until the window is closed
+    pub fn run(&mut self, gba: &mut Gba) {
+        let mut presenter = Presenter::default();
+        presenter.set_post_process(PostProcess {
+            color_correction: gba.settings().color_correction,
+            ..self.post
+        });
+        let mut audio = self.audio.as_ref().map(|device| {
+            let apu_rate = gba.mem().io().apu.sample_rate();
+            AudioOutput::new(self.queue.clone(), apu_rate, device.spec().freq as u32)
+        });
+
+        // Rumble reaches the pad once a frame
+        let rumble = Rc::new(Cell::new(None));
+        let rumble_cb = rumble.clone();
+        gba.set_rumble_callback(move |on| rumble_cb.set(Some(on)));
+
+        'frames: while self.poll_input(gba) {
+            while let Some(action) = gba.take_hotkey() {
+                self.hotkeys.push_back(action);
+            }
+            while let Some(action) = self.hotkeys.pop_front() {
+                if let Some(post) = toggle_post_process(action, presenter.post_process()) {
+                    presenter.set_post_process(post);
+                }
+                else if !self.handle_hotkey(action, gba) {
+                    break 'frames;
+                }
+            }
+
+            self.run_frame(gba);
+            self.rewind.capture(gba);
+
+            if let (Some(on), Some(pad)) = (rumble.take(), self.controller.as_mut()) {
+                let strength = if on { RUMBLE_STRENGTH } else { 0 };
+                if let Err(e) = pad.set_rumble(strength, strength, RUMBLE_MS) {
+                    warn!(target: "frontend", "failed to rumble: {}", e);
+                }
+            }
+
+            // Sound only plays at normal speed. Faster it would back up in
+            // the queue, and slower it would run dry. It's recorded at any.
+            let samples = gba.mem_mut().io_mut().apu.drain_samples();
+            self.capture.record(gba, &samples);
+            if let Some(ref mut output) = audio {
+                if self.pacer.speed() == Speed::NORMAL {
+                    output.push(&samples, gba.mem().io().apu.sample_rate());
+                }
+            }
+            if self.pacer.should_show() {
+                presenter.present_ppu(&gba.mem().io().ppu, self);
+            }
+            self.pacer.wait();
+        }
+        gba.clear_rumble_callback();
+        self.capture.finish();
+    }
+}
+
+impl VideoSink for SdlFrontend {
+    fn present(&mut self, frame: &Frame, _meta: &FrameMetadata) {
+        if self.texture_size != (frame.width, frame.height) {
+            match self.canvas.texture_creator()
+                .create_texture_streaming(PixelFormatEnum::RGBA32,
+                                          frame.width as u32, frame.height as u32) {
+                Ok(texture) => {
+                    self.texture = texture;
+                    self.texture_size = (frame.width, frame.height);
+                },
+                Err(e) => {
+                    warn!(target: "frontend", "failed to resize frame texture: {}", e);
+                    return;
+                },
+            }
+        }
+        if let Err(e) = self.texture.update(None, frame.data, frame.pitch()) {
+            warn!(target: "frontend", "failed to update frame texture: {}", e);
+            return;
+        }
+        self.canvas.clear();
+        if let Err(e) = self.canvas.copy(&self.texture, None, None) {
+            warn!(target: "frontend", "failed to draw frame: {}", e);
+        }
+        self.canvas.present();
+    }
+
+    fn preferred_format(&self) -> PixelFormat {
+        PixelFormat::Rgba8888
+    }
+}
+
+impl SdlFrontend {
+    #[cfg(feature = "lua")]
+    fn load_script(&mut self, path: &str, gba: &mut Gba) -> Result<(), String> {
+        let script = ScriptHost::load(path, gba)
+            .map_err(|e| format!("Failed to load {}: {}", path, e))?;
+        self.script = Some(script);
+        Ok(())
+    }
+
+    #[cfg(not(feature = "lua"))]
+    fn load_script(&mut self, _path: &str, _gba: &mut Gba) -> Result<(), String> {
+        Err("--script needs the lua feature".to_string())
+    }
+}
+
+/// Open a window and play the game
+pub fn run(opts: &Options, config: &Config, gba: &mut Gba) -> Result<(), String> {
+    start_session(opts, config, gba)?;
+
+    let mut frontend = SdlFrontend::open(config, opts.state_path(config))?;
+    frontend.pacer_mut().set_speed(opts.speed);
+    frontend.pacer_mut().set_frame_skip(opts.frame_skip);
+    if let Some(ref path) = opts.script {
+        frontend.load_script(path, gba)?;
+    }
+    frontend.capture = Capture::start(opts.record_video.as_deref(), gba)?;
+    frontend.run(gba);
+
+    end_session(opts, config, gba)
+}
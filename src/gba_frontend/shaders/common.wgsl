@@ -0,0 +1,27 @@
+// Put in front of every scaling shader, which only has to supply fs_main.
+// The frame is drawn with one triangle covering the viewport, the picture
+// kept at the GBA's aspect ratio.
+
+struct Params {
+    source_size: vec2<f32>, // Of the frame, in pixels
+    output_size: vec2<f32>, // Of the viewport it is drawn into
+}
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>, // 0 to 1 across the frame
+}
+
+@group(0) @binding(0) var frame: texture_2d<f32>;
+@group(0) @binding(1) var nearest_sampler: sampler;
+@group(0) @binding(2) var linear_sampler: sampler;
+@group(0) @binding(3) var<uniform> params: Params;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
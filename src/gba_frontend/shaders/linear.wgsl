@@ -0,0 +1,5 @@
+// Smoothed between pixels, as the CPU path's linear filter draws it
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(frame, linear_sampler, in.uv);
+}
@@ -0,0 +1,5 @@
+// Each pixel a sharp block, as the CPU path draws it
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(frame, nearest_sampler, in.uv);
+}
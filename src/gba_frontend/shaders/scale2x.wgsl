@@ -0,0 +1,34 @@
+// Scale2x (EPX): each pixel split in four, each quarter taking the color
+// of the neighbours beside it where they agree, which rounds off diagonal
+// edges without blurring
+fn texel(pos: vec2<i32>, offset: vec2<i32>) -> vec4<f32> {
+    let size = vec2<i32>(params.source_size);
+    return textureLoad(frame, clamp(pos + offset, vec2<i32>(0, 0), size - 1), 0);
+}
+
+fn same(a: vec4<f32>, b: vec4<f32>) -> bool {
+    return all(a == b);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let pos = in.uv * params.source_size;
+    let p = vec2<i32>(floor(pos));
+    let quarter = fract(pos);
+
+    let b = texel(p, vec2<i32>(0, -1));
+    let d = texel(p, vec2<i32>(-1, 0));
+    let e = texel(p, vec2<i32>(0, 0));
+    let f = texel(p, vec2<i32>(1, 0));
+    let h = texel(p, vec2<i32>(0, 1));
+
+    // The two neighbours meeting at this quarter's corner
+    let across = select(d, f, quarter.x >= 0.5);
+    let along = select(b, h, quarter.y >= 0.5);
+    let opposite_across = select(f, d, quarter.x >= 0.5);
+    let opposite_along = select(h, b, quarter.y >= 0.5);
+    if same(across, along) && !same(along, opposite_across) && !same(across, opposite_along) {
+        return across;
+    }
+    return e;
+}
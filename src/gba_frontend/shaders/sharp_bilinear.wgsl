@@ -0,0 +1,11 @@
+// Nearest to the largest whole scale that fits, then bilinear for the
+// rest, so pixels stay sharp and evenly sized at any window size
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = in.uv * params.source_size;
+    let scale = max(floor(params.output_size / params.source_size), vec2<f32>(1.0, 1.0));
+    let region = 0.5 - 0.5 / scale;
+    let from_center = fract(texel) - 0.5;
+    let offset = (from_center - clamp(from_center, -region, region)) * scale + 0.5;
+    return textureSample(frame, linear_sampler, (floor(texel) + offset) / params.source_size);
+}
@@ -0,0 +1,102 @@
+use wasm_bindgen::prelude::*;
+
+use gba_config::{Config, RtcMode};
+use gba_keypad::BUTTONS;
+use gba_mem::gpio::rtc::RTC_EPOCH;
+use gba_ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use gba_system::Gba;
+use gba_video::{convert, PixelFormat};
+
+/// The emulator as seen from JavaScript. The page hands over the ROM's
+/// bytes, then once per animation frame pushes input, runs a frame, draws
+/// the framebuffer and queues the audio. See web/ for an example page.
+#[wasm_bindgen]
+#[derive(Debug)]
+pub struct WebGba {
+    gba: Gba,
+    frame: Vec<u8>, // RGBA, reused between frames
+}
+
+#[wasm_bindgen]
+impl WebGba {
+    /// now is the time the cartridge clock starts at, in seconds since
+    /// 1970, e.g. Date.now() / 1000
+    #[wasm_bindgen(constructor)]
+    pub fn new(rom: &[u8], now: f64) -> Result<WebGba, JsValue> {
+        let mut gba = Gba::load_bytes(rom, &Config::default())
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+
+        // There is no host clock to read in the browser, so a cartridge
+        // clock runs with emulated time from what the page says it is
+        let mut settings = gba.settings().clone();
+        let has_rtc = gba.game().is_some_and(|game| game.has_rtc());
+        if settings.rtc == RtcMode::Host || (settings.rtc == RtcMode::Auto && has_rtc) {
+            settings.rtc = RtcMode::Fixed;
+            settings.rtc_start = Some(if now > RTC_EPOCH as f64 { now as u64 } else { RTC_EPOCH });
+        }
+        gba.set_settings(settings);
+
+        Ok(WebGba {
+            gba,
+            frame: Vec::new(),
+        })
+    }
+
+    /// Screen width in pixels
+    pub fn width() -> usize {
+        SCREEN_WIDTH
+    }
+
+    /// Screen height in pixels
+    pub fn height() -> usize {
+        SCREEN_HEIGHT
+    }
+
+    /// Press or release a button, given by its KEYINPUT bit (A = 1, B = 2,
+    /// Select = 4, ..., L = 0x200). Other values are ignored.
+    pub fn set_button(&mut self, button: u16, pressed: bool) {
+        if let Some(&button) = BUTTONS.iter().find(|&&b| b as u16 == button) {
+            self.gba.set_button(button, pressed);
+        }
+    }
+
+    /// Run until the next frame is drawn
+    pub fn run_frame(&mut self) {
+        self.gba.run_frame();
+    }
+
+    /// The last frame the PPU finished, as width * height RGBA pixels ready
+    /// for an ImageData
+    pub fn framebuffer(&mut self) -> Vec<u8> {
+        convert(self.gba.mem().io().ppu.framebuffer().pixels(),
+                PixelFormat::Rgba8888, &mut self.frame);
+        self.frame.clone()
+    }
+
+    /// Samples per second the APU mixes at
+    pub fn sample_rate(&self) -> u32 {
+        self.gba.mem().io().apu.sample_rate()
+    }
+
+    /// Mix at the rate of the page's AudioContext
+    pub fn set_sample_rate(&mut self, rate: u32) {
+        if rate > 0 {
+            self.gba.mem_mut().io_mut().apu.set_sample_rate(rate);
+        }
+    }
+
+    /// Stereo samples mixed since the last call, interleaved left, right
+    pub fn take_audio(&mut self) -> Vec<i16> {
+        self.gba.mem_mut().io_mut().apu.drain_samples()
+    }
+
+    /// Battery-backed save data, for the page to keep in local storage
+    pub fn export_battery(&self) -> Vec<u8> {
+        self.gba.export_battery()
+    }
+
+    /// Load a battery save exported before
+    pub fn import_battery(&mut self, data: &[u8]) -> Result<(), JsValue> {
+        self.gba.import_battery(data).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+}
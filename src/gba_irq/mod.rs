@@ -0,0 +1,91 @@
+use core::fmt;
+
+/// Interrupt sources and their bit positions in IE/IF from:
+/// http://problemkaputt.de/gbatek.htm#gbainterruptcontrol
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interrupt {
+    /// Vertical blank started
+    VBlank  = 0x0001,
+    /// Horizontal blank started
+    HBlank  = 0x0002,
+    /// VCOUNT matched the DISPSTAT LYC setting
+    VCount  = 0x0004,
+    /// Timer 0 overflowed
+    Timer0  = 0x0008,
+    /// Timer 1 overflowed
+    Timer1  = 0x0010,
+    /// Timer 2 overflowed
+    Timer2  = 0x0020,
+    /// Timer 3 overflowed
+    Timer3  = 0x0040,
+    /// Serial transfer finished
+    Serial  = 0x0080,
+    /// DMA 0 finished
+    Dma0    = 0x0100,
+    /// DMA 1 finished
+    Dma1    = 0x0200,
+    /// DMA 2 finished
+    Dma2    = 0x0400,
+    /// DMA 3 finished
+    Dma3    = 0x0800,
+    /// KEYCNT's condition was met
+    Keypad  = 0x1000,
+    /// Cartridge removed, or its IRQ pin raised
+    GamePak = 0x2000,
+}
+
+/// Bits of IE and IF that exist
+pub const IRQ_MASK: u16 = 0x3FFF;
+/// Bits of IME that exist
+pub const IME_MASK: u16 = 0x0001;
+
+/// Interrupt master enable, enable, and request flag registers
+#[derive(Clone, Copy, Debug, Default)]
+pub struct IrqController {
+    ie: u16,
+    if_: u16,
+    ime: u16,
+}
+
+impl_save_state!(IrqController { ie, if_, ime });
+impl_serde_via_state!(IrqController);
+
+impl IrqController {
+    /// Latch an interrupt request into IF, whether or not it is enabled
+    pub fn request(&mut self, irq: Interrupt) {
+        trace!(target: "irq", "{:?} requested", irq);
+        self.if_ |= irq as u16;
+    }
+
+    /// An enabled interrupt is waiting and the master enable is set
+    pub fn is_pending(&self) -> bool {
+        self.ime & IME_MASK != 0 && self.is_requested()
+    }
+
+    /// An enabled interrupt is waiting, whatever IME says. This is what
+    /// wakes a halted CPU.
+    pub fn is_requested(&self) -> bool {
+        self.ie & self.if_ != 0
+    }
+
+    /// Interrupt enable
+    pub fn ie(&self) -> u16          { self.ie }
+    /// Write IE
+    pub fn set_ie(&mut self, val: u16) { self.ie = val & IRQ_MASK; }
+
+    /// Interrupt request flags; writing a 1 to a bit acknowledges it
+    pub fn if_(&self) -> u16      { self.if_ }
+    /// Acknowledge the requests set in val
+    pub fn ack(&mut self, val: u16) { self.if_ &= !val; }
+
+    /// Interrupt master enable
+    pub fn ime(&self) -> u16           { self.ime }
+    /// Write IME
+    pub fn set_ime(&mut self, val: u16) { self.ime = val & IME_MASK; }
+}
+
+impl fmt::Display for IrqController {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write![f, "IE:{:#06x} IF:{:#06x} IME:{}", self.ie, self.if_, self.ime]
+    }
+}
@@ -0,0 +1,121 @@
+use core::fmt;
+
+use gba_apu::inspect::Channel;
+use gba_keypad::{Button, BUTTONS};
+use gba_ppu::Layer;
+
+/// Emulator actions that can be bound to a chord on the emulated pad
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HotkeyAction {
+    /// Save to the current slot
+    SaveState,
+    /// Load from the current slot
+    LoadState,
+    /// Step back through the rewind buffer
+    Rewind,
+    /// Toggle
+    Pause,
+    /// Toggle between normal speed and double
+    FastForward,
+    /// Toggle between normal speed and half
+    SlowMotion,
+    /// Toggle between normal speed and as fast as possible
+    Unthrottle,
+    /// Reset the game
+    Reset,
+    /// Save the screen to a PNG
+    Screenshot,
+    /// Toggle
+    ColorCorrection,
+    /// Toggle
+    FrameBlending,
+    /// Toggle, prescaling by 2 if nothing else is
+    Scanlines,
+    /// Toggle
+    MuteChannel(Channel),
+    /// Toggle between just this channel and all of them
+    SoloChannel(Channel),
+    /// Toggle drawing the layer
+    ToggleLayer(Layer),
+    /// Close the emulator
+    Quit,
+}
+
+impl HotkeyAction {
+    /// The action's name in snake case, e.g. "save_state". The channel and
+    /// layer actions name theirs, e.g. "mute_wave", "solo_fifo_a" or
+    /// "layer_bg2".
+    pub fn from_name(name: &str) -> Option<HotkeyAction> {
+        if let Some(channel) = name.strip_prefix("mute_") {
+            return Channel::from_name(channel).map(HotkeyAction::MuteChannel);
+        }
+        if let Some(channel) = name.strip_prefix("solo_") {
+            return Channel::from_name(channel).map(HotkeyAction::SoloChannel);
+        }
+        if let Some(layer) = name.strip_prefix("layer_") {
+            return Layer::from_name(layer).map(HotkeyAction::ToggleLayer);
+        }
+        match name {
+            "save_state" => Some(HotkeyAction::SaveState),
+            "load_state" => Some(HotkeyAction::LoadState),
+            "rewind" => Some(HotkeyAction::Rewind),
+            "pause" => Some(HotkeyAction::Pause),
+            "fast_forward" => Some(HotkeyAction::FastForward),
+            "slow_motion" => Some(HotkeyAction::SlowMotion),
+            "unthrottle" => Some(HotkeyAction::Unthrottle),
+            "reset" => Some(HotkeyAction::Reset),
+            "screenshot" => Some(HotkeyAction::Screenshot),
+            "color_correction" => Some(HotkeyAction::ColorCorrection),
+            "frame_blending" => Some(HotkeyAction::FrameBlending),
+            "scanlines" => Some(HotkeyAction::Scanlines),
+            "quit" => Some(HotkeyAction::Quit),
+            _ => None,
+        }
+    }
+}
+
+/// Buttons that trigger an action when all of them are held together
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Chord {
+    buttons: u16,
+    action: HotkeyAction,
+}
+
+impl Chord {
+    /// A chord of buttons, all held together, triggering action
+    pub fn new(buttons: &[Button], action: HotkeyAction) -> Chord {
+        Chord {
+            buttons: buttons.iter().fold(0, |acc, &b| acc | b as u16),
+            action,
+        }
+    }
+
+    /// What the chord triggers
+    pub fn action(&self) -> HotkeyAction {
+        self.action
+    }
+
+    /// Bits of the buttons in the chord, as in KEYINPUT but active-high
+    pub fn buttons(&self) -> u16 {
+        self.buttons
+    }
+
+    /// The chord has just been completed by pressing button
+    pub fn completed_by(&self, button: Button, pressed: u16) -> bool {
+        self.buttons & button as u16 != 0 && pressed & self.buttons == self.buttons
+    }
+}
+
+impl fmt::Display for Chord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut first = true;
+        for button in BUTTONS.iter().filter(|&&b| self.buttons & b as u16 != 0) {
+            if !first {
+                write![f, "+"]?;
+            }
+            write![f, "{}", button]?;
+            first = false;
+        }
+        write![f, " -> {:?}", self.action]
+    }
+}
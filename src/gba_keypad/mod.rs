@@ -0,0 +1,272 @@
+/// Hotkeys bound to chords on the pad
+pub mod hotkey;
+
+use alloc::collections::VecDeque;
+use core::fmt;
+
+use gba_keypad::hotkey::{Chord, HotkeyAction};
+use gba_irq::{Interrupt, IrqController};
+use gba_mem::Address;
+use prelude::*;
+
+/// Keypad registers from:
+/// http://problemkaputt.de/gbatek.htm#gbakeypadinput
+pub const KEYINPUT: Address = 0x04000130;
+/// Keypad interrupt control
+pub const KEYCNT:   Address = 0x04000132;
+
+const KEY_MASK:    u16 = 0x03FF; // Ten buttons
+const KEYCNT_IRQ:  u16 = 0x4000;
+const KEYCNT_AND:  u16 = 0x8000; // Condition; 0 = any selected key, 1 = all
+/// Bits of KEYCNT that can be written
+pub const KEYCNT_MASK: u16 = KEY_MASK | KEYCNT_IRQ | KEYCNT_AND;
+
+/// The buttons, as their bits in KEYINPUT
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Button {
+    /// A button
+    A      = 0x0001,
+    /// B button
+    B      = 0x0002,
+    /// Select button
+    Select = 0x0004,
+    /// Start button
+    Start  = 0x0008,
+    /// D-pad right
+    Right  = 0x0010,
+    /// D-pad left
+    Left   = 0x0020,
+    /// D-pad up
+    Up     = 0x0040,
+    /// D-pad down
+    Down   = 0x0080,
+    /// Right shoulder
+    R      = 0x0100,
+    /// Left shoulder
+    L      = 0x0200,
+}
+
+/// Every button, in KEYINPUT bit order
+pub const BUTTONS: [Button; 10] = [
+    Button::A, Button::B, Button::Select, Button::Start, Button::Right,
+    Button::Left, Button::Up, Button::Down, Button::R, Button::L,
+];
+
+impl fmt::Display for Button {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Button::A      => "A",
+            Button::B      => "B",
+            Button::Select => "Select",
+            Button::Start  => "Start",
+            Button::Right  => "Right",
+            Button::Left   => "Left",
+            Button::Up     => "Up",
+            Button::Down   => "Down",
+            Button::R      => "R",
+            Button::L      => "L",
+        };
+        write![f, "{}", name]
+    }
+}
+
+/// The buttons, the keypad interrupt and the emulator's hotkeys
+#[derive(Clone, Debug, Default)]
+pub struct Keypad {
+    pressed: u16, // Active-high; KEYINPUT reads the inverse
+    keycnt: u16,
+    irq_line: bool, // KEYCNT condition was met at the last check
+    hotkeys: Vec<Chord>,
+    suppressed: u16, // Held chord buttons hidden from the game
+    fired: VecDeque<HotkeyAction>,
+    forced: u16, // Held whatever the pad says, e.g. by the Game Boy Player
+}
+
+// Held buttons follow the host's input, so only the game's side is saved
+impl_save_state!(Keypad { keycnt, irq_line });
+impl_serde_via_state!(Keypad);
+
+impl Keypad {
+    /// Press or release button, raising the keypad interrupt if that meets KEYCNT's condition
+    pub fn set_button(&mut self, button: Button, pressed: bool,
+                      irq: &mut IrqController) {
+        if pressed {
+            self.pressed |= button as u16;
+        }
+        else {
+            self.pressed &= !(button as u16);
+        }
+
+        // Chord buttons stay hidden until they are let go
+        self.suppressed &= self.pressed;
+        if pressed {
+            for chord in self.hotkeys.iter() {
+                if chord.completed_by(button, self.pressed) {
+                    self.suppressed |= chord.buttons();
+                    self.fired.push_back(chord.action());
+                }
+            }
+        }
+
+        self.update_irq(irq);
+    }
+
+    /// Pressed on the pad, whether or not the game can see it
+    pub fn is_pressed(&self, button: Button) -> bool {
+        self.pressed & button as u16 != 0
+    }
+
+    /// Release every button
+    pub fn release_all(&mut self, irq: &mut IrqController) {
+        self.pressed = 0;
+        self.suppressed = 0;
+        self.update_irq(irq);
+    }
+
+    /// Bind a chord of buttons to an emulator action
+    pub fn add_hotkey(&mut self, buttons: &[Button], action: HotkeyAction) {
+        self.hotkeys.push(Chord::new(buttons, action));
+    }
+
+    /// The hotkeys bound so far
+    pub fn hotkeys(&self) -> &[Chord] {
+        &self.hotkeys
+    }
+
+    /// Unbind every hotkey
+    pub fn clear_hotkeys(&mut self) {
+        self.hotkeys.clear();
+        self.suppressed = 0;
+    }
+
+    /// Next hotkey triggered on the pad, oldest first
+    pub fn take_hotkey(&mut self) -> Option<HotkeyAction> {
+        self.fired.pop_front()
+    }
+
+    /// Replace the held buttons wholesale, e.g. from a movie. Hotkeys don't
+    /// fire, so the game sees exactly mask.
+    pub fn set_held(&mut self, mask: u16, irq: &mut IrqController) {
+        self.pressed = mask & KEY_MASK;
+        self.suppressed = 0;
+        self.update_irq(irq);
+    }
+
+    /// Buttons the game sees as held
+    pub fn visible(&self) -> u16 {
+        self.pressed & !self.suppressed | self.forced
+    }
+
+    /// Hold the buttons in mask whatever the pad says
+    pub fn set_forced(&mut self, mask: u16, irq: &mut IrqController) {
+        self.forced = mask & KEY_MASK;
+        self.update_irq(irq);
+    }
+
+    /// Buttons are active-low: a cleared bit means pressed
+    pub fn keyinput(&self) -> u16 {
+        !self.visible() & KEY_MASK
+    }
+
+    /// Keypad interrupt control as written
+    pub fn keycnt(&self) -> u16 {
+        self.keycnt
+    }
+
+    /// Write KEYCNT, which may raise the keypad interrupt straight away
+    pub fn set_keycnt(&mut self, val: u16, irq: &mut IrqController) {
+        self.keycnt = val & KEYCNT_MASK;
+        self.update_irq(irq);
+    }
+
+    // The keypad interrupt fires when the KEYCNT condition becomes true
+    fn irq_condition(&self) -> bool {
+        let selected = self.keycnt & KEY_MASK;
+        if self.keycnt & KEYCNT_IRQ == 0 || selected == 0 {
+            return false;
+        }
+
+        if self.keycnt & KEYCNT_AND != 0 {
+            self.visible() & selected == selected
+        }
+        else {
+            self.visible() & selected != 0
+        }
+    }
+
+    fn update_irq(&mut self, irq: &mut IrqController) {
+        let line = self.irq_condition();
+        if line && !self.irq_line {
+            irq.request(Interrupt::Keypad);
+        }
+        self.irq_line = line;
+    }
+}
+
+impl fmt::Display for Keypad {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write![f, "Keypad: KEYINPUT:{:#06x} KEYCNT:{:#06x} [",
+               self.keyinput(), self.keycnt]?;
+        for button in BUTTONS.iter().filter(|&&b| self.is_pressed(b)) {
+            write![f, " {}", button]?;
+        }
+        write![f, " ]"]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use self::Button::*;
+
+    #[test]
+    fn keycnt_condition_raises_the_keypad_irq() {
+        let ab = A as u16 | B as u16;
+        // KEYCNT, buttons held, whether IF bit 12 is raised
+        let cases: &[(u16, &[Button], bool)] = &[
+            (KEYCNT_IRQ | ab,              &[],              false),
+            (KEYCNT_IRQ | ab,              &[A],             true),
+            (KEYCNT_IRQ | ab,              &[B, Start],      true),
+            (KEYCNT_IRQ | ab,              &[Start],         false),
+            (KEYCNT_IRQ | KEYCNT_AND | ab, &[A],             false),
+            (KEYCNT_IRQ | KEYCNT_AND | ab, &[A, B],          true),
+            (KEYCNT_IRQ | KEYCNT_AND | ab, &[A, Start, B],   true),
+            (KEYCNT_IRQ | KEYCNT_AND | ab, &[Start, Select], false),
+            (ab,                           &[A, B],          false),
+            (KEYCNT_AND | ab,              &[A, B],          false),
+            (KEYCNT_IRQ,                   &[A, B],          false),
+            (KEYCNT_IRQ | KEYCNT_AND,      &[A, B],          false),
+        ];
+
+        for (i, &(keycnt, held, raised)) in cases.iter().enumerate() {
+            let mut keypad = Keypad::default();
+            let mut irq = IrqController::default();
+            keypad.set_keycnt(keycnt, &mut irq);
+            for &button in held {
+                keypad.set_button(button, true, &mut irq);
+            }
+            assert_eq!(irq.if_() & Interrupt::Keypad as u16 != 0, raised,
+                       "case {}: KEYCNT {:#06x}", i, keycnt);
+        }
+    }
+
+    #[test]
+    fn keypad_irq_fires_on_the_rising_edge() {
+        let mut keypad = Keypad::default();
+        let mut irq = IrqController::default();
+        keypad.set_button(A, true, &mut irq);
+
+        // Already met when KEYCNT is written
+        keypad.set_keycnt(KEYCNT_IRQ | A as u16, &mut irq);
+        assert_eq!(irq.if_(), Interrupt::Keypad as u16);
+
+        // Staying met doesn't raise it again
+        irq.ack(Interrupt::Keypad as u16);
+        keypad.set_button(B, true, &mut irq);
+        assert_eq!(irq.if_(), 0);
+
+        keypad.set_button(A, false, &mut irq);
+        keypad.set_button(A, true, &mut irq);
+        assert_eq!(irq.if_(), Interrupt::Keypad as u16);
+    }
+}
@@ -0,0 +1,145 @@
+// ROMs are usually passed around compressed. Archives are told apart from
+// raw images by their magic number rather than the file extension, since a
+// ROM can start with anything but never with these.
+
+use std::fs;
+#[cfg(feature = "archive")]
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+#[cfg(feature = "archive")]
+use flate2::read::GzDecoder;
+#[cfg(feature = "archive")]
+use zip::ZipArchive;
+
+use gba_error::{GbaError, GbaResult};
+
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+const GZIP_MAGIC: &[u8] = b"\x1F\x8B";
+const SEVEN_ZIP_MAGIC: &[u8] = b"7z\xBC\xAF\x27\x1C";
+
+// Entries in a zip taken to be the ROM
+#[cfg(feature = "archive")]
+const ROM_EXTS: [&str; 2] = ["gba", "bin"];
+
+/// Compressed formats ROMs can come in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArchiveKind {
+    /// Zip, taking the first .gba or .bin inside
+    Zip,
+    /// Gzip
+    Gzip,
+    /// 7-Zip, taking the first .gba or .bin inside
+    SevenZip,
+}
+
+impl ArchiveKind {
+    /// The format data is in, going by its magic number
+    pub fn detect(data: &[u8]) -> Option<ArchiveKind> {
+        if data.starts_with(ZIP_MAGIC) {
+            Some(ArchiveKind::Zip)
+        }
+        else if data.starts_with(GZIP_MAGIC) {
+            Some(ArchiveKind::Gzip)
+        }
+        else if data.starts_with(SEVEN_ZIP_MAGIC) {
+            Some(ArchiveKind::SevenZip)
+        }
+        else {
+            None
+        }
+    }
+}
+
+/// The file at path, decompressed if it is an archive
+pub fn read_rom<P: AsRef<Path>>(path: P) -> GbaResult<Vec<u8>> {
+    extract_rom(fs::read(path)?)
+}
+
+/// The ROM in data if it is an archive, or else data as it is. A zip gives
+/// its first .gba or .bin file.
+pub fn extract_rom(data: Vec<u8>) -> GbaResult<Vec<u8>> {
+    match ArchiveKind::detect(&data) {
+        None => Ok(data),
+        Some(ArchiveKind::SevenZip) =>
+            Err(GbaError::Archive("7z archives aren't supported; extract the ROM first"
+                                  .to_string())),
+        Some(kind) => decompress(kind, &data),
+    }
+}
+
+#[cfg(feature = "archive")]
+fn decompress(kind: ArchiveKind, data: &[u8]) -> GbaResult<Vec<u8>> {
+    let mut rom = Vec::new();
+    match kind {
+        ArchiveKind::Gzip => {
+            GzDecoder::new(data).read_to_end(&mut rom)?;
+        },
+        _ => {
+            let archive_error = |e: ::zip::result::ZipError| GbaError::Archive(e.to_string());
+            let mut zip = ZipArchive::new(Cursor::new(data)).map_err(archive_error)?;
+            let index = (0..zip.len()).find(|&i| {
+                zip.by_index(i).ok()
+                    .is_some_and(|entry| !entry.is_dir() && is_rom_name(entry.name()))
+            }).ok_or_else(|| GbaError::Archive("no .gba or .bin file in the zip".to_string()))?;
+            zip.by_index(index).map_err(archive_error)?.read_to_end(&mut rom)?;
+        },
+    }
+    Ok(rom)
+}
+
+#[cfg(not(feature = "archive"))]
+fn decompress(_kind: ArchiveKind, _data: &[u8]) -> GbaResult<Vec<u8>> {
+    Err(GbaError::Archive("built without archive support; extract the ROM first".to_string()))
+}
+
+#[cfg(feature = "archive")]
+fn is_rom_name(name: &str) -> bool {
+    Path::new(name).extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ROM_EXTS.iter().any(|rom_ext| ext.eq_ignore_ascii_case(rom_ext)))
+}
+
+#[cfg(all(test, feature = "archive"))]
+mod tests {
+    use std::io::Write;
+
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use zip::ZipWriter;
+    use zip::write::FileOptions;
+
+    use super::*;
+
+    const ROM: &[u8] = b"\x2E\x00\x00\xEA not really a ROM";
+
+    fn zip_of(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        for &(name, data) in files {
+            zip.start_file(name, FileOptions::default()).unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn raw_roms_pass_through() {
+        assert_eq!(extract_rom(ROM.to_vec()).unwrap(), ROM);
+    }
+
+    #[test]
+    fn zips_give_the_first_rom() {
+        let zip = zip_of(&[("readme.txt", b"hello"), ("Game (E).GBA", ROM), ("other.bin", b"no")]);
+        assert_eq!(extract_rom(zip).unwrap(), ROM);
+
+        let zip = zip_of(&[("readme.txt", b"hello")]);
+        assert!(extract_rom(zip).is_err());
+    }
+
+    #[test]
+    fn gzip() {
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        gz.write_all(ROM).unwrap();
+        assert_eq!(extract_rom(gz.finish().unwrap()).unwrap(), ROM);
+    }
+}
@@ -0,0 +1,161 @@
+use alloc::collections::VecDeque;
+use core::fmt;
+
+use gba_mem::Address;
+
+/// A single CPU access on the bus
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BusAccess {
+    /// Address of the access
+    pub addr: Address,
+    /// Bytes
+    pub size: u8,
+    /// Value read or written
+    pub val: u32,
+    /// Whether it was a write
+    pub write: bool,
+}
+
+impl fmt::Display for BusAccess {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = if self.write { "write" } else { "read" };
+        write![f, "{}{} {:#010x} = {:#x}", op, self.size * 8, self.addr, self.val]
+    }
+}
+
+/// The first access where a replay differed from the recording. None on
+/// either side means one run made fewer accesses than the other.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BusMismatch {
+    /// What the recording had
+    pub expected: Option<BusAccess>,
+    /// What the replay did
+    pub actual: Option<BusAccess>,
+}
+
+impl fmt::Display for BusMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.expected, self.actual) {
+            (Some(e), Some(a)) => write![f, "expected {}, got {}", e, a],
+            (Some(e), None) => write![f, "expected {}, got nothing", e],
+            (None, Some(a)) => write![f, "unexpected {}", a],
+            (None, None) => write![f, "no mismatch"],
+        }
+    }
+}
+
+/// What the bus log is doing
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Default)]
+pub enum BusLogMode {
+    /// Not logging
+    #[default]
+    Off,
+    /// Accesses go to the hardware and are logged
+    Record,
+    /// Reads are answered from the log and writes are dropped
+    Replay,
+}
+
+
+/// Recording of CPU bus traffic so a second core can be run over exactly
+/// the same accesses without touching the hardware twice
+#[derive(Debug, Default)]
+pub struct BusLog {
+    mode: BusLogMode,
+    accesses: VecDeque<BusAccess>,
+    mismatch: Option<BusMismatch>,
+}
+
+impl BusLog {
+    /// What the log is doing
+    pub fn mode(&self) -> BusLogMode {
+        self.mode
+    }
+
+    /// Start a new recording
+    pub fn record(&mut self) {
+        self.mode = BusLogMode::Record;
+        self.accesses.clear();
+        self.mismatch = None;
+    }
+
+    /// Answer reads from the recording, from the start
+    pub fn replay(&mut self) {
+        self.mode = BusLogMode::Replay;
+        self.mismatch = None;
+    }
+
+    /// Stop logging, reporting whether the replay matched the recording
+    pub fn stop(&mut self) -> Result<(), BusMismatch> {
+        let replaying = self.mode == BusLogMode::Replay;
+        self.mode = BusLogMode::Off;
+
+        if let Some(mismatch) = self.mismatch.take() {
+            return Err(mismatch);
+        }
+        match self.accesses.pop_front() {
+            Some(left) if replaying => Err(BusMismatch {
+                expected: Some(left),
+                actual: None,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    // Next logged access while replaying, noting the first mismatch
+    fn replay_next(&mut self, access: BusAccess) -> Option<BusAccess> {
+        let expected = self.accesses.pop_front();
+        let matches = match expected {
+            Some(e) => e.addr == access.addr && e.size == access.size &&
+                       e.write == access.write &&
+                       (!access.write || e.val == access.val),
+            None => false,
+        };
+
+        if matches {
+            expected
+        }
+        else {
+            if self.mismatch.is_none() {
+                self.mismatch = Some(BusMismatch {
+                    expected,
+                    actual: Some(access),
+                });
+            }
+            None
+        }
+    }
+
+    /// Value for a read while replaying. None means the read should go to
+    /// the hardware.
+    pub fn replay_read(&mut self, addr: Address, size: u8) -> Option<u32> {
+        if self.mode != BusLogMode::Replay {
+            return None;
+        }
+        let access = BusAccess { addr, size, val: 0, write: false };
+        self.replay_next(access).map(|a| a.val)
+    }
+
+    /// Log a read that went to the hardware, when recording
+    pub fn log_read(&mut self, access: BusAccess) {
+        if self.mode == BusLogMode::Record {
+            self.accesses.push_back(access);
+        }
+    }
+
+    /// Log a write, returning whether it should reach the hardware
+    pub fn log_write(&mut self, access: BusAccess) -> bool {
+        match self.mode {
+            BusLogMode::Off => true,
+            BusLogMode::Record => {
+                self.accesses.push_back(access);
+                true
+            },
+            BusLogMode::Replay => {
+                self.replay_next(access);
+                false
+            },
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use gba_mem::Address;
+use gba_mem::mem_regions::{ExternRam, InternRam, MemoryRegion};
+use gba_mem::page_table::{page, Page};
+use prelude::*;
+
+/// Pages of RAM that code can run from are tracked in 1KB pages
+pub const CODE_PAGE_SHIFT: usize = 10;
+/// Bytes in a code page
+pub const CODE_PAGE_SIZE: usize = 1 << CODE_PAGE_SHIFT;
+
+/// Counts the writes to each page of EWRAM and IWRAM, so code decoded from a
+/// page can tell when it has gone stale. The BIOS and ROM never change.
+#[derive(Clone, Debug)]
+pub struct CodePages {
+    ewram: Vec<u32>,
+    iwram: Vec<u32>,
+}
+
+impl Default for CodePages {
+    fn default() -> CodePages {
+        CodePages {
+            ewram: vec![0; ExternRam::SIZE >> CODE_PAGE_SHIFT],
+            iwram: vec![0; InternRam::SIZE >> CODE_PAGE_SHIFT],
+        }
+    }
+}
+
+impl CodePages {
+    // Both RAMs are mirrored throughout their regions
+    fn page(addr: Address) -> Option<(bool, usize)> {
+        match page(addr) {
+            Page::Ewram =>
+                Some((true, ((addr - ExternRam::lo()) % ExternRam::SIZE) >> CODE_PAGE_SHIFT)),
+            Page::Iwram =>
+                Some((false, ((addr - InternRam::lo()) % InternRam::SIZE) >> CODE_PAGE_SHIFT)),
+            _ => None,
+        }
+    }
+
+    /// Changes whenever the page holding addr is written
+    pub fn generation(&self, addr: Address) -> u32 {
+        match CodePages::page(addr) {
+            Some((true, i)) => self.ewram[i],
+            Some((false, i)) => self.iwram[i],
+            None => 0,
+        }
+    }
+
+    /// Bump the generation of addr's page, if it's in RAM
+    pub fn note_write(&mut self, addr: Address) {
+        let gen = match CodePages::page(addr) {
+            Some((true, i)) => &mut self.ewram[i],
+            Some((false, i)) => &mut self.iwram[i],
+            None => return,
+        };
+        *gen = gen.wrapping_add(1);
+    }
+}
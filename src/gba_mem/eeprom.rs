@@ -0,0 +1,313 @@
+use core::cell::Cell;
+
+use gba_error::{GbaError, GbaResult};
+use gba_mem::Address;
+use gba_state::{SaveState, StateReader, StateWriter};
+use prelude::*;
+
+// Serial EEPROM save chip on the ROM bus, at the top of the cartridge
+// space: http://problemkaputt.de/gbatek.htm#gbacartbackupeeprom
+//
+// Games talk to it a bit at a time through bit 0 of halfword accesses,
+// nearly always with DMA3. A read sends 11, the block address and a stop
+// bit, then takes 68 bits back: 4 to ignore and the block's 64, most
+// significant first. A write sends 10, the address, 64 bits of data and a
+// stop bit, and reads back 1 once the chip is ready again.
+//
+// The 512 byte parts take 6 bit addresses and the 8K ones 14 bits. Nothing
+// in the ROM says which is fitted, and guessing wrong corrupts saves, so
+// unless the settings say, the size is taken from the length of the first
+// DMA the game sends a request with.
+const EEPROM_START: Address = 0x0D000000;
+const EEPROM_END: Address = 0x0DFFFFFF;
+// ROMs over 16M only leave the last 256 bytes for the chip
+const EEPROM_LARGE_ROM_START: Address = 0x0DFFFF00;
+
+const EEPROM_MAX: usize = 0x2000;
+const BLOCK_BYTES: usize = 8;
+const CMD_READ: u32 = 0b11;
+const READ_BITS: u32 = 68;
+const READ_JUNK_BITS: u32 = 4;
+
+/// How big the chip is, which sets how many address bits games send
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EepromSize {
+    /// 512 bytes
+    Small,
+    /// 8K
+    Large,
+}
+
+impl EepromSize {
+    /// Bytes the chip holds
+    pub fn bytes(&self) -> usize {
+        match *self {
+            EepromSize::Small => 0x200,
+            EepromSize::Large => EEPROM_MAX,
+        }
+    }
+
+    fn addr_bits(&self) -> u32 {
+        match *self {
+            EepromSize::Small => 6,
+            EepromSize::Large => 14,
+        }
+    }
+
+    // From the halfwords in a DMA sending a read or a write request
+    fn from_request_len(count: u32) -> Option<EepromSize> {
+        match count {
+            9 | 73 => Some(EepromSize::Small),
+            17 | 81 => Some(EepromSize::Large),
+            _ => None,
+        }
+    }
+
+    fn from_bytes(bytes: usize) -> Option<EepromSize> {
+        match bytes {
+            0x200 => Some(EepromSize::Small),
+            EEPROM_MAX => Some(EepromSize::Large),
+            _ => None,
+        }
+    }
+}
+
+/// Serial EEPROM save chip, talked to a bit at a time over DMA
+#[derive(Clone, Debug)]
+pub struct Eeprom {
+    fitted: bool,
+    start: Address, // Lowest address it answers at
+    size: Option<EepromSize>, // Until the game's first request
+    data: Vec<u8>, // Only the start is used by a small chip
+    header: u32, // Command and address bits of the request being sent
+    value: u64, // Data bits of a write being sent
+    bits: u32, // Sent of the request so far
+    block: usize, // Being read back
+    read_pos: Cell<u32>, // Bits of it read back, READ_BITS once done
+}
+
+impl Default for Eeprom {
+    fn default() -> Eeprom {
+        Eeprom {
+            fitted: false,
+            start: EEPROM_START,
+            size: None,
+            data: vec![0xFF; EEPROM_MAX],
+            header: 0,
+            value: 0,
+            bits: 0,
+            block: 0,
+            read_pos: Cell::new(READ_BITS),
+        }
+    }
+}
+
+// Whether it is fitted and where comes from the settings, not the state
+impl SaveState for Eeprom {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(match self.size {
+            None => 0,
+            Some(EepromSize::Small) => 1,
+            Some(EepromSize::Large) => 2,
+        });
+        w.write_bytes(&self.data);
+        self.header.save_state(w);
+        self.value.save_state(w);
+        self.bits.save_state(w);
+        self.block.save_state(w);
+        self.read_pos.save_state(w);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> GbaResult<()> {
+        self.size = match r.read_u8()? {
+            0 => None,
+            1 => Some(EepromSize::Small),
+            2 => Some(EepromSize::Large),
+            n => return Err(GbaError::InvalidSaveState(format!("bad EEPROM size {}", n))),
+        };
+        r.read_bytes_into(&mut self.data)?;
+        self.header.load_state(r)?;
+        self.value.load_state(r)?;
+        self.bits.load_state(r)?;
+        self.block.load_state(r)?;
+        self.read_pos.load_state(r)
+    }
+}
+
+impl_serde_via_state!(Eeprom);
+
+impl Eeprom {
+    /// Whether the cartridge has one
+    pub fn is_fitted(&self) -> bool {
+        self.fitted
+    }
+
+    /// A size the settings give replaces one worked out before; with none,
+    /// it is worked out from the game's first request
+    pub fn fit(&mut self, fitted: bool, size: Option<EepromSize>, large_rom: bool) {
+        self.fitted = fitted;
+        self.start = if large_rom { EEPROM_LARGE_ROM_START } else { EEPROM_START };
+        if size.is_some() {
+            self.size = size;
+        }
+    }
+
+    /// How big the chip is, once known
+    pub fn size(&self) -> Option<EepromSize> {
+        self.size
+    }
+
+    /// Whether addr reaches the chip
+    pub fn maps(&self, addr: Address) -> bool {
+        self.fitted && addr >= self.start && addr <= EEPROM_END
+    }
+
+    /// A DMA of count halfwords is about to be sent to the chip
+    pub fn detect_size(&mut self, count: u32) {
+        if self.size.is_none() {
+            self.size = EepromSize::from_request_len(count);
+            if let Some(size) = self.size {
+                info!(target: "bus", "EEPROM is {} bytes, from a {} bit request",
+                      size.bytes(), count);
+            }
+        }
+    }
+
+    /// What would go in a battery file, once the size is known
+    pub fn contents(&self) -> Option<&[u8]> {
+        self.size.map(|size| &self.data[..size.bytes()])
+    }
+
+    /// Fill the chip from a battery file, its length giving the size
+    pub fn load(&mut self, data: &[u8]) -> GbaResult<()> {
+        let size = EepromSize::from_bytes(data.len()).ok_or_else(|| GbaError::InvalidSaveFile(
+            format!("{} Bytes of EEPROM, expected 512 or 8192", data.len())))?;
+        self.size = Some(size);
+        self.data[..data.len()].copy_from_slice(data);
+        Ok(())
+    }
+
+    fn addr_bits(&mut self) -> u32 {
+        if self.size.is_none() {
+            warn!(target: "bus", "EEPROM request not sent by DMA, assuming 512 bytes");
+            self.size = Some(EepromSize::Small);
+        }
+        self.size.map_or(0, |size| size.addr_bits())
+    }
+
+    fn block_start(&self, block: usize) -> usize {
+        let blocks = self.size.map_or(EEPROM_MAX, |size| size.bytes()) / BLOCK_BYTES;
+        (block % blocks) * BLOCK_BYTES
+    }
+
+    /// The next bit of a read
+    pub fn read(&self) -> u16 {
+        let pos = self.read_pos.get();
+        if pos >= READ_BITS {
+            return 1; // Ready
+        }
+        self.read_pos.set(pos + 1);
+        if pos < READ_JUNK_BITS {
+            return 0;
+        }
+        let bit = pos - READ_JUNK_BITS;
+        let byte = self.data[self.block_start(self.block) + bit as usize / 8];
+        (byte >> (7 - bit % 8)) as u16 & 1
+    }
+
+    /// Clock in the next bit of a request
+    pub fn write(&mut self, val: u16) {
+        // Sending a request gives up on any read in progress
+        self.read_pos.set(READ_BITS);
+        let bit = val as u32 & 1;
+        if self.bits == 0 && bit == 0 {
+            return; // Waiting for a request
+        }
+
+        self.bits += 1;
+        let addr_bits = self.addr_bits();
+        let header_bits = 2 + addr_bits;
+        if self.bits <= header_bits {
+            self.header = self.header << 1 | bit;
+            return;
+        }
+
+        let block = (self.header & ((1 << addr_bits) - 1)) as usize;
+        if self.header >> addr_bits == CMD_READ {
+            // The stop bit
+            self.block = block;
+            self.read_pos.set(0);
+            self.end_request();
+        }
+        else if self.bits <= header_bits + 64 {
+            self.value = self.value << 1 | bit as u64;
+        }
+        else {
+            let start = self.block_start(block);
+            self.data[start..start + BLOCK_BYTES].copy_from_slice(&self.value.to_be_bytes());
+            self.end_request();
+        }
+    }
+
+    fn end_request(&mut self) {
+        self.header = 0;
+        self.value = 0;
+        self.bits = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn send(eeprom: &mut Eeprom, bits: u64, len: u32) {
+        eeprom.detect_size(len);
+        for i in (0..len).rev() {
+            eeprom.write((bits >> i) as u16 & 1);
+        }
+    }
+
+    fn write_block(eeprom: &mut Eeprom, addr_bits: u32, block: u64, val: u64) {
+        let request = (0b10 << addr_bits | block) as u128;
+        let bits = (request << 64 | val as u128) << 1;
+        eeprom.detect_size(3 + addr_bits + 64);
+        for i in (0..3 + addr_bits + 64).rev() {
+            eeprom.write((bits >> i) as u16 & 1);
+        }
+    }
+
+    fn read_block(eeprom: &mut Eeprom, addr_bits: u32, block: u64) -> u64 {
+        send(eeprom, (0b11 << addr_bits | block) << 1, 3 + addr_bits);
+        (0..READ_BITS).fold(0, |val, _| val << 1 | eeprom.read() as u64)
+    }
+
+    #[test]
+    fn size_comes_from_the_first_request() {
+        let mut eeprom = Eeprom::default();
+        eeprom.fit(true, None, false);
+        write_block(&mut eeprom, 14, 0x3FF, 0x0123456789ABCDEF);
+        assert_eq!(eeprom.size(), Some(EepromSize::Large));
+        assert_eq!(read_block(&mut eeprom, 14, 0x3FF), 0x0123456789ABCDEF);
+        assert_eq!(eeprom.read(), 1);
+        assert_eq!(&eeprom.contents().unwrap()[0x1FF8..], &[0x01, 0x23, 0x45, 0x67,
+                                                               0x89, 0xAB, 0xCD, 0xEF]);
+
+        let mut eeprom = Eeprom::default();
+        eeprom.fit(true, None, false);
+        write_block(&mut eeprom, 6, 3, 0xFEDCBA9876543210);
+        assert_eq!(eeprom.size(), Some(EepromSize::Small));
+        assert_eq!(read_block(&mut eeprom, 6, 3), 0xFEDCBA9876543210);
+        assert_eq!(eeprom.contents().unwrap().len(), 0x200);
+    }
+
+    #[test]
+    fn settings_fix_the_size() {
+        let mut eeprom = Eeprom::default();
+        eeprom.fit(true, Some(EepromSize::Large), true);
+        assert!(!eeprom.maps(EEPROM_START));
+        assert!(eeprom.maps(EEPROM_END));
+        eeprom.detect_size(9);
+        assert_eq!(eeprom.size(), Some(EepromSize::Large));
+        assert!(eeprom.load(&[0; 100]).is_err());
+    }
+}
@@ -0,0 +1,294 @@
+use gba_error::{GbaError, GbaResult};
+use gba_mem::Address;
+use gba_state::{SaveState, StateReader, StateWriter};
+use prelude::*;
+
+// Flash save chip on the SRAM bus, in place of the SRAM:
+// http://problemkaputt.de/gbatek.htm#gbacartbackupflashrom
+//
+// Commands are written as AAh to E005555h, 55h to E002AAAh, then the
+// command to E005555h. Erased bytes read FFh and writing a byte takes a
+// write command first. 128K chips show one 64K bank at a time.
+const CMD1_ADDR: usize = 0x5555;
+const CMD2_ADDR: usize = 0x2AAA;
+
+const CMD_ENTER_ID: u8 = 0x90;
+const CMD_EXIT_ID: u8 = 0xF0;
+const CMD_ERASE: u8 = 0x80;
+const CMD_ERASE_CHIP: u8 = 0x10;
+const CMD_ERASE_SECTOR: u8 = 0x30;
+const CMD_WRITE: u8 = 0xA0;
+const CMD_BANK: u8 = 0xB0;
+
+const BANK_BYTES: usize = 0x10000;
+const SECTOR_BYTES: usize = 0x1000;
+const PAGE_BYTES: usize = 0x80; // Atmel chips write a page at a time
+
+/// What the chip answers to in ID mode, and how it is written. Games check
+/// the ID to pick how to drive the chip, and some only know a few.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FlashId {
+    /// Manufacturer ID
+    pub manufacturer: u8,
+    /// Device ID
+    pub device: u8,
+    /// Of 64K
+    pub banks: usize,
+    /// A write command fills a 128 byte page, with no erase
+    pub page_writes: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Ready,
+    Cmd1, // Had the first byte of a command
+    Cmd2, // Had the second
+    Write, // The next byte written is stored
+    Page(u32), // Bytes of an Atmel page stored so far
+    Bank, // The next byte written to 0 picks the bank
+}
+
+impl SaveState for State {
+    fn save_state(&self, w: &mut StateWriter) {
+        let (tag, count) = match *self {
+            State::Ready => (0, 0),
+            State::Cmd1 => (1, 0),
+            State::Cmd2 => (2, 0),
+            State::Write => (3, 0),
+            State::Page(count) => (4, count),
+            State::Bank => (5, 0),
+        };
+        w.write_u8(tag);
+        w.write_u32(count);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> GbaResult<()> {
+        let tag = r.read_u8()?;
+        let count = r.read_u32()?;
+        *self = match tag {
+            0 => State::Ready,
+            1 => State::Cmd1,
+            2 => State::Cmd2,
+            3 => State::Write,
+            4 => State::Page(count),
+            5 => State::Bank,
+            n => return Err(GbaError::InvalidSaveState(format!("bad flash state {}", n))),
+        };
+        Ok(())
+    }
+}
+
+/// Flash save chip, driven by commands written to magic addresses
+#[derive(Clone, Debug)]
+pub struct Flash {
+    id: Option<FlashId>, // Fitted
+    data: Vec<u8>,
+    state: State,
+    id_mode: bool,
+    erasing: bool, // Had an erase command, waiting for what to erase
+    bank: usize,
+    page: usize, // Start of the Atmel page being written
+}
+
+impl Default for Flash {
+    fn default() -> Flash {
+        Flash {
+            id: None,
+            data: vec![0xFF; 2 * BANK_BYTES],
+            state: State::Ready,
+            id_mode: false,
+            erasing: false,
+            bank: 0,
+            page: 0,
+        }
+    }
+}
+
+// Which chip is fitted comes from the settings, not the state
+impl SaveState for Flash {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(&self.data);
+        self.state.save_state(w);
+        self.id_mode.save_state(w);
+        self.erasing.save_state(w);
+        self.bank.save_state(w);
+        self.page.save_state(w);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> GbaResult<()> {
+        r.read_bytes_into(&mut self.data)?;
+        self.state.load_state(r)?;
+        self.id_mode.load_state(r)?;
+        self.erasing.load_state(r)?;
+        self.bank.load_state(r)?;
+        self.page.load_state(r)
+    }
+}
+
+impl_serde_via_state!(Flash);
+
+impl Flash {
+    /// Whether the cartridge has one
+    pub fn is_fitted(&self) -> bool {
+        self.id.is_some()
+    }
+
+    /// Which chip is fitted
+    pub fn id(&self) -> Option<FlashId> {
+        self.id
+    }
+
+    /// Fit the chip id, or none
+    pub fn fit(&mut self, id: Option<FlashId>) {
+        self.id = id;
+        self.bank = self.bank.min(self.banks() - 1);
+    }
+
+    fn banks(&self) -> usize {
+        self.id.map_or(1, |id| id.banks)
+    }
+
+    /// What would go in a battery file
+    pub fn contents(&self) -> Option<&[u8]> {
+        self.id.map(|id| &self.data[..id.banks * BANK_BYTES])
+    }
+
+    /// Fill the chip from a battery file. A 64K file fills the first bank
+    /// of a 128K chip.
+    pub fn load(&mut self, data: &[u8]) -> GbaResult<()> {
+        if data.len() > self.data.len() {
+            return Err(GbaError::InvalidSaveFile(
+                format!("{} Bytes of flash, at most {} fit", data.len(), self.data.len())));
+        }
+        self.data[..data.len()].copy_from_slice(data);
+        Ok(())
+    }
+
+    /// Read a byte, or the ID in ID mode
+    pub fn read(&self, addr: Address) -> u8 {
+        let offset = addr % BANK_BYTES;
+        match (self.id, offset) {
+            (Some(id), 0) if self.id_mode => id.manufacturer,
+            (Some(id), 1) if self.id_mode => id.device,
+            _ => self.data[self.bank * BANK_BYTES + offset],
+        }
+    }
+
+    /// Write a byte, which is a command or the data of one
+    pub fn write(&mut self, addr: Address, val: u8) {
+        let offset = addr % BANK_BYTES;
+        let page_writes = self.id.is_some_and(|id| id.page_writes);
+        self.state = match (self.state, offset, val) {
+            (State::Write, _, _) => {
+                self.data[self.bank * BANK_BYTES + offset] = val;
+                State::Ready
+            },
+            (State::Page(count), _, _) => {
+                if count == 0 {
+                    self.page = self.bank * BANK_BYTES + (offset & !(PAGE_BYTES - 1));
+                    for b in self.data[self.page..self.page + PAGE_BYTES].iter_mut() {
+                        *b = 0xFF;
+                    }
+                }
+                self.data[self.page + (offset & (PAGE_BYTES - 1))] = val;
+                if count as usize + 1 < PAGE_BYTES { State::Page(count + 1) } else { State::Ready }
+            },
+            (State::Bank, 0, _) => {
+                self.bank = val as usize % self.banks();
+                State::Ready
+            },
+            (State::Ready, CMD1_ADDR, 0xAA) => State::Cmd1,
+            (State::Cmd1, CMD2_ADDR, 0x55) => State::Cmd2,
+            (State::Cmd2, CMD1_ADDR, cmd) => self.command(cmd, page_writes),
+            (State::Cmd2, sector, CMD_ERASE_SECTOR) if self.erasing => {
+                let start = self.bank * BANK_BYTES + (sector & !(SECTOR_BYTES - 1));
+                for b in self.data[start..start + SECTOR_BYTES].iter_mut() {
+                    *b = 0xFF;
+                }
+                self.erasing = false;
+                State::Ready
+            },
+            // Some chips leave ID mode without the command sequence
+            (_, _, CMD_EXIT_ID) => {
+                self.id_mode = false;
+                State::Ready
+            },
+            _ => State::Ready,
+        };
+    }
+
+    fn command(&mut self, cmd: u8, page_writes: bool) -> State {
+        match cmd {
+            CMD_ENTER_ID => self.id_mode = true,
+            CMD_EXIT_ID => self.id_mode = false,
+            CMD_ERASE => {
+                self.erasing = true;
+                return State::Ready;
+            },
+            CMD_ERASE_CHIP if self.erasing => {
+                for b in self.data.iter_mut() {
+                    *b = 0xFF;
+                }
+            },
+            CMD_WRITE if page_writes => return State::Page(0),
+            CMD_WRITE => return State::Write,
+            CMD_BANK if self.banks() > 1 => return State::Bank,
+            _ => warn!(target: "bus", "unknown flash command {:#04x}", cmd),
+        }
+        self.erasing = false;
+        State::Ready
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SANYO: FlashId = FlashId { manufacturer: 0x62, device: 0x13, banks: 2, page_writes: false };
+
+    fn command(flash: &mut Flash, cmd: u8) {
+        flash.write(0x0E005555, 0xAA);
+        flash.write(0x0E002AAA, 0x55);
+        flash.write(0x0E005555, cmd);
+    }
+
+    #[test]
+    fn answers_with_its_id() {
+        let mut flash = Flash::default();
+        flash.fit(Some(SANYO));
+        command(&mut flash, CMD_ENTER_ID);
+        assert_eq!((flash.read(0x0E000000), flash.read(0x0E000001)), (0x62, 0x13));
+        command(&mut flash, CMD_EXIT_ID);
+        assert_eq!(flash.read(0x0E000000), 0xFF);
+    }
+
+    #[test]
+    fn writes_and_erases_in_banks() {
+        let mut flash = Flash::default();
+        flash.fit(Some(SANYO));
+        command(&mut flash, CMD_BANK);
+        flash.write(0x0E000000, 1);
+        command(&mut flash, CMD_WRITE);
+        flash.write(0x0E001234, 0x5A);
+        assert_eq!(flash.read(0x0E001234), 0x5A);
+        assert_eq!(flash.contents().unwrap()[BANK_BYTES + 0x1234], 0x5A);
+
+        command(&mut flash, CMD_ERASE);
+        flash.write(0x0E005555, 0xAA);
+        flash.write(0x0E002AAA, 0x55);
+        flash.write(0x0E001000, CMD_ERASE_SECTOR);
+        assert_eq!(flash.read(0x0E001234), 0xFF);
+    }
+
+    #[test]
+    fn atmel_chips_write_pages() {
+        let mut flash = Flash::default();
+        flash.fit(Some(FlashId { manufacturer: 0x1F, device: 0x3D, banks: 1, page_writes: true }));
+        command(&mut flash, CMD_WRITE);
+        for i in 0..PAGE_BYTES {
+            flash.write(0x0E000080 + i, i as u8);
+        }
+        assert_eq!(flash.read(0x0E0000FF), 0x7F);
+        assert_eq!(flash.state, State::Ready);
+    }
+}
@@ -0,0 +1,56 @@
+use gba_mem::gpio::GpioDevice;
+
+/// Rotation sensor on WarioWare: Twisted, which measures turning about the
+/// axis through the screen. Raising SAMPLE latches a reading, which is then
+/// shifted out MSB first on falling edges of CLK:
+/// http://problemkaputt.de/gbatek.htm#gbacartgyrosensor
+pub const PIN_SAMPLE: u8 = 1;
+/// Clock pin
+pub const PIN_CLK: u8 = 2;
+/// Serial data pin
+pub const PIN_DATA: u8 = 4;
+
+/// Reading when the cartridge is still
+pub const GYRO_CENTER: u16 = 0x6C0;
+
+/// WarioWare: Twisted's gyro sensor
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Gyro {
+    rate: i16, // From still, in sensor counts
+    sample: u16,
+    pins: u8, // Levels last driven by the GBA
+    data_out: bool,
+}
+
+impl_save_state!(Gyro { rate, sample, pins, data_out });
+
+impl Gyro {
+    /// Turning rate, in sensor counts from still
+    pub fn rate(&self) -> i16 {
+        self.rate
+    }
+
+    /// How fast the cartridge is turning, in sensor counts from still.
+    /// Positive is clockwise; about 0x300 either way is a hard twist.
+    pub fn set_rate(&mut self, rate: i16) {
+        self.rate = rate;
+    }
+}
+
+impl GpioDevice for Gyro {
+    fn write_pins(&mut self, pins: u8) {
+        let old = self.pins;
+        self.pins = pins;
+        if pins & PIN_SAMPLE != 0 {
+            self.sample = (GYRO_CENTER as i32 + self.rate as i32).clamp(0, 0xFFF) as u16;
+        }
+        if old & PIN_CLK != 0 && pins & PIN_CLK == 0 {
+            self.data_out = self.sample & 0x8000 != 0;
+            self.sample <<= 1;
+        }
+    }
+
+    fn read_pins(&self) -> u8 {
+        if self.data_out { PIN_DATA } else { 0 }
+    }
+}
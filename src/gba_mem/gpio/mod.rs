@@ -0,0 +1,258 @@
+/// The WarioWare: Twisted gyro sensor
+pub mod gyro;
+/// The real time clock
+pub mod rtc;
+/// The rumble motor
+pub mod rumble;
+/// The Boktai solar sensor
+pub mod solar;
+
+use gba_mem::Address;
+use gba_mem::gpio::gyro::Gyro;
+use gba_mem::gpio::rtc::{Rtc, RtcClock};
+use gba_mem::gpio::rumble::Rumble;
+use gba_mem::gpio::solar::SolarSensor;
+use prelude::*;
+
+/// General purpose I/O port some cartridges map over the ROM at
+/// 0x080000C4-0x080000C9 to reach a clock or sensors through four pins:
+/// http://problemkaputt.de/gbatek.htm#gbacartioport
+///
+/// Every fitted device sees every write to the pins and the levels they
+/// drive back are ORed together.
+pub const GPIO_DATA: Address = 0x080000C4;
+/// Which pins the GBA drives
+pub const GPIO_DIRECTION: Address = 0x080000C6;
+/// Whether the port can be read back
+pub const GPIO_CONTROL: Address = 0x080000C8;
+const GPIO_LO: Address = GPIO_DATA;
+const GPIO_HI: Address = GPIO_CONTROL + 1;
+
+const PIN_MASK: u8 = 0xF;
+
+/// Something wired to the port's pins
+pub trait GpioDevice {
+    /// The GBA has written pins, with the ones it isn't driving low
+    fn write_pins(&mut self, pins: u8);
+    /// Levels the device drives
+    fn read_pins(&self) -> u8;
+}
+
+/// What a cartridge has on its port
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GpioDevices {
+    /// Real-time clock, and the clock it follows
+    pub rtc: Option<RtcClock>,
+    /// Boktai light sensor
+    pub solar: bool,
+    /// WarioWare: Twisted gyro
+    pub gyro: bool,
+    /// Rumble motor
+    pub rumble: bool,
+}
+
+impl GpioDevices {
+    /// Whether anything is on the port at all
+    pub fn any(&self) -> bool {
+        self.rtc.is_some() || self.solar || self.gyro || self.rumble
+    }
+}
+
+/// The cartridge's GPIO port and the devices wired to it
+#[derive(Clone, Copy, Debug)]
+pub struct Gpio {
+    data: u8, // Levels last written for the output pins
+    direction: u8, // Set bits are outputs from the GBA
+    readable: bool, // While clear, reads see the ROM underneath
+    fitted: GpioDevices,
+    rtc: Rtc,
+    solar: SolarSensor,
+    gyro: Gyro,
+    rumble: Rumble,
+}
+
+// What is wired to the pins comes from the settings, not the state
+impl_save_state!(Gpio { data, direction, readable, rtc, solar, gyro, rumble });
+
+impl Default for Gpio {
+    fn default() -> Gpio {
+        Gpio {
+            data: 0,
+            direction: 0,
+            readable: false,
+            fitted: GpioDevices::default(),
+            rtc: Rtc::new(RtcClock::Host),
+            solar: SolarSensor::default(),
+            gyro: Gyro::default(),
+            rumble: Rumble::default(),
+        }
+    }
+}
+
+impl Gpio {
+    /// Whether addr is one of the port's registers
+    pub fn contains(addr: Address) -> bool {
+        (GPIO_LO..=GPIO_HI).contains(&addr)
+    }
+
+    /// Cartridges without anything on the port have plain ROM here
+    pub fn present(&self) -> bool {
+        self.fitted.any()
+    }
+
+    /// Whether reads of addr go to the port rather than the ROM
+    pub fn maps_read(&self, addr: Address) -> bool {
+        self.present() && self.readable && Gpio::contains(addr)
+    }
+
+    /// Whether writes to addr go to the port as well as the ROM
+    pub fn maps_write(&self, addr: Address) -> bool {
+        self.present() && Gpio::contains(addr)
+    }
+
+    /// What is wired to the port
+    pub fn fitted(&self) -> GpioDevices {
+        self.fitted
+    }
+
+    /// Change what is wired to the port. A clock that was already fitted
+    /// keeps running.
+    pub fn fit(&mut self, devices: GpioDevices) {
+        if let Some(clock) = devices.rtc {
+            if self.fitted.rtc != Some(clock) {
+                self.rtc = Rtc::new(clock);
+            }
+        }
+        self.fitted = devices;
+    }
+
+    /// The real-time clock, if fitted
+    pub fn rtc(&self) -> Option<&Rtc> {
+        if self.fitted.rtc.is_some() { Some(&self.rtc) } else { None }
+    }
+
+    /// The real-time clock, if fitted, for writing
+    pub fn rtc_mut(&mut self) -> Option<&mut Rtc> {
+        if self.fitted.rtc.is_some() { Some(&mut self.rtc) } else { None }
+    }
+
+    /// The solar sensor, if fitted
+    pub fn solar(&self) -> Option<&SolarSensor> {
+        if self.fitted.solar { Some(&self.solar) } else { None }
+    }
+
+    /// The solar sensor, if fitted, for writing
+    pub fn solar_mut(&mut self) -> Option<&mut SolarSensor> {
+        if self.fitted.solar { Some(&mut self.solar) } else { None }
+    }
+
+    /// The gyro sensor, if fitted
+    pub fn gyro(&self) -> Option<&Gyro> {
+        if self.fitted.gyro { Some(&self.gyro) } else { None }
+    }
+
+    /// The gyro sensor, if fitted, for writing
+    pub fn gyro_mut(&mut self) -> Option<&mut Gyro> {
+        if self.fitted.gyro { Some(&mut self.gyro) } else { None }
+    }
+
+    /// The rumble motor, if fitted
+    pub fn rumble(&self) -> Option<&Rumble> {
+        if self.fitted.rumble { Some(&self.rumble) } else { None }
+    }
+
+    /// The rumble motor, if fitted, for writing
+    pub fn rumble_mut(&mut self) -> Option<&mut Rumble> {
+        if self.fitted.rumble { Some(&mut self.rumble) } else { None }
+    }
+
+    /// Run the real-time clock for cycles
+    pub fn step(&mut self, cycles: u32) {
+        if self.fitted.rtc.is_some() {
+            self.rtc.step(cycles);
+        }
+    }
+
+    fn devices_mut(&mut self) -> Vec<&mut dyn GpioDevice> {
+        let mut devices: Vec<&mut dyn GpioDevice> = Vec::new();
+        if self.fitted.rtc.is_some() {
+            devices.push(&mut self.rtc);
+        }
+        if self.fitted.solar {
+            devices.push(&mut self.solar);
+        }
+        if self.fitted.gyro {
+            devices.push(&mut self.gyro);
+        }
+        if self.fitted.rumble {
+            devices.push(&mut self.rumble);
+        }
+        devices
+    }
+
+    // Levels the devices drive onto the pins
+    fn pins_in(&self) -> u8 {
+        let mut pins = 0;
+        if self.fitted.rtc.is_some() {
+            pins |= self.rtc.read_pins();
+        }
+        if self.fitted.solar {
+            pins |= self.solar.read_pins();
+        }
+        if self.fitted.gyro {
+            pins |= self.gyro.read_pins();
+        }
+        pins
+    }
+
+    fn read_reg(&self, addr: Address) -> u16 {
+        match addr {
+            GPIO_DATA => ((self.data & self.direction | self.pins_in() & !self.direction)
+                          & PIN_MASK) as u16,
+            GPIO_DIRECTION => self.direction as u16,
+            GPIO_CONTROL => self.readable as u16,
+            _ => 0,
+        }
+    }
+
+    fn write_reg(&mut self, addr: Address, val: u16) {
+        match addr {
+            GPIO_DATA => {
+                self.data = val as u8 & PIN_MASK;
+                let pins = self.data & self.direction;
+                for device in self.devices_mut() {
+                    device.write_pins(pins);
+                }
+            },
+            GPIO_DIRECTION => self.direction = val as u8 & PIN_MASK,
+            GPIO_CONTROL => self.readable = val & 1 != 0,
+            _ => {},
+        }
+    }
+
+    /// The registers are halfwords; other sizes are split into them
+    pub fn read(&self, addr: Address, size: u8) -> u32 {
+        match size {
+            1 => (self.read_reg(addr & !1) >> (8 * (addr & 1))) as u32 & 0xFF,
+            2 => self.read_reg(addr & !1) as u32,
+            _ => self.read_reg(addr & !3) as u32 | (self.read_reg((addr & !3) + 2) as u32) << 16,
+        }
+    }
+
+    /// Write size bytes to the port's registers
+    pub fn write(&mut self, addr: Address, size: u8, val: u32) {
+        match size {
+            1 => {
+                let reg = addr & !1;
+                let shift = 8 * (addr & 1);
+                let old = self.read_reg(reg) & !(0xFF << shift);
+                self.write_reg(reg, old | (val as u16 & 0xFF) << shift);
+            },
+            2 => self.write_reg(addr & !1, val as u16),
+            _ => {
+                self.write_reg(addr & !3, val as u16);
+                self.write_reg((addr & !3) + 2, (val >> 16) as u16);
+            },
+        }
+    }
+}
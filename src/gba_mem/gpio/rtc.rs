@@ -0,0 +1,356 @@
+#[cfg(feature = "std")]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use gba_apu::CPU_FREQ;
+use gba_error::{GbaError, GbaResult};
+use gba_mem::gpio::GpioDevice;
+use gba_state::{SaveState, StateReader, StateWriter};
+
+/// Seiko S-3511 real-time clock, as fitted to Pokemon Ruby, Sapphire and
+/// Emerald and the Boktai games. It talks a three wire serial protocol over
+/// the cartridge GPIO pins, see:
+/// http://problemkaputt.de/gbatek.htm#gbacartrealtimeclockrtc
+///
+/// A transfer starts when CS goes high. The game clocks in a command byte on
+/// rising edges of SCK, then the command's data bytes follow, from the GBA
+/// for writes and from the clock for reads. Bytes travel LSB first.
+pub const PIN_SCK: u8 = 1;
+/// Serial data pin
+pub const PIN_SIO: u8 = 2;
+/// Chip select pin
+pub const PIN_CS: u8 = 4;
+
+const CMD_MAGIC: u8 = 0x06; // Low nibble of every command
+const CMD_READ: u8 = 0x80;
+const CMD_RESET: u8 = 0;
+const CMD_DATETIME: u8 = 2;
+const CMD_CONTROL: u8 = 4;
+const CMD_TIME: u8 = 6;
+const DATA_LEN: [usize; 8] = [0, 0, 7, 0, 1, 0, 3, 0]; // Bytes, by command
+
+const CONTROL_24H: u8 = 0x40;
+const HOUR_PM: u8 = 0x80;
+
+/// 2000-01-01 00:00:00 UTC. The clock counts two digit years from here.
+pub const RTC_EPOCH: u64 = 946684800;
+const SECS_PER_DAY: i64 = 86400;
+
+/// What the clock counts from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RtcClock {
+    /// The host's clock. There are no time zones in std, so this is UTC.
+    /// Without std there is no clock to ask, and it runs like Fixed from
+    /// RTC_EPOCH.
+    Host,
+    /// Starts at a Unix time at power on and runs with emulated time, so
+    /// runs are repeatable
+    Fixed(u64),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Phase {
+    Idle,
+    Command,
+    Write,
+    Read,
+}
+
+impl SaveState for Phase {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(*self as u8);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> GbaResult<()> {
+        *self = match r.read_u8()? {
+            0 => Phase::Idle,
+            1 => Phase::Command,
+            2 => Phase::Write,
+            3 => Phase::Read,
+            n => return Err(GbaError::InvalidSaveState(format!("bad RTC phase {}", n))),
+        };
+        Ok(())
+    }
+}
+
+fn bcd(n: i64) -> u8 {
+    (((n / 10) << 4) | (n % 10)) as u8
+}
+
+fn from_bcd(b: u8) -> i64 {
+    (b >> 4) as i64 * 10 + (b & 0xF) as i64
+}
+
+// Proleptic Gregorian calendar conversions, from Howard Hinnant's
+// chrono-Compatible Low-Level Date Algorithms
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((m + 9) % 12) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    (if m <= 2 { yoe + era * 400 + 1 } else { yoe + era * 400 }, m, d)
+}
+
+/// Seiko S-3511 real-time clock, as driven over the GPIO pins
+#[derive(Clone, Copy, Debug)]
+pub struct Rtc {
+    clock: RtcClock,
+    offset: i64, // Seconds the game has moved the clock by
+    cycles: u64, // Run since power on, for the fixed clock
+    control: u8,
+    phase: Phase,
+    cmd: u8,
+    buf: [u8; 7],
+    bits: usize, // Transferred in this phase
+    pins: u8, // Levels last driven by the GBA
+    sio_out: bool,
+}
+
+// Which clock is fitted comes from the settings, not the state
+impl_save_state!(Rtc { offset, cycles, control, phase, cmd, buf, bits, pins, sio_out });
+
+impl Rtc {
+    /// A clock counting from clock
+    pub fn new(clock: RtcClock) -> Rtc {
+        Rtc {
+            clock,
+            offset: 0,
+            cycles: 0,
+            control: CONTROL_24H,
+            phase: Phase::Idle,
+            cmd: 0,
+            buf: [0; 7],
+            bits: 0,
+            pins: 0,
+            sio_out: false,
+        }
+    }
+
+    /// What the clock counts from
+    pub fn clock(&self) -> RtcClock {
+        self.clock
+    }
+
+    fn base(&self) -> i64 {
+        match self.clock {
+            #[cfg(feature = "std")]
+            RtcClock::Host => SystemTime::now().duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs() as i64)
+                .unwrap_or(RTC_EPOCH as i64),
+            #[cfg(not(feature = "std"))]
+            RtcClock::Host => RTC_EPOCH as i64 + (self.cycles / CPU_FREQ as u64) as i64,
+            RtcClock::Fixed(start) => start as i64 + (self.cycles / CPU_FREQ as u64) as i64,
+        }
+    }
+
+    /// The time the game sees, in seconds since 1970
+    pub fn now(&self) -> i64 {
+        self.base() + self.offset
+    }
+
+    /// Set the time the game sees, in seconds since 1970
+    pub fn set_now(&mut self, secs: i64) {
+        self.offset = secs - self.base();
+    }
+
+    /// Run the clock for cycles
+    pub fn step(&mut self, cycles: u32) {
+        self.cycles += cycles as u64;
+    }
+
+    fn command(&self) -> u8 {
+        self.cmd >> 4 & 7
+    }
+
+    fn data_len(&self) -> usize {
+        DATA_LEN[self.command() as usize]
+    }
+
+    fn start_command(&mut self) {
+        self.bits = 0;
+        self.buf = [0; 7];
+        if self.cmd & 0xF != CMD_MAGIC {
+            self.phase = Phase::Idle;
+            return;
+        }
+
+        if self.data_len() == 0 {
+            if self.command() == CMD_RESET {
+                self.control = 0;
+                self.set_now(RTC_EPOCH as i64);
+            }
+            // The clock's interrupt goes to the cartridge IRQ line, which
+            // isn't wired up, so forcing one does nothing
+            self.phase = Phase::Idle;
+        }
+        else if self.cmd & CMD_READ != 0 {
+            self.fill_read();
+            self.phase = Phase::Read;
+        }
+        else {
+            self.phase = Phase::Write;
+        }
+    }
+
+    fn fill_read(&mut self) {
+        let now = self.now();
+        let days = now.div_euclid(SECS_PER_DAY);
+        let secs = now.rem_euclid(SECS_PER_DAY);
+        let (y, m, d) = civil_from_days(days);
+        let hour = secs / 3600;
+        let mut hour_bcd = bcd(if self.control & CONTROL_24H != 0 { hour } else { hour % 12 });
+        if hour >= 12 {
+            hour_bcd |= HOUR_PM;
+        }
+        let time = [hour_bcd, bcd(secs / 60 % 60), bcd(secs % 60)];
+
+        match self.command() {
+            CMD_DATETIME => {
+                let date = [bcd((y - 2000).rem_euclid(100)), bcd(m), bcd(d),
+                            bcd((days + 4).rem_euclid(7))]; // 1970-01-01 was a Thursday
+                self.buf[..4].copy_from_slice(&date);
+                self.buf[4..].copy_from_slice(&time);
+            },
+            CMD_TIME => self.buf[..3].copy_from_slice(&time),
+            CMD_CONTROL => self.buf[0] = self.control,
+            _ => {},
+        }
+    }
+
+    fn finish_write(&mut self) {
+        let now = self.now();
+        let (date, time) = match self.command() {
+            CMD_DATETIME => {
+                let days = days_from_civil(2000 + from_bcd(self.buf[0]), from_bcd(self.buf[1]),
+                                           from_bcd(self.buf[2]));
+                (days, &self.buf[4..7])
+            },
+            CMD_TIME => (now.div_euclid(SECS_PER_DAY), &self.buf[..3]),
+            CMD_CONTROL => {
+                self.control = self.buf[0];
+                return;
+            },
+            _ => return,
+        };
+
+        let mut hour = from_bcd(time[0] & !HOUR_PM);
+        if self.control & CONTROL_24H == 0 && time[0] & HOUR_PM != 0 {
+            hour += 12;
+        }
+        let secs = hour * 3600 + from_bcd(time[1]) * 60 + from_bcd(time[2]);
+        self.set_now(date * SECS_PER_DAY + secs);
+    }
+}
+
+impl GpioDevice for Rtc {
+    fn write_pins(&mut self, pins: u8) {
+        let old = self.pins;
+        self.pins = pins;
+        if pins & PIN_CS == 0 {
+            self.phase = Phase::Idle;
+            return;
+        }
+        if old & PIN_CS == 0 {
+            self.phase = Phase::Command;
+            self.cmd = 0;
+            self.bits = 0;
+        }
+        if old & PIN_SCK != 0 || pins & PIN_SCK == 0 {
+            return;
+        }
+
+        let bit = (pins & PIN_SIO != 0) as u8;
+        match self.phase {
+            Phase::Idle => {},
+            Phase::Command => {
+                self.cmd |= bit << self.bits;
+                self.bits += 1;
+                if self.bits == 8 {
+                    self.start_command();
+                }
+            },
+            Phase::Write => {
+                self.buf[self.bits / 8] |= bit << (self.bits % 8);
+                self.bits += 1;
+                if self.bits == self.data_len() * 8 {
+                    self.finish_write();
+                    self.phase = Phase::Idle;
+                }
+            },
+            Phase::Read => {
+                self.sio_out = self.buf[self.bits / 8] >> (self.bits % 8) & 1 != 0;
+                self.bits += 1;
+                if self.bits == self.data_len() * 8 {
+                    self.phase = Phase::Idle;
+                }
+            },
+        }
+    }
+
+    fn read_pins(&self) -> u8 {
+        if self.sio_out { PIN_SIO } else { 0 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prelude::*;
+
+    fn send(rtc: &mut Rtc, byte: u8) {
+        for i in 0..8 {
+            let sio = if byte >> i & 1 != 0 { PIN_SIO } else { 0 };
+            rtc.write_pins(PIN_CS | sio);
+            rtc.write_pins(PIN_CS | PIN_SCK | sio);
+        }
+    }
+
+    fn receive(rtc: &mut Rtc, len: usize) -> Vec<u8> {
+        let mut data = vec![0; len];
+        for i in 0..len * 8 {
+            rtc.write_pins(PIN_CS);
+            rtc.write_pins(PIN_CS | PIN_SCK);
+            data[i / 8] |= (rtc.read_pins() >> 1 & 1) << (i % 8);
+        }
+        data
+    }
+
+    fn start(rtc: &mut Rtc) {
+        rtc.write_pins(PIN_SCK);
+        rtc.write_pins(PIN_SCK | PIN_CS);
+    }
+
+    #[test]
+    fn fixed_clock_reads_back_as_bcd() {
+        // 2004-06-15 13:45:30, a Tuesday
+        let mut rtc = Rtc::new(RtcClock::Fixed(1087307130));
+        rtc.step(CPU_FREQ * 2);
+        start(&mut rtc);
+        send(&mut rtc, 0xA6);
+        assert_eq!(receive(&mut rtc, 7), [0x04, 0x06, 0x15, 0x02, 0x93, 0x45, 0x32]);
+    }
+
+    #[test]
+    fn writing_the_time_moves_the_clock() {
+        let mut rtc = Rtc::new(RtcClock::Fixed(RTC_EPOCH));
+        start(&mut rtc);
+        send(&mut rtc, 0x66);
+        for &b in [0x23, 0x59, 0x58].iter() {
+            send(&mut rtc, b);
+        }
+        rtc.step(CPU_FREQ * 3);
+        assert_eq!(rtc.now(), RTC_EPOCH as i64 + SECS_PER_DAY + 1);
+    }
+}
@@ -0,0 +1,47 @@
+use gba_mem::gpio::GpioDevice;
+
+/// Vibration motor on WarioWare: Twisted and Drill Dozer, switched by one
+/// pin. Games vary its strength by switching it on and off quickly, so the
+/// frontend is told about every change.
+pub const PIN_RUMBLE: u8 = 8;
+
+/// Rumble motor, as in WarioWare: Twisted and Drill Dozer
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Rumble {
+    on: bool,
+    changed: bool,
+}
+
+impl_save_state!(Rumble { on });
+
+impl Rumble {
+    /// Whether the motor is running
+    pub fn is_on(&self) -> bool {
+        self.on
+    }
+
+    /// The motor's new state, if it changed since the last call
+    pub fn take_change(&mut self) -> Option<bool> {
+        if self.changed {
+            self.changed = false;
+            Some(self.on)
+        }
+        else {
+            None
+        }
+    }
+}
+
+impl GpioDevice for Rumble {
+    fn write_pins(&mut self, pins: u8) {
+        let on = pins & PIN_RUMBLE != 0;
+        if on != self.on {
+            self.on = on;
+            self.changed = true;
+        }
+    }
+
+    fn read_pins(&self) -> u8 {
+        0
+    }
+}
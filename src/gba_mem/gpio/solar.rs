@@ -0,0 +1,91 @@
+use gba_mem::gpio::GpioDevice;
+
+/// Light sensor on the Boktai cartridges. The game resets a counter, then
+/// clocks it up until the sensor raises FLAG, which happens sooner the
+/// brighter the light:
+/// http://problemkaputt.de/gbatek.htm#gbacartsolarsensor
+pub const PIN_CLK: u8 = 1;
+/// Reset pin, clearing the counter
+pub const PIN_RESET: u8 = 2;
+/// Pin the sensor raises once the counter passes the light level
+pub const PIN_FLAG: u8 = 8;
+
+/// Brightness in a dark room
+pub const DEFAULT_BRIGHTNESS: u8 = 0x20;
+
+/// Boktai's light sensor
+#[derive(Clone, Copy, Debug)]
+pub struct SolarSensor {
+    brightness: u8, // 0 for darkness up to 0xFF for full sunlight
+    counter: u8,
+    pins: u8, // Levels last driven by the GBA
+}
+
+impl_save_state!(SolarSensor { brightness, counter, pins });
+
+impl Default for SolarSensor {
+    fn default() -> SolarSensor {
+        SolarSensor {
+            brightness: DEFAULT_BRIGHTNESS,
+            counter: 0,
+            pins: 0,
+        }
+    }
+}
+
+impl SolarSensor {
+    /// How bright the light is, from 0 for darkness to 0xFF for full sun
+    pub fn brightness(&self) -> u8 {
+        self.brightness
+    }
+
+    /// Set how bright the light is
+    pub fn set_brightness(&mut self, brightness: u8) {
+        self.brightness = brightness;
+    }
+}
+
+impl GpioDevice for SolarSensor {
+    fn write_pins(&mut self, pins: u8) {
+        let old = self.pins;
+        self.pins = pins;
+        if pins & PIN_RESET != 0 {
+            self.counter = 0;
+        }
+        else if pins & PIN_CLK != 0 && old & PIN_CLK == 0 {
+            self.counter = self.counter.saturating_add(1);
+        }
+    }
+
+    fn read_pins(&self) -> u8 {
+        if self.counter >= 0xFF - self.brightness { PIN_FLAG } else { 0 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Clock the counter until FLAG rises, as Boktai does
+    fn count(sensor: &mut SolarSensor) -> u32 {
+        sensor.write_pins(PIN_RESET);
+        sensor.write_pins(0);
+        let mut n = 0;
+        while sensor.read_pins() & PIN_FLAG == 0 {
+            sensor.write_pins(PIN_CLK);
+            sensor.write_pins(0);
+            n += 1;
+        }
+        n
+    }
+
+    #[test]
+    fn brighter_light_flags_sooner() {
+        let mut sensor = SolarSensor::default();
+        let dark = count(&mut sensor);
+        sensor.set_brightness(0xE0);
+        let bright = count(&mut sensor);
+        assert_eq!(bright, 0x1F);
+        assert!(bright < dark);
+    }
+}
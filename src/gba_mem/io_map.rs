@@ -0,0 +1,662 @@
+// Every IO register the emulator implements, in address order: where it
+// is, which bits read back and which can be written, its value at power
+// on, how the bus reaches it, and its bit fields for debuggers. IoRegs
+// dispatches accesses through this table, so a register added here shows
+// up in the IO viewer too. Layouts from:
+// http://problemkaputt.de/gbatek.htm#gbaiomap
+
+use core::cmp::Ordering;
+use core::fmt;
+
+use gba_irq::{IME_MASK, IRQ_MASK};
+use gba_keypad::KEYCNT_MASK;
+use gba_mem::Address;
+use gba_mem::io_regs::{IoRegs, PowerState};
+use gba_mem::waitstate::WAITCNT_WRITABLE;
+use gba_ppu::{BGCNT_TEXT_WRITE_MASK, BGCNT_WRITE_MASK, DISPCNT_WRITE_MASK, DISPSTAT_WRITE_MASK};
+use prelude::*;
+
+/// A named run of bits within a register
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IoField {
+    /// As gbatek calls it
+    pub name: &'static str,
+    /// Lowest bit
+    pub shift: u8,
+    /// Width in bits
+    pub bits: u8,
+}
+
+impl IoField {
+    /// This field's bits of val
+    pub fn get(&self, val: u32) -> u32 {
+        (val >> self.shift) & ((1 << self.bits) - 1)
+    }
+}
+
+const fn field(name: &'static str, shift: u8, bits: u8) -> IoField {
+    IoField { name, shift, bits }
+}
+
+// Hooks reach the halfword at an address within the register; writes
+// only change the bits set in the mask
+type ReadFn = fn(&IoRegs, Address) -> u16;
+type WriteFn = fn(&mut IoRegs, Address, u16, u16);
+
+/// One IO register: where it is, which bits do what and the hooks behind it
+#[derive(Clone, Copy, Debug)]
+pub struct IoReg {
+    /// As gbatek calls it
+    pub name: &'static str,
+    /// Address of the lowest byte
+    pub addr: Address,
+    /// Bytes
+    pub size: usize,
+    /// Bits that read back; the rest read as zero
+    pub read_mask: u32,
+    /// Bits writes can change
+    pub write_mask: u32,
+    /// At power on
+    pub reset: u32,
+    /// Named bit fields, for viewers
+    pub fields: &'static [IoField],
+    /// Registers without a device behind them are kept in IoRegs' latches
+    pub latched: bool,
+    read: ReadFn,
+    write: WriteFn,
+    peek: ReadFn, // read without side effects or the read mask
+}
+
+impl IoReg {
+    /// Whether addr is within the register
+    pub fn contains(&self, addr: Address) -> bool {
+        addr >= self.addr && addr < self.addr + self.size
+    }
+
+    // The part of a whole-register mask covering the halfword at addr
+    fn half(&self, bits: u32, addr: Address) -> u16 {
+        (bits >> (8 * (addr - self.addr))) as u16
+    }
+
+    /// Read the halfword at addr through the register's hook
+    pub fn read(&self, io: &IoRegs, addr: Address) -> u16 {
+        (self.read)(io, addr) & self.half(self.read_mask, addr)
+    }
+
+    /// Write the halfword at addr through the register's hook, keeping the bits outside mask
+    pub fn write(&self, io: &mut IoRegs, addr: Address, val: u16, mask: u16) {
+        (self.write)(io, addr, val, mask & self.half(self.write_mask, addr))
+    }
+
+    /// The whole register, as a debugger would see it
+    pub fn peek(&self, io: &IoRegs) -> u32 {
+        (0..self.size.min(4)).step_by(2)
+            .fold(0, |val, i| val | ((self.peek)(io, self.addr + i) as u32) << (8 * i))
+    }
+
+    /// The power on value of the halfword at addr
+    pub fn reset_half(&self, addr: Address) -> u16 {
+        self.half(self.reset, addr)
+    }
+}
+
+/// The register holding addr, if any
+pub fn find(addr: Address) -> Option<&'static IoReg> {
+    IO_REGS.binary_search_by(|reg| {
+        if reg.addr + reg.size <= addr { Ordering::Less }
+        else if reg.addr > addr { Ordering::Greater }
+        else { Ordering::Equal }
+    }).ok().map(|i| &IO_REGS[i])
+}
+
+/// Whether a write to addr can change anything. Unused addresses can't.
+pub fn is_writable(addr: Address) -> bool {
+    find(addr & !1).is_some_and(|reg| reg.write_mask != 0)
+}
+
+/// A register's value now, for an IO viewer
+#[derive(Clone, Copy, Debug)]
+pub struct IoRegView {
+    /// The register
+    pub reg: &'static IoReg,
+    /// Its value
+    pub value: u32,
+}
+
+impl IoRegView {
+    /// Each field's name and value
+    pub fn fields(&self) -> Vec<(&'static str, u32)> {
+        self.reg.fields.iter().map(|f| (f.name, f.get(self.value))).collect()
+    }
+}
+
+impl fmt::Display for IoRegView {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write![f, "{:<12} {:08x} = {:0width$x}", self.reg.name, self.reg.addr, self.value,
+               width = 2 * self.reg.size]?;
+        for (name, val) in self.fields() {
+            write![f, " {}={:#x}", name, val]?;
+        }
+        Ok(())
+    }
+}
+
+/// Every register's current value, without disturbing the machine
+pub fn view(io: &IoRegs) -> Vec<IoRegView> {
+    IO_REGS.iter().map(|reg| IoRegView { reg, value: reg.peek(io) }).collect()
+}
+
+fn merge(old: u16, val: u16, mask: u16) -> u16 {
+    (old & !mask) | (val & mask)
+}
+
+fn latch_read(io: &IoRegs, addr: Address) -> u16 {
+    io.latch(addr)
+}
+
+fn latch_write(io: &mut IoRegs, addr: Address, val: u16, mask: u16) {
+    let latch = merge(io.latch(addr), val, mask);
+    io.set_latch(addr, latch);
+}
+
+fn dispcnt_write(io: &mut IoRegs, _: Address, val: u16, mask: u16) {
+    let dispcnt = merge(io.ppu.dispcnt(), val, mask);
+    io.ppu.set_dispcnt(dispcnt);
+}
+
+fn dispstat_write(io: &mut IoRegs, _: Address, val: u16, mask: u16) {
+    let dispstat = merge(io.ppu.dispstat(), val, mask);
+    io.ppu.set_dispstat(dispstat);
+}
+
+fn keycnt_write(io: &mut IoRegs, _: Address, val: u16, mask: u16) {
+    let keycnt = merge(io.keypad.keycnt(), val, mask);
+    io.keypad.set_keycnt(keycnt, &mut io.irq);
+}
+
+fn ie_write(io: &mut IoRegs, _: Address, val: u16, mask: u16) {
+    let ie = merge(io.irq.ie(), val, mask);
+    io.irq.set_ie(ie);
+}
+
+fn waitcnt_write(io: &mut IoRegs, _: Address, val: u16, mask: u16) {
+    let waitcnt = merge(io.waitcnt.read(), val, mask);
+    io.waitcnt.write(waitcnt);
+}
+
+fn ime_write(io: &mut IoRegs, _: Address, val: u16, mask: u16) {
+    let ime = merge(io.irq.ime(), val, mask);
+    io.irq.set_ime(ime);
+}
+
+// Writing 1 to an IF bit acknowledges that interrupt
+fn if_write(io: &mut IoRegs, _: Address, val: u16, mask: u16) {
+    io.irq.ack(val & mask);
+}
+
+// POSTFLG in the low byte; writing HALTCNT in the high byte halts the CPU,
+// or with bit 15 set stops it
+fn haltcnt_write(io: &mut IoRegs, addr: Address, val: u16, mask: u16) {
+    let postflg = merge(io.latch(addr), val, mask & 0x00FF);
+    io.set_latch(addr, postflg);
+    if mask & 0xFF00 != 0 {
+        io.power = if val & 0x8000 != 0 { PowerState::Stopped } else { PowerState::Halted };
+    }
+}
+
+fn bgcnt_read(io: &IoRegs, addr: Address) -> u16 {
+    io.ppu.bgcnt((addr - BG0CNT) / 2)
+}
+
+fn bgcnt_write(io: &mut IoRegs, addr: Address, val: u16, mask: u16) {
+    let bg = (addr - BG0CNT) / 2;
+    let bgcnt = merge(io.ppu.bgcnt(bg), val, mask);
+    io.ppu.set_bgcnt(bg, bgcnt);
+}
+
+// Devices that decode their own registers
+fn apu_read(io: &IoRegs, addr: Address) -> u16 {
+    io.apu.read16(addr)
+}
+
+fn apu_write(io: &mut IoRegs, addr: Address, val: u16, mask: u16) {
+    io.apu.write16(addr, val, mask);
+}
+
+fn dma_read(io: &IoRegs, addr: Address) -> u16 {
+    io.dma.read16(addr)
+}
+
+fn dma_write(io: &mut IoRegs, addr: Address, val: u16, mask: u16) {
+    io.dma.write16(addr, val, mask);
+}
+
+fn timer_read(io: &IoRegs, addr: Address) -> u16 {
+    io.note_volatile_read();
+    io.timers_now().read16(addr)
+}
+
+fn timer_write(io: &mut IoRegs, addr: Address, val: u16, mask: u16) {
+    io.timers.write16(addr, val, mask);
+}
+
+fn sio_read(io: &IoRegs, addr: Address) -> u16 {
+    io.sio.read16(addr)
+}
+
+fn sio_write(io: &mut IoRegs, addr: Address, val: u16, mask: u16) {
+    io.sio.write16(addr, val, mask);
+}
+
+const NO_FIELDS: &[IoField] = &[];
+
+const DISPCNT_FIELDS: &[IoField] = &[
+    field("mode", 0, 3), field("cgb", 3, 1), field("frame", 4, 1), field("hblank_free", 5, 1),
+    field("obj_1d", 6, 1), field("forced_blank", 7, 1), field("bg0", 8, 1), field("bg1", 9, 1),
+    field("bg2", 10, 1), field("bg3", 11, 1), field("obj", 12, 1), field("win0", 13, 1),
+    field("win1", 14, 1), field("obj_win", 15, 1),
+];
+const DISPSTAT_FIELDS: &[IoField] = &[
+    field("vblank", 0, 1), field("hblank", 1, 1), field("vcount", 2, 1),
+    field("vblank_irq", 3, 1), field("hblank_irq", 4, 1), field("vcount_irq", 5, 1),
+    field("lyc", 8, 8),
+];
+const VCOUNT_FIELDS: &[IoField] = &[field("line", 0, 8)];
+const BGCNT_FIELDS: &[IoField] = &[
+    field("priority", 0, 2), field("char_base", 2, 2), field("mosaic", 6, 1),
+    field("colors_256", 7, 1), field("screen_base", 8, 5), field("wraparound", 13, 1),
+    field("size", 14, 2),
+];
+
+const OFS_FIELDS: &[IoField] = &[field("offset", 0, 9)];
+// 8.8 fixed point
+const AFFINE_PARAM_FIELDS: &[IoField] = &[
+    field("fraction", 0, 8), field("integer", 8, 7), field("sign", 15, 1),
+];
+// 19.8 fixed point
+const AFFINE_REF_FIELDS: &[IoField] = &[
+    field("fraction", 0, 8), field("integer", 8, 19), field("sign", 27, 1),
+];
+const WINH_FIELDS: &[IoField] = &[field("right", 0, 8), field("left", 8, 8)];
+const WINV_FIELDS: &[IoField] = &[field("bottom", 0, 8), field("top", 8, 8)];
+const WININ_FIELDS: &[IoField] = &[
+    field("win0_bg", 0, 4), field("win0_obj", 4, 1), field("win0_blend", 5, 1),
+    field("win1_bg", 8, 4), field("win1_obj", 12, 1), field("win1_blend", 13, 1),
+];
+const WINOUT_FIELDS: &[IoField] = &[
+    field("out_bg", 0, 4), field("out_obj", 4, 1), field("out_blend", 5, 1),
+    field("obj_win_bg", 8, 4), field("obj_win_obj", 12, 1), field("obj_win_blend", 13, 1),
+];
+const MOSAIC_FIELDS: &[IoField] = &[
+    field("bg_h", 0, 4), field("bg_v", 4, 4), field("obj_h", 8, 4), field("obj_v", 12, 4),
+];
+const BLDCNT_FIELDS: &[IoField] = &[
+    field("first", 0, 6), field("effect", 6, 2), field("second", 8, 6),
+];
+const BLDALPHA_FIELDS: &[IoField] = &[field("eva", 0, 5), field("evb", 8, 5)];
+const BLDY_FIELDS: &[IoField] = &[field("evy", 0, 5)];
+
+const SWEEP_FIELDS: &[IoField] = &[
+    field("shift", 0, 3), field("decrease", 3, 1), field("time", 4, 3),
+];
+const DUTY_FIELDS: &[IoField] = &[
+    field("length", 0, 6), field("duty", 6, 2), field("env_step", 8, 3),
+    field("env_increase", 11, 1), field("env_volume", 12, 4),
+];
+const FREQ_FIELDS: &[IoField] = &[
+    field("rate", 0, 11), field("length_enable", 14, 1), field("restart", 15, 1),
+];
+const SOUND3CNT_L_FIELDS: &[IoField] = &[
+    field("two_banks", 5, 1), field("bank", 6, 1), field("enable", 7, 1),
+];
+const SOUND3CNT_H_FIELDS: &[IoField] = &[
+    field("length", 0, 8), field("volume", 13, 2), field("force_75", 15, 1),
+];
+const SOUND4CNT_L_FIELDS: &[IoField] = &[
+    field("length", 0, 6), field("env_step", 8, 3), field("env_increase", 11, 1),
+    field("env_volume", 12, 4),
+];
+const SOUND4CNT_H_FIELDS: &[IoField] = &[
+    field("ratio", 0, 3), field("width_7", 3, 1), field("shift", 4, 4),
+    field("length_enable", 14, 1), field("restart", 15, 1),
+];
+const SOUNDCNT_L_FIELDS: &[IoField] = &[
+    field("right_volume", 0, 3), field("left_volume", 4, 3), field("right_enable", 8, 4),
+    field("left_enable", 12, 4),
+];
+const SOUNDCNT_H_FIELDS: &[IoField] = &[
+    field("psg_volume", 0, 2), field("a_volume", 2, 1), field("b_volume", 3, 1),
+    field("a_right", 8, 1), field("a_left", 9, 1), field("a_timer", 10, 1),
+    field("a_reset", 11, 1), field("b_right", 12, 1), field("b_left", 13, 1),
+    field("b_timer", 14, 1), field("b_reset", 15, 1),
+];
+const SOUNDCNT_X_FIELDS: &[IoField] = &[
+    field("sound1_on", 0, 1), field("sound2_on", 1, 1), field("sound3_on", 2, 1),
+    field("sound4_on", 3, 1), field("enable", 7, 1),
+];
+const SOUNDBIAS_FIELDS: &[IoField] = &[field("level", 1, 9), field("resolution", 14, 2)];
+
+const DMACNT_H_FIELDS: &[IoField] = &[
+    field("dst_control", 5, 2), field("src_control", 7, 2), field("repeat", 9, 1),
+    field("word", 10, 1), field("drq", 11, 1), field("timing", 12, 2), field("irq", 14, 1),
+    field("enable", 15, 1),
+];
+const TMCNT_H_FIELDS: &[IoField] = &[
+    field("prescaler", 0, 2), field("cascade", 2, 1), field("irq", 6, 1), field("enable", 7, 1),
+];
+
+// SIOCNT's fields depend on the mode; these are the multiplayer ones
+const SIOCNT_FIELDS: &[IoField] = &[
+    field("baud", 0, 2), field("si", 2, 1), field("sd", 3, 1), field("id", 4, 2),
+    field("error", 6, 1), field("start", 7, 1), field("mode", 12, 2), field("irq", 14, 1),
+];
+const RCNT_FIELDS: &[IoField] = &[
+    field("sc", 0, 1), field("sd", 1, 1), field("si", 2, 1), field("so", 3, 1),
+    field("directions", 4, 4), field("irq", 8, 1), field("mode", 14, 2),
+];
+
+// Buttons read 0 while pressed
+const KEYINPUT_FIELDS: &[IoField] = &[
+    field("a", 0, 1), field("b", 1, 1), field("select", 2, 1), field("start", 3, 1),
+    field("right", 4, 1), field("left", 5, 1), field("up", 6, 1), field("down", 7, 1),
+    field("r", 8, 1), field("l", 9, 1),
+];
+const KEYCNT_FIELDS: &[IoField] = &[
+    field("a", 0, 1), field("b", 1, 1), field("select", 2, 1), field("start", 3, 1),
+    field("right", 4, 1), field("left", 5, 1), field("up", 6, 1), field("down", 7, 1),
+    field("r", 8, 1), field("l", 9, 1), field("irq", 14, 1), field("all", 15, 1),
+];
+
+const IRQ_FIELDS: &[IoField] = &[
+    field("vblank", 0, 1), field("hblank", 1, 1), field("vcount", 2, 1), field("timer0", 3, 1),
+    field("timer1", 4, 1), field("timer2", 5, 1), field("timer3", 6, 1), field("serial", 7, 1),
+    field("dma0", 8, 1), field("dma1", 9, 1), field("dma2", 10, 1), field("dma3", 11, 1),
+    field("keypad", 12, 1), field("gamepak", 13, 1),
+];
+const WAITCNT_FIELDS: &[IoField] = &[
+    field("sram", 0, 2), field("ws0_first", 2, 2), field("ws0_second", 4, 1),
+    field("ws1_first", 5, 2), field("ws1_second", 7, 1), field("ws2_first", 8, 2),
+    field("ws2_second", 10, 1), field("phi", 11, 2), field("prefetch", 14, 1), field("cgb", 15, 1),
+];
+const IME_FIELDS: &[IoField] = &[field("enable", 0, 1)];
+const POSTFLG_FIELDS: &[IoField] = &[field("booted", 0, 1)];
+
+// The value given for an optional key in io_registers!, or the default
+macro_rules! io_default {
+    (; $default:expr) => { $default };
+    ($val:expr; $default:expr) => { $val };
+}
+
+// Whether a register has no read hook, and so lives in the latches
+macro_rules! io_latched {
+    () => { true };
+    ($read:expr) => { false };
+}
+
+// Declares the IO registers: a const for each address, and IO_REGS. Each
+// entry is NAME = address, bytes { key: value, ... } with these keys, all
+// optional but in this order:
+//
+//     read_mask   bits that read back (all)
+//     write_mask  bits writes can change (all)
+//     reset       the value at power on (0)
+//     fields      bit fields for debuggers (none)
+//     read        fn(&IoRegs, Address) -> u16 for the device behind it
+//     write       fn(&mut IoRegs, Address, val, mask) for the same
+//     peek        a read without side effects, when read has them
+//
+// A register without read and write hooks is only a latch in IoRegs,
+// which is all a lot of the display registers are until the PPU draws
+// with them. Device registers should agree with their device about masks
+// and resets; the tests check the resets.
+macro_rules! io_registers {
+    ($($name:ident = $addr:expr, $size:tt {
+        $(read_mask: $read_mask:expr,)?
+        $(write_mask: $write_mask:expr,)?
+        $(reset: $reset:expr,)?
+        $(fields: $fields:expr,)?
+        $(read: $read:expr,)?
+        $(write: $write:expr,)?
+        $(peek: $peek:expr,)?
+    })*) => {
+        $(
+            /// An IO register's address
+            pub const $name: Address = $addr;
+        )*
+
+        /// Every IO register, by address
+        #[allow(trivial_numeric_casts)]
+        pub static IO_REGS: &'static [IoReg] = &[$(
+            IoReg {
+                name: stringify!($name),
+                addr: $addr,
+                size: $size,
+                read_mask: io_default!($(($read_mask) as u32)?; !0),
+                write_mask: io_default!($(($write_mask) as u32)?; !0),
+                reset: io_default!($($reset)?; 0),
+                fields: io_default!($($fields)?; NO_FIELDS),
+                latched: io_latched!($($read)?),
+                read: io_default!($($read)?; latch_read),
+                write: io_default!($($write)?; latch_write),
+                peek: io_default!($($peek)?; io_default!($($read)?; latch_read)),
+            },
+        )*];
+    };
+}
+
+io_registers! {
+    DISPCNT = 0x04000000, 2 {
+        write_mask: DISPCNT_WRITE_MASK,
+        fields: DISPCNT_FIELDS,
+        read: |io, _| io.ppu.dispcnt(),
+        write: dispcnt_write,
+    }
+    DISPSTAT = 0x04000004, 2 {
+        write_mask: DISPSTAT_WRITE_MASK,
+        fields: DISPSTAT_FIELDS,
+        read: |io, _| io.ppu.dispstat(),
+        write: dispstat_write,
+    }
+    VCOUNT = 0x04000006, 2 { write_mask: 0, fields: VCOUNT_FIELDS, read: |io, _| io.ppu.vcount(), }
+    BG0CNT = 0x04000008, 2 {
+        write_mask: BGCNT_TEXT_WRITE_MASK,
+        fields: BGCNT_FIELDS,
+        read: bgcnt_read,
+        write: bgcnt_write,
+    }
+    BG1CNT = 0x0400000A, 2 {
+        write_mask: BGCNT_TEXT_WRITE_MASK,
+        fields: BGCNT_FIELDS,
+        read: bgcnt_read,
+        write: bgcnt_write,
+    }
+    BG2CNT = 0x0400000C, 2 {
+        write_mask: BGCNT_WRITE_MASK,
+        fields: BGCNT_FIELDS,
+        read: bgcnt_read,
+        write: bgcnt_write,
+    }
+    BG3CNT = 0x0400000E, 2 {
+        write_mask: BGCNT_WRITE_MASK,
+        fields: BGCNT_FIELDS,
+        read: bgcnt_read,
+        write: bgcnt_write,
+    }
+    BG0HOFS = 0x04000010, 2 { read_mask: 0, write_mask: 0x01FF, fields: OFS_FIELDS, }
+    BG0VOFS = 0x04000012, 2 { read_mask: 0, write_mask: 0x01FF, fields: OFS_FIELDS, }
+    BG1HOFS = 0x04000014, 2 { read_mask: 0, write_mask: 0x01FF, fields: OFS_FIELDS, }
+    BG1VOFS = 0x04000016, 2 { read_mask: 0, write_mask: 0x01FF, fields: OFS_FIELDS, }
+    BG2HOFS = 0x04000018, 2 { read_mask: 0, write_mask: 0x01FF, fields: OFS_FIELDS, }
+    BG2VOFS = 0x0400001A, 2 { read_mask: 0, write_mask: 0x01FF, fields: OFS_FIELDS, }
+    BG3HOFS = 0x0400001C, 2 { read_mask: 0, write_mask: 0x01FF, fields: OFS_FIELDS, }
+    BG3VOFS = 0x0400001E, 2 { read_mask: 0, write_mask: 0x01FF, fields: OFS_FIELDS, }
+    // The affine backgrounds start out untransformed
+    BG2PA = 0x04000020, 2 { read_mask: 0, reset: 0x0100, fields: AFFINE_PARAM_FIELDS, }
+    BG2PB = 0x04000022, 2 { read_mask: 0, fields: AFFINE_PARAM_FIELDS, }
+    BG2PC = 0x04000024, 2 { read_mask: 0, fields: AFFINE_PARAM_FIELDS, }
+    BG2PD = 0x04000026, 2 { read_mask: 0, reset: 0x0100, fields: AFFINE_PARAM_FIELDS, }
+    BG2X = 0x04000028, 4 { read_mask: 0, write_mask: 0x0FFFFFFF, fields: AFFINE_REF_FIELDS, }
+    BG2Y = 0x0400002C, 4 { read_mask: 0, write_mask: 0x0FFFFFFF, fields: AFFINE_REF_FIELDS, }
+    BG3PA = 0x04000030, 2 { read_mask: 0, reset: 0x0100, fields: AFFINE_PARAM_FIELDS, }
+    BG3PB = 0x04000032, 2 { read_mask: 0, fields: AFFINE_PARAM_FIELDS, }
+    BG3PC = 0x04000034, 2 { read_mask: 0, fields: AFFINE_PARAM_FIELDS, }
+    BG3PD = 0x04000036, 2 { read_mask: 0, reset: 0x0100, fields: AFFINE_PARAM_FIELDS, }
+    BG3X = 0x04000038, 4 { read_mask: 0, write_mask: 0x0FFFFFFF, fields: AFFINE_REF_FIELDS, }
+    BG3Y = 0x0400003C, 4 { read_mask: 0, write_mask: 0x0FFFFFFF, fields: AFFINE_REF_FIELDS, }
+    WIN0H = 0x04000040, 2 { read_mask: 0, fields: WINH_FIELDS, }
+    WIN1H = 0x04000042, 2 { read_mask: 0, fields: WINH_FIELDS, }
+    WIN0V = 0x04000044, 2 { read_mask: 0, fields: WINV_FIELDS, }
+    WIN1V = 0x04000046, 2 { read_mask: 0, fields: WINV_FIELDS, }
+    WININ = 0x04000048, 2 { write_mask: 0x3F3F, fields: WININ_FIELDS, }
+    WINOUT = 0x0400004A, 2 { write_mask: 0x3F3F, fields: WINOUT_FIELDS, }
+    MOSAIC = 0x0400004C, 2 { read_mask: 0, fields: MOSAIC_FIELDS, }
+    BLDCNT = 0x04000050, 2 { write_mask: 0x3FFF, fields: BLDCNT_FIELDS, }
+    BLDALPHA = 0x04000052, 2 { write_mask: 0x1F1F, fields: BLDALPHA_FIELDS, }
+    BLDY = 0x04000054, 2 { read_mask: 0, write_mask: 0x001F, fields: BLDY_FIELDS, }
+    SOUND1CNT_L = 0x04000060, 2 { fields: SWEEP_FIELDS, read: apu_read, write: apu_write, }
+    SOUND1CNT_H = 0x04000062, 2 { fields: DUTY_FIELDS, read: apu_read, write: apu_write, }
+    SOUND1CNT_X = 0x04000064, 2 { fields: FREQ_FIELDS, read: apu_read, write: apu_write, }
+    SOUND2CNT_L = 0x04000068, 2 { fields: DUTY_FIELDS, read: apu_read, write: apu_write, }
+    SOUND2CNT_H = 0x0400006C, 2 { fields: FREQ_FIELDS, read: apu_read, write: apu_write, }
+    SOUND3CNT_L = 0x04000070, 2 { fields: SOUND3CNT_L_FIELDS, read: apu_read, write: apu_write, }
+    SOUND3CNT_H = 0x04000072, 2 { fields: SOUND3CNT_H_FIELDS, read: apu_read, write: apu_write, }
+    SOUND3CNT_X = 0x04000074, 2 { fields: FREQ_FIELDS, read: apu_read, write: apu_write, }
+    SOUND4CNT_L = 0x04000078, 2 { fields: SOUND4CNT_L_FIELDS, read: apu_read, write: apu_write, }
+    SOUND4CNT_H = 0x0400007C, 2 { fields: SOUND4CNT_H_FIELDS, read: apu_read, write: apu_write, }
+    SOUNDCNT_L = 0x04000080, 2 { fields: SOUNDCNT_L_FIELDS, read: apu_read, write: apu_write, }
+    SOUNDCNT_H = 0x04000082, 2 { fields: SOUNDCNT_H_FIELDS, read: apu_read, write: apu_write, }
+    SOUNDCNT_X = 0x04000084, 2 { fields: SOUNDCNT_X_FIELDS, read: apu_read, write: apu_write, }
+    SOUNDBIAS = 0x04000088, 2 {
+        reset: 0x0200,
+        fields: SOUNDBIAS_FIELDS,
+        read: apu_read,
+        write: apu_write,
+    }
+    WAVE_RAM0_L = 0x04000090, 2 { read: apu_read, write: apu_write, }
+    WAVE_RAM0_H = 0x04000092, 2 { read: apu_read, write: apu_write, }
+    WAVE_RAM1_L = 0x04000094, 2 { read: apu_read, write: apu_write, }
+    WAVE_RAM1_H = 0x04000096, 2 { read: apu_read, write: apu_write, }
+    WAVE_RAM2_L = 0x04000098, 2 { read: apu_read, write: apu_write, }
+    WAVE_RAM2_H = 0x0400009A, 2 { read: apu_read, write: apu_write, }
+    WAVE_RAM3_L = 0x0400009C, 2 { read: apu_read, write: apu_write, }
+    WAVE_RAM3_H = 0x0400009E, 2 { read: apu_read, write: apu_write, }
+    FIFO_A = 0x040000A0, 4 { read: apu_read, write: apu_write, }
+    FIFO_B = 0x040000A4, 4 { read: apu_read, write: apu_write, }
+    DMA0SAD = 0x040000B0, 4 { read_mask: 0, read: dma_read, write: dma_write, }
+    DMA0DAD = 0x040000B4, 4 { read_mask: 0, read: dma_read, write: dma_write, }
+    DMA0CNT_L = 0x040000B8, 2 { read_mask: 0, read: dma_read, write: dma_write, }
+    DMA0CNT_H = 0x040000BA, 2 { fields: DMACNT_H_FIELDS, read: dma_read, write: dma_write, }
+    DMA1SAD = 0x040000BC, 4 { read_mask: 0, read: dma_read, write: dma_write, }
+    DMA1DAD = 0x040000C0, 4 { read_mask: 0, read: dma_read, write: dma_write, }
+    DMA1CNT_L = 0x040000C4, 2 { read_mask: 0, read: dma_read, write: dma_write, }
+    DMA1CNT_H = 0x040000C6, 2 { fields: DMACNT_H_FIELDS, read: dma_read, write: dma_write, }
+    DMA2SAD = 0x040000C8, 4 { read_mask: 0, read: dma_read, write: dma_write, }
+    DMA2DAD = 0x040000CC, 4 { read_mask: 0, read: dma_read, write: dma_write, }
+    DMA2CNT_L = 0x040000D0, 2 { read_mask: 0, read: dma_read, write: dma_write, }
+    DMA2CNT_H = 0x040000D2, 2 { fields: DMACNT_H_FIELDS, read: dma_read, write: dma_write, }
+    DMA3SAD = 0x040000D4, 4 { read_mask: 0, read: dma_read, write: dma_write, }
+    DMA3DAD = 0x040000D8, 4 { read_mask: 0, read: dma_read, write: dma_write, }
+    DMA3CNT_L = 0x040000DC, 2 { read_mask: 0, read: dma_read, write: dma_write, }
+    DMA3CNT_H = 0x040000DE, 2 { fields: DMACNT_H_FIELDS, read: dma_read, write: dma_write, }
+    TM0CNT_L = 0x04000100, 2 { read: timer_read, write: timer_write, }
+    TM0CNT_H = 0x04000102, 2 { fields: TMCNT_H_FIELDS, read: timer_read, write: timer_write, }
+    TM1CNT_L = 0x04000104, 2 { read: timer_read, write: timer_write, }
+    TM1CNT_H = 0x04000106, 2 { fields: TMCNT_H_FIELDS, read: timer_read, write: timer_write, }
+    TM2CNT_L = 0x04000108, 2 { read: timer_read, write: timer_write, }
+    TM2CNT_H = 0x0400010A, 2 { fields: TMCNT_H_FIELDS, read: timer_read, write: timer_write, }
+    TM3CNT_L = 0x0400010C, 2 { read: timer_read, write: timer_write, }
+    TM3CNT_H = 0x0400010E, 2 { fields: TMCNT_H_FIELDS, read: timer_read, write: timer_write, }
+    SIOMULTI0 = 0x04000120, 2 { read: sio_read, write: sio_write, }
+    SIOMULTI1 = 0x04000122, 2 { read: sio_read, write: sio_write, }
+    SIOMULTI2 = 0x04000124, 2 { read: sio_read, write: sio_write, }
+    SIOMULTI3 = 0x04000126, 2 { read: sio_read, write: sio_write, }
+    SIOCNT = 0x04000128, 2 {
+        reset: 0x0004,
+        fields: SIOCNT_FIELDS,
+        read: sio_read,
+        write: sio_write,
+    }
+    SIODATA8 = 0x0400012A, 2 {
+        read: sio_read,
+        write: sio_write,
+        peek: |io, addr| io.sio.peek16(addr),
+    }
+    KEYINPUT = 0x04000130, 2 {
+        write_mask: 0,
+        reset: 0x03FF,
+        fields: KEYINPUT_FIELDS,
+        read: |io, _| io.keypad.keyinput(),
+    }
+    KEYCNT = 0x04000132, 2 {
+        write_mask: KEYCNT_MASK,
+        fields: KEYCNT_FIELDS,
+        read: |io, _| io.keypad.keycnt(),
+        write: keycnt_write,
+    }
+    RCNT = 0x04000134, 2 { fields: RCNT_FIELDS, read: sio_read, write: sio_write, }
+    IE = 0x04000200, 2 {
+        write_mask: IRQ_MASK,
+        fields: IRQ_FIELDS,
+        read: |io, _| io.irq.ie(),
+        write: ie_write,
+    }
+    IF = 0x04000202, 2 {
+        write_mask: IRQ_MASK,
+        fields: IRQ_FIELDS,
+        read: |io, _| io.irq.if_(),
+        write: if_write,
+    }
+    WAITCNT = 0x04000204, 2 {
+        write_mask: WAITCNT_WRITABLE,
+        fields: WAITCNT_FIELDS,
+        read: |io, _| io.waitcnt.read(),
+        write: waitcnt_write,
+    }
+    IME = 0x04000208, 2 {
+        write_mask: IME_MASK,
+        fields: IME_FIELDS,
+        read: |io, _| io.irq.ime(),
+        write: ime_write,
+    }
+    // With HALTCNT, which is write only, in the high byte
+    POSTFLG = 0x04000300, 2 {
+        read_mask: 0x0001,
+        fields: POSTFLG_FIELDS,
+        write: haltcnt_write,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registers_start_at_their_reset_values() {
+        let io = IoRegs::default();
+        for reg in IO_REGS.iter() {
+            assert_eq!(reg.peek(&io), reg.reset, "{}", reg.name);
+        }
+    }
+
+    #[test]
+    fn masks_apply_to_reads_and_writes() {
+        let mut io = IoRegs::default();
+        let winin = find(WININ).unwrap();
+        winin.write(&mut io, WININ, 0xFFFF, 0xFFFF);
+        assert_eq!(winin.read(&io, WININ), 0x3F3F);
+        let bg2x = find(BG2X).unwrap();
+        bg2x.write(&mut io, BG2X + 2, 0xFFFF, 0xFFFF);
+        assert_eq!(bg2x.read(&io, BG2X + 2), 0);
+        assert_eq!(bg2x.peek(&io), 0x0FFF0000);
+    }
+
+    #[test]
+    fn registers_are_in_order_and_apart() {
+        for pair in IO_REGS.windows(2) {
+            assert!(pair[0].addr + pair[0].size <= pair[1].addr,
+                    "{} overlaps {}", pair[0].name, pair[1].name);
+        }
+        assert_eq!(find(0x040000B2).map(|r| r.name), Some("DMA0SAD"));
+        assert_eq!(find(0x04000002).map(|r| r.name), None);
+    }
+}
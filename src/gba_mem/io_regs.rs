@@ -0,0 +1,362 @@
+use core::cell::Cell;
+
+use gba_apu::Apu;
+use gba_dma::{Dma, DmaTiming};
+use gba_error::{GbaError, GbaResult};
+use gba_irq::{Interrupt, IrqController};
+use gba_keypad::{Button, Keypad};
+use gba_mem::Address;
+use gba_mem::io_map;
+use gba_mem::mem_regions::{BusWidth, MemRead, MemValue, MemWrite, MemoryRegion};
+use gba_mem::scheduler::{Event, Scheduler};
+use gba_mem::waitstate::WaitCnt;
+use gba_ppu::{Ppu, PpuEvents, NUM_BACKGROUNDS, VISIBLE_LINES};
+use gba_ppu::render::BgRegs;
+use gba_sio::Sio;
+use gba_state::{SaveState, StateReader, StateWriter};
+use gba_timer::Timers;
+
+// Halfwords of IO space, for registers kept as latches
+const LATCHES: usize = 0x200;
+
+// Only the keypad, link port and cartridge can wake the CPU from Stop
+const STOP_WAKE: u16 = Interrupt::Keypad as u16 | Interrupt::Serial as u16 |
+                       Interrupt::GamePak as u16;
+
+/// What HALTCNT has put the CPU into. The BIOS's Halt and Stop calls
+/// (SWI 2 and 3) come down to a write there.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Default)]
+pub enum PowerState {
+    /// Executing normally
+    #[default]
+    Running,
+    /// Until an enabled interrupt is requested, whatever IME says
+    Halted,
+    /// As Halted, but the clocks stop too, leaving only the interrupts
+    /// from outside to wake it
+    Stopped,
+}
+
+
+impl SaveState for PowerState {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(*self as u8);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> GbaResult<()> {
+        *self = match r.read_u8()? {
+            0 => PowerState::Running,
+            1 => PowerState::Halted,
+            2 => PowerState::Stopped,
+            n => return Err(GbaError::InvalidSaveState(format!("bad power state {}", n))),
+        };
+        Ok(())
+    }
+}
+
+/// Memory mapped IO registers. They are accessed a halfword at a time, so
+/// byte and word accesses are built on top of read16/write16.
+#[derive(Debug)]
+pub struct IoRegs {
+    /// Video
+    pub ppu: Ppu,
+    /// Sound
+    pub apu: Apu,
+    /// The four timers
+    pub timers: Timers,
+    /// The four DMA channels
+    pub dma: Dma,
+    /// Buttons
+    pub keypad: Keypad,
+    /// Serial port
+    pub sio: Sio,
+    /// Interrupt control
+    pub irq: IrqController,
+    /// Wait state control
+    pub waitcnt: WaitCnt,
+    /// What HALTCNT has done
+    pub power: PowerState,
+    // Registers no device owns yet, see io_map
+    latches: [u16; LATCHES],
+    scheduler: Scheduler,
+    pending: u32, // Cycles the devices haven't been caught up on
+    volatile_read: Cell<bool>, // A timer was read, see take_volatile_read
+}
+
+// The deadlines are worked out again from the devices after a load
+impl SaveState for IoRegs {
+    fn save_state(&self, w: &mut StateWriter) {
+        self.ppu.save_state(w);
+        self.apu.save_state(w);
+        self.timers.save_state(w);
+        self.dma.save_state(w);
+        self.keypad.save_state(w);
+        self.sio.save_state(w);
+        self.irq.save_state(w);
+        self.waitcnt.save_state(w);
+        self.power.save_state(w);
+        self.latches.save_state(w);
+        self.pending.save_state(w);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> GbaResult<()> {
+        self.ppu.load_state(r)?;
+        self.apu.load_state(r)?;
+        self.timers.load_state(r)?;
+        self.dma.load_state(r)?;
+        self.keypad.load_state(r)?;
+        self.sio.load_state(r)?;
+        self.irq.load_state(r)?;
+        self.waitcnt.load_state(r)?;
+        self.power.load_state(r)?;
+        self.latches.load_state(r)?;
+        self.pending.load_state(r)?;
+        self.reschedule();
+        Ok(())
+    }
+}
+
+impl_serde_via_state!(IoRegs);
+
+impl Default for IoRegs {
+    fn default() -> IoRegs {
+        let mut io = IoRegs {
+            ppu: Ppu::default(),
+            apu: Apu::default(),
+            timers: Timers::default(),
+            dma: Dma::default(),
+            keypad: Keypad::default(),
+            sio: Sio::default(),
+            irq: IrqController::default(),
+            waitcnt: WaitCnt::default(),
+            power: PowerState::Running,
+            latches: [0; LATCHES],
+            scheduler: Scheduler::default(),
+            pending: 0,
+            volatile_read: Cell::new(false),
+        };
+        for reg in io_map::IO_REGS.iter().filter(|reg| reg.latched) {
+            for addr in (reg.addr..reg.addr + reg.size).step_by(2) {
+                io.set_latch(addr, reg.reset_half(addr));
+            }
+        }
+        io.reschedule();
+        io
+    }
+}
+
+impl IoRegs {
+    /// Advance the devices behind the IO registers by a number of cycles.
+    /// They are only caught up once one of them is due to do something, see
+    /// scheduler.rs. Any DMA transfers this triggers are left pending for
+    /// the bus to run.
+    pub fn step(&mut self, cycles: u32) -> PpuEvents {
+        self.pending += cycles;
+        self.scheduler.advance(cycles);
+        if self.scheduler.is_due() {
+            self.sync()
+        }
+        else {
+            PpuEvents::default()
+        }
+    }
+
+    /// Catch the devices up on every cycle they have missed
+    pub fn sync(&mut self) -> PpuEvents {
+        let cycles = ::core::mem::replace(&mut self.pending, 0);
+        let events = self.run_devices(cycles);
+        self.reschedule();
+        events
+    }
+
+    fn run_devices(&mut self, cycles: u32) -> PpuEvents {
+        let overflows = self.timers.step(cycles, &mut self.irq);
+        // Only timers 0 and 1 can clock the Direct Sound channels
+        for (timer, &count) in overflows[..2].iter().enumerate() {
+            for fifo in self.apu.timer_overflow(timer, count) {
+                self.dma.request_fifo(fifo);
+            }
+        }
+        self.apu.step(cycles);
+        self.sio.step(cycles, &mut self.irq);
+
+        let events = self.ppu.step(cycles, &mut self.irq);
+        if events.vblank {
+            self.dma.trigger(DmaTiming::VBlank);
+        }
+        if events.hblank && self.ppu.vcount() < VISIBLE_LINES {
+            self.dma.trigger(DmaTiming::HBlank);
+        }
+        events
+    }
+
+    // Ask each device when it next has something to do by itself
+    fn reschedule(&mut self) {
+        self.scheduler.schedule(Event::Ppu, Some(self.ppu.cycles_to_event()));
+        self.scheduler.schedule(Event::Timers, self.timers.cycles_to_overflow());
+        self.scheduler.schedule(Event::Apu, Some(self.apu.cycles_to_event()));
+        self.scheduler.schedule(Event::Sio, self.sio.cycles_to_event());
+    }
+
+    /// When each part of the hardware next needs to run
+    pub fn scheduler(&self) -> &Scheduler {
+        &self.scheduler
+    }
+
+    /// Cycles until the devices next have something to do by themselves,
+    /// which is as far as a halted CPU can be skipped ahead. Stopped, only
+    /// the LCD is counted.
+    pub fn cycles_to_event(&self) -> u32 {
+        let cycles = match self.power {
+            PowerState::Stopped => Some(self.ppu.cycles_to_event()),
+            _ => self.scheduler.cycles_to_next(),
+        };
+        cycles.unwrap_or(1).max(1)
+    }
+
+    /// Stopped, only the LCD's timing carries on, so frontends still get
+    /// frames. With its clock stopped it raises no interrupts.
+    pub fn step_stopped(&mut self, cycles: u32) -> PpuEvents {
+        self.sync();
+        self.scheduler.advance(cycles);
+        let events = self.ppu.step(cycles, &mut IrqController::default());
+        self.reschedule();
+        events
+    }
+
+    /// The timers as of now, counting the cycles they haven't been caught
+    /// up on, for reading their counters
+    pub fn timers_now(&self) -> Timers {
+        let mut timers = self.timers;
+        timers.step(self.pending, &mut IrqController::default());
+        timers
+    }
+
+    /// The timer counters change with every cycle, so a loop reading them
+    /// isn't idle even if nothing else it sees changes
+    pub fn note_volatile_read(&self) {
+        self.volatile_read.set(true);
+    }
+
+    /// Whether a timer was read since the last call
+    pub fn take_volatile_read(&self) -> bool {
+        self.volatile_read.replace(false)
+    }
+
+    /// Wake the CPU if an interrupt it sleeps through has been requested
+    pub fn update_power(&mut self) {
+        let wake = match self.power {
+            PowerState::Running => return,
+            PowerState::Halted => self.irq.is_requested(),
+            PowerState::Stopped => self.irq.ie() & self.irq.if_() & STOP_WAKE != 0,
+        };
+        if wake {
+            self.power = PowerState::Running;
+        }
+    }
+
+    /// The scroll offsets and affine parameters the compositor reads, which
+    /// are kept as latches
+    pub fn bg_regs(&self) -> BgRegs {
+        let mut regs = BgRegs::default();
+        for bg in 0..NUM_BACKGROUNDS {
+            regs.hofs[bg] = self.latch(io_map::BG0HOFS + 4 * bg);
+            regs.vofs[bg] = self.latch(io_map::BG0VOFS + 4 * bg);
+        }
+        for affine in 0..2 {
+            let base = io_map::BG2PA + 0x10 * affine;
+            for param in 0..4 {
+                regs.affine[affine][param] = self.latch(base + 2 * param) as i16;
+            }
+            // 28-bit signed reference points
+            let point = |addr| {
+                let bits = self.latch(addr) as u32 | (self.latch(addr + 2) as u32) << 16;
+                ((bits << 4) as i32) >> 4
+            };
+            regs.origin[affine] = (point(base + 8), point(base + 12));
+        }
+        regs
+    }
+
+    /// Press or release a button on the keypad
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        self.keypad.set_button(button, pressed, &mut self.irq);
+        self.update_power();
+    }
+
+    /// The halfword at addr of a register kept as a latch
+    pub fn latch(&self, addr: Address) -> u16 {
+        self.latches[(addr - IoRegs::lo()) / 2]
+    }
+
+    /// Set the halfword at addr of a register kept as a latch
+    pub fn set_latch(&mut self, addr: Address, val: u16) {
+        self.latches[(addr - IoRegs::lo()) / 2] = val;
+    }
+
+    /// Registers are found in the io_map table; unused addresses read as
+    /// zero and ignore writes
+    pub fn read16(&self, addr: Address) -> u16 {
+        let addr = addr & !1;
+        io_map::find(addr).map_or(0, |reg| reg.read(self, addr))
+    }
+
+    /// Only the bits selected by mask are written so that byte writes leave
+    /// the other half of the register alone. The devices are caught up
+    /// first, as the write may change when they next need to be.
+    pub fn write16(&mut self, addr: Address, val: u16, mask: u16) {
+        let addr = addr & !1;
+        if let Some(reg) = io_map::find(addr) {
+            self.sync();
+            reg.write(self, addr, val, mask);
+            self.reschedule();
+        }
+    }
+}
+
+impl MemoryRegion for IoRegs {
+    #[inline]
+    fn lo() -> Address { 0x04000000 }
+
+    #[inline]
+    fn hi() -> Address { 0x040003FF }
+
+    #[inline]
+    fn bus_width() -> BusWidth { BusWidth::BW32 }
+
+    #[inline]
+    fn len() -> usize { 0x400 }
+}
+
+// Registers are halfwords: bytes read or write part of one, and words two
+impl<T: MemValue> MemRead<T> for IoRegs {
+    fn read(&self, addr: Address) -> T {
+        T::from_bits(match T::SIZE {
+            1 => (self.read16(addr) >> ((addr & 1) * 8)) as u32,
+            2 => self.read16(addr) as u32,
+            _ => {
+                let addr = addr & !3;
+                self.read16(addr) as u32 | (self.read16(addr + 2) as u32) << 16
+            },
+        })
+    }
+}
+
+impl<T: MemValue> MemWrite<T> for IoRegs {
+    fn write(&mut self, addr: Address, val: T) {
+        let val = val.to_bits();
+        match T::SIZE {
+            1 => {
+                let shift = (addr & 1) * 8;
+                self.write16(addr, (val as u8 as u16) << shift, 0xFF << shift);
+            },
+            2 => self.write16(addr, val as u16, 0xFFFF),
+            _ => {
+                let addr = addr & !3;
+                self.write16(addr, val as u16, 0xFFFF);
+                self.write16(addr + 2, (val >> 16) as u16, 0xFFFF);
+            },
+        }
+    }
+}
@@ -1,33 +1,39 @@
-use std::fmt;
-use std::fmt::Debug;
-use std::io::{Cursor, Read, Write};
-use std::fs::{File, OpenOptions};
-use std::io;
+use core::fmt;
+use core::fmt::Debug;
+#[cfg(feature = "std")]
+use std::fs::{self, OpenOptions};
+#[cfg(feature = "std")]
+use std::io::{Read, Write};
+#[cfg(feature = "std")]
 use std::path::Path;
 
-use byteorder::{ReadBytesExt, WriteBytesExt, LittleEndian};
-
+use gba_error::{GbaError, GbaResult};
 use gba_mem::Address;
+use gba_mem::rom_header::RomHeader;
+use gba_state::{SaveState, StateReader, StateWriter};
+use prelude::*;
 
 pub const BYTE_WIDTH: u16 = 8;
 
 #[derive(Clone, Copy, Debug)]
 pub enum BusWidth {
+    BW8,
     BW16,
     BW32,
 }
 
 impl BusWidth {
     #[inline]
-    pub fn to_bytes(&self) -> u16 {
-        match *self {
+    pub fn to_bytes(self) -> u16 {
+        match self {
+            BusWidth::BW8  => 1,
             BusWidth::BW16 => 2,
             BusWidth::BW32 => 4,
         }
     }
 
     #[inline]
-    pub fn to_bits(&self) -> u16 {
+    pub fn to_bits(self) -> u16 {
         self.to_bytes() * BYTE_WIDTH
     }
 }
@@ -37,10 +43,8 @@ pub trait MemoryRegion {
     fn hi() -> Address;
     fn bus_width() -> BusWidth;
 
-    #[inline]
-    fn len() -> usize {
-        (Self::hi() - Self::lo())/BYTE_WIDTH as Address + 1
-    }
+    // Bytes of memory behind the window, which mirrors over the rest of it
+    fn len() -> usize;
 
     #[inline]
     fn contains_cmp(addr: Address) -> isize {
@@ -57,10 +61,7 @@ pub trait MemoryRegion {
 
     #[inline]
     fn contains(addr: Address) -> bool {
-        match Self::contains_cmp(addr) {
-            0 => true,
-            _ => false,
-        }
+        Self::contains_cmp(addr) == 0
     }
 }
 
@@ -72,72 +73,168 @@ pub trait MemWrite<T> {
     fn write(&mut self, addr: Address, val: T);
 }
 
+mod sealed {
+    pub trait Sealed {}
+}
+
+// Values that can travel over the bus: bytes, halfwords and words. Only
+// those widths exist, so no other types can be added.
+pub trait MemValue: sealed::Sealed + Copy + Default {
+    const SIZE: u8; // Bytes
+
+    fn to_bits(self) -> u32;
+    fn from_bits(bits: u32) -> Self;
+
+    // From and to the start of a little-endian slice at least SIZE long
+    fn read_le(bytes: &[u8]) -> Self;
+    fn write_le(self, bytes: &mut [u8]);
+}
+
+macro_rules! def_mem_value {
+    ($ty:ty, $size:expr) => {
+        impl sealed::Sealed for $ty {}
+
+        #[allow(trivial_numeric_casts)]
+        impl MemValue for $ty {
+            const SIZE: u8 = $size;
+
+            fn to_bits(self) -> u32 { self as u32 }
+            fn from_bits(bits: u32) -> Self { bits as $ty }
+
+            #[inline]
+            fn read_le(bytes: &[u8]) -> Self {
+                let mut le = [0; $size];
+                le.copy_from_slice(&bytes[..$size]);
+                <$ty>::from_le_bytes(le)
+            }
+
+            #[inline]
+            fn write_le(self, bytes: &mut [u8]) {
+                bytes[..$size].copy_from_slice(&self.to_le_bytes());
+            }
+        }
+    };
+}
+
+def_mem_value!(u8,  1);
+def_mem_value!(u16, 2);
+def_mem_value!(u32, 4);
+
+// A region is declared with the window it answers to on the bus, the
+// size of the memory behind it and, if it isn't simply repeated through
+// the window, how offsets in the window mirror onto that memory.
 macro_rules! new_mem_region {
-    ($name:ident, $lo:expr, $hi:expr, $bus:expr) => {
+    ($name:ident, $lo:expr, $hi:expr, $size:expr, $bus:expr) => {
+        new_mem_region!($name, $lo, $hi, $size, $bus, |offset: usize| offset % $size);
+    };
+
+    ($name:ident, $lo:expr, $hi:expr, $size:expr, $bus:expr, $mirror:expr) => {
+        #[doc = concat!("Memory mapped from ", stringify!($lo), " to ", stringify!($hi))]
         pub struct $name {
-            mem: Vec<u8>,//Box<[u8; (($hi - $lo) as usize)/(BYTE_WIDTH as usize) + 1]>,
+            mem: Vec<u8>, // SIZE bytes
         }
 
         impl $name {
-            pub fn create_from_array(array: &[u8]) -> $name {
-                let mut ret = $name {
-                    mem: vec![0; (($hi - $lo) as usize)/(BYTE_WIDTH as usize) + 1],
-                };
+            /// Bytes of memory behind the window
+            pub const SIZE: usize = $size;
+
+            // Index into mem for an access of the given size. Accesses are
+            // aligned to their size like on the real bus, so they can never
+            // run off the end.
+            #[inline]
+            fn offset(addr: Address, size: usize) -> usize {
+                let mirror: fn(usize) -> usize = $mirror;
+                mirror((addr - $lo) & !(size - 1))
+            }
 
-                println!("{:x}\n{:x}", ret.mem.len(), array.len());
-                ret.mem.copy_from_slice(array);
+            /// An image no bigger than the memory, the rest left zeroed
+            pub fn from_bytes(data: &[u8]) -> GbaResult<$name> {
+                if data.len() > $size {
+                    return Err(GbaError::RomTooLarge {
+                        region: stringify!($name),
+                        size: data.len(),
+                        max: $size,
+                    });
+                }
 
-                ret
+                let mut ret = $name::default();
+                ret.mem[..data.len()].copy_from_slice(data);
+                Ok(ret)
             }
 
-            pub fn create_from_file(file_path: &str) -> io::Result<$name> {
-                let file_path = Path::new(file_path);
-                let mut file = try!(File::open(file_path));
-                let file_len = try!(file.metadata()).len() as usize;
-                let mem_len = $name::len();
+            /// An image read from a file
+            #[cfg(feature = "std")]
+            pub fn create_from_file(file_path: &str) -> GbaResult<$name> {
+                $name::from_bytes(&fs::read(file_path)?)
+            }
 
-                if file_len > mem_len {
-                    let errmsg = match file_path.to_str() {
-                        Some(f) => format!("File {} ({} Bytes) is too big for the {} memory region ({} Bytes).", f, file_len, stringify!($name), mem_len),
-                        None => format!("File is too big for the {} memory region.", stringify!($name)),
-                    };
+            /// An image read to the end of a stream, e.g. a socket or stdin
+            #[cfg(feature = "std")]
+            pub fn from_reader<R: Read>(mut reader: R) -> GbaResult<$name> {
+                let mut data = Vec::new();
+                reader.read_to_end(&mut data)?;
+                $name::from_bytes(&data)
+            }
 
-                    Err(io::Error::new(io::ErrorKind::Other, errmsg))
-                }
-                else {
-                    let mut ret = $name {
-                        mem: vec![0; (($hi - $lo) as usize)/(BYTE_WIDTH as usize) + 1],
-                    };
+            /// len bytes from offset into the memory, or None if they run
+            /// past the end
+            pub fn get(&self, offset: usize, len: usize) -> Option<&[u8]> {
+                self.mem.get(offset..offset.checked_add(len)?)
+            }
 
-                    try!(file.read(ret.mem.as_mut_slice()));
+            /// As get, for writing
+            pub fn get_mut(&mut self, offset: usize, len: usize) -> Option<&mut [u8]> {
+                self.mem.get_mut(offset..offset.checked_add(len)?)
+            }
 
-                    Ok(ret)
-                }
+            /// The whole memory
+            pub fn as_slice(&self) -> &[u8] {
+                &self.mem
             }
 
-            pub fn to_file(&self, file_path: &str) {
+            /// The whole memory, for writing
+            pub fn as_mut_slice(&mut self) -> &mut [u8] {
+                &mut self.mem
+            }
+
+            /// Write the whole memory to a file
+            #[cfg(feature = "std")]
+            pub fn to_file(&self, file_path: &str) -> GbaResult<()> {
                 let file_path = Path::new(file_path);
                 let mut file = OpenOptions::new()
                     .write(true)
                     .create(true)
-                    .open(file_path).unwrap();
+                    .open(file_path)?;
 
-                file.write_all(self.mem.as_ref()).unwrap();
+                file.write_all(self.mem.as_ref())?;
+                Ok(())
             }
         }
 
         impl Default for $name {
             fn default() -> Self {
                 $name {
-                    mem: vec![0; (($hi - $lo) as usize)/(BYTE_WIDTH as usize) + 1],
+                    mem: vec![0; $size],
                 }
             }
         }
 
+        impl SaveState for $name {
+            fn save_state(&self, w: &mut StateWriter) {
+                w.write_bytes(&self.mem);
+            }
+
+            fn load_state(&mut self, r: &mut StateReader) -> GbaResult<()> {
+                r.read_bytes_into(&mut self.mem)
+            }
+        }
+
+        impl_serde_via_state!($name);
+
         impl Debug for $name {
             fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-                write!(f, "{}{{ lo:{:#x}, lo:{:#x}, bus_width:{} }}",
-                       stringify!($name), $name::lo(), $name::hi(),
+                write!(f, "{}{{ lo:{:#x}, hi:{:#x}, len:{:#x}, bus_width:{} }}",
+                       stringify!($name), $name::lo(), $name::hi(), $name::len(),
                        $name::bus_width().to_bits())
             }
         }
@@ -151,117 +248,295 @@ macro_rules! new_mem_region {
 
             #[inline]
             fn bus_width() -> BusWidth { $bus }
-        }
-    };
-}
 
-macro_rules! def_mem_region_ops {
-    ($name:ty) => {};
+            #[inline]
+            fn len() -> usize { $size }
+        }
 
-    (mem_read_as_self: $name:ty, $func:ident, $ty:ty) => {
-        #[allow(trivial_numeric_casts)]
-        impl MemRead<$ty> for $name {
-            fn read(&self, addr: Address) -> $ty {
-                self.mem[(addr - Self::lo()) as usize] as $ty
+        impl<T: MemValue> MemRead<T> for $name {
+            #[inline]
+            fn read(&self, addr: Address) -> T {
+                T::read_le(&self.mem[Self::offset(addr, T::SIZE as usize)..])
             }
         }
     };
+}
 
-    (mem_read_as_other: $name:ty, $func:ident, $ty:ty) => {
-        impl MemRead<$ty> for $name {
-            fn read(&self, addr: Address) -> $ty {
-                let loc = (addr - Self::lo()) as u64;
-                let mut rdr = Cursor::new((*self.mem).as_ref());
-                rdr.set_position(loc);
-                rdr.$func::<LittleEndian>().unwrap()
+// Regions the bus can write to. Which widths it writes with is up to the
+// bus, e.g. byte writes never reach video memory.
+macro_rules! def_mem_region_writes {
+    ($($name:ty),*) => {
+        $(
+            impl<T: MemValue> MemWrite<T> for $name {
+                #[inline]
+                fn write(&mut self, addr: Address, val: T) {
+                    val.write_le(&mut self.mem[Self::offset(addr, T::SIZE as usize)..])
+                }
             }
-        }
+        )*
     };
+}
 
-    (mem_write_as_self: $name:ty, $func:ident, $ty:ty) => {
-        #[allow(trivial_numeric_casts)]
-        impl MemWrite<$ty> for $name {
-            fn write(&mut self, addr: Address, val: $ty) {
-                self.mem[addr - Self::lo()] = val as u8;
-            }
+// Declare memory regions
+new_mem_region!(SystemRom, 0x00000000, 0x0001FFFF, 0x4000,    BusWidth::BW32);
+new_mem_region!(ExternRam, 0x02000000, 0x02FFFFFF, 0x40000,   BusWidth::BW16);
+new_mem_region!(InternRam, 0x03000000, 0x03FFFFFF, 0x8000,    BusWidth::BW32);
+new_mem_region!(PalettRam, 0x05000000, 0x05FFFFFF, 0x400,     BusWidth::BW16);
+// 96K of VRAM mirrors in 128K blocks, the last 32K repeating the upper 32K
+new_mem_region!(VisualRam, 0x06000000, 0x06FFFFFF, 0x18000,   BusWidth::BW16,
+                |offset: usize| match offset & 0x1FFFF {
+                    o if o >= 0x18000 => o - 0x8000,
+                    o => o,
+                });
+new_mem_region!(OAM,       0x07000000, 0x07FFFFFF, 0x400,     BusWidth::BW32);
+new_mem_region!(PakRam,    0x0E000000, 0x0FFFFFFF, 0x10000,   BusWidth::BW8);
+
+/// The cartridge ROM, seen through three windows with their own wait states,
+/// see waitstate.rs. Only the image is kept, padded to a power of two like
+/// the mask ROMs it came from. Reads past the image, in the padding or
+/// beyond, see the open bus, where the cartridge leaves the halfword address
+/// it was last sent.
+#[derive(Default)]
+pub struct PakRom {
+    mem: Vec<u8>, // A power of two Bytes, or empty
+}
+
+impl PakRom {
+    /// The most a cartridge can address through each window
+    pub const SIZE: usize = 0x2000000;
+
+    /// A ROM image, padded to a power of two with open bus values
+    pub fn from_bytes(data: &[u8]) -> GbaResult<PakRom> {
+        if data.len() > PakRom::SIZE {
+            return Err(GbaError::RomTooLarge {
+                region: "PakRom",
+                size: data.len(),
+                max: PakRom::SIZE,
+            });
         }
-    };
 
-    (mem_write_as_other: $name:ty, $func:ident, $ty:ty) => {
-        impl MemWrite<$ty> for $name {
-            fn write(&mut self, addr: Address, val: $ty) {
-                let loc = (addr - Self::lo()) as u64;
-                let mut wtr = Cursor::new((*self.mem).as_mut());
-                wtr.set_position(loc);
-                wtr.$func::<LittleEndian>(val).unwrap()
-            }
+        let mut mem = data.to_vec();
+        if !mem.is_empty() {
+            mem.resize(data.len().next_power_of_two(), 0);
         }
-    };
+        for (offset, byte) in mem.iter_mut().enumerate().skip(data.len()) {
+            *byte = PakRom::open_bus(offset);
+        }
+        Ok(PakRom { mem })
+    }
 
+    /// The padded image
+    pub fn as_slice(&self) -> &[u8] {
+        &self.mem
+    }
 
-    (read: $name:ty, 8)  => {
-        def_mem_region_ops!(mem_read_as_self:   $name, read_i8,   i8 );
-        def_mem_region_ops!(mem_read_as_self:   $name, read_u8,   u8 );
-    };
-    (read: $name:ty, 16) => {
-        def_mem_region_ops!(mem_read_as_other:  $name, read_i16,  i16);
-        def_mem_region_ops!(mem_read_as_other:  $name, read_u16,  u16);
-    };
-    (read: $name:ty, 32) => {
-        def_mem_region_ops!(mem_read_as_other:  $name, read_i32,  i32);
-        def_mem_region_ops!(mem_read_as_other:  $name, read_u32,  u32);
-        def_mem_region_ops!(mem_read_as_other:  $name, read_f32,  f32);
-    };
+    /// The padded image, for writing
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.mem
+    }
 
-    (write: $name:ty, 8)  => {
-        def_mem_region_ops!(mem_write_as_self:  $name, write_i8,  i8 );
-        def_mem_region_ops!(mem_write_as_self:  $name, write_u8,  u8 );
-    };
-    (write: $name:ty, 16) => {
-        def_mem_region_ops!(mem_write_as_other: $name, write_i16, i16);
-        def_mem_region_ops!(mem_write_as_other: $name, write_u16, u16);
-    };
-    (write: $name:ty, 32) => {
-        def_mem_region_ops!(mem_write_as_other: $name, write_i32, i32);
-        def_mem_region_ops!(mem_write_as_other: $name, write_u32, u32);
-        def_mem_region_ops!(mem_write_as_other: $name, write_f32, f32);
-    };
+    /// The cartridge header at the start of the image
+    pub fn header(&self) -> RomHeader {
+        RomHeader::parse(self.as_slice())
+    }
 
-    ($name:ty, r, $tok:tt) => { def_mem_region_ops!(read:  $name, $tok); };
-    ($name:ty, w, $tok:tt) => { def_mem_region_ops!(write: $name, $tok); };
-    ($name:ty, rw, $tok:tt) => {
-        def_mem_region_ops!(read:  $name, $tok);
-        def_mem_region_ops!(write: $name, $tok);
-    };
-    ($name:ty, wr, $tok:tt) => { def_mem_region_ops!($name, rw, $tok); };
+    // Offset into the ROM of an aligned access of the given size, the same
+    // through every window
+    #[inline]
+    fn offset(addr: Address, size: usize) -> usize {
+        (addr - PakRom::lo()) & (PakRom::SIZE - 1) & !(size - 1)
+    }
 
-    ($name:ty, $op:tt[]) => {};
-    ($name:ty, $op:tt[ $tok:tt ]) => { def_mem_region_ops!($name, $op, $tok); };
-    ($name:ty, $op:tt[ $tok:tt, $($toks:tt),* ]) => {
-        def_mem_region_ops!($name, $op, $tok);
-        def_mem_region_ops!($name, $op[ $($toks),* ]);
-    };
+    // Each halfword past the end of the ROM reads as its own address
+    fn open_bus<T: MemValue>(offset: usize) -> T {
+        let half = |o: usize| (o as u32 >> 1) & 0xFFFF;
+        let base = offset & !1;
+        let bits = half(base) | half(base + 2) << 16;
+        T::from_bits(bits >> ((offset & 1) * 8))
+    }
+}
 
-    ($name:ty, $op:tt[ $($toks:tt),* ], $( $r_op:tt[ $($r_size:tt),* ] ),*) => {
-        def_mem_region_ops!($name, $op[ $($toks),* ]);
-        def_mem_region_ops!($name, $( $r_op[ $($r_size),* ] ),*);
-    };
+impl Debug for PakRom {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PakRom{{ lo:{:#x}, hi:{:#x}, len:{:#x}, bus_width:{} }}",
+               PakRom::lo(), PakRom::hi(), self.mem.len(), PakRom::bus_width().to_bits())
+    }
 }
 
-// Declare memory regions
-new_mem_region!(SystemRom, 0x00000000, 0x0001FFFF, BusWidth::BW32);
-new_mem_region!(ExternRam, 0x02000000, 0x0203FFFF, BusWidth::BW32);
-new_mem_region!(InternRam, 0x03000000, 0x03007FFF, BusWidth::BW32);
-new_mem_region!(PalettRam, 0x05000000, 0x050003FF, BusWidth::BW32);
-new_mem_region!(VisualRam, 0x06000000, 0x06017FFF, BusWidth::BW16);
-new_mem_region!(OAM,       0x07000000, 0x070003FF, BusWidth::BW32);
-new_mem_region!(PakRom,    0x08000000, 0x0FFFFFFF, BusWidth::BW16);
-
-// Implement read and write operations
-def_mem_region_ops!(SystemRom, r[8, 16, 32]);
-def_mem_region_ops!(ExternRam, rw[8, 16, 32]);
-def_mem_region_ops!(InternRam, rw[8, 16, 32]);
-def_mem_region_ops!(PalettRam, r[8, 16, 32], w[16, 32]);
-def_mem_region_ops!(VisualRam, r[8, 16, 32], w[16, 32]);
-def_mem_region_ops!(OAM,       r[8, 16, 32], w[16, 32]);
-def_mem_region_ops!(PakRom,    rw[8, 16, 32]);
+impl MemoryRegion for PakRom {
+    #[inline]
+    fn lo() -> Address { 0x08000000 }
+
+    #[inline]
+    fn hi() -> Address { 0x0DFFFFFF }
+
+    #[inline]
+    fn bus_width() -> BusWidth { BusWidth::BW16 }
+
+    #[inline]
+    fn len() -> usize { PakRom::SIZE }
+}
+
+impl<T: MemValue> MemRead<T> for PakRom {
+    #[inline]
+    fn read(&self, addr: Address) -> T {
+        let offset = Self::offset(addr, T::SIZE as usize);
+        match self.mem.get(offset..) {
+            Some(bytes) if !bytes.is_empty() => T::read_le(bytes),
+            _ => PakRom::open_bus(offset),
+        }
+    }
+}
+
+def_mem_region_writes!(ExternRam, InternRam, PalettRam, VisualRam, OAM, PakRam);
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng, XorShiftRng};
+
+    use super::*;
+
+    const FUZZ_ACCESSES: usize = 20000;
+
+    fn rng() -> XorShiftRng {
+        SeedableRng::from_seed([0x1234, 0x5678, 0x9abc, 0xdef0])
+    }
+
+    fn fuzz_reads<R>(region: &R)
+        where R: MemoryRegion + MemRead<u8> + MemRead<u16> + MemRead<u32> {
+        let mut rng = rng();
+        for &addr in [R::lo(), R::hi(), R::hi() - 1, R::hi() - 3].iter() {
+            <R as MemRead<u8>>::read(region, addr);
+            <R as MemRead<u16>>::read(region, addr);
+            <R as MemRead<u32>>::read(region, addr);
+        }
+        for _ in 0..FUZZ_ACCESSES {
+            let addr = rng.gen_range(R::lo(), R::hi() + 1);
+            <R as MemRead<u8>>::read(region, addr);
+            <R as MemRead<u16>>::read(region, addr);
+            <R as MemRead<u32>>::read(region, addr);
+        }
+    }
+
+    // Write random words anywhere in the window and read them back both
+    // where they were written and through the same offset one size along
+    fn fuzz_writes<R>(region: &mut R, size: usize)
+        where R: MemoryRegion + MemRead<u32> + MemWrite<u32> + MemWrite<u16> {
+        let mut rng = rng();
+        for _ in 0..FUZZ_ACCESSES {
+            let addr = rng.gen_range(R::lo(), R::hi() + 1);
+            let val = rng.gen::<u32>();
+            <R as MemWrite<u16>>::write(region, addr, val as u16);
+            <R as MemWrite<u32>>::write(region, addr, val);
+            assert_eq!(<R as MemRead<u32>>::read(region, addr), val);
+
+            let mirror = if addr + size <= R::hi() { addr + size } else { addr - size };
+            if mirror >= R::lo() && mirror <= R::hi() {
+                assert_eq!(<R as MemRead<u32>>::read(region, mirror), val,
+                           "{:#x} should mirror {:#x}", mirror, addr);
+            }
+        }
+    }
+
+    #[test]
+    fn fuzz_reads_stay_in_bounds() {
+        fuzz_reads(&SystemRom::default());
+        fuzz_reads(&ExternRam::default());
+        fuzz_reads(&InternRam::default());
+        fuzz_reads(&PalettRam::default());
+        fuzz_reads(&VisualRam::default());
+        fuzz_reads(&OAM::default());
+        fuzz_reads(&PakRom::default());
+        fuzz_reads(&PakRam::default());
+    }
+
+    #[test]
+    fn fuzz_writes_follow_mirrors() {
+        fuzz_writes(&mut ExternRam::default(), ExternRam::SIZE);
+        fuzz_writes(&mut InternRam::default(), InternRam::SIZE);
+        fuzz_writes(&mut PalettRam::default(), PalettRam::SIZE);
+        fuzz_writes(&mut OAM::default(), OAM::SIZE);
+        fuzz_writes(&mut PakRam::default(), PakRam::SIZE);
+        // Whole 128K VRAM blocks always mirror each other
+        fuzz_writes(&mut VisualRam::default(), 0x20000);
+    }
+
+    // Sizes from http://problemkaputt.de/gbatek.htm#gbamemorymap
+    #[test]
+    fn regions_are_their_full_size() {
+        assert_eq!(SystemRom::len(), 16 * 1024);
+        assert_eq!(ExternRam::len(), 256 * 1024);
+        assert_eq!(InternRam::len(), 32 * 1024);
+        assert_eq!(PalettRam::len(), 1024);
+        assert_eq!(VisualRam::len(), 96 * 1024);
+        assert_eq!(OAM::len(), 1024);
+        assert_eq!(PakRom::len(), 32 * 1024 * 1024);
+        assert_eq!(PakRam::len(), 64 * 1024);
+        assert_eq!(VisualRam::default().as_slice().len(), VisualRam::len());
+
+        let mut oam = OAM::default();
+        assert_eq!(oam.get(0x3FC, 4).map(|b| b.len()), Some(4));
+        assert!(oam.get(0x3FD, 4).is_none());
+        assert!(oam.get_mut(usize::MAX, 2).is_none());
+    }
+
+    #[test]
+    fn images_are_padded_to_size() {
+        let bios = SystemRom::from_bytes(&[0xAB; 0x100]).unwrap();
+        assert_eq!(bios.as_slice().len(), SystemRom::SIZE);
+        assert_eq!(<SystemRom as MemRead<u8>>::read(&bios, 0xFF), 0xAB);
+        assert_eq!(<SystemRom as MemRead<u8>>::read(&bios, 0x100), 0);
+        assert!(SystemRom::from_bytes(&[0; SystemRom::SIZE + 1]).is_err());
+    }
+
+    #[test]
+    fn rom_is_only_as_big_as_its_image() {
+        let rom = PakRom::from_bytes(&[0xAB; 0x300]).unwrap();
+        assert_eq!(rom.as_slice().len(), 0x400);
+        assert_eq!(<PakRom as MemRead<u8>>::read(&rom, 0x080002FF), 0xAB);
+        assert_eq!(<PakRom as MemRead<u8>>::read(&rom, 0x08000300), 0x80);
+        // The same ROM through the other wait state windows
+        assert_eq!(<PakRom as MemRead<u16>>::read(&rom, 0x0A000000), 0xABAB);
+        assert_eq!(<PakRom as MemRead<u16>>::read(&rom, 0x0C000000), 0xABAB);
+        assert!(PakRom::from_bytes(&[]).unwrap().as_slice().is_empty());
+        assert!(PakRom::from_bytes(&vec![0; PakRom::SIZE + 1]).is_err());
+    }
+
+    #[test]
+    fn reads_past_the_rom_are_open_bus() {
+        let rom = PakRom::from_bytes(&[0xAB; 0x400]).unwrap();
+        assert_eq!(<PakRom as MemRead<u16>>::read(&rom, 0x08000400), 0x0200);
+        assert_eq!(<PakRom as MemRead<u32>>::read(&rom, 0x08001000), 0x08010800);
+        assert_eq!(<PakRom as MemRead<u8>>::read(&rom, 0x08001003), 0x08);
+        assert_eq!(<PakRom as MemRead<u16>>::read(&rom, 0x0A000400), 0x0200);
+        assert_eq!(<PakRom as MemRead<u16>>::read(&PakRom::default(), 0x08000002), 0x0001);
+    }
+
+    // The padding up to a power of two reads the same as past the end
+    #[test]
+    fn rom_padding_is_open_bus() {
+        let rom = PakRom::from_bytes(&[0xAB; 0x2FF]).unwrap();
+        assert_eq!(<PakRom as MemRead<u16>>::read(&rom, 0x080002FE), 0x01AB);
+        assert_eq!(<PakRom as MemRead<u16>>::read(&rom, 0x08000300), 0x0180);
+        assert_eq!(<PakRom as MemRead<u32>>::read(&rom, 0x080003FC), 0x01FF01FE);
+        assert_eq!(&rom.as_slice()[0x300..0x304], &[0x80, 0x01, 0x81, 0x01]);
+    }
+
+    #[test]
+    fn vram_upper_32k_mirrors() {
+        let mut vram = VisualRam::default();
+        vram.write(0x06010000, 0xdeadbeefu32);
+        assert_eq!(<VisualRam as MemRead<u32>>::read(&vram, 0x06018000), 0xdeadbeef);
+        vram.write(0x06000000, 0x12345678u32);
+        assert_eq!(<VisualRam as MemRead<u32>>::read(&vram, 0x06020000), 0x12345678);
+    }
+
+    #[test]
+    fn accesses_are_aligned() {
+        let mut ram = InternRam::default();
+        ram.write(0x03000002, 0x11223344u32);
+        assert_eq!(<InternRam as MemRead<u32>>::read(&ram, 0x03000000), 0x11223344);
+        assert_eq!(<InternRam as MemRead<u16>>::read(&ram, 0x03000003), 0x1122);
+        assert_eq!(<InternRam as MemRead<u8>>::read(&ram, 0x03000003), 0x11);
+    }
+}
@@ -1,111 +1,827 @@
+/// Loading ROMs from zip and 7z archives
+#[cfg(feature = "std")]
+pub mod archive;
+/// Recording and replaying bus accesses
+pub mod bus_log;
+/// Telling when code in RAM has been overwritten
+pub mod code_pages;
+/// EEPROM saves
+pub mod eeprom;
+/// Flash saves
+pub mod flash;
+/// Devices on the cartridge's GPIO port
+pub mod gpio;
+/// Where each IO register is and how it's read and written
+pub mod io_map;
+/// The IO registers
+pub mod io_regs;
 mod mem_regions;
+/// Mapping addresses to memory regions by their top byte
+pub mod page_table;
+/// The cartridge header
+pub mod rom_header;
+/// When each device next has something to do
+pub mod scheduler;
+/// The tilt sensor on the SRAM bus
+pub mod tilt;
+/// Wait states and access timing
+pub mod waitstate;
+/// Watchpoints and memory watches
+pub mod watch;
 
-use gba_mem::mem_regions::{SystemRom, ExternRam, InternRam,
-                           PalettRam, VisualRam, OAM, PakRom,
-                           MemRead, MemWrite, MemoryRegion};
-use std::io;
+use gba_error::{GbaError, GbaResult};
+use gba_mem::bus_log::{BusAccess, BusLog, BusLogMode, BusMismatch};
+use gba_mem::code_pages::CodePages;
+use gba_mem::eeprom::{Eeprom, EepromSize};
+use gba_mem::flash::Flash;
+use gba_mem::gpio::Gpio;
+use gba_mem::io_regs::IoRegs;
+use gba_mem::page_table::{page, Page};
+use gba_mem::rom_header::RomHeader;
+use gba_mem::tilt::TiltSensor;
+use gba_mem::waitstate::Prefetch;
+use gba_mem::watch::{Watchpoint, WatchHit, WatchId, WatchKind, WatchTiming, Watches};
+use gba_ppu::PpuEvents;
+use gba_ppu::render::VideoMem;
+pub use gba_mem::mem_regions::{PakRom, SystemRom, ExternRam, InternRam,
+                               PalettRam, VisualRam, OAM, PakRam};
+use gba_mem::mem_regions::{BusWidth, MemRead, MemWrite, MemValue, MemoryRegion};
+use prelude::*;
+use core::cell::{Cell, RefCell};
+use core::ops::Range;
 
+/// A bus address
 pub type Address = usize;
 
+// The biggest ROM that leaves an EEPROM all of its 16M page
+const EEPROM_MAX_ROM: usize = 0x1000000;
+
+/// The BIOS is built in from roms/gba.bin until it can be emulated
+pub const BUILTIN_BIOS: &[u8] = include_bytes!("../../roms/gba.bin");
+
+/// An access the hardware would quietly make something of: to an address
+/// with nothing behind it, a write to ROM or to an IO register that can't be
+/// written, or a misaligned load or store. The GBA bus never aborts, but
+/// strict mode reports these so the CPU can raise a prefetch or data abort
+/// exception.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BusAbort {
+    /// Address that was accessed
+    pub addr: Address,
+    /// Whether it was a write
+    pub write: bool,
+}
+
+/// The GBA's memory map, with everything on the bus behind it
 #[derive(Debug)]
 pub struct Memory {
     sys_rom: SystemRom,
     ext_ram: ExternRam,
     int_ram: InternRam,
+    io:      IoRegs,
     pal_ram: PalettRam,
     vis_ram: VisualRam,
     oam:     OAM,
     pak_rom: PakRom,
+    rom_len: usize, // Of the image loaded into it
+    pak_ram: PakRam, // Battery-backed cartridge SRAM
+    eeprom:  Eeprom, // Battery-backed serial save chip over the ROM
+    flash:   Flash, // Save chip in place of the SRAM
+    gpio:    Gpio, // Cartridge I/O port over the ROM
+    tilt:    TiltSensor, // Cartridge accelerometer over the SRAM
+    prefetch: Prefetch,
+    next_seq: Address, // An access here follows on from the last one
+    cycles: u32, // Charged for accesses since take_cycles
+    writes: u64, // Bus writes since power on, see write_count
+    strict_aborts: bool,
+    abort: Cell<Option<BusAbort>>,
+    bus_log: RefCell<BusLog>,
+    watchpoints: Vec<Watchpoint>,
+    watch_hit: Cell<Option<WatchHit>>,
+    watches: RefCell<Watches>,
+    multiboot: bool, // Booted from a program in EWRAM with no cartridge
+    code_pages: CodePages,
 }
 
+// The ROMs come from files and are not part of a save state
+impl_save_state!(Memory { ext_ram, int_ram, io, pal_ram, vis_ram, oam, pak_ram, eeprom,
+                          flash, gpio, tilt, prefetch, next_seq });
+
 impl Memory {
-    pub fn new(pak_filename: &str) -> io::Result<Memory> {
-        println!("WARNING: BIOS emulation not implemented. Please emulate bios rather than use a ROM.");
-        Ok(Memory {
-            sys_rom: SystemRom::create_from_array(include_bytes!("../../roms/gba.bin")),
+    /// The ROM file may be zipped or gzipped
+    #[cfg(feature = "std")]
+    pub fn new(pak_filename: &str) -> GbaResult<Memory> {
+        Memory::with_rom_bytes(&archive::read_rom(pak_filename)?)
+    }
+
+    /// A machine from BIOS and cartridge images already in memory, for
+    /// frontends with no filesystem
+    pub fn from_bytes(bios: &[u8], rom: &[u8]) -> GbaResult<Memory> {
+        let mut mem = Memory::with_rom_bytes(rom)?;
+        mem.set_bios(bios)?;
+        Ok(mem)
+    }
+
+    /// A machine with the built in BIOS and a cartridge image already in
+    /// memory
+    pub fn with_rom_bytes(rom: &[u8]) -> GbaResult<Memory> {
+        let mut mem = Memory::with_pak(PakRom::from_bytes(rom)?);
+        mem.rom_len = rom.len();
+        Ok(mem)
+    }
+
+    /// Swap in another BIOS image, e.g. a dump of a real one, before the
+    /// machine starts running. A short image is padded with zeroes.
+    pub fn set_bios(&mut self, bios: &[u8]) -> GbaResult<()> {
+        self.sys_rom = SystemRom::from_bytes(bios).map_err(|_| GbaError::BadBios(
+            format!("{} Bytes is bigger than the {} Byte BIOS", bios.len(), SystemRom::SIZE)))?;
+        Ok(())
+    }
+
+    /// A machine with an empty cartridge slot and a multiboot program
+    /// already in EWRAM, as the BIOS leaves it after a link cable transfer
+    #[cfg(feature = "std")]
+    pub fn new_multiboot(mb_filename: &str) -> GbaResult<Memory> {
+        let mut mem = Memory::with_pak(PakRom::default());
+        mem.ext_ram = ExternRam::create_from_file(mb_filename)?;
+        mem.multiboot = true;
+        Ok(mem)
+    }
+
+    /// A machine with an empty cartridge slot, for unit tests
+    #[cfg(test)]
+    pub fn blank() -> Memory {
+        Memory::with_pak(PakRom::default())
+    }
+
+    fn with_pak(pak_rom: PakRom) -> Memory {
+        Memory {
+            sys_rom: SystemRom::from_bytes(BUILTIN_BIOS).expect("the built in BIOS is 16K"),
             ext_ram: ExternRam::default(),
             int_ram: InternRam::default(),
+            io:      IoRegs::default(),
             pal_ram: PalettRam::default(),
             vis_ram: VisualRam::default(),
             oam:     OAM::default(),
-            pak_rom: try!(PakRom::create_from_file(pak_filename)),
-        })
-    }
-
-    pub fn read<T>(&self, addr: Address) -> T
-        where SystemRom: MemRead<T>,
-              ExternRam: MemRead<T>,
-              InternRam: MemRead<T>,
-              PalettRam: MemRead<T>,
-              VisualRam: MemRead<T>,
-              OAM: MemRead<T>,
-              PakRom: MemRead<T> {
-        match addr {
-            _ if addr >= SystemRom::lo() && addr <= SystemRom::hi() =>
-                <SystemRom as MemRead<T>>::read(&self.sys_rom, addr),
-            _ if addr >= ExternRam::lo() && addr <= ExternRam::hi() =>
-                <ExternRam as MemRead<T>>::read(&self.ext_ram, addr),
-            _ if addr >= InternRam::lo() && addr <= InternRam::hi() =>
-                <InternRam as MemRead<T>>::read(&self.int_ram, addr),
-            _ if addr >= PalettRam::lo() && addr <= PalettRam::hi() =>
-                <PalettRam as MemRead<T>>::read(&self.pal_ram, addr),
-            _ if addr >= VisualRam::lo() && addr <= VisualRam::hi() =>
-                <VisualRam as MemRead<T>>::read(&self.vis_ram, addr),
-            _ if addr >= OAM::lo() && addr <= OAM::hi() =>
-                <OAM as MemRead<T>>::read(&self.oam, addr),
-            _ if addr >= PakRom::lo() && addr <= PakRom::hi() =>
-                <PakRom as MemRead<T>>::read(&self.pak_rom, addr),
-            _ => unreachable!(),
-        }
-    }
-
-    pub fn write8<T>(&mut self, addr: Address, val: T)
-        where ExternRam: MemWrite<T>,
-              InternRam: MemWrite<T>,
-              PakRom: MemWrite<T> {
-        match addr {
-            _ if addr >= ExternRam::lo() && addr <= ExternRam::hi() =>
-                <ExternRam as MemWrite<T>>::write(&mut self.ext_ram, addr, val),
-            _ if addr >= InternRam::lo() && addr <= InternRam::hi() =>
-                <InternRam as MemWrite<T>>::write(&mut self.int_ram, addr, val),
-            _ if addr >= PakRom::lo() && addr <= PakRom::hi() =>
-                <PakRom as MemWrite<T>>::write(&mut self.pak_rom, addr, val),
-            _ => unreachable!(),
-        }
-    }
-
-    pub fn write16<T>(&mut self, addr: Address, val: T)
-        where ExternRam: MemWrite<T>,
-              InternRam: MemWrite<T>,
-              PalettRam: MemWrite<T>,
-              VisualRam: MemWrite<T>,
-              OAM: MemWrite<T>,
-              PakRom: MemWrite<T> {
-        match addr {
-            _ if addr >= ExternRam::lo() && addr <= ExternRam::hi() =>
-                <ExternRam as MemWrite<T>>::write(&mut self.ext_ram, addr, val),
-            _ if addr >= InternRam::lo() && addr <= InternRam::hi() =>
-                <InternRam as MemWrite<T>>::write(&mut self.int_ram, addr, val),
-            _ if addr >= PalettRam::lo() && addr <= PalettRam::hi() =>
-                <PalettRam as MemWrite<T>>::write(&mut self.pal_ram, addr, val),
-            _ if addr >= VisualRam::lo() && addr <= VisualRam::hi() =>
-                <VisualRam as MemWrite<T>>::write(&mut self.vis_ram, addr, val),
-            _ if addr >= OAM::lo() && addr <= OAM::hi() =>
-                <OAM as MemWrite<T>>::write(&mut self.oam, addr, val),
-            _ if addr >= PakRom::lo() && addr <= PakRom::hi() =>
-                <PakRom as MemWrite<T>>::write(&mut self.pak_rom, addr, val),
-            _ => unreachable!(),
-        }
-    }
-
-    pub fn write32<T>(&mut self, addr: Address, val: T)
-        where ExternRam: MemWrite<T>,
-              InternRam: MemWrite<T>,
-              PalettRam: MemWrite<T>,
-              VisualRam: MemWrite<T>,
-              OAM: MemWrite<T>,
-              PakRom: MemWrite<T> {
-        self.write16::<T>(addr, val);
+            pak_rom,
+            rom_len: 0,
+            pak_ram: PakRam::default(),
+            eeprom:  Eeprom::default(),
+            flash:   Flash::default(),
+            gpio:    Gpio::default(),
+            tilt:    TiltSensor::default(),
+            prefetch: Prefetch::default(),
+            next_seq: 0,
+            cycles: 0,
+            writes: 0,
+            strict_aborts: false,
+            abort: Cell::new(None),
+            bus_log: RefCell::new(BusLog::default()),
+            watchpoints: Vec::new(),
+            watch_hit: Cell::new(None),
+            watches: RefCell::new(Watches::default()),
+            multiboot: false,
+            code_pages: CodePages::default(),
+        }
+    }
+
+    /// Whether a multiboot image was loaded in place of a cartridge
+    pub fn is_multiboot(&self) -> bool {
+        self.multiboot
+    }
+
+    /// Changes whenever the RAM page holding addr is written, for caching
+    /// code decoded from it
+    pub fn code_generation(&self, addr: Address) -> u32 {
+        self.code_pages.generation(addr)
+    }
+
+    // The program the BIOS boots, which starts with its header
+    fn boot_image(&self) -> &[u8] {
+        if self.multiboot { self.ext_ram.as_slice() } else { self.pak_rom.as_slice() }
+    }
+
+    /// Write over the cartridge ROM image, e.g. to apply a game's fixes.
+    /// The three wait state windows all map onto it; bytes past the end of
+    /// the ROM are dropped, as is a patch outside the windows.
+    pub fn patch_rom(&mut self, addr: Address, bytes: &[u8]) {
+        if !(PakRom::lo()..=PakRom::hi()).contains(&addr) {
+            return warn!(target: "bus", "ROM patch at {:#010x} is outside the ROM", addr);
+        }
+        let rom = self.pak_rom.as_mut_slice();
+        let start = ((addr - PakRom::lo()) % PakRom::SIZE).min(rom.len());
+        let end = (start + bytes.len()).min(rom.len());
+        if end - start < bytes.len() {
+            warn!(target: "bus", "ROM patch at {:#010x} runs past the end of the ROM", addr);
+        }
+        rom[start..end].copy_from_slice(&bytes[..end - start]);
+    }
+
+    /// The cartridge ROM as loaded, without the padding after it
+    pub fn rom(&self) -> &[u8] {
+        &self.pak_rom.as_slice()[..self.rom_len]
+    }
+
+    /// Header of the cartridge or multiboot image
+    pub fn rom_header(&self) -> RomHeader {
+        RomHeader::parse(self.boot_image())
+    }
+
+    /// Check the cartridge header the way the BIOS would before booting it
+    pub fn check_rom_header(&self) -> GbaResult<()> {
+        self.rom_header().validate(self.boot_image())
+    }
+
+    // Cycles a CPU access to the game pak takes under the current WAITCNT
+    // settings, or None for the rest of the bus. Opcode fetches from ROM go
+    // through the prefetch unit; any other pak access interrupts it.
+    fn pak_access_cycles(&mut self, addr: Address, size: usize, seq: bool,
+                         opcode: bool) -> Option<u32> {
+        let waitcnt = self.io.waitcnt;
+        if PakRom::contains(addr) {
+            if opcode {
+                return Some(self.prefetch.fetch(addr, size, &waitcnt));
+            }
+            self.prefetch.flush();
+            Some(waitcnt.rom_cycles(addr, size, seq))
+        }
+        else if PakRam::contains(addr) {
+            // Only one byte is transferred whatever the access size
+            self.prefetch.flush();
+            Some(1 + waitcnt.sram_waits())
+        }
+        else {
+            None
+        }
+    }
+
+    /// Cycles a CPU access of size Bytes takes. Sequential accesses follow
+    /// on from the previous address, which only the game pak cares about.
+    /// See: http://problemkaputt.de/gbatek.htm#gbamemorymap
+    pub fn access_cycles(&mut self, addr: Address, size: usize, seq: bool,
+                         opcode: bool) -> u32 {
+        match self.pak_access_cycles(addr, size, seq, opcode) {
+            Some(cycles) => cycles,
+            None => Memory::bus_cycles(addr, size),
+        }
+    }
+
+    /// Cycles an opcode fetch from addr takes if it is the same every time,
+    /// i.e. off the game pak, whose waits and prefetch unit vary
+    pub fn fixed_fetch_cycles(addr: Address, size: usize) -> Option<u32> {
+        if PakRom::contains(addr) || PakRam::contains(addr) {
+            None
+        }
+        else {
+            Some(Memory::bus_cycles(addr, size))
+        }
+    }
+
+    fn bus_cycles(addr: Address, size: usize) -> u32 {
+        let (width, waits) = match addr {
+            _ if ExternRam::contains(addr) =>
+                (ExternRam::bus_width(), waitstate::EWRAM_WAITS),
+            _ if PalettRam::contains(addr) => (PalettRam::bus_width(), 0),
+            _ if VisualRam::contains(addr) => (VisualRam::bus_width(), 0),
+            // BIOS, IWRAM, IO, OAM and open bus are all 32 bits, no waits
+            _ => (BusWidth::BW32, 0),
+        };
+        waitstate::bus_cycles(width.to_bytes() as usize, waits, size)
+    }
+
+    /// Charge the cycles of a CPU access, working out whether it is
+    /// sequential from the one before
+    pub fn charge(&mut self, addr: Address, size: usize, opcode: bool) {
+        let seq = addr == self.next_seq;
+        self.cycles += self.access_cycles(addr, size, seq, opcode);
+        self.next_seq = addr + size;
+    }
+
+    /// Charge opcode fetches worked out ahead of time, e.g. by compiled
+    /// code, the last of them ending just before next_seq
+    pub fn charge_fetches(&mut self, cycles: u32, next_seq: Address) {
+        self.cycles += cycles;
+        self.next_seq = next_seq;
+    }
+
+    /// Nothing looks at single accesses: no watchpoints, bus log or strict
+    /// aborts. Fetches can then be charged without being made.
+    pub fn is_plain_bus(&self) -> bool {
+        !self.strict_aborts && self.watchpoints.is_empty() && self.watches.borrow().is_empty() &&
+        self.bus_log.borrow().mode() == BusLogMode::Off
+    }
+
+    /// Cycles charged since the last call, i.e. by the last instruction
+    pub fn take_cycles(&mut self) -> u32 {
+        let cycles = self.cycles;
+        self.cycles = 0;
+        cycles
+    }
+
+    /// Goes up with every write by the CPU or a DMA, so a loop that stores
+    /// nothing can be told apart from one that does
+    pub fn write_count(&self) -> u64 {
+        self.writes
+    }
+
+    /// Opt in to reporting bad accesses as bus aborts
+    pub fn set_strict_aborts(&mut self, strict: bool) {
+        self.strict_aborts = strict;
+    }
+
+    /// Whether bad accesses raise aborts
+    pub fn is_strict_aborts(&self) -> bool {
+        self.strict_aborts
+    }
+
+    /// Take the abort raised by the last bad access, if any
+    pub fn take_abort(&self) -> Option<BusAbort> {
+        self.abort.take()
+    }
+
+    // Unmapped reads return open bus. The prefetched opcode isn't tracked
+    // yet, so open bus reads as zero.
+    fn unmapped_read<T: MemValue>(&self, addr: Address) -> T {
+        debug!(target: "bus", "unmapped {}-bit read from {:#010x}", T::SIZE * 8, addr);
+        if self.strict_aborts {
+            self.abort.set(Some(BusAbort { addr, write: false }));
+        }
+        T::default()
+    }
+
+    // Writes to unmapped addresses are ignored
+    fn unmapped_write(&self, addr: Address) {
+        debug!(target: "bus", "unmapped write to {:#010x}", addr);
+        if self.strict_aborts {
+            self.abort.set(Some(BusAbort { addr, write: true }));
+        }
+    }
+
+    // Writes to the BIOS or cartridge ROM that no save chip or I/O port on
+    // the cartridge answers, and in strict mode to IO registers with no
+    // writable bits. The bus drops them. Games only do this by mistake, so
+    // strict mode aborts.
+    fn read_only_write(&self, addr: Address) {
+        debug!(target: "bus", "write to read only {:#010x}", addr);
+        if self.strict_aborts {
+            self.abort.set(Some(BusAbort { addr, write: true }));
+        }
+    }
+
+    // The bus ignores the bottom bits of a misaligned address
+    fn check_alignment(&self, addr: Address, size: u8, write: bool) {
+        if self.strict_aborts && !addr.is_multiple_of(size as Address) {
+            debug!(target: "bus", "misaligned {}-bit access to {:#010x}", size * 8, addr);
+            self.abort.set(Some(BusAbort { addr, write }));
+        }
+    }
+
+    fn is_mapped(addr: Address) -> bool {
+        match page(addr) {
+            Page::Bios => addr <= SystemRom::hi(),
+            Page::Io => addr <= IoRegs::hi(),
+            Page::Unmapped => false,
+            _ => true,
+        }
+    }
+
+    /// Log CPU accesses from here on
+    pub fn record_bus(&self) {
+        self.bus_log.borrow_mut().record();
+    }
+
+    /// Answer reads from the recording and drop writes, checking each access
+    /// against the log
+    pub fn replay_bus(&self) {
+        self.bus_log.borrow_mut().replay();
+    }
+
+    /// Stop recording or replaying, with the first access a replay differed at
+    pub fn stop_bus_log(&self) -> Result<(), BusMismatch> {
+        self.bus_log.borrow_mut().stop()
+    }
+
+    // Returns the access if it should go ahead to the bus
+    fn log_write<T: MemValue>(&self, addr: Address, val: T) -> Option<BusAccess> {
+        self.check_alignment(addr, T::SIZE, true);
+        let access = BusAccess {
+            addr,
+            size: T::SIZE,
+            val: val.to_bits(),
+            write: true,
+        };
+        self.check_watch(&access);
+        if !self.bus_log.borrow_mut().log_write(access) {
+            return None;
+        }
+        self.run_watches(WatchTiming::Before, &access);
+        Some(access)
+    }
+
+    /// Call back on every CPU access of a kind to addresses in range, both
+    /// before and after it. None if range is empty. Callbacks only see the
+    /// access, never the Memory, so they can't add or remove watches while
+    /// the list is running.
+    pub fn add_watch<F>(&mut self, range: Range<Address>, kind: WatchKind,
+                        callback: F) -> Option<WatchId>
+        where F: FnMut(WatchTiming, &BusAccess) + 'static
+    {
+        self.watches.get_mut().add(range, kind, Box::new(callback))
+    }
+
+    /// Returns whether there was a callback with that id
+    pub fn remove_watch(&mut self, id: WatchId) -> bool {
+        self.watches.get_mut().remove(id)
+    }
+
+    fn run_watches(&self, timing: WatchTiming, access: &BusAccess) {
+        let mut watches = self.watches.borrow_mut();
+        if !watches.is_empty() {
+            watches.run(timing, access);
+        }
+    }
+
+    /// Returns the index of the new watchpoint
+    pub fn add_watchpoint(&mut self, watch: Watchpoint) -> usize {
+        self.watchpoints.push(watch);
+        self.watchpoints.len() - 1
+    }
+
+    /// Remove a watchpoint by the index add_watchpoint gave, returning it
+    pub fn remove_watchpoint(&mut self, index: usize) -> Option<Watchpoint> {
+        if index < self.watchpoints.len() {
+            Some(self.watchpoints.remove(index))
+        }
+        else {
+            None
+        }
+    }
+
+    /// Watchpoints set, by index
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// Take the first watchpoint hit since the last call, if any
+    pub fn take_watch_hit(&self) -> Option<WatchHit> {
+        self.watch_hit.take()
+    }
+
+    // Only CPU accesses are watched; DMA goes through the bus directly
+    fn check_watch(&self, access: &BusAccess) {
+        if self.watchpoints.is_empty() || self.watch_hit.get().is_some() {
+            return;
+        }
+        if let Some(index) = self.watchpoints.iter().position(|w| w.matches(access)) {
+            self.watch_hit.set(Some(WatchHit { index, access: *access }));
+        }
+    }
+
+    fn check_mapped(addr: Address) -> GbaResult<()> {
+        if Memory::is_mapped(addr) {
+            Ok(())
+        }
+        else {
+            Err(GbaError::UnmappedAddress(addr))
+        }
+    }
+
+    /// Work RAM on the board and in the CPU, for memory searches and viewers
+    pub fn ewram(&self) -> &[u8] {
+        self.ext_ram.as_slice()
+    }
+
+    /// Internal work RAM
+    pub fn iwram(&self) -> &[u8] {
+        self.int_ram.as_slice()
+    }
+
+    /// Video memory as the PPU sees it, for VRAM viewers
+    pub fn palette(&self) -> &[u8] {
+        self.pal_ram.as_slice()
+    }
+
+    /// Video RAM
+    pub fn vram(&self) -> &[u8] {
+        self.vis_ram.as_slice()
+    }
+
+    /// Object attribute memory
+    pub fn oam(&self) -> &[u8] {
+        self.oam.as_slice()
+    }
+
+    /// Battery-backed save memory on the cartridge
+    pub fn pak_ram(&self) -> &[u8] {
+        self.pak_ram.as_slice()
+    }
+
+    /// Cartridge SRAM, for writing
+    pub fn pak_ram_mut(&mut self) -> &mut [u8] {
+        self.pak_ram.as_mut_slice()
+    }
+
+    /// The cartridge's EEPROM
+    pub fn eeprom(&self) -> &Eeprom {
+        &self.eeprom
+    }
+
+    /// The cartridge's EEPROM, for writing
+    pub fn eeprom_mut(&mut self) -> &mut Eeprom {
+        &mut self.eeprom
+    }
+
+    /// Fit or remove the EEPROM, which moves up out of the way of bigger
+    /// ROMs
+    pub fn fit_eeprom(&mut self, fitted: bool, size: Option<EepromSize>) {
+        let large_rom = self.rom_len > EEPROM_MAX_ROM;
+        self.eeprom.fit(fitted, size, large_rom);
+    }
+
+    /// The cartridge's flash
+    pub fn flash(&self) -> &Flash {
+        &self.flash
+    }
+
+    /// The cartridge's flash, for writing
+    pub fn flash_mut(&mut self) -> &mut Flash {
+        &mut self.flash
+    }
+
+    /// Devices on the cartridge's GPIO port
+    pub fn gpio(&self) -> &Gpio {
+        &self.gpio
+    }
+
+    /// Devices on the cartridge's GPIO port, for writing
+    pub fn gpio_mut(&mut self) -> &mut Gpio {
+        &mut self.gpio
+    }
+
+    /// The cartridge's tilt sensor
+    pub fn tilt(&self) -> &TiltSensor {
+        &self.tilt
+    }
+
+    /// The cartridge's tilt sensor, for writing
+    pub fn tilt_mut(&mut self) -> &mut TiltSensor {
+        &mut self.tilt
+    }
+
+    /// The IO registers and the devices behind them
+    pub fn io(&self) -> &IoRegs {
+        &self.io
+    }
+
+    /// The IO registers and the devices behind them, for writing
+    pub fn io_mut(&mut self) -> &mut IoRegs {
+        &mut self.io
+    }
+
+    /// Advance the hardware on the bus by a number of CPU cycles
+    pub fn step(&mut self, cycles: u32) -> PpuEvents {
+        self.prefetch.run(cycles, &self.io.waitcnt);
+        self.gpio.step(cycles);
+        let events = self.io.step(cycles);
+        self.render_line();
+        self.run_dma();
+        events
+    }
+
+    // Draw the line the LCD has reached H-Blank on. It's done before any
+    // H-Blank DMA, which sets up the next line.
+    fn render_line(&mut self) {
+        if !self.io.ppu.is_line_due() {
+            return;
+        }
+        let regs = self.io.bg_regs();
+        let video = VideoMem {
+            palette: self.pal_ram.as_slice(),
+            vram: self.vis_ram.as_slice(),
+            oam: self.oam.as_slice(),
+        };
+        self.io.ppu.render_due_line(&regs, &video);
+    }
+
+    // Carry out every pending DMA transfer in priority order
+    fn run_dma(&mut self) {
+        while let Some(ch) = self.io.dma.next_pending() {
+            let xfer = self.io.dma.start(ch);
+            let (mut src, mut dst) = (xfer.src, xfer.dst);
+            if self.eeprom.maps(dst as Address) {
+                self.eeprom.detect_size(xfer.count);
+            }
+
+            for _ in 0..xfer.count {
+                if xfer.word {
+                    let val = self.bus_read::<u32>((src & !3) as Address);
+                    self.bus_write16::<u32>((dst & !3) as Address, val);
+                }
+                else {
+                    let val = self.bus_read::<u16>((src & !1) as Address);
+                    self.bus_write16::<u16>((dst & !1) as Address, val);
+                }
+                src = src.wrapping_add(xfer.src_step);
+                dst = dst.wrapping_add(xfer.dst_step);
+            }
+
+            self.io.dma.finish(&xfer, src, dst, &mut self.io.irq);
+        }
+    }
+
+    /// Read a byte, halfword or word over the bus as the CPU does
+    pub fn read<T: MemValue>(&self, addr: Address) -> T {
+        self.check_alignment(addr, T::SIZE, false);
+        if let Some(val) = self.bus_log.borrow_mut().replay_read(addr, T::SIZE) {
+            return T::from_bits(val);
+        }
+
+        if !self.watches.borrow().is_empty() {
+            let access = BusAccess {
+                addr,
+                size: T::SIZE,
+                val: self.peek::<T>(addr).to_bits(),
+                write: false,
+            };
+            self.run_watches(WatchTiming::Before, &access);
+        }
+
+        let val = self.bus_read::<T>(addr);
+        let access = BusAccess {
+            addr,
+            size: T::SIZE,
+            val: val.to_bits(),
+            write: false,
+        };
+        self.check_watch(&access);
+        self.bus_log.borrow_mut().log_read(access);
+        self.run_watches(WatchTiming::After, &access);
+        val
+    }
+
+    /// Read an opcode, charging its fetch
+    pub fn fetch<T: MemValue>(&mut self, addr: Address) -> T {
+        self.charge(addr, T::SIZE as usize, true);
+        self.read::<T>(addr)
+    }
+
+    /// Read for a debugger or viewer: no logging, watchpoints or aborts
+    pub fn peek<T: MemValue>(&self, addr: Address) -> T {
+        if Memory::is_mapped(addr) {
+            self.bus_read::<T>(addr)
+        }
+        else {
+            T::default()
+        }
+    }
+
+    fn bus_read<T: MemValue>(&self, addr: Address) -> T {
+        match page(addr) {
+            Page::Ewram => self.ext_ram.read(addr),
+            Page::Iwram => self.int_ram.read(addr),
+            Page::Palette => self.pal_ram.read(addr),
+            Page::Vram => self.vis_ram.read(addr),
+            Page::Oam => self.oam.read(addr),
+            Page::Rom if self.gpio.maps_read(addr) =>
+                T::from_bits(self.gpio.read(addr, T::SIZE)),
+            Page::Rom if self.eeprom.maps(addr) => T::from_bits(self.eeprom.read() as u32),
+            Page::Rom => self.pak_rom.read(addr),
+            Page::Sram if self.tilt.maps(addr) => T::from_bits(self.tilt.read(addr) as u32),
+            Page::Sram if self.flash.is_fitted() => T::from_bits(self.flash.read(addr) as u32),
+            Page::Sram => self.pak_ram.read(addr),
+            Page::Bios if addr <= SystemRom::hi() =>
+                self.sys_rom.read(addr),
+            Page::Io if addr <= IoRegs::hi() => self.io.read(addr),
+            _ => self.unmapped_read(addr),
+        }
+    }
+
+    /// Write a byte over the bus as the CPU does
+    pub fn write8<T: MemValue>(&mut self, addr: Address, val: T) {
+        if let Some(access) = self.log_write(addr, val) {
+            self.bus_write8(addr, val);
+            self.run_watches(WatchTiming::After, &access);
+        }
+    }
+
+    fn bus_write8<T: MemValue>(&mut self, addr: Address, val: T) {
+        self.writes += 1;
+        match page(addr) {
+            Page::Ewram => {
+                self.ext_ram.write(addr, val);
+                self.code_pages.note_write(addr);
+            },
+            Page::Iwram => {
+                self.int_ram.write(addr, val);
+                self.code_pages.note_write(addr);
+            },
+            Page::Io if self.strict_aborts && !io_map::is_writable(addr) =>
+                self.read_only_write(addr),
+            Page::Io if addr <= IoRegs::hi() => {
+                self.io.write(addr, val);
+                self.run_dma();
+            },
+            Page::Sram if self.tilt.maps(addr) => self.tilt.write(addr, val.to_bits() as u8),
+            Page::Sram if self.flash.is_fitted() => self.flash.write(addr, val.to_bits() as u8),
+            Page::Sram => self.pak_ram.write(addr, val),
+            Page::Rom if self.gpio.maps_write(addr) =>
+                self.gpio.write(addr, T::SIZE, val.to_bits()),
+            Page::Bios | Page::Rom => self.read_only_write(addr),
+            // Video memory only takes halfwords, so a byte lands in both
+            // halves of one. OAM and the sprite tiles ignore them.
+            Page::Palette => self.pal_ram.write(addr, Memory::doubled_byte(val)),
+            Page::Vram if addr & 0x1FFFF < self.bg_vram_size() =>
+                self.vis_ram.write(addr, Memory::doubled_byte(val)),
+            Page::Vram | Page::Oam => {},
+            _ if Memory::is_mapped(addr) => {},
+            _ => self.unmapped_write(addr),
+        }
+    }
+
+    fn doubled_byte<T: MemValue>(val: T) -> u16 {
+        (val.to_bits() as u8 as u16) * 0x0101
+    }
+
+    // The backgrounds' share of VRAM, the rest holding sprite tiles. The
+    // bitmap modes (3-5) take 16K more of it for their frame buffers.
+    fn bg_vram_size(&self) -> Address {
+        if self.io.ppu.dispcnt() & 7 >= 3 { 0x14000 } else { 0x10000 }
+    }
+
+    /// Write a halfword over the bus as the CPU does
+    pub fn write16<T: MemValue>(&mut self, addr: Address, val: T) {
+        if let Some(access) = self.log_write(addr, val) {
+            self.bus_write16(addr, val);
+            self.run_watches(WatchTiming::After, &access);
+        }
+    }
+
+    fn bus_write16<T: MemValue>(&mut self, addr: Address, val: T) {
+        self.writes += 1;
+        match page(addr) {
+            Page::Ewram => {
+                self.ext_ram.write(addr, val);
+                self.code_pages.note_write(addr);
+            },
+            Page::Iwram => {
+                self.int_ram.write(addr, val);
+                self.code_pages.note_write(addr);
+            },
+            Page::Palette => self.pal_ram.write(addr, val),
+            Page::Vram => self.vis_ram.write(addr, val),
+            Page::Oam => self.oam.write(addr, val),
+            Page::Io if self.strict_aborts && !io_map::is_writable(addr) =>
+                self.read_only_write(addr),
+            Page::Io if addr <= IoRegs::hi() => {
+                self.io.write(addr, val);
+                self.run_dma();
+            },
+            Page::Sram if self.tilt.maps(addr) => self.tilt.write(addr, val.to_bits() as u8),
+            Page::Sram if self.flash.is_fitted() => self.flash.write(addr, val.to_bits() as u8),
+            Page::Sram => self.pak_ram.write(addr, val),
+            Page::Rom if self.gpio.maps_write(addr) =>
+                self.gpio.write(addr, T::SIZE, val.to_bits()),
+            Page::Rom if self.eeprom.maps(addr) => self.eeprom.write(val.to_bits() as u16),
+            Page::Bios | Page::Rom => self.read_only_write(addr),
+            _ if Memory::is_mapped(addr) => {},
+            _ => self.unmapped_write(addr),
+        }
+    }
+
+    /// Write a word over the bus as the CPU does
+    pub fn write32<T: MemValue>(&mut self, addr: Address, val: T) {
+        if let Some(access) = self.log_write(addr, val) {
+            self.bus_write16(addr, val);
+            self.run_watches(WatchTiming::After, &access);
+        }
+    }
+
+    /// Strict accessors that fail on unmapped addresses instead of falling
+    /// back to open bus
+    pub fn try_read<T: MemValue>(&self, addr: Address) -> GbaResult<T> {
+        Memory::check_mapped(addr)?;
+        Ok(self.read(addr))
+    }
+
+    /// write8, failing on an unmapped address
+    pub fn try_write8<T: MemValue>(&mut self, addr: Address, val: T) -> GbaResult<()> {
+        Memory::check_mapped(addr)?;
+        self.write8(addr, val);
+        Ok(())
+    }
+
+    /// write16, failing on an unmapped address
+    pub fn try_write16<T: MemValue>(&mut self, addr: Address, val: T) -> GbaResult<()> {
+        Memory::check_mapped(addr)?;
+        self.write16(addr, val);
+        Ok(())
+    }
+
+    /// write32, failing on an unmapped address
+    pub fn try_write32<T: MemValue>(&mut self, addr: Address, val: T) -> GbaResult<()> {
+        Memory::check_mapped(addr)?;
+        self.write32(addr, val);
+        Ok(())
     }
 }
 
@@ -127,3 +843,69 @@ impl Memory {
 //         }
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rom_patches_stay_in_the_rom() {
+        let mut mem = Memory::with_rom_bytes(&[0; 0x100]).unwrap();
+        mem.patch_rom(0x0A000010, &[1, 2]);
+        mem.patch_rom(0x080000FF, &[3, 4]);
+        mem.patch_rom(0x02000000, &[5]);
+        mem.patch_rom(0x0E000000, &[6]);
+        assert_eq!(&mem.rom()[0x10..0x12], &[1, 2]);
+        assert_eq!(mem.rom()[0xFF], 3);
+        assert!(!mem.rom().contains(&5) && !mem.rom().contains(&6));
+    }
+
+    #[test]
+    fn byte_writes_to_video_memory() {
+        let mut mem = Memory::blank();
+        mem.write8::<u8>(0x05000011, 0x12);
+        mem.write8::<u8>(0x0600FFFE, 0x34);
+        mem.write8::<u8>(0x06010000, 0x56);
+        mem.write8::<u8>(0x07000000, 0x78);
+        assert_eq!(mem.read::<u16>(0x05000010), 0x1212);
+        assert_eq!(mem.read::<u16>(0x0600FFFE), 0x3434);
+        assert_eq!(mem.read::<u16>(0x06010000), 0);
+        assert_eq!(mem.read::<u16>(0x07000000), 0);
+
+        // In the bitmap modes the backgrounds reach further into VRAM
+        mem.write16::<u16>(0x04000000, 3);
+        mem.write8::<u8>(0x06013FFF, 0x9A);
+        mem.write8::<u8>(0x06014000, 0xBC);
+        assert_eq!(mem.read::<u16>(0x06013FFE), 0x9A9A);
+        assert_eq!(mem.read::<u16>(0x06014000), 0);
+    }
+
+    #[test]
+    fn strict_mode_aborts_bad_accesses() {
+        let mut mem = Memory::blank();
+        mem.write16::<u16>(0x08000000, 1);
+        mem.write8::<u8>(0x0A000001, 1);
+        assert_eq!(mem.read::<u16>(0x08000000), 0);
+        assert_eq!(mem.read::<u8>(0x08000001), 0);
+        let _ = mem.read::<u32>(0x10000000);
+        assert_eq!(mem.take_abort(), None);
+
+        mem.set_strict_aborts(true);
+        mem.write16::<u16>(0x08000000, 1);
+        assert_eq!(mem.take_abort(), Some(BusAbort { addr: 0x08000000, write: true }));
+        mem.write32::<u32>(0x00000100, 1);
+        assert_eq!(mem.take_abort(), Some(BusAbort { addr: 0x00000100, write: true }));
+        let _ = mem.read::<u32>(0x10000000);
+        assert_eq!(mem.take_abort(), Some(BusAbort { addr: 0x10000000, write: false }));
+
+        // VCOUNT can only be read
+        mem.write16::<u16>(0x04000006, 1);
+        assert_eq!(mem.take_abort(), Some(BusAbort { addr: 0x04000006, write: true }));
+        let _ = mem.read::<u32>(0x02000002);
+        assert_eq!(mem.take_abort(), Some(BusAbort { addr: 0x02000002, write: false }));
+
+        mem.write16::<u16>(0x02000000, 1);
+        mem.write16::<u16>(0x04000000, 1);
+        assert_eq!(mem.take_abort(), None);
+    }
+}
@@ -0,0 +1,50 @@
+use gba_mem::Address;
+
+/// The bus decodes the top byte of an address to pick a region, so the
+/// address space splits into 16MB pages each answered by at most one
+/// region. Looking the page up replaces a chain of range comparisons on
+/// every access. The BIOS and IO registers fill only the bottom of their
+/// pages, and the cartridge has its GPIO port and tilt sensor inside the
+/// ROM and SRAM pages; those few cases still check the address.
+pub const PAGE_SHIFT: usize = 24;
+
+/// Which memory a 16MB page of the address space maps to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Page {
+    /// System ROM
+    Bios,
+    /// On-board work RAM
+    Ewram,
+    /// On-chip work RAM
+    Iwram,
+    /// IO registers
+    Io,
+    /// Palette RAM
+    Palette,
+    /// Video RAM
+    Vram,
+    /// Object attributes
+    Oam,
+    /// All three wait state windows
+    Rom,
+    /// Cartridge save memory
+    Sram,
+    /// Nothing; reads are open bus
+    Unmapped,
+}
+
+const PAGES: [Page; 16] = [
+    Page::Bios, Page::Unmapped, Page::Ewram, Page::Iwram,
+    Page::Io, Page::Palette, Page::Vram, Page::Oam,
+    Page::Rom, Page::Rom, Page::Rom, Page::Rom,
+    Page::Rom, Page::Rom, Page::Sram, Page::Sram,
+];
+
+/// The page addr is in
+#[inline]
+pub fn page(addr: Address) -> Page {
+    match PAGES.get(addr >> PAGE_SHIFT) {
+        Some(&page) => page,
+        None => Page::Unmapped,
+    }
+}
@@ -0,0 +1,126 @@
+use core::fmt;
+
+use gba_error::{GbaError, GbaResult};
+use prelude::*;
+
+/// Cartridge header layout from:
+/// http://problemkaputt.de/gbatek.htm#gbacartridgeheader
+pub const HEADER_SIZE: usize = 0xC0;
+
+const LOGO_OFFSET: usize = 0x04;
+/// Bytes of the compressed Nintendo logo
+pub const LOGO_LEN: usize = 156;
+const TITLE_OFFSET: usize = 0xA0;
+const TITLE_LEN: usize = 12;
+const GAME_CODE_OFFSET: usize = 0xAC;
+const MAKER_CODE_OFFSET: usize = 0xB0;
+const FIXED_OFFSET: usize = 0xB2;
+const UNIT_CODE_OFFSET: usize = 0xB3;
+const DEVICE_TYPE_OFFSET: usize = 0xB4;
+const VERSION_OFFSET: usize = 0xBC;
+const CHECKSUM_OFFSET: usize = 0xBD;
+
+/// Value every header has at 0xB2
+pub const FIXED_VALUE: u8 = 0x96;
+
+fn text(bytes: &[u8]) -> String {
+    bytes.iter()
+        .take_while(|&&b| b != 0)
+        .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '?' })
+        .collect()
+}
+
+/// The first 192 Bytes of a cartridge ROM
+#[derive(Clone)]
+pub struct RomHeader {
+    /// ARM branch to the start of the game
+    pub entry: u32,
+    /// Compressed Nintendo logo the BIOS checks
+    pub logo: [u8; LOGO_LEN],
+    /// Game title, upper case ASCII
+    pub title: String,
+    /// Four character game code, e.g. AXVE
+    pub game_code: String,
+    /// Two character maker code, e.g. 01 for Nintendo
+    pub maker_code: String,
+    /// Always FIXED_VALUE
+    pub fixed: u8,
+    /// Zero for the GBA
+    pub unit_code: u8,
+    /// Zero except on debug units
+    pub device_type: u8,
+    /// Software version
+    pub version: u8,
+    /// Complement check over 0xA0-0xBC
+    pub checksum: u8,
+}
+
+impl RomHeader {
+    /// Parse the header at the start of rom, which is zero padded if it is
+    /// too short to hold one
+    pub fn parse(rom: &[u8]) -> RomHeader {
+        let mut raw = [0; HEADER_SIZE];
+        let len = rom.len().min(HEADER_SIZE);
+        raw[..len].copy_from_slice(&rom[..len]);
+
+        let mut logo = [0; LOGO_LEN];
+        logo.copy_from_slice(&raw[LOGO_OFFSET..LOGO_OFFSET + LOGO_LEN]);
+        RomHeader {
+            entry: u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]),
+            logo,
+            title: text(&raw[TITLE_OFFSET..TITLE_OFFSET + TITLE_LEN]),
+            game_code: text(&raw[GAME_CODE_OFFSET..GAME_CODE_OFFSET + 4]),
+            maker_code: text(&raw[MAKER_CODE_OFFSET..MAKER_CODE_OFFSET + 2]),
+            fixed: raw[FIXED_OFFSET],
+            unit_code: raw[UNIT_CODE_OFFSET],
+            device_type: raw[DEVICE_TYPE_OFFSET],
+            version: raw[VERSION_OFFSET],
+            checksum: raw[CHECKSUM_OFFSET],
+        }
+    }
+
+    /// The checksum the BIOS expects for bytes 0xA0-0xBC of rom
+    pub fn expected_checksum(rom: &[u8]) -> u8 {
+        let sum = rom.iter()
+            .chain([0u8; HEADER_SIZE].iter())
+            .skip(TITLE_OFFSET)
+            .take(CHECKSUM_OFFSET - TITLE_OFFSET)
+            .fold(0u8, |sum, &b| sum.wrapping_sub(b));
+        sum.wrapping_sub(0x19)
+    }
+
+    /// Check the fields the BIOS refuses to boot without. The logo isn't
+    /// compared since there is no BIOS copy of it to compare against.
+    pub fn validate(&self, rom: &[u8]) -> GbaResult<()> {
+        let mut problems = Vec::new();
+        if self.fixed != FIXED_VALUE {
+            problems.push(format!("fixed value is {:#04x}, not {:#04x}", self.fixed, FIXED_VALUE));
+        }
+        let expected = RomHeader::expected_checksum(rom);
+        if self.checksum != expected {
+            problems.push(format!("checksum is {:#04x}, expected {:#04x}", self.checksum, expected));
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        }
+        else {
+            Err(GbaError::BadRomHeader(problems.join(", ")))
+        }
+    }
+}
+
+impl fmt::Debug for RomHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write![f, "RomHeader {{ title: {:?}, game_code: {:?}, maker_code: {:?}, \
+                   version: {}, checksum: {:#04x} }}",
+               self.title, self.game_code, self.maker_code, self.version, self.checksum]
+    }
+}
+
+impl fmt::Display for RomHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write![f, "{} [{}] maker {} v{}", self.title, self.game_code, self.maker_code,
+               self.version]
+    }
+}
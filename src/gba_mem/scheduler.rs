@@ -0,0 +1,107 @@
+// Keeps the time each device behind the IO registers next has something to
+// do by itself: a scanline boundary, a timer overflow, a frame sequencer
+// step or the end of a serial transfer. Between those the devices can't
+// change anything the CPU sees, so the CPU runs on and the devices are
+// caught up in one go when the earliest deadline comes round.
+
+use alloc::collections::BinaryHeap;
+use core::cmp::Reverse;
+
+/// Hardware that needs running at a set cycle
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Event {
+    /// H-Blank or the next line, and the DMAs and interrupts they start
+    Ppu,
+    /// An overflow, which may also clock Direct Sound
+    Timers,
+    /// A frame sequencer step, where channels can switch off
+    Apu,
+    /// A transfer finishing
+    Sio,
+}
+
+const NUM_EVENTS: usize = 4;
+
+/// Deadlines in cycles since power on, held in a min-heap. Rescheduling an
+/// event leaves its old entry in the heap, where it is told apart from the
+/// current one and dropped when it reaches the top.
+#[derive(Clone, Debug, Default)]
+pub struct Scheduler {
+    now: u64,
+    deadlines: [Option<u64>; NUM_EVENTS],
+    heap: BinaryHeap<Reverse<(u64, Event)>>,
+}
+
+impl Scheduler {
+    /// Cycles run since power on
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    /// Move time on by cycles
+    pub fn advance(&mut self, cycles: u32) {
+        self.now += cycles as u64;
+    }
+
+    /// Set when event next happens, in cycles from now, or cancel it
+    pub fn schedule(&mut self, event: Event, cycles: Option<u32>) {
+        let deadline = cycles.map(|cycles| self.now + cycles as u64);
+        if self.deadlines[event as usize] == deadline {
+            return;
+        }
+        self.deadlines[event as usize] = deadline;
+        if let Some(at) = deadline {
+            self.heap.push(Reverse((at, event)));
+        }
+        self.drop_stale();
+    }
+
+    fn drop_stale(&mut self) {
+        while let Some(&Reverse((at, event))) = self.heap.peek() {
+            if self.deadlines[event as usize] == Some(at) {
+                break;
+            }
+            self.heap.pop();
+        }
+    }
+
+    /// The next event and when it happens
+    pub fn peek(&self) -> Option<(u64, Event)> {
+        self.heap.peek().map(|&Reverse(next)| next)
+    }
+
+    /// Cycles until the next event, 0 if it is already due
+    pub fn cycles_to_next(&self) -> Option<u32> {
+        self.peek().map(|(at, _)| at.saturating_sub(self.now) as u32)
+    }
+
+    /// Whether any event's time has come
+    pub fn is_due(&self) -> bool {
+        self.peek().is_some_and(|(at, _)| at <= self.now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_earliest_current_deadline_comes_first() {
+        let mut sched = Scheduler::default();
+        sched.schedule(Event::Ppu, Some(960));
+        sched.schedule(Event::Timers, Some(100));
+        sched.schedule(Event::Timers, Some(2000)); // Replaces the first
+        sched.schedule(Event::Sio, Some(500));
+        assert_eq!(sched.peek(), Some((500, Event::Sio)));
+
+        sched.schedule(Event::Sio, None);
+        assert_eq!(sched.cycles_to_next(), Some(960));
+        sched.advance(1000);
+        assert!(sched.is_due());
+        assert_eq!(sched.cycles_to_next(), Some(0));
+
+        sched.schedule(Event::Ppu, Some(272));
+        assert!(!sched.is_due());
+        assert_eq!(sched.peek(), Some((1272, Event::Ppu)));
+    }
+}
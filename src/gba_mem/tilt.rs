@@ -0,0 +1,88 @@
+use gba_mem::Address;
+
+// Two axis accelerometer on Yoshi's Universal Gravitation and Koro Koro
+// Puzzle. Unlike the GPIO devices it sits on the SRAM bus:
+// http://problemkaputt.de/gbatek.htm#gbacarttiltsensor
+//
+// Writing 55h then AAh to the start registers takes a sample. Each axis
+// reads back as 12 bits split over two registers, with bit 7 of the X high
+// byte set once the sample is ready.
+const TILT_START1: Address = 0x0E008000;
+const TILT_START2: Address = 0x0E008100;
+const TILT_X_LO: Address = 0x0E008200;
+const TILT_X_HI: Address = 0x0E008300;
+const TILT_Y_LO: Address = 0x0E008400;
+const TILT_Y_HI: Address = 0x0E008500;
+const TILT_READY: u8 = 0x80;
+
+/// Reading when the cartridge is held level
+pub const TILT_CENTER: u16 = 0x3A0;
+
+/// The accelerometer and what it last sampled
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TiltSensor {
+    fitted: bool,
+    tilt_x: i16, // From level, in sensor counts
+    tilt_y: i16,
+    sample_x: u16,
+    sample_y: u16,
+    armed: bool, // The first start byte was written
+}
+
+// Whether it is fitted comes from the settings, not the state
+impl_save_state!(TiltSensor { tilt_x, tilt_y, sample_x, sample_y, armed });
+
+impl TiltSensor {
+    /// Whether the cartridge has one
+    pub fn is_fitted(&self) -> bool {
+        self.fitted
+    }
+
+    /// Fit the sensor, or take it out
+    pub fn set_fitted(&mut self, fitted: bool) {
+        self.fitted = fitted;
+    }
+
+    /// Whether addr reaches the sensor
+    pub fn maps(&self, addr: Address) -> bool {
+        self.fitted && (TILT_START1..=TILT_Y_HI).contains(&addr)
+    }
+
+    /// How far the cartridge is tipped, as set_tilt gave it
+    pub fn tilt(&self) -> (i16, i16) {
+        (self.tilt_x, self.tilt_y)
+    }
+
+    /// How far the cartridge is tipped on each axis, in sensor counts from
+    /// level. About 0x100 is as far as games expect.
+    pub fn set_tilt(&mut self, x: i16, y: i16) {
+        self.tilt_x = x;
+        self.tilt_y = y;
+    }
+
+    /// Read a sensor register
+    pub fn read(&self, addr: Address) -> u8 {
+        let (x, y) = (self.sample_x, self.sample_y);
+        match addr {
+            TILT_X_LO => x as u8,
+            TILT_X_HI => (x >> 8) as u8 & 0xF | TILT_READY,
+            TILT_Y_LO => y as u8,
+            TILT_Y_HI => (y >> 8) as u8 & 0xF,
+            _ => 0,
+        }
+    }
+
+    /// Write a sensor register, which starts a sample
+    pub fn write(&mut self, addr: Address, val: u8) {
+        match (addr, val) {
+            (TILT_START1, 0x55) => self.armed = true,
+            (TILT_START2, 0xAA) if self.armed => {
+                let axis = |t: i16| (TILT_CENTER as i32 + t as i32).clamp(0, 0xFFF) as u16;
+                self.sample_x = axis(self.tilt_x);
+                self.sample_y = axis(self.tilt_y);
+                self.armed = false;
+            },
+            _ => self.armed = false,
+        }
+    }
+}
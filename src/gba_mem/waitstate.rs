@@ -0,0 +1,214 @@
+use gba_mem::Address;
+
+/// Game pak bus timing from:
+/// http://problemkaputt.de/gbatek.htm#gbasystemcontrol
+pub const WAITCNT: Address = 0x04000204;
+
+const WAITCNT_PREFETCH:   u16 = 1 << 14;
+/// Bit 15 reports the pak type
+pub const WAITCNT_WRITABLE: u16 = 0x5FFF;
+
+// Extra cycles for the 2-bit first access (and SRAM) settings
+const FIRST_WAITS: [u32; 4] = [4, 3, 2, 8];
+
+/// The cartridge ROM appears three times, each window with its own timing
+pub const WS0_LO: Address = 0x08000000;
+/// Start of the second ROM window
+pub const WS1_LO: Address = 0x0A000000;
+/// Start of the third ROM window
+pub const WS2_LO: Address = 0x0C000000;
+/// End of the third ROM window
+pub const WS2_HI: Address = 0x0DFFFFFF;
+
+/// Which wait state window of the cartridge ROM addr is in
+pub fn pak_wait_state(addr: Address) -> Option<usize> {
+    match addr {
+        WS0_LO..=WS2_HI => Some((addr - WS0_LO) >> 25),
+        _ => None,
+    }
+}
+
+/// Wait states of the on-board work RAM, the only internal memory with any
+pub const EWRAM_WAITS: u32 = 2;
+
+/// Cycles for an access of size Bytes to memory with a fixed timing. Wider
+/// accesses than the bus are split into one access per bus width.
+pub fn bus_cycles(width: usize, waits: u32, size: usize) -> u32 {
+    let accesses = size.div_ceil(width);
+    accesses as u32 * (1 + waits)
+}
+
+/// Halfwords the prefetch unit can hold
+pub const PREFETCH_SIZE: u32 = 8;
+
+/// Wait state control, the WAITCNT register
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WaitCnt(u16);
+
+impl_save_state!(WaitCnt { 0 });
+
+bitfield!(WaitCnt {
+    0 => sram_setting: u16 [0, 2];
+});
+
+impl WaitCnt {
+    /// WAITCNT as it reads
+    pub fn read(&self) -> u16 {
+        self.0
+    }
+
+    /// Write WAITCNT
+    pub fn write(&mut self, val: u16) {
+        self.0 = val & WAITCNT_WRITABLE;
+    }
+
+    /// Whether the prefetch buffer is on
+    pub fn prefetch(&self) -> bool {
+        self.0 & WAITCNT_PREFETCH != 0
+    }
+
+    /// Wait states added to an 8-bit SRAM access
+    pub fn sram_waits(&self) -> u32 {
+        FIRST_WAITS[self.sram_setting() as usize]
+    }
+
+    /// Wait states added to a 16-bit ROM access in window ws. Sequential
+    /// accesses follow on from the previous address and are faster.
+    pub fn rom_waits(&self, ws: usize, seq: bool) -> u32 {
+        let shift = 2 + 3 * ws as u16;
+        if seq {
+            let slow = (self.0 >> (shift + 2)) & 1 == 0;
+            match (ws, slow) {
+                (_, false) => 1,
+                (0, true) => 2,
+                (1, true) => 4,
+                (_, true) => 8,
+            }
+        }
+        else {
+            FIRST_WAITS[((self.0 >> shift) & 3) as usize]
+        }
+    }
+
+    /// Cycles for an access of size Bytes to the cartridge ROM. The bus is
+    /// 16 bits wide, so a word takes a second, sequential halfword access.
+    pub fn rom_cycles(&self, addr: Address, size: usize, seq: bool) -> u32 {
+        let ws = pak_wait_state(addr).unwrap_or(0);
+        let first = 1 + self.rom_waits(ws, seq);
+        if size == 4 {
+            first + 1 + self.rom_waits(ws, true)
+        }
+        else {
+            first
+        }
+    }
+}
+
+/// While the CPU is busy elsewhere, the prefetch unit keeps reading
+/// halfwords from the cartridge after the last opcode fetched from it.
+/// Opcode fetches it already holds take a single cycle.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Prefetch {
+    next: Address, // Address of the oldest halfword held, or being read
+    count: u32, // Halfwords held
+    progress: u32, // Cycles into reading the next halfword
+}
+
+impl_save_state!(Prefetch { next, count, progress });
+
+impl Prefetch {
+    /// Forget what was read ahead, e.g. after a data access to the pak
+    pub fn flush(&mut self) {
+        self.count = 0;
+        self.progress = 0;
+    }
+
+    /// Cycles to fetch an opcode of size Bytes from the cartridge ROM
+    pub fn fetch(&mut self, addr: Address, size: usize, waitcnt: &WaitCnt) -> u32 {
+        let halfwords = size as u32 / 2;
+        if !waitcnt.prefetch() || addr != self.next {
+            // Miss: the buffer restarts after this opcode. Without prefetch
+            // straight-line code still gets sequential timing.
+            let seq = !waitcnt.prefetch() && addr == self.next;
+            let cycles = waitcnt.rom_cycles(addr, size, seq);
+            self.next = addr + size;
+            self.flush();
+            return cycles;
+        }
+
+        // Wait for halfwords still on their way
+        let ws = pak_wait_state(addr).unwrap_or(0);
+        let seq = 1 + waitcnt.rom_waits(ws, true);
+        let mut cycles = 0;
+        while self.count < halfwords {
+            cycles += seq - self.progress;
+            self.progress = 0;
+            self.count += 1;
+        }
+        self.count -= halfwords;
+        self.next = addr + size;
+        cycles.max(1)
+    }
+
+    /// Let the unit read ahead for cycles the CPU spent off the pak bus
+    pub fn run(&mut self, cycles: u32, waitcnt: &WaitCnt) {
+        if !waitcnt.prefetch() || pak_wait_state(self.next).is_none() {
+            return;
+        }
+        let ws = pak_wait_state(self.next).unwrap_or(0);
+        let seq = 1 + waitcnt.rom_waits(ws, true);
+        self.progress += cycles;
+        while self.progress >= seq && self.count < PREFETCH_SIZE {
+            self.progress -= seq;
+            self.count += 1;
+        }
+        if self.count == PREFETCH_SIZE {
+            self.progress = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rom_waits_follow_waitcnt() {
+        let mut waitcnt = WaitCnt::default();
+        assert_eq!(waitcnt.rom_cycles(0x08000000, 2, false), 5);
+        assert_eq!(waitcnt.rom_cycles(0x0A000000, 2, true), 5);
+        assert_eq!(waitcnt.rom_cycles(0x0C000000, 4, false), 5 + 9);
+
+        // The usual setting games pick: WS0 3/1, prefetch on
+        waitcnt.write(0x4014);
+        assert_eq!(waitcnt.rom_cycles(0x08000000, 2, false), 4);
+        assert_eq!(waitcnt.rom_cycles(0x08000000, 4, true), 4);
+        assert!(waitcnt.prefetch());
+    }
+
+    #[test]
+    fn bus_cycles_split_wide_accesses() {
+        assert_eq!(bus_cycles(4, 0, 4), 1);
+        assert_eq!(bus_cycles(2, 0, 4), 2);
+        assert_eq!(bus_cycles(2, EWRAM_WAITS, 2), 3);
+        assert_eq!(bus_cycles(2, EWRAM_WAITS, 4), 6);
+        assert_eq!(bus_cycles(2, 0, 1), 1);
+    }
+
+    #[test]
+    fn prefetch_hides_sequential_fetches() {
+        let mut waitcnt = WaitCnt::default();
+        waitcnt.write(0x4014);
+        let mut prefetch = Prefetch::default();
+
+        assert_eq!(prefetch.fetch(0x08000000, 2, &waitcnt), 4);
+        // Long enough to read two halfwords ahead
+        prefetch.run(4, &waitcnt);
+        assert_eq!(prefetch.fetch(0x08000002, 2, &waitcnt), 1);
+        assert_eq!(prefetch.fetch(0x08000004, 2, &waitcnt), 1);
+        // Nothing buffered for this one, so it waits a full access
+        assert_eq!(prefetch.fetch(0x08000006, 2, &waitcnt), 2);
+        // A jump misses
+        assert_eq!(prefetch.fetch(0x08000100, 2, &waitcnt), 4);
+    }
+}
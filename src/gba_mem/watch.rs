@@ -0,0 +1,191 @@
+use core::fmt;
+use core::ops::Range;
+
+use gba_mem::Address;
+use gba_mem::bus_log::BusAccess;
+use prelude::*;
+
+/// Which CPU accesses a watchpoint fires on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchKind {
+    /// Reads only
+    Read,
+    /// Writes only
+    Write,
+    /// Reads and writes
+    Access,
+}
+
+impl WatchKind {
+    fn matches(&self, write: bool) -> bool {
+        match *self {
+            WatchKind::Read => !write,
+            WatchKind::Write => write,
+            WatchKind::Access => true,
+        }
+    }
+}
+
+impl fmt::Display for WatchKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            WatchKind::Read => write![f, "r"],
+            WatchKind::Write => write![f, "w"],
+            WatchKind::Access => write![f, "rw"],
+        }
+    }
+}
+
+/// A range of addresses the debugger wants to stop on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Watchpoint {
+    /// First address
+    pub lo: Address,
+    /// Inclusive
+    pub hi: Address,
+    /// Which accesses it fires on
+    pub kind: WatchKind,
+}
+
+impl Watchpoint {
+    /// Watch len bytes from addr
+    pub fn new(addr: Address, len: usize, kind: WatchKind) -> Watchpoint {
+        assert!(len > 0);
+        Watchpoint {
+            lo: addr,
+            hi: addr + len - 1,
+            kind,
+        }
+    }
+
+    /// Whether any byte of the access falls in the range
+    pub fn matches(&self, access: &BusAccess) -> bool {
+        let last = access.addr + access.size as Address - 1;
+        self.kind.matches(access.write) && access.addr <= self.hi && last >= self.lo
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write![f, "{:#010x}-{:#010x} {}", self.lo, self.hi, self.kind]
+    }
+}
+
+/// The first access that hit a watchpoint since the hit was last taken
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WatchHit {
+    /// Into Memory::watchpoints()
+    pub index: usize,
+    /// The access
+    pub access: BusAccess,
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write![f, "watchpoint {}: {}", self.index, self.access]
+    }
+}
+
+/// When a watch callback runs: before the access reaches the bus, seeing
+/// the value being written or about to be read, or after it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchTiming {
+    /// Before the access
+    Before,
+    /// After the access
+    After,
+}
+
+/// Identifies a callback added with Memory::add_watch
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct WatchId(u32);
+
+/// Called with each matching access
+pub type WatchCallback = Box<dyn FnMut(WatchTiming, &BusAccess)>;
+
+struct Watch {
+    id: WatchId,
+    range: Watchpoint,
+    callback: WatchCallback,
+}
+
+/// Callbacks on CPU accesses to ranges of addresses. Unlike watchpoints,
+/// which only record that one was hit, these run on every matching access.
+#[derive(Default)]
+pub struct Watches {
+    next_id: u32,
+    watches: Vec<Watch>,
+}
+
+impl Watches {
+    /// None if range is empty, since nothing could ever match it
+    pub fn add(&mut self, range: Range<Address>, kind: WatchKind,
+               callback: WatchCallback) -> Option<WatchId> {
+        if range.start >= range.end {
+            return None;
+        }
+        let id = WatchId(self.next_id);
+        self.next_id += 1;
+        self.watches.push(Watch {
+            id,
+            range: Watchpoint::new(range.start, range.end - range.start, kind),
+            callback,
+        });
+        Some(id)
+    }
+
+    /// Returns whether there was a callback with that id
+    pub fn remove(&mut self, id: WatchId) -> bool {
+        let len = self.watches.len();
+        self.watches.retain(|w| w.id != id);
+        self.watches.len() != len
+    }
+
+    /// Whether there are no callbacks
+    pub fn is_empty(&self) -> bool {
+        self.watches.is_empty()
+    }
+
+    /// Run every callback watching the access, in the order they were added
+    pub fn run(&mut self, timing: WatchTiming, access: &BusAccess) {
+        for watch in self.watches.iter_mut().filter(|w| w.range.matches(access)) {
+            (watch.callback)(timing, access);
+        }
+    }
+}
+
+impl fmt::Debug for Watches {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.watches.iter().map(|w| &w.range)).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::rc::Rc;
+    use core::cell::RefCell;
+    use gba_mem::Memory;
+
+    #[test]
+    fn watch_runs_before_and_after_matching_accesses() {
+        let mut mem = Memory::blank();
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let log = seen.clone();
+        let id = mem.add_watch(0x02000010..0x02000014, WatchKind::Write, move |timing, access| {
+            log.borrow_mut().push((timing, access.addr, access.val));
+        }).unwrap();
+        assert_eq!(mem.add_watch(0x02000010..0x02000010, WatchKind::Write, |_, _| {}), None);
+
+        mem.write32::<u32>(0x02000010, 0xdeadbeef);
+        mem.write8::<u8>(0x02000020, 1);
+        mem.read::<u32>(0x02000010);
+        assert_eq!(*seen.borrow(), vec![(WatchTiming::Before, 0x02000010, 0xdeadbeef),
+                                        (WatchTiming::After, 0x02000010, 0xdeadbeef)]);
+
+        assert!(mem.remove_watch(id));
+        assert!(!mem.remove_watch(id));
+        mem.write8::<u8>(0x02000011, 1);
+        assert_eq!(seen.borrow().len(), 2);
+    }
+}
@@ -0,0 +1,375 @@
+/// Sending netplay packets to the other player
+pub mod transport;
+
+use std::collections::VecDeque;
+
+use gba_config::NetplayConfig;
+use gba_error::{GbaError, GbaResult};
+use gba_ppu::FRAME_CYCLES;
+use gba_sio::local::LocalCable;
+use gba_state::audit::{self, Divergence};
+use gba_state::{SaveState, StateReader, StateWriter};
+use gba_system::Gba;
+
+use self::transport::Transport;
+
+// Rollback netplay for two player link cable games, after GGPO. Link
+// transfers are far too frequent and too tightly timed to wait on the
+// network for, so every peer runs both consoles, joined by a LocalCable,
+// and only the players' input is sent. A frame is run as soon as the local
+// input for it is known, guessing the other player repeats their last
+// input. When their real input arrives and differs, the machines are put
+// back to the frame it was for and run forward again.
+//
+// Input is delayed a few frames before the game sees it, which gives it
+// time to arrive and makes rollbacks rarer. Every so often each peer sends
+// a hash of a frame both have the real input for; if the hashes differ the
+// peers have desynced, which determinism audits are for tracking down.
+//
+// Both peers must start from the same state: the same ROM, BIOS and
+// settings, freshly booted.
+
+// Packets: "N" | ack: u32 | start: u32 | count: u32 | input: u16... |
+//          has hash: u8 | hash frame: u32 | hash: u64
+//
+// ack is how many of the other player's frames of input have arrived, and
+// the input is this player's from start on.
+const PACKET_TAG: u8 = b'N';
+const MAX_PACKET_INPUTS: usize = 256;
+
+// Both consoles are run a scanline at a time in turn, so transfers
+// between them see the same timing on every peer
+const SLICE_CYCLES: u64 = FRAME_CYCLES as u64 / 228;
+
+fn set_keys(gba: &mut Gba, keys: u16) {
+    let io = gba.mem_mut().io_mut();
+    io.keypad.set_held(keys, &mut io.irq);
+}
+
+/// One peer's side of a rollback netplay game
+#[derive(Debug)]
+pub struct RollbackSession {
+    consoles: [Gba; 2],
+    cable: LocalCable,
+    player: usize, // Which console this peer plays
+    transport: Box<dyn Transport>,
+    config: NetplayConfig,
+    frame: u32, // Next to run
+    local: Vec<u16>, // This player's input by frame, including the delay
+    remote: Vec<u16>, // The other player's, as far as it has arrived
+    used: Vec<u16>, // What was run with for the other player, real or guessed
+    confirmed: u32, // Frames run with the other player's real input
+    peer_ack: usize, // Frames of our input the other player has
+    snapshots: VecDeque<(u32, Vec<u8>)>, // Both machines before each unconfirmed frame
+    local_hash: Option<(u32, u64)>, // Latest of ours, sent with every packet
+    remote_hashes: VecDeque<(u32, u64)>,
+    hashes: VecDeque<(u32, u64)>, // Ours, waiting for the other player's
+    desync: Option<Divergence>,
+    rollbacks: u64,
+}
+
+impl RollbackSession {
+    /// Join two freshly booted consoles with a cable and play one of them,
+    /// player 0 being the link's parent
+    pub fn new(mut consoles: [Gba; 2], player: usize, transport: Box<dyn Transport>,
+               config: &NetplayConfig) -> GbaResult<RollbackSession> {
+        if player > 1 {
+            return Err(GbaError::InvalidConfig(format!("no netplay player {}", player)));
+        }
+        let cable = LocalCable::new();
+        for (i, gba) in consoles.iter_mut().enumerate() {
+            gba.set_link_device(Box::new(cable.end(i)));
+        }
+        Ok(RollbackSession {
+            consoles,
+            cable,
+            player,
+            transport,
+            config: *config,
+            frame: 0,
+            local: Vec::new(),
+            remote: Vec::new(),
+            used: Vec::new(),
+            confirmed: 0,
+            peer_ack: 0,
+            snapshots: VecDeque::new(),
+            local_hash: None,
+            remote_hashes: VecDeque::new(),
+            hashes: VecDeque::new(),
+            desync: None,
+            rollbacks: 0,
+        })
+    }
+
+    /// This peer's player, 0 or 1
+    pub fn player(&self) -> usize {
+        self.player
+    }
+
+    /// The console player is playing on
+    pub fn console(&self, player: usize) -> &Gba {
+        &self.consoles[player]
+    }
+
+    /// The console this peer shows
+    pub fn local_console(&self) -> &Gba {
+        &self.consoles[self.player]
+    }
+
+    /// Frames run
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    /// Frames run with both players' real input, which no rollback can undo
+    pub fn confirmed_frame(&self) -> u32 {
+        self.confirmed
+    }
+
+    /// Times the consoles have been rolled back
+    pub fn rollbacks(&self) -> u64 {
+        self.rollbacks
+    }
+
+    /// The first hashed frame the peers disagreed on
+    pub fn desync(&self) -> Option<Divergence> {
+        self.desync
+    }
+
+    /// Run the next frame with this player's input, a KEYINPUT mask with
+    /// pressed buttons set. Returns false, without using the input, if this
+    /// peer is as far ahead of the other as rollback allows; the frontend
+    /// should try again next frame.
+    pub fn advance_frame(&mut self, keys: u16) -> GbaResult<bool> {
+        self.poll()?;
+        if self.frame >= self.remote.len() as u32 + self.config.max_rollback {
+            self.send();
+            return Ok(false);
+        }
+
+        let delay = self.config.input_delay as usize;
+        while self.local.len() < self.frame as usize + delay {
+            self.local.push(0);
+        }
+        self.local.push(keys);
+        self.send();
+
+        let frame = self.frame;
+        self.run_frame(frame)?;
+        self.confirm()?;
+        Ok(true)
+    }
+
+    fn send(&mut self) {
+        let end = self.local.len().min(self.peer_ack + MAX_PACKET_INPUTS);
+        let mut w = StateWriter::new();
+        w.write_u8(PACKET_TAG);
+        w.write_u32(self.remote.len() as u32);
+        w.write_u32(self.peer_ack as u32);
+        w.write_u32((end - self.peer_ack) as u32);
+        for &keys in self.local[self.peer_ack..end].iter() {
+            w.write_u16(keys);
+        }
+        w.write_u8(self.local_hash.is_some() as u8);
+        let (hash_frame, hash) = self.local_hash.unwrap_or((0, 0));
+        w.write_u32(hash_frame);
+        w.write_u64(hash);
+        self.transport.send(&w.into_bytes());
+    }
+
+    fn receive(&mut self, packet: &[u8]) -> GbaResult<()> {
+        let mut r = StateReader::new(packet);
+        if r.read_u8()? != PACKET_TAG {
+            return Err(GbaError::InvalidSaveState("not a netplay packet".to_string()));
+        }
+        let ack = r.read_u32()? as usize;
+        let start = r.read_u32()? as usize;
+        let count = r.read_u32()? as usize;
+        for i in start..start + count {
+            let keys = r.read_u16()?;
+            // Anything past a lost packet waits for it to be resent
+            if i == self.remote.len() {
+                self.remote.push(keys);
+            }
+        }
+        let has_hash = r.read_u8()? != 0;
+        let hash_frame = r.read_u32()?;
+        let hash = r.read_u64()?;
+
+        self.peer_ack = self.peer_ack.max(ack.min(self.local.len()));
+        if has_hash && self.remote_hashes.back().is_none_or(|&(f, _)| f < hash_frame) {
+            self.remote_hashes.push_back((hash_frame, hash));
+        }
+        Ok(())
+    }
+
+    fn poll(&mut self) -> GbaResult<()> {
+        while let Some(packet) = self.transport.recv() {
+            if let Err(e) = self.receive(&packet) {
+                warn!(target: "debug", "ignoring netplay packet: {}", e);
+            }
+        }
+
+        // Run again from the first frame that guessed the other player
+        // wrong
+        let known = (self.remote.len() as u32).min(self.frame);
+        let wrong = (self.confirmed..known).find(|&f| self.used[f as usize] != self.remote[f as usize]);
+        if let Some(wrong) = wrong {
+            let end = self.frame;
+            self.restore(wrong)?;
+            for frame in wrong..end {
+                self.run_frame(frame)?;
+            }
+            self.rollbacks += 1;
+        }
+        self.confirm()
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        for gba in self.consoles.iter() {
+            w.write_bytes(&gba.save_state());
+        }
+        self.cable.save_state(&mut w);
+        w.into_bytes()
+    }
+
+    fn restore(&mut self, frame: u32) -> GbaResult<()> {
+        while self.snapshots.back().is_some_and(|&(f, _)| f > frame) {
+            self.snapshots.pop_back();
+        }
+        let state = match self.snapshots.pop_back() {
+            Some((f, state)) if f == frame => state,
+            _ => return Err(GbaError::InvalidSaveState(
+                format!("no netplay snapshot of frame {}", frame))),
+        };
+        let mut r = StateReader::new(&state);
+        for gba in self.consoles.iter_mut() {
+            gba.load_state(r.read_block()?)?;
+        }
+        self.cable.load_state(&mut r)?;
+        self.frame = frame;
+        self.used.truncate(frame as usize);
+        Ok(())
+    }
+
+    fn run_frame(&mut self, frame: u32) -> GbaResult<()> {
+        let snapshot = self.snapshot();
+        self.snapshots.push_back((frame, snapshot));
+
+        let remote = match self.remote.get(frame as usize) {
+            Some(&keys) => keys,
+            None => self.remote.last().cloned().unwrap_or(0),
+        };
+        self.used.push(remote);
+        let local = self.local[frame as usize];
+        let keys = if self.player == 0 { [local, remote] } else { [remote, local] };
+        for (gba, &keys) in self.consoles.iter_mut().zip(keys.iter()) {
+            set_keys(gba, keys);
+            gba.begin_frame();
+        }
+
+        let mut cycles = 0;
+        while cycles < FRAME_CYCLES as u64 {
+            for gba in self.consoles.iter_mut() {
+                gba.run_cycles(SLICE_CYCLES);
+            }
+            cycles += SLICE_CYCLES;
+        }
+        self.frame = frame + 1;
+        Ok(())
+    }
+
+    // Move past frames run with real input on both sides, hashing the
+    // state at each interval for the other player to check
+    fn confirm(&mut self) -> GbaResult<()> {
+        let confirmed = (self.remote.len() as u32).min(self.frame);
+        let interval = self.config.hash_interval;
+        for frame in self.confirmed + 1..confirmed + 1 {
+            if interval == 0 || frame % interval != 0 {
+                continue;
+            }
+            let hash = match self.snapshots.iter().find(|&&(f, _)| f == frame) {
+                Some((_, state)) => audit::state_hash(state),
+                None => audit::state_hash(&self.snapshot()),
+            };
+            self.local_hash = Some((frame, hash));
+            self.hashes.push_back((frame, hash));
+        }
+        self.confirmed = confirmed;
+        while self.snapshots.front().is_some_and(|&(f, _)| f < confirmed) {
+            self.snapshots.pop_front();
+        }
+        self.check_hashes();
+        Ok(())
+    }
+
+    fn check_hashes(&mut self) {
+        while let (Some(&(local_frame, local)), Some(&(remote_frame, remote))) =
+                (self.hashes.front(), self.remote_hashes.front()) {
+            if local_frame < remote_frame {
+                self.hashes.pop_front();
+            }
+            else if remote_frame < local_frame {
+                self.remote_hashes.pop_front();
+            }
+            else {
+                if local != remote && self.desync.is_none() {
+                    let desync = Divergence {
+                        frame: local_frame as u64,
+                        expected: Some(remote),
+                        actual: Some(local),
+                    };
+                    warn!(target: "debug", "netplay desync: {}", desync);
+                    self.desync = Some(desync);
+                }
+                self.hashes.pop_front();
+                self.remote_hashes.pop_front();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::transport::ChannelTransport;
+    use gba_system::builder::GbaBuilder;
+
+    fn consoles() -> [Gba; 2] {
+        let rom = vec![0; 0x400];
+        [GbaBuilder::new().rom(&rom).build().unwrap(), GbaBuilder::new().rom(&rom).build().unwrap()]
+    }
+
+    #[test]
+    fn peers_stay_in_sync_through_rollbacks() {
+        let config = NetplayConfig { input_delay: 1, max_rollback: 2, hash_interval: 2 };
+        let (a, b) = ChannelTransport::pair();
+        let mut host = RollbackSession::new(consoles(), 0, Box::new(a), &config).unwrap();
+        let mut guest = RollbackSession::new(consoles(), 1, Box::new(b), &config).unwrap();
+
+        // The guest runs ahead, guessing the host's input, as far as it may
+        let mut ahead = 0;
+        while guest.advance_frame(0x10).unwrap() {
+            ahead += 1;
+        }
+        assert_eq!(ahead, config.max_rollback);
+
+        for keys in 0..4 {
+            host.advance_frame(keys + 1).unwrap();
+            guest.advance_frame(0x10).unwrap();
+        }
+        while host.frame() < guest.frame() {
+            host.advance_frame(0).unwrap();
+        }
+        host.poll().unwrap();
+        guest.poll().unwrap();
+
+        assert!(guest.rollbacks() > 0);
+        assert_eq!(host.confirmed_frame(), guest.confirmed_frame());
+        assert!(host.local_hash.is_some());
+        assert_eq!(host.local_hash, guest.local_hash);
+        assert_eq!(host.desync(), None);
+        assert_eq!(guest.desync(), None);
+    }
+}
@@ -0,0 +1,94 @@
+use std::fmt;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use gba_error::{GbaError, GbaResult};
+
+/// Carries netplay packets to the other player. Packets may be lost or
+/// arrive out of order; the session resends anything not acknowledged.
+pub trait Transport: fmt::Debug {
+    /// Send packet, or drop it
+    fn send(&mut self, packet: &[u8]);
+
+    /// The next packet that has arrived, without waiting for one
+    fn recv(&mut self) -> Option<Vec<u8>>;
+}
+
+// Large enough for a packet carrying a full rollback window of input
+const MAX_PACKET: usize = 1024;
+
+/// Packets over UDP to a peer at a known address
+#[derive(Debug)]
+pub struct UdpTransport {
+    socket: UdpSocket,
+    peer: SocketAddr,
+}
+
+impl UdpTransport {
+    /// Bind to local and send to peer
+    pub fn new<A: ToSocketAddrs, B: ToSocketAddrs>(local: A, peer: B) -> GbaResult<UdpTransport> {
+        let socket = UdpSocket::bind(local)?;
+        socket.set_nonblocking(true)?;
+        let peer = peer.to_socket_addrs()?.next().ok_or_else(|| GbaError::Io(
+            io::Error::new(io::ErrorKind::InvalidInput, "no address for the other player")))?;
+        Ok(UdpTransport { socket, peer })
+    }
+
+    /// Where the socket is bound
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.socket.local_addr().ok()
+    }
+}
+
+impl Transport for UdpTransport {
+    fn send(&mut self, packet: &[u8]) {
+        if let Err(e) = self.socket.send_to(packet, self.peer) {
+            debug!(target: "debug", "netplay packet not sent: {}", e);
+        }
+    }
+
+    fn recv(&mut self) -> Option<Vec<u8>> {
+        let mut buf = [0; MAX_PACKET];
+        loop {
+            match self.socket.recv_from(&mut buf) {
+                // Anyone else sending to the port is ignored
+                Ok((len, from)) if from == self.peer => return Some(buf[..len].to_vec()),
+                Ok(_) => continue,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return None,
+                Err(e) => {
+                    debug!(target: "debug", "netplay packet not received: {}", e);
+                    return None;
+                },
+            }
+        }
+    }
+}
+
+/// Two ends connected in the same process, for tests and for running both
+/// players side by side
+#[derive(Debug)]
+pub struct ChannelTransport {
+    tx: Sender<Vec<u8>>,
+    rx: Receiver<Vec<u8>>,
+}
+
+impl ChannelTransport {
+    /// Two ends joined to each other
+    pub fn pair() -> (ChannelTransport, ChannelTransport) {
+        let (tx_a, rx_a) = channel();
+        let (tx_b, rx_b) = channel();
+        (ChannelTransport { tx: tx_a, rx: rx_b }, ChannelTransport { tx: tx_b, rx: rx_a })
+    }
+}
+
+impl Transport for ChannelTransport {
+    fn send(&mut self, packet: &[u8]) {
+        // The other end hanging up looks the same as losing the packet
+        let _ = self.tx.send(packet.to_vec());
+    }
+
+    fn recv(&mut self) -> Option<Vec<u8>> {
+        self.rx.try_recv().ok()
+    }
+}
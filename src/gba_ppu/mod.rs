@@ -0,0 +1,541 @@
+/// The scanline compositor
+pub mod render;
+
+use core::fmt;
+
+use gba_error::GbaResult;
+use gba_irq::{Interrupt, IrqController};
+use gba_ppu::render::{BgRegs, VideoMem};
+use gba_state::{SaveState, StateReader, StateWriter};
+use prelude::*;
+
+/// LCD timing from:
+/// http://problemkaputt.de/gbatek.htm#lcddimensionsandtimings
+pub const SCREEN_WIDTH:    usize = 240;
+/// Lines on the LCD
+pub const SCREEN_HEIGHT:   usize = 160;
+/// 240 dots * 4 cycles
+pub const HDRAW_CYCLES:    u32 = 960;
+/// 68 dots * 4 cycles
+pub const HBLANK_CYCLES:   u32 = 272;
+/// 1232
+pub const SCANLINE_CYCLES: u32 = HDRAW_CYCLES + HBLANK_CYCLES;
+/// Lines drawn each frame
+pub const VISIBLE_LINES:   u16 = 160;
+/// Lines of V-Blank after them
+pub const VBLANK_LINES:    u16 = 68;
+/// 228
+pub const TOTAL_LINES:     u16 = VISIBLE_LINES + VBLANK_LINES;
+/// 280896
+pub const FRAME_CYCLES:    u32 = SCANLINE_CYCLES * TOTAL_LINES as u32;
+
+/// Refresh rate of the LCD: 16.78MHz / 280896 cycles per frame
+pub const REFRESH_RATE: f64 = 16777216.0 / FRAME_CYCLES as f64; // ~59.73Hz
+
+// DISPSTAT bits from:
+// http://problemkaputt.de/gbatek.htm#lcdiodisplaystatus
+const DISPSTAT_VBLANK:     u16 = 0x0001; // V-Blank flag (read only)
+const DISPSTAT_HBLANK:     u16 = 0x0002; // H-Blank flag (read only)
+const DISPSTAT_VCOUNT:     u16 = 0x0004; // V-Counter flag (read only)
+const DISPSTAT_VBLANK_IRQ: u16 = 0x0008;
+const DISPSTAT_HBLANK_IRQ: u16 = 0x0010;
+const DISPSTAT_VCOUNT_IRQ: u16 = 0x0020;
+/// DISPSTAT bits a game can write: the IRQ enables and LYC
+pub const DISPSTAT_WRITE_MASK: u16 = 0xFF38;
+
+/// DISPCNT and BGxCNT bits from:
+/// http://problemkaputt.de/gbatek.htm#lcdiodisplaycontrol
+/// http://problemkaputt.de/gbatek.htm#lcdiobgcontrol
+pub const NUM_BACKGROUNDS:       usize = 4;
+/// The CGB mode bit is for the BIOS only
+pub const DISPCNT_WRITE_MASK:    u16 = 0xFFF7;
+/// BGCNT bits a game can write on BG2 and BG3
+pub const BGCNT_WRITE_MASK:      u16 = 0xFFFF;
+/// No wraparound bit on BG0 and BG1
+pub const BGCNT_TEXT_WRITE_MASK: u16 = 0xDFFF;
+
+// Layer enables in DISPCNT, BG0 to BG3 then OBJ
+const DISPCNT_LAYER_SHIFT: u16 = 8;
+const DISPCNT_LAYERS:      u16 = 0x1F00;
+
+// The V-Blank flag is set on lines 160..226 but not on the final line
+const VBLANK_FLAG_END: u16 = TOTAL_LINES - 1;
+
+/// Things that happened while the PPU was advanced
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PpuEvents {
+    /// H-Blank started
+    pub hblank: bool,
+    /// V-Blank started
+    pub vblank: bool,
+    /// VCOUNT reached LYC
+    pub vcount_match: bool,
+    /// A full frame is ready to be presented
+    pub frame_complete: bool,
+}
+
+/// Finished picture in the GBA's native 15-bit BGR format
+#[derive(Clone)]
+pub struct FrameBuffer {
+    pixels: Vec<u16>,
+}
+
+impl FrameBuffer {
+    /// Pixels, left to right then top to bottom
+    pub fn pixels(&self) -> &[u16] {
+        &self.pixels
+    }
+
+    /// Pixels, for writing
+    pub fn pixels_mut(&mut self) -> &mut [u16] {
+        &mut self.pixels
+    }
+
+    /// One line's pixels, for writing
+    pub fn line_mut(&mut self, line: usize) -> &mut [u16] {
+        &mut self.pixels[line * SCREEN_WIDTH..(line + 1) * SCREEN_WIDTH]
+    }
+}
+
+impl Default for FrameBuffer {
+    fn default() -> FrameBuffer {
+        FrameBuffer {
+            pixels: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+        }
+    }
+}
+
+impl SaveState for FrameBuffer {
+    fn save_state(&self, w: &mut StateWriter) {
+        for &pixel in self.pixels.iter() {
+            w.write_u16(pixel);
+        }
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> GbaResult<()> {
+        for pixel in self.pixels.iter_mut() {
+            *pixel = r.read_u16()?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for FrameBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write![f, "FrameBuffer {{ {}x{} }}", SCREEN_WIDTH, SCREEN_HEIGHT]
+    }
+}
+
+/// What the compositor draws, in the order DISPCNT gives their enable bits
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layer {
+    /// Background 0
+    Bg0,
+    /// Background 1
+    Bg1,
+    /// Background 2
+    Bg2,
+    /// Background 3
+    Bg3,
+    /// Sprites
+    Obj,
+}
+
+/// Every layer, in the order DISPCNT gives their enable bits
+pub const LAYERS: [Layer; 5] = [Layer::Bg0, Layer::Bg1, Layer::Bg2, Layer::Bg3, Layer::Obj];
+
+impl Layer {
+    /// The layer called name, e.g. "bg2" or "obj"
+    pub fn from_name(name: &str) -> Option<Layer> {
+        LAYERS.iter().cloned().find(|layer| layer.name() == name)
+    }
+
+    /// The layer's name, as the hotkeys and command line use it
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Layer::Bg0 => "bg0",
+            Layer::Bg1 => "bg1",
+            Layer::Bg2 => "bg2",
+            Layer::Bg3 => "bg3",
+            Layer::Obj => "obj",
+        }
+    }
+
+    // The layer's enable bit in DISPCNT
+    fn bit(&self) -> u16 {
+        1 << (DISPCNT_LAYER_SHIFT + *self as u16)
+    }
+}
+
+impl fmt::Display for Layer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write![f, "{}", self.name()]
+    }
+}
+
+/// The LCD controller: its registers, timing and the picture it draws
+#[derive(Clone, Debug, Default)]
+pub struct Ppu {
+    framebuffer: FrameBuffer,
+    dispcnt: u16,
+    bgcnt: [u16; NUM_BACKGROUNDS],
+    dispstat: u16,
+    vcount: u16,
+    cycle: u32, // Cycle within the current scanline
+    frame: u64,
+    hidden: u16, // Layers the host has turned off, as DISPCNT bits
+    line_due: bool, // The current line has reached H-Blank but isn't drawn
+}
+
+// Hidden layers belong to the host, like muted sound channels, so they
+// aren't saved in states. A line due to be drawn is drawn before the state
+// can be saved.
+impl_save_state!(Ppu { framebuffer, dispcnt, bgcnt, dispstat, vcount, cycle, frame });
+impl_serde_via_state!(Ppu);
+
+bitfield!(Ppu {
+    dispcnt => pub bg_mode: u16 [0, 3];
+    dispstat => pub lyc: u16 [8, 8]; // VCount setting
+});
+
+impl Ppu {
+    /// Advance the LCD by a number of CPU cycles, raising any enabled
+    /// DISPSTAT interrupts.
+    pub fn step(&mut self, cycles: u32, irq: &mut IrqController) -> PpuEvents {
+        let mut events = PpuEvents::default();
+        let mut remaining = cycles;
+
+        while remaining > 0 {
+            let boundary = if self.cycle < HDRAW_CYCLES {
+                HDRAW_CYCLES
+            }
+            else {
+                SCANLINE_CYCLES
+            };
+            let adv = ::core::cmp::min(remaining, boundary - self.cycle);
+            self.cycle += adv;
+            remaining -= adv;
+
+            if self.cycle == HDRAW_CYCLES {
+                self.enter_hblank(irq, &mut events);
+            }
+            else if self.cycle == SCANLINE_CYCLES {
+                self.cycle = 0;
+                self.next_line(irq, &mut events);
+            }
+        }
+
+        events
+    }
+
+    /// Cycles until the next H-Blank or line starts
+    pub fn cycles_to_event(&self) -> u32 {
+        if self.cycle < HDRAW_CYCLES {
+            HDRAW_CYCLES - self.cycle
+        }
+        else {
+            SCANLINE_CYCLES - self.cycle
+        }
+    }
+
+    fn enter_hblank(&mut self, irq: &mut IrqController, events: &mut PpuEvents) {
+        self.dispstat |= DISPSTAT_HBLANK;
+        self.line_due = self.vcount < VISIBLE_LINES;
+        events.hblank = true;
+        if self.dispstat & DISPSTAT_HBLANK_IRQ != 0 {
+            irq.request(Interrupt::HBlank);
+        }
+    }
+
+    fn next_line(&mut self, irq: &mut IrqController, events: &mut PpuEvents) {
+        self.dispstat &= !DISPSTAT_HBLANK;
+        self.vcount = (self.vcount + 1) % TOTAL_LINES;
+
+        if self.vcount == VISIBLE_LINES {
+            self.dispstat |= DISPSTAT_VBLANK;
+            self.frame += 1;
+            events.vblank = true;
+            events.frame_complete = true;
+            if self.dispstat & DISPSTAT_VBLANK_IRQ != 0 {
+                irq.request(Interrupt::VBlank);
+            }
+        }
+        else if self.vcount == VBLANK_FLAG_END {
+            self.dispstat &= !DISPSTAT_VBLANK;
+        }
+
+        self.update_vcount_match(irq, events);
+    }
+
+    fn update_vcount_match(&mut self, irq: &mut IrqController, events: &mut PpuEvents) {
+        if self.vcount == self.lyc() {
+            self.dispstat |= DISPSTAT_VCOUNT;
+            events.vcount_match = true;
+            if self.dispstat & DISPSTAT_VCOUNT_IRQ != 0 {
+                irq.request(Interrupt::VCount);
+            }
+        }
+        else {
+            self.dispstat &= !DISPSTAT_VCOUNT;
+        }
+    }
+
+    /// DISPCNT
+    pub fn dispcnt(&self) -> u16 {
+        self.dispcnt
+    }
+
+    /// Write DISPCNT
+    pub fn set_dispcnt(&mut self, val: u16) {
+        if val & 7 > 5 {
+            warn!(target: "ppu", "DISPCNT selects BG mode {}, which doesn't exist", val & 7);
+        }
+        self.dispcnt = (self.dispcnt & !DISPCNT_WRITE_MASK) | (val & DISPCNT_WRITE_MASK);
+    }
+
+    /// The DISPCNT layer enables the compositor obeys: the game's, less any
+    /// the host has hidden
+    pub fn layers_enabled(&self) -> u16 {
+        self.dispcnt & DISPCNT_LAYERS & !self.hidden
+    }
+
+    /// Whether a layer is drawn: enabled by the game and not hidden
+    pub fn is_layer_enabled(&self, layer: Layer) -> bool {
+        self.layers_enabled() & layer.bit() != 0
+    }
+
+    /// Whether the host has hidden a layer
+    pub fn is_layer_hidden(&self, layer: Layer) -> bool {
+        self.hidden & layer.bit() != 0
+    }
+
+    /// Hide a layer from the compositor, or show it again
+    pub fn set_layer_hidden(&mut self, layer: Layer, hidden: bool) {
+        if hidden {
+            self.hidden |= layer.bit();
+        }
+        else {
+            self.hidden &= !layer.bit();
+        }
+    }
+
+    /// Returns whether the layer is now hidden
+    pub fn toggle_layer(&mut self, layer: Layer) -> bool {
+        self.hidden ^= layer.bit();
+        self.is_layer_hidden(layer)
+    }
+
+    /// Show every layer the game enables
+    pub fn show_all_layers(&mut self) {
+        self.hidden = 0;
+    }
+
+    /// Whether a line has reached H-Blank without being drawn yet
+    pub fn is_line_due(&self) -> bool {
+        self.line_due
+    }
+
+    /// Draw the line that has reached H-Blank into the framebuffer, if it
+    /// hasn't been drawn yet
+    pub fn render_due_line(&mut self, regs: &BgRegs, video: &VideoMem) {
+        if !::core::mem::replace(&mut self.line_due, false) {
+            return;
+        }
+        let line = self.vcount as usize;
+        let (dispcnt, bgcnt, enabled) = (self.dispcnt, self.bgcnt, self.layers_enabled());
+        render::render_line(line, dispcnt, &bgcnt, regs, enabled, video,
+                            self.framebuffer.line_mut(line));
+    }
+
+    /// BGxCNT of background bg
+    pub fn bgcnt(&self, bg: usize) -> u16 {
+        self.bgcnt[bg]
+    }
+
+    /// Write BGxCNT of background bg
+    pub fn set_bgcnt(&mut self, bg: usize, val: u16) {
+        let mask = if bg < 2 { BGCNT_TEXT_WRITE_MASK } else { BGCNT_WRITE_MASK };
+        self.bgcnt[bg] = val & mask;
+    }
+
+    /// DISPSTAT
+    pub fn dispstat(&self) -> u16 {
+        self.dispstat
+    }
+
+    /// Only the IRQ enables and LYC are writable; the status flags are kept
+    pub fn set_dispstat(&mut self, val: u16) {
+        self.dispstat = (self.dispstat & !DISPSTAT_WRITE_MASK) |
+                        (val & DISPSTAT_WRITE_MASK);
+        if self.vcount == self.lyc() {
+            self.dispstat |= DISPSTAT_VCOUNT;
+        }
+        else {
+            self.dispstat &= !DISPSTAT_VCOUNT;
+        }
+    }
+
+    /// Line being drawn
+    pub fn vcount(&self) -> u16 {
+        self.vcount
+    }
+
+    /// Cycle within the current scanline
+    pub fn cycle(&self) -> u32 {
+        self.cycle
+    }
+
+    /// Number of frames completed since power on
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Picture drawn by the PPU; complete once a frame has finished
+    pub fn framebuffer(&self) -> &FrameBuffer {
+        &self.framebuffer
+    }
+
+    /// Picture drawn by the PPU, for writing
+    pub fn framebuffer_mut(&mut self) -> &mut FrameBuffer {
+        &mut self.framebuffer
+    }
+
+    /// Whether the current line is in H-Blank
+    pub fn is_hblank(&self) -> bool { self.dispstat & DISPSTAT_HBLANK != 0 }
+    /// Whether the LCD is in V-Blank
+    pub fn is_vblank(&self) -> bool { self.dispstat & DISPSTAT_VBLANK != 0 }
+}
+
+impl fmt::Display for Ppu {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write![f, "PPU: line {:3} cycle {:4} DISPSTAT:{:#06x} frame {}",
+               self.vcount, self.cycle, self.dispstat, self.frame]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step_lines(ppu: &mut Ppu, irq: &mut IrqController, lines: u32) -> PpuEvents {
+        ppu.step(SCANLINE_CYCLES * lines, irq)
+    }
+
+    #[test]
+    fn scanline_is_1232_cycles_with_hblank_at_960() {
+        let mut ppu = Ppu::default();
+        let mut irq = IrqController::default();
+        assert_eq!(ppu.cycles_to_event(), HDRAW_CYCLES);
+
+        assert_eq!(ppu.step(HDRAW_CYCLES - 1, &mut irq), PpuEvents::default());
+        assert!(!ppu.is_hblank());
+        let events = ppu.step(1, &mut irq);
+        assert!(events.hblank && ppu.is_hblank());
+        assert_eq!((ppu.vcount(), ppu.cycle()), (0, 960));
+        assert_eq!(ppu.cycles_to_event(), HBLANK_CYCLES);
+
+        ppu.step(HBLANK_CYCLES - 1, &mut irq);
+        assert!(ppu.is_hblank());
+        assert_eq!(ppu.vcount(), 0);
+        ppu.step(1, &mut irq);
+        assert!(!ppu.is_hblank());
+        assert_eq!((ppu.vcount(), ppu.cycle()), (1, 0));
+    }
+
+    #[test]
+    fn vblank_covers_lines_160_to_226_and_wraps() {
+        let mut ppu = Ppu::default();
+        let mut irq = IrqController::default();
+        let events = step_lines(&mut ppu, &mut irq, 159);
+        assert!(!events.vblank && !ppu.is_vblank());
+
+        let events = step_lines(&mut ppu, &mut irq, 1);
+        assert_eq!(ppu.vcount(), 160);
+        assert!(events.vblank && ppu.is_vblank());
+
+        step_lines(&mut ppu, &mut irq, 66);
+        assert_eq!(ppu.vcount(), 226);
+        assert!(ppu.is_vblank());
+        step_lines(&mut ppu, &mut irq, 1);
+        assert_eq!(ppu.vcount(), 227);
+        assert!(!ppu.is_vblank());
+
+        step_lines(&mut ppu, &mut irq, 1);
+        assert_eq!((ppu.vcount(), ppu.cycle(), ppu.frame()), (0, 0, 1));
+    }
+
+    #[test]
+    fn vcount_matches_lyc() {
+        let mut ppu = Ppu::default();
+        let mut irq = IrqController::default();
+        ppu.set_dispstat(5 << 8 | DISPSTAT_VCOUNT_IRQ);
+        assert_eq!(ppu.dispstat() & DISPSTAT_VCOUNT, 0);
+
+        let events = step_lines(&mut ppu, &mut irq, 5);
+        assert!(events.vcount_match);
+        assert_ne!(ppu.dispstat() & DISPSTAT_VCOUNT, 0);
+        assert_eq!(irq.if_(), Interrupt::VCount as u16);
+
+        let events = step_lines(&mut ppu, &mut irq, 1);
+        assert!(!events.vcount_match);
+        assert_eq!(ppu.dispstat() & DISPSTAT_VCOUNT, 0);
+
+        // Writing LYC compares against the current line straight away
+        ppu.set_dispstat(6 << 8);
+        assert_ne!(ppu.dispstat() & DISPSTAT_VCOUNT, 0);
+    }
+
+    #[test]
+    fn irqs_need_their_dispstat_enables() {
+        let mut ppu = Ppu::default();
+        let mut irq = IrqController::default();
+        let events = step_lines(&mut ppu, &mut irq, TOTAL_LINES as u32);
+        assert!(events.hblank && events.vblank && events.vcount_match);
+        assert_eq!(irq.if_(), 0);
+
+        let enables = [
+            (DISPSTAT_VBLANK_IRQ, Interrupt::VBlank),
+            (DISPSTAT_HBLANK_IRQ, Interrupt::HBlank),
+            (DISPSTAT_VCOUNT_IRQ, Interrupt::VCount),
+        ];
+        for &(enable, interrupt) in enables.iter() {
+            let mut irq = IrqController::default();
+            ppu.set_dispstat(enable);
+            step_lines(&mut ppu, &mut irq, TOTAL_LINES as u32);
+            assert_eq!(irq.if_(), interrupt as u16);
+        }
+    }
+
+    #[test]
+    fn frame_completes_once_per_frame() {
+        let mut ppu = Ppu::default();
+        let mut irq = IrqController::default();
+        let mut frames = 0;
+        for _ in 0..3 * FRAME_CYCLES / 4 {
+            if ppu.step(4, &mut irq).frame_complete {
+                frames += 1;
+            }
+        }
+        assert_eq!(frames, 3);
+        assert_eq!(ppu.frame(), 3);
+    }
+
+    #[test]
+    fn hidden_layers_mask_dispcnt() {
+        let mut ppu = Ppu::default();
+        ppu.set_dispcnt(0x1500); // BG0, BG2 and OBJ
+        assert_eq!(ppu.layers_enabled(), 0x1500);
+
+        ppu.set_layer_hidden(Layer::Obj, true);
+        ppu.set_layer_hidden(Layer::Bg1, true);
+        assert_eq!(ppu.layers_enabled(), 0x0500);
+        assert!(!ppu.is_layer_enabled(Layer::Bg1));
+        assert_eq!(ppu.dispcnt(), 0x1500); // The game still sees its own
+
+        assert!(!ppu.toggle_layer(Layer::Obj));
+        assert!(ppu.is_layer_enabled(Layer::Obj));
+        ppu.show_all_layers();
+        assert!(LAYERS.iter().all(|&layer| !ppu.is_layer_hidden(layer)));
+        assert_eq!(Layer::from_name("bg3"), Some(Layer::Bg3));
+    }
+}
@@ -0,0 +1,432 @@
+// The scanline compositor: draws one line of the picture from video memory
+// as the LCD reaches its H-Blank. Formats from:
+// http://problemkaputt.de/gbatek.htm#lcdvrambgscreendataformatbgmap
+// http://problemkaputt.de/gbatek.htm#lcdobjoamattributes
+//
+// Backgrounds in every mode and sprites are drawn in priority order over
+// the backdrop. Windows, blending and mosaic aren't applied yet, and
+// affine backgrounds take their reference points from the registers as
+// written rather than from the counters the hardware steps each line.
+
+use gba_ppu::{Layer, LAYERS, NUM_BACKGROUNDS, SCREEN_HEIGHT, SCREEN_WIDTH};
+use prelude::*;
+
+const CHAR_BLOCK: usize = 0x4000;
+const SCREEN_BLOCK: usize = 0x800;
+const OBJ_TILES: usize = 0x10000; // VRAM offset of sprite tiles
+const OBJ_TILES_LEN: usize = 0x8000;
+const OBJ_PALETTE: usize = 256; // Sprite colors follow the 256 background ones
+const NUM_SPRITES: usize = 128;
+const BITMAP_FRAME: usize = 0xA000; // Offset of the second frame in modes 4 and 5
+
+const DISPCNT_FRAME:   u16 = 0x0010;
+const DISPCNT_OBJ_1D:  u16 = 0x0040;
+const DISPCNT_BLANK:   u16 = 0x0080; // Forced blank, the LCD shows white
+const BGCNT_8BPP:      u16 = 0x0080;
+const BGCNT_WRAP:      u16 = 0x2000; // Affine backgrounds repeat
+const WHITE:           u16 = 0x7FFF;
+
+// Sprite sizes in pixels by shape then size
+const SPRITE_SIZES: [[(i32, i32); 4]; 3] = [
+    [(8, 8), (16, 16), (32, 32), (64, 64)],  // Square
+    [(16, 8), (32, 8), (32, 16), (64, 32)],  // Wide
+    [(8, 16), (8, 32), (16, 32), (32, 64)],  // Tall
+];
+
+/// The memory the compositor draws from
+#[derive(Clone, Copy, Debug)]
+pub struct VideoMem<'a> {
+    /// Palette RAM
+    pub palette: &'a [u8],
+    /// Video RAM
+    pub vram: &'a [u8],
+    /// Object attributes
+    pub oam: &'a [u8],
+}
+
+impl<'a> VideoMem<'a> {
+    fn color(&self, entry: usize) -> u16 {
+        u16::from_le_bytes([self.palette[2 * entry], self.palette[2 * entry + 1]]) & WHITE
+    }
+
+    fn vram16(&self, offset: usize) -> u16 {
+        u16::from_le_bytes([self.vram[offset], self.vram[offset + 1]])
+    }
+
+    fn oam16(&self, offset: usize) -> u16 {
+        u16::from_le_bytes([self.oam[offset], self.oam[offset + 1]])
+    }
+
+    // A pixel's palette index in a tile, 0 being transparent
+    fn tile_pixel(&self, tile: usize, x: usize, y: usize, bpp8: bool) -> u8 {
+        if bpp8 {
+            self.vram.get(tile + 8 * y + x).cloned().unwrap_or(0)
+        }
+        else {
+            let byte = self.vram.get(tile + 4 * y + x / 2).cloned().unwrap_or(0);
+            if x & 1 == 0 { byte & 0xF } else { byte >> 4 }
+        }
+    }
+}
+
+/// The background registers the IO latches hold that the compositor reads
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BgRegs {
+    /// BGxHOFS
+    pub hofs: [u16; NUM_BACKGROUNDS],
+    /// BGxVOFS
+    pub vofs: [u16; NUM_BACKGROUNDS],
+    /// PA, PB, PC and PD of BG2 and BG3
+    pub affine: [[i16; 4]; 2],
+    /// X and Y of BG2 and BG3, with 8 fraction bits
+    pub origin: [(i32, i32); 2],
+}
+
+// A drawn pixel and what it would lose to: lower priorities and, at the
+// same priority, earlier layers are in front
+#[derive(Clone, Copy)]
+struct Pixel {
+    color: u16,
+    priority: u8,
+}
+
+/// Draw a line of the picture. Layers DISPCNT leaves off or the host has
+/// hidden are left out.
+pub fn render_line(line: usize, dispcnt: u16, bgcnt: &[u16; NUM_BACKGROUNDS], regs: &BgRegs,
+                   enabled: u16, video: &VideoMem, out: &mut [u16]) {
+    debug_assert!(line < SCREEN_HEIGHT);
+    if dispcnt & DISPCNT_BLANK != 0 {
+        for pixel in out.iter_mut() {
+            *pixel = WHITE;
+        }
+        return;
+    }
+
+    let is_enabled = |layer: Layer| enabled & (0x100 << layer as u16) != 0;
+    let mut bgs: Vec<(usize, Vec<Option<u16>>)> = Vec::new();
+    for (bg, &layer) in LAYERS[..NUM_BACKGROUNDS].iter().enumerate() {
+        if !is_enabled(layer) {
+            continue;
+        }
+        let pixels = match (dispcnt & 7, bg) {
+            (0, _) | (1, 0) | (1, 1) => text_line(bg, line, bgcnt[bg], regs, video),
+            (1, 2) | (2, 2) | (2, 3) => affine_line(bg, line, bgcnt[bg], regs, video),
+            (3, 2) | (4, 2) | (5, 2) => bitmap_line(dispcnt, line, regs, video),
+            _ => continue,
+        };
+        bgs.push((bg, pixels));
+    }
+    // Lower priority numbers are drawn in front, then lower BG numbers
+    bgs.sort_by_key(|&(bg, _)| (bgcnt[bg] & 3, bg));
+
+    let objs = if is_enabled(Layer::Obj) {
+        obj_line(dispcnt, line, video)
+    }
+    else {
+        vec![None; SCREEN_WIDTH]
+    };
+
+    let backdrop = video.color(0);
+    for (x, pixel) in out.iter_mut().enumerate().take(SCREEN_WIDTH) {
+        let bg = bgs.iter()
+            .filter_map(|&(bg, ref pixels)| pixels[x].map(|color| Pixel {
+                color,
+                priority: (bgcnt[bg] & 3) as u8,
+            }))
+            .next();
+        // Sprites go in front of backgrounds of the same priority
+        *pixel = match (objs[x], bg) {
+            (Some(obj), Some(bg)) if bg.priority < obj.priority => bg.color,
+            (Some(obj), _) => obj.color,
+            (None, Some(bg)) => bg.color,
+            (None, None) => backdrop,
+        };
+    }
+}
+
+// A tiled background, scrolled by its offsets
+fn text_line(bg: usize, line: usize, bgcnt: u16, regs: &BgRegs,
+             video: &VideoMem) -> Vec<Option<u16>> {
+    let size = (bgcnt >> 14) as usize;
+    let (width, height) = (256 << (size & 1), 256 << (size >> 1));
+    let char_base = ((bgcnt >> 2) & 3) as usize * CHAR_BLOCK;
+    let screen_base = ((bgcnt >> 8) & 0x1F) as usize * SCREEN_BLOCK;
+    let bpp8 = bgcnt & BGCNT_8BPP != 0;
+    let y = (line + regs.vofs[bg] as usize) % height;
+
+    (0..SCREEN_WIDTH).map(|sx| {
+        let x = (sx + regs.hofs[bg] as usize) % width;
+        // Maps bigger than 256 pixels are made of 32x32 tile blocks
+        let block = x / 256 + (y / 256) * (width / 256);
+        let entry = screen_base + block * SCREEN_BLOCK + ((y % 256) / 8 * 32 + (x % 256) / 8) * 2;
+        let entry = video.vram16(entry);
+        let (mut tx, mut ty) = (x % 8, y % 8);
+        if entry & 0x400 != 0 {
+            tx = 7 - tx;
+        }
+        if entry & 0x800 != 0 {
+            ty = 7 - ty;
+        }
+        let tile_bytes = if bpp8 { 64 } else { 32 };
+        let tile = char_base + (entry & 0x3FF) as usize * tile_bytes;
+        // Background tiles can't reach into the sprite tiles
+        if tile >= OBJ_TILES {
+            return None;
+        }
+        match video.tile_pixel(tile, tx, ty, bpp8) {
+            0 => None,
+            index if bpp8 => Some(video.color(index as usize)),
+            index => Some(video.color(16 * (entry >> 12) as usize + index as usize)),
+        }
+    }).collect()
+}
+
+// Where each pixel of the line samples a rotated and scaled background,
+// in whole pixels
+fn affine_coords(bg: usize, line: usize, regs: &BgRegs) -> impl Iterator<Item = (i32, i32)> {
+    let [pa, pb, pc, pd] = regs.affine[bg - 2];
+    let (ox, oy) = regs.origin[bg - 2];
+    let x0 = ox + line as i32 * pb as i32;
+    let y0 = oy + line as i32 * pd as i32;
+    (0..SCREEN_WIDTH as i32).map(move |sx| {
+        ((x0 + sx * pa as i32) >> 8, (y0 + sx * pc as i32) >> 8)
+    })
+}
+
+fn affine_line(bg: usize, line: usize, bgcnt: u16, regs: &BgRegs,
+               video: &VideoMem) -> Vec<Option<u16>> {
+    let size = 128 << (bgcnt >> 14);
+    let char_base = ((bgcnt >> 2) & 3) as usize * CHAR_BLOCK;
+    let screen_base = ((bgcnt >> 8) & 0x1F) as usize * SCREEN_BLOCK;
+    let wrap = bgcnt & BGCNT_WRAP != 0;
+
+    affine_coords(bg, line, regs).map(|(x, y)| {
+        let (x, y) = if wrap {
+            (x.rem_euclid(size), y.rem_euclid(size))
+        }
+        else if x < 0 || y < 0 || x >= size || y >= size {
+            return None;
+        }
+        else {
+            (x, y)
+        };
+        let (x, y) = (x as usize, y as usize);
+        let tiles = size as usize / 8;
+        let tile = video.vram[screen_base + (y / 8) * tiles + x / 8] as usize;
+        match video.tile_pixel(char_base + tile * 64, x % 8, y % 8, true) {
+            0 => None,
+            index => Some(video.color(index as usize)),
+        }
+    }).collect()
+}
+
+// BG2 in the bitmap modes: 15-bit color in mode 3, a 256 color frame in
+// mode 4 and a smaller 15-bit frame in mode 5, the last two double buffered
+fn bitmap_line(dispcnt: u16, line: usize, regs: &BgRegs, video: &VideoMem) -> Vec<Option<u16>> {
+    let mode = dispcnt & 7;
+    let frame = if mode != 3 && dispcnt & DISPCNT_FRAME != 0 { BITMAP_FRAME } else { 0 };
+    let (width, height) = if mode == 5 { (160, 128) } else { (240, 160) };
+
+    affine_coords(2, line, regs).map(|(x, y)| {
+        if x < 0 || y < 0 || x >= width || y >= height {
+            return None;
+        }
+        let pixel = (y * width + x) as usize;
+        match mode {
+            4 => match video.vram[frame + pixel] {
+                0 => None,
+                index => Some(video.color(index as usize)),
+            },
+            _ => Some(video.vram16(frame + 2 * pixel) & WHITE),
+        }
+    }).collect()
+}
+
+// The frontmost sprite pixel at each point of the line: the lowest
+// priority number, then the lowest OAM entry
+fn obj_line(dispcnt: u16, line: usize, video: &VideoMem) -> Vec<Option<Pixel>> {
+    let mut out: Vec<Option<Pixel>> = vec![None; SCREEN_WIDTH];
+    for n in 0..NUM_SPRITES {
+        let attr0 = video.oam16(8 * n);
+        let attr1 = video.oam16(8 * n + 2);
+        let attr2 = video.oam16(8 * n + 4);
+        let affine = attr0 & 0x100 != 0;
+        // Without affine, bit 9 hides the sprite. Mode 2 is the OBJ
+        // window, which isn't drawn, and mode 3 is prohibited.
+        if (!affine && attr0 & 0x200 != 0) || (attr0 >> 10) & 3 >= 2 {
+            continue;
+        }
+        let shape = (attr0 >> 14) as usize;
+        if shape == 3 {
+            continue;
+        }
+        let (w, h) = SPRITE_SIZES[shape][(attr1 >> 14) as usize];
+        // Double size affine sprites get twice the room to rotate in
+        let (bw, bh) = if affine && attr0 & 0x200 != 0 { (2 * w, 2 * h) } else { (w, h) };
+        let dy = (line as i32 - (attr0 & 0xFF) as i32).rem_euclid(256);
+        if dy >= bh {
+            continue;
+        }
+        let x0 = if attr1 & 0x100 != 0 { (attr1 & 0x1FF) as i32 - 512 } else { (attr1 & 0x1FF) as i32 };
+        let priority = ((attr2 >> 10) & 3) as u8;
+        let bpp8 = attr0 & 0x2000 != 0;
+        let tile_num = (attr2 & 0x3FF) as usize;
+        // 2D mapping lays sprite tiles out in a 32 tile wide sheet
+        let row_tiles = if dispcnt & DISPCNT_OBJ_1D != 0 {
+            (w as usize / 8) * if bpp8 { 2 } else { 1 }
+        }
+        else {
+            32
+        };
+        let params = if affine {
+            let group = 32 * ((attr1 >> 9) & 0x1F) as usize;
+            [0, 1, 2, 3].map(|i| video.oam16(group + 8 * i + 6) as i16 as i32)
+        }
+        else {
+            [0x100, 0, 0, 0x100]
+        };
+        let (hflip, vflip) = (!affine && attr1 & 0x1000 != 0, !affine && attr1 & 0x2000 != 0);
+
+        for dx in 0..bw {
+            let sx = x0 + dx;
+            if sx < 0 || sx >= SCREEN_WIDTH as i32 {
+                continue;
+            }
+            if let Some(front) = out[sx as usize] {
+                if front.priority <= priority {
+                    continue;
+                }
+            }
+            // Texture coordinates, rotated about the sprite's centre
+            let (cx, cy) = (dx - bw / 2, dy - bh / 2);
+            let mut tx = ((params[0] * cx + params[1] * cy) >> 8) + w / 2;
+            let mut ty = ((params[2] * cx + params[3] * cy) >> 8) + h / 2;
+            if tx < 0 || ty < 0 || tx >= w || ty >= h {
+                continue;
+            }
+            if hflip {
+                tx = w - 1 - tx;
+            }
+            if vflip {
+                ty = h - 1 - ty;
+            }
+            let (tx, ty) = (tx as usize, ty as usize);
+            let step = if bpp8 { 2 } else { 1 };
+            let tile = tile_num + (ty / 8) * row_tiles + (tx / 8) * step;
+            let offset = OBJ_TILES + (tile * 32) % OBJ_TILES_LEN;
+            let color = match video.tile_pixel(offset, tx % 8, ty % 8, bpp8) {
+                0 => continue,
+                index if bpp8 => video.color(OBJ_PALETTE + index as usize),
+                index => video.color(OBJ_PALETTE + 16 * (attr2 >> 12) as usize + index as usize),
+            };
+            out[sx as usize] = Some(Pixel { color, priority });
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Scene {
+        palette: Vec<u8>,
+        vram: Vec<u8>,
+        oam: Vec<u8>,
+    }
+
+    impl Scene {
+        fn new() -> Scene {
+            let mut oam = vec![0; 0x400];
+            // Hide every sprite
+            for n in 0..NUM_SPRITES {
+                oam[8 * n + 1] = 0x02;
+            }
+            Scene { palette: vec![0; 0x400], vram: vec![0; 0x18000], oam }
+        }
+
+        fn set_color(&mut self, entry: usize, color: u16) {
+            self.palette[2 * entry..2 * entry + 2].copy_from_slice(&color.to_le_bytes());
+        }
+
+        fn render(&self, dispcnt: u16, bgcnt: &[u16; 4], enabled: u16) -> Vec<u16> {
+            let video = VideoMem { palette: &self.palette, vram: &self.vram, oam: &self.oam };
+            let mut out = vec![0; SCREEN_WIDTH];
+            render_line(0, dispcnt, bgcnt, &identity(), enabled & dispcnt, &video, &mut out);
+            out
+        }
+    }
+
+    fn identity() -> BgRegs {
+        BgRegs { affine: [[0x100, 0, 0, 0x100]; 2], ..BgRegs::default() }
+    }
+
+    // BG0 showing 4bpp tile 1, filled with color 1, over the whole map
+    // at screen block 8, and a 16x16 sprite of color 2 at x=8
+    fn scene() -> Scene {
+        let mut scene = Scene::new();
+        scene.set_color(0, 0x001F);
+        scene.set_color(1, 0x03E0);
+        scene.set_color(OBJ_PALETTE + 2, 0x7C00);
+        for byte in scene.vram[32..64].iter_mut() {
+            *byte = 0x11;
+        }
+        for entry in 0..32 * 32 {
+            scene.vram[8 * SCREEN_BLOCK + 2 * entry] = 1;
+        }
+        for byte in scene.vram[OBJ_TILES..OBJ_TILES + 4 * 32].iter_mut() {
+            *byte = 0x22;
+        }
+        scene.oam[..6].copy_from_slice(&[0x00, 0x00, 0x08, 0x40, 0x00, 0x00]);
+        scene
+    }
+
+    #[test]
+    fn forced_blank_is_white() {
+        let out = scene().render(0x1180, &[0x0800; 4], 0x1F00);
+        assert!(out.iter().all(|&pixel| pixel == WHITE));
+    }
+
+    #[test]
+    fn backdrop_shows_without_layers() {
+        let out = scene().render(0x0000, &[0x0800; 4], 0x1F00);
+        assert!(out.iter().all(|&pixel| pixel == 0x001F));
+    }
+
+    #[test]
+    fn sprites_draw_over_backgrounds() {
+        let out = scene().render(0x1140, &[0x0800; 4], 0x1F00);
+        assert_eq!(out[0], 0x03E0);
+        assert_eq!(out[8], 0x7C00);
+        assert_eq!(out[23], 0x7C00);
+        assert_eq!(out[24], 0x03E0);
+
+        // Unless the background is in front
+        let mut behind = scene();
+        behind.oam[5] = 0x04; // Priority 1
+        let out = behind.render(0x1140, &[0x0800; 4], 0x1F00);
+        assert_eq!(out[8], 0x03E0);
+    }
+
+    #[test]
+    fn hidden_layers_are_left_out() {
+        let scene = scene();
+        let out = scene.render(0x1140, &[0x0800; 4], 0x1F00 & !(1 << 12));
+        assert_eq!(out[8], 0x03E0);
+        let out = scene.render(0x1140, &[0x0800; 4], 0x1F00 & !(1 << 8));
+        assert_eq!(out[0], 0x001F);
+        assert_eq!(out[8], 0x7C00);
+    }
+
+    #[test]
+    fn bitmap_modes() {
+        let mut scene = Scene::new();
+        scene.vram[..2].copy_from_slice(&0x1234u16.to_le_bytes());
+        assert_eq!(scene.render(0x0403, &[0; 4], 0x1F00)[0], 0x1234);
+
+        scene.set_color(5, 0x0ABC);
+        scene.vram[BITMAP_FRAME + 1] = 5;
+        let out = scene.render(0x0414, &[0; 4], 0x1F00);
+        assert_eq!((out[0], out[1]), (0, 0x0ABC));
+    }
+}
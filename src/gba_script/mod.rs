@@ -0,0 +1,416 @@
+// Lua scripting in the style of FCEUX and BizHawk: a script registers
+// callbacks for the start and end of each frame and for accesses to memory
+// ranges, and from inside them reads and writes memory, holds buttons and
+// draws over the frame. For example:
+//
+//     event.onframeend(function()
+//         gui.box(4, 4, 60, 14, 0x000000, true)
+//         gui.text(6, 6, memory.read16(0x02024284), 0xffffff)
+//     end)
+//     event.onmemorywrite(0x03001000, 4, function(addr, val, size)
+//         memory.write32(addr, 999)
+//     end)
+//
+// The machine is only reachable while a script is running; calling
+// memory.read8 and friends any other time is an error.
+
+use std::cell::RefCell;
+use std::fs;
+use std::rc::Rc;
+
+use mlua::{Function, Lua, RegistryKey, Scope, Table};
+
+use gba_error::{GbaError, GbaResult};
+use gba_keypad::BUTTONS;
+use gba_mem::Address;
+use gba_mem::watch::{Watchpoint, WatchHit, WatchKind};
+use gba_ppu::{Layer, SCREEN_HEIGHT, SCREEN_WIDTH};
+use gba_system::Gba;
+
+// Glyphs for gui.text, 3 pixels wide and 5 tall, one row per 3 bits with
+// the leftmost pixel in the high bit. Only digits and hex letters, which
+// covers the usual HUD of counters and addresses.
+const FONT: [(char, [u8; 5]); 16] = [
+    ('0', [0b111, 0b101, 0b101, 0b101, 0b111]),
+    ('1', [0b010, 0b110, 0b010, 0b010, 0b111]),
+    ('2', [0b111, 0b001, 0b111, 0b100, 0b111]),
+    ('3', [0b111, 0b001, 0b111, 0b001, 0b111]),
+    ('4', [0b101, 0b101, 0b111, 0b001, 0b001]),
+    ('5', [0b111, 0b100, 0b111, 0b001, 0b111]),
+    ('6', [0b111, 0b100, 0b111, 0b101, 0b111]),
+    ('7', [0b111, 0b001, 0b001, 0b001, 0b001]),
+    ('8', [0b111, 0b101, 0b111, 0b101, 0b111]),
+    ('9', [0b111, 0b101, 0b111, 0b001, 0b111]),
+    ('a', [0b010, 0b101, 0b111, 0b101, 0b101]),
+    ('b', [0b110, 0b101, 0b110, 0b101, 0b110]),
+    ('c', [0b011, 0b100, 0b100, 0b100, 0b011]),
+    ('d', [0b110, 0b101, 0b101, 0b101, 0b110]),
+    ('e', [0b111, 0b100, 0b110, 0b100, 0b111]),
+    ('f', [0b111, 0b100, 0b110, 0b100, 0b100]),
+];
+
+fn script_error(e: mlua::Error) -> GbaError {
+    GbaError::Script(e.to_string())
+}
+
+// A callback for accesses to a range of memory
+#[derive(Debug)]
+struct MemoryHook {
+    watch: Watchpoint,
+    callback: RegistryKey,
+    installed: bool, // Whether the watchpoint has been added to the bus yet
+}
+
+// Everything the script has registered, shared with the event functions
+#[derive(Debug, Default)]
+struct Callbacks {
+    frame_start: Vec<RegistryKey>,
+    frame_end: Vec<RegistryKey>,
+    memory: Vec<MemoryHook>,
+}
+
+/// A loaded script and the callbacks it registered
+#[derive(Debug)]
+pub struct ScriptHost {
+    lua: Lua,
+    callbacks: Rc<RefCell<Callbacks>>,
+}
+
+impl ScriptHost {
+    /// Run the script at path, which registers its callbacks on gba
+    pub fn load(path: &str, gba: &mut Gba) -> GbaResult<ScriptHost> {
+        let source = fs::read_to_string(path)?;
+        ScriptHost::from_source(&source, path, gba)
+    }
+
+    /// Run a script's top level, which is where it registers its callbacks.
+    /// name is what errors in it are reported against.
+    pub fn from_source(source: &str, name: &str, gba: &mut Gba) -> GbaResult<ScriptHost> {
+        let host = ScriptHost {
+            lua: Lua::new(),
+            callbacks: Rc::new(RefCell::new(Callbacks::default())),
+        };
+        host.register_events().map_err(script_error)?;
+        host.with_machine(gba, |lua| lua.load(source).set_name(name).exec())?;
+        host.install_hooks(gba);
+        Ok(host)
+    }
+
+    /// Gba::run_frame with the script's callbacks. Memory hooks run after
+    /// the instruction that made the access, and only the first access to
+    /// hit a watchpoint in each instruction is seen.
+    pub fn run_frame(&mut self, gba: &mut Gba) -> GbaResult<()> {
+        let frame_start = self.functions(|c| &c.frame_start)?;
+        self.call_all(gba, &frame_start, ())?;
+        gba.begin_frame();
+
+        let hooked = !self.callbacks.borrow().memory.is_empty();
+        loop {
+            // Drop any hit from the script's own accesses
+            gba.mem().take_watch_hit();
+            let events = gba.step();
+            if hooked {
+                if let Some(hit) = gba.mem().take_watch_hit() {
+                    self.memory_hit(gba, &hit)?;
+                }
+            }
+            if events.frame_complete {
+                break;
+            }
+        }
+
+        let frame_end = self.functions(|c| &c.frame_end)?;
+        self.call_all(gba, &frame_end, ())
+    }
+
+    fn memory_hit(&self, gba: &mut Gba, hit: &WatchHit) -> GbaResult<()> {
+        let hooks = {
+            let callbacks = self.callbacks.borrow();
+            callbacks.memory.iter()
+                .filter(|hook| hook.watch.matches(&hit.access))
+                .map(|hook| self.lua.registry_value::<Function>(&hook.callback))
+                .collect::<mlua::Result<Vec<_>>>()
+                .map_err(script_error)?
+        };
+        let access = hit.access;
+        self.call_all(gba, &hooks, (access.addr, access.val, access.size))
+    }
+
+    // Look up a list of callbacks, so they can register more while they run
+    fn functions<F>(&self, list: F) -> GbaResult<Vec<Function<'_>>>
+        where F: Fn(&Callbacks) -> &Vec<RegistryKey>
+    {
+        let callbacks = self.callbacks.borrow();
+        list(&callbacks).iter()
+            .map(|key| self.lua.registry_value(key))
+            .collect::<mlua::Result<_>>()
+            .map_err(script_error)
+    }
+
+    fn call_all<A>(&self, gba: &mut Gba, functions: &[Function], args: A) -> GbaResult<()>
+        where A: for<'lua> mlua::IntoLuaMulti<'lua> + Clone
+    {
+        if functions.is_empty() {
+            return Ok(());
+        }
+        self.with_machine(gba, |_| {
+            for f in functions {
+                f.call::<_, ()>(args.clone())?;
+            }
+            Ok(())
+        })?;
+        self.install_hooks(gba);
+        Ok(())
+    }
+
+    // Hooks registered since the last call start watching the bus
+    fn install_hooks(&self, gba: &mut Gba) {
+        for hook in self.callbacks.borrow_mut().memory.iter_mut().filter(|h| !h.installed) {
+            gba.mem_mut().add_watchpoint(hook.watch);
+            hook.installed = true;
+        }
+    }
+
+    // The event table, which outlives any one call into the script
+    fn register_events(&self) -> mlua::Result<()> {
+        let lua = &self.lua;
+        let event = lua.create_table()?;
+
+        let callbacks = self.callbacks.clone();
+        event.set("onframestart", lua.create_function(move |lua, f: Function| {
+            callbacks.borrow_mut().frame_start.push(lua.create_registry_value(f)?);
+            Ok(())
+        })?)?;
+        let callbacks = self.callbacks.clone();
+        event.set("onframeend", lua.create_function(move |lua, f: Function| {
+            callbacks.borrow_mut().frame_end.push(lua.create_registry_value(f)?);
+            Ok(())
+        })?)?;
+
+        for &(name, kind) in &[("onmemoryread", WatchKind::Read),
+                               ("onmemorywrite", WatchKind::Write)] {
+            let callbacks = self.callbacks.clone();
+            let register = move |lua: &Lua, (addr, len, f): (Address, usize, Function)| {
+                if len == 0 {
+                    return Err(mlua::Error::RuntimeError("empty memory range".to_string()));
+                }
+                callbacks.borrow_mut().memory.push(MemoryHook {
+                    watch: Watchpoint::new(addr, len, kind),
+                    callback: lua.create_registry_value(f)?,
+                    installed: false,
+                });
+                Ok(())
+            };
+            event.set(name, lua.create_function(register)?)?;
+        }
+
+        lua.globals().set("event", event)
+    }
+
+    // Run f with the emu, memory, joypad and gui tables bound to gba
+    fn with_machine<F, R>(&self, gba: &mut Gba, f: F) -> GbaResult<R>
+        where F: FnOnce(&Lua) -> mlua::Result<R>
+    {
+        let gba = RefCell::new(gba);
+        let lua = &self.lua;
+        lua.scope(|scope| {
+            let globals = lua.globals();
+            globals.set("emu", emu_table(lua, scope, &gba)?)?;
+            globals.set("memory", memory_table(lua, scope, &gba)?)?;
+            globals.set("joypad", joypad_table(lua, scope, &gba)?)?;
+            globals.set("gui", gui_table(lua, scope, &gba)?)?;
+            f(lua)
+        }).map_err(script_error)
+    }
+}
+
+fn emu_table<'lua, 'scope>(lua: &'lua Lua, scope: &Scope<'lua, 'scope>,
+                           gba: &'scope RefCell<&mut Gba>) -> mlua::Result<Table<'lua>> {
+    let emu = lua.create_table()?;
+    emu.set("frame", scope.create_function(move |_, ()| Ok(gba.borrow().frame()))?)?;
+    emu.set("cycles", scope.create_function(move |_, ()| Ok(gba.borrow().cycles()))?)?;
+    // emu.showlayer("obj", false) leaves the sprites out of the picture
+    emu.set("showlayer", scope.create_function(move |_, (name, shown): (String, bool)| {
+        match Layer::from_name(&name) {
+            Some(layer) => {
+                gba.borrow_mut().set_layer_hidden(layer, !shown);
+                Ok(())
+            }
+            None => Err(mlua::Error::RuntimeError(format!("unknown layer {:?}", name))),
+        }
+    })?)?;
+    Ok(emu)
+}
+
+// Reads don't disturb the machine; writes go through the bus like the CPU's
+fn memory_table<'lua, 'scope>(lua: &'lua Lua, scope: &Scope<'lua, 'scope>,
+                              gba: &'scope RefCell<&mut Gba>) -> mlua::Result<Table<'lua>> {
+    let memory = lua.create_table()?;
+    memory.set("read8", scope.create_function(move |_, addr: Address| {
+        Ok(gba.borrow().mem().peek::<u8>(addr))
+    })?)?;
+    memory.set("read16", scope.create_function(move |_, addr: Address| {
+        Ok(gba.borrow().mem().peek::<u16>(addr))
+    })?)?;
+    memory.set("read32", scope.create_function(move |_, addr: Address| {
+        Ok(gba.borrow().mem().peek::<u32>(addr))
+    })?)?;
+    memory.set("write8", scope.create_function(move |_, (addr, val): (Address, u32)| {
+        gba.borrow_mut().mem_mut().write8::<u8>(addr, val as u8);
+        Ok(())
+    })?)?;
+    memory.set("write16", scope.create_function(move |_, (addr, val): (Address, u32)| {
+        gba.borrow_mut().mem_mut().write16::<u16>(addr, val as u16);
+        Ok(())
+    })?)?;
+    memory.set("write32", scope.create_function(move |_, (addr, val): (Address, u32)| {
+        gba.borrow_mut().mem_mut().write32::<u32>(addr, val);
+        Ok(())
+    })?)?;
+    Ok(memory)
+}
+
+// Buttons by name, as in the Python bindings: a, b, select, start, right,
+// left, up, down, r or l
+fn joypad_table<'lua, 'scope>(lua: &'lua Lua, scope: &Scope<'lua, 'scope>,
+                              gba: &'scope RefCell<&mut Gba>) -> mlua::Result<Table<'lua>> {
+    let joypad = lua.create_table()?;
+    joypad.set("set", scope.create_function(move |_, (name, pressed): (String, bool)| {
+        match BUTTONS.iter().find(|b| b.to_string().eq_ignore_ascii_case(&name)) {
+            Some(&button) => {
+                gba.borrow_mut().set_button(button, pressed);
+                Ok(())
+            }
+            None => Err(mlua::Error::RuntimeError(format!("unknown button {:?}", name))),
+        }
+    })?)?;
+    Ok(joypad)
+}
+
+// Drawing on the finished frame, so it belongs in onframeend. Colors are
+// 0xRRGGBB and coordinates off the screen are clipped.
+fn gui_table<'lua, 'scope>(lua: &'lua Lua, scope: &Scope<'lua, 'scope>,
+                           gba: &'scope RefCell<&mut Gba>) -> mlua::Result<Table<'lua>> {
+    let gui = lua.create_table()?;
+    gui.set("pixel", scope.create_function(move |_, (x, y, color): (i32, i32, u32)| {
+        Canvas::of(&mut gba.borrow_mut()).pixel(x, y, color);
+        Ok(())
+    })?)?;
+    gui.set("line", scope.create_function(
+        move |_, (x0, y0, x1, y1, color): (i32, i32, i32, i32, u32)| {
+            Canvas::of(&mut gba.borrow_mut()).line(x0, y0, x1, y1, color);
+            Ok(())
+        })?)?;
+    gui.set("box", scope.create_function(
+        move |_, (x0, y0, x1, y1, color, fill): (i32, i32, i32, i32, u32, Option<bool>)| {
+            Canvas::of(&mut gba.borrow_mut()).rect(x0, y0, x1, y1, color, fill.unwrap_or(false));
+            Ok(())
+        })?)?;
+    gui.set("text", scope.create_function(
+        move |_, (x, y, text, color): (i32, i32, mlua::String, u32)| {
+            Canvas::of(&mut gba.borrow_mut()).text(x, y, &text.to_string_lossy(), color);
+            Ok(())
+        })?)?;
+    Ok(gui)
+}
+
+// The PPU's framebuffer, in its BGR555
+struct Canvas<'a> {
+    pixels: &'a mut [u16],
+}
+
+impl<'a> Canvas<'a> {
+    fn of(gba: &'a mut Gba) -> Canvas<'a> {
+        Canvas {
+            pixels: gba.mem_mut().io_mut().ppu.framebuffer_mut().pixels_mut(),
+        }
+    }
+
+    fn pixel(&mut self, x: i32, y: i32, color: u32) {
+        if x < 0 || y < 0 || x as usize >= SCREEN_WIDTH || y as usize >= SCREEN_HEIGHT {
+            return;
+        }
+        let (r, g, b) = ((color >> 19) & 0x1f, (color >> 11) & 0x1f, (color >> 3) & 0x1f);
+        self.pixels[y as usize * SCREEN_WIDTH + x as usize] = (r | g << 5 | b << 10) as u16;
+    }
+
+    // Bresenham's
+    fn line(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, color: u32) {
+        let (dx, dy) = ((x1 - x0).abs(), -(y1 - y0).abs());
+        let (sx, sy) = (if x0 < x1 { 1 } else { -1 }, if y0 < y1 { 1 } else { -1 });
+        let (mut x, mut y, mut err) = (x0, y0, dx + dy);
+        loop {
+            self.pixel(x, y, color);
+            if x == x1 && y == y1 {
+                break;
+            }
+            let e2 = 2 * err;
+            if e2 >= dy {
+                err += dy;
+                x += sx;
+            }
+            if e2 <= dx {
+                err += dx;
+                y += sy;
+            }
+        }
+    }
+
+    fn rect(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, color: u32, fill: bool) {
+        let (left, right) = (x0.min(x1), x0.max(x1));
+        let (top, bottom) = (y0.min(y1), y0.max(y1));
+        if fill {
+            for y in top..bottom + 1 {
+                self.line(left, y, right, y, color);
+            }
+        }
+        else {
+            self.line(left, top, right, top, color);
+            self.line(left, bottom, right, bottom, color);
+            self.line(left, top, left, bottom, color);
+            self.line(right, top, right, bottom, color);
+        }
+    }
+
+    // Characters without a glyph leave a gap
+    fn text(&mut self, x: i32, y: i32, text: &str, color: u32) {
+        for (i, c) in text.chars().enumerate() {
+            let c = c.to_ascii_lowercase();
+            let glyph = match FONT.iter().find(|&&(g, _)| g == c) {
+                Some((_, rows)) => rows,
+                None => continue,
+            };
+            let left = x + 4 * i as i32;
+            for (row, bits) in glyph.iter().enumerate() {
+                for col in 0..3 {
+                    if bits & (0b100 >> col) != 0 {
+                        self.pixel(left + col, y + row as i32, color);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canvas_colors_and_clipping() {
+        let mut pixels = vec![0u16; SCREEN_WIDTH * SCREEN_HEIGHT];
+        {
+            let mut canvas = Canvas { pixels: &mut pixels };
+            canvas.pixel(1, 0, 0xff0000);
+            canvas.pixel(2, 0, 0x0000ff);
+            canvas.pixel(-1, 0, 0xffffff);
+            canvas.rect(0, 2, 2, 3, 0x00ff00, true);
+        }
+        assert_eq!(pixels[1], 0x001f);
+        assert_eq!(pixels[2], 0x7c00);
+        assert_eq!(pixels[SCREEN_WIDTH - 1], 0);
+        for &i in &[2 * SCREEN_WIDTH, 2 * SCREEN_WIDTH + 2, 3 * SCREEN_WIDTH + 1] {
+            assert_eq!(pixels[i], 0x03e0);
+        }
+    }
+}
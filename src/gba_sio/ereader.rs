@@ -0,0 +1,44 @@
+use gba_sio::{Disconnected, SerialDevice};
+
+// An e-Reader on the other end of the cable with no card ever scanned.
+// Games that trade e-Card data see a partner and wait for a card rather
+// than report a link error, so the player can back out of the menu. The
+// e-Reader is the parent, so this GBA is player 1.
+const IDLE: u16 = 0;
+
+/// An e-Reader waiting for a card
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EReader;
+
+impl SerialDevice for EReader {
+    // The e-Reader only ever clocks transfers itself, and it has nothing
+    // to send
+    fn normal(&mut self, data: u32, bits: u32, master: bool) -> Option<u32> {
+        if master { Some(IDLE as u32) } else { Disconnected.normal(data, bits, master) }
+    }
+
+    fn multiplayer_id(&self) -> Option<u8> {
+        Some(1)
+    }
+
+    // Never starts a transfer, so the game keeps waiting for a card
+    fn multiplayer(&mut self, _data: u16) -> Option<[u16; 4]> {
+        None
+    }
+
+    fn uart_send(&mut self, byte: u8) {
+        Disconnected.uart_send(byte)
+    }
+
+    fn uart_receive(&mut self) -> Option<u8> {
+        Disconnected.uart_receive()
+    }
+
+    fn gp_write(&mut self, pins: u8) {
+        Disconnected.gp_write(pins)
+    }
+
+    fn gp_read(&self) -> u8 {
+        Disconnected.gp_read()
+    }
+}
@@ -0,0 +1,89 @@
+use gba_sio::{Disconnected, SerialDevice};
+
+// The Game Boy Player for the GameCube. A game finds it by reading all four
+// directions held during its boot logo, then talks to it over the link
+// port in Normal 32-bit mode, mostly to drive the controller's rumble. The
+// player answers with a fixed handshake, after which each word from the
+// game is a rumble command. The sequence is the one mGBA answers with.
+const HANDSHAKE: [u32; 13] = [
+    0x0000494E, 0x0000494E,
+    0xB6B1494E, 0xB6B1544E,
+    0xABB1544E, 0xABB14E45,
+    0xB1BA4E45, 0xB1BA4F44,
+    0xB0BB4F44, 0xB0BB8002,
+    0x10000010, 0x20000013,
+    0x30000003,
+];
+// Games restart the handshake after a few commands
+const RESTART: usize = 17;
+
+const RUMBLE_MASK: u32 = 0x33;
+const RUMBLE_START: u32 = 0x22; // 0x00 stops it, 0x11 stops it at once
+
+/// How long the directions read as held after boot, in frames. Games
+/// check during the logo they show first.
+pub const DETECT_FRAMES: u64 = 240;
+
+/// The Game Boy Player on the link port
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GbPlayer {
+    position: usize, // Transfers into the handshake
+    rumble: bool,
+    changed: bool,
+}
+
+impl SerialDevice for GbPlayer {
+    fn normal(&mut self, data: u32, bits: u32, master: bool) -> Option<u32> {
+        if bits != 32 {
+            return Disconnected.normal(data, bits, master);
+        }
+
+        // The words the game sends during the handshake are ignored
+        if self.position >= HANDSHAKE.len() - 1 {
+            let rumble = data & RUMBLE_MASK == RUMBLE_START;
+            self.changed |= rumble != self.rumble;
+            self.rumble = rumble;
+        }
+
+        if self.position >= RESTART {
+            self.position = 0;
+        }
+        let reply = HANDSHAKE[self.position.min(HANDSHAKE.len() - 1)];
+        self.position += 1;
+        Some(reply)
+    }
+
+    fn multiplayer_id(&self) -> Option<u8> {
+        Disconnected.multiplayer_id()
+    }
+
+    fn multiplayer(&mut self, data: u16) -> Option<[u16; 4]> {
+        Disconnected.multiplayer(data)
+    }
+
+    fn uart_send(&mut self, byte: u8) {
+        Disconnected.uart_send(byte)
+    }
+
+    fn uart_receive(&mut self) -> Option<u8> {
+        Disconnected.uart_receive()
+    }
+
+    fn gp_write(&mut self, pins: u8) {
+        Disconnected.gp_write(pins)
+    }
+
+    fn gp_read(&self) -> u8 {
+        Disconnected.gp_read()
+    }
+
+    fn take_rumble(&mut self) -> Option<bool> {
+        if self.changed {
+            self.changed = false;
+            Some(self.rumble)
+        }
+        else {
+            None
+        }
+    }
+}
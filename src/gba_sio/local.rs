@@ -0,0 +1,191 @@
+use alloc::collections::VecDeque;
+use alloc::rc::Rc;
+use core::cell::RefCell;
+
+use gba_error::GbaResult;
+use gba_sio::{SerialDevice, DISCONNECTED_MULTI};
+use gba_state::{SaveState, StateReader, StateWriter};
+use prelude::*;
+
+/// A link cable between two consoles in the same process, player 0 the
+/// parent. Nothing in it depends on the host, so two machines stepped in
+/// the same order over it always do the same thing, and its state can be
+/// saved alongside theirs. Netplay runs both ends of it on every peer.
+#[derive(Clone, Debug)]
+pub struct LocalCable {
+    cable: Rc<RefCell<Cable>>,
+}
+
+#[derive(Debug)]
+struct Cable {
+    multi_send: u16, // The child's SIOMLT_SEND, as it last showed it
+    multi_done: Option<[u16; 4]>, // A transfer the child hasn't seen yet
+    armed: [Option<u32>; 2], // Data an end clocked from outside is waiting to shift out
+    shifted: [Option<u32>; 2], // What the other end clocked into it
+    uart: [VecDeque<u8>; 2], // Bytes sent to each end, not yet received
+    pins: [u8; 2], // General purpose levels each end drives
+}
+
+impl Default for Cable {
+    fn default() -> Cable {
+        Cable {
+            multi_send: DISCONNECTED_MULTI,
+            multi_done: None,
+            armed: [None; 2],
+            shifted: [None; 2],
+            uart: [VecDeque::new(), VecDeque::new()],
+            pins: [0xF; 2],
+        }
+    }
+}
+
+fn save_option(val: Option<u32>, w: &mut StateWriter) {
+    w.write_u8(val.is_some() as u8);
+    w.write_u32(val.unwrap_or(0));
+}
+
+fn load_option(r: &mut StateReader) -> GbaResult<Option<u32>> {
+    let some = r.read_u8()? != 0;
+    let val = r.read_u32()?;
+    Ok(if some { Some(val) } else { None })
+}
+
+impl SaveState for LocalCable {
+    fn save_state(&self, w: &mut StateWriter) {
+        let cable = self.cable.borrow();
+        w.write_u16(cable.multi_send);
+        w.write_u8(cable.multi_done.is_some() as u8);
+        cable.multi_done.unwrap_or([0; 4]).save_state(w);
+        for end in 0..2 {
+            save_option(cable.armed[end], w);
+            save_option(cable.shifted[end], w);
+            w.write_bytes(&cable.uart[end].iter().cloned().collect::<Vec<u8>>());
+            w.write_u8(cable.pins[end]);
+        }
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> GbaResult<()> {
+        let mut cable = self.cable.borrow_mut();
+        cable.multi_send = r.read_u16()?;
+        let done = r.read_u8()? != 0;
+        let mut multi = [0; 4];
+        multi.load_state(r)?;
+        cable.multi_done = if done { Some(multi) } else { None };
+        for end in 0..2 {
+            cable.armed[end] = load_option(r)?;
+            cable.shifted[end] = load_option(r)?;
+            cable.uart[end] = r.read_block()?.iter().cloned().collect();
+            cable.pins[end] = r.read_u8()?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for LocalCable {
+    fn default() -> LocalCable {
+        LocalCable::new()
+    }
+}
+
+impl LocalCable {
+    /// A cable with nothing plugged in
+    pub fn new() -> LocalCable {
+        LocalCable { cable: Rc::new(RefCell::new(Cable::default())) }
+    }
+
+    /// The plug for player 0 or 1's link port
+    pub fn end(&self, player: usize) -> LocalLinkEnd {
+        assert!(player < 2, "a local cable only has two ends");
+        LocalLinkEnd {
+            cable: self.cable.clone(),
+            player,
+        }
+    }
+}
+
+/// One GBA's plug on a LocalCable
+#[derive(Debug)]
+pub struct LocalLinkEnd {
+    cable: Rc<RefCell<Cable>>,
+    player: usize,
+}
+
+impl SerialDevice for LocalLinkEnd {
+    fn normal(&mut self, data: u32, bits: u32, master: bool) -> Option<u32> {
+        let mask = !0 >> (32 - bits);
+        let other = 1 - self.player;
+        let mut cable = self.cable.borrow_mut();
+        if master {
+            // An end not waiting for a clock isn't driving its line
+            let received = cable.armed[other].take().unwrap_or(!0);
+            cable.shifted[other] = Some(data & mask);
+            Some(received & mask)
+        }
+        else {
+            match cable.shifted[self.player].take() {
+                Some(received) => Some(received),
+                None => {
+                    cable.armed[self.player] = Some(data);
+                    None
+                },
+            }
+        }
+    }
+
+    fn multiplayer_id(&self) -> Option<u8> {
+        Some(self.player as u8)
+    }
+
+    fn multiplayer(&mut self, data: u16) -> Option<[u16; 4]> {
+        let mut cable = self.cable.borrow_mut();
+        if self.player == 0 {
+            let done = [data, cable.multi_send, DISCONNECTED_MULTI, DISCONNECTED_MULTI];
+            cable.multi_done = Some(done);
+            Some(done)
+        }
+        else {
+            cable.multi_send = data;
+            cable.multi_done.take()
+        }
+    }
+
+    fn uart_send(&mut self, byte: u8) {
+        self.cable.borrow_mut().uart[1 - self.player].push_back(byte);
+    }
+
+    fn uart_receive(&mut self) -> Option<u8> {
+        self.cable.borrow_mut().uart[self.player].pop_front()
+    }
+
+    fn gp_write(&mut self, pins: u8) {
+        self.cable.borrow_mut().pins[self.player] = pins;
+    }
+
+    fn gp_read(&self) -> u8 {
+        self.cable.borrow().pins[1 - self.player]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ends_swap_data() {
+        let cable = LocalCable::new();
+        let (mut parent, mut child) = (cable.end(0), cable.end(1));
+        assert_eq!(child.multiplayer(0x2222), None);
+        assert_eq!(parent.multiplayer(0x1111), Some([0x1111, 0x2222, 0xFFFF, 0xFFFF]));
+
+        let mut w = StateWriter::new();
+        cable.save_state(&mut w);
+        assert_eq!(child.multiplayer(0x2222), Some([0x1111, 0x2222, 0xFFFF, 0xFFFF]));
+        assert_eq!(child.multiplayer(0x2222), None);
+        cable.clone().load_state(&mut StateReader::new(&w.into_bytes())).unwrap();
+        assert!(child.multiplayer(0x2222).is_some());
+
+        assert_eq!(child.normal(0xAB, 8, false), None);
+        assert_eq!(parent.normal(0x12, 8, true), Some(0xAB));
+        assert_eq!(child.normal(0xAB, 8, false), Some(0x12));
+    }
+}
@@ -0,0 +1,425 @@
+/// An e-Reader with no card scanned
+pub mod ereader;
+/// The GameCube's Game Boy Player
+pub mod gb_player;
+/// Link cable between two machines in the same process
+pub mod local;
+/// Link cable over the network
+#[cfg(feature = "std")]
+pub mod net;
+
+use core::cell::Cell;
+use core::fmt;
+
+use gba_apu::CPU_FREQ;
+use gba_irq::{Interrupt, IrqController};
+use gba_mem::Address;
+use prelude::*;
+
+/// Serial port registers from:
+/// http://problemkaputt.de/gbatek.htm#gbacommunicationports
+pub const SIOMULTI0: Address = 0x04000120; // Also SIODATA32
+/// The last player's halfword in multiplayer mode
+pub const SIOMULTI3: Address = 0x04000126;
+/// Control register, laid out differently in each mode
+pub const SIOCNT:    Address = 0x04000128;
+/// Also SIOMLT_SEND
+pub const SIODATA8:  Address = 0x0400012A;
+/// Mode select and the general-purpose pins
+pub const RCNT:      Address = 0x04000134;
+
+const SIOCNT_INTERNAL_CLOCK: u16 = 0x0001; // Normal mode
+const SIOCNT_2MHZ:           u16 = 0x0002; // Normal mode
+const SIOCNT_SI:             u16 = 0x0004; // Normal and multiplayer mode
+const SIOCNT_SD:             u16 = 0x0008; // Multiplayer mode
+const SIOCNT_ID_SHIFT:       u16 = 4; // Multiplayer mode
+const SIOCNT_SEND_FULL:      u16 = 0x0010; // UART mode
+const SIOCNT_RECV_EMPTY:     u16 = 0x0020; // UART mode
+const SIOCNT_START:          u16 = 0x0080;
+const SIOCNT_RECV_ENABLE:    u16 = 0x0800; // UART mode
+const SIOCNT_IRQ:            u16 = 0x4000;
+// Read only status bits, by mode
+const SIOCNT_NORMAL_RO: u16 = SIOCNT_SI;
+const SIOCNT_MULTI_RO:  u16 = 0x007C;
+const SIOCNT_UART_RO:   u16 = SIOCNT_SEND_FULL | SIOCNT_RECV_EMPTY;
+
+const RCNT_GP_DIR_SHIFT: u16 = 4;
+const RCNT_GP_PINS: u8 = 0xF;
+
+// Cycles per bit with the internal clock at 256KHz or 2MHz
+const NORMAL_BIT_CYCLES: [u32; 2] = [64, 8];
+// Multiplayer baud rates. A transfer is a start bit, 16 data bits and a
+// stop bit from each of up to four players.
+const MULTI_BAUD: [u32; 4] = [9600, 38400, 57600, 115200];
+const MULTI_BITS: u32 = 4 * 18;
+
+/// No other GBA drives these lines, so their pull-ups win
+pub const DISCONNECTED_MULTI: u16 = 0xFFFF;
+
+/// What the link port is doing, from SIOCNT and RCNT
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SioMode {
+    /// Normal mode, 8 bits at a time
+    Normal8,
+    /// Normal mode, 32 bits at a time
+    Normal32,
+    /// Up to four GBAs swapping halfwords
+    Multiplayer,
+    /// Serial with RTS/CTS
+    Uart,
+    /// The four pins driven directly
+    GeneralPurpose,
+    /// Not emulated; behaves as though nothing is connected
+    JoyBus,
+}
+
+/// Whatever is plugged into the link port. The port calls in as emulated
+/// transfers complete, so a device waiting on the other end returns None
+/// and is asked again later.
+pub trait SerialDevice: fmt::Debug {
+    /// Normal mode: shift bits of data out and return what was shifted in.
+    /// When this GBA supplies the clock the device must answer; otherwise
+    /// it returns None until the other end clocks a transfer.
+    fn normal(&mut self, data: u32, bits: u32, master: bool) -> Option<u32>;
+
+    /// Multiplayer mode: this GBA's player number, or None without a cable
+    fn multiplayer_id(&self) -> Option<u8>;
+
+    /// Multiplayer mode: swap halfwords with the other players. The parent
+    /// calls this when it starts a transfer; children are polled until the
+    /// parent has started one.
+    fn multiplayer(&mut self, data: u16) -> Option<[u16; 4]>;
+
+    /// UART mode: send a byte
+    fn uart_send(&mut self, byte: u8);
+    /// UART mode: a byte received, if one has arrived
+    fn uart_receive(&mut self) -> Option<u8>;
+
+    /// General-purpose mode: levels on SC, SD, SI and SO (bits 0-3). The
+    /// GBA writes the pins it drives and reads what the other end drives.
+    fn gp_write(&mut self, pins: u8);
+    /// General-purpose mode: the levels the other end drives
+    fn gp_read(&self) -> u8;
+
+    /// Whether a rumble motor on the other end switched on or off since
+    /// the last call
+    fn take_rumble(&mut self) -> Option<bool> {
+        None
+    }
+}
+
+/// An empty link port. Transfers this GBA clocks read back all ones and the
+/// multiplayer status reports a bad connection, which is how games tell
+/// there is nobody to play with.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Disconnected;
+
+impl SerialDevice for Disconnected {
+    fn normal(&mut self, _data: u32, bits: u32, master: bool) -> Option<u32> {
+        if master { Some(!0 >> (32 - bits)) } else { None }
+    }
+
+    fn multiplayer_id(&self) -> Option<u8> {
+        None
+    }
+
+    fn multiplayer(&mut self, data: u16) -> Option<[u16; 4]> {
+        Some([data, DISCONNECTED_MULTI, DISCONNECTED_MULTI, DISCONNECTED_MULTI])
+    }
+
+    fn uart_send(&mut self, _byte: u8) {}
+
+    fn uart_receive(&mut self) -> Option<u8> {
+        None
+    }
+
+    fn gp_write(&mut self, _pins: u8) {}
+
+    fn gp_read(&self) -> u8 {
+        RCNT_GP_PINS
+    }
+}
+
+/// The serial port and whatever is plugged into it
+#[derive(Debug)]
+pub struct Sio {
+    siocnt: u16,
+    rcnt: u16,
+    multi: [u16; 4], // SIOMULTI0-3, with SIODATA32 in the first two
+    send: u16, // SIODATA8 or SIOMLT_SEND as written
+    recv: u8, // Last byte received over the UART
+    recv_full: Cell<bool>, // Cleared by reading SIODATA8
+    busy_cycles: u32, // Until the transfer in progress completes
+    device: Box<dyn SerialDevice>,
+}
+
+// What is plugged in isn't part of the state
+impl_save_state!(Sio { siocnt, rcnt, multi, send, recv, recv_full, busy_cycles });
+impl_serde_via_state!(Sio);
+
+impl Default for Sio {
+    fn default() -> Sio {
+        Sio {
+            siocnt: 0,
+            rcnt: 0,
+            multi: [0; 4],
+            send: 0,
+            recv: 0,
+            recv_full: Cell::new(false),
+            busy_cycles: 0,
+            device: Box::new(Disconnected),
+        }
+    }
+}
+
+impl Sio {
+    /// What's plugged into the link port
+    pub fn device(&self) -> &dyn SerialDevice {
+        &*self.device
+    }
+
+    /// What's plugged into the link port, for writing
+    pub fn device_mut(&mut self) -> &mut dyn SerialDevice {
+        &mut *self.device
+    }
+
+    /// Plug something into the link port, returning what was there
+    pub fn set_device(&mut self, device: Box<dyn SerialDevice>) -> Box<dyn SerialDevice> {
+        ::core::mem::replace(&mut self.device, device)
+    }
+
+    /// The mode SIOCNT and RCNT select
+    pub fn mode(&self) -> SioMode {
+        match (self.rcnt >> 14, self.siocnt >> 12 & 3) {
+            (0, 0) | (1, 0) => SioMode::Normal8,
+            (0, 1) | (1, 1) => SioMode::Normal32,
+            (0, 2) | (1, 2) => SioMode::Multiplayer,
+            (0, _) | (1, _) => SioMode::Uart,
+            (2, _) => SioMode::GeneralPurpose,
+            _ => SioMode::JoyBus,
+        }
+    }
+
+    /// Whether a transfer is in progress
+    pub fn is_busy(&self) -> bool {
+        self.siocnt & SIOCNT_START != 0
+    }
+
+    /// Cycles until the transfer in progress completes, if one is timed
+    pub fn cycles_to_event(&self) -> Option<u32> {
+        if self.busy_cycles > 0 { Some(self.busy_cycles) } else { None }
+    }
+
+    fn normal_bits(&self) -> u32 {
+        if self.mode() == SioMode::Normal32 { 32 } else { 8 }
+    }
+
+    fn normal_data(&self) -> u32 {
+        match self.mode() {
+            SioMode::Normal32 => self.multi[0] as u32 | (self.multi[1] as u32) << 16,
+            _ => self.send as u8 as u32,
+        }
+    }
+
+    fn is_child(&self) -> bool {
+        self.device.multiplayer_id().is_some_and(|id| id != 0)
+    }
+
+    fn start(&mut self) {
+        match self.mode() {
+            SioMode::Normal8 | SioMode::Normal32
+                // With an external clock the other end decides when
+                if self.siocnt & SIOCNT_INTERNAL_CLOCK != 0 => {
+                    let rate = (self.siocnt & SIOCNT_2MHZ != 0) as usize;
+                    self.busy_cycles = self.normal_bits() * NORMAL_BIT_CYCLES[rate];
+                },
+            SioMode::Multiplayer if !self.is_child() => {
+                let baud = MULTI_BAUD[(self.siocnt & 3) as usize];
+                self.busy_cycles = MULTI_BITS * (CPU_FREQ / baud);
+            },
+            _ => {},
+        }
+    }
+
+    fn finish(&mut self, irq: &mut IrqController) -> bool {
+        match self.mode() {
+            SioMode::Normal8 | SioMode::Normal32 => {
+                let master = self.siocnt & SIOCNT_INTERNAL_CLOCK != 0;
+                let received = match self.device.normal(self.normal_data(), self.normal_bits(),
+                                                        master) {
+                    Some(received) => received,
+                    None => return false,
+                };
+                if self.mode() == SioMode::Normal32 {
+                    self.multi[0] = received as u16;
+                    self.multi[1] = (received >> 16) as u16;
+                }
+                else {
+                    self.send = self.send & 0xFF00 | received as u8 as u16;
+                }
+            },
+            SioMode::Multiplayer => {
+                match self.device.multiplayer(self.send) {
+                    Some(data) => self.multi = data,
+                    None => return false,
+                }
+            },
+            _ => {},
+        }
+
+        self.siocnt &= !SIOCNT_START;
+        if self.siocnt & SIOCNT_IRQ != 0 {
+            irq.request(Interrupt::Serial);
+        }
+        true
+    }
+
+    /// Run transfers in progress for cycles, raising the serial IRQ as they finish
+    pub fn step(&mut self, cycles: u32, irq: &mut IrqController) {
+        match self.mode() {
+            SioMode::Uart if self.siocnt & SIOCNT_RECV_ENABLE != 0 && !self.recv_full.get() => {
+                if let Some(byte) = self.device.uart_receive() {
+                    self.recv = byte;
+                    self.recv_full.set(true);
+                    if self.siocnt & SIOCNT_IRQ != 0 {
+                        irq.request(Interrupt::Serial);
+                    }
+                }
+            },
+            // The parent starts multiplayer transfers for everyone
+            SioMode::Multiplayer if self.is_child() => {
+                if let Some(data) = self.device.multiplayer(self.send) {
+                    self.multi = data;
+                    if self.siocnt & SIOCNT_IRQ != 0 {
+                        irq.request(Interrupt::Serial);
+                    }
+                }
+            },
+            SioMode::Normal8 | SioMode::Normal32 if self.is_busy() && self.busy_cycles == 0 => {
+                self.finish(irq);
+            },
+            _ => {},
+        }
+
+        if self.busy_cycles > 0 {
+            self.busy_cycles = self.busy_cycles.saturating_sub(cycles);
+            // A device still waiting on the other end is asked again
+            // next step
+            if self.busy_cycles == 0 && !self.finish(irq) {
+                self.busy_cycles = 1;
+            }
+        }
+    }
+
+    /// SIOCNT, with the status bits of the current mode
+    pub fn siocnt(&self) -> u16 {
+        match self.mode() {
+            SioMode::Normal8 | SioMode::Normal32 => {
+                // SI idles high when nothing drives it
+                self.siocnt & !SIOCNT_NORMAL_RO | SIOCNT_SI
+            },
+            SioMode::Multiplayer => {
+                let status = match self.device.multiplayer_id() {
+                    Some(id) => {
+                        let si = if id == 0 { 0 } else { SIOCNT_SI };
+                        si | SIOCNT_SD | (id as u16) << SIOCNT_ID_SHIFT
+                    },
+                    None => 0,
+                };
+                self.siocnt & !SIOCNT_MULTI_RO | status
+            },
+            SioMode::Uart => {
+                let empty = if self.recv_full.get() { 0 } else { SIOCNT_RECV_EMPTY };
+                self.siocnt & !SIOCNT_UART_RO | empty
+            },
+            _ => self.siocnt,
+        }
+    }
+
+    /// RCNT, with the pins the other end drives in general-purpose mode
+    pub fn rcnt(&self) -> u16 {
+        if self.mode() != SioMode::GeneralPurpose {
+            return self.rcnt;
+        }
+        let dir = (self.rcnt >> RCNT_GP_DIR_SHIFT) as u8 & RCNT_GP_PINS;
+        let pins = self.rcnt as u8 & dir | self.device.gp_read() & !dir;
+        self.rcnt & !(RCNT_GP_PINS as u16) | (pins & RCNT_GP_PINS) as u16
+    }
+
+    /// Read a serial register
+    pub fn read16(&self, addr: Address) -> u16 {
+        match addr {
+            SIOMULTI0..=SIOMULTI3 => self.multi[(addr - SIOMULTI0) / 2],
+            SIOCNT => self.siocnt(),
+            SIODATA8 if self.mode() == SioMode::Uart => {
+                self.recv_full.set(false);
+                self.recv as u16
+            },
+            SIODATA8 => self.send,
+            RCNT => self.rcnt(),
+            _ => 0,
+        }
+    }
+
+    /// read16 without taking the UART's received byte, for debuggers
+    pub fn peek16(&self, addr: Address) -> u16 {
+        match addr {
+            SIODATA8 if self.mode() == SioMode::Uart => self.recv as u16,
+            _ => self.read16(addr),
+        }
+    }
+
+    /// Write the bits in mask of a serial register
+    pub fn write16(&mut self, addr: Address, val: u16, mask: u16) {
+        let merge = |old: u16| (old & !mask) | (val & mask);
+
+        match addr {
+            SIOMULTI0..=SIOMULTI3 => {
+                let i = (addr - SIOMULTI0) / 2;
+                self.multi[i] = merge(self.multi[i]);
+            },
+            SIOCNT => {
+                let was_busy = self.is_busy();
+                self.siocnt = merge(self.siocnt);
+                if !was_busy && self.is_busy() {
+                    self.start();
+                }
+            },
+            SIODATA8 => {
+                self.send = merge(self.send);
+                if self.mode() == SioMode::Uart {
+                    self.device.uart_send(self.send as u8);
+                }
+            },
+            RCNT => {
+                self.rcnt = merge(self.rcnt);
+                if self.mode() == SioMode::GeneralPurpose {
+                    let dir = (self.rcnt >> RCNT_GP_DIR_SHIFT) as u8 & RCNT_GP_PINS;
+                    self.device.gp_write(self.rcnt as u8 & dir);
+                }
+            },
+            _ => {},
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lone_multiplayer_parent_reads_disconnected() {
+        let mut sio = Sio::default();
+        let mut irq = IrqController::default();
+        sio.write16(SIOCNT, 0x2000 | SIOCNT_IRQ | 3, 0xFFFF);
+        sio.write16(SIODATA8, 0x1234, 0xFFFF);
+        sio.write16(SIOCNT, SIOCNT_START, SIOCNT_START);
+        assert!(sio.is_busy());
+
+        sio.step(CPU_FREQ, &mut irq);
+        assert!(!sio.is_busy());
+        assert_eq!(sio.read16(SIOMULTI0), 0x1234);
+        assert_eq!(sio.read16(SIOMULTI0 + 2), DISCONNECTED_MULTI);
+        assert_eq!(irq.if_(), Interrupt::Serial as u16);
+    }
+}
@@ -0,0 +1,277 @@
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use gba_error::{GbaError, GbaResult};
+use gba_sio::{Disconnected, SerialDevice, DISCONNECTED_MULTI};
+
+// A link cable between emulators over TCP. One instance hosts as player 0,
+// the parent, and up to three others connect as its children. Transfers
+// run in lockstep: when the parent's game starts one, the parent sends its
+// halfword to each child and waits for theirs, then sends everyone the
+// result. A child that doesn't answer in time is dropped and reads as
+// unplugged from then on.
+const MAX_CHILDREN: usize = 3;
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+// The port is polled every step, so children only look at the socket once
+// in this many polls. This is still far more often than games transfer.
+const POLL_INTERVAL: u32 = 1024;
+
+// Every message is a tag and four halfwords
+const MSG_LEN: usize = 9;
+const MSG_HELLO: u8 = b'H'; // Parent to child: player number
+const MSG_START: u8 = b'S'; // Parent to child: parent's halfword
+const MSG_REPLY: u8 = b'R'; // Child to parent: child's halfword
+const MSG_DONE:  u8 = b'D'; // Parent to child: every player's halfword
+
+fn send_msg(stream: &mut TcpStream, tag: u8, data: [u16; 4]) -> io::Result<()> {
+    let mut msg = [0; MSG_LEN];
+    msg[0] = tag;
+    for (i, &half) in data.iter().enumerate() {
+        LittleEndian::write_u16(&mut msg[1 + 2 * i..], half);
+    }
+    stream.write_all(&msg)
+}
+
+fn recv_msg(stream: &mut TcpStream) -> io::Result<(u8, [u16; 4])> {
+    let mut msg = [0; MSG_LEN];
+    stream.read_exact(&mut msg)?;
+    let mut data = [0; 4];
+    for (i, half) in data.iter_mut().enumerate() {
+        *half = LittleEndian::read_u16(&msg[1 + 2 * i..]);
+    }
+    Ok((msg[0], data))
+}
+
+fn open(stream: &TcpStream) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    stream.set_read_timeout(Some(TIMEOUT))
+}
+
+type Children = Arc<Mutex<[Option<TcpStream>; MAX_CHILDREN]>>;
+
+#[derive(Debug)]
+enum Role {
+    Parent {
+        children: Children, // Filled in by the accepting thread
+        addr: SocketAddr,
+    },
+    Child {
+        stream: Option<TcpStream>, // None once the parent is lost
+        id: u8,
+        polls: u32,
+    },
+}
+
+/// One end of a link cable over TCP, as the parent or a child
+#[derive(Debug)]
+pub struct NetLink {
+    role: Role,
+}
+
+impl NetLink {
+    /// Host a link as the parent. Children can join at any time.
+    pub fn host<A: ToSocketAddrs>(addr: A) -> GbaResult<NetLink> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let children: Children = Arc::new(Mutex::new([None, None, None]));
+
+        let slots = children.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+                let mut slots = slots.lock().unwrap();
+                // A full link turns the newcomer away by hanging up
+                if let Some(i) = slots.iter().position(|s| s.is_none()) {
+                    let id = i as u16 + 1;
+                    if open(&stream).and_then(|_| send_msg(&mut stream, MSG_HELLO, [id, 0, 0, 0]))
+                        .is_ok() {
+                        slots[i] = Some(stream);
+                    }
+                }
+            }
+        });
+
+        Ok(NetLink { role: Role::Parent { children, addr } })
+    }
+
+    /// Join a hosted link as a child, waiting for the parent to assign a
+    /// player number
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> GbaResult<NetLink> {
+        let mut stream = TcpStream::connect(addr)?;
+        open(&stream)?;
+        let id = match recv_msg(&mut stream) {
+            Ok((MSG_HELLO, data)) => data[0] as u8,
+            Ok(_) => return Err(GbaError::Io(
+                io::Error::new(io::ErrorKind::InvalidData, "unexpected link handshake"))),
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Err(GbaError::Io(
+                io::Error::new(io::ErrorKind::ConnectionRefused, "link is full"))),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(NetLink { role: Role::Child { stream: Some(stream), id, polls: 0 } })
+    }
+
+    /// Where children should connect when hosting
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        match self.role {
+            Role::Parent { addr, .. } => Some(addr),
+            Role::Child { .. } => None,
+        }
+    }
+
+    /// GBAs on the link, counting this one
+    pub fn num_players(&self) -> usize {
+        match self.role {
+            Role::Parent { ref children, .. } =>
+                1 + children.lock().unwrap().iter().filter(|s| s.is_some()).count(),
+            Role::Child { ref stream, .. } => if stream.is_some() { 2 } else { 1 },
+        }
+    }
+}
+
+fn parent_transfer(children: &Children, data: u16) -> [u16; 4] {
+    let mut result = [data, DISCONNECTED_MULTI, DISCONNECTED_MULTI, DISCONNECTED_MULTI];
+    let mut slots = children.lock().unwrap();
+
+    // Start everyone before waiting on anyone
+    for slot in slots.iter_mut() {
+        let failed = match *slot {
+            Some(ref mut stream) => send_msg(stream, MSG_START, [data, 0, 0, 0]).is_err(),
+            None => false,
+        };
+        if failed {
+            *slot = None;
+        }
+    }
+
+    for (i, slot) in slots.iter_mut().enumerate() {
+        let reply = match *slot {
+            Some(ref mut stream) => recv_msg(stream),
+            None => continue,
+        };
+        match reply {
+            Ok((MSG_REPLY, reply)) => result[i + 1] = reply[0],
+            _ => *slot = None,
+        }
+    }
+
+    for slot in slots.iter_mut() {
+        let failed = match *slot {
+            Some(ref mut stream) => send_msg(stream, MSG_DONE, result).is_err(),
+            None => false,
+        };
+        if failed {
+            *slot = None;
+        }
+    }
+    result
+}
+
+// Answer the parent if it has started a transfer
+fn child_transfer(stream: &mut TcpStream, data: u16) -> io::Result<Option<[u16; 4]>> {
+    let mut msg = [0; MSG_LEN];
+    stream.set_nonblocking(true)?;
+    let ready = stream.peek(&mut msg);
+    stream.set_nonblocking(false)?;
+    match ready {
+        Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+        Ok(n) if n < MSG_LEN => return Ok(None),
+        Ok(_) => {},
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    if recv_msg(stream)?.0 != MSG_START {
+        return Ok(None);
+    }
+    send_msg(stream, MSG_REPLY, [data, 0, 0, 0])?;
+    match recv_msg(stream)? {
+        (MSG_DONE, result) => Ok(Some(result)),
+        _ => Err(io::ErrorKind::InvalidData.into()),
+    }
+}
+
+impl SerialDevice for NetLink {
+    // Only multiplayer mode goes over the network
+    fn normal(&mut self, data: u32, bits: u32, master: bool) -> Option<u32> {
+        Disconnected.normal(data, bits, master)
+    }
+
+    fn multiplayer_id(&self) -> Option<u8> {
+        match self.role {
+            Role::Parent { .. } => Some(0),
+            Role::Child { stream: Some(_), id, .. } => Some(id),
+            Role::Child { stream: None, .. } => None,
+        }
+    }
+
+    fn multiplayer(&mut self, data: u16) -> Option<[u16; 4]> {
+        match self.role {
+            Role::Parent { ref children, .. } => Some(parent_transfer(children, data)),
+            Role::Child { ref mut stream, ref mut polls, .. } => {
+                let result = match *stream {
+                    Some(ref mut s) => {
+                        *polls = polls.wrapping_add(1);
+                        if *polls % POLL_INTERVAL != 0 {
+                            return None;
+                        }
+                        child_transfer(s, data)
+                    },
+                    None => return Disconnected.multiplayer(data),
+                };
+                result.unwrap_or_else(|_| {
+                    *stream = None;
+                    None
+                })
+            },
+        }
+    }
+
+    fn uart_send(&mut self, byte: u8) {
+        Disconnected.uart_send(byte)
+    }
+
+    fn uart_receive(&mut self) -> Option<u8> {
+        Disconnected.uart_receive()
+    }
+
+    fn gp_write(&mut self, pins: u8) {
+        Disconnected.gp_write(pins)
+    }
+
+    fn gp_read(&self) -> u8 {
+        Disconnected.gp_read()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parent_and_child_swap_halfwords() {
+        let mut parent = NetLink::host("127.0.0.1:0").unwrap();
+        let addr = parent.local_addr().unwrap();
+        let mut child = NetLink::connect(addr).unwrap();
+        assert_eq!(child.multiplayer_id(), Some(1));
+
+        let child = thread::spawn(move || {
+            loop {
+                if let Some(result) = child.multiplayer(0x2222) {
+                    return result;
+                }
+            }
+        });
+        let result = parent.multiplayer(0x1111).unwrap();
+        assert_eq!(result, [0x1111, 0x2222, DISCONNECTED_MULTI, DISCONNECTED_MULTI]);
+        assert_eq!(child.join().unwrap(), result);
+    }
+}
@@ -0,0 +1,160 @@
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+use core::fmt;
+
+use gba_error::{GbaError, GbaResult};
+use prelude::*;
+
+// Determinism audits: a hash of the whole machine's save state after every
+// frame. Two runs from the same state with the same input must give the
+// same stream, or something outside the emulated machine (the host clock,
+// an uninitialised field, iteration order) is leaking in, and netplay and
+// movies will drift.
+//
+// Streams are stored as text, one hash in hex per frame, so two can be
+// compared with diff.
+
+/// 64 bit FNV-1a, which is plenty to tell states apart and the same on
+/// every host
+pub fn state_hash(state: &[u8]) -> u64 {
+    state.iter().fold(0xCBF29CE484222325, |hash, &b| (hash ^ b as u64).wrapping_mul(0x100000001B3))
+}
+
+/// The first frame two runs disagree on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    /// Frame number, from 0
+    pub frame: u64,
+    /// None if the reference stream ended first
+    pub expected: Option<u64>,
+    /// None if this run's stream ended first
+    pub actual: Option<u64>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let hash = |h: Option<u64>| h.map_or("nothing".to_string(), |h| format!("{:016x}", h));
+        write![f, "state diverged at frame {}: expected {}, got {}",
+               self.frame, hash(self.expected), hash(self.actual)]
+    }
+}
+
+/// Where two hash streams first differ, counting one running out as a
+/// difference
+pub fn compare(expected: &[u64], actual: &[u64]) -> Option<Divergence> {
+    let len = expected.len().max(actual.len());
+    (0..len)
+        .find(|&i| expected.get(i) != actual.get(i))
+        .map(|i| Divergence {
+            frame: i as u64,
+            expected: expected.get(i).cloned(),
+            actual: actual.get(i).cloned(),
+        })
+}
+
+/// Hashes as text, one hex hash a line
+pub fn encode(hashes: &[u64]) -> String {
+    hashes.iter().map(|h| format!("{:016x}\n", h)).collect()
+}
+
+/// Read hashes written by encode
+pub fn decode(text: &str) -> GbaResult<Vec<u64>> {
+    text.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| u64::from_str_radix(line, 16).map_err(|_| GbaError::InvalidSaveState(
+            format!("bad state hash {:?}", line))))
+        .collect()
+}
+
+/// The hashes of a run so far, checked against a reference run's as they
+/// come in when there is one
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StateAudit {
+    hashes: Vec<u64>,
+    reference: Option<Vec<u64>>,
+    divergence: Option<Divergence>,
+}
+
+impl StateAudit {
+    /// An audit with no reference to check against
+    pub fn new() -> StateAudit {
+        StateAudit::default()
+    }
+
+    /// An audit checking each hash against reference
+    pub fn against(reference: Vec<u64>) -> StateAudit {
+        StateAudit {
+            reference: Some(reference),
+            ..StateAudit::default()
+        }
+    }
+
+    /// Add the next frame's hash. Returns the divergence if this is the
+    /// frame the run first leaves the reference.
+    pub fn record(&mut self, hash: u64) -> Option<Divergence> {
+        let frame = self.hashes.len();
+        self.hashes.push(hash);
+        if self.divergence.is_some() {
+            return None;
+        }
+        let expected = match self.reference {
+            Some(ref reference) => reference.get(frame).cloned(),
+            None => return None,
+        };
+        if expected == Some(hash) {
+            return None;
+        }
+        self.divergence = Some(Divergence {
+            frame: frame as u64,
+            expected,
+            actual: Some(hash),
+        });
+        self.divergence
+    }
+
+    /// Hashes recorded so far
+    pub fn hashes(&self) -> &[u64] {
+        &self.hashes
+    }
+
+    /// The first frame that didn't match the reference
+    pub fn divergence(&self) -> Option<Divergence> {
+        self.divergence
+    }
+
+    /// Write the hashes recorded so far to path
+    #[cfg(feature = "std")]
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> GbaResult<()> {
+        fs::write(path, encode(&self.hashes))?;
+        Ok(())
+    }
+}
+
+/// Read hashes saved by StateAudit::save
+#[cfg(feature = "std")]
+pub fn load<P: AsRef<Path>>(path: P) -> GbaResult<Vec<u64>> {
+    decode(&fs::read_to_string(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_first_divergence() {
+        let mut audit = StateAudit::against(vec![1, 2, 3]);
+        assert_eq!(audit.record(1), None);
+        assert_eq!(audit.record(5).map(|d| d.frame), Some(1));
+        assert_eq!(audit.record(3), None);
+        assert_eq!(audit.divergence().unwrap().expected, Some(2));
+
+        assert_eq!(compare(&[1, 2], &[1, 2]), None);
+        assert_eq!(compare(&[1, 2], &[1]).map(|d| (d.frame, d.actual)), Some((1, None)));
+        assert_eq!(decode(&encode(audit.hashes())).unwrap(), vec![1, 5, 3]);
+        assert!(decode("xyz").is_err());
+    }
+}
@@ -0,0 +1,110 @@
+use gba_error::{GbaError, GbaResult};
+use gba_mem::Memory;
+use gba_state::{StateReader, StateWriter};
+use prelude::*;
+
+/// Start of every battery file
+pub const BATTERY_MAGIC: &[u8; 4] = b"GBAB";
+/// Bumped only for changes older readers can't skip over
+pub const BATTERY_VERSION: u32 = 1;
+
+const CHUNK_PAK_RAM: &[u8; 4] = b"SRAM";
+const CHUNK_EEPROM: &[u8; 4] = b"EEPR";
+const CHUNK_FLASH: &[u8; 4] = b"FLSH";
+
+fn invalid(msg: &str) -> GbaError {
+    GbaError::InvalidSaveFile(msg.to_string())
+}
+
+fn write_tag(w: &mut StateWriter, tag: &[u8; 4]) {
+    for &b in tag.iter() {
+        w.write_u8(b);
+    }
+}
+
+fn read_tag(r: &mut StateReader) -> GbaResult<[u8; 4]> {
+    let mut tag = [0; 4];
+    for b in tag.iter_mut() {
+        *b = r.read_u8()?;
+    }
+    Ok(tag)
+}
+
+/// Battery-backed cartridge data, kept apart from save states so it can be
+/// synced between machines and emulator versions. The layout is a header
+/// followed by tagged chunks:
+///
+/// ```text
+/// "GBAB" | version: u32 | { tag: [u8; 4] | len: u32 | data }*
+/// ```
+///
+/// All integers are little-endian. Readers skip chunks they don't know, so
+/// new kinds of backup (e.g. a cartridge clock) only ever add chunks and
+/// files stay readable in both directions.
+pub fn export(mem: &Memory) -> Vec<u8> {
+    let mut w = StateWriter::new();
+    write_tag(&mut w, BATTERY_MAGIC);
+    w.write_u32(BATTERY_VERSION);
+
+    write_tag(&mut w, CHUNK_PAK_RAM);
+    w.write_bytes(mem.pak_ram());
+    // Until the game has used it, the size of the EEPROM isn't known
+    if let Some(eeprom) = mem.eeprom().contents() {
+        write_tag(&mut w, CHUNK_EEPROM);
+        w.write_bytes(eeprom);
+    }
+    if let Some(flash) = mem.flash().contents() {
+        write_tag(&mut w, CHUNK_FLASH);
+        w.write_bytes(flash);
+    }
+    w.into_bytes()
+}
+
+/// Everything is checked before anything is written, so a bad file leaves
+/// the cartridge untouched
+pub fn import(mem: &mut Memory, data: &[u8]) -> GbaResult<()> {
+    let truncated = |_| invalid("unexpected end of data");
+    let mut r = StateReader::new(data);
+
+    if &read_tag(&mut r).map_err(truncated)? != BATTERY_MAGIC {
+        return Err(invalid("not a battery file"));
+    }
+    // Newer versions only add chunks, so any version can be read
+    r.read_u32().map_err(truncated)?;
+
+    let mut pak_ram = None;
+    let mut eeprom = None;
+    let mut flash = None;
+    while r.remaining() > 0 {
+        let tag = read_tag(&mut r).map_err(truncated)?;
+        let block = r.read_block().map_err(truncated)?;
+        if &tag == CHUNK_PAK_RAM {
+            pak_ram = Some(block);
+        }
+        else if &tag == CHUNK_EEPROM {
+            eeprom = Some(block);
+        }
+        else if &tag == CHUNK_FLASH {
+            flash = Some(block);
+        }
+    }
+
+    if let Some(block) = pak_ram {
+        let len = mem.pak_ram().len();
+        if block.len() > len {
+            return Err(GbaError::InvalidSaveFile(
+                format!("{} Bytes of save RAM, at most {} fit", block.len(), len)));
+        }
+    }
+    // Each checks the size before filling the chip
+    if let Some(block) = eeprom {
+        mem.eeprom_mut().load(block)?;
+    }
+    if let Some(block) = flash {
+        mem.flash_mut().load(block)?;
+    }
+    if let Some(block) = pak_ram {
+        mem.pak_ram_mut()[..block.len()].copy_from_slice(block);
+    }
+    Ok(())
+}
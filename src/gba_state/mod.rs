@@ -0,0 +1,339 @@
+/// Determinism audits hashing the state every frame
+pub mod audit;
+/// Battery save files
+pub mod battery;
+/// The rewind buffer
+#[cfg(feature = "std")]
+pub mod rewind;
+/// Input movies
+pub mod movie;
+/// Numbered save state slots
+#[cfg(feature = "std")]
+pub mod slots;
+
+use core::cell::Cell;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use gba_error::{GbaError, GbaResult};
+use prelude::*;
+
+/// Save state header. The version is bumped whenever the layout of any
+/// saved component changes.
+pub const STATE_MAGIC: &[u8; 4] = b"GBAS";
+/// Bumped whenever the layout changes; older states are refused
+pub const STATE_VERSION: u32 = 14;
+
+/// Little-endian byte stream that machine components save themselves into
+#[derive(Debug, Default)]
+pub struct StateWriter {
+    buf: Vec<u8>,
+}
+
+impl StateWriter {
+    /// An empty state
+    pub fn new() -> StateWriter {
+        StateWriter::default()
+    }
+
+    /// Append a byte
+    pub fn write_u8(&mut self, val: u8) { self.buf.push(val); }
+    /// Append a little-endian halfword
+    pub fn write_u16(&mut self, val: u16) { self.write_le(2, |b| LittleEndian::write_u16(b, val)); }
+    /// Append a little-endian word
+    pub fn write_u32(&mut self, val: u32) { self.write_le(4, |b| LittleEndian::write_u32(b, val)); }
+    /// Append a little-endian doubleword
+    pub fn write_u64(&mut self, val: u64) { self.write_le(8, |b| LittleEndian::write_u64(b, val)); }
+
+    fn write_le<F: FnOnce(&mut [u8])>(&mut self, len: usize, write: F) {
+        let start = self.buf.len();
+        self.buf.resize(start + len, 0);
+        write(&mut self.buf[start..]);
+    }
+
+    /// Append bytes as they are
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_u32(bytes.len() as u32);
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Bytes written so far
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Whether nothing has been written
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// The finished state
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+/// Reads back what a StateWriter produced, failing on truncated data
+#[derive(Debug)]
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+fn truncated() -> GbaError {
+    GbaError::InvalidSaveState("unexpected end of data".to_string())
+}
+
+impl<'a> StateReader<'a> {
+    /// Read data from the start
+    pub fn new(data: &'a [u8]) -> StateReader<'a> {
+        StateReader {
+            data,
+            pos: 0,
+        }
+    }
+
+    /// Next byte
+    pub fn read_u8(&mut self) -> GbaResult<u8>   { self.take(1).map(|b| b[0]) }
+    /// Next little-endian halfword
+    pub fn read_u16(&mut self) -> GbaResult<u16> { self.take(2).map(LittleEndian::read_u16) }
+    /// Next little-endian word
+    pub fn read_u32(&mut self) -> GbaResult<u32> { self.take(4).map(LittleEndian::read_u32) }
+    /// Next little-endian doubleword
+    pub fn read_u64(&mut self) -> GbaResult<u64> { self.take(8).map(LittleEndian::read_u64) }
+
+    // The next len bytes
+    fn take(&mut self, len: usize) -> GbaResult<&'a [u8]> {
+        if self.remaining() < len {
+            return Err(truncated());
+        }
+        self.pos += len;
+        Ok(&self.data[self.pos - len..self.pos])
+    }
+
+    /// Read a length-prefixed block written by write_bytes
+    pub fn read_block(&mut self) -> GbaResult<&'a [u8]> {
+        let len = self.read_u32()? as usize;
+        self.take(len)
+    }
+
+    /// Read a length-prefixed block into out, which must be the same size
+    pub fn read_bytes_into(&mut self, out: &mut [u8]) -> GbaResult<()> {
+        let block = self.read_block()?;
+        if block.len() != out.len() {
+            return Err(GbaError::InvalidSaveState(
+                format!("expected a {} byte block, found {}", out.len(), block.len())));
+        }
+        out.copy_from_slice(block);
+        Ok(())
+    }
+
+    /// Bytes not read yet
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+}
+
+/// A piece of the machine that can be written to and restored from a save
+/// state
+pub trait SaveState {
+    /// Append this part's state to w
+    fn save_state(&self, w: &mut StateWriter);
+    /// Restore this part's state from r
+    fn load_state(&mut self, r: &mut StateReader) -> GbaResult<()>;
+}
+
+macro_rules! def_save_state_int {
+    ($ty:ty, $write:ident, $read:ident, $as:ty) => {
+        #[allow(trivial_numeric_casts)]
+        impl SaveState for $ty {
+            fn save_state(&self, w: &mut StateWriter) {
+                w.$write(*self as $as);
+            }
+
+            fn load_state(&mut self, r: &mut StateReader) -> GbaResult<()> {
+                *self = r.$read()? as $ty;
+                Ok(())
+            }
+        }
+    };
+}
+
+def_save_state_int!(u8,    write_u8,  read_u8,  u8);
+def_save_state_int!(i8,    write_u8,  read_u8,  u8);
+def_save_state_int!(u16,   write_u16, read_u16, u16);
+def_save_state_int!(i16,   write_u16, read_u16, u16);
+def_save_state_int!(u32,   write_u32, read_u32, u32);
+def_save_state_int!(u64,   write_u64, read_u64, u64);
+def_save_state_int!(i64,   write_u64, read_u64, u64);
+def_save_state_int!(usize, write_u64, read_u64, u64);
+
+impl SaveState for bool {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(*self as u8);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> GbaResult<()> {
+        *self = r.read_u8()? != 0;
+        Ok(())
+    }
+}
+
+impl<T: SaveState + Copy> SaveState for Cell<T> {
+    fn save_state(&self, w: &mut StateWriter) {
+        self.get().save_state(w);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> GbaResult<()> {
+        self.get_mut().load_state(r)
+    }
+}
+
+impl<T: SaveState, const N: usize> SaveState for [T; N] {
+    fn save_state(&self, w: &mut StateWriter) {
+        for item in self.iter() {
+            item.save_state(w);
+        }
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> GbaResult<()> {
+        for item in self.iter_mut() {
+            item.load_state(r)?;
+        }
+        Ok(())
+    }
+}
+
+// Implement SaveState for a struct by saving the listed fields in order.
+// Fields left out keep their current value on load.
+macro_rules! impl_save_state {
+    ($ty:ty { $($field:tt),* $(,)* }) => {
+        impl $crate::gba_state::SaveState for $ty {
+            fn save_state(&self, w: &mut $crate::gba_state::StateWriter) {
+                $( $crate::gba_state::SaveState::save_state(&self.$field, w); )*
+            }
+
+            fn load_state(&mut self, r: &mut $crate::gba_state::StateReader)
+                          -> $crate::gba_error::GbaResult<()> {
+                $( $crate::gba_state::SaveState::load_state(&mut self.$field, r)?; )*
+                Ok(())
+            }
+        }
+    };
+}
+
+/// Serde support goes through the save state encoding, so a piece of the
+/// machine serializes the same way wherever it ends up: a save state, a
+/// netplay sync or a test fixture. Formats with a byte string type store it
+/// as one; the rest store an array of bytes.
+#[cfg(feature = "serde")]
+pub fn serialize_state<T, S>(val: &T, serializer: S) -> Result<S::Ok, S::Error>
+    where T: SaveState, S: serde::Serializer
+{
+    let mut w = StateWriter::new();
+    val.save_state(&mut w);
+    serializer.serialize_bytes(&w.into_bytes())
+}
+
+/// Load over a default value, so fields a state leaves out (settings and the
+/// like) come out as they would on a new machine
+#[cfg(feature = "serde")]
+pub fn deserialize_state<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where T: SaveState + Default, D: serde::Deserializer<'de>
+{
+    use serde::de::Error;
+
+    let bytes = deserializer.deserialize_byte_buf(StateBytes)?;
+    let mut r = StateReader::new(&bytes);
+    let mut val = T::default();
+    val.load_state(&mut r).map_err(D::Error::custom)?;
+    if r.remaining() != 0 {
+        return Err(D::Error::custom(format!("{} Bytes left over", r.remaining())));
+    }
+    Ok(val)
+}
+
+#[cfg(feature = "serde")]
+struct StateBytes;
+
+#[cfg(feature = "serde")]
+impl<'de> serde::de::Visitor<'de> for StateBytes {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write![f, "save state bytes"]
+    }
+
+    fn visit_bytes<E>(self, bytes: &[u8]) -> Result<Vec<u8>, E> {
+        Ok(bytes.to_vec())
+    }
+
+    fn visit_byte_buf<E>(self, bytes: Vec<u8>) -> Result<Vec<u8>, E> {
+        Ok(bytes)
+    }
+
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(b) = seq.next_element()? {
+            bytes.push(b);
+        }
+        Ok(bytes)
+    }
+}
+
+// Serialize and Deserialize for types with SaveState and Default, when the
+// serde feature is on
+macro_rules! impl_serde_via_state {
+    ($($ty:ty),* $(,)*) => {
+        $(
+            #[cfg(feature = "serde")]
+            impl ::serde::Serialize for $ty {
+                fn serialize<S: ::serde::Serializer>(&self, serializer: S)
+                                                     -> Result<S::Ok, S::Error> {
+                    $crate::gba_state::serialize_state(self, serializer)
+                }
+            }
+
+            #[cfg(feature = "serde")]
+            impl<'de> ::serde::Deserialize<'de> for $ty {
+                fn deserialize<D: ::serde::Deserializer<'de>>(deserializer: D)
+                                                              -> Result<Self, D::Error> {
+                    $crate::gba_state::deserialize_state(deserializer)
+                }
+            }
+        )*
+    };
+}
+
+#[cfg(all(test, feature = "config"))]
+mod tests {
+    use super::*;
+    use gba_cpu::ARM7;
+    use gba_mem::InternRam;
+    use toml;
+
+    #[derive(Serialize, Deserialize)]
+    struct Fixture {
+        cpu: ARM7,
+        ram: InternRam,
+    }
+
+    fn state<T: SaveState>(val: &T) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        val.save_state(&mut w);
+        w.into_bytes()
+    }
+
+    #[test]
+    fn serde_matches_save_states() {
+        let mut fixture = Fixture { cpu: ARM7::default(), ram: InternRam::default() };
+        fixture.cpu.set_pc(0x08000124);
+        fixture.ram.as_mut_slice()[5] = 0xAB;
+
+        let text = toml::to_string(&fixture).unwrap();
+        let back: Fixture = toml::from_str(&text).unwrap();
+        assert_eq!(state(&back.cpu), state(&fixture.cpu));
+        assert_eq!(state(&back.ram), state(&fixture.ram));
+        assert!(toml::from_str::<Fixture>("cpu = [1, 2]\nram = []").is_err());
+    }
+}
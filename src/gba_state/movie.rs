@@ -0,0 +1,171 @@
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+use gba_error::{GbaError, GbaResult};
+use gba_state::{StateReader, StateWriter};
+use prelude::*;
+
+/// Start of every movie file
+pub const MOVIE_MAGIC: &[u8; 4] = b"GBMV";
+/// Bumped whenever the layout changes; older movies are refused
+pub const MOVIE_VERSION: u32 = 1;
+
+fn invalid(msg: &str) -> GbaError {
+    GbaError::InvalidSaveState(msg.to_string())
+}
+
+/// Input recorded a frame at a time, replayed from the save state it was
+/// recorded from:
+///
+/// ```text
+/// "GBMV" | version: u32 | rom crc32: u32 | state | frames: u32 | input...
+/// ```
+///
+/// The state is a length prefixed block and each frame's input is the
+/// buttons the game saw, as a KEYINPUT mask with pressed buttons set.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Movie {
+    /// Of the ROM it was recorded with
+    pub rom_crc32: u32,
+    /// Save state playback starts from
+    pub start: Vec<u8>,
+    /// Buttons held, one entry per frame
+    pub input: Vec<u16>,
+}
+
+impl Movie {
+    /// An empty movie starting from the save state start
+    pub fn new(rom_crc32: u32, start: Vec<u8>) -> Movie {
+        Movie {
+            rom_crc32,
+            start,
+            input: Vec::new(),
+        }
+    }
+
+    /// Frames of input recorded
+    pub fn frames(&self) -> usize {
+        self.input.len()
+    }
+
+    /// The movie as a file
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        for &b in MOVIE_MAGIC.iter() {
+            w.write_u8(b);
+        }
+        w.write_u32(MOVIE_VERSION);
+        w.write_u32(self.rom_crc32);
+        w.write_bytes(&self.start);
+        w.write_u32(self.input.len() as u32);
+        for &buttons in self.input.iter() {
+            w.write_u16(buttons);
+        }
+        w.into_bytes()
+    }
+
+    /// Read a movie file
+    pub fn decode(data: &[u8]) -> GbaResult<Movie> {
+        let mut r = StateReader::new(data);
+        let mut magic = [0; 4];
+        for b in magic.iter_mut() {
+            *b = r.read_u8()?;
+        }
+        if &magic != MOVIE_MAGIC {
+            return Err(invalid("not a movie"));
+        }
+        let version = r.read_u32()?;
+        if version != MOVIE_VERSION {
+            return Err(GbaError::InvalidSaveState(
+                format!("movie version {} is not supported (expected {})",
+                        version, MOVIE_VERSION)));
+        }
+        let rom_crc32 = r.read_u32()?;
+        let start = r.read_block()?.to_vec();
+        let frames = r.read_u32()? as usize;
+        if r.remaining() != frames * 2 {
+            return Err(invalid("movie input doesn't match its length"));
+        }
+        let mut input = Vec::with_capacity(frames);
+        for _ in 0..frames {
+            input.push(r.read_u16()?);
+        }
+
+        Ok(Movie {
+            rom_crc32,
+            start,
+            input,
+        })
+    }
+
+    /// Read a movie from path
+    #[cfg(feature = "std")]
+    pub fn load<P: AsRef<Path>>(path: P) -> GbaResult<Movie> {
+        Movie::decode(&fs::read(path)?)
+    }
+
+    /// Write the movie to path
+    #[cfg(feature = "std")]
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> GbaResult<()> {
+        fs::write(path, self.encode())?;
+        Ok(())
+    }
+}
+
+/// What Gba is doing with a movie
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MovieMode {
+    /// Appending each frame's input to a movie
+    Recording(Movie),
+    /// Replaying a movie
+    Playing {
+        /// The movie
+        movie: Movie,
+        /// Next frame of input
+        frame: usize,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn movie() -> Movie {
+        Movie {
+            rom_crc32: 0x1f1c08fb,
+            start: vec![1, 2, 3, 4, 5],
+            input: vec![0x0000, 0x0001, 0x0009, 0x03FF],
+        }
+    }
+
+    #[test]
+    fn files_decode_to_the_same_movie() {
+        let movie = movie();
+        assert_eq!(Movie::decode(&movie.encode()).unwrap(), movie);
+        let empty = Movie::new(0, Vec::new());
+        assert_eq!(Movie::decode(&empty.encode()).unwrap().frames(), 0);
+    }
+
+    #[test]
+    fn bad_headers_are_rejected() {
+        let data = movie().encode();
+        let reject = |data: &[u8], expected: &str| match Movie::decode(data) {
+            Err(GbaError::InvalidSaveState(msg)) => assert!(msg.contains(expected), "{}", msg),
+            other => panic!("expected {:?}, got {:?}", expected, other),
+        };
+
+        let mut magic = data.clone();
+        magic[0] = b'X';
+        reject(&magic, "not a movie");
+        let mut version = data.clone();
+        version[4] = MOVIE_VERSION as u8 + 1;
+        reject(&version, "version");
+        reject(&data[..data.len() - 2], "length");
+        let mut longer = data.clone();
+        longer.extend_from_slice(&[0, 0]);
+        reject(&longer, "length");
+        assert!(Movie::decode(&data[..6]).is_err());
+    }
+}
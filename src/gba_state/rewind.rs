@@ -0,0 +1,254 @@
+use alloc::collections::VecDeque;
+
+use gba_error::{GbaError, GbaResult};
+use gba_system::Gba;
+
+// A snapshot older than the one after it, stored as the difference
+// between the two
+#[derive(Clone, Debug)]
+struct Delta {
+    frame: u64,
+    data: Vec<u8>,
+}
+
+// Encode the bytes that differ between two equally sized states as runs of
+// (unchanged count, changed count, changed bytes XOR'd together)
+fn encode_delta(from: &[u8], to: &[u8]) -> Vec<u8> {
+    debug_assert_eq!(from.len(), to.len());
+
+    let mut out = Vec::new();
+    let mut pos = 0;
+    while pos < from.len() {
+        let start = pos;
+        while pos < from.len() && from[pos] == to[pos] {
+            pos += 1;
+        }
+        let skip = pos - start;
+
+        let start = pos;
+        while pos < from.len() && from[pos] != to[pos] {
+            pos += 1;
+        }
+        let changed = pos - start;
+
+        if changed == 0 {
+            break;
+        }
+        out.extend_from_slice(&(skip as u32).to_le_bytes());
+        out.extend_from_slice(&(changed as u32).to_le_bytes());
+        out.extend(from[start..pos].iter().zip(&to[start..pos]).map(|(a, b)| a ^ b));
+    }
+    out
+}
+
+// Turn one side of an encoded delta into the other
+fn apply_delta(state: &mut [u8], delta: &[u8]) -> GbaResult<()> {
+    let corrupt = || GbaError::InvalidSaveState("corrupt rewind delta".to_string());
+    let read_u32 = |at: usize| -> GbaResult<usize> {
+        delta.get(at..at + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+            .ok_or_else(corrupt)
+    };
+
+    let mut at = 0;
+    let mut pos = 0;
+    while at < delta.len() {
+        pos += read_u32(at)?;
+        let changed = read_u32(at + 4)?;
+        at += 8;
+
+        let bytes = delta.get(at..at + changed).ok_or_else(corrupt)?;
+        let target = state.get_mut(pos..pos + changed).ok_or_else(corrupt)?;
+        for (b, d) in target.iter_mut().zip(bytes) {
+            *b ^= d;
+        }
+        at += changed;
+        pos += changed;
+    }
+    Ok(())
+}
+
+/// Ring buffer of snapshots taken every few frames. Only the newest is kept
+/// whole; older ones are deltas walked back from it, so each costs little
+/// more than the bytes the game changed in between.
+#[derive(Debug)]
+pub struct RewindBuffer {
+    interval: u64, // Frames between snapshots
+    capacity: usize, // Most snapshots kept, including the newest
+    latest: Option<(u64, Vec<u8>)>,
+    history: VecDeque<Delta>, // Oldest first
+}
+
+impl RewindBuffer {
+    /// A buffer taking a snapshot every interval frames and keeping capacity
+    pub fn new(interval: u64, capacity: usize) -> RewindBuffer {
+        assert!(interval > 0);
+        assert!(capacity > 0);
+
+        RewindBuffer {
+            interval,
+            capacity,
+            latest: None,
+            history: VecDeque::new(),
+        }
+    }
+
+    /// Snapshots currently held
+    pub fn len(&self) -> usize {
+        self.latest.iter().count() + self.history.len()
+    }
+
+    /// Whether no snapshots are kept
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    /// Drop every snapshot
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.history.clear();
+    }
+
+    /// Frame of the oldest snapshot that can be rewound to
+    pub fn oldest_frame(&self) -> Option<u64> {
+        self.history.front().map(|delta| delta.frame)
+            .or_else(|| self.latest.as_ref().map(|&(frame, _)| frame))
+    }
+
+    /// Call once per frame. A snapshot is taken when interval frames have
+    /// passed since the last one.
+    pub fn capture(&mut self, gba: &Gba) {
+        let frame = gba.frame();
+        let due = match self.latest {
+            Some((last, _)) => frame >= last + self.interval || frame < last,
+            None => true,
+        };
+        if due {
+            self.push(frame, gba.save_state());
+        }
+    }
+
+    /// Add a snapshot of the given frame as the newest
+    pub fn push(&mut self, frame: u64, state: Vec<u8>) {
+        if let Some((last_frame, last)) = self.latest.take() {
+            if last.len() == state.len() {
+                self.history.push_back(Delta {
+                    frame: last_frame,
+                    data: encode_delta(&state, &last),
+                });
+            }
+            else {
+                // A different machine layout can't be walked back to
+                self.history.clear();
+            }
+        }
+        self.latest = Some((frame, state));
+
+        while self.len() > self.capacity {
+            self.history.pop_front();
+        }
+    }
+
+    /// Restore the newest snapshot at least frames before the current
+    /// frame, or the oldest one held if history doesn't go back that far.
+    /// Snapshots newer than the one restored are dropped. Returns the frame
+    /// restored to, or None if there is nothing to rewind to.
+    pub fn rewind(&mut self, gba: &mut Gba, frames: u64) -> GbaResult<Option<u64>> {
+        let target = gba.frame().saturating_sub(frames);
+
+        let (mut frame, mut state) = match self.latest.take() {
+            Some(latest) => latest,
+            None => return Ok(None),
+        };
+        while frame > target {
+            let delta = match self.history.pop_back() {
+                Some(delta) => delta,
+                None => break,
+            };
+            if let Err(e) = apply_delta(&mut state, &delta.data) {
+                // The chain is broken, so nothing older can be trusted
+                self.history.clear();
+                return Err(e);
+            }
+            frame = delta.frame;
+        }
+
+        let loaded = gba.load_state(&state);
+        self.latest = Some((frame, state));
+        loaded.map(|_| Some(frame))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn machine() -> Gba {
+        Gba::builder().rom(&[0; 0x200]).build().unwrap()
+    }
+
+    #[test]
+    fn rewinds_to_earlier_snapshots() {
+        let mut gba = machine();
+        let mut rewind = RewindBuffer::new(2, 8);
+        let mut states = Vec::new();
+        for i in 0..10 {
+            gba.mem_mut().write8(0x02000000 + i, i as u8 + 1);
+            rewind.capture(&gba);
+            states.push(gba.save_state());
+            gba.run_frame();
+        }
+        assert_eq!(gba.frame(), 10);
+        assert_eq!((rewind.len(), rewind.oldest_frame()), (5, Some(0)));
+
+        // Back to the newest snapshot at or before frame 7
+        assert_eq!(rewind.rewind(&mut gba, 3).unwrap(), Some(6));
+        assert_eq!(gba.save_state(), states[6]);
+        assert_eq!(rewind.len(), 4);
+
+        // Further back than history goes stops at the oldest
+        assert_eq!(rewind.rewind(&mut gba, 100).unwrap(), Some(0));
+        assert_eq!(gba.save_state(), states[0]);
+        assert_eq!(rewind.len(), 1);
+    }
+
+    #[test]
+    fn oldest_snapshots_are_dropped() {
+        let mut gba = machine();
+        let mut rewind = RewindBuffer::new(1, 3);
+        for _ in 0..5 {
+            rewind.capture(&gba);
+            gba.run_frame();
+        }
+        assert_eq!((rewind.len(), rewind.oldest_frame()), (3, Some(2)));
+    }
+
+    #[test]
+    fn corrupt_deltas_are_rejected() {
+        let from = [1, 2, 3, 4, 5, 6];
+        let to = [1, 9, 3, 4, 8, 8];
+        let delta = encode_delta(&from, &to);
+        let mut state = from;
+        apply_delta(&mut state, &delta).unwrap();
+        assert_eq!(state, to);
+
+        // Cut short, or running past the end of the state
+        assert!(apply_delta(&mut state, &delta[..delta.len() - 1]).is_err());
+        assert!(apply_delta(&mut state[..4], &delta).is_err());
+
+        let mut gba = machine();
+        let mut rewind = RewindBuffer::new(1, 4);
+        for _ in 0..3 {
+            rewind.capture(&gba);
+            gba.run_frame();
+        }
+        let before = gba.save_state();
+        rewind.history.back_mut().unwrap().data.push(0xFF);
+        match rewind.rewind(&mut gba, 2) {
+            Err(GbaError::InvalidSaveState(_)) => {},
+            other => panic!("expected a corrupt delta, got {:?}", other),
+        }
+        assert!(rewind.is_empty());
+        assert_eq!(gba.save_state(), before);
+    }
+}
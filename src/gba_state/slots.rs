@@ -0,0 +1,157 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use gba_error::{GbaError, GbaResult};
+use gba_ppu::{FrameBuffer, SCREEN_HEIGHT, SCREEN_WIDTH};
+use gba_state::{StateReader, StateWriter};
+
+/// Start of every slot file
+pub const SLOT_MAGIC: &[u8; 4] = b"GBSL";
+/// Bumped whenever the layout changes; older slots are refused
+pub const SLOT_VERSION: u32 = 1;
+
+/// Slots list_slots() looks at, numbered from 0
+pub const NUM_SLOTS: u32 = 10;
+
+const THUMB_SCALE: usize = 2;
+/// Thumbnail width in pixels
+pub const THUMB_WIDTH: usize = SCREEN_WIDTH / THUMB_SCALE;
+/// Thumbnail height in pixels
+pub const THUMB_HEIGHT: usize = SCREEN_HEIGHT / THUMB_SCALE;
+
+fn invalid(msg: &str) -> GbaError {
+    GbaError::InvalidSaveState(msg.to_string())
+}
+
+/// The screen when a slot was saved, in the GBA's 15-bit BGR format
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Thumbnail {
+    /// In pixels
+    pub width: usize,
+    /// In pixels
+    pub height: usize,
+    /// Row by row from the top left
+    pub pixels: Vec<u16>,
+}
+
+impl Thumbnail {
+    /// Every other pixel of every other line
+    pub fn from_frame(frame: &FrameBuffer) -> Thumbnail {
+        let src = frame.pixels();
+        let pixels = (0..THUMB_HEIGHT)
+            .flat_map(|y| (0..THUMB_WIDTH).map(move |x| (x, y)))
+            .map(|(x, y)| src[y * THUMB_SCALE * SCREEN_WIDTH + x * THUMB_SCALE])
+            .collect();
+        Thumbnail {
+            width: THUMB_WIDTH,
+            height: THUMB_HEIGHT,
+            pixels,
+        }
+    }
+}
+
+/// What is in a slot, without its state
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SlotInfo {
+    /// Which slot
+    pub slot: u32,
+    /// When it was saved
+    pub saved: SystemTime,
+    /// Frames since power on when saved
+    pub frame: u64,
+    /// The screen when saved
+    pub thumbnail: Thumbnail,
+}
+
+/// The current time at the precision slots record it
+pub fn now() -> SystemTime {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+/// A save state file with what a slot picker shows in front of it:
+///
+/// ```text
+/// "GBSL" | version: u32 | saved: u64 | frame: u64
+///        | width: u16 | height: u16 | thumbnail | state
+/// ```
+///
+/// saved is in seconds since the Unix epoch, the thumbnail is the screen
+/// in 15-bit BGR at half size, and the thumbnail and state are length
+/// prefixed blocks.
+pub fn encode(info: &SlotInfo, state: &[u8]) -> Vec<u8> {
+    let mut w = StateWriter::new();
+    for &b in SLOT_MAGIC.iter() {
+        w.write_u8(b);
+    }
+    w.write_u32(SLOT_VERSION);
+    let secs = info.saved.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    w.write_u64(secs);
+    w.write_u64(info.frame);
+    w.write_u16(info.thumbnail.width as u16);
+    w.write_u16(info.thumbnail.height as u16);
+    let thumb: Vec<u8> = info.thumbnail.pixels.iter()
+        .flat_map(|p| p.to_le_bytes().to_vec())
+        .collect();
+    w.write_bytes(&thumb);
+    w.write_bytes(state);
+    w.into_bytes()
+}
+
+/// Split a slot file into its metadata and save state
+pub fn decode(slot: u32, data: &[u8]) -> GbaResult<(SlotInfo, &[u8])> {
+    let mut r = StateReader::new(data);
+    let mut magic = [0; 4];
+    for b in magic.iter_mut() {
+        *b = r.read_u8()?;
+    }
+    if &magic != SLOT_MAGIC {
+        return Err(invalid("not a save slot"));
+    }
+    let version = r.read_u32()?;
+    if version != SLOT_VERSION {
+        return Err(GbaError::InvalidSaveState(
+            format!("slot version {} is not supported (expected {})", version, SLOT_VERSION)));
+    }
+    let saved = UNIX_EPOCH + Duration::from_secs(r.read_u64()?);
+    let frame = r.read_u64()?;
+    let width = r.read_u16()? as usize;
+    let height = r.read_u16()? as usize;
+    let thumb = r.read_block()?;
+    if thumb.len() != width * height * 2 {
+        return Err(invalid("thumbnail doesn't match its size"));
+    }
+    let state = r.read_block()?;
+
+    let info = SlotInfo {
+        slot,
+        saved,
+        frame,
+        thumbnail: Thumbnail {
+            width,
+            height,
+            pixels: thumb.chunks(2).map(|p| u16::from_le_bytes([p[0], p[1]])).collect(),
+        },
+    };
+    Ok((info, state))
+}
+
+/// Write a slot file, creating the directories the layout puts it in
+pub fn write(path: &Path, info: &SlotInfo, state: &[u8]) -> GbaResult<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, encode(info, state))?;
+    Ok(())
+}
+
+/// Read a slot file, or None if the slot is empty
+pub fn read(path: &Path) -> GbaResult<Option<Vec<u8>>> {
+    match fs::read(path) {
+        Ok(data) => Ok(Some(data)),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
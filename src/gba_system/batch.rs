@@ -0,0 +1,185 @@
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use gba_config::Config;
+use gba_system::Gba;
+
+/// One game to run headless for a number of frames
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BatchItem {
+    /// ROM to load
+    pub rom: PathBuf,
+    /// Frames to run it for
+    pub frames: u64,
+}
+
+impl BatchItem {
+    /// Run rom for frames
+    pub fn new<P: Into<PathBuf>>(rom: P, frames: u64) -> BatchItem {
+        BatchItem {
+            rom: rom.into(),
+            frames,
+        }
+    }
+}
+
+/// How far a run got
+/// How a game's run ended
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BatchOutcome {
+    /// Ran every frame asked for
+    Finished,
+    /// The ROM couldn't be read
+    LoadFailed(String),
+    /// Loaded, but a real GBA wouldn't boot it
+    BadHeader(String),
+    /// The emulator panicked
+    Crashed(String),
+}
+
+/// What running one item gave
+#[derive(Debug)]
+pub struct BatchResult<T> {
+    /// The item
+    pub item: BatchItem,
+    /// How it ended
+    pub outcome: BatchOutcome,
+    /// Frames completed before the run ended
+    pub frames: u64,
+    /// Cycles run
+    pub cycles: u64,
+    /// Wall time taken
+    pub elapsed: Duration,
+    /// What inspect returned, if the run finished
+    pub output: Option<T>,
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    }
+    else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    }
+    else {
+        "unknown panic".to_string()
+    }
+}
+
+/// Run a single game the way a compatibility sweep does: load it, refuse it
+/// if the header is bad, then run the frames and let inspect look at the
+/// machine afterwards. A panic in the core ends only this run.
+pub fn run_item<T, F>(item: &BatchItem, config: &Config, inspect: F) -> BatchResult<T>
+    where F: FnOnce(&BatchItem, &mut Gba) -> T {
+    let start = Instant::now();
+    let mut result = BatchResult {
+        item: item.clone(),
+        outcome: BatchOutcome::Finished,
+        frames: 0,
+        cycles: 0,
+        elapsed: Duration::default(),
+        output: None,
+    };
+
+    let mut gba = match Gba::load(&item.rom.to_string_lossy(), config) {
+        Ok(gba) => gba,
+        Err(e) => {
+            result.outcome = BatchOutcome::LoadFailed(e.to_string());
+            result.elapsed = start.elapsed();
+            return result;
+        },
+    };
+    if let Err(e) = gba.mem().check_rom_header() {
+        result.outcome = BatchOutcome::BadHeader(e.to_string());
+        result.elapsed = start.elapsed();
+        return result;
+    }
+
+    let run = panic::catch_unwind(AssertUnwindSafe(|| {
+        for _ in 0..item.frames {
+            gba.run_frame();
+            result.frames += 1;
+        }
+    }));
+    result.cycles = gba.cycles();
+    match run {
+        Ok(()) => result.output = Some(inspect(item, &mut gba)),
+        Err(payload) => result.outcome = BatchOutcome::Crashed(panic_message(&*payload)),
+    }
+    result.elapsed = start.elapsed();
+    result
+}
+
+/// Runs many games on a pool of threads, each with its own Gba. Results
+/// come back in the order of the items, whichever thread ran them.
+#[derive(Clone, Debug)]
+pub struct BatchRunner {
+    threads: usize,
+    config: Config,
+}
+
+impl BatchRunner {
+    /// One worker per CPU the OS reports
+    pub fn new() -> BatchRunner {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        BatchRunner::with_threads(threads)
+    }
+
+    /// A runner using threads workers
+    pub fn with_threads(threads: usize) -> BatchRunner {
+        BatchRunner {
+            threads: threads.max(1),
+            config: Config::default(),
+        }
+    }
+
+    /// Per-game settings and the rest of the config every run loads with
+    pub fn set_config(&mut self, config: Config) {
+        self.config = config;
+    }
+
+    /// Workers running items at once
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Run items across the workers, calling inspect on each game that
+    /// finishes. Results are in the order of items.
+    pub fn run<T, F>(&self, items: &[BatchItem], inspect: F) -> Vec<BatchResult<T>>
+        where T: Send,
+              F: Fn(&BatchItem, &mut Gba) -> T + Sync {
+        let next = AtomicUsize::new(0);
+        let results: Mutex<Vec<Option<BatchResult<T>>>> =
+            Mutex::new(items.iter().map(|_| None).collect());
+
+        thread::scope(|scope| {
+            for _ in 0..self.threads.min(items.len()) {
+                scope.spawn(|| loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let item = match items.get(i) {
+                        Some(item) => item,
+                        None => break,
+                    };
+                    let result = run_item(item, &self.config, &inspect);
+                    results.lock().unwrap()[i] = Some(result);
+                });
+            }
+        });
+
+        results.into_inner().unwrap()
+            .into_iter()
+            .map(|result| result.expect("batch item was never run"))
+            .collect()
+    }
+}
+
+impl Default for BatchRunner {
+    fn default() -> BatchRunner {
+        BatchRunner::new()
+    }
+}
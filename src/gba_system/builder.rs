@@ -0,0 +1,113 @@
+// Building a machine from images already in memory, for embedders with no
+// filesystem to load from: the web page, fuzzers and tests.
+
+use std::io::Read;
+
+use gba_config::{Config, GameId, SaveType};
+use gba_cpu::ARM7;
+use gba_error::GbaResult;
+use gba_mem::Memory;
+
+use super::Gba;
+
+/// e.g. Gba::builder().rom(&rom).bios(&bios).config(config).build()
+#[derive(Debug, Default)]
+pub struct GbaBuilder {
+    rom: Option<Vec<u8>>, // The cartridge slot left empty if not given
+    bios: Option<Vec<u8>>, // The built in BIOS if not given
+    config: Config,
+}
+
+impl GbaBuilder {
+    /// No ROM, the built in BIOS and the default config
+    pub fn new() -> GbaBuilder {
+        GbaBuilder::default()
+    }
+
+    /// The cartridge ROM image
+    pub fn rom(mut self, rom: &[u8]) -> GbaBuilder {
+        self.rom = Some(rom.to_vec());
+        self
+    }
+
+    /// The cartridge ROM image, read to the end of reader
+    pub fn rom_reader<R: Read>(mut self, mut reader: R) -> GbaResult<GbaBuilder> {
+        let mut rom = Vec::new();
+        reader.read_to_end(&mut rom)?;
+        self.rom = Some(rom);
+        Ok(self)
+    }
+
+    /// A BIOS image to boot instead of the built in one
+    pub fn bios(mut self, bios: &[u8]) -> GbaBuilder {
+        self.bios = Some(bios.to_vec());
+        self
+    }
+
+    /// A BIOS image, read to the end of reader
+    pub fn bios_reader<R: Read>(mut self, mut reader: R) -> GbaResult<GbaBuilder> {
+        let mut bios = Vec::new();
+        reader.read_to_end(&mut bios)?;
+        self.bios = Some(bios);
+        Ok(self)
+    }
+
+    /// Settings to run with
+    pub fn config(mut self, config: Config) -> GbaBuilder {
+        self.config = config;
+        self
+    }
+
+    /// Power on a machine with what was given
+    pub fn build(self) -> GbaResult<Gba> {
+        let game = self.rom.as_ref().map(|rom| GameId::from_rom(rom));
+        let mut mem = Memory::with_rom_bytes(self.rom.as_ref().map_or(&[][..], |rom| &rom[..]))?;
+        if let Some(ref bios) = self.bios {
+            mem.set_bios(bios)?;
+        }
+
+        let mut gba = Gba::from_parts(ARM7::default(), mem);
+        if let Some(ref game) = game {
+            gba.settings = self.config.settings_for(game);
+        }
+        gba.slots = self.config.slots.clone();
+        gba.set_emulation_config(&self.config.emulation);
+        gba.game = game;
+        gba.save_library = SaveType::detect(gba.mem.rom()).map(|(save_type, _)| save_type);
+        gba.fit_cartridge();
+        if self.config.emulation.skip_bios {
+            gba.skip_bios();
+        }
+        Ok(gba)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gba_mem::{io_map, BUILTIN_BIOS};
+
+    #[test]
+    fn builds_from_memory() {
+        let rom = vec![0xAB; 0x200];
+        let gba = GbaBuilder::new().rom_reader(&rom[..]).unwrap()
+            .bios(BUILTIN_BIOS)
+            .build()
+            .unwrap();
+        assert!(gba.game().is_some());
+
+        assert!(GbaBuilder::new().build().unwrap().game().is_none());
+        assert!(GbaBuilder::new().rom(&vec![0; 0x2000001]).build().is_err());
+    }
+
+    #[test]
+    fn skips_the_bios() {
+        let mut config = Config::default();
+        config.emulation.skip_bios = true;
+        let gba = GbaBuilder::new().rom(&[0; 0x200]).config(config).build().unwrap();
+        assert_eq!(gba.cpu().pc(), 0x08000000);
+        assert_eq!(gba.cpu().reg(13).read(), 0x03007F00);
+        assert_eq!(gba.mem().io().ppu.dispcnt(), 0x0080);
+        assert_eq!(gba.mem().peek::<u8>(io_map::POSTFLG), 1);
+    }
+}
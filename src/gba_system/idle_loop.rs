@@ -0,0 +1,140 @@
+// Spots a game busy-waiting, e.g. polling VCOUNT or IF for the next
+// V-Blank, so the time it would spin for can be skipped. A loop is idle if
+// an iteration leaves the CPU exactly as the one before did, wrote nothing
+// and read nothing that changes by itself. The registers it polls only
+// change on a scheduled event, so until the next one it would keep going
+// round the same way.
+
+use gba_cpu::arm_cpu::CoreState;
+use gba_cpu::{RType, ARM7};
+use gba_mem::Memory;
+
+// Longest backward branch taken as closing a loop. Wait loops are a few
+// instructions, and larger ones are unlikely to be idle.
+const MAX_LOOP_BYTES: RType = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Iteration {
+    head: RType, // Where the loop branches back to
+    state: CoreState,
+    writes: u64,
+}
+
+/// Watches backward branches for a loop that is only waiting
+#[derive(Clone, Copy, Debug)]
+pub struct IdleLoop {
+    enabled: bool,
+    known: Option<RType>, // A loop from the game's settings, skipped unchecked
+    last: Option<Iteration>,
+    skip: bool,
+}
+
+impl Default for IdleLoop {
+    fn default() -> IdleLoop {
+        IdleLoop {
+            enabled: true,
+            known: None,
+            last: None,
+            skip: false,
+        }
+    }
+}
+
+impl IdleLoop {
+    /// Whether idle loops are looked for
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Look for idle loops, or stop
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.last = None;
+        self.skip = false;
+    }
+
+    /// Trust that a branch to addr always starts an idle loop, for games
+    /// whose wait loops the heuristic misses
+    pub fn set_known(&mut self, addr: Option<RType>) {
+        self.known = addr;
+    }
+
+    /// Look at the instruction just run from pc. Called after every one.
+    pub fn check(&mut self, pc: RType, cpu: &ARM7, mem: &Memory) {
+        if !self.enabled {
+            return;
+        }
+        let head = cpu.pc();
+        if head >= pc || pc - head > MAX_LOOP_BYTES {
+            return;
+        }
+        // The flag covers everything since the last backward branch, i.e.
+        // the iteration being checked
+        let volatile = mem.io().take_volatile_read();
+        if self.known == Some(head) {
+            self.skip = true;
+            return;
+        }
+
+        let iteration = Iteration {
+            head,
+            state: cpu.state(),
+            writes: mem.write_count(),
+        };
+        self.skip = !volatile && self.last == Some(iteration);
+        self.last = Some(iteration);
+    }
+
+    /// Whether the CPU is spinning in an idle loop, clearing the flag
+    pub fn take_skip(&mut self) -> bool {
+        let skip = self.skip;
+        self.skip = false;
+        skip
+    }
+
+    /// Whether the CPU is spinning in an idle loop, leaving the flag set
+    pub fn is_skip_pending(&self) -> bool {
+        self.skip
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The CPU as it branches back from the end of a three instruction wait
+    // loop, having read the register at addr
+    fn iterate(idle: &mut IdleLoop, cpu: &mut ARM7, mem: &Memory, addr: usize) {
+        let _ = mem.read::<u16>(addr);
+        cpu.set_pc(0x03000000);
+        idle.check(0x03000008, cpu, mem);
+    }
+
+    #[test]
+    fn second_identical_iteration_is_idle() {
+        let (mut cpu, mut mem) = (ARM7::default(), Memory::blank());
+        let mut idle = IdleLoop::default();
+        iterate(&mut idle, &mut cpu, &mem, 0x04000006);
+        assert!(!idle.take_skip());
+        iterate(&mut idle, &mut cpu, &mem, 0x04000006);
+        assert!(idle.take_skip());
+
+        // Writing anything in between means it is doing something
+        mem.write16::<u16>(0x02000000, 1);
+        iterate(&mut idle, &mut cpu, &mem, 0x04000006);
+        assert!(!idle.take_skip());
+    }
+
+    #[test]
+    fn timer_reads_are_not_idle() {
+        let (mut cpu, mem) = (ARM7::default(), Memory::blank());
+        let mut idle = IdleLoop::default();
+        iterate(&mut idle, &mut cpu, &mem, 0x04000100);
+        iterate(&mut idle, &mut cpu, &mem, 0x04000100);
+        assert!(!idle.take_skip());
+
+        idle.set_known(Some(0x03000000));
+        iterate(&mut idle, &mut cpu, &mem, 0x04000100);
+        assert!(idle.take_skip());
+    }
+}
@@ -0,0 +1,1057 @@
+/// Running many games headless for compatibility sweeps
+pub mod batch;
+/// Building a machine from images in memory
+pub mod builder;
+/// Skipping idle loops
+pub mod idle_loop;
+/// The test ROM runner
+#[cfg(feature = "config")]
+pub mod test_roms;
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use gba_apu::inspect::{ApuInfo, Channel};
+use gba_cheats::CheatEngine;
+use gba_config::{Config, EmulationConfig, FlashChip, GameId, GameSettings, Peripheral, RtcMode,
+                 SaveType, Sensor, SlotLayout, Strictness};
+use gba_cpu::{Core, UndefinedPolicy, ARM7};
+use gba_cpu::arm_cpu::MULTIBOOT_ENTRY;
+use gba_cpu::block_cache::BlockCache;
+#[cfg(feature = "jit")]
+use gba_cpu::jit::JitCache;
+use gba_debug::{GuestMem, Tracer};
+use gba_error::{GbaError, GbaResult};
+use gba_keypad::Button;
+use gba_keypad::hotkey::HotkeyAction;
+use gba_mem::{archive, io_map, Address, Memory};
+use gba_mem::eeprom::EepromSize;
+use gba_mem::gpio::GpioDevices;
+use gba_mem::io_regs::PowerState;
+use gba_mem::gpio::rtc::{RtcClock, RTC_EPOCH};
+use gba_ppu::{Layer, PpuEvents, FRAME_CYCLES};
+use gba_sio::{Disconnected, SerialDevice};
+use gba_sio::ereader::EReader;
+use gba_sio::gb_player::{self, GbPlayer};
+use gba_state::audit::{self, Divergence, StateAudit};
+use gba_state::battery;
+use gba_state::movie::{Movie, MovieMode};
+use gba_state::slots::{self, SlotInfo, Thumbnail, NUM_SLOTS};
+use gba_state::{SaveState, StateReader, StateWriter, STATE_MAGIC, STATE_VERSION};
+use gba_video::screenshot::Screenshot;
+
+use self::builder::GbaBuilder;
+use self::idle_loop::IdleLoop;
+
+/// Multiboot programs go in EWRAM, so they are loaded from their own
+/// extension rather than told apart by their header
+pub const MULTIBOOT_EXT: &str = "mb";
+
+// Header bytes the BIOS fills in after a link cable transfer, see:
+// http://problemkaputt.de/gbatek.htm#gbacartridgeheader
+const MULTIBOOT_MODE: Address = 0x020000C4;
+const MULTIBOOT_MODE_NORMAL: u8 = 2;
+
+// What the BIOS leaves in the IO registers and IWRAM when it starts the
+// cartridge, see:
+// http://problemkaputt.de/gbatek.htm#biosfunctions
+const BOOT_DISPCNT: u16 = 0x0080; // Forced blank
+const BOOT_SOUNDBIAS: u16 = 0x0200;
+const BOOT_POSTFLG: u8 = 1; // Past the first boot
+const SOFT_RESET_FLAG: Address = 0x03007FFA; // Where SoftReset restarts: zero for ROM
+
+// A frontend's rumble handler, wrapped so Gba can still be Debug
+struct RumbleCallback(Box<dyn FnMut(bool)>);
+
+impl fmt::Debug for RumbleCallback {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write![f, "RumbleCallback"]
+    }
+}
+
+// What runs the CPU's instructions
+#[derive(Debug)]
+enum Engine {
+    Interpreter,
+    Cached(BlockCache),
+    #[cfg(feature = "jit")]
+    Jit(Box<JitCache>),
+}
+
+/// A whole console: the CPU and everything on its bus. This is the entry
+/// point for programs embedding the emulator without a frontend.
+#[derive(Debug)]
+pub struct Gba {
+    cpu: ARM7,
+    mem: Memory,
+    cycles: u64,
+    game: Option<GameId>,
+    settings: GameSettings,
+    slots: SlotLayout,
+    tracer: Option<Tracer>,
+    rumble: Option<RumbleCallback>,
+    movie: Option<MovieMode>,
+    cheats: CheatEngine,
+    peripheral: Peripheral, // Plugged in for the settings
+    engine: Engine,
+    idle: IdleLoop,
+    save_library: Option<SaveType>, // Found in the ROM, for an auto save type
+    audit: Option<StateAudit>, // State hashes of the frames run since it started
+}
+
+impl Gba {
+    /// Load a ROM with the default settings
+    pub fn new(pak_filename: &str) -> GbaResult<Gba> {
+        Gba::load(pak_filename, &Config::default())
+    }
+
+    /// Load a game, applying any settings the config has for it. Multiboot
+    /// programs boot without a cartridge; see load_multiboot.
+    pub fn load(pak_filename: &str, config: &Config) -> GbaResult<Gba> {
+        if is_multiboot_file(pak_filename) {
+            return Gba::load_multiboot(pak_filename, config);
+        }
+        Gba::builder().rom(&archive::read_rom(pak_filename)?).config(config.clone()).build()
+    }
+
+    /// Load a game from an image already in memory, e.g. one a web page was
+    /// handed, with the built in BIOS
+    pub fn load_bytes(rom: &[u8], config: &Config) -> GbaResult<Gba> {
+        Gba::builder().rom(rom).config(config.clone()).build()
+    }
+
+    /// Set up a machine from ROM and BIOS images in memory or in readers
+    pub fn builder() -> GbaBuilder {
+        GbaBuilder::new()
+    }
+
+    /// Boot a multiboot program with the cartridge slot empty. The BIOS
+    /// isn't run: the machine starts as the BIOS leaves it once it has
+    /// received the program from a master GBA in Normal mode, at the RAM
+    /// entry point in the header.
+    pub fn load_multiboot(mb_filename: &str, config: &Config) -> GbaResult<Gba> {
+        let game = GameId::from_file(mb_filename)?;
+        let mut gba = Gba::from_parts(ARM7::default(), Memory::new_multiboot(mb_filename)?);
+        gba.skip_bios();
+        gba.cpu.set_pc(MULTIBOOT_ENTRY);
+        gba.mem.write8::<u8>(MULTIBOOT_MODE, MULTIBOOT_MODE_NORMAL);
+
+        gba.settings = config.settings_for(&game);
+        gba.slots = config.slots.clone();
+        gba.set_emulation_config(&config.emulation);
+        gba.game = Some(game);
+        gba.fit_cartridge();
+        Ok(gba)
+    }
+
+    /// A machine from a CPU and memory already set up, with nothing else fitted
+    pub fn from_parts(cpu: ARM7, mem: Memory) -> Gba {
+        Gba {
+            cpu,
+            mem,
+            cycles: 0,
+            game: None,
+            settings: GameSettings::default(),
+            slots: SlotLayout::default(),
+            tracer: None,
+            rumble: None,
+            movie: None,
+            cheats: CheatEngine::default(),
+            peripheral: Peripheral::None,
+            engine: Engine::Interpreter,
+            idle: IdleLoop::default(),
+            save_library: None,
+            audit: None,
+        }
+    }
+
+    /// Boot from a BIOS dump instead of the built in one
+    pub fn load_bios<P: AsRef<Path>>(&mut self, path: P) -> GbaResult<()> {
+        let bios = fs::read(path)?;
+        self.mem.set_bios(&bios)
+    }
+
+    /// Put the machine in the state the BIOS leaves it in once its intro
+    /// has played, at the cartridge entry point, instead of running the
+    /// BIOS. The BIOS won't start a cartridge whose header fails its
+    /// checks; this does, with a warning, as other emulators' skip intro
+    /// options do.
+    pub fn skip_bios(&mut self) {
+        if let Err(e) = self.mem.check_rom_header() {
+            if self.game.is_some() {
+                warn!(target: "boot", "Skipping the BIOS, which wouldn't boot this: {}", e);
+            }
+        }
+        self.cpu.skip_bios();
+        self.mem.write16::<u16>(io_map::DISPCNT, BOOT_DISPCNT);
+        self.mem.write16::<u16>(io_map::SOUNDBIAS, BOOT_SOUNDBIAS);
+        self.mem.write8::<u8>(io_map::POSTFLG, BOOT_POSTFLG);
+        self.mem.write8::<u8>(SOFT_RESET_FLAG, 0);
+    }
+
+    /// IDs of the loaded game, if one was loaded from a file
+    pub fn game(&self) -> Option<&GameId> {
+        self.game.as_ref()
+    }
+
+    /// Settings in effect for the loaded game
+    pub fn settings(&self) -> &GameSettings {
+        &self.settings
+    }
+
+    /// Replace the game's settings. Hardware they fit is only fitted on load.
+    pub fn set_settings(&mut self, settings: GameSettings) {
+        self.settings = settings;
+        self.fit_cartridge();
+    }
+
+    // Fit the cartridge with the extra hardware the settings ask for. A
+    // clock that is already fitted keeps running.
+    fn fit_cartridge(&mut self) {
+        self.idle.set_known(self.settings.idle_loop);
+        let has_rtc = self.game.as_ref().is_some_and(|game| game.has_rtc());
+        let rtc = match self.settings.rtc {
+            RtcMode::Auto if has_rtc => Some(RtcClock::Host),
+            RtcMode::Auto | RtcMode::None => None,
+            RtcMode::Host => Some(RtcClock::Host),
+            RtcMode::Fixed => Some(RtcClock::Fixed(self.settings.rtc_start.unwrap_or(RTC_EPOCH))),
+        };
+        let sensors = match self.settings.sensors {
+            Some(ref sensors) => sensors.clone(),
+            None => self.game.as_ref().map_or(Vec::new(), |game| game.sensors()),
+        };
+
+        let rumble = self.settings.rumble
+            .unwrap_or_else(|| self.game.as_ref().is_some_and(|game| game.has_rumble()));
+
+        self.mem.gpio_mut().fit(GpioDevices {
+            rtc,
+            solar: sensors.contains(&Sensor::Solar),
+            gyro: sensors.contains(&Sensor::Gyro),
+            rumble,
+        });
+        self.mem.tilt_mut().set_fitted(sensors.contains(&Sensor::Tilt));
+
+        let save_type = match self.settings.save_type {
+            SaveType::Auto => self.save_library.unwrap_or(SaveType::None),
+            save_type => save_type,
+        };
+        // The library only says it's an EEPROM, not how big
+        let (eeprom, eeprom_size) = match self.settings.save_type {
+            SaveType::Auto => (save_type == SaveType::Eeprom512, None),
+            SaveType::Eeprom512 => (true, Some(EepromSize::Small)),
+            SaveType::Eeprom8k => (true, Some(EepromSize::Large)),
+            _ => (false, None),
+        };
+        self.mem.fit_eeprom(eeprom, eeprom_size);
+        let flash = match save_type {
+            SaveType::Flash64 | SaveType::Flash128 =>
+                self.settings.flash_chip.or_else(|| FlashChip::default_for(save_type)),
+            _ => None,
+        };
+        self.mem.flash_mut().fit(flash.map(|chip| chip.id()));
+
+        for patch in &self.settings.patches {
+            self.mem.patch_rom(patch.addr as Address, &patch.bytes);
+        }
+
+        // Unplugging a peripheral leaves the port empty, but with none
+        // asked for, a device the frontend plugged in stays
+        if self.settings.peripheral != self.peripheral {
+            let device: Box<dyn SerialDevice> = match self.settings.peripheral {
+                Peripheral::None => Box::new(Disconnected),
+                Peripheral::GbPlayer => Box::new(GbPlayer::default()),
+                Peripheral::EReader => Box::new(EReader),
+            };
+            self.set_link_device(device);
+            self.peripheral = self.settings.peripheral;
+        }
+    }
+
+    /// Log every instruction executed from now on through tracer
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    /// The tracer, if one is set
+    pub fn tracer_mut(&mut self) -> Option<&mut Tracer> {
+        self.tracer.as_mut()
+    }
+
+    /// Stop tracing, handing back the tracer
+    pub fn take_tracer(&mut self) -> Option<Tracer> {
+        self.tracer.take()
+    }
+
+    /// The CPU
+    pub fn cpu(&self) -> &ARM7 {
+        &self.cpu
+    }
+
+    /// The CPU, for writing
+    pub fn cpu_mut(&mut self) -> &mut ARM7 {
+        &mut self.cpu
+    }
+
+    /// The memory map and everything on the bus
+    pub fn mem(&self) -> &Memory {
+        &self.mem
+    }
+
+    /// The memory map and everything on the bus, for writing
+    pub fn mem_mut(&mut self) -> &mut Memory {
+        &mut self.mem
+    }
+
+    /// Typed, side effect free reads of game data
+    pub fn guest(&self) -> GuestMem<'_> {
+        GuestMem::new(&self.mem)
+    }
+
+    /// CPU cycles run since power on
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Frames completed since power on
+    pub fn frame(&self) -> u64 {
+        self.mem.io().ppu.frame()
+    }
+
+    /// The last frame the PPU finished, as RGBA
+    pub fn screenshot(&self) -> Screenshot {
+        Screenshot::from_framebuffer(self.mem.io().ppu.framebuffer())
+    }
+
+    /// What each sound channel is doing
+    pub fn apu_info(&self) -> ApuInfo {
+        self.mem.io().apu.info()
+    }
+
+    /// Leave a channel out of what is heard and recorded. The game can't
+    /// tell.
+    pub fn set_channel_muted(&mut self, ch: Channel, muted: bool) {
+        self.mem.io_mut().apu.set_muted(ch, muted);
+    }
+
+    /// Hear only one channel, or with None all of them again
+    pub fn solo_channel(&mut self, ch: Option<Channel>) {
+        self.mem.io_mut().apu.solo(ch);
+    }
+
+    /// Leave a background or the sprites out of the picture, for debugging
+    /// rendering or capturing a scene without its HUD. The game can't tell.
+    pub fn set_layer_hidden(&mut self, layer: Layer, hidden: bool) {
+        self.mem.io_mut().ppu.set_layer_hidden(layer, hidden);
+    }
+
+    /// Whether a layer is hidden
+    pub fn is_layer_hidden(&self, layer: Layer) -> bool {
+        self.mem.io().ppu.is_layer_hidden(layer)
+    }
+
+    /// Ignored while a movie is playing, which supplies the input itself
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        if let Some(MovieMode::Playing { .. }) = self.movie {
+            return;
+        }
+        self.mem.io_mut().set_button(button, pressed);
+    }
+
+    /// Next hotkey chord completed on the emulated pad
+    pub fn take_hotkey(&mut self) -> Option<HotkeyAction> {
+        self.mem.io_mut().keypad.take_hotkey()
+    }
+
+    // CRC of the loaded ROM, which save states are tied to
+    fn game_crc(&self) -> u32 {
+        self.game.as_ref().map_or(0, |game| game.crc32)
+    }
+
+    /// Snapshot of the whole machine: CPU, RAM, IO registers and the
+    /// hardware counters behind them
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        for &b in STATE_MAGIC.iter() {
+            w.write_u8(b);
+        }
+        w.write_u32(STATE_VERSION);
+        w.write_u32(self.game_crc());
+        self.cpu.save_state(&mut w);
+        self.mem.save_state(&mut w);
+        self.cycles.save_state(&mut w);
+        w.into_bytes()
+    }
+
+    /// Restore a snapshot taken by save_state. A bad state leaves the
+    /// machine untouched: the header and size are checked first, and the
+    /// parts are loaded over a snapshot that is put back if one is bad.
+    pub fn load_state(&mut self, data: &[u8]) -> GbaResult<()> {
+        let mut r = StateReader::new(data);
+        self.read_state_header(&mut r)?;
+        let backup = self.save_state();
+        if data.len() != backup.len() {
+            return Err(GbaError::InvalidSaveState(
+                format!("{} Bytes long, expected {}", data.len(), backup.len())));
+        }
+
+        if let Err(e) = self.load_parts(&mut r) {
+            let mut r = StateReader::new(&backup);
+            self.read_state_header(&mut r).and_then(|_| self.load_parts(&mut r))
+                .expect("a state just saved loads back");
+            return Err(e);
+        }
+        match self.engine {
+            Engine::Interpreter => {},
+            Engine::Cached(ref mut cache) => cache.clear(),
+            #[cfg(feature = "jit")]
+            Engine::Jit(ref mut jit) => jit.clear(),
+        }
+        Ok(())
+    }
+
+    fn read_state_header(&self, r: &mut StateReader) -> GbaResult<()> {
+        let mut magic = [0; 4];
+        for b in magic.iter_mut() {
+            *b = r.read_u8()?;
+        }
+        if &magic != STATE_MAGIC {
+            return Err(GbaError::InvalidSaveState("not a save state".to_string()));
+        }
+        let version = r.read_u32()?;
+        if version != STATE_VERSION {
+            return Err(GbaError::InvalidSaveState(
+                format!("version {} is not supported (expected {})", version, STATE_VERSION)));
+        }
+        let crc = r.read_u32()?;
+        if crc != self.game_crc() {
+            return Err(GbaError::InvalidSaveState(
+                format!("made with a different ROM (crc32 {:08x})", crc)));
+        }
+        Ok(())
+    }
+
+    fn load_parts(&mut self, r: &mut StateReader) -> GbaResult<()> {
+        self.cpu.load_state(r)?;
+        self.mem.load_state(r)?;
+        self.cycles.load_state(r)
+    }
+
+    /// Just the cartridge's battery-backed data, in a format that stays
+    /// stable across emulator versions
+    pub fn export_battery(&self) -> Vec<u8> {
+        battery::export(&self.mem)
+    }
+
+    /// Replace the cartridge's backup memory with a battery save file
+    pub fn import_battery(&mut self, data: &[u8]) -> GbaResult<()> {
+        battery::import(&mut self.mem, data)
+    }
+
+    /// Where save slots are kept
+    pub fn slot_layout(&self) -> &SlotLayout {
+        &self.slots
+    }
+
+    /// Change where save slots are kept
+    pub fn set_slot_layout(&mut self, layout: SlotLayout) {
+        self.slots = layout;
+    }
+
+    /// File the layout keeps save slot n of the loaded game in
+    pub fn slot_path(&self, n: u32) -> PathBuf {
+        let unknown = GameId { code: None, crc32: self.game_crc() };
+        self.slots.path(self.game.as_ref().unwrap_or(&unknown), n)
+    }
+
+    /// Save state into slot n along with when it was taken and the screen
+    pub fn save_slot(&mut self, n: u32) -> GbaResult<SlotInfo> {
+        let info = SlotInfo {
+            slot: n,
+            saved: slots::now(),
+            frame: self.frame(),
+            thumbnail: Thumbnail::from_frame(self.mem.io().ppu.framebuffer()),
+        };
+        slots::write(&self.slot_path(n), &info, &self.save_state())?;
+        Ok(info)
+    }
+
+    /// Load the state in slot n
+    pub fn load_slot(&mut self, n: u32) -> GbaResult<SlotInfo> {
+        let data = match slots::read(&self.slot_path(n))? {
+            Some(data) => data,
+            None => return Err(GbaError::InvalidSaveState(format!("slot {} is empty", n))),
+        };
+        let (info, state) = slots::decode(n, &data)?;
+        self.load_state(state)?;
+        Ok(info)
+    }
+
+    /// The filled slots of the loaded game. Slots that can't be read are
+    /// left out rather than failing the whole list.
+    pub fn list_slots(&self) -> Vec<SlotInfo> {
+        (0..NUM_SLOTS)
+            .filter_map(|n| {
+                let data = slots::read(&self.slot_path(n)).ok()??;
+                slots::decode(n, &data).ok().map(|(info, _)| info)
+            })
+            .collect()
+    }
+
+    /// Execute one instruction, or under the JIT a run of them up to a
+    /// branch, and advance the rest of the hardware alongside. While the
+    /// CPU is halted, or spinning in an idle loop, there is no instruction,
+    /// and the hardware is skipped ahead to its next event instead.
+    pub fn step(&mut self) -> PpuEvents {
+        self.step_engine(true)
+    }
+
+    /// Execute exactly one instruction, which under the JIT step might not,
+    /// e.g. to stop at a breakpoint
+    pub fn step_instruction(&mut self) -> PpuEvents {
+        self.step_engine(false)
+    }
+
+    // The JIT runs to the exit of a block in one go unless tracing, which
+    // looks at every instruction
+    #[cfg_attr(not(feature = "jit"), allow(unused_variables))]
+    fn step_engine(&mut self, whole_block: bool) -> PpuEvents {
+        if self.mem.io().power != PowerState::Running || self.idle.take_skip() {
+            return self.step_asleep();
+        }
+
+        let pc = self.cpu.pc();
+        let trace = self.tracer.as_ref()
+            .and_then(|tracer| tracer.start(&self.cpu, &self.mem, self.cycles));
+        // The address of the last instruction run
+        let last = match self.engine {
+            Engine::Interpreter => {
+                self.cpu.step(&mut self.mem);
+                pc
+            },
+            Engine::Cached(ref mut cache) => {
+                cache.step(&mut self.cpu, &mut self.mem);
+                pc
+            },
+            #[cfg(feature = "jit")]
+            Engine::Jit(ref mut jit) if whole_block && self.tracer.is_none() =>
+                jit.run(&mut self.cpu, &mut self.mem),
+            #[cfg(feature = "jit")]
+            Engine::Jit(ref mut jit) => {
+                jit.step(&mut self.cpu, &mut self.mem);
+                pc
+            },
+        };
+        if let (Some(pending), Some(tracer)) = (trace, self.tracer.as_mut()) {
+            tracer.finish(pending, &self.cpu);
+        }
+
+        // Only reported when the bus is in strict mode
+        if let Some(abort) = self.mem.take_abort() {
+            debug!(target: "cpu", "{} abort at {:#010x} by the instruction at {:#010x}",
+                   if abort.write { "write" } else { "read" }, abort.addr, pc);
+            if !abort.write && abort.addr == pc as Address {
+                self.cpu.prefetch_abort(pc);
+            }
+            else {
+                self.cpu.data_abort(pc);
+            }
+        }
+        self.idle.check(last, &self.cpu, &self.mem);
+
+        // Instructions are charged for their bus accesses. Internal cycles
+        // aren't modelled yet, so every instruction costs at least one.
+        let cycles = self.mem.take_cycles().max(1);
+        self.cycles += cycles as u64;
+        let events = self.mem.step(cycles);
+        self.mem.io_mut().update_power();
+        self.check_irq();
+
+        let rumble = self.mem.gpio_mut().rumble_mut().and_then(|r| r.take_change())
+            .or_else(|| self.mem.io_mut().sio.device_mut().take_rumble());
+        if let Some(on) = rumble {
+            if let Some(ref mut callback) = self.rumble {
+                (callback.0)(on);
+            }
+        }
+        events
+    }
+
+    fn step_asleep(&mut self) -> PpuEvents {
+        let cycles = self.mem.io().cycles_to_event();
+        self.cycles += cycles as u64;
+        let events = if self.mem.io().power == PowerState::Stopped {
+            self.mem.io_mut().step_stopped(cycles)
+        }
+        else {
+            self.mem.step(cycles)
+        };
+        self.mem.io_mut().update_power();
+        self.check_irq();
+        events
+    }
+
+    // Enter the IRQ handler if an enabled interrupt is waiting and neither
+    // IME nor the CPSR's I bit masks it, including straight after an
+    // interrupt wakes the CPU from a halt
+    fn check_irq(&mut self) {
+        if self.mem.io().power == PowerState::Running &&
+           self.mem.io().irq.is_pending() && !self.cpu.is_irq_disable() {
+            trace!(target: "irq", "IRQ taken at {:#010x}", self.cpu.pc());
+            self.cpu.interrupt();
+        }
+    }
+
+    /// Whether the next step skips time rather than running an instruction
+    pub fn is_asleep(&self) -> bool {
+        self.mem.io().power != PowerState::Running || self.idle.is_skip_pending()
+    }
+
+    /// Skip the time games spend busy-waiting for the next interrupt. It's
+    /// on unless turned off; the loops can also be given in the settings.
+    pub fn set_idle_loop_skip(&mut self, enabled: bool) {
+        self.idle.set_enabled(enabled);
+    }
+
+    /// Whether idle loops are skipped
+    pub fn idle_loop_skip(&self) -> bool {
+        self.idle.is_enabled()
+    }
+
+    /// Apply the emulation settings that can change while running
+    pub fn set_emulation_config(&mut self, config: &EmulationConfig) {
+        self.mem.set_strict_aborts(config.strictness == Strictness::Strict);
+    }
+
+    /// What the CPU does on an instruction it can't decode
+    pub fn set_undefined_policy(&mut self, policy: UndefinedPolicy) {
+        self.cpu.set_undefined_policy(policy);
+    }
+
+    /// What the CPU does with an instruction it can't execute
+    pub fn undefined_policy(&self) -> UndefinedPolicy {
+        self.cpu.undefined_policy()
+    }
+
+    /// Switch between the cached and the plain interpreter. They run the
+    /// same instructions with the same timing.
+    pub fn set_cached_interpreter(&mut self, cached: bool) {
+        self.engine = if cached {
+            Engine::Cached(BlockCache::default())
+        }
+        else {
+            Engine::Interpreter
+        };
+    }
+
+    /// The block cache, if it is the engine in use
+    pub fn block_cache(&self) -> Option<&BlockCache> {
+        match self.engine {
+            Engine::Cached(ref cache) => Some(cache),
+            _ => None,
+        }
+    }
+
+    /// Switch between the JIT and the plain interpreter, which run the same
+    /// instructions with the same timing, though step runs a whole block
+    /// under the JIT. Fails if the JIT can't generate code for the host.
+    #[cfg(feature = "jit")]
+    pub fn set_jit(&mut self, jit: bool) -> GbaResult<()> {
+        self.engine = if jit { Engine::Jit(Box::new(JitCache::new()?)) } else { Engine::Interpreter };
+        Ok(())
+    }
+
+    /// The JIT, if it is the engine in use
+    #[cfg(feature = "jit")]
+    pub fn jit(&self) -> Option<&JitCache> {
+        match self.engine {
+            Engine::Jit(ref jit) => Some(&**jit),
+            _ => None,
+        }
+    }
+
+    /// Call f with the new state whenever the cartridge's rumble motor, or
+    /// the Game Boy Player's, switches on or off, e.g. to drive a gamepad's
+    pub fn set_rumble_callback<F>(&mut self, f: F)
+        where F: FnMut(bool) + 'static {
+        self.rumble = Some(RumbleCallback(Box::new(f)));
+    }
+
+    /// Stop telling the frontend about the rumble motor
+    pub fn clear_rumble_callback(&mut self) {
+        self.rumble = None;
+    }
+
+    /// Plug a device into the link port, returning what was there before.
+    /// Nothing is connected until this is called.
+    pub fn set_link_device(&mut self, device: Box<dyn SerialDevice>) -> Box<dyn SerialDevice> {
+        self.mem.io_mut().sio.set_device(device)
+    }
+
+    /// Run until the PPU finishes the current frame. Movies record and play
+    /// back input here, so frame advance is this called once. Cheats are
+    /// applied as the frame starts, before the game reads anything.
+    pub fn run_frame(&mut self) {
+        self.begin_frame();
+        while !self.step().frame_complete {}
+        self.audit_frame();
+    }
+
+    /// What run_frame does before running any instructions, for callers
+    /// that step through the frame themselves
+    pub fn begin_frame(&mut self) {
+        self.movie_frame();
+        self.detect_gb_player();
+        if self.settings.cheats_enabled {
+            self.cheats.apply(&mut self.mem);
+        }
+    }
+
+    // The Game Boy Player shows itself by holding all four directions
+    // while the game boots
+    fn detect_gb_player(&mut self) {
+        let detecting = self.peripheral == Peripheral::GbPlayer &&
+            self.cycles / (FRAME_CYCLES as u64) < gb_player::DETECT_FRAMES;
+        let forced = if detecting {
+            Button::Up as u16 | Button::Down as u16 | Button::Left as u16 | Button::Right as u16
+        }
+        else {
+            0
+        };
+        let io = self.mem.io_mut();
+        io.keypad.set_forced(forced, &mut io.irq);
+    }
+
+    /// Hash of the whole machine's state, equal for two machines that will
+    /// go on to behave the same
+    pub fn state_hash(&self) -> u64 {
+        audit::state_hash(&self.save_state())
+    }
+
+    /// Hash the state after every frame from now on, checking each against
+    /// the reference stream if one is given
+    pub fn start_audit(&mut self, reference: Option<Vec<u64>>) {
+        self.audit = Some(reference.map_or_else(StateAudit::new, StateAudit::against));
+    }
+
+    /// The audit in progress, if any
+    pub fn audit(&self) -> Option<&StateAudit> {
+        self.audit.as_ref()
+    }
+
+    /// Stop the audit, returning it
+    pub fn stop_audit(&mut self) -> Option<StateAudit> {
+        self.audit.take()
+    }
+
+    fn audit_frame(&mut self) {
+        if self.audit.is_none() {
+            return;
+        }
+        let hash = self.state_hash();
+        if let Some(divergence) = self.audit.as_mut().and_then(|audit| audit.record(hash)) {
+            warn!(target: "debug", "{}", divergence);
+        }
+    }
+
+    /// Run the same frames twice from the current state and compare the
+    /// state after each, leaving the machine as the second run left it.
+    /// Anything found is nondeterminism in the emulator, not the game.
+    pub fn audit_determinism(&mut self, frames: u64) -> GbaResult<Option<Divergence>> {
+        let start = self.save_state();
+        let mut runs = Vec::new();
+        for _ in 0..2 {
+            self.load_state(&start)?;
+            runs.push((0..frames).map(|_| {
+                self.run_frame();
+                self.state_hash()
+            }).collect::<Vec<_>>());
+        }
+        Ok(audit::compare(&runs[0], &runs[1]))
+    }
+
+    /// The game's cheats
+    pub fn cheats(&self) -> &CheatEngine {
+        &self.cheats
+    }
+
+    /// The game's cheats, for adding and removing them
+    pub fn cheats_mut(&mut self) -> &mut CheatEngine {
+        &mut self.cheats
+    }
+
+    /// Start recording input from the current state
+    pub fn record_movie(&mut self) {
+        let movie = Movie::new(self.game_crc(), self.save_state());
+        self.movie = Some(MovieMode::Recording(movie));
+    }
+
+    /// Load the movie's starting state and replay its input from there. It
+    /// must have been recorded with this ROM.
+    pub fn play_movie(&mut self, movie: Movie) -> GbaResult<()> {
+        if movie.rom_crc32 != self.game_crc() {
+            return Err(GbaError::InvalidSaveState(
+                format!("movie was recorded with ROM {:08x}, not {:08x}",
+                        movie.rom_crc32, self.game_crc())));
+        }
+        self.load_state(&movie.start)?;
+        self.movie = Some(MovieMode::Playing { movie, frame: 0 });
+        Ok(())
+    }
+
+    /// Stop recording or playing, returning the movie
+    pub fn stop_movie(&mut self) -> Option<Movie> {
+        self.movie.take().map(|mode| match mode {
+            MovieMode::Recording(movie) => movie,
+            MovieMode::Playing { movie, .. } => movie,
+        })
+    }
+
+    /// The movie being recorded or played, if any
+    pub fn movie_mode(&self) -> Option<&MovieMode> {
+        self.movie.as_ref()
+    }
+
+    // Record or apply the input of the frame about to run. Playback hands
+    // the pad back, with nothing held, once the movie runs out.
+    fn movie_frame(&mut self) {
+        let input = match self.movie {
+            Some(MovieMode::Recording(ref mut movie)) => {
+                movie.input.push(self.mem.io().keypad.visible());
+                return;
+            },
+            Some(MovieMode::Playing { ref movie, ref mut frame }) => {
+                let input = movie.input.get(*frame).cloned();
+                *frame += 1;
+                input
+            },
+            None => return,
+        };
+
+        let io = self.mem.io_mut();
+        io.keypad.set_held(input.unwrap_or(0), &mut io.irq);
+        if input.is_none() {
+            self.movie = None;
+        }
+    }
+
+    /// Run for at least n cycles
+    pub fn run_cycles(&mut self, n: u64) {
+        let end = self.cycles + n;
+        while self.cycles < end {
+            self.step();
+        }
+    }
+
+    /// Run until cond holds, checking after every instruction. Returns
+    /// immediately if it already holds.
+    pub fn run_until<F>(&mut self, mut cond: F)
+        where F: FnMut(&Gba) -> bool {
+        while !cond(self) {
+            self.step_instruction();
+        }
+    }
+}
+
+impl fmt::Display for Gba {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write![f, "{}", self.cpu]?;
+        writeln![f, "{}", self.mem.io().ppu]?;
+        write![f, "cycles: {}", self.cycles]
+    }
+}
+
+/// Whether a file is a multiboot image rather than a cartridge ROM
+pub fn is_multiboot_file(filename: &str) -> bool {
+    Path::new(filename).extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case(MULTIBOOT_EXT))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gba_cpu::arm_cpu::ARM7Mode;
+    use gba_irq::Interrupt;
+
+    #[test]
+    fn bad_states_leave_the_machine_untouched() {
+        let mut gba = Gba::builder().rom(&[0; 0x200]).build().unwrap();
+        gba.mem.fit_eeprom(true, Some(EepromSize::Small));
+        let small = gba.save_state();
+        gba.mem.fit_eeprom(true, Some(EepromSize::Large));
+        let at = small.iter().zip(gba.save_state()).position(|(&a, b)| a != b).unwrap();
+
+        // Everything before the EEPROM loads fine, so would be half loaded
+        let mut bad = small.clone();
+        bad[at] = 7;
+        gba.run_frame();
+        let before = gba.save_state();
+        match gba.load_state(&bad) {
+            Err(GbaError::InvalidSaveState(msg)) => assert!(msg.contains("EEPROM")),
+            other => panic!("expected a bad EEPROM size, got {:?}", other),
+        }
+        assert_eq!(gba.save_state(), before);
+        assert_eq!(gba.mem.eeprom().size(), Some(EepromSize::Large));
+
+        gba.load_state(&small).unwrap();
+        assert_eq!(gba.save_state(), small);
+    }
+
+    #[test]
+    fn states_load_back_exactly() {
+        let mut gba = Gba::builder().rom(&[0; 0x200]).build().unwrap();
+        gba.skip_bios();
+        gba.mem.write8(0x02000010, 0x5Au8);
+        gba.run_frame();
+        let state = gba.save_state();
+
+        gba.run_frame();
+        gba.mem.write8(0x02000010, 0u8);
+        gba.load_state(&state).unwrap();
+        assert_eq!(gba.save_state(), state);
+        assert_eq!((gba.frame(), gba.mem.peek::<u8>(0x02000010)), (1, 0x5A));
+
+        // A fresh machine given the state runs on the same as the original
+        let mut other = Gba::builder().rom(&[0; 0x200]).build().unwrap();
+        other.load_state(&state).unwrap();
+        gba.run_frame();
+        other.run_frame();
+        assert_eq!(other.save_state(), gba.save_state());
+    }
+
+    #[test]
+    fn states_from_elsewhere_are_rejected() {
+        let mut gba = Gba::builder().rom(&[0; 0x200]).build().unwrap();
+        let state = gba.save_state();
+        let other = Gba::builder().rom(&[1; 0x200]).build().unwrap();
+        let check = |gba: &mut Gba, state: &[u8], expected: &str| {
+            match gba.load_state(state) {
+                Err(GbaError::InvalidSaveState(msg)) => assert!(msg.contains(expected), "{}", msg),
+                other => panic!("expected {:?}, got {:?}", expected, other),
+            }
+        };
+
+        check(&mut gba, &other.save_state(), "different ROM");
+        let mut old = state.clone();
+        old[STATE_MAGIC.len()..STATE_MAGIC.len() + 4]
+            .copy_from_slice(&(STATE_VERSION - 1).to_le_bytes());
+        check(&mut gba, &old, "not supported");
+        check(&mut gba, &state[..state.len() - 1], "Bytes long");
+        check(&mut gba, b"GBA!", "not a save state");
+        assert_eq!(gba.save_state(), state);
+    }
+
+    #[test]
+    fn skipping_the_bios_sets_up_the_boot_registers() {
+        let mut gba = Gba::builder().rom(&[0; 0x200]).build().unwrap();
+        gba.mem.write8(SOFT_RESET_FLAG, 1u8);
+        gba.skip_bios();
+        assert_eq!(gba.cpu.pc(), 0x08000000);
+        assert_eq!(gba.cpu.mode(), ARM7Mode::System);
+        assert!(!gba.cpu.is_irq_disable());
+        assert_eq!(gba.cpu.reg_in_mode(13, ARM7Mode::System), 0x03007F00);
+        assert_eq!(gba.cpu.reg_in_mode(13, ARM7Mode::IRQ), 0x03007FA0);
+        assert_eq!(gba.cpu.reg_in_mode(13, ARM7Mode::Supervisor), 0x03007FE0);
+        assert!((0..13).all(|r| gba.cpu.reg(r).read() == 0));
+
+        assert_eq!(gba.mem.peek::<u16>(io_map::DISPCNT), 0x0080);
+        assert_eq!(gba.mem.peek::<u16>(io_map::SOUNDBIAS), 0x0200);
+        assert_eq!(gba.mem.peek::<u8>(io_map::POSTFLG), 1);
+        assert_eq!(gba.mem.peek::<u8>(SOFT_RESET_FLAG), 0);
+    }
+
+    #[test]
+    fn movies_replay_the_recorded_input() {
+        let mut gba = Gba::builder().rom(&[0; 0x200]).build().unwrap();
+        gba.run_frame();
+        gba.record_movie();
+        let mut seen = Vec::new();
+        for i in 0..8 {
+            gba.set_button(Button::A, i % 2 == 0);
+            gba.set_button(Button::Start, i == 3);
+            gba.run_frame();
+            seen.push(gba.mem.io().keypad.visible());
+        }
+        let end = gba.save_state();
+        let movie = Movie::decode(&gba.stop_movie().unwrap().encode()).unwrap();
+        assert_eq!(movie.frames(), 8);
+        assert_eq!(movie.input, seen);
+
+        // The pad is ignored while the movie plays
+        let mut other = Gba::builder().rom(&[0; 0x200]).build().unwrap();
+        other.play_movie(movie.clone()).unwrap();
+        let mut replayed = Vec::new();
+        for _ in 0..8 {
+            other.set_button(Button::B, true);
+            other.run_frame();
+            replayed.push(other.mem.io().keypad.visible());
+        }
+        assert_eq!(replayed, seen);
+        assert_eq!(other.save_state(), end);
+
+        // Running out hands the pad back with nothing held
+        other.run_frame();
+        assert!(other.movie_mode().is_none());
+        assert_eq!(other.mem.io().keypad.visible(), 0);
+
+        let mut wrong_rom = Gba::builder().rom(&[1; 0x200]).build().unwrap();
+        let before = wrong_rom.save_state();
+        match wrong_rom.play_movie(movie) {
+            Err(GbaError::InvalidSaveState(msg)) => assert!(msg.contains("recorded with ROM")),
+            other => panic!("expected a ROM mismatch, got {:?}", other),
+        }
+        assert!(wrong_rom.movie_mode().is_none());
+        assert_eq!(wrong_rom.save_state(), before);
+    }
+
+    // SWI 2 with IME set: the VBlank interrupt wakes the CPU straight into
+    // the IRQ exception, returning to the instruction after the halt
+    #[test]
+    fn halt_wakes_into_the_irq_handler() {
+        let mut gba = Gba::builder().rom(&[0; 0x200]).build().unwrap();
+        gba.cpu.skip_bios();
+        let cpsr = gba.cpu.cpsr().read();
+        gba.mem.write16(io_map::DISPSTAT, 0x0008u16);
+        gba.mem.write16(io_map::IE, Interrupt::VBlank as u16);
+        gba.mem.write16(io_map::IME, 1u16);
+        gba.mem.write8(io_map::POSTFLG + 1, 0u8);
+        assert!(gba.is_asleep());
+
+        // Skipping ahead through the lines before VBlank
+        for _ in 0..1000 {
+            if !gba.is_asleep() {
+                break;
+            }
+            gba.step();
+        }
+        assert_eq!(gba.mem.io().power, PowerState::Running);
+        assert_eq!(gba.mem.io().irq.if_(), Interrupt::VBlank as u16);
+        assert_eq!(gba.cpu.pc(), 0x18);
+        assert_eq!(gba.cpu.mode(), ARM7Mode::IRQ);
+        assert!(gba.cpu.is_irq_disable());
+        assert_eq!(gba.cpu.lr(), 0x08000004);
+        assert_eq!(gba.cpu.spsr().map(|spsr| spsr.read()), Some(cpsr));
+    }
+
+    // Without IME the halt still ends, but execution carries on in place
+    #[test]
+    fn masked_interrupts_only_wake_the_cpu() {
+        let mut gba = Gba::builder().rom(&[0; 0x200]).build().unwrap();
+        gba.cpu.skip_bios();
+        gba.mem.write16(io_map::DISPSTAT, 0x0008u16);
+        gba.mem.write16(io_map::IE, Interrupt::VBlank as u16);
+        gba.mem.write8(io_map::POSTFLG + 1, 0u8);
+
+        // Skipping ahead through the lines before VBlank
+        for _ in 0..1000 {
+            if !gba.is_asleep() {
+                break;
+            }
+            gba.step();
+        }
+        assert_eq!(gba.mem.io().power, PowerState::Running);
+        assert_eq!(gba.cpu.pc(), 0x08000000);
+        assert_eq!(gba.cpu.mode(), ARM7Mode::System);
+    }
+}
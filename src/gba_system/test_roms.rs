@@ -0,0 +1,269 @@
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use toml;
+
+use gba_error::{GbaError, GbaResult};
+use gba_mem::Address;
+use gba_system::batch::{BatchItem, BatchOutcome, BatchRunner};
+
+/// Environment variable naming the manifest the test-ROM integration test
+/// runs. The test ROMs aren't ours to redistribute, so without it the test
+/// does nothing.
+pub const MANIFEST_ENV: &str = "GBA_TEST_ROMS";
+
+/// A word the ROM leaves in memory when it is done, e.g. the number of the
+/// first failing test
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub struct MemCheck {
+    /// Address of the word
+    pub addr: u32,
+    /// Value it should hold
+    pub value: u32,
+}
+
+/// One test ROM and how to tell it passed. Suites that only report on
+/// screen (armwrestler, the mGBA suite) are checked against the golden
+/// screenshot hash of a known good run; jsmolka's gba-tests also leave a
+/// result in memory. A mismatched frame is saved next to the ROM as
+/// <rom>.actual.png.
+///
+/// The compositor doesn't apply windows, blending or mosaic yet, so a
+/// golden only holds for screens that don't use them. The hashing itself
+/// is checked against the synthetic frames in gba_video/goldens.toml.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct TestRom {
+    /// Shown in the results
+    pub name: String,
+    /// Relative to the manifest file
+    pub path: PathBuf,
+    /// Frames to run
+    pub frames: u64,
+    /// Word to check once done
+    #[serde(default)]
+    pub result: Option<MemCheck>,
+    /// CRC32 the last frame should hash to
+    #[serde(default)]
+    pub frame_crc32: Option<u32>,
+}
+
+/// A list of test ROMs, e.g.:
+///
+/// ```toml
+/// [[rom]]
+/// name = "gba-tests arm"
+/// path = "gba-tests/arm/arm.gba"
+/// frames = 30
+/// result = { addr = 0x03000000, value = 0 }
+///
+/// [[rom]]
+/// name = "armwrestler"
+/// path = "armwrestler.gba"
+/// frames = 120
+/// frame_crc32 = 0x00000000 # From the frame_crc32 of a good run
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+pub struct TestManifest {
+    /// The test ROMs
+    #[serde(default)]
+    pub rom: Vec<TestRom>,
+}
+
+impl TestManifest {
+    /// Read a manifest from TOML
+    pub fn parse(text: &str) -> GbaResult<TestManifest> {
+        toml::from_str(text).map_err(|e| GbaError::InvalidConfig(e.to_string()))
+    }
+
+    /// ROM paths come back resolved against the manifest's directory
+    pub fn load<P: AsRef<Path>>(path: P) -> GbaResult<TestManifest> {
+        let mut text = String::new();
+        File::open(path.as_ref())?.read_to_string(&mut text)?;
+        let mut manifest = TestManifest::parse(&text)?;
+
+        let dir = path.as_ref().parent().unwrap_or(Path::new(""));
+        for rom in manifest.rom.iter_mut() {
+            rom.path = dir.join(&rom.path);
+        }
+        Ok(manifest)
+    }
+
+    /// The manifest MANIFEST_ENV points at, if it is set
+    pub fn from_env() -> Option<GbaResult<TestManifest>> {
+        env::var_os(MANIFEST_ENV).map(TestManifest::load)
+    }
+
+    /// Run every test ROM on runner
+    pub fn run(&self, runner: &BatchRunner) -> Vec<TestRomResult> {
+        let items: Vec<BatchItem> = self.rom.iter()
+            .map(|rom| BatchItem::new(rom.path.clone(), rom.frames))
+            .collect();
+        // The inspector isn't told which ROM it is looking at, so it reads
+        // every result address and each ROM picks out its own
+        let addrs: Vec<Option<Address>> = self.rom.iter()
+            .map(|rom| rom.result.map(|check| check.addr as Address))
+            .collect();
+        let results = runner.run(&items, |_, gba| {
+            let words: Vec<Option<u32>> = addrs.iter()
+                .map(|addr| addr.map(|addr| gba.mem().peek::<u32>(addr)))
+                .collect();
+            (gba.screenshot(), words)
+        });
+
+        self.rom.iter().zip(results).enumerate().map(|(i, (rom, result))| {
+            let mut failures = Vec::new();
+            let mut crc = None;
+            match result.output {
+                Some((ref shot, ref words)) => {
+                    let frame = shot.crc32();
+                    crc = Some(frame);
+                    if let (Some(check), Some(word)) = (rom.result, words[i]) {
+                        if word != check.value {
+                            failures.push(format!("[{:#010x}] is {:#x}, expected {:#x}",
+                                                  check.addr, word, check.value));
+                        }
+                    }
+                    if let Some(expected) = rom.frame_crc32 {
+                        if frame != expected {
+                            failures.push(format!("frame CRC32 is {:#010x}, expected {:#010x}",
+                                                  frame, expected));
+                            let actual = rom.path.with_extension("actual.png");
+                            if let Err(e) = shot.save_png(&actual) {
+                                failures.push(format!("failed to save {}: {}",
+                                                      actual.display(), e));
+                            }
+                        }
+                    }
+                },
+                None => failures.push(format!("{:?}", result.outcome)),
+            }
+            TestRomResult {
+                name: rom.name.clone(),
+                outcome: result.outcome,
+                frame_crc32: crc,
+                failures,
+            }
+        }).collect()
+    }
+}
+
+/// How a test ROM did
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TestRomResult {
+    /// As in the manifest
+    pub name: String,
+    /// How the run ended
+    pub outcome: BatchOutcome,
+    /// To fill in the manifest from a good run
+    pub frame_crc32: Option<u32>,
+    /// Checks that didn't hold
+    pub failures: Vec<String>,
+}
+
+impl TestRomResult {
+    /// Whether every check held
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// Run the test ROMs with the default settings, one thread per CPU
+pub fn run_manifest(manifest: &TestManifest) -> Vec<TestRomResult> {
+    manifest.run(&BatchRunner::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::process;
+
+    use gba_mem::rom_header::{RomHeader, FIXED_VALUE, HEADER_SIZE};
+
+    use super::*;
+
+    // The blank frame from gba_video/goldens.toml, which a ROM that
+    // draws nothing shows
+    const BLANK_FRAME_CRC32: u32 = 0x10366D18;
+
+    // Just a header the BIOS accepts
+    fn synthetic_rom() -> Vec<u8> {
+        let mut rom = vec![0; HEADER_SIZE];
+        rom[0xB2] = FIXED_VALUE;
+        rom[0xBD] = RomHeader::expected_checksum(&rom);
+        rom
+    }
+
+    #[test]
+    fn manifests_parse() {
+        let manifest = TestManifest::parse(r#"
+            [[rom]]
+            name = "gba-tests arm"
+            path = "gba-tests/arm/arm.gba"
+            frames = 30
+            result = { addr = 0x03000000, value = 0 }
+
+            [[rom]]
+            name = "armwrestler"
+            path = "armwrestler.gba"
+            frames = 120
+            frame_crc32 = 0x10366D18
+        "#).unwrap();
+        assert_eq!(manifest.rom.len(), 2);
+        assert_eq!(manifest.rom[0].result, Some(MemCheck { addr: 0x03000000, value: 0 }));
+        assert_eq!(manifest.rom[0].frame_crc32, None);
+        assert_eq!(manifest.rom[1].frame_crc32, Some(BLANK_FRAME_CRC32));
+        assert!(TestManifest::parse("[[rom]]\nname = \"no path\"").is_err());
+    }
+
+    // A ROM built by the test against the blank golden, and against a
+    // wrong one, which saves the frame it got instead
+    #[test]
+    fn frames_are_checked_against_goldens() {
+        let dir = env::temp_dir().join(format!("gba-test-roms-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("good.gba"), synthetic_rom()).unwrap();
+        fs::write(dir.join("bad.gba"), synthetic_rom()).unwrap();
+        fs::write(dir.join("manifest.toml"), format!("
+            [[rom]]
+            name = \"good\"
+            path = \"good.gba\"
+            frames = 1
+            frame_crc32 = {}
+
+            [[rom]]
+            name = \"bad\"
+            path = \"bad.gba\"
+            frames = 1
+            frame_crc32 = 0x12345678
+        ", BLANK_FRAME_CRC32)).unwrap();
+
+        let manifest = TestManifest::load(dir.join("manifest.toml")).unwrap();
+        let results = manifest.run(&BatchRunner::with_threads(2));
+        let actual = dir.join("bad.actual.png").exists();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(results[0].passed(), "{:?}", results[0].failures);
+        assert_eq!(results[0].frame_crc32, Some(BLANK_FRAME_CRC32));
+        assert!(!results[1].passed());
+        assert_eq!(results[1].failures,
+                   [format!("frame CRC32 is {:#010x}, expected 0x12345678", BLANK_FRAME_CRC32)]);
+        assert!(actual);
+    }
+
+    // Runs the manifest MANIFEST_ENV names, if any, e.g.
+    // GBA_TEST_ROMS=../gba-tests/manifest.toml cargo test test_roms
+    #[test]
+    fn test_roms_pass() {
+        let manifest = match TestManifest::from_env() {
+            Some(manifest) => manifest.unwrap(),
+            None => return,
+        };
+        let failed: Vec<String> = run_manifest(&manifest).iter()
+            .filter(|result| !result.passed())
+            .map(|result| format!("{}: {}", result.name, result.failures.join(", ")))
+            .collect();
+        assert!(failed.is_empty(), "{}", failed.join("\n"));
+    }
+}
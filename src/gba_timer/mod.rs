@@ -0,0 +1,161 @@
+use core::fmt;
+
+use gba_irq::{Interrupt, IrqController};
+use gba_mem::Address;
+
+/// Timer registers from:
+/// http://problemkaputt.de/gbatek.htm#gbatimers
+pub const TM0CNT_L: Address = 0x04000100;
+/// Timer 3's control register, the last of the block
+pub const TM3CNT_H: Address = 0x0400010E;
+
+/// Timers, from 0 to 3
+pub const NUM_TIMERS: usize = 4;
+
+const TMCNT_PRESCALER: u16 = 0x0003;
+const TMCNT_COUNT_UP:  u16 = 0x0004; // Tick on previous timer overflow
+const TMCNT_IRQ:       u16 = 0x0040;
+const TMCNT_ENABLE:    u16 = 0x0080;
+const TMCNT_MASK:      u16 = 0x00C7;
+
+// Cycles per tick for each prescaler selection, as a shift
+const PRESCALER_SHIFT: [u32; 4] = [0, 6, 8, 10]; // 1, 64, 256, 1024
+
+const TIMER_IRQS: [Interrupt; NUM_TIMERS] = [
+    Interrupt::Timer0, Interrupt::Timer1, Interrupt::Timer2, Interrupt::Timer3,
+];
+
+/// One timer's registers and internal state
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Timer {
+    reload: u16,
+    counter: u16,
+    cnt: u16,
+    prescale_cycles: u32, // Cycles not yet turned into a tick
+}
+
+impl_save_state!(Timer { reload, counter, cnt, prescale_cycles });
+
+impl Timer {
+    /// Count now
+    pub fn counter(&self) -> u16 { self.counter }
+    /// Value loaded on overflow
+    pub fn reload(&self) -> u16 { self.reload }
+    /// TMxCNT_H as written
+    pub fn cnt(&self) -> u16 { self.cnt }
+
+    /// Whether TMxCNT_H starts it
+    pub fn is_enabled(&self) -> bool { self.cnt & TMCNT_ENABLE != 0 }
+    fn is_count_up(&self) -> bool { self.cnt & TMCNT_COUNT_UP != 0 }
+
+    fn write_cnt(&mut self, val: u16) {
+        let was_enabled = self.is_enabled();
+        self.cnt = val & TMCNT_MASK;
+        if !was_enabled && self.is_enabled() {
+            self.counter = self.reload;
+            self.prescale_cycles = 0;
+        }
+    }
+
+    // Advance the counter by a number of ticks, returning the number of
+    // overflows
+    fn tick(&mut self, ticks: u32) -> u32 {
+        let total = self.counter as u32 + ticks;
+        if total <= 0xFFFF {
+            self.counter = total as u16;
+            return 0;
+        }
+
+        let period = 0x10000 - self.reload as u32;
+        let excess = total - 0x10000;
+        self.counter = (self.reload as u32 + excess % period) as u16;
+        1 + excess / period
+    }
+}
+
+/// The four timers
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Timers {
+    timers: [Timer; NUM_TIMERS],
+}
+
+impl_save_state!(Timers { timers });
+impl_serde_via_state!(Timers);
+
+impl Timers {
+    /// Timer idx
+    pub fn timer(&self, idx: usize) -> &Timer {
+        &self.timers[idx]
+    }
+
+    /// Advance all timers, returning how many times each overflowed
+    pub fn step(&mut self, cycles: u32, irq: &mut IrqController) -> [u32; NUM_TIMERS] {
+        let mut overflows = [0; NUM_TIMERS];
+
+        for i in 0..NUM_TIMERS {
+            let prev_overflows = if i > 0 { overflows[i - 1] } else { 0 };
+            let timer = &mut self.timers[i];
+            if !timer.is_enabled() {
+                continue;
+            }
+
+            let ticks = if i > 0 && timer.is_count_up() {
+                prev_overflows
+            }
+            else {
+                let shift = PRESCALER_SHIFT[(timer.cnt & TMCNT_PRESCALER) as usize];
+                timer.prescale_cycles += cycles;
+                let ticks = timer.prescale_cycles >> shift;
+                timer.prescale_cycles &= (1 << shift) - 1;
+                ticks
+            };
+
+            overflows[i] = timer.tick(ticks);
+            if overflows[i] > 0 && timer.cnt & TMCNT_IRQ != 0 {
+                irq.request(TIMER_IRQS[i]);
+            }
+        }
+
+        overflows
+    }
+
+    /// Cycles until the next timer running off the clock overflows, if any
+    /// is. Cascaded timers only overflow along with the one before them.
+    pub fn cycles_to_overflow(&self) -> Option<u32> {
+        self.timers.iter().enumerate()
+            .filter(|&(i, t)| t.is_enabled() && !(i > 0 && t.is_count_up()))
+            .map(|(_, t)| {
+                let shift = PRESCALER_SHIFT[(t.cnt & TMCNT_PRESCALER) as usize];
+                ((0x10000 - t.counter as u32) << shift) - t.prescale_cycles
+            })
+            .min()
+    }
+
+    /// Read the counter or control register at addr
+    pub fn read16(&self, addr: Address) -> u16 {
+        let timer = &self.timers[(addr - TM0CNT_L) / 4];
+        if addr & 2 == 0 { timer.counter } else { timer.cnt }
+    }
+
+    /// Write the register half at addr, keeping the bits outside mask
+    pub fn write16(&mut self, addr: Address, val: u16, mask: u16) {
+        let timer = &mut self.timers[(addr - TM0CNT_L) / 4];
+        if addr & 2 == 0 {
+            timer.reload = (timer.reload & !mask) | (val & mask);
+        }
+        else {
+            let cnt = (timer.cnt & !mask) | (val & mask);
+            timer.write_cnt(cnt);
+        }
+    }
+}
+
+impl fmt::Display for Timers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, t) in self.timers.iter().enumerate() {
+            writeln![f, "TM{}: {:#06x} reload:{:#06x} cnt:{:#06x}",
+                   i, t.counter, t.reload, t.cnt]?;
+        }
+        Ok(())
+    }
+}
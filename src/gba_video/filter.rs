@@ -0,0 +1,238 @@
+use gba_video::bgr555_to_rgb;
+use prelude::*;
+
+// Post-processing between the PPU and the screen, each step optional and
+// changeable between frames:
+//
+// - Color correction. The GBA's LCD is dark and washed out next to a
+//   modern monitor, and games were coloured to suit it, so shown as they
+//   are they look garish. Colors are taken through the LCD's response and
+//   its channels' bleed into each other, as in higan, then back through the
+//   monitor's gamma.
+// - Frame blending, for the LCD's slow response. Games flicker sprites on
+//   alternate frames to fake transparency, which ghosting on the real
+//   screen smoothed out.
+// - Integer scaling in software, for sinks that can only stretch the frame
+//   with a blurry filter, optionally darkening every last row as scanlines.
+
+// How bright each 5 bit channel is on the LCD, (c / 31) ^ 4 out of 2^24 - 1
+const LCD_LINEAR: [u32; 32] = [
+    0, 18, 291, 1471, 4651, 11354, 23544, 43618,
+    74410, 119191, 181666, 265977, 376702, 518856, 697887, 919683,
+    1190565, 1517291, 1907055, 2367486, 2906652, 3533054, 4255630, 5083753,
+    6027234, 7096319, 8301690, 9654464, 11166195, 12848874, 14714927, 16777215,
+];
+
+// Linear brightness, out of 2^24 - 1, halfway between each 8 bit output
+// level and the next once taken back through a 2.2 gamma and scaled by
+// 255 / 280, the brightest row of the color matrix. Generated with
+//
+//     ((v + 0.5) * 280 / 255 / 255) ^ 2.2 * (2^24 - 1)
+const OUT_LEVELS: [u32; 255] = [
+    23, 255, 786, 1647, 2863, 4451, 6428, 8807, 11599, 14814,
+    18463, 22554, 27095, 32094, 37558, 43493, 49906, 56803, 64190, 72072,
+    80454, 89342, 98740, 108653, 119085, 130041, 141525, 153540, 166092, 179184,
+    192819, 207002, 221735, 237022, 252867, 269273, 286243, 303780, 321888, 340568,
+    359825, 379661, 400080, 421082, 442673, 464853, 487626, 510995, 534962, 559528,
+    584698, 610473, 636856, 663849, 691454, 719673, 748510, 777965, 808041, 838741,
+    870067, 902020, 934602, 967816, 1001664, 1036148, 1071269, 1107029, 1143431, 1180476,
+    1218167, 1256504, 1295491, 1335128, 1375417, 1416361, 1457961, 1500218, 1543135, 1586713,
+    1630954, 1675859, 1721430, 1767669, 1814577, 1862157, 1910408, 1959334, 2008935, 2059214,
+    2110171, 2161808, 2214127, 2267129, 2320816, 2375189, 2430249, 2485999, 2542438, 2599570,
+    2657395, 2715914, 2775129, 2835042, 2895653, 2956965, 3018978, 3081693, 3145112, 3209237,
+    3274068, 3339608, 3405856, 3472815, 3540485, 3608869, 3677967, 3747780, 3818309, 3889557,
+    3961524, 4034211, 4107619, 4181750, 4256605, 4332184, 4408490, 4485524, 4563285, 4641777,
+    4720999, 4800952, 4881639, 4963060, 5045216, 5128108, 5211738, 5296106, 5381214, 5467062,
+    5553652, 5640985, 5729061, 5817882, 5907449, 5997763, 6088825, 6180636, 6273197, 6366509,
+    6460573, 6555390, 6650961, 6747287, 6844369, 6942208, 7040805, 7140160, 7240276, 7341153,
+    7442791, 7545192, 7648357, 7752286, 7856981, 7962443, 8068672, 8175670, 8283436, 8391973,
+    8501281, 8611361, 8722215, 8833841, 8946243, 9059420, 9173374, 9288105, 9403615, 9519903,
+    9636972, 9754822, 9873453, 9992867, 10113064, 10234046, 10355813, 10478366, 10601705, 10725833,
+    10850749, 10976454, 11102949, 11230236, 11358314, 11487185, 11616850, 11747308, 11878562, 12010612,
+    12143458, 12277102, 12411544, 12546785, 12682826, 12819668, 12957311, 13095756, 13235004, 13375056,
+    13515912, 13657574, 13800042, 13943316, 14087398, 14232289, 14377988, 14524498, 14671817, 14819949,
+    14968892, 15118648, 15269217, 15420601, 15572800, 15725814, 15879645, 16034293, 16189759, 16346044,
+    16503148, 16661072, 16819816, 16979382, 17139771, 17300982, 17463016, 17625875, 17789559, 17954069,
+    18119405, 18285567, 18452558, 18620377, 18789025, 18958503, 19128811, 19299950, 19471921, 19644725,
+    19818362, 19992832, 20168137, 20344277, 20521253,
+];
+
+// How much of the LCD's blue, green and red end up in each output channel,
+// out of 255
+const COLOR_MATRIX: [[u64; 3]; 3] = [
+    [0, 50, 255], // Red
+    [30, 230, 10], // Green
+    [220, 10, 50], // Blue
+];
+
+/// A BGR555 color as it looks on the LCD
+pub fn correct_color(px: u16) -> [u8; 3] {
+    let lcd = [LCD_LINEAR[(px >> 10) as usize & 0x1F],
+               LCD_LINEAR[(px >> 5) as usize & 0x1F],
+               LCD_LINEAR[px as usize & 0x1F]];
+    let mut rgb = [0; 3];
+    for (out, row) in rgb.iter_mut().zip(COLOR_MATRIX.iter()) {
+        let linear = row.iter().zip(lcd.iter()).map(|(&m, &c)| m * c as u64).sum::<u64>() / 255;
+        *out = OUT_LEVELS.iter().take_while(|&&level| (level as u64) < linear).count() as u8;
+    }
+    rgb
+}
+
+/// How frames are scaled up
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScaleFilter {
+    /// Each pixel becomes a square
+    Nearest,
+    /// Nearest, with the last row of each pixel at half brightness
+    Scanlines,
+}
+
+impl ScaleFilter {
+    /// "nearest" or "scanlines"
+    pub fn from_name(name: &str) -> Option<ScaleFilter> {
+        match name {
+            "nearest" => Some(ScaleFilter::Nearest),
+            "scanlines" => Some(ScaleFilter::Scanlines),
+            _ => None,
+        }
+    }
+}
+
+/// Post-processing applied to frames before they are shown
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PostProcess {
+    /// Approximate the GBA screen's colors
+    pub color_correction: bool,
+    /// Average each frame with the one before, as the GBA screen's slow
+    /// response does
+    pub frame_blending: bool,
+    /// 1 to leave the size alone
+    pub scale: usize,
+    /// How to scale
+    pub scale_filter: ScaleFilter,
+}
+
+impl Default for PostProcess {
+    fn default() -> PostProcess {
+        PostProcess {
+            color_correction: false,
+            frame_blending: false,
+            scale: 1,
+            scale_filter: ScaleFilter::Nearest,
+        }
+    }
+}
+
+impl PostProcess {
+    /// Whether frames come out as the PPU drew them
+    pub fn is_none(&self) -> bool {
+        !self.color_correction && !self.frame_blending && self.scale <= 1
+    }
+}
+
+/// Runs the post-processing, keeping what it needs between frames
+#[derive(Debug, Default)]
+pub struct PostProcessor {
+    settings: PostProcess,
+    corrected: Vec<[u8; 3]>, // Every BGR555 color, once color correction is first used
+    previous: Vec<[u8; 3]>, // Last frame before blending
+    colors: Vec<[u8; 3]>,
+    out: Vec<[u8; 3]>,
+}
+
+impl PostProcessor {
+    /// A processor applying settings
+    pub fn new(settings: PostProcess) -> PostProcessor {
+        PostProcessor {
+            settings,
+            ..PostProcessor::default()
+        }
+    }
+
+    /// What is applied
+    pub fn settings(&self) -> PostProcess {
+        self.settings
+    }
+
+    /// Change what is applied
+    pub fn set_settings(&mut self, settings: PostProcess) {
+        if !settings.frame_blending {
+            self.previous.clear();
+        }
+        self.settings = settings;
+    }
+
+    /// Process a BGR555 picture width pixels wide, giving RGB pixels width *
+    /// scale wide
+    pub fn process(&mut self, pixels: &[u16], width: usize) -> &[[u8; 3]] {
+        if self.settings.color_correction && self.corrected.is_empty() {
+            self.corrected = (0..0x8000).map(correct_color).collect();
+        }
+
+        self.colors.clear();
+        if self.settings.color_correction {
+            let corrected = &self.corrected;
+            self.colors.extend(pixels.iter().map(|&px| corrected[px as usize & 0x7FFF]));
+        }
+        else {
+            self.colors.extend(pixels.iter().map(|&px| bgr555_to_rgb(px)));
+        }
+
+        if self.settings.frame_blending {
+            if self.previous.len() != self.colors.len() {
+                self.previous = self.colors.clone();
+            }
+            for (px, prev) in self.colors.iter_mut().zip(self.previous.iter_mut()) {
+                let current = *px;
+                for c in 0..3 {
+                    px[c] = (current[c] as u16 + prev[c] as u16).div_ceil(2) as u8;
+                }
+                *prev = current;
+            }
+        }
+
+        let scale = self.settings.scale.max(1);
+        if scale == 1 {
+            return &self.colors;
+        }
+        self.out.clear();
+        for row in self.colors.chunks(width) {
+            for y in 0..scale {
+                let dim = y == scale - 1 && self.settings.scale_filter == ScaleFilter::Scanlines;
+                for &px in row.iter() {
+                    let px = if dim { [px[0] / 2, px[1] / 2, px[2] / 2] } else { px };
+                    for _ in 0..scale {
+                        self.out.push(px);
+                    }
+                }
+            }
+        }
+        &self.out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn corrects_colors_like_the_lcd() {
+        assert_eq!(correct_color(0), [0, 0, 0]);
+        // Full white comes out a little dim, and full red bleeds
+        assert_eq!(correct_color(0x7FFF), [252, 238, 242]);
+        let red = correct_color(0x001F);
+        assert!(red[0] > 200 && red[1] > 0 && red[2] > 0);
+    }
+
+    #[test]
+    fn blends_and_scales() {
+        let mut post = PostProcessor::new(PostProcess {
+            frame_blending: true,
+            scale: 2,
+            scale_filter: ScaleFilter::Scanlines,
+            ..PostProcess::default()
+        });
+        assert_eq!(post.process(&[0x7FFF], 1), &[[255; 3], [255; 3], [127; 3], [127; 3]]);
+        assert_eq!(post.process(&[0], 1)[0], [128; 3]);
+    }
+}
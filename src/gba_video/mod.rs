@@ -0,0 +1,241 @@
+/// Post-processing filters
+pub mod filter;
+/// Recording gameplay to video
+#[cfg(feature = "record")]
+pub mod record;
+/// Screenshots
+pub mod screenshot;
+
+use core::fmt;
+
+use gba_ppu::{FrameBuffer, Ppu, REFRESH_RATE, SCREEN_HEIGHT, SCREEN_WIDTH};
+use prelude::*;
+
+use self::filter::{PostProcess, PostProcessor};
+
+/// Pixel layouts a sink can ask frames to be converted to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// Native GBA format; no conversion
+    Bgr555,
+    /// 16-bit, as most displays take it
+    Rgb565,
+    /// Bytes in R, G, B, A order
+    Rgba8888,
+}
+
+impl PixelFormat {
+    /// Bytes each pixel takes
+    pub fn bytes_per_pixel(&self) -> usize {
+        match *self {
+            PixelFormat::Bgr555 | PixelFormat::Rgb565 => 2,
+            PixelFormat::Rgba8888 => 4,
+        }
+    }
+}
+
+/// How a sink paces presentation
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VsyncHint {
+    /// present() returns immediately; the caller must pace emulation
+    Free,
+    /// present() waits for the host display, pacing emulation itself
+    Blocking,
+}
+
+/// Information about a presented frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FrameMetadata {
+    /// Frames completed since power on
+    pub frame: u64,
+    /// Pixels across
+    pub width: usize,
+    /// Pixels down
+    pub height: usize,
+    /// Frames per second the LCD runs at
+    pub refresh_rate: f64,
+}
+
+impl fmt::Display for FrameMetadata {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write![f, "frame {} ({}x{} @ {:.2}Hz)",
+               self.frame, self.width, self.height, self.refresh_rate]
+    }
+}
+
+/// A picture handed to a sink, converted to the sink's preferred format
+#[derive(Debug)]
+pub struct Frame<'a> {
+    /// Layout of the pixels
+    pub format: PixelFormat,
+    /// Pixels across
+    pub width: usize,
+    /// Pixels down
+    pub height: usize,
+    /// Pixels, left to right then top to bottom
+    pub data: &'a [u8],
+}
+
+impl<'a> Frame<'a> {
+    /// Bytes per row
+    pub fn pitch(&self) -> usize {
+        self.width * self.format.bytes_per_pixel()
+    }
+}
+
+/// Anything that can display or store emulated frames. Frontends implement
+/// this so the PPU never needs to know where its output goes.
+pub trait VideoSink {
+    /// Display or store a frame
+    fn present(&mut self, frame: &Frame, meta: &FrameMetadata);
+
+    /// Format frames are converted to before they are presented
+    fn preferred_format(&self) -> PixelFormat {
+        PixelFormat::Rgba8888
+    }
+
+    /// How the sink paces presentation
+    fn vsync(&self) -> VsyncHint {
+        VsyncHint::Free
+    }
+}
+
+/// Sink that drops every frame, for running without a display
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NullSink {
+    frames: u64,
+}
+
+impl NullSink {
+    /// Frames dropped so far
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+}
+
+impl VideoSink for NullSink {
+    fn present(&mut self, _frame: &Frame, _meta: &FrameMetadata) {
+        self.frames += 1;
+    }
+
+    fn preferred_format(&self) -> PixelFormat {
+        PixelFormat::Bgr555
+    }
+}
+
+// Expand a 5-bit channel to 8 bits
+fn expand5(c: u16) -> u8 {
+    let c = (c & 0x1F) as u8;
+    (c << 3) | (c >> 2)
+}
+
+/// A BGR555 pixel as 8-bit red, green and blue
+pub fn bgr555_to_rgb(px: u16) -> [u8; 3] {
+    [expand5(px), expand5(px >> 5), expand5(px >> 10)]
+}
+
+/// Convert a BGR555 picture into a byte buffer of the given format
+pub fn convert(pixels: &[u16], format: PixelFormat, out: &mut Vec<u8>) {
+    out.clear();
+    out.reserve(pixels.len() * format.bytes_per_pixel());
+
+    for &px in pixels {
+        let (r, g, b) = (px & 0x1F, (px >> 5) & 0x1F, (px >> 10) & 0x1F);
+        match format {
+            PixelFormat::Bgr555 => {
+                out.push(px as u8);
+                out.push((px >> 8) as u8 & 0x7F);
+            },
+            PixelFormat::Rgb565 => {
+                // Green gets a sixth bit by repeating its top bit
+                let g6 = (g << 1) | (g >> 4);
+                let rgb = (r << 11) | (g6 << 5) | b;
+                out.push(rgb as u8);
+                out.push((rgb >> 8) as u8);
+            },
+            PixelFormat::Rgba8888 => {
+                out.push(expand5(r));
+                out.push(expand5(g));
+                out.push(expand5(b));
+                out.push(0xFF);
+            },
+        }
+    }
+}
+
+/// Convert a post-processed RGB picture into a byte buffer of the given
+/// format
+pub fn convert_rgb(pixels: &[[u8; 3]], format: PixelFormat, out: &mut Vec<u8>) {
+    out.clear();
+    out.reserve(pixels.len() * format.bytes_per_pixel());
+
+    for &[r, g, b] in pixels {
+        match format {
+            PixelFormat::Bgr555 => {
+                let bgr = (b as u16 >> 3) << 10 | (g as u16 >> 3) << 5 | r as u16 >> 3;
+                out.push(bgr as u8);
+                out.push((bgr >> 8) as u8);
+            },
+            PixelFormat::Rgb565 => {
+                let rgb = (r as u16 >> 3) << 11 | (g as u16 >> 2) << 5 | b as u16 >> 3;
+                out.push(rgb as u8);
+                out.push((rgb >> 8) as u8);
+            },
+            PixelFormat::Rgba8888 => out.extend_from_slice(&[r, g, b, 0xFF]),
+        }
+    }
+}
+
+/// Converts finished frames, post-processing them if asked, and hands them
+/// to a sink, reusing its buffers between frames
+#[derive(Debug, Default)]
+pub struct Presenter {
+    buf: Vec<u8>,
+    post: PostProcessor,
+}
+
+impl Presenter {
+    /// The post-processing applied to frames
+    pub fn post_process(&self) -> PostProcess {
+        self.post.settings()
+    }
+
+    /// Takes effect from the next frame presented
+    pub fn set_post_process(&mut self, settings: PostProcess) {
+        self.post.set_settings(settings);
+    }
+
+    /// Convert the frame to the sink's format, post-process it and present it
+    pub fn present<S: VideoSink + ?Sized>(&mut self, fb: &FrameBuffer,
+                                          frame_num: u64, sink: &mut S) {
+        let format = sink.preferred_format();
+        let settings = self.post.settings();
+        let scale = if settings.is_none() {
+            convert(fb.pixels(), format, &mut self.buf);
+            1
+        }
+        else {
+            convert_rgb(self.post.process(fb.pixels(), SCREEN_WIDTH), format, &mut self.buf);
+            settings.scale.max(1)
+        };
+
+        let frame = Frame {
+            format,
+            width: SCREEN_WIDTH * scale,
+            height: SCREEN_HEIGHT * scale,
+            data: &self.buf,
+        };
+        let meta = FrameMetadata {
+            frame: frame_num,
+            width: SCREEN_WIDTH * scale,
+            height: SCREEN_HEIGHT * scale,
+            refresh_rate: REFRESH_RATE,
+        };
+        sink.present(&frame, &meta);
+    }
+
+    /// Present the PPU's current picture
+    pub fn present_ppu<S: VideoSink + ?Sized>(&mut self, ppu: &Ppu, sink: &mut S) {
+        self.present(ppu.framebuffer(), ppu.frame(), sink);
+    }
+}
@@ -0,0 +1,412 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use gba_error::GbaResult;
+use gba_ppu::REFRESH_RATE;
+use gba_video::screenshot::Screenshot;
+
+// Matroska layout from:
+// https://www.matroska.org/technical/elements.html
+// Every element size is written 8 bytes long, so the ones only known at
+// the end can be filled in where they are.
+const EBML: u32 = 0x1A45DFA3;
+const EBML_VERSION: u32 = 0x4286;
+const EBML_READ_VERSION: u32 = 0x42F7;
+const EBML_MAX_ID_LENGTH: u32 = 0x42F2;
+const EBML_MAX_SIZE_LENGTH: u32 = 0x42F3;
+const DOC_TYPE: u32 = 0x4282;
+const DOC_TYPE_VERSION: u32 = 0x4287;
+const DOC_TYPE_READ_VERSION: u32 = 0x4285;
+const SEGMENT: u32 = 0x18538067;
+const INFO: u32 = 0x1549A966;
+const TIMESTAMP_SCALE: u32 = 0x2AD7B1;
+const DURATION: u32 = 0x4489;
+const MUXING_APP: u32 = 0x4D80;
+const WRITING_APP: u32 = 0x5741;
+const TRACKS: u32 = 0x1654AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const TRACK_UID: u32 = 0x73C5;
+const TRACK_TYPE: u32 = 0x83;
+const CODEC_ID: u32 = 0x86;
+const DEFAULT_DURATION: u32 = 0x23E383;
+const VIDEO: u32 = 0xE0;
+const PIXEL_WIDTH: u32 = 0xB0;
+const PIXEL_HEIGHT: u32 = 0xBA;
+const COLOUR_SPACE: u32 = 0x2EB524;
+const AUDIO: u32 = 0xE1;
+const SAMPLING_FREQUENCY: u32 = 0xB5;
+const CHANNELS: u32 = 0x9F;
+const BIT_DEPTH: u32 = 0x6264;
+const CLUSTER: u32 = 0x1F43B675;
+const TIMESTAMP: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+
+const TRACK_VIDEO: u8 = 1;
+const TRACK_AUDIO: u8 = 2;
+const TYPE_VIDEO: u64 = 1;
+const TYPE_AUDIO: u64 = 2;
+const KEYFRAME: u8 = 0x80;
+const APP_NAME: &str = "rusty-gba";
+const CLUSTER_MS: u64 = 1000; // Block timestamps are 16-bit offsets from their cluster's
+
+// Frames are stored as RGB24, which players know by this FourCC
+const RGB24_FOURCC: [u8; 4] = [b'R', b'G', b'B', 24];
+
+fn write_id(out: &mut Vec<u8>, id: u32) {
+    let bytes = id.to_be_bytes();
+    let skip = bytes.iter().take_while(|&&b| b == 0).count();
+    out.extend_from_slice(&bytes[skip..]);
+}
+
+fn write_size(out: &mut Vec<u8>, size: u64) {
+    out.push(0x01);
+    out.extend_from_slice(&size.to_be_bytes()[1..]);
+}
+
+fn element(out: &mut Vec<u8>, id: u32, body: &[u8]) {
+    write_id(out, id);
+    write_size(out, body.len() as u64);
+    out.extend_from_slice(body);
+}
+
+fn uint_element(out: &mut Vec<u8>, id: u32, val: u64) {
+    let bytes = val.to_be_bytes();
+    let skip = bytes.iter().take_while(|&&b| b == 0).count().min(7);
+    element(out, id, &bytes[skip..]);
+}
+
+fn float_element(out: &mut Vec<u8>, id: u32, val: f64) {
+    element(out, id, &val.to_bits().to_be_bytes());
+}
+
+fn ms(val: f64) -> u64 {
+    val.round() as u64
+}
+
+/// Uncompressed video and PCM sound in a Matroska file. Nothing needs
+/// encoding, so it keeps up with the emulator, at about 7MB a second; run
+/// it through ffmpeg to compress it.
+#[derive(Debug)]
+pub struct MkvWriter<W: Write + Seek> {
+    out: W,
+    segment_start: u64, // Where the segment's contents begin
+    duration_at: u64, // Where the duration's value is, filled in at the end
+    cluster: Vec<u8>, // Blocks of the cluster being filled
+    cluster_ms: u64,
+    end_ms: u64,
+}
+
+impl<W: Write + Seek> MkvWriter<W> {
+    /// Start a file on out for width by height frames and sample_rate audio
+    pub fn new(mut out: W, width: usize, height: usize, sample_rate: u32)
+               -> GbaResult<MkvWriter<W>> {
+        let mut head = Vec::new();
+        let mut ebml = Vec::new();
+        uint_element(&mut ebml, EBML_VERSION, 1);
+        uint_element(&mut ebml, EBML_READ_VERSION, 1);
+        uint_element(&mut ebml, EBML_MAX_ID_LENGTH, 4);
+        uint_element(&mut ebml, EBML_MAX_SIZE_LENGTH, 8);
+        element(&mut ebml, DOC_TYPE, b"matroska");
+        uint_element(&mut ebml, DOC_TYPE_VERSION, 4);
+        uint_element(&mut ebml, DOC_TYPE_READ_VERSION, 2);
+        element(&mut head, EBML, &ebml);
+
+        // Unknown size until finished
+        write_id(&mut head, SEGMENT);
+        head.extend_from_slice(&[0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+        let segment_start = head.len() as u64;
+
+        let mut info = Vec::new();
+        uint_element(&mut info, TIMESTAMP_SCALE, 1_000_000); // Milliseconds
+        element(&mut info, MUXING_APP, APP_NAME.as_bytes());
+        element(&mut info, WRITING_APP, APP_NAME.as_bytes());
+        float_element(&mut info, DURATION, 0.0);
+        let duration_at = segment_start + 12 + info.len() as u64 - 8;
+        element(&mut head, INFO, &info);
+
+        let mut video = Vec::new();
+        uint_element(&mut video, PIXEL_WIDTH, width as u64);
+        uint_element(&mut video, PIXEL_HEIGHT, height as u64);
+        element(&mut video, COLOUR_SPACE, &RGB24_FOURCC);
+        let mut video_track = Vec::new();
+        uint_element(&mut video_track, TRACK_NUMBER, TRACK_VIDEO as u64);
+        uint_element(&mut video_track, TRACK_UID, TRACK_VIDEO as u64);
+        uint_element(&mut video_track, TRACK_TYPE, TYPE_VIDEO);
+        element(&mut video_track, CODEC_ID, b"V_UNCOMPRESSED");
+        uint_element(&mut video_track, DEFAULT_DURATION, (1e9 / REFRESH_RATE).round() as u64);
+        element(&mut video_track, VIDEO, &video);
+
+        let mut audio = Vec::new();
+        float_element(&mut audio, SAMPLING_FREQUENCY, sample_rate as f64);
+        uint_element(&mut audio, CHANNELS, 2);
+        uint_element(&mut audio, BIT_DEPTH, 16);
+        let mut audio_track = Vec::new();
+        uint_element(&mut audio_track, TRACK_NUMBER, TRACK_AUDIO as u64);
+        uint_element(&mut audio_track, TRACK_UID, TRACK_AUDIO as u64);
+        uint_element(&mut audio_track, TRACK_TYPE, TYPE_AUDIO);
+        element(&mut audio_track, CODEC_ID, b"A_PCM/INT/LIT");
+        element(&mut audio_track, AUDIO, &audio);
+
+        let mut tracks = Vec::new();
+        element(&mut tracks, TRACK_ENTRY, &video_track);
+        element(&mut tracks, TRACK_ENTRY, &audio_track);
+        element(&mut head, TRACKS, &tracks);
+
+        out.write_all(&head)?;
+        Ok(MkvWriter {
+            out,
+            segment_start,
+            duration_at,
+            cluster: Vec::new(),
+            cluster_ms: 0,
+            end_ms: 0,
+        })
+    }
+
+    // Add a block to track at time_ms, which must not be before the last
+    // block's by more than a cluster
+    fn block(&mut self, track: u8, time_ms: u64, data: &[u8]) -> GbaResult<()> {
+        if !self.cluster.is_empty() && time_ms >= self.cluster_ms + CLUSTER_MS {
+            self.flush_cluster()?;
+        }
+        if self.cluster.is_empty() {
+            self.cluster_ms = time_ms;
+            uint_element(&mut self.cluster, TIMESTAMP, time_ms);
+        }
+        let offset = time_ms as i64 - self.cluster_ms as i64;
+        write_id(&mut self.cluster, SIMPLE_BLOCK);
+        write_size(&mut self.cluster, 4 + data.len() as u64);
+        self.cluster.push(0x80 | track);
+        self.cluster.extend_from_slice(&(offset as i16).to_be_bytes());
+        self.cluster.push(KEYFRAME);
+        self.cluster.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn flush_cluster(&mut self) -> GbaResult<()> {
+        let mut head = Vec::new();
+        write_id(&mut head, CLUSTER);
+        write_size(&mut head, self.cluster.len() as u64);
+        self.out.write_all(&head)?;
+        self.out.write_all(&self.cluster)?;
+        self.cluster.clear();
+        Ok(())
+    }
+
+    /// A frame as 8-bit RGBA, starting at time_ms and lasting frame_ms
+    pub fn video(&mut self, rgba: &[u8], time_ms: f64, frame_ms: f64) -> GbaResult<()> {
+        let rgb: Vec<u8> = rgba.chunks(4).flat_map(|p| p[..3].iter().cloned()).collect();
+        self.end_ms = self.end_ms.max(ms(time_ms + frame_ms));
+        self.block(TRACK_VIDEO, ms(time_ms), &rgb)
+    }
+
+    /// Interleaved stereo samples, starting at time_ms
+    pub fn audio(&mut self, samples: &[i16], time_ms: f64) -> GbaResult<()> {
+        if samples.is_empty() {
+            return Ok(());
+        }
+        let mut pcm = Vec::with_capacity(samples.len() * 2);
+        for &sample in samples {
+            pcm.extend_from_slice(&sample.to_le_bytes());
+        }
+        self.block(TRACK_AUDIO, ms(time_ms), &pcm)
+    }
+
+    /// Write what's left and fill in the sizes. Returns the output.
+    pub fn finish(mut self) -> GbaResult<W> {
+        if !self.cluster.is_empty() {
+            self.flush_cluster()?;
+        }
+        let end = self.out.stream_position()?;
+        let mut size = Vec::new();
+        write_size(&mut size, end - self.segment_start);
+        self.out.seek(SeekFrom::Start(self.segment_start - 8))?;
+        self.out.write_all(&size)?;
+        self.out.seek(SeekFrom::Start(self.duration_at))?;
+        self.out.write_all(&(self.end_ms as f64).to_bits().to_be_bytes())?;
+        self.out.seek(SeekFrom::Start(end))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+const WAV_HEADER_LEN: u32 = 44;
+const WAV_FORMAT_PCM: u16 = 1;
+
+/// 16-bit stereo samples in a WAV file
+#[derive(Debug)]
+pub struct WavWriter<W: Write + Seek> {
+    out: W,
+    data_len: u32,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    /// Start a file on out at sample_rate
+    pub fn new(mut out: W, sample_rate: u32) -> GbaResult<WavWriter<W>> {
+        let channels = 2u16;
+        let block_align = channels * 2;
+        let mut header = Vec::with_capacity(WAV_HEADER_LEN as usize);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&(WAV_HEADER_LEN - 8).to_le_bytes()); // Filled in at the end
+        header.extend_from_slice(b"WAVEfmt ");
+        header.extend_from_slice(&16u32.to_le_bytes());
+        header.extend_from_slice(&WAV_FORMAT_PCM.to_le_bytes());
+        header.extend_from_slice(&channels.to_le_bytes());
+        header.extend_from_slice(&sample_rate.to_le_bytes());
+        header.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+        header.extend_from_slice(&block_align.to_le_bytes());
+        header.extend_from_slice(&16u16.to_le_bytes());
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&0u32.to_le_bytes());
+        out.write_all(&header)?;
+        Ok(WavWriter { out, data_len: 0 })
+    }
+
+    /// Append interleaved stereo samples
+    pub fn write(&mut self, samples: &[i16]) -> GbaResult<()> {
+        let mut pcm = Vec::with_capacity(samples.len() * 2);
+        for &sample in samples {
+            pcm.extend_from_slice(&sample.to_le_bytes());
+        }
+        self.out.write_all(&pcm)?;
+        self.data_len += pcm.len() as u32;
+        Ok(())
+    }
+
+    /// Fill in the lengths. Returns the output.
+    pub fn finish(mut self) -> GbaResult<W> {
+        self.out.seek(SeekFrom::Start(4))?;
+        self.out.write_all(&(WAV_HEADER_LEN - 8 + self.data_len).to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(WAV_HEADER_LEN as u64 - 4))?;
+        self.out.write_all(&self.data_len.to_le_bytes())?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+#[derive(Debug)]
+enum Output {
+    Matroska(MkvWriter<BufWriter<File>>),
+    Frames { dir: PathBuf, wav: WavWriter<BufWriter<File>> }, // PNGs numbered by frame
+}
+
+/// Records every frame run, shown or not, with the sound made during it.
+/// Sound is placed by the count of samples before it and pictures by the
+/// count of frames, so the two stay together however fast the game ran.
+#[derive(Debug)]
+pub struct Recorder {
+    output: Output,
+    sample_rate: u32,
+    frames: u64,
+    samples: u64, // Stereo samples so far
+}
+
+impl Recorder {
+    /// A path ending in .mkv gets a Matroska file; anything else is made a
+    /// directory of PNG frames and an audio.wav
+    pub fn create<P: AsRef<Path>>(path: P, sample_rate: u32) -> GbaResult<Recorder> {
+        use gba_ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+        let path = path.as_ref();
+        let mkv = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("mkv"));
+        let output = if mkv {
+            let file = BufWriter::new(File::create(path)?);
+            Output::Matroska(MkvWriter::new(file, SCREEN_WIDTH, SCREEN_HEIGHT, sample_rate)?)
+        }
+        else {
+            fs::create_dir_all(path)?;
+            let wav = BufWriter::new(File::create(path.join("audio.wav"))?);
+            Output::Frames { dir: path.to_path_buf(), wav: WavWriter::new(wav, sample_rate)? }
+        };
+        Ok(Recorder {
+            output,
+            sample_rate,
+            frames: 0,
+            samples: 0,
+        })
+    }
+
+    /// Frames recorded so far
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Add one frame's picture and the interleaved stereo samples made
+    /// while it ran
+    pub fn record(&mut self, frame: &Screenshot, samples: &[i16]) -> GbaResult<()> {
+        let frame_ms = 1000.0 / REFRESH_RATE;
+        match self.output {
+            Output::Matroska(ref mut mkv) => {
+                mkv.video(&frame.rgba, self.frames as f64 * frame_ms, frame_ms)?;
+                mkv.audio(samples, self.samples as f64 * 1000.0 / self.sample_rate as f64)?;
+            },
+            Output::Frames { ref dir, ref mut wav } => {
+                fs::write(dir.join(format!("frame_{:06}.png", self.frames)), frame.to_png())?;
+                wav.write(samples)?;
+            },
+        }
+        self.frames += 1;
+        self.samples += samples.len() as u64 / 2;
+        Ok(())
+    }
+
+    /// Finish the files; until then they can't be played
+    pub fn finish(self) -> GbaResult<()> {
+        match self.output {
+            Output::Matroska(mkv) => { mkv.finish()?; },
+            Output::Frames { wav, .. } => { wav.finish()?; },
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn mkv_sizes_filled_in() {
+        let mut mkv = MkvWriter::new(Cursor::new(Vec::new()), 2, 1, 32768).unwrap();
+        let frame_ms = 1000.0 / REFRESH_RATE;
+        for i in 0..120 {
+            mkv.video(&[1, 2, 3, 255, 4, 5, 6, 255], i as f64 * frame_ms, frame_ms).unwrap();
+            mkv.audio(&[0x1234; 1098], i as f64 * frame_ms).unwrap();
+        }
+        let data = mkv.finish().unwrap().into_inner();
+        assert_eq!(&data[..4], &[0x1A, 0x45, 0xDF, 0xA3]);
+
+        // The segment runs to the end of the file
+        let segment = data.windows(4).position(|w| w == [0x18, 0x53, 0x80, 0x67]).unwrap() + 4;
+        let mut size = [0; 8];
+        size[1..].copy_from_slice(&data[segment + 1..segment + 8]);
+        assert_eq!(data[segment], 0x01);
+        assert_eq!(u64::from_be_bytes(size), (data.len() - segment - 8) as u64);
+
+        let duration = data.windows(2).position(|w| w == [0x44, 0x89]).unwrap() + 10;
+        let mut bits = [0; 8];
+        bits.copy_from_slice(&data[duration..duration + 8]);
+        assert_eq!(f64::from_bits(u64::from_be_bytes(bits)), 2009.0);
+
+        // A cluster a second
+        let clusters = data.windows(4).filter(|w| *w == [0x1F, 0x43, 0xB6, 0x75]).count();
+        assert_eq!(clusters, 2);
+        // RGB without the alpha
+        assert!(data.windows(6).any(|w| w == [1, 2, 3, 4, 5, 6]));
+    }
+
+    #[test]
+    fn wav_lengths_filled_in() {
+        let mut wav = WavWriter::new(Cursor::new(Vec::new()), 32768).unwrap();
+        wav.write(&[1, -1, 2, -2]).unwrap();
+        wav.write(&[3, -3]).unwrap();
+        let data = wav.finish().unwrap().into_inner();
+        assert_eq!(data.len(), 44 + 12);
+        assert_eq!(&data[4..8], &(36u32 + 12).to_le_bytes());
+        assert_eq!(&data[40..44], &12u32.to_le_bytes());
+        assert_eq!(&data[44..46], &1i16.to_le_bytes());
+    }
+}
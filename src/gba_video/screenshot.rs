@@ -0,0 +1,213 @@
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+use crc32fast;
+
+#[cfg(feature = "std")]
+use gba_error::GbaResult;
+use gba_ppu::{FrameBuffer, SCREEN_HEIGHT, SCREEN_WIDTH};
+use gba_video::{convert, PixelFormat};
+use prelude::*;
+
+// PNG file layout from:
+// https://www.w3.org/TR/png/
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+const PNG_BIT_DEPTH: u8 = 8;
+const PNG_COLOR_RGBA: u8 = 6;
+
+// Largest block deflate can store uncompressed
+const STORED_BLOCK_MAX: usize = 0xFFFF;
+const ADLER_MOD: u32 = 65521;
+
+/// A copy of one frame as 8-bit RGBA
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Screenshot {
+    /// In pixels
+    pub width: usize,
+    /// In pixels
+    pub height: usize,
+    /// Four bytes a pixel, row by row from the top left
+    pub rgba: Vec<u8>,
+}
+
+impl Screenshot {
+    /// A copy of fb
+    pub fn from_framebuffer(fb: &FrameBuffer) -> Screenshot {
+        let mut rgba = Vec::new();
+        convert(fb.pixels(), PixelFormat::Rgba8888, &mut rgba);
+        Screenshot {
+            width: SCREEN_WIDTH,
+            height: SCREEN_HEIGHT,
+            rgba,
+        }
+    }
+
+    /// Hash for golden image tests. It covers only the pixels, so it
+    /// doesn't change if the PNG encoding does.
+    pub fn crc32(&self) -> u32 {
+        crc32fast::hash(&self.rgba)
+    }
+
+    /// Encode as a PNG. The image data is stored rather than compressed,
+    /// which is plenty for a 240x160 picture and needs no deflate encoder.
+    pub fn to_png(&self) -> Vec<u8> {
+        let mut ihdr = Vec::with_capacity(13);
+        ihdr.extend_from_slice(&(self.width as u32).to_be_bytes());
+        ihdr.extend_from_slice(&(self.height as u32).to_be_bytes());
+        ihdr.extend_from_slice(&[PNG_BIT_DEPTH, PNG_COLOR_RGBA, 0, 0, 0]);
+
+        // Each row starts with its filter type, 0 for none
+        let mut raw = Vec::with_capacity(self.height * (1 + self.width * 4));
+        for row in self.rgba.chunks(self.width * 4) {
+            raw.push(0);
+            raw.extend_from_slice(row);
+        }
+
+        let mut png = PNG_SIGNATURE.to_vec();
+        write_chunk(&mut png, b"IHDR", &ihdr);
+        write_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+        write_chunk(&mut png, b"IEND", &[]);
+        png
+    }
+
+    /// Write the screenshot to path as a PNG
+    #[cfg(feature = "std")]
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> GbaResult<()> {
+        fs::write(path, self.to_png())?;
+        Ok(())
+    }
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32fast::hash(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % ADLER_MOD;
+        b = (b + a) % ADLER_MOD;
+    }
+    (b << 16) | a
+}
+
+// Wrap data in a zlib stream of uncompressed deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let blocks = data.len().div_ceil(STORED_BLOCK_MAX);
+    let mut out = Vec::with_capacity(data.len() + 5 * blocks + 6);
+    out.extend_from_slice(&[0x78, 0x01]); // Deflate, 32K window, no dictionary
+
+    let mut chunks = data.chunks(STORED_BLOCK_MAX).peekable();
+    while let Some(chunk) = chunks.next() {
+        let last = chunks.peek().is_none();
+        let len = chunk.len() as u16;
+        out.push(last as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(chunk);
+    }
+    if data.is_empty() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn png_chunks_are_well_formed() {
+        let shot = Screenshot::from_framebuffer(&FrameBuffer::default());
+        let png = shot.to_png();
+        assert_eq!(&png[..8], &PNG_SIGNATURE);
+
+        // Walk the chunks, checking each CRC
+        let mut at = 8;
+        let mut kinds = Vec::new();
+        while at < png.len() {
+            let len = u32::from_be_bytes([png[at], png[at + 1], png[at + 2], png[at + 3]]) as usize;
+            let body = &png[at + 4..at + 8 + len];
+            let crc = &png[at + 8 + len..at + 12 + len];
+            assert_eq!(crc32fast::hash(body).to_be_bytes(), crc);
+            kinds.push(String::from_utf8_lossy(&body[..4]).into_owned());
+            at += 12 + len;
+        }
+        assert_eq!(kinds, ["IHDR", "IDAT", "IEND"]);
+        assert_eq!(&png[16..24], &[0, 0, 0, 240, 0, 0, 0, 160]);
+    }
+
+    // The golden hash of a blank screen, which every ROM shows before it
+    // draws anything
+    #[test]
+    fn blank_frame_matches_golden() {
+        let shot = Screenshot::from_framebuffer(&FrameBuffer::default());
+        assert!(shot.rgba.chunks(4).all(|px| px == [0, 0, 0, 0xFF]));
+        assert_eq!(shot.crc32(), 0x10366D18);
+    }
+
+    #[cfg(feature = "config")]
+    #[derive(Deserialize)]
+    struct Golden {
+        name: String,
+        crc32: u32,
+    }
+
+    #[cfg(feature = "config")]
+    #[derive(Deserialize)]
+    struct Goldens {
+        frame: Vec<Golden>,
+    }
+
+    // The synthetic frames goldens.toml names, in BGR555
+    fn pattern(name: &str) -> FrameBuffer {
+        let mut fb = FrameBuffer::default();
+        for y in 0..SCREEN_HEIGHT {
+            for (x, px) in fb.line_mut(y).iter_mut().enumerate() {
+                *px = match name {
+                    "blank" => 0x0000,
+                    "white" => 0x7FFF,
+                    // Red, green and blue thirds
+                    "primaries" => [0x001F, 0x03E0, 0x7C00][x * 3 / SCREEN_WIDTH],
+                    "ramp" => (x & 0x1F | (y & 0x1F) << 5 | ((x + y) & 0x1F) << 10) as u16,
+                    "checkerboard" => if (x / 8 + y / 8) % 2 == 0 { 0x7FFF } else { 0x0000 },
+                    _ => panic!("no pattern called {}", name),
+                };
+            }
+        }
+        fb
+    }
+
+    #[test]
+    fn patterns_convert_to_rgba() {
+        let white = Screenshot::from_framebuffer(&pattern("white"));
+        assert!(white.rgba.chunks(4).all(|px| px == [0xFF, 0xFF, 0xFF, 0xFF]));
+
+        let primaries = Screenshot::from_framebuffer(&pattern("primaries"));
+        let px = |x: usize| &primaries.rgba[x * 4..x * 4 + 4];
+        assert_eq!(px(0), [0xFF, 0, 0, 0xFF]);
+        assert_eq!(px(SCREEN_WIDTH / 2), [0, 0xFF, 0, 0xFF]);
+        assert_eq!(px(SCREEN_WIDTH - 1), [0, 0, 0xFF, 0xFF]);
+    }
+
+    #[cfg(feature = "config")]
+    #[test]
+    fn synthetic_frames_match_goldens() {
+        let goldens: Goldens = ::toml::from_str(include_str!("goldens.toml")).unwrap();
+        assert_eq!(goldens.frame.len(), 5);
+        for golden in goldens.frame.iter() {
+            let shot = Screenshot::from_framebuffer(&pattern(&golden.name));
+            assert_eq!(shot.crc32(), golden.crc32, "{} frame is {:#010x}, expected {:#010x}",
+                       golden.name, shot.crc32(), golden.crc32);
+        }
+    }
+}
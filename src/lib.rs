@@ -0,0 +1,137 @@
+//! Game Boy Advance emulator core: the CPU, memory, video, sound and the
+//! other hardware, put together by `Gba`, and the frontends that drive it
+
+// #![cfg_attr(feature = "dev", allow(unstable_features))]
+// #![cfg_attr(feature = "dev", feature(plugin))]
+// #![cfg_attr(feature = "dev", plugin(clippy))]
+
+// #![deny(missing_docs,
+//         missing_debug_implementations, missing_copy_implementations,
+//         trivial_casts, trivial_numeric_casts,
+//         unsafe_code,
+//         unstable_features,
+//         unused_import_braces, unused_qualifications)]
+#![warn(missing_docs,
+        missing_debug_implementations, missing_copy_implementations,
+        trivial_casts, trivial_numeric_casts,
+        unsafe_code, unstable_features,
+        unused_import_braces, unused_qualifications)]
+
+// The core builds without std when the std feature is off, for embedded
+// frontends and the like. It still needs an allocator.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "std")]
+extern crate core;
+#[macro_use]
+extern crate alloc;
+extern crate byteorder;
+extern crate crc32fast;
+#[macro_use]
+extern crate log;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "serde")]
+#[cfg_attr(feature = "std", macro_use)]
+extern crate serde_derive;
+#[cfg(feature = "config")]
+extern crate toml;
+#[cfg(feature = "archive")]
+extern crate flate2;
+#[cfg(feature = "archive")]
+extern crate zip;
+#[cfg(test)]
+extern crate rand;
+#[cfg(feature = "audio")]
+extern crate cpal;
+#[cfg(feature = "sdl")]
+extern crate sdl2;
+#[cfg(feature = "wasm")]
+extern crate wasm_bindgen;
+#[cfg(feature = "python")]
+extern crate pyo3;
+#[cfg(feature = "python")]
+extern crate numpy;
+#[cfg(feature = "lua")]
+extern crate mlua;
+#[cfg(feature = "gpu")]
+extern crate wgpu;
+#[cfg(feature = "gpu")]
+extern crate winit;
+#[cfg(feature = "gpu")]
+extern crate pollster;
+#[cfg(feature = "jit")]
+extern crate cranelift_codegen;
+#[cfg(feature = "jit")]
+extern crate cranelift_frontend;
+#[cfg(feature = "jit")]
+extern crate cranelift_jit;
+#[cfg(feature = "jit")]
+extern crate cranelift_module;
+#[cfg(feature = "jit")]
+extern crate cranelift_native;
+
+// The alloc types std would otherwise bring into scope
+mod prelude {
+    pub use alloc::boxed::Box;
+    pub use alloc::string::{String, ToString};
+    pub use alloc::vec::Vec;
+}
+
+/// Fields of hardware registers packed into plain integers
+#[macro_use]
+pub mod gba_bits;
+/// Save states, rewind, movies and battery saves
+#[macro_use]
+pub mod gba_state;
+
+/// The memory bus and everything mapped onto it
+pub mod gba_mem;
+/// The ARM7TDMI: interpreter, block cache and JIT
+pub mod gba_cpu;
+/// Settings from the config file, per game and on the command line
+#[cfg(feature = "std")]
+pub mod gba_config;
+/// The sound hardware: the four PSG channels and Direct Sound
+pub mod gba_apu;
+/// Host audio output
+#[cfg(feature = "std")]
+pub mod gba_audio;
+/// Cheat codes and RAM search
+pub mod gba_cheats;
+/// Debugger, tracing and crash capture
+#[cfg(feature = "std")]
+pub mod gba_debug;
+/// The four DMA channels
+pub mod gba_dma;
+/// The crate's error type
+pub mod gba_error;
+/// Frontends that drive the emulator: SDL, libretro, Python and others
+#[cfg(feature = "std")]
+pub mod gba_frontend;
+/// Interrupt requests and their enable registers
+pub mod gba_irq;
+/// The keypad and hotkeys bound to it
+pub mod gba_keypad;
+/// Rollback netplay between two machines
+#[cfg(feature = "std")]
+pub mod gba_netplay;
+/// The LCD controller and the scanline renderer
+pub mod gba_ppu;
+/// Lua scripting
+#[cfg(feature = "lua")]
+pub mod gba_script;
+/// The serial port and the devices on the other end of the link cable
+pub mod gba_sio;
+/// The whole machine, put together for frontends
+#[cfg(feature = "std")]
+pub mod gba_system;
+/// The four hardware timers
+pub mod gba_timer;
+/// Converting, filtering, recording and saving frames
+pub mod gba_video;
+
+pub use gba_cpu::arm_cpu::ARM7;
+pub use gba_mem::Memory;
+#[cfg(feature = "std")]
+pub use gba_system::Gba;
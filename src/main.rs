@@ -1,41 +1,260 @@
-// #![cfg_attr(feature = "dev", allow(unstable_features))]
-// #![cfg_attr(feature = "dev", feature(plugin))]
-// #![cfg_attr(feature = "dev", plugin(clippy))]
-
-// #![deny(missing_docs,
-//         missing_debug_implementations, missing_copy_implementations,
-//         trivial_casts, trivial_numeric_casts,
-//         unsafe_code,
-//         unstable_features,
-//         unused_import_braces, unused_qualifications)]
+//! Command line frontend for the emulator core in lib.rs
 #![warn(missing_docs,
         missing_debug_implementations, missing_copy_implementations,
         trivial_casts, trivial_numeric_casts,
         unsafe_code, unstable_features,
         unused_import_braces, unused_qualifications)]
 
-extern crate byteorder;
-
-pub mod gba_mem;
-pub mod gba_cpu;
+extern crate gba;
+#[macro_use]
+extern crate log;
 
 use std::env;
-use std::fs::File;
+use std::path::Path;
+use std::process;
 
-pub use gba_cpu::arm_cpu::ARM7;
-pub use gba_mem::Memory;
+use gba::Gba;
+use gba::gba_config::Config;
+use gba::gba_cpu::coverage::CoverageReport;
+use gba::gba_debug;
+use gba::gba_frontend;
+use gba::gba_frontend::{DisasmOptions, Options};
+use gba::gba_frontend::info::{self, RomInfo};
+use gba::gba_frontend::logging;
+use gba::gba_mem::archive;
+use gba::gba_sio::net::NetLink;
 
 fn main() {
-    let pak_rom_filename = env::args()
-        .nth(1)
-        .expect("PAK ROM argument not specified");
+    // Developer command: report decoder coverage of the ARMv4T encodings
+    if env::args().nth(1).is_some_and(|a| a == "coverage") {
+        print!("{}", CoverageReport::new(None));
+        return;
+    }
+
+    // Print what can be told about a ROM without running it
+    if env::args().nth(1).is_some_and(|a| a == "info") {
+        let path = env::args().nth(2).unwrap_or_else(|| {
+            println!("Usage: gba info <ROM>");
+            process::exit(1);
+        });
+        match archive::read_rom(&path) {
+            Ok(rom) => print!("{}", RomInfo::new(&rom)),
+            Err(e) => {
+                println!("Failed to read {}: {}", path, e);
+                process::exit(1);
+            },
+        }
+        return;
+    }
+
+    // Print a stretch of a ROM's code without running it
+    if env::args().nth(1).is_some_and(|a| a == "disasm") {
+        if let Err(e) = disasm(env::args().skip(2)) {
+            println!("{}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
+    let opts = match Options::parse(env::args().skip(1)) {
+        Ok(opts) => opts,
+        Err(e) => {
+            println!("{}", e);
+            println!("Usage: gba <PAK ROM> [--headless | --gpu] [--debug] [--force] [--cached | --jit] \
+                      [--strict] \
+                      [--no-game-db] \
+                      [--scale N] [--config FILE] [--speed unlimited|MULTIPLIER] [--frame-skip N] \
+                      [--bios FILE] [--save-dir DIR] [--filter nearest|linear] \
+                      [--shader NAME|FILE] [--fullscreen] [--mute] \
+                      [--log LEVELS] [--undefined exception|skip|break] \
+                      [--record MOVIE | --play MOVIE] [--cheats FILE] \
+                      [--record-video FILE.mkv|DIR] \
+                      [--audit HASHES] [--audit-against HASHES] \
+                      [--script FILE] [--sensor solar|tilt|gyro]... [--save-type TYPE] \
+                      [--link-host PORT | --link-connect HOST:PORT | --peripheral gbplayer|ereader]");
+            process::exit(1);
+        },
+    };
+
+    logging::init(opts.log.clone());
+    let mut config = load_config(&opts);
+    opts.apply(&mut config);
+    let mut gba = match Gba::load(opts.rom.as_str(), &config) {
+        Ok(gba) => gba,
+        Err(e) => {
+            println!("Failed to load {}: {}", opts.rom, e);
+            process::exit(1);
+        },
+    };
+
+    match config.bios {
+        Some(ref path) => if let Err(e) = gba.load_bios(path) {
+            println!("Failed to load BIOS {}: {}", path.display(), e);
+            process::exit(1);
+        },
+        None => info!(target: "bus", "booting with the built in BIOS"),
+    }
+
+    if let Some(ref path) = opts.cheats {
+        if let Err(e) = gba.cheats_mut().load(path) {
+            println!("Failed to load {}: {}", path, e);
+            process::exit(1);
+        }
+        for cheat in gba.cheats().cheats().iter().filter(|cheat| cheat.has_master_code()) {
+            println!("WARNING: the master code in {} isn't needed and is ignored.", cheat.name);
+        }
+        // Asking for cheats on the command line beats the config
+        let mut settings = gba.settings().clone();
+        settings.cheats_enabled = true;
+        gba.set_settings(settings);
+    }
 
-    let mut m = Memory::new(pak_rom_filename.as_str()).unwrap();
+    if !opts.sensors.is_empty() {
+        let mut settings = gba.settings().clone();
+        settings.sensors = Some(opts.sensors.clone());
+        gba.set_settings(settings);
+    }
 
-    m.write32::<u32>(0x02000000, 0xdeadbeef);
+    if let Some(save_type) = opts.save_type {
+        let mut settings = gba.settings().clone();
+        settings.save_type = save_type;
+        gba.set_settings(settings);
+    }
 
-    println!("{:#x}", m.read::<u8>(0x02000000));
+    gba.set_cached_interpreter(opts.cached);
+    set_jit(&opts, &mut gba);
+    gba.set_undefined_policy(opts.undefined);
+
+    if let Some(peripheral) = opts.peripheral {
+        let mut settings = gba.settings().clone();
+        settings.peripheral = peripheral;
+        gba.set_settings(settings);
+    }
+
+    let link = match (opts.link_host, opts.link_connect.as_ref()) {
+        (Some(port), _) => Some(NetLink::host(("0.0.0.0", port))),
+        (_, Some(addr)) => Some(NetLink::connect(addr.as_str())),
+        _ => None,
+    };
+    match link {
+        Some(Ok(link)) => {
+            gba.set_link_device(Box::new(link));
+        },
+        Some(Err(e)) => {
+            println!("Failed to set up the link cable: {}", e);
+            process::exit(1);
+        },
+        None => {},
+    }
+
+    // A real GBA won't boot these, so only run them when asked to
+    if let Err(e) = gba.mem().check_rom_header() {
+        if opts.force {
+            println!("WARNING: {}", e);
+        }
+        else {
+            println!("{}. Use --force to run it anyway.", e);
+            process::exit(1);
+        }
+    }
+
+    if opts.debug {
+        if let Err(e) = gba_debug::repl::run_stdio(&mut gba) {
+            println!("Debugger I/O failed: {}", e);
+            process::exit(1);
+        }
+    }
+    else if opts.headless {
+        gba_frontend::run_headless(gba.mem_mut());
+    }
+    else {
+        run_frontend(&opts, &config, &mut gba);
+    }
+}
+
+fn disasm<I: Iterator<Item = String>>(args: I) -> Result<(), String> {
+    let opts = DisasmOptions::parse(args)
+        .map_err(|e| format!("{}\nUsage: gba disasm <ROM> [--start ADDR] [--len BYTES] [--thumb]", e))?;
+    let rom = archive::read_rom(&opts.rom)
+        .map_err(|e| format!("Failed to read {}: {}", opts.rom, e))?;
+    for line in info::disassemble_rom(&rom, opts.start, opts.len, opts.thumb)? {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+// An explicit --config must load; the default file is optional
+fn load_config(opts: &Options) -> Config {
+    let path = match opts.config {
+        Some(ref path) => path.as_str(),
+        None if Path::new(gba_frontend::DEFAULT_CONFIG).exists() =>
+            gba_frontend::DEFAULT_CONFIG,
+        None => return Config::default(),
+    };
+
+    match Config::load(path) {
+        Ok(config) => config,
+        Err(e) => {
+            println!("Failed to load config {}: {}", path, e);
+            process::exit(1);
+        },
+    }
+}
+
+#[cfg(feature = "jit")]
+fn set_jit(opts: &Options, gba: &mut Gba) {
+    if !opts.jit {
+        return;
+    }
+    if let Err(e) = gba.set_jit(true) {
+        println!("Failed to start the JIT: {}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(not(feature = "jit"))]
+fn set_jit(opts: &Options, _gba: &mut Gba) {
+    if opts.jit {
+        println!("WARNING: built without the jit feature; using the interpreter.");
+    }
+}
+
+#[cfg(feature = "gpu")]
+fn run_gpu(opts: &Options, config: &Config, gba: &mut Gba) -> bool {
+    if !opts.gpu {
+        return false;
+    }
+    if let Err(e) = gba_frontend::gpu::run(opts, config, gba) {
+        println!("Failed to start the GPU frontend: {}", e);
+        process::exit(1);
+    }
+    true
+}
+
+#[cfg(not(feature = "gpu"))]
+fn run_gpu(opts: &Options, _config: &Config, _gba: &mut Gba) -> bool {
+    if opts.gpu {
+        println!("WARNING: built without the gpu feature; using the default frontend.");
+    }
+    false
+}
+
+#[cfg(feature = "sdl")]
+fn run_frontend(opts: &Options, config: &Config, gba: &mut Gba) {
+    if run_gpu(opts, config, gba) {
+        return;
+    }
+    if let Err(e) = gba_frontend::sdl::run(opts, config, gba) {
+        println!("Failed to start the frontend: {}", e);
+        process::exit(1);
+    }
+}
 
-    let cpu = ARM7::default();
-    println!("{}", cpu);
+#[cfg(not(feature = "sdl"))]
+fn run_frontend(opts: &Options, config: &Config, gba: &mut Gba) {
+    if run_gpu(opts, config, gba) {
+        return;
+    }
+    println!("WARNING: built without the sdl feature; running headless.");
+    gba_frontend::run_headless(gba.mem_mut());
 }
@@ -0,0 +1,16 @@
+#!/bin/sh
+# Build the emulator for the browser into web/pkg, next to index.html.
+# Needs the wasm32 target and a wasm-bindgen CLI matching the crate's:
+#
+#     rustup target add wasm32-unknown-unknown
+#     cargo install wasm-bindgen-cli
+#
+# Then serve this directory, e.g. python3 -m http.server -d web, since
+# browsers won't load modules from file:// URLs.
+set -e
+cd "$(dirname "$0")/.."
+
+cargo rustc --release --lib --crate-type cdylib \
+    --target wasm32-unknown-unknown --features wasm
+wasm-bindgen --target web --no-typescript --out-dir web/pkg \
+    target/wasm32-unknown-unknown/release/gba.wasm